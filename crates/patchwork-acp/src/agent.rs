@@ -1,8 +1,9 @@
 //! Agent infrastructure for LLM communication.
 //!
 //! The Agent manages think block execution by creating LLM sessions with the
-//! successor agent (like claude-code-acp). Interpreter threads send ThinkRequests
-//! through channels, and the Agent spawns async tasks to handle each request.
+//! successor agent (like claude-code-acp), or with the agent a request names
+//! (see `routes`). Interpreter threads send ThinkRequests through channels,
+//! and the Agent spawns async tasks to handle each request.
 //!
 //! This design is inspired by Niko Matsakis's threadbare prototype.
 //!
@@ -16,20 +17,19 @@
 //! 3. Agent creates LLM sessions and accumulates responses
 //! 4. Results are sent back via `ThinkResponse` on `std::sync::mpsc`
 
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 
 use sacp::schema::{
     ContentBlock, NewSessionRequest, NewSessionResponse, PromptRequest, PromptResponse,
     SessionNotification, SessionUpdate, StopReason,
 };
 use sacp::JrConnectionCx;
-use sacp_proxy::{JrCxExt, McpServer, McpServiceRegistry};
-use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use sacp_proxy::McpServiceRegistry;
 use tokio::sync::mpsc::{channel, unbounded_channel, Sender, UnboundedReceiver, UnboundedSender};
-use tokio::sync::{oneshot, Mutex};
 
-use patchwork_eval::{AgentHandle, ThinkRequest, ThinkResponse, Value};
+use patchwork_eval::{AgentHandle, Schema, ThinkRequest, ThinkResponse, Value};
+
+use crate::routes::{Agents, Downstream};
 
 /// Result of a think block execution.
 pub type ThinkResult = Result<Value, String>;
@@ -46,34 +46,21 @@ pub enum RedirectMessage {
 
 /// Messages that get routed to individual think sessions.
 pub enum PerSessionMessage {
-    /// Notification from the successor agent (streaming content, etc.).
-    SessionNotification(SessionNotification),
-    /// The LLM invoked the "do" tool.
-    DoInvocation(DoArg, oneshot::Sender<String>),
+    /// Notification from the successor agent (streaming content, etc.),
+    /// boxed since it is much larger than the other messages.
+    SessionNotification(Box<SessionNotification>),
     /// The prompt completed.
     PromptResponse(PromptResponse),
 }
 
-/// Argument for the MCP "do" tool.
-#[derive(JsonSchema, Deserialize, Serialize)]
-pub struct DoArg {
-    /// Index of the child to execute.
-    pub number: usize,
-}
-
-/// Result from the MCP "do" tool.
-#[derive(JsonSchema, Deserialize, Serialize)]
-pub struct DoResult {
-    /// The text result of executing the child.
-    pub text: String,
-}
-
 /// Shared state for the agent, accessible from async tasks.
 pub struct AgentState {
     /// Channel for sending redirect messages.
     pub redirect_tx: UnboundedSender<RedirectMessage>,
-    /// MCP registry with the "do" tool.
+    /// MCP servers offered to think sessions.
     pub mcp_registry: McpServiceRegistry,
+    /// Agents from the config's `agents` table, set once they have started.
+    pub agents: OnceLock<Agents>,
 }
 
 /// Create an agent that bridges the interpreter to async LLM sessions.
@@ -103,6 +90,7 @@ pub fn create_agent(
     let state = Arc::new(AgentState {
         redirect_tx: redirect_tx.clone(),
         mcp_registry,
+        agents: OnceLock::new(),
     });
 
     // Spawn redirect actor via cx.spawn() - it doesn't need to call block_task()
@@ -123,14 +111,33 @@ pub fn create_agent(
 /// Process a single think request from the interpreter.
 pub async fn process_think_request(cx: JrConnectionCx, request: ThinkRequest, state: Arc<AgentState>) -> Result<(), sacp::Error> {
     let ThinkRequest {
+        op,
         prompt,
-        bindings: _,
         expect,
+        model,
+        agent,
         response_tx,
+        ..
     } = request;
+    tracing::debug!("{} request expecting {}", op, expect);
+
+    // Strings are asked for as text, everything else as JSON
+    let expect = match expect {
+        Schema::String => "string",
+        _ => "json",
+    };
+
+    // ACP sessions don't let the client pick a model, so the agent's
+    // default answers every attempt in the chain
+    if let Some(model) = &model {
+        tracing::debug!("think request for model {} sent to the agent's default", model);
+    }
 
     // Execute the think block and send responses
-    let result = think_message(cx, prompt, expect, state).await;
+    let result = match downstream(cx, agent.as_deref(), &state) {
+        Ok(downstream) => think_message(downstream, prompt, expect.to_string(), state, &response_tx).await,
+        Err(message) => Err(message),
+    };
 
     // Send the Complete response
     let _ = response_tx.send(ThinkResponse::Complete { result });
@@ -138,6 +145,27 @@ pub async fn process_think_request(cx: JrConnectionCx, request: ThinkRequest, st
     Ok(())
 }
 
+/// The agent a think request goes to: the one it names, or the successor.
+fn downstream(cx: JrConnectionCx, agent: Option<&str>, state: &AgentState) -> Result<Downstream, String> {
+    let Some(name) = agent else {
+        return Ok(Downstream::Successor(cx));
+    };
+    let agents = state.agents.get();
+    if let Some(reason) = agents.and_then(|agents| agents.failed.get(name)) {
+        return Err(format!("agent `{}` failed to start: {}", name, reason));
+    }
+    match agents.and_then(|agents| agents.running.get(name)) {
+        Some(agent_cx) => Ok(Downstream::Agent(agent_cx.clone())),
+        None => {
+            let mut names: Vec<&str> =
+                agents.into_iter().flat_map(|agents| agents.running.keys()).map(String::as_str).collect();
+            names.sort_unstable();
+            let running = if names.is_empty() { "none".to_string() } else { names.join(", ") };
+            Err(format!("no agent named `{}` is running (running agents: {})", name, running))
+        }
+    }
+}
+
 /// The redirect actor maintains a stack of active thinkers and routes messages.
 ///
 /// When nested think blocks occur, each one pushes onto the stack. Messages
@@ -164,12 +192,13 @@ async fn redirect_actor(mut rx: UnboundedReceiver<RedirectMessage>) {
     }
 }
 
-/// Handle a single think block by creating an LLM session with the successor.
+/// Handle a single think block by creating an LLM session with `downstream`.
 async fn think_message(
-    cx: JrConnectionCx,
+    downstream: Downstream,
     prompt: String,
     expect: String,
     state: Arc<AgentState>,
+    response_tx: &std::sync::mpsc::Sender<ThinkResponse>,
 ) -> ThinkResult {
     // Build the augmented prompt with type hints
     let augmented_prompt = augment_prompt_with_type_hint(&prompt, &expect);
@@ -180,15 +209,17 @@ async fn think_message(
         mcp_servers: vec![],
         meta: None,
     };
+    // The successor reaches our MCP servers over the proxy chain, and the
+    // agents we started over their connections to us (see `routes`)
     state
         .mcp_registry
         .add_registered_mcp_servers_to(&mut new_session);
 
-    // Start a new session with the successor agent (e.g., claude-code-acp)
+    // Start a new session with the agent (e.g., claude-code-acp)
     // This uses block_task().await directly because think_message is spawned via cx.spawn(),
     // so it's part of the connection's event loop and can receive responses.
-    tracing::info!("THINK_MSG: about to send session/new to successor");
-    let response_future = cx.send_request_to_successor(new_session);
+    tracing::info!("THINK_MSG: about to send session/new");
+    let response_future = downstream.send_request(new_session);
     tracing::info!("THINK_MSG: request future created, now calling block_task()");
     let session_result = response_future.block_task().await;
     tracing::info!("THINK_MSG: block_task() RETURNED! is_ok={:?}", session_result.is_ok());
@@ -211,10 +242,10 @@ async fn think_message(
     }
     tracing::info!("think_message: pushed thinker onto stack");
 
-    // Send the prompt request to the successor
-    tracing::info!("think_message: sending prompt to successor for session {}", session_id);
-    let prompt_result = cx
-        .send_request_to_successor(PromptRequest {
+    // Send the prompt request to the agent
+    tracing::info!("think_message: sending prompt for session {}", session_id);
+    let prompt_result = downstream
+        .send_request(PromptRequest {
             session_id: session_id.clone(),
             prompt: vec![augmented_prompt.into()],
             meta: None,
@@ -248,12 +279,12 @@ async fn think_message(
                     }
                 }
             }
-            PerSessionMessage::DoInvocation(DoArg { number }, do_tx) => {
-                // TODO: In Phase 5, this will trigger recursive evaluation
-                // For now, return an error
-                let _ = do_tx.send(format!("do({}) not yet implemented", number));
-            }
             PerSessionMessage::PromptResponse(response) => {
+                // ACP doesn't say which model answered, only why it stopped
+                let stop_reason = serde_json::to_value(response.stop_reason)
+                    .ok()
+                    .and_then(|reason| reason.as_str().map(str::to_string));
+                let _ = response_tx.send(ThinkResponse::Metadata { model: None, stop_reason });
                 match response.stop_reason {
                     StopReason::EndTurn => break,
                     reason => {
@@ -272,36 +303,6 @@ async fn think_message(
     extract_response_value(&result_text, &expect)
 }
 
-/// Create the MCP server offering the "do" tool.
-///
-/// This should be registered with the proxy's MCP registry so that when
-/// we create sessions with the successor, the "do" tool is available.
-pub fn create_mcp_server(redirect_tx: UnboundedSender<RedirectMessage>) -> McpServer {
-    let redirect_tx = Arc::new(Mutex::new(redirect_tx));
-    McpServer::new()
-        .instructions("Patchwork interpreter tools for recursive evaluation")
-        .tool_fn(
-            "do",
-            "Execute a Patchwork code fragment by index. Call this when instructed to execute a specific numbered code block.",
-            {
-                let redirect_tx = redirect_tx.clone();
-                async move |arg: DoArg, _cx| -> Result<DoResult, sacp::Error> {
-                    let (result_tx, result_rx) = oneshot::channel();
-                    let tx = redirect_tx.lock().await;
-                    tx.send(RedirectMessage::IncomingMessage(
-                        PerSessionMessage::DoInvocation(arg, result_tx),
-                    ))
-                    .map_err(sacp::util::internal_error)?;
-                    drop(tx);
-                    Ok(DoResult {
-                        text: result_rx.await.map_err(sacp::util::internal_error)?,
-                    })
-                }
-            },
-            |f, a, b| Box::pin(f(a, b)),
-        )
-}
-
 /// Augment the prompt with type hint instructions for response formatting.
 fn augment_prompt_with_type_hint(prompt: &str, expect: &str) -> String {
     match expect {
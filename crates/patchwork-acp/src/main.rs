@@ -2,56 +2,86 @@
 //!
 //! This proxy sits between an editor (like Zed) and an agent (like Claude Code),
 //! intercepting prompts that contain Patchwork code and executing them with
-//! integrated LLM support via think blocks.
+//! integrated LLM support via think blocks. Think blocks can also be routed to
+//! other agents the proxy starts itself; see `routes`.
 
 mod agent;
+mod routes;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 use sacp::schema::{
-    ContentBlock, ContentChunk, Plan, PlanEntry, PlanEntryPriority, PlanEntryStatus,
-    PromptRequest, PromptResponse, SessionNotification, SessionUpdate, StopReason, TextContent,
+    CancelNotification, ClientCapabilities, ContentBlock, ContentChunk, InitializeRequest, InitializeResponse,
+    PermissionOption, PermissionOptionId, PermissionOptionKind, Plan, PlanEntry, PlanEntryPriority, PlanEntryStatus,
+    PromptRequest, PromptResponse,
+    RequestPermissionOutcome, RequestPermissionRequest, ResourceLink, SessionNotification, SessionUpdate,
+    StopReason, TextContent, ToolCallId, ToolCallUpdate, ToolCallUpdateFields, ToolKind,
 };
-use sacp::{JrConnectionCx, JrHandlerChain, JrRequestCx};
+use sacp::{Handled, JrConnectionCx, JrHandlerChain, JrMessageHandler, JrRequestCx};
 use sacp_proxy::{AcpProxyExt, JrCxExt, McpServiceRegistry};
 use tokio::sync::mpsc::UnboundedSender;
 use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
 use tracing_subscriber::EnvFilter;
 
+use patchwork_eval::diagnostics::Renderer;
+use patchwork_eval::document;
 use patchwork_eval::{
-    AgentHandle, Error as EvalError, Interpreter,
-    PlanReporter, PlanUpdate as EvalPlanUpdate, PrintSink,
-    ThoughtChunk as EvalThoughtChunk, ThoughtReporter,
+    refused_requests, AgentHandle, ApprovalDecision, ApprovalHandler, Artifact, ApprovalRequest, Backend, CacheScope,
+    CancellationToken, CapabilityRequest, Config, ConfigLayer,
+    Error as EvalError, FileAccess, Interpreter, Outcome, Permission, PlanReporter, PlanUpdate as EvalPlanUpdate,
+    PrintSink, ProgressReporter, ProgressUpdate as EvalProgressUpdate, SandboxPolicy, Session, ThinkCache,
+    ThoughtChunk as EvalThoughtChunk, ThoughtReporter, Warning as EvalWarning, WarningReporter,
 };
 
 use crate::agent::{PerSessionMessage, RedirectMessage};
 
 /// The Patchwork proxy state.
 struct PatchworkProxy {
-    /// Sessions with active evaluations (session IDs).
-    active_sessions: HashSet<String>,
+    /// Sessions with active evaluations, and the tokens that cancel them.
+    active_sessions: HashMap<String, CancellationToken>,
     /// Agent handle for think blocks.
     agent_handle: Option<AgentHandle>,
+    /// Answers to think blocks, reused as the config's `think_cache` allows.
+    think_cache: Option<ThinkCache>,
     /// Redirect channel for routing session notifications to think blocks.
     redirect_tx: Option<UnboundedSender<RedirectMessage>>,
+    /// Settings applied to every evaluation.
+    config: Config,
+    /// Programs the user chose to always allow, per session.
+    always_allowed: HashMap<String, HashSet<String>>,
 }
 
 impl PatchworkProxy {
-    fn new() -> Self {
+    fn new(config: Config) -> Self {
         Self {
-            active_sessions: HashSet::new(),
+            active_sessions: HashMap::new(),
             agent_handle: None,
+            think_cache: None,
             redirect_tx: None,
+            config,
+            always_allowed: HashMap::new(),
         }
     }
 
     fn has_active_evaluation(&self, session_id: &str) -> bool {
-        self.active_sessions.contains(session_id)
+        self.active_sessions.contains_key(session_id)
     }
 
-    fn start_evaluation(&mut self, session_id: &str) {
-        self.active_sessions.insert(session_id.to_string());
+    fn start_evaluation(&mut self, session_id: &str, token: CancellationToken) {
+        self.active_sessions.insert(session_id.to_string(), token);
+    }
+
+    /// Cancel the session's evaluation, if one is running.
+    fn cancel_evaluation(&self, session_id: &str) -> bool {
+        match self.active_sessions.get(session_id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
     }
 
     fn end_evaluation(&mut self, session_id: &str) {
@@ -59,17 +89,57 @@ impl PatchworkProxy {
     }
 
     fn set_agent(&mut self, handle: AgentHandle, redirect_tx: UnboundedSender<RedirectMessage>) {
+        self.think_cache = Some(ThinkCache::new(handle.clone()));
         self.agent_handle = Some(handle);
         self.redirect_tx = Some(redirect_tx);
     }
 
-    fn agent_handle(&self) -> Option<AgentHandle> {
-        self.agent_handle.clone()
+    /// The handle the session's think blocks should use, or None when
+    /// running offline.
+    fn agent_handle(&self, session_id: &str) -> Option<AgentHandle> {
+        match (self.config.backend, &self.think_cache) {
+            (Backend::Offline, _) => None,
+            (_, Some(cache)) => Some(cache.handle(self.config.think_cache, session_id)),
+            (_, None) => self.agent_handle.clone(),
+        }
+    }
+
+    /// Forget the cached answers the session's think blocks would reuse,
+    /// returning how many there were.
+    fn clear_think_cache(&self, session_id: &str) -> usize {
+        match &self.think_cache {
+            Some(cache) => cache.clear(self.config.think_cache, session_id),
+            None => 0,
+        }
+    }
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Narrow the settings to what the editor can support.
+    fn restrict_to_client(&mut self, capabilities: &ClientCapabilities) {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        client_policy(capabilities).apply(&mut self.config, &cwd);
     }
 
     fn redirect_tx(&self) -> Option<UnboundedSender<RedirectMessage>> {
         self.redirect_tx.clone()
     }
+
+    fn always_allow(&mut self, session_id: &str, program: &str) {
+        self.always_allowed
+            .entry(session_id.to_string())
+            .or_default()
+            .insert(program.to_string());
+    }
+
+    fn always_allowed(&self, session_id: &str) -> Vec<String> {
+        self.always_allowed
+            .get(session_id)
+            .map(|programs| programs.iter().cloned().collect())
+            .unwrap_or_default()
+    }
 }
 
 /// Check if a message appears to be Patchwork code or shell shorthand.
@@ -77,15 +147,16 @@ impl PatchworkProxy {
 /// Returns the code to execute if this is Patchwork input, None otherwise.
 /// - Starting with `{` → block mode (code passed through)
 /// - Starting with `$` → shell shorthand (wrapped in print block)
+/// - Prose with fenced ```patchwork blocks → the blocks' code, in order
 fn detect_patchwork_input(text: &str) -> Option<String> {
     let trimmed = text.trim_start();
 
     if trimmed.starts_with('{') {
         // Block mode - pass through as-is
         Some(text.to_string())
-    } else if trimmed.starts_with('$') {
+    } else if let Some(command) = trimmed.strip_prefix('$') {
         // Shell shorthand - wrap in print block
-        let command = trimmed[1..].trim_start(); // Remove $ and any following whitespace
+        let command = command.trim_start(); // Remove $ and any following whitespace
         Some(format!(
             r#"{{
   var output = ($ {})
@@ -94,8 +165,43 @@ fn detect_patchwork_input(text: &str) -> Option<String> {
             command
         ))
     } else {
-        None
+        // Code embedded in prose runs as one turn; the prose is for the reader
+        let blocks = document::find_blocks(text);
+        (!blocks.is_empty()).then(|| blocks.iter().map(|block| block.code).collect::<Vec<_>>().join("\n"))
+    }
+}
+
+/// The most programs may do for a client with `capabilities`: no shell
+/// commands unless it runs terminals, no file writes unless it writes
+/// files, and file access confined unless it reads them.
+fn client_policy(capabilities: &ClientCapabilities) -> SandboxPolicy {
+    let mut policy = SandboxPolicy::unrestricted();
+    if !capabilities.terminal {
+        policy.capabilities.shell = Permission::Deny;
+    }
+    if !capabilities.fs.write_text_file {
+        policy.capabilities.file_write = Permission::Deny;
+    }
+    if !capabilities.fs.read_text_file {
+        policy.capabilities.file_access = FileAccess::Confined;
+    }
+    policy
+}
+
+/// Explain why code that asks for `refused` won't run.
+fn refusal_message(refused: &[CapabilityRequest]) -> String {
+    let mut message = "This code asks for capabilities this session doesn't allow:".to_string();
+    for request in refused {
+        message.push_str(&format!("\n  {}: {}", request.capability, request.what));
+        if let Some(line) = request.line {
+            message.push_str(&format!(" (line {})", line));
+        }
     }
+    message.push_str(&format!(
+        "\nThe editor's capabilities, the config, and {} set what sessions allow.",
+        patchwork_eval::WORKSPACE_POLICY_FILE
+    ));
+    message
 }
 
 /// Extract the text content from a prompt request.
@@ -110,6 +216,14 @@ fn extract_prompt_text(request: &PromptRequest) -> Option<String> {
     })
 }
 
+/// The name a client asked to keep the session under, from
+/// `"_meta": {"patchwork": {"session": "<name>"}}` on the prompt.
+fn requested_session_name(request: &PromptRequest) -> Option<String> {
+    let meta = request.meta.as_ref()?;
+    let name = meta.get("patchwork")?.get("session")?.as_str()?;
+    Some(name.to_string())
+}
+
 /// Handle a prompt request, checking for Patchwork code.
 ///
 /// IMPORTANT: This handler must NOT block! If we await on long-running work,
@@ -132,6 +246,27 @@ async fn handle_prompt(
         return Ok(());
     };
 
+    // `/cache clear` is ours; the successor's own slash commands pass through
+    if text.trim() == "/cache clear" {
+        let (cleared, scope) = {
+            let proxy_guard = proxy.lock().unwrap();
+            (proxy_guard.clear_think_cache(&session_id), proxy_guard.config().think_cache)
+        };
+        let message = match scope {
+            CacheScope::Off => "Think blocks aren't cached; set `think_cache` to `session` or `global` to cache them.".to_string(),
+            scope => {
+                let noun = if cleared == 1 { "answer" } else { "answers" };
+                format!("Cleared {} cached {} ({} scope).", cleared, noun, scope)
+            }
+        };
+        let connection_cx = cx.connection_cx().clone();
+        if let Err(e) = send_agent_message(&connection_cx, &session_id, message) {
+            tracing::warn!("Failed to send cache message: {}", e);
+        }
+        cx.respond(create_text_response(String::new()))?;
+        return Ok(());
+    }
+
     // Check if it's Patchwork code or shell shorthand
     let Some(code) = detect_patchwork_input(&text) else {
         // Not Patchwork input, forward unchanged
@@ -144,8 +279,8 @@ async fn handle_prompt(
 
     tracing::info!("Detected Patchwork input, executing...");
 
-    // Check for active evaluation and get agent handle
-    let agent_handle = {
+    // Check for active evaluation and get agent handle and config
+    let (agent_handle, config) = {
         let proxy_guard = proxy.lock().unwrap();
 
         if proxy_guard.has_active_evaluation(&session_id) {
@@ -157,20 +292,38 @@ async fn handle_prompt(
             return Ok(());
         }
 
-        proxy_guard.agent_handle()
+        (proxy_guard.agent_handle(&session_id), proxy_guard.config().clone())
     };
 
-    // Mark session as active
+    // Turn away code that would fail partway through on a denied capability
+    let refused = refused_requests(&code, &config.capabilities);
+    if !refused.is_empty() {
+        cx.respond_with_error(sacp::Error::invalid_request().with_data(refusal_message(&refused)))?;
+        return Ok(());
+    }
+
+    // Mark session as active; session/cancel notifications use the token to stop it
+    let token = CancellationToken::new();
     {
         let mut proxy_guard = proxy.lock().unwrap();
-        proxy_guard.start_evaluation(&session_id);
+        proxy_guard.start_evaluation(&session_id, token.clone());
     }
 
     // CRITICAL: Spawn the evaluation as a separate task to avoid blocking
     // the incoming_protocol_actor. If we block here, responses from our
     // think blocks won't be dispatched, causing a deadlock.
     let connection_cx = cx.connection_cx().clone();
-    connection_cx.spawn(run_patchwork_evaluation(proxy, session_id, code, agent_handle, cx))?;
+    let session_name = requested_session_name(&request);
+    connection_cx.spawn(run_patchwork_evaluation(
+        proxy,
+        session_id,
+        session_name,
+        code,
+        agent_handle,
+        config,
+        token,
+        cx,
+    ))?;
 
     Ok(())
 }
@@ -178,11 +331,15 @@ async fn handle_prompt(
 /// Run Patchwork evaluation in a spawned task.
 ///
 /// This runs as a separate task so it doesn't block the message processing loop.
+#[allow(clippy::too_many_arguments)]
 async fn run_patchwork_evaluation(
     proxy: Arc<Mutex<PatchworkProxy>>,
     session_id: String,
+    session_name: Option<String>,
     text: String,
     agent_handle: Option<AgentHandle>,
+    config: Config,
+    token: CancellationToken,
     cx: JrRequestCx<PromptResponse>,
 ) -> Result<(), sacp::Error> {
     // Create a channel for print output
@@ -197,14 +354,62 @@ async fn run_patchwork_evaluation(
     let (thought_tx, thought_rx): (ThoughtReporter, std::sync::mpsc::Receiver<EvalThoughtChunk>) =
         std::sync::mpsc::channel();
 
-    // Create interpreter with agent handle, print sink, plan reporter, and thought reporter
+    // Create a channel for progress reports
+    let (progress_tx, progress_rx): (ProgressReporter, std::sync::mpsc::Receiver<EvalProgressUpdate>) =
+        std::sync::mpsc::channel();
+
+    // Create a channel for warnings
+    let (warning_tx, warning_rx): (WarningReporter, std::sync::mpsc::Receiver<EvalWarning>) =
+        std::sync::mpsc::channel();
+
+    // Create a channel for shell command approvals
+    let (approval_tx, approval_rx): (ApprovalHandler, std::sync::mpsc::Receiver<ApprovalRequest>) =
+        std::sync::mpsc::channel();
+
+    // Create interpreter with agent handle, print sink, plan reporter, thought reporter,
+    // and approval handler
     let mut interp = match agent_handle {
         Some(handle) => Interpreter::with_agent(handle),
         None => Interpreter::new(),
     };
+    interp.configure(&config);
     interp.set_print_sink(print_tx);
     interp.set_plan_reporter(plan_tx);
     interp.set_thought_reporter(thought_tx);
+    interp.set_progress_reporter(progress_tx);
+    interp.set_warning_reporter(warning_tx);
+    interp.set_approval_handler(approval_tx);
+    interp.set_cancellation_token(token.clone());
+
+    // Keep the run's variables in a session for the ACP session, so later
+    // messages can use them. It expires with `session.ttl` unless the client
+    // names it, which keeps it for a REPL attached with `--session`.
+    let opened = match &session_name {
+        Some(name) => Session::attach(&config, name),
+        None => Session::resume(&config, &session_id),
+    };
+    let keeps_state = opened.is_ok();
+    match opened {
+        Ok(session) => {
+            interp.set_session(session);
+            if let Err(e) = interp.resume_session() {
+                tracing::warn!("Failed to restore session variables: {}", e);
+            }
+        }
+        Err(e) => {
+            tracing::warn!("Failed to open the session for {}: {}", session_id, e);
+            // Without a session directory, programs can't use `session`
+            match Session::start(&config) {
+                Ok(session) => interp.set_session(session),
+                Err(e) => tracing::warn!("Failed to create a session directory: {}", e),
+            }
+        }
+    }
+
+    // Carry "always allow" answers over from earlier evaluations in this session
+    for program in proxy.lock().unwrap().always_allowed(&session_id) {
+        interp.runtime_mut().always_allow(&program);
+    }
 
     // Spawn a task to forward print messages as notifications
     let connection_cx = cx.connection_cx().clone();
@@ -227,15 +432,69 @@ async fn run_patchwork_evaluation(
         forward_thought_chunks_to_notifications(thought_rx, &connection_cx_for_thoughts, &session_id_for_thoughts)
     });
 
+    // Spawn a task to forward progress reports as notifications
+    let connection_cx_for_progress = cx.connection_cx().clone();
+    let session_id_for_progress = session_id.clone();
+    let progress_forwarder = tokio::task::spawn_blocking(move || {
+        forward_progress_to_notifications(progress_rx, &connection_cx_for_progress, &session_id_for_progress)
+    });
+
+    // Spawn a task to forward warnings as notifications
+    let connection_cx_for_warnings = cx.connection_cx().clone();
+    let session_id_for_warnings = session_id.clone();
+    let warning_forwarder = tokio::task::spawn_blocking(move || {
+        forward_warnings_to_notifications(warning_rx, &connection_cx_for_warnings, &session_id_for_warnings)
+    });
+
+    // Spawn a task to ask the client to approve shell commands
+    let connection_cx_for_approvals = cx.connection_cx().clone();
+    let session_id_for_approvals = session_id.clone();
+    let proxy_for_approvals = Arc::clone(&proxy);
+    let approval_forwarder = tokio::task::spawn_blocking(move || {
+        forward_approval_requests_to_client(
+            approval_rx,
+            &connection_cx_for_approvals,
+            &session_id_for_approvals,
+            &proxy_for_approvals,
+        )
+    });
+
     // Evaluate on a blocking thread since interpreter may block on channels
-    let eval_result = tokio::task::spawn_blocking(move || interp.eval(&text))
-        .await
+    let (eval_result, artifacts) = tokio::task::spawn_blocking(move || {
+        let started = Instant::now();
+        let result = interp.eval_interactive(&text);
+        // Report it like a whole program's run, writing `--result-json` too
+        interp.runtime_mut().record_run(&result, started.elapsed());
+        if keeps_state {
+            if let Err(e) = interp.save_session() {
+                tracing::warn!("Failed to save session variables: {}", e);
+            }
+        }
+        let mut artifacts = Vec::new();
+        if let Some(session) = interp.take_session() {
+            artifacts = session.artifacts().unwrap_or_else(|e| {
+                tracing::warn!("Failed to read artifacts: {}", e);
+                Vec::new()
+            });
+            if let Err(e) = session.finish(Outcome::of(&result)) {
+                tracing::warn!("Failed to clean up session directory: {}", e);
+            }
+        }
+        (result, artifacts)
+    })
+    .await
         .map_err(|e| sacp::Error::internal_error().with_data(format!("Task error: {}", e)))?;
 
     // Wait for forwarders to complete (they will finish when channels are dropped)
     let _ = print_forwarder.await;
     let _ = plan_forwarder.await;
     let _ = thought_forwarder.await;
+    let _ = progress_forwarder.await;
+    let _ = warning_forwarder.await;
+    let _ = approval_forwarder.await;
+
+    // Link the run's artifacts so the client can open them
+    send_artifact_links(&artifacts, &cx.connection_cx(), &session_id);
 
     // End the evaluation regardless of result
     {
@@ -254,17 +513,24 @@ async fn run_patchwork_evaluation(
             ));
             cx.respond(response)?;
         }
-        Err(EvalError::Exception(value)) => {
-            tracing::error!("Patchwork code threw exception: {:?}", value);
+        Err(_) if token.is_cancelled() => {
+            // ACP asks for a Cancelled stop reason rather than an error
+            tracing::info!("Patchwork evaluation cancelled");
+            cx.respond(PromptResponse {
+                stop_reason: StopReason::Cancelled,
+                meta: None,
+            })?;
+        }
+        Err(e @ EvalError::Exception(_)) => {
+            tracing::error!("Patchwork code threw exception: {}", e);
             cx.respond_with_error(
-                sacp::Error::internal_error()
-                    .with_data(format!("Patchwork exception: {}", value)),
+                sacp::Error::internal_error().with_data(e.render(&Renderer::plain())),
             )?;
         }
         Err(e) => {
             tracing::error!("Patchwork parse/eval error: {}", e);
             cx.respond_with_error(
-                sacp::Error::invalid_params().with_data(format!("Patchwork error: {}", e)),
+                sacp::Error::invalid_params().with_data(e.render(&Renderer::plain())),
             )?;
         }
     }
@@ -283,12 +549,43 @@ fn forward_prints_to_notifications(
     while let Ok(message) = rx.recv() {
         tracing::debug!("Forwarding print output: {}", message);
 
+        if let Err(e) = send_agent_message(connection_cx, session_id, message) {
+            tracing::warn!("Failed to send print notification: {}", e);
+            break;
+        }
+    }
+}
+
+/// Show `text` to the user as a message from the agent.
+fn send_agent_message(connection_cx: &JrConnectionCx, session_id: &str, text: String) -> Result<(), sacp::Error> {
+    connection_cx.send_notification(SessionNotification {
+        session_id: session_id.to_string().into(),
+        update: SessionUpdate::AgentMessageChunk(ContentChunk {
+            content: ContentBlock::Text(TextContent {
+                annotations: None,
+                text,
+                meta: None,
+            }),
+            meta: None,
+        }),
+        meta: None,
+    })
+}
+
+/// Send a resource link for each artifact the run stored.
+fn send_artifact_links(artifacts: &[Artifact], connection_cx: &JrConnectionCx, session_id: &str) {
+    for artifact in artifacts {
         let notification = SessionNotification {
             session_id: session_id.to_string().into(),
             update: SessionUpdate::AgentMessageChunk(ContentChunk {
-                content: ContentBlock::Text(TextContent {
+                content: ContentBlock::ResourceLink(ResourceLink {
                     annotations: None,
-                    text: message,
+                    description: None,
+                    mime_type: Some(artifact.mime.clone()),
+                    name: artifact.name.clone(),
+                    size: Some(artifact.size as i64),
+                    title: None,
+                    uri: format!("file://{}", artifact.path.display()),
                     meta: None,
                 }),
                 meta: None,
@@ -297,7 +594,7 @@ fn forward_prints_to_notifications(
         };
 
         if let Err(e) = connection_cx.send_notification(notification) {
-            tracing::warn!("Failed to send print notification: {}", e);
+            tracing::warn!("Failed to send artifact link: {}", e);
             break;
         }
     }
@@ -380,6 +677,179 @@ fn forward_thought_chunks_to_notifications(
     }
 }
 
+/// Forward progress reports from the interpreter to ACP notifications.
+///
+/// ACP has no progress update of its own, so each report is sent as a
+/// SessionUpdate::Plan with a single entry showing the message and percentage.
+fn forward_progress_to_notifications(
+    rx: std::sync::mpsc::Receiver<EvalProgressUpdate>,
+    connection_cx: &JrConnectionCx,
+    session_id: &str,
+) {
+    while let Ok(update) = rx.recv() {
+        tracing::debug!("Forwarding progress: {:.0}% {}", update.fraction * 100.0, update.message);
+
+        let percent = (update.fraction * 100.0).round();
+        let content = if update.message.is_empty() {
+            format!("{}%", percent)
+        } else {
+            format!("{} ({}%)", update.message, percent)
+        };
+        let status = if update.fraction >= 1.0 {
+            PlanEntryStatus::Completed
+        } else {
+            PlanEntryStatus::InProgress
+        };
+
+        let notification = SessionNotification {
+            session_id: session_id.to_string().into(),
+            update: SessionUpdate::Plan(Plan {
+                entries: vec![PlanEntry {
+                    content,
+                    priority: PlanEntryPriority::Medium,
+                    status,
+                    meta: None,
+                }],
+                meta: None,
+            }),
+            meta: None,
+        };
+
+        if let Err(e) = connection_cx.send_notification(notification) {
+            tracing::warn!("Failed to send progress notification: {}", e);
+            break;
+        }
+    }
+}
+
+/// Forward warnings from the interpreter to ACP notifications.
+///
+/// ACP has no warning update, so each warning is sent as an
+/// AgentMessageChunk on a line of its own, after the output so far.
+fn forward_warnings_to_notifications(
+    rx: std::sync::mpsc::Receiver<EvalWarning>,
+    connection_cx: &JrConnectionCx,
+    session_id: &str,
+) {
+    while let Ok(warning) = rx.recv() {
+        tracing::warn!("Patchwork {}", warning);
+
+        let notification = SessionNotification {
+            session_id: session_id.to_string().into(),
+            update: SessionUpdate::AgentMessageChunk(ContentChunk {
+                content: ContentBlock::Text(TextContent {
+                    annotations: None,
+                    text: format!("\n{}\n", warning),
+                    meta: None,
+                }),
+                meta: None,
+            }),
+            meta: None,
+        };
+
+        if let Err(e) = connection_cx.send_notification(notification) {
+            tracing::warn!("Failed to send warning notification: {}", e);
+            break;
+        }
+    }
+}
+
+const ALLOW_ONCE: &str = "allow-once";
+const ALLOW_ALWAYS: &str = "allow-always";
+const REJECT_ONCE: &str = "reject-once";
+
+/// Ask the client to approve shell commands and `approve` gates from the
+/// interpreter.
+///
+/// This runs in a blocking context and sends each approval request as an ACP
+/// permission request, blocking until the user answers. "Always allow"
+/// answers are remembered on the proxy for the rest of the session; gates
+/// only offer allow and deny.
+fn forward_approval_requests_to_client(
+    rx: std::sync::mpsc::Receiver<ApprovalRequest>,
+    connection_cx: &JrConnectionCx,
+    session_id: &str,
+    proxy: &Mutex<PatchworkProxy>,
+) {
+    let runtime = tokio::runtime::Handle::current();
+    for (index, request) in rx.iter().enumerate() {
+        let fields = match &request.program {
+            Some(_) => {
+                tracing::info!("Requesting approval for shell command: {}", request.summary);
+                ToolCallUpdateFields {
+                    kind: Some(ToolKind::Execute),
+                    title: Some(format!("Run `{}`", request.summary)),
+                    raw_input: Some(serde_json::json!({ "command": request.summary })),
+                    ..Default::default()
+                }
+            }
+            None => {
+                tracing::info!("Requesting approval for: {}", request.summary);
+                ToolCallUpdateFields {
+                    kind: Some(ToolKind::Other),
+                    title: Some(request.summary.clone()),
+                    raw_input: Some(serde_json::json!({ "summary": request.summary })),
+                    ..Default::default()
+                }
+            }
+        };
+
+        let mut options = vec![permission_option(ALLOW_ONCE, "Allow".to_string(), PermissionOptionKind::AllowOnce)];
+        if let Some(program) = &request.program {
+            options.push(permission_option(
+                ALLOW_ALWAYS,
+                format!("Always allow `{}`", program),
+                PermissionOptionKind::AllowAlways,
+            ));
+        }
+        options.push(permission_option(REJECT_ONCE, "Deny".to_string(), PermissionOptionKind::RejectOnce));
+
+        let permission_request = RequestPermissionRequest {
+            session_id: session_id.to_string().into(),
+            tool_call: ToolCallUpdate {
+                id: ToolCallId(format!("patchwork-approval-{}", index).into()),
+                fields,
+                meta: None,
+            },
+            options,
+            meta: None,
+        };
+
+        let decision = match runtime.block_on(connection_cx.send_request(permission_request).block_task()) {
+            Ok(response) => match response.outcome {
+                RequestPermissionOutcome::Selected { option_id } => match &*option_id.0 {
+                    ALLOW_ONCE => ApprovalDecision::AllowOnce,
+                    ALLOW_ALWAYS => match &request.program {
+                        Some(program) => {
+                            proxy.lock().unwrap().always_allow(session_id, program);
+                            ApprovalDecision::AlwaysAllow
+                        }
+                        None => ApprovalDecision::AllowOnce,
+                    },
+                    _ => ApprovalDecision::Deny,
+                },
+                RequestPermissionOutcome::Cancelled => ApprovalDecision::Deny,
+            },
+            Err(e) => {
+                tracing::warn!("Permission request failed: {}", e);
+                ApprovalDecision::Deny
+            }
+        };
+
+        // The interpreter may have given up waiting; nothing to do then
+        let _ = request.response_tx.send(decision);
+    }
+}
+
+fn permission_option(id: &str, name: String, kind: PermissionOptionKind) -> PermissionOption {
+    PermissionOption {
+        id: PermissionOptionId(id.into()),
+        name,
+        kind,
+        meta: None,
+    }
+}
+
 /// Create a simple text response.
 fn create_text_response(_text: String) -> PromptResponse {
     // TODO: In a full implementation, we'd need to send progress notifications
@@ -403,37 +873,23 @@ async fn main() -> anyhow::Result<()> {
 
     tracing::info!("Starting Patchwork ACP proxy");
 
-    // Create shared proxy state
-    let proxy = Arc::new(Mutex::new(PatchworkProxy::new()));
+    // Load settings: defaults < config files < PATCHWORK_* env < flags
+    let (cli, rest) = ConfigLayer::from_args(std::env::args().skip(1))?;
+    if let Some(arg) = rest.first() {
+        anyhow::bail!("unexpected argument `{}`", arg);
+    }
+    let cwd = std::env::current_dir()?;
+    let mut config = Config::load(&cwd, &cli)?;
+    SandboxPolicy::load(&cwd)?.apply(&mut config, &cwd);
+    if config.backend == Backend::Anthropic {
+        anyhow::bail!("the `anthropic` backend is not supported by the ACP proxy; use `acp` or `offline`");
+    }
+    tracing::info!("Using {} backend", config.backend);
 
-    // Create MCP registry for the "do" tool
-    let mcp_registry = McpServiceRegistry::default();
+    // Create shared proxy state
+    let proxy = Arc::new(Mutex::new(PatchworkProxy::new(config)));
 
-    // Build the handler chain
-    let proxy_clone = Arc::clone(&proxy);
-    let proxy_for_notifs = Arc::clone(&proxy);
-    JrHandlerChain::new()
-        .name("patchwork-acp")
-        .on_receive_request(move |request: PromptRequest, cx: JrRequestCx<PromptResponse>| {
-            let proxy = Arc::clone(&proxy_clone);
-            async move {
-                handle_prompt(proxy, request, cx).await
-            }
-        })
-        // Route session notifications from successor to active think blocks
-        .on_receive_notification_from_successor({
-            async move |notification: SessionNotification, _cx| {
-                // Route to redirect actor if we have one
-                if let Some(redirect_tx) = proxy_for_notifs.lock().unwrap().redirect_tx() {
-                    let _ = redirect_tx.send(RedirectMessage::IncomingMessage(
-                        PerSessionMessage::SessionNotification(notification),
-                    ));
-                }
-                Ok(())
-            }
-        })
-        .provide_mcp(mcp_registry)
-        .proxy()
+    handler_chain(Arc::clone(&proxy))
         .connect_to(sacp::ByteStreams::new(
             tokio::io::stdout().compat_write(),
             tokio::io::stdin().compat(),
@@ -446,6 +902,10 @@ async fn main() -> anyhow::Result<()> {
                 let (agent_handle, redirect_tx, mut request_rx, state) =
                     agent::create_agent(cx.clone(), mcp_registry);
 
+                // Start the agents think blocks can be routed to besides the successor
+                let agents = proxy_for_client.lock().unwrap().config().agents.clone();
+                let _ = state.agents.set(routes::start_agents(&agents, &state, &cx).await);
+
                 // Store in proxy so handle_prompt can access it
                 {
                     let mut proxy = proxy_for_client.lock().unwrap();
@@ -468,3 +928,121 @@ async fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// The proxy's handlers, in front of the sacp-proxy layer that talks to
+/// the conductor.
+fn handler_chain(proxy: Arc<Mutex<PatchworkProxy>>) -> JrHandlerChain<impl JrMessageHandler> {
+    // Create MCP registry for the "do" tool
+    let mcp_registry = McpServiceRegistry::default();
+
+    let proxy_clone = Arc::clone(&proxy);
+    let proxy_for_init = Arc::clone(&proxy);
+    let proxy_for_notifs = Arc::clone(&proxy);
+    let proxy_for_cancel = proxy;
+    JrHandlerChain::new()
+        .name("patchwork-acp")
+        // Limit what programs may do to what the editor supports, then let
+        // the proxy layer negotiate the proxy capability and ask the successor
+        .on_receive_request(async move |request: InitializeRequest, cx: JrRequestCx<InitializeResponse>| {
+            proxy_for_init.lock().unwrap().restrict_to_client(&request.client_capabilities);
+            Ok(Handled::No((request, cx)))
+        })
+        .on_receive_request(move |request: PromptRequest, cx: JrRequestCx<PromptResponse>| {
+            let proxy = Arc::clone(&proxy_clone);
+            async move {
+                handle_prompt(proxy, request, cx).await
+            }
+        })
+        // Stop the session's evaluation on session/cancel, then pass the
+        // notification on so the successor cancels its in-flight turns too
+        .on_receive_notification({
+            async move |notification: CancelNotification, cx: JrConnectionCx| {
+                let session_id = notification.session_id.to_string();
+                if proxy_for_cancel.lock().unwrap().cancel_evaluation(&session_id) {
+                    tracing::info!("Cancelling Patchwork evaluation for session {}", session_id);
+                }
+                cx.send_notification_to_successor(notification)
+            }
+        })
+        // Route session notifications from successor to active think blocks
+        .on_receive_notification_from_successor({
+            async move |notification: SessionNotification, _cx| {
+                // Route to redirect actor if we have one
+                if let Some(redirect_tx) = proxy_for_notifs.lock().unwrap().redirect_tx() {
+                    let _ = redirect_tx.send(RedirectMessage::IncomingMessage(
+                        PerSessionMessage::SessionNotification(Box::new(notification)),
+                    ));
+                }
+                Ok(())
+            }
+        })
+        .provide_mcp(mcp_registry)
+        .proxy()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sacp::schema::VERSION;
+    use sacp::{MetaCapabilityExt, Proxy};
+    use sacp_proxy::SuccessorRequest;
+    use tokio::task::LocalSet;
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_initialize_advertises_proxy() {
+        LocalSet::new()
+            .run_until(async {
+                let (conductor_writer, proxy_reader) = tokio::io::duplex(4096);
+                let (proxy_writer, conductor_reader) = tokio::io::duplex(4096);
+                let proxy = Arc::new(Mutex::new(PatchworkProxy::new(Config::default())));
+                let chain = handler_chain(proxy);
+                tokio::task::spawn_local(async move {
+                    let _ = chain
+                        .serve(sacp::ByteStreams::new(proxy_writer.compat_write(), proxy_reader.compat()))
+                        .await;
+                });
+
+                // Play the conductor: ask the proxy to initialize, and answer
+                // for the successor when it passes the request on
+                let successor_saw_proxy = Arc::new(Mutex::new(None));
+                let saw = Arc::clone(&successor_saw_proxy);
+                let response = Arc::new(Mutex::new(None));
+                let received = Arc::clone(&response);
+                JrHandlerChain::new()
+                    .on_receive_request(
+                        async move |request: SuccessorRequest<InitializeRequest>,
+                                    cx: JrRequestCx<InitializeResponse>| {
+                            *saw.lock().unwrap() = Some(request.request.has_meta_capability(Proxy));
+                            cx.respond(InitializeResponse {
+                                protocol_version: VERSION,
+                                agent_capabilities: Default::default(),
+                                auth_methods: vec![],
+                                agent_info: None,
+                                meta: None,
+                            })
+                        },
+                    )
+                    .with_client(
+                        sacp::ByteStreams::new(conductor_writer.compat_write(), conductor_reader.compat()),
+                        async move |cx| {
+                            let request = InitializeRequest {
+                                protocol_version: VERSION,
+                                client_capabilities: ClientCapabilities::default(),
+                                client_info: None,
+                                meta: None,
+                            }
+                            .add_meta_capability(Proxy);
+                            *received.lock().unwrap() = Some(cx.send_request(request).block_task().await?);
+                            Ok(())
+                        },
+                    )
+                    .await
+                    .unwrap();
+
+                assert_eq!(*successor_saw_proxy.lock().unwrap(), Some(false));
+                let response = response.lock().unwrap().take().unwrap();
+                assert!(response.has_meta_capability(Proxy));
+            })
+            .await;
+    }
+}
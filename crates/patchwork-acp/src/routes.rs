@@ -0,0 +1,216 @@
+//! Downstream agents besides the successor.
+//!
+//! The config's `agents` table names agents the proxy starts next to its
+//! successor, such as a local model behind its own ACP adapter:
+//!
+//! ```json
+//! {
+//!   "agents": {
+//!     "local": { "command": "ollama-acp", "args": ["--model", "llama3"] }
+//!   }
+//! }
+//! ```
+//!
+//! A think block goes to the agent its skill's `@agent name` annotation
+//! names, else to the config's `agent`, else to the successor, so one
+//! editor session can mix models. Each agent is started once, when the
+//! proxy connects, and speaks ACP on its standard input and output with the
+//! proxy as its client. An agent that doesn't initialize within
+//! `AGENT_STARTUP_TIMEOUT` is stopped, and think blocks routed to it fail
+//! with the reason. Session notifications it sends are routed to the think
+//! block waiting on it like the successor's; its permission requests go to
+//! the editor, and its sessions get the proxy's MCP servers, as the
+//! successor's do.
+
+use std::collections::{BTreeMap, HashMap};
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::Context;
+use sacp::schema::{
+    ClientCapabilities, InitializeRequest, RequestPermissionRequest, RequestPermissionResponse, SessionNotification,
+    VERSION,
+};
+use sacp::{
+    Handled, JrConnectionCx, JrHandlerChain, JrMessage, JrMessageHandler, JrRequest, JrRequestCx, JrResponse,
+    MessageAndCx,
+};
+use sacp_proxy::{JrCxExt, McpServiceRegistry, SuccessorNotification, SuccessorRequest};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
+
+use patchwork_eval::AgentCommand;
+
+use crate::agent::{AgentState, PerSessionMessage, RedirectMessage};
+
+/// How long an agent may take to start and answer `initialize`.
+const AGENT_STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where a think block opens its session.
+#[derive(Clone)]
+pub enum Downstream {
+    /// The next agent in the proxy chain, such as claude-code-acp.
+    Successor(JrConnectionCx),
+    /// An agent the proxy started from the `agents` table.
+    Agent(JrConnectionCx),
+}
+
+impl Downstream {
+    /// Send `request` to this agent.
+    pub fn send_request<Req: JrRequest>(&self, request: Req) -> JrResponse<Req::Response> {
+        match self {
+            Downstream::Successor(cx) => cx.send_request_to_successor(request),
+            Downstream::Agent(cx) => cx.send_request(request),
+        }
+    }
+}
+
+/// The agents from the config's `agents` table.
+#[derive(Default)]
+pub struct Agents {
+    /// Connections to the agents that came up, by name.
+    pub running: HashMap<String, JrConnectionCx>,
+    /// Why each of the others didn't, by name.
+    pub failed: HashMap<String, String>,
+}
+
+/// Start every agent in `agents` at once. Agents that fail to start are
+/// logged and left out, so think blocks routed to them fail with the reason.
+/// `client_cx` is the proxy's connection to the editor.
+pub async fn start_agents(
+    agents: &BTreeMap<String, AgentCommand>,
+    state: &AgentState,
+    client_cx: &JrConnectionCx,
+) -> Agents {
+    let mut starting = tokio::task::JoinSet::new();
+    for (name, command) in agents {
+        let (name, command) = (name.clone(), command.clone());
+        let redirect_tx = state.redirect_tx.clone();
+        let mcp_registry = state.mcp_registry.clone();
+        let client_cx = client_cx.clone();
+        starting.spawn(async move {
+            let started = start_agent(&name, &command, redirect_tx, mcp_registry, client_cx).await;
+            (name, command, started)
+        });
+    }
+
+    let mut started = Agents::default();
+    while let Some(joined) = starting.join_next().await {
+        match joined {
+            Ok((name, command, Ok(cx))) => {
+                tracing::info!("Started agent {} ({})", name, command.command);
+                started.running.insert(name, cx);
+            }
+            Ok((name, _, Err(e))) => {
+                tracing::error!("Failed to start agent {}: {:#}", name, e);
+                started.failed.insert(name, format!("{:#}", e));
+            }
+            Err(e) => tracing::error!("Failed to start an agent: {}", e),
+        }
+    }
+    started
+}
+
+/// Run `command` and connect to it as an ACP client.
+async fn start_agent(
+    name: &str,
+    command: &AgentCommand,
+    redirect_tx: UnboundedSender<RedirectMessage>,
+    mcp_registry: McpServiceRegistry,
+    client_cx: JrConnectionCx,
+) -> anyhow::Result<JrConnectionCx> {
+    let mut child = tokio::process::Command::new(&command.command)
+        .args(&command.args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("couldn't run `{}`", command.command))?;
+    let stdin = child.stdin.take().expect("stdin is piped");
+    let stdout = child.stdout.take().expect("stdout is piped");
+
+    let (ready_tx, ready_rx) = oneshot::channel();
+    let name = name.to_string();
+    let connection_task = tokio::spawn(async move {
+        // Held here so the agent is killed when its connection ends
+        let _child = child;
+        let connection = async move {
+            JrHandlerChain::new()
+                .name("patchwork-acp")
+                .on_receive_notification(async move |notification: SessionNotification, _cx: JrConnectionCx| {
+                    let _ = redirect_tx.send(RedirectMessage::IncomingMessage(
+                        PerSessionMessage::SessionNotification(Box::new(notification)),
+                    ));
+                    Ok(())
+                })
+                // The editor answers its permission requests
+                .on_receive_request(
+                    move |request: RequestPermissionRequest, request_cx: JrRequestCx<RequestPermissionResponse>| {
+                        let client_cx = client_cx.clone();
+                        async move { client_cx.send_request(request).forward_to_request_cx(request_cx) }
+                    },
+                )
+                .with_handler(AgentMcp(mcp_registry))
+                .connect_to(sacp::ByteStreams::new(stdin.compat_write(), stdout.compat()))?
+                .with_client(async move |cx| {
+                    cx.send_request(InitializeRequest {
+                        protocol_version: VERSION,
+                        client_capabilities: ClientCapabilities::default(),
+                        client_info: None,
+                        meta: None,
+                    })
+                    .block_task()
+                    .await?;
+                    let _ = ready_tx.send(cx);
+                    // Stay connected for as long as the proxy runs
+                    std::future::pending::<Result<(), sacp::Error>>().await
+                })
+                .await
+        };
+        if let Err(e) = connection.await {
+            tracing::error!("Agent {} disconnected: {}", name, e);
+        }
+    });
+
+    match tokio::time::timeout(AGENT_STARTUP_TIMEOUT, ready_rx).await {
+        Ok(ready) => ready.context("the agent exited before it was ready"),
+        Err(_) => {
+            // Dropping the connection kills the agent
+            connection_task.abort();
+            anyhow::bail!("the agent didn't initialize within {} seconds", AGENT_STARTUP_TIMEOUT.as_secs())
+        }
+    }
+}
+
+/// Serves the proxy's MCP servers to an agent it started. The registry
+/// answers `_mcp/*` messages as they arrive from a successor, so they are
+/// wrapped the same way before it sees them.
+struct AgentMcp(McpServiceRegistry);
+
+impl JrMessageHandler for AgentMcp {
+    fn describe_chain(&self) -> impl std::fmt::Debug {
+        "AgentMcp"
+    }
+
+    async fn handle_message(&mut self, message: MessageAndCx) -> Result<Handled<MessageAndCx>, sacp::Error> {
+        if !message.method().starts_with("_mcp/") {
+            return Ok(Handled::No(message));
+        }
+        let original = message.message().clone();
+        let wrapped = match message {
+            MessageAndCx::Request(request, cx) => {
+                MessageAndCx::Request(SuccessorRequest { request, meta: None }.into_untyped_message()?, cx)
+            }
+            MessageAndCx::Notification(notification, cx) => {
+                MessageAndCx::Notification(SuccessorNotification { notification, meta: None }.into_untyped_message()?, cx)
+            }
+        };
+        // A connection the registry doesn't know goes on as it came
+        Ok(match self.0.handle_message(wrapped).await? {
+            Handled::Yes => Handled::Yes,
+            Handled::No(MessageAndCx::Request(_, cx)) => Handled::No(MessageAndCx::Request(original, cx)),
+            Handled::No(MessageAndCx::Notification(_, cx)) => Handled::No(MessageAndCx::Notification(original, cx)),
+        })
+    }
+}
@@ -0,0 +1,1091 @@
+//! The type checking pass.
+//!
+//! Walks a parsed program, infers types bottom-up from literals and
+//! annotations, and reports operations that are guaranteed to fail at
+//! runtime: calling a non-function, indexing with the wrong key type,
+//! calling with the wrong number of arguments, and annotation mismatches.
+//!
+//! The pass is deliberately permissive: anything it cannot see through is
+//! `Type::Unknown`, and unknown types never produce diagnostics.
+
+use std::collections::HashMap;
+
+use patchwork_parser::ast::*;
+use patchwork_parser::diagnostics::{Diagnostic, Label};
+use patchwork_parser::resolve::{ResolvedProgram, SymbolId, SymbolTable};
+
+use crate::types::{FunctionType, Type};
+
+/// Diagnostic codes reported by the checker.
+pub mod codes {
+    pub const NOT_CALLABLE: &str = "TY_NOT_CALLABLE";
+    pub const BAD_INDEX: &str = "TY_BAD_INDEX";
+    pub const ARITY: &str = "TY_ARITY";
+    pub const MISMATCH: &str = "TY_MISMATCH";
+    pub const BAD_OPERAND: &str = "TY_BAD_OPERAND";
+    pub const ASSIGN_CONST: &str = "TY_ASSIGN_CONST";
+}
+
+/// How a variable was introduced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingKind {
+    /// `var name = ...`
+    Var,
+    /// A name bound by a destructuring pattern.
+    Pattern,
+    /// `for var name in ...`
+    LoopVar,
+    /// A function, skill, or worker parameter.
+    Param,
+}
+
+/// A variable binding and its inferred type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub name: String,
+    pub kind: BindingKind,
+    pub ty: Type,
+    /// Name of the enclosing item (function, skill, worker).
+    pub context: String,
+    /// Declared with an explicit type annotation.
+    pub annotated: bool,
+    /// The resolver's symbol for this binding (see `check_resolved`).
+    pub symbol: Option<SymbolId>,
+    /// Byte range of the binding's name (see `check_resolved`).
+    pub span: Option<(usize, usize)>,
+}
+
+/// Output of the checker.
+#[derive(Debug, Clone, Default)]
+pub struct CheckResult {
+    pub diagnostics: Vec<Diagnostic>,
+    /// Every binding in the program, in source order.
+    pub bindings: Vec<Binding>,
+}
+
+impl CheckResult {
+    /// Were any errors reported?
+    pub fn has_errors(&self) -> bool {
+        !self.diagnostics.is_empty()
+    }
+}
+
+/// Type check a parsed program.
+pub fn check_program(program: &Program) -> CheckResult {
+    let mut checker = Checker::new(None);
+    checker.check_program(program);
+    CheckResult {
+        diagnostics: checker.diagnostics,
+        bindings: checker.bindings,
+    }
+}
+
+/// Type check a resolved program.
+///
+/// Same as `check_program`, but each binding also records its symbol and
+/// source span from the resolver's symbol table.
+pub fn check_resolved(resolved: &ResolvedProgram) -> CheckResult {
+    let mut checker = Checker::new(Some(&resolved.symbols));
+    checker.check_program(resolved.program);
+    CheckResult {
+        diagnostics: checker.diagnostics,
+        bindings: checker.bindings,
+    }
+}
+
+/// Signatures of the interpreter's builtin functions.
+fn builtin_signature(name: &str) -> Option<FunctionType> {
+    let sig = match name {
+        "cat" => FunctionType::new(vec![Type::Unknown], Type::String),
+        "json" => FunctionType::new(vec![Type::Unknown], Type::Unknown),
+        "print" => FunctionType::variadic(Type::Null),
+        "len" => FunctionType::new(vec![Type::Unknown], Type::Number),
+        "keys" => FunctionType::new(vec![Type::Unknown], Type::Array(Box::new(Type::String))),
+        "values" => FunctionType::new(vec![Type::Unknown], Type::Array(Box::new(Type::Unknown))),
+        "typeof" | "type_of" => FunctionType::new(vec![Type::Unknown], Type::String),
+        "is_null" | "is_string" | "is_number" | "is_boolean" | "is_array" | "is_object" | "is_set"
+        | "is_tuple" => {
+            FunctionType::new(vec![Type::Unknown], Type::Boolean)
+        }
+        "read" => FunctionType::new(vec![Type::String], Type::String),
+        "write" => FunctionType::new(vec![Type::String, Type::Unknown], Type::Null),
+        // Sets and tuples have no static type of their own yet
+        "set" | "tuple" | "union" | "intersect" => FunctionType::variadic(Type::Unknown),
+        "contains" => FunctionType::new(vec![Type::Unknown, Type::Unknown], Type::Boolean),
+        "group_by" => FunctionType::new(vec![Type::Unknown, Type::String], Type::Unknown),
+        "sort_by" => FunctionType::variadic(Type::Array(Box::new(Type::Unknown))),
+        "unique" | "flatten" => FunctionType::new(vec![Type::Unknown], Type::Array(Box::new(Type::Unknown))),
+        "chunk" => FunctionType::new(
+            vec![Type::Unknown, Type::Number],
+            Type::Array(Box::new(Type::Array(Box::new(Type::Unknown)))),
+        ),
+        "to_fixed" => FunctionType::new(vec![Type::Number, Type::Number], Type::String),
+        "format_number" => FunctionType::variadic(Type::String),
+        "pad_left" | "pad_right" => FunctionType::variadic(Type::String),
+        "truncate" => FunctionType::variadic(Type::String),
+        "template" | "render_template" => FunctionType::new(vec![Type::String, Type::Unknown], Type::String),
+        "include_prompt" => FunctionType::new(vec![Type::String], Type::String),
+        "validate" => FunctionType::new(vec![Type::Unknown, Type::Unknown], Type::Unknown),
+        "eval_patchwork" => FunctionType::variadic(Type::Unknown),
+        "budget_remaining" => {
+            let budget = Type::Object(vec![
+                ("llm_calls".to_string(), Type::Unknown),
+                ("total_tokens".to_string(), Type::Unknown),
+                ("cost_usd".to_string(), Type::Unknown),
+            ]);
+            FunctionType::new(vec![], budget)
+        }
+        "history" => {
+            let entry = Type::Object(vec![
+                ("kind".to_string(), Type::String),
+                ("prompt".to_string(), Type::String),
+                ("response".to_string(), Type::Unknown),
+            ]);
+            FunctionType::new(vec![], Type::Array(Box::new(entry)))
+        }
+        "last_response" => FunctionType::new(vec![], Type::Unknown),
+        "pin" => FunctionType::new(vec![Type::Unknown], Type::Null),
+        "pinned" => FunctionType::new(vec![], Type::Array(Box::new(Type::String))),
+        "last_call_meta" => {
+            let meta = Type::Object(vec![
+                ("value".to_string(), Type::Unknown),
+                ("model".to_string(), Type::Unknown),
+                ("tokens_in".to_string(), Type::Number),
+                ("tokens_out".to_string(), Type::Number),
+                ("latency_ms".to_string(), Type::Number),
+                ("stop_reason".to_string(), Type::Unknown),
+            ]);
+            FunctionType::new(vec![], meta)
+        }
+        "fork_context" => FunctionType::new(vec![], Type::Number),
+        "in_context" => FunctionType::new(vec![Type::Number, Type::Unknown], Type::Unknown),
+        "merge_context" => FunctionType::new(vec![Type::Number], Type::Null),
+        "progress" => FunctionType::variadic(Type::Null),
+        "warn" => FunctionType::new(vec![Type::Unknown], Type::Null),
+        "step_done" => FunctionType::new(vec![], Type::Null),
+        "sleep" => FunctionType::new(vec![Type::Unknown], Type::Null),
+        "schedule_at" => FunctionType::new(vec![Type::Unknown, Type::Unknown], Type::Unknown),
+        "with_timeout" => FunctionType::new(vec![Type::Unknown, Type::Unknown], Type::Unknown),
+        "now" => FunctionType::new(vec![], Type::Number),
+        "wait_all" => FunctionType::variadic(Type::Array(Box::new(Type::String))),
+        "wait_any" => FunctionType::variadic(Type::Unknown),
+        "temp_file" | "temp_dir" => FunctionType::variadic(Type::String),
+        _ => return None,
+    };
+    Some(sig)
+}
+
+struct Variable {
+    ty: Type,
+    /// Declared with an explicit annotation; assignments must respect it.
+    annotated: bool,
+    /// Declared with a module-level `const`; assignments are errors.
+    constant: bool,
+}
+
+struct Checker<'r> {
+    symbols: Option<&'r SymbolTable>,
+    scopes: Vec<HashMap<String, Variable>>,
+    functions: HashMap<String, FunctionType>,
+    aliases: HashMap<String, Type>,
+    /// Name of the item currently being checked.
+    context: String,
+    diagnostics: Vec<Diagnostic>,
+    bindings: Vec<Binding>,
+}
+
+impl<'r> Checker<'r> {
+    fn new(symbols: Option<&'r SymbolTable>) -> Self {
+        Self {
+            symbols,
+            scopes: vec![HashMap::new()],
+            functions: HashMap::new(),
+            aliases: HashMap::new(),
+            context: String::new(),
+            diagnostics: Vec::new(),
+            bindings: Vec::new(),
+        }
+    }
+
+    fn check_program(&mut self, program: &Program) {
+        // First pass: collect type aliases and callable signatures so items
+        // can refer to each other regardless of declaration order.
+        for item in &program.items {
+            if let Item::Type(decl) = item {
+                let ty = Type::from_annotation(&decl.type_expr, &self.aliases);
+                self.aliases.insert(decl.name.to_string(), ty);
+            }
+        }
+        for item in &program.items {
+            match item {
+                Item::Function(decl) => self.declare_callable(decl.name, &decl.params),
+                Item::Skill(decl) => self.declare_callable(decl.name, &decl.params),
+                Item::Worker(decl) => self.declare_callable(decl.name, &decl.params),
+                _ => {}
+            }
+        }
+
+        // Module-level variables, in the order the interpreter initializes
+        // them, so each sees the types of the declarations it reads.
+        let order = self
+            .symbols
+            .and_then(|table| table.initialization_order().ok())
+            .unwrap_or_else(|| (0..program.items.len()).collect());
+        for index in order {
+            if let Item::Var(decl) = &program.items[index] {
+                let ty = self.infer(&decl.init);
+                self.bind_pattern(&decl.pattern, ty, true, BindingKind::Var);
+                if decl.is_const {
+                    for name in decl.pattern.names() {
+                        if let Some(var) = self.scopes[0].get_mut(name) {
+                            var.constant = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Second pass: check bodies.
+        for item in &program.items {
+            match item {
+                Item::Function(decl) => self.check_callable(decl.name, &decl.params, &decl.body),
+                Item::Skill(decl) => self.check_callable(decl.name, &decl.params, &decl.body),
+                Item::Worker(decl) => self.check_callable(decl.name, &decl.params, &decl.body),
+                Item::Trait(decl) => {
+                    for method in &decl.methods {
+                        let name = format!("{}.{}", decl.name, method.name);
+                        self.check_callable(&name, &method.params, &method.body);
+                    }
+                }
+                Item::Eval(decl) => self.check_eval(decl),
+                Item::Import(_) | Item::Type(_) | Item::Var(_) => {}
+            }
+        }
+    }
+
+    fn check_eval(&mut self, decl: &EvalDecl) {
+        self.context = format!("eval \"{}\"", decl.name);
+        let cases = self.infer(&decl.input);
+        let case = match cases {
+            Type::Array(element) => *element,
+            _ => Type::Unknown,
+        };
+        self.push_scope();
+        self.bind("input", case, false, BindingKind::Param);
+        let output = self.infer(&decl.output);
+        self.bind("output", output.clone(), false, BindingKind::Param);
+        for check in &decl.checks {
+            match check {
+                EvalCheck::Contains(expr) | EvalCheck::Judge(expr) => {
+                    self.infer(expr);
+                }
+                EvalCheck::Expect(name) => {
+                    let expected = Type::from_annotation(&TypeExpr::Name(name), &self.aliases);
+                    if !output.is_unknown() && !expected.is_unknown() && !output.is_assignable_to(&expected) {
+                        self.report_at(
+                            codes::MISMATCH,
+                            format!("The output is {} but `expect` wants {}", output, expected),
+                            name,
+                        );
+                    }
+                }
+            }
+        }
+        self.pop_scope();
+    }
+
+    fn declare_callable(&mut self, name: &str, params: &[Param]) {
+        let params = params
+            .iter()
+            .map(|p| self.annotation_type(p.type_ann.as_ref()))
+            .collect();
+        self.functions
+            .insert(name.to_string(), FunctionType::new(params, Type::Unknown));
+    }
+
+    fn check_callable(&mut self, name: &str, params: &[Param], body: &Block) {
+        self.context = name.to_string();
+        self.push_scope();
+        for param in params {
+            let ty = self.annotation_type(param.type_ann.as_ref());
+            let annotated = param.type_ann.is_some();
+            self.bind(param.name, ty, annotated, BindingKind::Param);
+        }
+        self.check_block(body);
+        self.pop_scope();
+    }
+
+    fn annotation_type(&self, ann: Option<&TypeExpr>) -> Type {
+        ann.map(|t| Type::from_annotation(t, &self.aliases))
+            .unwrap_or(Type::Unknown)
+    }
+
+    // ---- scopes ----
+
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+
+    /// Bind `name`, which must be the identifier slice from the AST so the
+    /// symbol table can identify it.
+    fn bind(&mut self, name: &str, ty: Type, annotated: bool, kind: BindingKind) {
+        let symbol = self.symbols.and_then(|table| table.declared_by(name));
+        self.bindings.push(Binding {
+            name: name.to_string(),
+            kind,
+            ty: ty.clone(),
+            context: self.context.clone(),
+            annotated,
+            symbol,
+            span: symbol.and_then(|id| self.symbols?.symbol(id).span),
+        });
+        self.scopes
+            .last_mut()
+            .expect("scope stack should never be empty")
+            .insert(name.to_string(), Variable { ty, annotated, constant: false });
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Variable> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn report(&mut self, code: &str, message: String) {
+        let mut diag = Diagnostic::error(message).with_code(code);
+        if !self.context.is_empty() {
+            diag = diag.with_note(format!("in `{}`", self.context));
+        }
+        self.diagnostics.push(diag);
+    }
+
+    /// Report a problem with the identifier slice `at`. When checking a
+    /// resolved program, the diagnostic points at it, and at the
+    /// declaration it refers to, if any.
+    fn report_at(&mut self, code: &str, message: String, at: &str) {
+        self.report(code, message);
+        let Some(table) = self.symbols else {
+            return;
+        };
+        let reference = table.reference(at);
+        let span = match reference {
+            Some(reference) => reference.span,
+            None => table.declared_by(at).and_then(|id| table.symbol(id).span),
+        };
+        let Some(span) = span else {
+            return;
+        };
+        let diag = self.diagnostics.last_mut().expect("just reported");
+        diag.labels.push(Label { span, message: String::new() });
+        let declared = reference.and_then(|r| r.resolution.symbol()).and_then(|id| table.symbol(id).span);
+        if let Some(declared) = declared {
+            diag.labels.push(Label { span: declared, message: format!("`{}` is declared here", at) });
+        }
+    }
+
+    // ---- statements ----
+
+    fn check_block(&mut self, block: &Block) -> Type {
+        self.push_scope();
+        let mut result = Type::Null;
+        for stmt in &block.statements {
+            result = self.check_statement(stmt);
+        }
+        self.pop_scope();
+        result
+    }
+
+    fn check_statement(&mut self, stmt: &Statement) -> Type {
+        match stmt {
+            Statement::VarDecl { pattern, init } => {
+                let ty = match init {
+                    Some(expr) => self.infer(expr),
+                    None => Type::Null,
+                };
+                self.bind_pattern(pattern, ty, init.is_some(), BindingKind::Var);
+                Type::Null
+            }
+
+            Statement::Expr(expr) => self.infer(expr),
+
+            Statement::If { condition, then_block, else_block } => {
+                self.infer(condition);
+                let then_ty = self.check_block(then_block);
+                match else_block {
+                    Some(block) => then_ty.join(&self.check_block(block)),
+                    None => then_ty.join(&Type::Null),
+                }
+            }
+
+            Statement::ForIn { var, iter, body } => {
+                let iter_ty = self.infer(iter);
+                let item_ty = match &iter_ty {
+                    Type::Array(elem) => (**elem).clone(),
+                    Type::String | Type::Literal(_) => Type::String,
+                    Type::Unknown | Type::Union(_) => Type::Unknown,
+                    other => {
+                        self.report(codes::BAD_OPERAND, format!("Cannot iterate over {}", other));
+                        Type::Unknown
+                    }
+                };
+                self.push_scope();
+                self.bind(var, item_ty, false, BindingKind::LoopVar);
+                self.check_block(body);
+                self.pop_scope();
+                Type::Unknown
+            }
+
+            Statement::While { condition, body } => {
+                self.infer(condition);
+                self.check_block(body);
+                Type::Unknown
+            }
+
+            Statement::Return(expr) => match expr {
+                Some(e) => self.infer(e),
+                None => Type::Null,
+            },
+
+            Statement::Succeed | Statement::Break => Type::Null,
+
+            Statement::TypeDecl { name, type_expr } => {
+                let ty = Type::from_annotation(type_expr, &self.aliases);
+                self.aliases.insert(name.to_string(), ty);
+                Type::Null
+            }
+
+            Statement::Plan { steps } => {
+                for step in steps {
+                    self.infer_string(step);
+                }
+                Type::Null
+            }
+        }
+    }
+
+    /// Bind the names in a pattern. `checked` is false for `var x` without
+    /// an initializer, where the annotation is not compared against `null`.
+    fn bind_pattern(&mut self, pattern: &Pattern, ty: Type, checked: bool, kind: BindingKind) {
+        match pattern {
+            Pattern::Identifier { name, type_ann } => match type_ann {
+                Some(ann) => {
+                    let declared = Type::from_annotation(ann, &self.aliases);
+                    if checked && !ty.is_assignable_to(&declared) {
+                        self.report_at(
+                            codes::MISMATCH,
+                            format!("Cannot assign {} to `{}` declared as {}", ty, name, declared),
+                            name,
+                        );
+                    }
+                    self.bind(name, declared, true, kind);
+                }
+                None => self.bind(name, ty, false, kind),
+            },
+
+            Pattern::Ignore => {}
+
+            Pattern::Object(fields) => {
+                if !matches!(ty, Type::Object(_) | Type::Unknown | Type::Union(_)) {
+                    self.report(codes::MISMATCH, format!("Cannot destructure {} as object", ty));
+                }
+                for field in fields {
+                    let field_ty = match &field.type_ann {
+                        Some(ann) => Type::from_annotation(ann, &self.aliases),
+                        None => ty.field(field.key),
+                    };
+                    self.bind_pattern(&field.pattern, field_ty, checked, BindingKind::Pattern);
+                }
+            }
+
+            Pattern::Array(patterns) => {
+                let elem_ty = match &ty {
+                    Type::Array(elem) => (**elem).clone(),
+                    Type::Unknown | Type::Union(_) => Type::Unknown,
+                    other => {
+                        self.report(codes::MISMATCH, format!("Cannot destructure {} as array", other));
+                        Type::Unknown
+                    }
+                };
+                for pat in patterns {
+                    self.bind_pattern(pat, elem_ty.clone(), checked, BindingKind::Pattern);
+                }
+            }
+        }
+    }
+
+    // ---- expressions ----
+
+    fn infer(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Identifier(name) => {
+                if let Some(var) = self.lookup(name) {
+                    var.ty.clone()
+                } else if let Some(func) = self.functions.get(*name) {
+                    Type::Function(func.clone())
+                } else if let Some(sig) = builtin_signature(name) {
+                    Type::Function(sig)
+                } else {
+                    Type::Unknown
+                }
+            }
+
+            Expr::Number(_) => Type::Number,
+            Expr::String(lit) => {
+                self.infer_string(lit);
+                Type::String
+            }
+            Expr::True | Expr::False => Type::Boolean,
+
+            Expr::Array(items) => {
+                let mut elem: Option<Type> = None;
+                for item in items {
+                    let ty = self.infer(item);
+                    elem = Some(match elem {
+                        None => ty,
+                        Some(prev) if prev == ty => prev,
+                        Some(_) => Type::Unknown,
+                    });
+                }
+                Type::Array(Box::new(elem.unwrap_or(Type::Unknown)))
+            }
+
+            Expr::Object(fields) => {
+                let mut typed = Vec::new();
+                for field in fields {
+                    let ty = match &field.value {
+                        Some(value) => self.infer(value),
+                        None => self.lookup(field.key).map(|v| v.ty.clone()).unwrap_or(Type::Unknown),
+                    };
+                    typed.push((field.key.to_string(), ty));
+                }
+                Type::Object(typed)
+            }
+
+            Expr::Binary { op, left, right } => self.infer_binary(op, left, right),
+
+            Expr::Unary { op, operand } => {
+                let ty = self.infer(operand);
+                match op {
+                    UnOp::Not => Type::Boolean,
+                    UnOp::Neg => {
+                        if !ty.is_assignable_to(&Type::Number) {
+                            self.report(codes::BAD_OPERAND, format!("Cannot negate {}", ty));
+                        }
+                        Type::Number
+                    }
+                    UnOp::Throw => Type::Unknown,
+                }
+            }
+
+            Expr::Call { callee, args } => self.infer_call(callee, args),
+
+            Expr::Member { object, field } => {
+                let ty = self.infer(object);
+                match ty {
+                    Type::Number | Type::Boolean | Type::Null => {
+                        self.report(
+                            codes::BAD_OPERAND,
+                            format!("Cannot access field '{}' on {}", field, ty),
+                        );
+                        Type::Unknown
+                    }
+                    other => other.field(field),
+                }
+            }
+
+            Expr::Index { object, index } => {
+                let obj_ty = self.infer(object);
+                let idx_ty = self.infer(index);
+                self.infer_index(&obj_ty, &idx_ty)
+            }
+
+            Expr::PostIncrement(operand) | Expr::PostDecrement(operand) => {
+                self.infer(operand);
+                Type::Number
+            }
+
+            Expr::Paren(inner) | Expr::Await(inner) => self.infer(inner),
+
+            Expr::Think(block) | Expr::Ask(block) => {
+                self.check_prompt(block);
+                Type::Unknown
+            }
+
+            Expr::Approve(block) => {
+                self.check_prompt(block);
+                Type::Null
+            }
+
+            Expr::Do(block) => {
+                self.check_block(block);
+                Type::Unknown
+            }
+
+            Expr::BareCommand { args, .. } => {
+                for arg in args {
+                    if let CommandArg::String(lit) = arg {
+                        self.infer_string(lit);
+                    }
+                }
+                Type::String
+            }
+
+            Expr::CommandSubst(inner) => {
+                self.infer(inner);
+                Type::String
+            }
+
+            Expr::ShellBackground(inner) => {
+                self.infer(inner);
+                Type::Unknown
+            }
+
+            Expr::ShellPipe { left, right }
+            | Expr::ShellAnd { left, right }
+            | Expr::ShellOr { left, right } => {
+                self.infer(left);
+                self.infer(right);
+                Type::Unknown
+            }
+
+            Expr::ShellRedirect { command, target, .. } => {
+                self.infer(command);
+                self.infer(target);
+                Type::Unknown
+            }
+        }
+    }
+
+    fn check_prompt(&mut self, prompt: &PromptBlock) {
+        for item in &prompt.items {
+            match item {
+                PromptItem::Text(_) => {}
+                PromptItem::Interpolation(expr) => {
+                    self.infer(expr);
+                }
+                PromptItem::Code(code) => {
+                    self.check_block(code);
+                }
+                PromptItem::Examples(fields) => {
+                    for value in fields.iter().filter_map(|field| field.value.as_ref()) {
+                        self.infer(value);
+                    }
+                }
+                PromptItem::Variant { block, .. } => self.check_prompt(block),
+            }
+        }
+    }
+
+    fn infer_string(&mut self, lit: &StringLiteral) {
+        for part in &lit.parts {
+            if let StringPart::Interpolation(expr) = part {
+                self.infer(expr);
+            }
+        }
+    }
+
+    fn infer_binary(&mut self, op: &BinOp, left: &Expr, right: &Expr) -> Type {
+        if let BinOp::Assign = op {
+            let value_ty = self.infer(right);
+            if let Expr::Identifier(name) = left {
+                if let Some(var) = self.lookup(name) {
+                    if var.constant {
+                        self.report_at(codes::ASSIGN_CONST, format!("Cannot assign to constant `{}`", name), name);
+                    } else if var.annotated && !value_ty.is_assignable_to(&var.ty) {
+                        let declared = var.ty.clone();
+                        self.report_at(
+                            codes::MISMATCH,
+                            format!("Cannot assign {} to `{}` declared as {}", value_ty, name, declared),
+                            name,
+                        );
+                    }
+                }
+            }
+            return value_ty;
+        }
+
+        let lt = self.infer(left);
+        let rt = self.infer(right);
+        let known = !lt.is_unknown() && !rt.is_unknown();
+
+        match op {
+            BinOp::Add => {
+                if lt == Type::Number && rt == Type::Number {
+                    Type::Number
+                } else if lt.is_stringy() || rt.is_stringy() {
+                    Type::String
+                } else {
+                    if known && !matches!(lt, Type::Union(_)) && !matches!(rt, Type::Union(_)) {
+                        self.report(codes::BAD_OPERAND, format!("Cannot add {} and {}", lt, rt));
+                    }
+                    Type::Unknown
+                }
+            }
+            // String repetition: "-" * 40
+            BinOp::Mul if (lt.is_stringy() && rt.is_assignable_to(&Type::Number))
+                || (rt.is_stringy() && lt.is_assignable_to(&Type::Number)) =>
+            {
+                Type::String
+            }
+            BinOp::Sub | BinOp::Mul | BinOp::Div => {
+                if !lt.is_assignable_to(&Type::Number) || !rt.is_assignable_to(&Type::Number) {
+                    self.report(
+                        codes::BAD_OPERAND,
+                        format!("Cannot perform numeric operation on {} and {}", lt, rt),
+                    );
+                }
+                Type::Number
+            }
+            BinOp::Lt | BinOp::Gt => {
+                let comparable = (lt.is_assignable_to(&Type::Number) && rt.is_assignable_to(&Type::Number))
+                    || (lt.is_assignable_to(&Type::String) && rt.is_assignable_to(&Type::String));
+                if known && !comparable {
+                    self.report(codes::BAD_OPERAND, format!("Cannot compare {} and {}", lt, rt));
+                }
+                Type::Boolean
+            }
+            BinOp::Eq | BinOp::NotEq | BinOp::And | BinOp::Or => Type::Boolean,
+            BinOp::Range => {
+                if !lt.is_assignable_to(&Type::Number) || !rt.is_assignable_to(&Type::Number) {
+                    self.report(codes::BAD_OPERAND, "Range requires numbers".to_string());
+                }
+                Type::Array(Box::new(Type::Number))
+            }
+            BinOp::Pipe => Type::Unknown,
+            BinOp::Assign => unreachable!("handled above"),
+        }
+    }
+
+    fn infer_call(&mut self, callee: &Expr, args: &[Expr]) -> Type {
+        let arg_types: Vec<Type> = args.iter().map(|a| self.infer(a)).collect();
+
+        // Method calls (`obj.method(...)`) are dispatched dynamically.
+        if let Expr::Member { object, .. } = callee {
+            self.infer(object);
+            return Type::Unknown;
+        }
+
+        let name = match callee {
+            Expr::Identifier(name) => Some(*name),
+            _ => None,
+        };
+        let callee_ty = self.infer(callee);
+
+        // Point at the callee when it is a name
+        let report = |checker: &mut Self, code: &str, message: String| match name {
+            Some(name) => checker.report_at(code, message, name),
+            None => checker.report(code, message),
+        };
+        match callee_ty {
+            Type::Function(sig) => {
+                let display = name.unwrap_or("function");
+                if !sig.variadic {
+                    if args.len() != sig.params.len() {
+                        report(
+                            self,
+                            codes::ARITY,
+                            format!(
+                                "{}() takes {} argument{} but {} {} given",
+                                display,
+                                sig.params.len(),
+                                if sig.params.len() == 1 { "" } else { "s" },
+                                args.len(),
+                                if args.len() == 1 { "was" } else { "were" },
+                            ),
+                        );
+                    } else {
+                        for (i, (arg_ty, param_ty)) in arg_types.iter().zip(&sig.params).enumerate() {
+                            if !arg_ty.is_assignable_to(param_ty) {
+                                report(
+                                    self,
+                                    codes::MISMATCH,
+                                    format!(
+                                        "Argument {} of {}() expects {} but got {}",
+                                        i + 1,
+                                        display,
+                                        param_ty,
+                                        arg_ty
+                                    ),
+                                );
+                            }
+                        }
+                    }
+                }
+                (*sig.ret).clone()
+            }
+            Type::Unknown | Type::Union(_) => Type::Unknown,
+            other => {
+                let what = match name {
+                    Some(name) => format!("`{}`", name),
+                    None => "expression".to_string(),
+                };
+                report(self, codes::NOT_CALLABLE, format!("Cannot call {} of type {}", what, other));
+                Type::Unknown
+            }
+        }
+    }
+
+    fn infer_index(&mut self, obj_ty: &Type, idx_ty: &Type) -> Type {
+        match obj_ty {
+            Type::Array(elem) => {
+                if !idx_ty.is_assignable_to(&Type::Number) {
+                    self.report(codes::BAD_INDEX, format!("Cannot index array with {}", idx_ty));
+                }
+                (**elem).clone()
+            }
+            Type::Object(_) => {
+                if !idx_ty.is_assignable_to(&Type::String) {
+                    self.report(codes::BAD_INDEX, format!("Cannot index object with {}", idx_ty));
+                }
+                match idx_ty {
+                    Type::Literal(key) => obj_ty.field(key),
+                    _ => Type::Unknown,
+                }
+            }
+            Type::Unknown | Type::Union(_) => Type::Unknown,
+            other => {
+                self.report(codes::BAD_INDEX, format!("Cannot index {} with {}", other, idx_ty));
+                Type::Unknown
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use patchwork_parser::parse;
+
+    fn check(source: &str) -> CheckResult {
+        let program = parse(source).expect("test source should parse");
+        check_program(&program)
+    }
+
+    fn codes_of(result: &CheckResult) -> Vec<String> {
+        result
+            .diagnostics
+            .iter()
+            .map(|d| d.code.clone().unwrap_or_default())
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_program_has_no_diagnostics() {
+        let result = check(r#"
+            skill main() {
+                var items = [1, 2, 3]
+                var total = 0
+                for var i in items {
+                    total = total + i
+                }
+                print("total: ${total}")
+            }
+        "#);
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+    }
+
+    #[test]
+    fn test_calling_a_number() {
+        let result = check(r#"
+            skill main() {
+                var n = 42
+                n()
+            }
+        "#);
+        assert_eq!(codes_of(&result), vec![codes::NOT_CALLABLE]);
+        assert!(result.diagnostics[0].message.contains("`n` of type number"));
+    }
+
+    #[test]
+    fn test_indexing_string_with_string() {
+        let result = check(r#"
+            skill main() {
+                var s = "hello"
+                var c = s["x"]
+            }
+        "#);
+        assert_eq!(codes_of(&result), vec![codes::BAD_INDEX]);
+        assert!(result.diagnostics[0].message.contains("Cannot index string with string"));
+    }
+
+    #[test]
+    fn test_wrong_arity_user_function() {
+        let result = check(r#"
+            fun greet(name) {
+                print(name)
+            }
+
+            skill main() {
+                greet("a", "b")
+            }
+        "#);
+        assert_eq!(codes_of(&result), vec![codes::ARITY]);
+        assert!(result.diagnostics[0].message.contains("greet() takes 1 argument but 2 were given"));
+    }
+
+    #[test]
+    fn test_wrong_arity_builtin() {
+        let result = check(r#"
+            skill main() {
+                var x = read()
+            }
+        "#);
+        assert_eq!(codes_of(&result), vec![codes::ARITY]);
+    }
+
+    #[test]
+    fn test_annotation_mismatch() {
+        let result = check(r#"
+            skill main() {
+                var count: number = "three"
+            }
+        "#);
+        assert_eq!(codes_of(&result), vec![codes::MISMATCH]);
+    }
+
+    #[test]
+    fn test_annotated_param_mismatch() {
+        let result = check(r#"
+            fun double(n: number) {
+                return n * 2
+            }
+
+            skill main() {
+                double("x")
+            }
+        "#);
+        assert_eq!(codes_of(&result), vec![codes::MISMATCH]);
+    }
+
+    #[test]
+    fn test_type_alias_union() {
+        let result = check(r#"
+            type Status = "ok" | "error"
+
+            skill main() {
+                var s: Status = "ok"
+            }
+        "#);
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+    }
+
+    #[test]
+    fn test_unknown_values_are_not_reported() {
+        let result = check(r#"
+            skill main() {
+                var data = json(read("x.json"))
+                var y = data.field[0]
+                data()
+            }
+        "#);
+        assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+    }
+
+    #[test]
+    fn test_bindings_record_inferred_types() {
+        let result = check(r#"
+            skill main() {
+                var name = "x"
+                var { a, b } = { a: 1, b: "two" }
+                for var n in [1, 2] {
+                    print(n)
+                }
+            }
+        "#);
+        let types: Vec<(String, String)> = result
+            .bindings
+            .iter()
+            .map(|b| (b.name.clone(), b.ty.to_string()))
+            .collect();
+        assert_eq!(types, vec![
+            ("name".to_string(), "string".to_string()),
+            ("a".to_string(), "number".to_string()),
+            ("b".to_string(), "string".to_string()),
+            ("n".to_string(), "number".to_string()),
+        ]);
+        assert_eq!(result.bindings[0].kind, BindingKind::Var);
+        assert_eq!(result.bindings[1].kind, BindingKind::Pattern);
+        assert_eq!(result.bindings[3].kind, BindingKind::LoopVar);
+        assert_eq!(result.bindings[0].context, "main");
+    }
+
+    #[test]
+    fn test_module_constants() {
+        let result = check(r#"
+            const LIMIT = 3
+            var seen = 0
+            skill main() {
+                seen = LIMIT
+                LIMIT = 4
+            }
+        "#);
+        assert_eq!(result.diagnostics.len(), 1, "{:?}", result.diagnostics);
+        assert_eq!(result.diagnostics[0].message, "Cannot assign to constant `LIMIT`");
+        assert_eq!(result.bindings[0].ty, Type::Number);
+    }
+
+    #[test]
+    fn test_resolved_bindings_have_spans() {
+        let source = "skill main() {\n  var name = \"x\"\n}";
+        let program = parse(source).unwrap();
+        let resolved = patchwork_parser::resolve::resolve(&program, source);
+        let result = check_resolved(&resolved);
+
+        let binding = &result.bindings[0];
+        let (start, end) = binding.span.expect("binding should have a span");
+        assert_eq!(&source[start..end], "name");
+        assert!(binding.symbol.is_some());
+        assert!(!binding.annotated);
+    }
+
+    #[test]
+    fn test_resolved_diagnostics_point_at_names() {
+        let source = "fun greet(name) {}\nskill main() {\n  greet(1, 2)\n}";
+        let program = parse(source).unwrap();
+        let resolved = patchwork_parser::resolve::resolve(&program, source);
+        let result = check_resolved(&resolved);
+
+        assert_eq!(codes_of(&result), vec![codes::ARITY]);
+        let labels = &result.diagnostics[0].labels;
+        assert_eq!(labels.len(), 2);
+        let text = |(start, end): (usize, usize)| &source[start..end];
+        assert_eq!(text(labels[0].span), "greet");
+        assert!(labels[0].span.0 > source.find('\n').unwrap());
+        assert_eq!(text(labels[1].span), "greet");
+        assert_eq!(labels[1].span.0, 4);
+        assert_eq!(labels[1].message, "`greet` is declared here");
+    }
+
+    #[test]
+    fn test_expect_mismatch() {
+        let result = check(r#"
+            type Summary = { title: string }
+            eval "summaries" {
+                input [1, 2]
+                output 42
+                expect Summary
+            }
+        "#);
+        assert_eq!(codes_of(&result), vec![codes::MISMATCH]);
+    }
+
+    #[test]
+    fn test_codes_are_explained() {
+        let all = [
+            codes::NOT_CALLABLE,
+            codes::BAD_INDEX,
+            codes::ARITY,
+            codes::MISMATCH,
+            codes::BAD_OPERAND,
+            codes::ASSIGN_CONST,
+        ];
+        for code in all {
+            let explanation = patchwork_parser::explain::lookup(code);
+            assert_eq!(explanation.map(|e| e.reporter), Some("checker"), "{} needs an explanation", code);
+        }
+    }
+}
@@ -0,0 +1,12 @@
+//! Static type checking for Patchwork programs.
+//!
+//! This crate provides an optional pass over the parsed AST that infers
+//! types from literals and annotations and reports operations that cannot
+//! succeed at runtime. The interpreter does not require it; tools like the
+//! LSP run it to surface mistakes and show inferred types.
+
+mod checker;
+mod types;
+
+pub use checker::{check_program, check_resolved, codes, Binding, BindingKind, CheckResult};
+pub use types::{FunctionType, Type};
@@ -0,0 +1,242 @@
+//! Static types inferred by the checker.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use patchwork_parser::ast::TypeExpr;
+
+/// A statically inferred type.
+///
+/// `Unknown` is the escape hatch for anything the checker cannot see through
+/// (think results, JSON parsed at runtime, shell pipelines). It is compatible
+/// with every other type so it never produces a diagnostic on its own.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Unknown,
+    Null,
+    Boolean,
+    Number,
+    String,
+    /// A string literal type: `"success"`.
+    Literal(String),
+    Array(Box<Type>),
+    /// Object type with its known fields, in declaration order.
+    Object(Vec<(String, Type)>),
+    Union(Vec<Type>),
+    Function(FunctionType),
+}
+
+/// The signature of a callable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionType {
+    pub params: Vec<Type>,
+    /// Accepts any number of arguments (e.g. `print`).
+    pub variadic: bool,
+    pub ret: Box<Type>,
+}
+
+impl FunctionType {
+    pub fn new(params: Vec<Type>, ret: Type) -> Self {
+        Self { params, variadic: false, ret: Box::new(ret) }
+    }
+
+    pub fn variadic(ret: Type) -> Self {
+        Self { params: vec![], variadic: true, ret: Box::new(ret) }
+    }
+}
+
+impl Type {
+    /// Convert a source type annotation into a type.
+    ///
+    /// `aliases` maps names introduced by `type Foo = ...` declarations.
+    /// Names the checker does not know (e.g. trait names) become `Unknown`.
+    pub fn from_annotation(expr: &TypeExpr, aliases: &HashMap<String, Type>) -> Type {
+        match expr {
+            TypeExpr::Name(name) => match *name {
+                "string" => Type::String,
+                "number" | "int" | "float" => Type::Number,
+                "bool" | "boolean" => Type::Boolean,
+                "null" => Type::Null,
+                other => aliases.get(other).cloned().unwrap_or(Type::Unknown),
+            },
+            TypeExpr::Literal(text) => Type::Literal(text.to_string()),
+            TypeExpr::Array(elem) => Type::Array(Box::new(Type::from_annotation(elem, aliases))),
+            TypeExpr::Object(fields) => Type::Object(
+                fields
+                    .iter()
+                    .map(|f| (f.key.to_string(), Type::from_annotation(&f.type_expr, aliases)))
+                    .collect(),
+            ),
+            TypeExpr::Union(types) => {
+                Type::union(types.iter().map(|t| Type::from_annotation(t, aliases)).collect())
+            }
+        }
+    }
+
+    /// Build a union, flattening nested unions and removing duplicates.
+    pub fn union(types: Vec<Type>) -> Type {
+        let mut members: Vec<Type> = Vec::new();
+        for ty in types {
+            let flat = match ty {
+                Type::Union(inner) => inner,
+                other => vec![other],
+            };
+            for t in flat {
+                if t == Type::Unknown {
+                    return Type::Unknown;
+                }
+                if !members.contains(&t) {
+                    members.push(t);
+                }
+            }
+        }
+        match members.len() {
+            0 => Type::Unknown,
+            1 => members.pop().unwrap(),
+            _ => Type::Union(members),
+        }
+    }
+
+    /// The least specific type covering both `self` and `other`.
+    pub fn join(&self, other: &Type) -> Type {
+        if self == other {
+            self.clone()
+        } else {
+            Type::union(vec![self.clone(), other.clone()])
+        }
+    }
+
+    /// Is this type fully unknown?
+    pub fn is_unknown(&self) -> bool {
+        matches!(self, Type::Unknown)
+    }
+
+    /// Is this a string or string literal?
+    pub fn is_stringy(&self) -> bool {
+        matches!(self, Type::String | Type::Literal(_))
+    }
+
+    /// Can a value of type `self` be used where `target` is expected?
+    pub fn is_assignable_to(&self, target: &Type) -> bool {
+        match (self, target) {
+            (Type::Unknown, _) | (_, Type::Unknown) => true,
+            (a, b) if a == b => true,
+            (Type::Literal(_), Type::String) => true,
+            // A plain string might hold the literal's value; only runtime can tell
+            (Type::String, Type::Literal(_)) => true,
+            (Type::Union(members), _) => members.iter().all(|m| m.is_assignable_to(target)),
+            (_, Type::Union(members)) => members.iter().any(|m| self.is_assignable_to(m)),
+            (Type::Array(a), Type::Array(b)) => a.is_assignable_to(b),
+            (Type::Object(have), Type::Object(want)) => want.iter().all(|(key, want_ty)| {
+                match have.iter().find(|(k, _)| k == key) {
+                    Some((_, have_ty)) => have_ty.is_assignable_to(want_ty),
+                    None => false,
+                }
+            }),
+            _ => false,
+        }
+    }
+
+    /// The type of a field read from a value of this type.
+    pub fn field(&self, name: &str) -> Type {
+        match self {
+            Type::Object(fields) => fields
+                .iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, t)| t.clone())
+                .unwrap_or(Type::Unknown),
+            _ => Type::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Unknown => write!(f, "unknown"),
+            Type::Null => write!(f, "null"),
+            Type::Boolean => write!(f, "boolean"),
+            Type::Number => write!(f, "number"),
+            Type::String => write!(f, "string"),
+            Type::Literal(text) => write!(f, "\"{}\"", text),
+            Type::Array(elem) => write!(f, "[{}]", elem),
+            Type::Object(fields) => {
+                if fields.is_empty() {
+                    return write!(f, "{{}}");
+                }
+                write!(f, "{{ ")?;
+                for (i, (key, ty)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, ty)?;
+                }
+                write!(f, " }}")
+            }
+            Type::Union(members) => {
+                for (i, ty) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " | ")?;
+                    }
+                    write!(f, "{}", ty)?;
+                }
+                Ok(())
+            }
+            Type::Function(func) => {
+                write!(f, "fun(")?;
+                if func.variadic {
+                    write!(f, "...")?;
+                } else {
+                    for (i, ty) in func.params.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{}", ty)?;
+                    }
+                }
+                write!(f, ") -> {}", func.ret)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let ty = Type::Object(vec![
+            ("name".to_string(), Type::String),
+            ("tags".to_string(), Type::Array(Box::new(Type::String))),
+        ]);
+        assert_eq!(ty.to_string(), "{ name: string, tags: [string] }");
+        assert_eq!(Type::union(vec![Type::Number, Type::Null]).to_string(), "number | null");
+    }
+
+    #[test]
+    fn test_union_flattens_and_dedupes() {
+        let ty = Type::union(vec![
+            Type::Number,
+            Type::Union(vec![Type::Number, Type::String]),
+        ]);
+        assert_eq!(ty, Type::Union(vec![Type::Number, Type::String]));
+        assert_eq!(Type::union(vec![Type::Number, Type::Unknown]), Type::Unknown);
+    }
+
+    #[test]
+    fn test_assignability() {
+        assert!(Type::Literal("ok".into()).is_assignable_to(&Type::String));
+        assert!(!Type::String.is_assignable_to(&Type::Number));
+        assert!(Type::Number.is_assignable_to(&Type::union(vec![Type::Number, Type::Null])));
+        assert!(Type::Unknown.is_assignable_to(&Type::Number));
+
+        let have = Type::Object(vec![
+            ("x".to_string(), Type::Number),
+            ("y".to_string(), Type::Number),
+        ]);
+        let want = Type::Object(vec![("x".to_string(), Type::Number)]);
+        assert!(have.is_assignable_to(&want));
+        assert!(!want.is_assignable_to(&have));
+    }
+}
@@ -0,0 +1,268 @@
+//! The grammar conformance corpus.
+//!
+//! `corpus/*.txt` at the repository root holds annotated snippets of
+//! Patchwork, each with the tokens the lexer should produce for it and the
+//! AST the parser should build from them:
+//!
+//! ```text
+//! === think block with an interpolation
+//! var x = think { Say hello to ${name} }
+//! --- tokens
+//! Code Var "var"
+//! Code Identifier "x"
+//! ...
+//! Prompt PromptText "Say"
+//! ...
+//! --- ast
+//! Program:
+//!   Var:
+//! ...
+//! ```
+//!
+//! Each token line gives the mode the token was lexed in, its rule, and its
+//! text; whitespace tokens are left out, but still drive mode changes. The
+//! AST is `dump_program`'s output, or `error: ` and the message when the
+//! snippet shouldn't parse. Lines before the first `===` describe the file.
+//!
+//! Because every case pins the lexer's modes and the parser's reading of
+//! the same source side by side, a change to one that the other doesn't
+//! expect shows up as a corpus failure instead of a confusing parse error
+//! elsewhere. The `corpus` test runs every file; with `PATCHWORK_BLESS=1`
+//! set it rewrites the expectations from the current output instead, for
+//! reviewing as a diff.
+
+use std::fmt::Write;
+
+use patchwork_lexer::{lex_str, LexerContext, ModeMachine, Rule};
+use patchwork_parser::ast_dump::dump_program;
+use try_next::TryNextWithContext;
+
+/// A corpus file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Corpus {
+    /// The lines before the first case.
+    pub preamble: String,
+    pub cases: Vec<Case>,
+}
+
+/// Which part of a case a line of a corpus file belongs to.
+#[derive(Clone, Copy)]
+enum Section {
+    Source,
+    Tokens,
+    Ast,
+}
+
+/// One snippet and what it should lex and parse to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub name: String,
+    /// The line of the case's `===` header, counting from 1.
+    pub line: usize,
+    pub source: String,
+    pub tokens: Option<String>,
+    pub ast: Option<String>,
+}
+
+impl Corpus {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut corpus = Corpus { preamble: String::new(), cases: Vec::new() };
+        let mut section = Section::Source;
+        for (i, line) in text.lines().enumerate() {
+            if let Some(name) = line.strip_prefix("=== ") {
+                corpus.cases.push(Case {
+                    name: name.trim().to_string(),
+                    line: i + 1,
+                    source: String::new(),
+                    tokens: None,
+                    ast: None,
+                });
+                section = Section::Source;
+                continue;
+            }
+            let Some(case) = corpus.cases.last_mut() else {
+                corpus.preamble.push_str(line);
+                corpus.preamble.push('\n');
+                continue;
+            };
+            if let Some(name) = line.strip_prefix("--- ") {
+                let (next, slot) = match name.trim() {
+                    "tokens" => (Section::Tokens, &mut case.tokens),
+                    "ast" => (Section::Ast, &mut case.ast),
+                    other => return Err(format!("line {}: unknown section `{}`", i + 1, other)),
+                };
+                if slot.is_some() {
+                    return Err(format!("line {}: `{}` has two `{}` sections", i + 1, case.name, name.trim()));
+                }
+                *slot = Some(String::new());
+                section = next;
+                continue;
+            }
+            let text = match section {
+                Section::Source => &mut case.source,
+                Section::Tokens => case.tokens.as_mut().expect("started above"),
+                Section::Ast => case.ast.as_mut().expect("started above"),
+            };
+            text.push_str(line);
+            text.push('\n');
+        }
+        for case in &mut corpus.cases {
+            trim_blank_lines(&mut case.source);
+            for section in [&mut case.tokens, &mut case.ast].into_iter().flatten() {
+                trim_blank_lines(section);
+            }
+        }
+        Ok(corpus)
+    }
+
+    /// The file's text, as `parse` reads it.
+    pub fn render(&self) -> String {
+        let mut out = self.preamble.clone();
+        for (i, case) in self.cases.iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+            }
+            let _ = writeln!(out, "=== {}", case.name);
+            out.push_str(&case.source);
+            if let Some(tokens) = &case.tokens {
+                let _ = write!(out, "--- tokens\n{}", tokens);
+            }
+            if let Some(ast) = &case.ast {
+                let _ = write!(out, "--- ast\n{}", ast);
+            }
+        }
+        out
+    }
+
+    /// Replace every case's expectations with what the lexer and parser
+    /// produce now.
+    pub fn bless(&mut self) {
+        for case in &mut self.cases {
+            case.tokens = Some(tokens(&case.source));
+            case.ast = Some(ast(&case.source));
+        }
+    }
+}
+
+impl Case {
+    /// Describe every way the lexer and parser disagree with the case.
+    pub fn check(&self) -> Vec<String> {
+        let mut failures = Vec::new();
+        if self.tokens.is_none() && self.ast.is_none() {
+            failures.push("no `--- tokens` or `--- ast` section".to_string());
+        }
+        if let Some(expected) = &self.tokens {
+            compare("tokens", expected, &tokens(&self.source), &mut failures);
+        }
+        if let Some(expected) = &self.ast {
+            compare("ast", expected, &ast(&self.source), &mut failures);
+        }
+        failures
+    }
+}
+
+/// The token stream for `source`, one token per line, ending at the first
+/// lexer error.
+pub fn tokens(source: &str) -> String {
+    let mut out = String::new();
+    let mut lexer = match lex_str(source) {
+        Ok(lexer) => lexer,
+        Err(e) => return format!("error: {}\n", e),
+    };
+    let mut context = LexerContext::default();
+    let mut modes = ModeMachine::new();
+    loop {
+        match lexer.try_next_with_context(&mut context) {
+            Ok(Some(token)) => {
+                let mode = modes.mode();
+                modes.advance(token.rule);
+                if matches!(token.rule, Rule::Whitespace | Rule::End | Rule::Empty) {
+                    continue;
+                }
+                let text = match &token.span {
+                    Some(span) => {
+                        let start = offset(source, span.start.line, span.start.column);
+                        let end = offset(source, span.end.line, span.end.column);
+                        source.get(start..end).unwrap_or("")
+                    }
+                    None => "",
+                };
+                let _ = writeln!(out, "{:?} {:?} {:?}", mode, token.rule, text);
+            }
+            Ok(None) => return out,
+            Err(e) => {
+                let _ = writeln!(out, "error: {}", e);
+                return out;
+            }
+        }
+    }
+}
+
+/// The AST dump for `source`, or the parse error.
+pub fn ast(source: &str) -> String {
+    match patchwork_parser::parse(source) {
+        Ok(program) => {
+            let mut dump = dump_program(&program);
+            trim_blank_lines(&mut dump);
+            dump
+        }
+        Err(e) => format!("error: {}\n", e),
+    }
+}
+
+/// The byte offset of a lexer position: a line and a column, both counted
+/// from 0, with columns counting characters.
+fn offset(source: &str, line: usize, column: usize) -> usize {
+    let line_start = match line {
+        0 => 0,
+        _ => match source.match_indices('\n').nth(line - 1) {
+            Some((i, _)) => i + 1,
+            None => return source.len(),
+        },
+    };
+    source[line_start..].char_indices().nth(column).map_or(source.len(), |(i, _)| line_start + i)
+}
+
+fn compare(section: &str, expected: &str, actual: &str, failures: &mut Vec<String>) {
+    if expected != actual {
+        failures.push(format!("{} differ\n--- expected\n{}--- actual\n{}", section, expected, actual));
+    }
+}
+
+/// Drop trailing blank lines, keeping the final newline of the last line
+/// with text.
+fn trim_blank_lines(text: &mut String) {
+    let len = text.trim_end().len();
+    text.truncate(len);
+    if !text.is_empty() {
+        text.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_render() {
+        let text = "Cases for numbers.\n\n=== one\nvar x = 1\n--- ast\nProgram:\n\n=== two\nvar y = 2\n--- tokens\nCode Var \"var\"\n";
+        let corpus = Corpus::parse(text).unwrap();
+        assert_eq!(corpus.preamble, "Cases for numbers.\n\n");
+        assert_eq!(corpus.cases.len(), 2);
+        assert_eq!(corpus.cases[0].line, 3);
+        assert_eq!(corpus.cases[0].source, "var x = 1\n");
+        assert_eq!(corpus.cases[0].ast.as_deref(), Some("Program:\n"));
+        assert_eq!(corpus.cases[1].tokens.as_deref(), Some("Code Var \"var\"\n"));
+        assert_eq!(corpus.render(), text);
+
+        assert!(Corpus::parse("=== bad\nx\n--- format\n").is_err());
+    }
+
+    #[test]
+    fn test_offsets() {
+        let source = "ab\nçd\n";
+        assert_eq!(offset(source, 0, 1), 1);
+        assert_eq!(offset(source, 1, 1), 5);
+        assert_eq!(offset(source, 5, 0), source.len());
+    }
+}
@@ -0,0 +1,42 @@
+//! Runs every `corpus/*.txt` file at the repository root through the lexer
+//! and parser. Set `PATCHWORK_BLESS=1` to rewrite the files' expectations
+//! from the current output instead of checking them.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use patchwork_corpus::Corpus;
+
+fn corpus_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../../corpus")
+}
+
+#[test]
+fn corpus() {
+    let bless = std::env::var_os("PATCHWORK_BLESS").is_some();
+    let mut files: Vec<PathBuf> = fs::read_dir(corpus_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "no corpus files in {}", corpus_dir().display());
+
+    let mut failures = Vec::new();
+    for path in &files {
+        let name = path.file_name().unwrap().to_string_lossy();
+        let text = fs::read_to_string(path).unwrap();
+        let mut corpus = Corpus::parse(&text).unwrap_or_else(|e| panic!("{}: {}", name, e));
+        if bless {
+            corpus.bless();
+            fs::write(path, corpus.render()).unwrap();
+            continue;
+        }
+        for case in &corpus.cases {
+            for failure in case.check() {
+                failures.push(format!("{}:{}: {}: {}", name, case.line, case.name, failure));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{} corpus failure(s):\n\n{}", failures.len(), failures.join("\n"));
+}
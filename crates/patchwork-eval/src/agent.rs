@@ -12,9 +12,13 @@
 //! - Think responses: Received via `std::sync::mpsc::Receiver` (blocking receive in sync interpreter)
 
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::mpsc;
+use std::time::Duration;
 use tokio::sync::mpsc::UnboundedSender;
 
+use crate::cache::{CacheKey, ScopedCache};
+use crate::schema::Schema;
 use crate::value::Value;
 
 /// Response from the agent during a think session.
@@ -34,6 +38,18 @@ pub enum ThinkResponse {
         result_tx: mpsc::SyncSender<String>,
     },
 
+    /// Token usage of an LLM call made for this think block.
+    ///
+    /// Sent before `Complete` by backends that can measure it; budget limits
+    /// on tokens and cost only see calls whose usage was reported.
+    Usage(Usage),
+
+    /// Which model answered and why it stopped, for `last_call_meta()`.
+    Metadata {
+        model: Option<String>,
+        stop_reason: Option<String>,
+    },
+
     /// The think block completed with a final value.
     Complete {
         /// The extracted value from the LLM response.
@@ -41,17 +57,115 @@ pub enum ThinkResponse {
     },
 }
 
+/// Tokens consumed (and optionally billed) by one LLM call.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Usage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// Cost in US dollars, if the backend knows its pricing.
+    pub cost_usd: Option<f64>,
+}
+
+impl Usage {
+    pub fn total_tokens(&self) -> u64 {
+        self.input_tokens + self.output_tokens
+    }
+}
+
+/// Which prompt operator a request comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThinkOp {
+    Think,
+    Ask,
+    /// Folding older think blocks into a summary, when the transcript
+    /// outgrows `max_context_tokens`.
+    Summarize,
+}
+
+impl fmt::Display for ThinkOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThinkOp::Think => write!(f, "think"),
+            ThinkOp::Ask => write!(f, "ask"),
+            ThinkOp::Summarize => write!(f, "summarize"),
+        }
+    }
+}
+
+/// How urgently a request should be answered when several are waiting.
+///
+/// Ordered most urgent first, so a `ThinkQueue` hands out the smallest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    /// An `ask` that someone is waiting on.
+    Interactive,
+    /// A `think` in the program the user started.
+    #[default]
+    Foreground,
+    /// Work from a batch fan-out, which can wait.
+    Background,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::Interactive, Priority::Foreground, Priority::Background];
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Priority::Interactive => write!(f, "interactive"),
+            Priority::Foreground => write!(f, "foreground"),
+            Priority::Background => write!(f, "background"),
+        }
+    }
+}
+
+/// A piece of a prompt, in the order the program wrote it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PromptPart {
+    /// Text written in the prompt, or examples formatted for it.
+    Text(String),
+    /// The value of an interpolated expression, like `$issue`.
+    Value(Value),
+}
+
+/// The text of a prompt made of `parts`.
+pub(crate) fn prompt_text(parts: &[PromptPart]) -> String {
+    let mut text = String::new();
+    for part in parts {
+        match part {
+            PromptPart::Text(s) => text.push_str(s),
+            PromptPart::Value(value) => text.push_str(&value.to_string_value()),
+        }
+    }
+    text
+}
+
 /// A request to execute a think block.
 ///
 /// The interpreter sends this to the agent, then blocks waiting for
 /// ThinkResponse messages on the provided channel.
 pub struct ThinkRequest {
+    pub op: ThinkOp,
     /// The interpolated prompt text to send to the LLM.
     pub prompt: String,
+    /// The prompt as written: its text and the values interpolated into
+    /// it, redacted like `prompt`.
+    pub parts: Vec<PromptPart>,
     /// Variable bindings available in the think block scope.
     pub bindings: HashMap<String, Value>,
-    /// Expected type hint for response extraction (e.g., "string", "json").
-    pub expect: String,
+    /// The shape the answer should have.
+    pub expect: Schema,
+    /// Model to ask, from the configured chain, or None for the backend's default.
+    pub model: Option<String>,
+    /// Downstream agent to ask, by the name the host's `agents` table gives
+    /// it, or None for the host's default.
+    pub agent: Option<String>,
+    /// How long the interpreter waits for an answer before trying the next
+    /// model, from the `think_timeout_secs` limit.
+    pub timeout: Option<Duration>,
+    /// Where the request goes in a `ThinkQueue`.
+    pub priority: Priority,
     /// Channel to receive responses from the agent.
     ///
     /// The agent will send ThinkResponse messages:
@@ -67,38 +181,45 @@ pub struct ThinkRequest {
 #[derive(Clone)]
 pub struct AgentHandle {
     tx: UnboundedSender<ThinkRequest>,
+    /// Answers to reuse, for a handle from a `ThinkCache`.
+    cache: Option<ScopedCache>,
 }
 
 impl AgentHandle {
     /// Create a new agent handle from a sender.
     pub fn new(tx: UnboundedSender<ThinkRequest>) -> Self {
-        Self { tx }
+        Self { tx, cache: None }
+    }
+
+    /// This handle's backend, answering from `cache` when it can.
+    pub(crate) fn reusing(&self, cache: ScopedCache) -> Self {
+        Self { tx: self.tx.clone(), cache: Some(cache) }
     }
 
     /// Send a think request to the agent.
     ///
-    /// Returns a receiver for ThinkResponse messages.
-    /// The send is non-blocking (uses tokio unbounded channel),
-    /// but the returned receiver is std::sync for blocking receive.
-    pub fn think(
-        &self,
-        prompt: String,
-        bindings: HashMap<String, Value>,
-        expect: String,
-    ) -> Result<mpsc::Receiver<ThinkResponse>, String> {
-        let (response_tx, response_rx) = mpsc::channel();
-
-        let request = ThinkRequest {
-            prompt,
-            bindings,
-            expect,
-            response_tx,
-        };
-
+    /// The send is non-blocking (uses tokio unbounded channel); answers
+    /// arrive on the std::sync receiver paired with `request.response_tx`.
+    pub fn think(&self, request: ThinkRequest) -> Result<(), String> {
+        if self.cache.as_ref().is_some_and(|cache| cache.reply(&request)) {
+            return Ok(());
+        }
         self.tx
             .send(request)
-            .map_err(|e| format!("Failed to send think request: {}", e))?;
+            .map_err(|e| format!("Failed to send think request: {}", e))
+    }
+
+    /// What to keep `request`'s answer under, when this handle reuses
+    /// answers and it's one to reuse.
+    pub(crate) fn answer_key(&self, request: &ThinkRequest) -> Option<CacheKey> {
+        self.cache.as_ref()?.key(request)
+    }
 
-        Ok(response_rx)
+    /// Keep `value`, the answer to the request `key` came from, for the
+    /// next time it's asked.
+    pub(crate) fn keep_answer(&self, key: CacheKey, value: Value, model: Option<String>, stop_reason: Option<String>) {
+        if let Some(cache) = &self.cache {
+            cache.keep(key, value, model, stop_reason);
+        }
     }
 }
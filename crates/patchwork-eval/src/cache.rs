@@ -0,0 +1,222 @@
+//! Answers to think blocks, kept so asking again doesn't ask the backend.
+//!
+//! A host that reruns the same code while someone works on it, like the
+//! ACP proxy, puts a `ThinkCache` in front of its backend. A `think` block
+//! whose prompt, expected type, model, and agent match one already answered
+//! gets the earlier answer straight away. `ask` blocks wait on a person and
+//! summaries on the transcript, so they always go through, as do blocks
+//! whose answer was an error.
+//!
+//! ```ignore
+//! let cache = ThinkCache::new(backend);
+//! let agent = cache.handle(config.think_cache, &session_id);
+//! let mut interp = Interpreter::with_agent(agent);
+//! ```
+//!
+//! With `CacheScope::Session` each session keeps its own answers; with
+//! `CacheScope::Global` every session shares one set. `clear` forgets them,
+//! for when a stale answer is in the way. Answers live as long as the cache.
+//!
+//! The handle looks answers up as the program asks, and the interpreter
+//! keeps each new one when it arrives, so the cache needs no thread of its
+//! own.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::agent::{AgentHandle, ThinkOp, ThinkRequest, ThinkResponse};
+use crate::config::CacheScope;
+use crate::value::Value;
+
+/// Answers by session, or under None for the global scope.
+type Answers = HashMap<Option<String>, HashMap<CacheKey, Answer>>;
+
+/// Keeps the answers think blocks get from a backend.
+#[derive(Clone)]
+pub struct ThinkCache {
+    backend: AgentHandle,
+    answers: Arc<Mutex<Answers>>,
+}
+
+/// The answers one handle reuses: the cache's, under its scope.
+#[derive(Clone)]
+pub(crate) struct ScopedCache {
+    answers: Arc<Mutex<Answers>>,
+    scope: Option<String>,
+}
+
+/// What makes two think blocks the same question.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey {
+    prompt: String,
+    expect: String,
+    model: Option<String>,
+    agent: Option<String>,
+}
+
+impl CacheKey {
+    fn of(request: &ThinkRequest) -> Self {
+        Self {
+            prompt: request.prompt.clone(),
+            expect: request.expect.to_string(),
+            model: request.model.clone(),
+            agent: request.agent.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Answer {
+    value: Value,
+    model: Option<String>,
+    stop_reason: Option<String>,
+}
+
+impl ThinkCache {
+    /// A cache in front of `backend`.
+    pub fn new(backend: AgentHandle) -> Self {
+        Self { backend, answers: Arc::default() }
+    }
+
+    /// A handle for an interpreter in session `session` to send its
+    /// requests through, reusing answers within `scope`. With
+    /// `CacheScope::Off` it's the backend's own handle.
+    pub fn handle(&self, scope: CacheScope, session: &str) -> AgentHandle {
+        match scope_key(scope, session) {
+            Some(scope) => self.backend.reusing(ScopedCache { answers: self.answers.clone(), scope }),
+            None => self.backend.clone(),
+        }
+    }
+
+    /// Forget the answers a handle for `session` in `scope` would reuse,
+    /// returning how many there were.
+    pub fn clear(&self, scope: CacheScope, session: &str) -> usize {
+        let Some(scope) = scope_key(scope, session) else {
+            return 0;
+        };
+        let mut answers = self.answers.lock().unwrap();
+        answers.remove(&scope).map_or(0, |answers| answers.len())
+    }
+}
+
+impl ScopedCache {
+    /// What to keep `request`'s answer under, or None when its answer
+    /// shouldn't be reused.
+    pub(crate) fn key(&self, request: &ThinkRequest) -> Option<CacheKey> {
+        (request.op == ThinkOp::Think).then(|| CacheKey::of(request))
+    }
+
+    /// Answer `request` with a kept answer, returning whether there was one.
+    pub(crate) fn reply(&self, request: &ThinkRequest) -> bool {
+        let Some(key) = self.key(request) else {
+            return false;
+        };
+        let answers = self.answers.lock().unwrap();
+        let Some(answer) = answers.get(&self.scope).and_then(|answers| answers.get(&key)).cloned() else {
+            return false;
+        };
+        drop(answers);
+        let Answer { value, model, stop_reason } = answer;
+        // The program may have stopped waiting; the request is answered
+        // either way
+        let _ = request.response_tx.send(ThinkResponse::Metadata { model, stop_reason });
+        let _ = request.response_tx.send(ThinkResponse::Complete { result: Ok(value) });
+        true
+    }
+
+    /// Keep `value` as the answer to the request `key` came from.
+    pub(crate) fn keep(&self, key: CacheKey, value: Value, model: Option<String>, stop_reason: Option<String>) {
+        let answer = Answer { value, model, stop_reason };
+        self.answers.lock().unwrap().entry(self.scope.clone()).or_default().insert(key, answer);
+    }
+}
+
+/// Where answers for `session` are kept in `scope`, or None when they
+/// aren't kept.
+fn scope_key(scope: CacheScope, session: &str) -> Option<Option<String>> {
+    match scope {
+        CacheScope::Off => None,
+        CacheScope::Session => Some(Some(session.to_string())),
+        CacheScope::Global => Some(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+
+    use tokio::sync::mpsc::unbounded_channel;
+
+    use super::*;
+    use crate::agent::Priority;
+    use crate::schema::Schema;
+
+    fn ask(agent: &AgentHandle, prompt: &str) -> Value {
+        let (response_tx, rx) = mpsc::channel();
+        let request = ThinkRequest {
+            op: ThinkOp::Think,
+            prompt: prompt.to_string(),
+            parts: Vec::new(),
+            bindings: HashMap::new(),
+            expect: Schema::String,
+            model: None,
+            agent: None,
+            timeout: None,
+            priority: Priority::default(),
+            response_tx,
+        };
+        // Keep the answer the way the interpreter does
+        let key = agent.answer_key(&request);
+        agent.think(request).unwrap();
+        loop {
+            if let ThinkResponse::Complete { result } = rx.recv().unwrap() {
+                let value = result.unwrap();
+                if let Some(key) = key {
+                    agent.keep_answer(key, value.clone(), None, None);
+                }
+                return value;
+            }
+        }
+    }
+
+    #[test]
+    fn test_think_cache_scopes() {
+        let (backend_tx, mut backend_rx) = unbounded_channel::<ThinkRequest>();
+        let backend = thread::spawn(move || {
+            let mut asked = 0;
+            while let Some(request) = backend_rx.blocking_recv() {
+                asked += 1;
+                let result = Ok(Value::String(format!("{} #{}", request.prompt, asked)));
+                let _ = request.response_tx.send(ThinkResponse::Complete { result });
+            }
+            asked
+        });
+        let cache = ThinkCache::new(AgentHandle::new(backend_tx));
+
+        let first = cache.handle(CacheScope::Session, "a");
+        assert_eq!(ask(&first, "hello"), Value::String("hello #1".to_string()));
+        // Another handle in the same session shares its answers
+        let again = cache.handle(CacheScope::Session, "a");
+        assert_eq!(ask(&again, "hello"), Value::String("hello #1".to_string()));
+        // Other sessions don't
+        let other = cache.handle(CacheScope::Session, "b");
+        assert_eq!(ask(&other, "hello"), Value::String("hello #2".to_string()));
+
+        let global = cache.handle(CacheScope::Global, "a");
+        assert_eq!(ask(&global, "hello"), Value::String("hello #3".to_string()));
+        let elsewhere = cache.handle(CacheScope::Global, "c");
+        assert_eq!(ask(&elsewhere, "hello"), Value::String("hello #3".to_string()));
+
+        assert_eq!(cache.clear(CacheScope::Session, "a"), 1);
+        assert_eq!(ask(&first, "hello"), Value::String("hello #4".to_string()));
+        assert_eq!(ask(&other, "hello"), Value::String("hello #2".to_string()));
+
+        let uncached = cache.handle(CacheScope::Off, "a");
+        assert_eq!(ask(&uncached, "hello"), Value::String("hello #5".to_string()));
+        assert_eq!(cache.clear(CacheScope::Off, "a"), 0);
+
+        drop((cache, first, again, other, global, elsewhere, uncached));
+        assert_eq!(backend.join().unwrap(), 5);
+    }
+}
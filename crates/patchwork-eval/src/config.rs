@@ -0,0 +1,1928 @@
+//! Layered configuration for Patchwork hosts.
+//!
+//! Settings are assembled from four layers, each overriding the one before:
+//!
+//! 1. built-in defaults
+//! 2. config files: the user file (`~/.config/patchwork/config.json`), then
+//!    the project file (`patchwork.json` in the working directory), or a
+//!    single explicit file named by `PATCHWORK_CONFIG` / `--config`
+//! 3. `PATCHWORK_*` environment variables
+//! 4. command-line flags
+//!
+//! Every host (the ACP proxy, `patchwork run`, the REPL) loads its settings
+//! through `Config::load` so a given setting means the same thing everywhere.
+//! A program's front matter can then narrow them for its own runs; see
+//! `FrontMatter`.
+//!
+//! A config file is a JSON object; every key is optional:
+//!
+//! ```json
+//! {
+//!   "backend": "acp",
+//!   "api_key": "sk-...",
+//!   "cache_dir": "/tmp/patchwork-cache",
+//!   "models": ["claude-opus-4", "claude-sonnet-4"],
+//!   "failover_on": ["rate_limit", "timeout"],
+//!   "agent": "claude",
+//!   "agents": {
+//!     "claude": { "command": "claude-code-acp" },
+//!     "local": { "command": "ollama-acp", "args": ["--model", "llama3"] }
+//!   },
+//!   "think_cache": "session",
+//!   "telemetry": "json:/var/log/patchwork/events.jsonl",
+//!   "background_on_exit": "wait",
+//!   "strict": true,
+//!   "lenient_shell": false,
+//!   "format": "pretty",
+//!   "prompt_variant": "split:concise=90,detailed=10",
+//!   "summary_prompt": "Summarize the conversation so far as terse notes.",
+//!   "redact": ["aws-keys", { "name": "ticket", "pattern": "TICKET-[0-9]+" }],
+//!   "schedule": { "every": "15m", "jitter": "1m" },
+//!   "session": { "dir": "/tmp/patchwork-sessions", "keep": "on-failure", "ttl": "168h" },
+//!   "capabilities": {
+//!     "shell": "ask-first",
+//!     "shell_allowlist": ["ls", "git"],
+//!     "file_write": "deny",
+//!     "notify": "deny",
+//!     "eval_code": "ask-first",
+//!     "file_access": "confined",
+//!     "file_roots": ["/data/shared"]
+//!   },
+//!   "limits": {
+//!     "max_think_calls": 20,
+//!     "max_loop_iterations": 10000,
+//!     "max_llm_calls": 50,
+//!     "max_total_tokens": 200000,
+//!     "max_cost_usd": 2.5,
+//!     "think_timeout_secs": 120,
+//!     "approval_timeout_secs": 600,
+//!     "spill_threshold_bytes": 67108864,
+//!     "max_example_tokens": 2000,
+//!     "max_context_tokens": 50000
+//!   }
+//! }
+//! ```
+//!
+//! The `max_llm_calls`, `max_total_tokens`, and `max_cost_usd` limits form a
+//! per-run budget: crossing one aborts the run with `Error::BudgetExceeded`.
+//! `spill_threshold_bytes` moves variable values at least that large to disk.
+//! `max_example_tokens` caps the few-shot `examples` sections of a prompt.
+//! `approval_timeout_secs` is how long an approval request waits for a
+//! person before counting as a refusal.
+//!
+//! `max_context_tokens` caps the estimated size of the conversation that
+//! `history()` returns. Past it, the oldest think and ask blocks are folded
+//! into a summary written by a think call; `summary_prompt` replaces the
+//! instructions for that call.
+//!
+//! `redact` lists what is removed from prompts and bindings before they are
+//! sent to the LLM; see `Redactor`. Each layer's rules are added to those of
+//! the layers before it, so a project file can't drop the user's. The
+//! `PATCHWORK_REDACT` variable and `--redact` flag take a comma-separated
+//! list of built-in rule names.
+//!
+//! `strict` turns the language's implicit coercions into runtime errors: a
+//! condition that is not a boolean or null, `+` between a string and a
+//! non-string, and reads of missing object fields or out-of-range indexes.
+//!
+//! A shell command that exits with a non-zero status throws a `ShellError`
+//! object with its `command`, `status`, and `stderr`. `lenient_shell`
+//! (`--lenient-shell`) makes it return its output instead, leaving the
+//! status for the program to check with `$?`.
+//!
+//! `format` chooses how hosts print returned values: `pretty`, `plain`, or
+//! `json` (see `ValueRenderer`). `result_json` is set only by
+//! `PATCHWORK_RESULT_JSON` or `--result-json`; see `RunResult`.
+//!
+//! `agents` names downstream agents the ACP proxy starts alongside its
+//! successor, and `agent` (`--agent`) the one think blocks go to; without
+//! it they go to the successor. A skill annotated `@agent name` sends its
+//! think blocks to that agent instead. Each layer's agents are added to
+//! those of the layers before it, replacing any with the same name.
+//!
+//! `think_cache` (`--think-cache`) lets a host answer a think block it has
+//! seen before without asking again: `off`, `session` to reuse answers
+//! within one session, or `global` across all of the host's sessions. See
+//! `ThinkCache`.
+//!
+//! `telemetry` (`--telemetry`) is where the runtime reports LLM calls, shell
+//! commands, errors, and run summaries: `off`, `json:<path>` to append them
+//! to a file, or `otlp:<endpoint>` to send them to an OpenTelemetry
+//! collector. See `TelemetrySink`.
+//!
+//! `background_on_exit` (`--background-on-exit`) is what happens to
+//! commands started with `$(cmd &)` that are still running when a program
+//! ends: `kill` them, with a warning for each, or `wait` for them, failing
+//! the run if any of them fail.
+//!
+//! `prompt_variant` chooses among the `variant` sections of think blocks;
+//! see `VariantPolicy`.
+//!
+//! `schedule.every` (`--every`) makes the host rerun the program on that
+//! interval until stopped, each run delayed by up to `schedule.jitter`
+//! (`--jitter`); see `Scheduler`.
+//!
+//! `session.dir` (`--session-dir`) is where runs get their session
+//! directories, `session.keep` (`--keep-session`) whether one outlives its
+//! run, and `session.ttl` (`--session-ttl`) how long kept ones last; see
+//! `Session`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::redact::{RedactionRule, Redactor};
+use crate::render::OutputFormat;
+use crate::runtime::resolve_symlinks;
+use crate::schedule::Schedule;
+use crate::session::KeepPolicy;
+use crate::timer::parse_duration;
+use crate::value::Value;
+
+/// Name of the project config file, looked up in the working directory.
+pub const PROJECT_CONFIG_FILE: &str = "patchwork.json";
+
+/// Which LLM backend think blocks are sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// The agent on the other side of the ACP connection.
+    #[default]
+    Acp,
+    /// The Anthropic API directly, authenticated with `api_key`.
+    Anthropic,
+    /// No LLM: think blocks return their interpolated prompt.
+    Offline,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "acp" => Ok(Backend::Acp),
+            "anthropic" => Ok(Backend::Anthropic),
+            "offline" => Ok(Backend::Offline),
+            other => Err(format!(
+                "unknown backend `{}` (expected acp, anthropic, or offline)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Acp => write!(f, "acp"),
+            Backend::Anthropic => write!(f, "anthropic"),
+            Backend::Offline => write!(f, "offline"),
+        }
+    }
+}
+
+/// Which think blocks share answers, for hosts that keep them; see
+/// `ThinkCache`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CacheScope {
+    /// Every think block asks the backend.
+    #[default]
+    Off,
+    /// A think block asked again in the same session gets the earlier answer.
+    Session,
+    /// A think block asked again in any of the host's sessions gets the
+    /// earlier answer.
+    Global,
+}
+
+impl FromStr for CacheScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(CacheScope::Off),
+            "session" => Ok(CacheScope::Session),
+            "global" => Ok(CacheScope::Global),
+            other => Err(format!(
+                "unknown cache scope `{}` (expected off, session, or global)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CacheScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheScope::Off => write!(f, "off"),
+            CacheScope::Session => write!(f, "session"),
+            CacheScope::Global => write!(f, "global"),
+        }
+    }
+}
+
+/// Where the runtime reports telemetry; see `TelemetrySink`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Telemetry {
+    /// Nothing is reported.
+    #[default]
+    Off,
+    /// Each event is appended to this file as a line of JSON.
+    JsonFile(PathBuf),
+    /// Each event is sent to the OTLP/HTTP collector at this URL, such as
+    /// `http://localhost:4318`.
+    Otlp(String),
+}
+
+impl FromStr for Telemetry {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            _ if s == "off" => Ok(Telemetry::Off),
+            Some(("json", path)) if !path.is_empty() => Ok(Telemetry::JsonFile(PathBuf::from(path))),
+            Some(("otlp", endpoint)) if !endpoint.is_empty() => Ok(Telemetry::Otlp(endpoint.to_string())),
+            _ => Err(format!(
+                "unknown telemetry sink `{}` (expected off, json:<path>, or otlp:<endpoint>)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Telemetry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Telemetry::Off => write!(f, "off"),
+            Telemetry::JsonFile(path) => write!(f, "json:{}", path.display()),
+            Telemetry::Otlp(endpoint) => write!(f, "otlp:{}", endpoint),
+        }
+    }
+}
+
+/// What happens to background commands still running when a program ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackgroundPolicy {
+    /// Stop them, warning about each.
+    #[default]
+    Kill,
+    /// Wait for them to exit, failing the run like `wait_all` if any fail.
+    Wait,
+}
+
+impl FromStr for BackgroundPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "kill" => Ok(BackgroundPolicy::Kill),
+            "wait" => Ok(BackgroundPolicy::Wait),
+            other => Err(format!("unknown background policy `{}` (expected kill or wait)", other)),
+        }
+    }
+}
+
+impl fmt::Display for BackgroundPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackgroundPolicy::Kill => write!(f, "kill"),
+            BackgroundPolicy::Wait => write!(f, "wait"),
+        }
+    }
+}
+
+/// Whether a capability may be used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Permission {
+    #[default]
+    Allow,
+    /// Ask the user before each use. Only supported for shell commands
+    /// and `eval_code`.
+    AskFirst,
+    Deny,
+}
+
+impl Permission {
+    /// Whichever of the two allows less.
+    pub fn stricter(self, other: Permission) -> Permission {
+        match (self, other) {
+            (Permission::Deny, _) | (_, Permission::Deny) => Permission::Deny,
+            (Permission::AskFirst, _) | (_, Permission::AskFirst) => Permission::AskFirst,
+            (Permission::Allow, Permission::Allow) => Permission::Allow,
+        }
+    }
+}
+
+impl FromStr for Permission {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Permission::Allow),
+            "ask-first" => Ok(Permission::AskFirst),
+            "deny" => Ok(Permission::Deny),
+            other => Err(format!(
+                "unknown permission `{}` (expected allow, ask-first, or deny)",
+                other
+            )),
+        }
+    }
+}
+
+/// Where file operations may reach.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileAccess {
+    /// Only inside the working directory and the configured `file_roots`,
+    /// after resolving symlinks.
+    #[default]
+    Confined,
+    /// Anywhere the process can reach.
+    Unrestricted,
+}
+
+impl FromStr for FileAccess {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "confined" => Ok(FileAccess::Confined),
+            "unrestricted" => Ok(FileAccess::Unrestricted),
+            other => Err(format!(
+                "unknown file access `{}` (expected confined or unrestricted)",
+                other
+            )),
+        }
+    }
+}
+
+/// A kind of think block failure, used to decide when to fail over to the
+/// next model in the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureClass {
+    /// The backend refused the request for sending too many.
+    RateLimit,
+    /// No answer within `think_timeout_secs`.
+    Timeout,
+    /// Any other backend error.
+    Error,
+}
+
+impl FromStr for FailureClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rate_limit" => Ok(FailureClass::RateLimit),
+            "timeout" => Ok(FailureClass::Timeout),
+            "error" => Ok(FailureClass::Error),
+            other => Err(format!(
+                "unknown failure class `{}` (expected rate_limit, timeout, or error)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FailureClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureClass::RateLimit => write!(f, "rate_limit"),
+            FailureClass::Timeout => write!(f, "timeout"),
+            FailureClass::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Which models think blocks try, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelChain {
+    /// Models to try, first to last. Empty means the backend's default.
+    pub models: Vec<String>,
+    /// Failures that move on to the next model; others fail the block.
+    pub failover_on: Vec<FailureClass>,
+    /// Downstream agent to ask, by name in the host's `agents` table.
+    /// None means the host's default agent.
+    pub agent: Option<String>,
+}
+
+impl Default for ModelChain {
+    fn default() -> Self {
+        Self {
+            models: Vec::new(),
+            failover_on: vec![FailureClass::RateLimit, FailureClass::Timeout, FailureClass::Error],
+            agent: None,
+        }
+    }
+}
+
+/// A downstream agent the ACP proxy can start and send think blocks to,
+/// such as a local model behind its own ACP adapter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentCommand {
+    /// Program to run; it must speak ACP on its standard input and output.
+    pub command: String,
+    pub args: Vec<String>,
+}
+
+/// How a think block with `variant name { ... }` sections chooses the one
+/// a call uses.
+///
+/// Written `first`, `pin:<name>`, `split`, `split:<name>=<weight>,...`, or
+/// `env:<VAR>`. Whenever the policy names a variant a block doesn't have,
+/// the block uses its first variant.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum VariantPolicy {
+    /// Each block's first variant.
+    #[default]
+    First,
+    /// The variant with this name.
+    Pin(String),
+    /// A random variant for each call, in proportion to its weight.
+    /// No weights means every variant is equally likely; with weights,
+    /// unlisted variants are never chosen.
+    Split(Vec<(String, u32)>),
+    /// The variant named by this environment variable when the call is
+    /// made, so a deployment can switch variants without a config change.
+    Env(String),
+}
+
+impl FromStr for VariantPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, arg) = match s.split_once(':') {
+            Some((kind, arg)) => (kind, Some(arg.trim())),
+            None => (s, None),
+        };
+        match (kind, arg) {
+            ("first", None) => Ok(VariantPolicy::First),
+            ("pin", Some(name)) if !name.is_empty() => Ok(VariantPolicy::Pin(name.to_string())),
+            ("split", None) => Ok(VariantPolicy::Split(Vec::new())),
+            ("split", Some(weights)) => {
+                let weights = comma_list(weights)
+                    .iter()
+                    .map(|entry| {
+                        let (name, weight) = entry
+                            .split_once('=')
+                            .ok_or_else(|| format!("expected `name=weight`, got `{}`", entry))?;
+                        let weight = weight
+                            .trim()
+                            .parse::<u32>()
+                            .map_err(|_| format!("expected a non-negative integer weight, got `{}`", weight.trim()))?;
+                        Ok((name.trim().to_string(), weight))
+                    })
+                    .collect::<Result<_, String>>()?;
+                Ok(VariantPolicy::Split(weights))
+            }
+            ("env", Some(var)) if !var.is_empty() => Ok(VariantPolicy::Env(var.to_string())),
+            _ => Err(format!(
+                "unknown variant policy `{}` (expected first, pin:<name>, split, split:<name>=<weight>,..., or env:<VAR>)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for VariantPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VariantPolicy::First => write!(f, "first"),
+            VariantPolicy::Pin(name) => write!(f, "pin:{}", name),
+            VariantPolicy::Split(weights) if weights.is_empty() => write!(f, "split"),
+            VariantPolicy::Split(weights) => {
+                let weights: Vec<String> = weights.iter().map(|(name, weight)| format!("{}={}", name, weight)).collect();
+                write!(f, "split:{}", weights.join(","))
+            }
+            VariantPolicy::Env(var) => write!(f, "env:{}", var),
+        }
+    }
+}
+
+/// What a running program is allowed to do.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CapabilityPolicy {
+    /// Running shell commands.
+    pub shell: Permission,
+    /// Programs that may always run, whatever `shell` says.
+    pub shell_allowlist: Vec<String>,
+    /// Writing files, via `write()` or output redirection.
+    pub file_write: Permission,
+    /// Where file builtins and redirections may read and write.
+    pub file_access: FileAccess,
+    /// Directories outside the working directory that confined file
+    /// operations may also use.
+    pub file_roots: Vec<PathBuf>,
+    /// Sending notifications with the `std.notify` module.
+    pub notify: Permission,
+    /// Running generated code with `eval_patchwork()`.
+    pub eval_code: Permission,
+}
+
+impl CapabilityPolicy {
+    /// The permission for a capability by its config name, e.g. `file_write`,
+    /// or `None` for a name that isn't one.
+    pub fn permission(&self, name: &str) -> Option<Permission> {
+        match name {
+            "shell" => Some(self.shell),
+            "file_write" => Some(self.file_write),
+            "notify" => Some(self.notify),
+            "eval_code" => Some(self.eval_code),
+            _ => None,
+        }
+    }
+
+    /// This policy with every capability but those in `names` denied, for
+    /// a skill annotated `@capabilities(...)`. The capabilities named keep
+    /// their permissions.
+    pub fn keep_only(&self, names: &[String]) -> CapabilityPolicy {
+        let named = |name: &str| names.iter().any(|n| n == name);
+        let keep = |name: &str, permission: Permission| if named(name) { permission } else { Permission::Deny };
+        CapabilityPolicy {
+            shell: keep("shell", self.shell),
+            shell_allowlist: if named("shell") { self.shell_allowlist.clone() } else { Vec::new() },
+            file_write: keep("file_write", self.file_write),
+            notify: keep("notify", self.notify),
+            eval_code: keep("eval_code", self.eval_code),
+            ..self.clone()
+        }
+    }
+
+    /// What both policies allow, for code that must not do more than
+    /// the program running it.
+    ///
+    /// Each permission is the stricter of the two. A program on `other`'s
+    /// allowlist stays allowlisted only if `self` would run it without
+    /// asking, and confined file access keeps only the roots `self` also
+    /// grants. Roots are compared where they lead from `working_dir`, with
+    /// `..` and symlinks resolved, and kept in that form; a root whose `..`
+    /// climbs above `/` is dropped.
+    pub fn restrict(&self, other: &CapabilityPolicy, working_dir: &Path) -> CapabilityPolicy {
+        let self_runs = |program: &String| self.shell == Permission::Allow || self.shell_allowlist.contains(program);
+        let resolve = |root: &PathBuf| resolve_symlinks(&working_dir.join(root));
+        let file_roots = match self.file_access {
+            FileAccess::Unrestricted => other.file_roots.clone(),
+            FileAccess::Confined => {
+                let granted: Vec<PathBuf> = self.file_roots.iter().filter_map(resolve).collect();
+                other
+                    .file_roots
+                    .iter()
+                    .filter_map(resolve)
+                    .filter(|root| granted.iter().any(|granted| root.starts_with(granted)))
+                    .collect()
+            }
+        };
+        CapabilityPolicy {
+            shell: self.shell.stricter(other.shell),
+            shell_allowlist: other.shell_allowlist.iter().filter(|program| self_runs(program)).cloned().collect(),
+            file_write: self.file_write.stricter(other.file_write),
+            file_access: match (self.file_access, other.file_access) {
+                (FileAccess::Unrestricted, FileAccess::Unrestricted) => FileAccess::Unrestricted,
+                _ => FileAccess::Confined,
+            },
+            file_roots,
+            notify: self.notify.stricter(other.notify),
+            eval_code: self.eval_code.stricter(other.eval_code),
+        }
+    }
+}
+
+/// Resource limits for a runtime. `None` means unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Limits {
+    /// Maximum number of think/ask blocks evaluated.
+    pub max_think_calls: Option<u64>,
+    /// Maximum iterations of any single `while` loop.
+    pub max_loop_iterations: Option<u64>,
+    /// Budget: maximum requests sent to the LLM.
+    pub max_llm_calls: Option<u64>,
+    /// Budget: maximum input plus output tokens across all LLM calls.
+    pub max_total_tokens: Option<u64>,
+    /// Budget: maximum spend in US dollars across all LLM calls.
+    pub max_cost_usd: Option<f64>,
+    /// Seconds to wait for each model's answer before failing over.
+    pub think_timeout_secs: Option<u64>,
+    /// Seconds to wait for a person to answer an approval request before
+    /// treating it as denied.
+    pub approval_timeout_secs: Option<u64>,
+    /// Strings and arrays at least this many bytes are stored on disk
+    /// instead of in memory when bound to a variable.
+    pub spill_threshold_bytes: Option<u64>,
+    /// Estimated tokens the `examples` sections of one prompt may use;
+    /// examples past the limit are left out.
+    pub max_example_tokens: Option<u64>,
+    /// Estimated tokens the conversation transcript may hold before its
+    /// oldest entries are summarized.
+    pub max_context_tokens: Option<u64>,
+}
+
+impl Limits {
+    /// The smaller of each limit set in either.
+    pub fn tighter(&self, other: &Limits) -> Limits {
+        fn min<T: PartialOrd>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(if b < a { b } else { a }),
+                (a, b) => a.or(b),
+            }
+        }
+        Limits {
+            max_think_calls: min(self.max_think_calls, other.max_think_calls),
+            max_loop_iterations: min(self.max_loop_iterations, other.max_loop_iterations),
+            max_llm_calls: min(self.max_llm_calls, other.max_llm_calls),
+            max_total_tokens: min(self.max_total_tokens, other.max_total_tokens),
+            max_cost_usd: min(self.max_cost_usd, other.max_cost_usd),
+            think_timeout_secs: min(self.think_timeout_secs, other.think_timeout_secs),
+            approval_timeout_secs: min(self.approval_timeout_secs, other.approval_timeout_secs),
+            spill_threshold_bytes: min(self.spill_threshold_bytes, other.spill_threshold_bytes),
+            max_example_tokens: min(self.max_example_tokens, other.max_example_tokens),
+            max_context_tokens: min(self.max_context_tokens, other.max_context_tokens),
+        }
+    }
+}
+
+/// Fully resolved settings.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Config {
+    pub backend: Backend,
+    pub api_key: Option<String>,
+    /// Directory for cached artifacts. Defaults to the user cache directory.
+    pub cache_dir: Option<PathBuf>,
+    pub capabilities: CapabilityPolicy,
+    pub limits: Limits,
+    pub models: ModelChain,
+    /// Downstream agents think blocks can be routed to, by name.
+    pub agents: BTreeMap<String, AgentCommand>,
+    /// Which think blocks reuse each other's answers.
+    pub think_cache: CacheScope,
+    /// Where LLM calls, shell commands, errors, and run summaries are reported.
+    pub telemetry: Telemetry,
+    /// What happens to background commands still running when a program ends.
+    pub background_on_exit: BackgroundPolicy,
+    /// Report implicit coercions as runtime errors.
+    pub strict: bool,
+    /// Let failing shell commands return instead of throwing.
+    pub lenient_shell: bool,
+    /// How returned values are printed.
+    pub format: OutputFormat,
+    /// How think blocks choose among their prompt variants.
+    pub prompt_variant: VariantPolicy,
+    /// Instructions for summarizing the conversation, replacing the default.
+    pub summary_prompt: Option<String>,
+    /// What to remove from prompts before they are sent.
+    pub redact: Vec<RedactionRule>,
+    /// File to write the run's `RunResult` JSON to.
+    pub result_json: Option<PathBuf>,
+    /// Rerun the program this often, if set.
+    pub every: Option<Duration>,
+    /// Delay each scheduled run by a random amount up to this long.
+    pub jitter: Duration,
+    /// Where session directories are created. Defaults to the system temp
+    /// directory.
+    pub session_dir: Option<PathBuf>,
+    /// Whether a session directory is kept after its run.
+    pub keep_session: KeepPolicy,
+    /// Remove kept sessions older than this when a new one starts.
+    pub session_ttl: Option<Duration>,
+}
+
+/// One layer of settings; unset fields leave lower layers in effect.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ConfigLayer {
+    /// Explicit config file (only meaningful in the env and CLI layers).
+    pub config_file: Option<PathBuf>,
+    pub backend: Option<Backend>,
+    pub api_key: Option<String>,
+    pub cache_dir: Option<PathBuf>,
+    pub shell: Option<Permission>,
+    pub shell_allowlist: Option<Vec<String>>,
+    pub file_write: Option<Permission>,
+    pub file_access: Option<FileAccess>,
+    pub file_roots: Option<Vec<PathBuf>>,
+    pub notify: Option<Permission>,
+    pub eval_code: Option<Permission>,
+    pub max_think_calls: Option<u64>,
+    pub max_loop_iterations: Option<u64>,
+    pub max_llm_calls: Option<u64>,
+    pub max_total_tokens: Option<u64>,
+    pub max_cost_usd: Option<f64>,
+    pub think_timeout_secs: Option<u64>,
+    pub approval_timeout_secs: Option<u64>,
+    pub spill_threshold_bytes: Option<u64>,
+    pub max_example_tokens: Option<u64>,
+    pub max_context_tokens: Option<u64>,
+    pub models: Option<Vec<String>>,
+    pub failover_on: Option<Vec<FailureClass>>,
+    pub agent: Option<String>,
+    pub agents: Option<BTreeMap<String, AgentCommand>>,
+    pub think_cache: Option<CacheScope>,
+    pub telemetry: Option<Telemetry>,
+    pub background_on_exit: Option<BackgroundPolicy>,
+    pub strict: Option<bool>,
+    pub lenient_shell: Option<bool>,
+    pub format: Option<OutputFormat>,
+    pub prompt_variant: Option<VariantPolicy>,
+    pub summary_prompt: Option<String>,
+    pub redact: Option<Vec<RedactionRule>>,
+    /// Only meaningful in the env and CLI layers.
+    pub result_json: Option<PathBuf>,
+    pub every: Option<Duration>,
+    pub jitter: Option<Duration>,
+    pub session_dir: Option<PathBuf>,
+    pub keep_session: Option<KeepPolicy>,
+    pub session_ttl: Option<Duration>,
+}
+
+/// A setting that could not be read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError {
+    /// Where the bad setting came from: a file path, env var, or flag.
+    pub origin: String,
+    pub message: String,
+}
+
+impl ConfigError {
+    pub(crate) fn new(origin: impl Into<String>, message: impl Into<String>) -> Self {
+        Self { origin: origin.into(), message: message.into() }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.origin, self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl ConfigLayer {
+    /// Read a layer from JSON config file contents.
+    pub fn from_json(text: &str, origin: &str) -> Result<Self, ConfigError> {
+        let root: serde_json::Value = serde_json::from_str(text)
+            .map_err(|e| ConfigError::new(origin, format!("invalid JSON: {}", e)))?;
+        Self::from_value(&root, origin)
+    }
+
+    /// Read a layer from settings already parsed, laid out as in a config file.
+    pub fn from_value(root: &serde_json::Value, origin: &str) -> Result<Self, ConfigError> {
+        let root = root
+            .as_object()
+            .ok_or_else(|| ConfigError::new(origin, "expected a JSON object"))?;
+
+        let mut layer = ConfigLayer::default();
+        for (key, value) in root {
+            let field = |name: &str| format!("{} ({})", origin, name);
+            match key.as_str() {
+                "backend" => layer.backend = Some(parse_json(value, &field("backend"))?),
+                "api_key" => layer.api_key = Some(json_str(value, &field("api_key"))?.to_string()),
+                "cache_dir" => {
+                    layer.cache_dir = Some(PathBuf::from(json_str(value, &field("cache_dir"))?))
+                }
+                "models" => layer.models = Some(json_str_array(value, &field("models"))?),
+                "failover_on" => {
+                    let origin = field("failover_on");
+                    let classes = json_str_array(value, &origin)?
+                        .iter()
+                        .map(|class| class.parse::<FailureClass>().map_err(|e| ConfigError::new(&origin, e)))
+                        .collect::<Result<_, _>>()?;
+                    layer.failover_on = Some(classes);
+                }
+                "agent" => layer.agent = Some(json_str(value, &field("agent"))?.to_string()),
+                "agents" => layer.agents = Some(json_agents(value, &field("agents"))?),
+                "think_cache" => layer.think_cache = Some(parse_json(value, &field("think_cache"))?),
+                "telemetry" => layer.telemetry = Some(parse_json(value, &field("telemetry"))?),
+                "background_on_exit" => {
+                    layer.background_on_exit = Some(parse_json(value, &field("background_on_exit"))?)
+                }
+                "strict" => {
+                    layer.strict = Some(
+                        value
+                            .as_bool()
+                            .ok_or_else(|| ConfigError::new(field("strict"), "expected true or false"))?,
+                    )
+                }
+                "lenient_shell" => {
+                    layer.lenient_shell = Some(
+                        value
+                            .as_bool()
+                            .ok_or_else(|| ConfigError::new(field("lenient_shell"), "expected true or false"))?,
+                    )
+                }
+                "format" => layer.format = Some(parse_json(value, &field("format"))?),
+                "prompt_variant" => layer.prompt_variant = Some(parse_json(value, &field("prompt_variant"))?),
+                "summary_prompt" => {
+                    layer.summary_prompt = Some(json_str(value, &field("summary_prompt"))?.to_string())
+                }
+                "redact" => layer.redact = Some(json_redaction_rules(value, &field("redact"))?),
+                "schedule" => {
+                    for (key, value) in json_object(value, &field("schedule"))? {
+                        let origin = field(&format!("schedule.{}", key));
+                        let duration = json_duration(value, &origin)?;
+                        match key.as_str() {
+                            "every" => layer.every = Some(duration),
+                            "jitter" => layer.jitter = Some(duration),
+                            _ => return Err(ConfigError::new(origin, "unknown schedule setting")),
+                        }
+                    }
+                }
+                "session" => {
+                    for (key, value) in json_object(value, &field("session"))? {
+                        let origin = field(&format!("session.{}", key));
+                        match key.as_str() {
+                            "dir" => layer.session_dir = Some(PathBuf::from(json_str(value, &origin)?)),
+                            "keep" => layer.keep_session = Some(parse_json(value, &origin)?),
+                            "ttl" => layer.session_ttl = Some(json_duration(value, &origin)?),
+                            _ => return Err(ConfigError::new(origin, "unknown session setting")),
+                        }
+                    }
+                }
+                "capabilities" => {
+                    for (cap, value) in json_object(value, &field("capabilities"))? {
+                        let origin = field(&format!("capabilities.{}", cap));
+                        match cap.as_str() {
+                            "shell" => layer.shell = Some(parse_json(value, &origin)?),
+                            "shell_allowlist" => {
+                                layer.shell_allowlist = Some(json_str_array(value, &origin)?)
+                            }
+                            "file_write" => {
+                                layer.file_write = Some(without_ask_first(parse_json(value, &origin)?, &origin)?)
+                            }
+                            "notify" => {
+                                layer.notify = Some(without_ask_first(parse_json(value, &origin)?, &origin)?)
+                            }
+                            "eval_code" => layer.eval_code = Some(parse_json(value, &origin)?),
+                            "file_access" => layer.file_access = Some(parse_json(value, &origin)?),
+                            "file_roots" => {
+                                layer.file_roots =
+                                    Some(json_str_array(value, &origin)?.into_iter().map(PathBuf::from).collect())
+                            }
+                            _ => return Err(ConfigError::new(origin, "unknown capability")),
+                        }
+                    }
+                }
+                "limits" => {
+                    for (limit, value) in json_object(value, &field("limits"))? {
+                        let origin = field(&format!("limits.{}", limit));
+                        let n = || {
+                            // Whole floats too, as Patchwork values convert to them
+                            value
+                                .as_u64()
+                                .or_else(|| value.as_f64().filter(|f| *f >= 0.0 && f.fract() == 0.0).map(|f| f as u64))
+                                .ok_or_else(|| ConfigError::new(&origin, "expected a non-negative integer"))
+                        };
+                        match limit.as_str() {
+                            "max_think_calls" => layer.max_think_calls = Some(n()?),
+                            "max_loop_iterations" => layer.max_loop_iterations = Some(n()?),
+                            "max_llm_calls" => layer.max_llm_calls = Some(n()?),
+                            "max_total_tokens" => layer.max_total_tokens = Some(n()?),
+                            "think_timeout_secs" => layer.think_timeout_secs = Some(n()?),
+                            "approval_timeout_secs" => layer.approval_timeout_secs = Some(n()?),
+                            "spill_threshold_bytes" => layer.spill_threshold_bytes = Some(n()?),
+                            "max_example_tokens" => layer.max_example_tokens = Some(n()?),
+                            "max_context_tokens" => layer.max_context_tokens = Some(n()?),
+                            "max_cost_usd" => {
+                                let dollars = value
+                                    .as_f64()
+                                    .filter(|d| *d >= 0.0)
+                                    .ok_or_else(|| ConfigError::new(&origin, "expected a non-negative number"))?;
+                                layer.max_cost_usd = Some(dollars);
+                            }
+                            _ => return Err(ConfigError::new(origin, "unknown limit")),
+                        }
+                    }
+                }
+                _ => return Err(ConfigError::new(field(key.as_str()), "unknown setting")),
+            }
+        }
+        Ok(layer)
+    }
+
+    /// Read a layer from a config file.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let origin = path.display().to_string();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::new(&origin, e.to_string()))?;
+        Self::from_json(&text, &origin)
+    }
+
+    /// Read a layer from `PATCHWORK_*` variables in the process environment.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_vars(std::env::vars())
+    }
+
+    /// Read a layer from `PATCHWORK_*` variables; other variables are ignored.
+    pub fn from_vars<I>(vars: I) -> Result<Self, ConfigError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut layer = ConfigLayer::default();
+        for (name, value) in vars {
+            let Some(setting) = name.strip_prefix("PATCHWORK_") else {
+                continue;
+            };
+            // Unknown PATCHWORK_* variables may belong to other tools
+            if let Some(key) = env_setting(setting) {
+                layer.set(key, &value, &name)?;
+            }
+        }
+        Ok(layer)
+    }
+
+    /// Read a layer from command-line flags.
+    ///
+    /// Accepts `--flag value` and `--flag=value`. Arguments that are not
+    /// config flags are returned, in order, for the host to interpret.
+    pub fn from_args<I>(args: I) -> Result<(Self, Vec<String>), ConfigError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut layer = ConfigLayer::default();
+        let mut rest = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let Some(flag) = arg.strip_prefix("--") else {
+                rest.push(arg);
+                continue;
+            };
+            let (name, inline) = match flag.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (flag, None),
+            };
+            let Some(key) = flag_setting(name) else {
+                rest.push(arg);
+                continue;
+            };
+            let origin = format!("--{}", name);
+            let value = match inline {
+                Some(value) => value,
+                None => args
+                    .next()
+                    .ok_or_else(|| ConfigError::new(&origin, "missing value"))?,
+            };
+            layer.set(key, &value, &origin)?;
+        }
+        Ok((layer, rest))
+    }
+
+    fn set(&mut self, key: Setting, value: &str, origin: &str) -> Result<(), ConfigError> {
+        let parse_err = |message: String| ConfigError::new(origin, message);
+        let number = |value: &str| {
+            value
+                .parse::<u64>()
+                .map_err(|_| parse_err(format!("expected a non-negative integer, got `{}`", value)))
+        };
+        let duration = |value: &str| parse_duration(&Value::String(value.to_string())).map_err(parse_err);
+        let boolean = |value: &str| match value {
+            "true" | "1" => Ok(true),
+            "false" | "0" => Ok(false),
+            _ => Err(parse_err(format!("expected true or false, got `{}`", value))),
+        };
+        match key {
+            Setting::ConfigFile => self.config_file = Some(PathBuf::from(value)),
+            Setting::Backend => self.backend = Some(value.parse().map_err(parse_err)?),
+            Setting::ApiKey => self.api_key = Some(value.to_string()),
+            Setting::CacheDir => self.cache_dir = Some(PathBuf::from(value)),
+            Setting::Shell => self.shell = Some(value.parse().map_err(parse_err)?),
+            Setting::ShellAllowlist => self.shell_allowlist = Some(comma_list(value)),
+            Setting::Models => self.models = Some(comma_list(value)),
+            Setting::FailoverOn => {
+                let classes = comma_list(value)
+                    .iter()
+                    .map(|class| class.parse::<FailureClass>().map_err(parse_err))
+                    .collect::<Result<_, _>>()?;
+                self.failover_on = Some(classes);
+            }
+            Setting::Agent => self.agent = Some(value.to_string()),
+            Setting::ThinkCache => self.think_cache = Some(value.parse().map_err(parse_err)?),
+            Setting::Telemetry => self.telemetry = Some(value.parse().map_err(parse_err)?),
+            Setting::BackgroundOnExit => self.background_on_exit = Some(value.parse().map_err(parse_err)?),
+            Setting::FileWrite => {
+                self.file_write = Some(without_ask_first(value.parse().map_err(parse_err)?, origin)?)
+            }
+            Setting::Notify => self.notify = Some(without_ask_first(value.parse().map_err(parse_err)?, origin)?),
+            Setting::EvalCode => self.eval_code = Some(value.parse().map_err(parse_err)?),
+            Setting::FileAccess => self.file_access = Some(value.parse().map_err(parse_err)?),
+            // A path list, separated like PATH
+            Setting::FileRoots => self.file_roots = Some(std::env::split_paths(value).collect()),
+            Setting::MaxThinkCalls => self.max_think_calls = Some(number(value)?),
+            Setting::MaxLoopIterations => self.max_loop_iterations = Some(number(value)?),
+            Setting::MaxLlmCalls => self.max_llm_calls = Some(number(value)?),
+            Setting::MaxTotalTokens => self.max_total_tokens = Some(number(value)?),
+            Setting::ThinkTimeoutSecs => self.think_timeout_secs = Some(number(value)?),
+            Setting::ApprovalTimeoutSecs => self.approval_timeout_secs = Some(number(value)?),
+            Setting::SpillThresholdBytes => self.spill_threshold_bytes = Some(number(value)?),
+            Setting::MaxExampleTokens => self.max_example_tokens = Some(number(value)?),
+            Setting::MaxContextTokens => self.max_context_tokens = Some(number(value)?),
+            Setting::Strict => self.strict = Some(boolean(value)?),
+            Setting::LenientShell => self.lenient_shell = Some(boolean(value)?),
+            Setting::Format => self.format = Some(value.parse().map_err(parse_err)?),
+            Setting::PromptVariant => self.prompt_variant = Some(value.parse().map_err(parse_err)?),
+            Setting::SummaryPrompt => self.summary_prompt = Some(value.to_string()),
+            Setting::Redact => {
+                let rules = comma_list(value)
+                    .iter()
+                    .map(|name| RedactionRule::preset(name).map_err(parse_err))
+                    .collect::<Result<_, _>>()?;
+                self.redact = Some(rules);
+            }
+            Setting::ResultJson => self.result_json = Some(PathBuf::from(value)),
+            Setting::Every => self.every = Some(duration(value)?),
+            Setting::Jitter => self.jitter = Some(duration(value)?),
+            Setting::SessionDir => self.session_dir = Some(PathBuf::from(value)),
+            Setting::KeepSession => self.keep_session = Some(value.parse().map_err(parse_err)?),
+            Setting::SessionTtl => self.session_ttl = Some(duration(value)?),
+            Setting::MaxCostUsd => {
+                let dollars = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|d| d.is_finite() && *d >= 0.0)
+                    .ok_or_else(|| parse_err(format!("expected a non-negative number, got `{}`", value)))?;
+                self.max_cost_usd = Some(dollars);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Setting {
+    ConfigFile,
+    Backend,
+    ApiKey,
+    CacheDir,
+    Shell,
+    ShellAllowlist,
+    FileWrite,
+    FileAccess,
+    FileRoots,
+    Notify,
+    EvalCode,
+    MaxThinkCalls,
+    MaxLoopIterations,
+    MaxLlmCalls,
+    MaxTotalTokens,
+    MaxCostUsd,
+    ThinkTimeoutSecs,
+    ApprovalTimeoutSecs,
+    SpillThresholdBytes,
+    MaxExampleTokens,
+    MaxContextTokens,
+    Models,
+    FailoverOn,
+    Agent,
+    ThinkCache,
+    Telemetry,
+    BackgroundOnExit,
+    Strict,
+    LenientShell,
+    Format,
+    PromptVariant,
+    SummaryPrompt,
+    Redact,
+    ResultJson,
+    Every,
+    Jitter,
+    SessionDir,
+    KeepSession,
+    SessionTtl,
+}
+
+/// Map `PATCHWORK_<NAME>` suffixes to settings.
+fn env_setting(name: &str) -> Option<Setting> {
+    Some(match name {
+        "CONFIG" => Setting::ConfigFile,
+        "BACKEND" => Setting::Backend,
+        "API_KEY" => Setting::ApiKey,
+        "CACHE_DIR" => Setting::CacheDir,
+        "SHELL" => Setting::Shell,
+        "SHELL_ALLOWLIST" => Setting::ShellAllowlist,
+        "FILE_WRITE" => Setting::FileWrite,
+        "FILE_ACCESS" => Setting::FileAccess,
+        "FILE_ROOTS" => Setting::FileRoots,
+        "NOTIFY" => Setting::Notify,
+        "EVAL_CODE" => Setting::EvalCode,
+        "MAX_THINK_CALLS" => Setting::MaxThinkCalls,
+        "MAX_LOOP_ITERATIONS" => Setting::MaxLoopIterations,
+        "MAX_LLM_CALLS" => Setting::MaxLlmCalls,
+        "MAX_TOTAL_TOKENS" => Setting::MaxTotalTokens,
+        "MAX_COST_USD" => Setting::MaxCostUsd,
+        "THINK_TIMEOUT_SECS" => Setting::ThinkTimeoutSecs,
+        "APPROVAL_TIMEOUT_SECS" => Setting::ApprovalTimeoutSecs,
+        "SPILL_THRESHOLD_BYTES" => Setting::SpillThresholdBytes,
+        "MAX_EXAMPLE_TOKENS" => Setting::MaxExampleTokens,
+        "MAX_CONTEXT_TOKENS" => Setting::MaxContextTokens,
+        "MODELS" => Setting::Models,
+        "FAILOVER_ON" => Setting::FailoverOn,
+        "AGENT" => Setting::Agent,
+        "THINK_CACHE" => Setting::ThinkCache,
+        "TELEMETRY" => Setting::Telemetry,
+        "BACKGROUND_ON_EXIT" => Setting::BackgroundOnExit,
+        "STRICT" => Setting::Strict,
+        "LENIENT_SHELL" => Setting::LenientShell,
+        "FORMAT" => Setting::Format,
+        "PROMPT_VARIANT" => Setting::PromptVariant,
+        "SUMMARY_PROMPT" => Setting::SummaryPrompt,
+        "REDACT" => Setting::Redact,
+        "RESULT_JSON" => Setting::ResultJson,
+        "EVERY" => Setting::Every,
+        "JITTER" => Setting::Jitter,
+        "SESSION_DIR" => Setting::SessionDir,
+        "KEEP_SESSION" => Setting::KeepSession,
+        "SESSION_TTL" => Setting::SessionTtl,
+        _ => return None,
+    })
+}
+
+/// Map `--<name>` flags to settings.
+fn flag_setting(name: &str) -> Option<Setting> {
+    Some(match name {
+        "config" => Setting::ConfigFile,
+        "backend" => Setting::Backend,
+        "api-key" => Setting::ApiKey,
+        "cache-dir" => Setting::CacheDir,
+        "shell" => Setting::Shell,
+        "shell-allowlist" => Setting::ShellAllowlist,
+        "file-write" => Setting::FileWrite,
+        "file-access" => Setting::FileAccess,
+        "file-roots" => Setting::FileRoots,
+        "notify" => Setting::Notify,
+        "eval-code" => Setting::EvalCode,
+        "max-think-calls" => Setting::MaxThinkCalls,
+        "max-loop-iterations" => Setting::MaxLoopIterations,
+        "max-llm-calls" => Setting::MaxLlmCalls,
+        "max-total-tokens" => Setting::MaxTotalTokens,
+        "max-cost-usd" => Setting::MaxCostUsd,
+        "think-timeout-secs" => Setting::ThinkTimeoutSecs,
+        "approval-timeout-secs" => Setting::ApprovalTimeoutSecs,
+        "spill-threshold-bytes" => Setting::SpillThresholdBytes,
+        "max-example-tokens" => Setting::MaxExampleTokens,
+        "max-context-tokens" => Setting::MaxContextTokens,
+        "models" => Setting::Models,
+        "failover-on" => Setting::FailoverOn,
+        "agent" => Setting::Agent,
+        "think-cache" => Setting::ThinkCache,
+        "telemetry" => Setting::Telemetry,
+        "background-on-exit" => Setting::BackgroundOnExit,
+        "strict" => Setting::Strict,
+        "lenient-shell" => Setting::LenientShell,
+        "format" => Setting::Format,
+        "prompt-variant" => Setting::PromptVariant,
+        "summary-prompt" => Setting::SummaryPrompt,
+        "redact" => Setting::Redact,
+        "result-json" => Setting::ResultJson,
+        "every" => Setting::Every,
+        "jitter" => Setting::Jitter,
+        "session-dir" => Setting::SessionDir,
+        "keep-session" => Setting::KeepSession,
+        "session-ttl" => Setting::SessionTtl,
+        _ => return None,
+    })
+}
+
+fn json_str<'a>(value: &'a serde_json::Value, origin: &str) -> Result<&'a str, ConfigError> {
+    value
+        .as_str()
+        .ok_or_else(|| ConfigError::new(origin, "expected a string"))
+}
+
+fn json_str_array(value: &serde_json::Value, origin: &str) -> Result<Vec<String>, ConfigError> {
+    value
+        .as_array()
+        .and_then(|items| items.iter().map(|item| item.as_str().map(str::to_string)).collect())
+        .ok_or_else(|| ConfigError::new(origin, "expected an array of strings"))
+}
+
+/// Split a comma-separated env var or flag value, dropping empty items.
+fn comma_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Asking first only makes sense for shell commands, which name exactly
+/// what will run; reject it for other capabilities rather than silently
+/// denying.
+fn without_ask_first(permission: Permission, origin: &str) -> Result<Permission, ConfigError> {
+    match permission {
+        Permission::AskFirst => Err(ConfigError::new(origin, "ask-first is only supported for shell commands and eval_code")),
+        permission => Ok(permission),
+    }
+}
+
+fn json_object<'a>(
+    value: &'a serde_json::Value,
+    origin: &str,
+) -> Result<&'a serde_json::Map<String, serde_json::Value>, ConfigError> {
+    value
+        .as_object()
+        .ok_or_else(|| ConfigError::new(origin, "expected an object"))
+}
+
+/// A `redact` list: built-in rule names, `{ name, pattern }` objects, and
+/// `{ path }` objects. Patterns are compiled here so mistakes are reported
+/// with the file they came from.
+fn json_redaction_rules(value: &serde_json::Value, origin: &str) -> Result<Vec<RedactionRule>, ConfigError> {
+    let items = value.as_array().ok_or_else(|| ConfigError::new(origin, "expected an array"))?;
+    let mut rules = Vec::new();
+    for item in items {
+        let rule = match item {
+            serde_json::Value::String(name) => RedactionRule::preset(name).map_err(|e| ConfigError::new(origin, e))?,
+            serde_json::Value::Object(fields) => match (fields.get("name"), fields.get("pattern"), fields.get("path")) {
+                (Some(name), Some(pattern), None) => RedactionRule::Pattern {
+                    name: json_str(name, origin)?.to_string(),
+                    pattern: json_str(pattern, origin)?.to_string(),
+                },
+                (None, None, Some(path)) => RedactionRule::Path(json_str(path, origin)?.to_string()),
+                _ => return Err(ConfigError::new(origin, "expected { name, pattern } or { path }")),
+            },
+            _ => return Err(ConfigError::new(origin, "expected a rule name or object")),
+        };
+        rules.push(rule);
+    }
+    Redactor::new(&rules).map_err(|e| ConfigError::new(origin, e))?;
+    Ok(rules)
+}
+
+/// An `agents` table: names mapped to `{ command, args }` objects.
+fn json_agents(value: &serde_json::Value, origin: &str) -> Result<BTreeMap<String, AgentCommand>, ConfigError> {
+    let mut agents = BTreeMap::new();
+    for (name, value) in json_object(value, origin)? {
+        let origin = format!("{}.{}", origin, name);
+        let fields = json_object(value, &origin)?;
+        let command = fields.get("command").ok_or_else(|| ConfigError::new(&origin, "missing `command`"))?;
+        let command = json_str(command, &origin)?.to_string();
+        let args = match fields.get("args") {
+            Some(args) => json_str_array(args, &origin)?,
+            None => Vec::new(),
+        };
+        if let Some(key) = fields.keys().find(|key| !matches!(key.as_str(), "command" | "args")) {
+            return Err(ConfigError::new(format!("{}.{}", origin, key), "unknown agent setting"));
+        }
+        agents.insert(name.clone(), AgentCommand { command, args });
+    }
+    Ok(agents)
+}
+
+/// A duration given as seconds or a string like `"15m"`.
+fn json_duration(value: &serde_json::Value, origin: &str) -> Result<Duration, ConfigError> {
+    let value = match value {
+        serde_json::Value::Number(n) => Value::Number(n.as_f64().unwrap_or(-1.0)),
+        serde_json::Value::String(s) => Value::String(s.clone()),
+        _ => return Err(ConfigError::new(origin, "expected a duration like \"15m\"")),
+    };
+    parse_duration(&value).map_err(|e| ConfigError::new(origin, e))
+}
+
+fn parse_json<T>(value: &serde_json::Value, origin: &str) -> Result<T, ConfigError>
+where
+    T: FromStr<Err = String>,
+{
+    json_str(value, origin)?
+        .parse()
+        .map_err(|e| ConfigError::new(origin, e))
+}
+
+impl Config {
+    /// Apply a layer on top of the current settings.
+    pub fn merge(&mut self, layer: &ConfigLayer) {
+        if let Some(backend) = layer.backend {
+            self.backend = backend;
+        }
+        if let Some(api_key) = &layer.api_key {
+            self.api_key = Some(api_key.clone());
+        }
+        if let Some(cache_dir) = &layer.cache_dir {
+            self.cache_dir = Some(cache_dir.clone());
+        }
+        if let Some(shell) = layer.shell {
+            self.capabilities.shell = shell;
+        }
+        if let Some(allowlist) = &layer.shell_allowlist {
+            self.capabilities.shell_allowlist = allowlist.clone();
+        }
+        if let Some(file_write) = layer.file_write {
+            self.capabilities.file_write = file_write;
+        }
+        if let Some(file_access) = layer.file_access {
+            self.capabilities.file_access = file_access;
+        }
+        if let Some(file_roots) = &layer.file_roots {
+            self.capabilities.file_roots = file_roots.clone();
+        }
+        if let Some(notify) = layer.notify {
+            self.capabilities.notify = notify;
+        }
+        if let Some(eval_code) = layer.eval_code {
+            self.capabilities.eval_code = eval_code;
+        }
+        if let Some(n) = layer.max_think_calls {
+            self.limits.max_think_calls = Some(n);
+        }
+        if let Some(n) = layer.max_loop_iterations {
+            self.limits.max_loop_iterations = Some(n);
+        }
+        if let Some(n) = layer.max_llm_calls {
+            self.limits.max_llm_calls = Some(n);
+        }
+        if let Some(n) = layer.max_total_tokens {
+            self.limits.max_total_tokens = Some(n);
+        }
+        if let Some(dollars) = layer.max_cost_usd {
+            self.limits.max_cost_usd = Some(dollars);
+        }
+        if let Some(secs) = layer.think_timeout_secs {
+            self.limits.think_timeout_secs = Some(secs);
+        }
+        if let Some(secs) = layer.approval_timeout_secs {
+            self.limits.approval_timeout_secs = Some(secs);
+        }
+        if let Some(bytes) = layer.spill_threshold_bytes {
+            self.limits.spill_threshold_bytes = Some(bytes);
+        }
+        if let Some(tokens) = layer.max_example_tokens {
+            self.limits.max_example_tokens = Some(tokens);
+        }
+        if let Some(tokens) = layer.max_context_tokens {
+            self.limits.max_context_tokens = Some(tokens);
+        }
+        if let Some(models) = &layer.models {
+            self.models.models = models.clone();
+        }
+        if let Some(classes) = &layer.failover_on {
+            self.models.failover_on = classes.clone();
+        }
+        if let Some(agent) = &layer.agent {
+            self.models.agent = Some(agent.clone());
+        }
+        if let Some(agents) = &layer.agents {
+            // A later layer adds agents or replaces them by name
+            self.agents.extend(agents.iter().map(|(name, agent)| (name.clone(), agent.clone())));
+        }
+        if let Some(scope) = layer.think_cache {
+            self.think_cache = scope;
+        }
+        if let Some(telemetry) = &layer.telemetry {
+            self.telemetry = telemetry.clone();
+        }
+        if let Some(policy) = layer.background_on_exit {
+            self.background_on_exit = policy;
+        }
+        if let Some(strict) = layer.strict {
+            self.strict = strict;
+        }
+        if let Some(lenient) = layer.lenient_shell {
+            self.lenient_shell = lenient;
+        }
+        if let Some(format) = layer.format {
+            self.format = format;
+        }
+        if let Some(policy) = &layer.prompt_variant {
+            self.prompt_variant = policy.clone();
+        }
+        if let Some(prompt) = &layer.summary_prompt {
+            self.summary_prompt = Some(prompt.clone());
+        }
+        if let Some(rules) = &layer.redact {
+            for rule in rules {
+                if !self.redact.contains(rule) {
+                    self.redact.push(rule.clone());
+                }
+            }
+        }
+        if let Some(path) = &layer.result_json {
+            self.result_json = Some(path.clone());
+        }
+        if let Some(every) = layer.every {
+            self.every = Some(every);
+        }
+        if let Some(jitter) = layer.jitter {
+            self.jitter = jitter;
+        }
+        if let Some(dir) = &layer.session_dir {
+            self.session_dir = Some(dir.clone());
+        }
+        if let Some(keep) = layer.keep_session {
+            self.keep_session = keep;
+        }
+        if let Some(ttl) = layer.session_ttl {
+            self.session_ttl = Some(ttl);
+        }
+    }
+
+    /// The schedule to rerun the program on, if `every` is set.
+    pub fn schedule(&self) -> Option<Schedule> {
+        self.every.map(|every| Schedule::every(every).with_jitter(self.jitter))
+    }
+
+    /// The directory session directories are created in.
+    pub fn session_root(&self) -> PathBuf {
+        self.session_dir.clone().unwrap_or_else(|| std::env::temp_dir().join("patchwork-sessions"))
+    }
+
+    /// Load settings for a host running in `working_dir`.
+    ///
+    /// `cli` holds the host's parsed command-line flags (see
+    /// `ConfigLayer::from_args`).
+    pub fn load(working_dir: &Path, cli: &ConfigLayer) -> Result<Config, ConfigError> {
+        let env = ConfigLayer::from_env()?;
+        Self::load_layers(working_dir, user_config_file(), &env, cli)
+    }
+
+    fn load_layers(
+        working_dir: &Path,
+        user_file: Option<PathBuf>,
+        env: &ConfigLayer,
+        cli: &ConfigLayer,
+    ) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+
+        // An explicit file replaces discovery; a missing explicit file is an error
+        let explicit = cli.config_file.as_ref().or(env.config_file.as_ref());
+        match explicit {
+            Some(path) => config.merge(&ConfigLayer::from_file(&working_dir.join(path))?),
+            None => {
+                let project_file = working_dir.join(PROJECT_CONFIG_FILE);
+                for path in user_file.iter().chain(Some(&project_file)) {
+                    if path.is_file() {
+                        config.merge(&ConfigLayer::from_file(path)?);
+                    }
+                }
+            }
+        }
+
+        config.merge(env);
+        config.merge(cli);
+        Ok(config)
+    }
+
+    /// The cache directory, falling back to the user cache directory.
+    pub fn cache_dir(&self) -> Option<PathBuf> {
+        self.cache_dir.clone().or_else(|| {
+            std::env::var_os("XDG_CACHE_HOME")
+                .map(PathBuf::from)
+                .or_else(|| home_dir().map(|home| home.join(".cache")))
+                .map(|dir| dir.join("patchwork"))
+        })
+    }
+}
+
+/// `$XDG_CONFIG_HOME/patchwork/config.json`, or `~/.config/patchwork/config.json`.
+fn user_config_file() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home_dir().map(|home| home.join(".config")))
+        .map(|dir| dir.join("patchwork").join("config.json"))
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_json_layer() {
+        let layer = ConfigLayer::from_json(
+            r#"{"backend": "offline", "capabilities": {"shell": "deny"}, "limits": {"max_think_calls": 3}}"#,
+            "test.json",
+        )
+        .unwrap();
+        assert_eq!(layer.backend, Some(Backend::Offline));
+        assert_eq!(layer.shell, Some(Permission::Deny));
+        assert_eq!(layer.file_write, None);
+        assert_eq!(layer.max_think_calls, Some(3));
+    }
+
+    #[test]
+    fn test_json_errors_name_the_setting() {
+        let err = ConfigLayer::from_json(r#"{"backend": "gpt"}"#, "test.json").unwrap_err();
+        assert_eq!(err.origin, "test.json (backend)");
+        assert!(err.message.contains("unknown backend"), "{}", err);
+
+        let err = ConfigLayer::from_json(r#"{"colour": true}"#, "test.json").unwrap_err();
+        assert_eq!(err.to_string(), "test.json (colour): unknown setting");
+    }
+
+    #[test]
+    fn test_env_layer_ignores_other_variables() {
+        let layer = ConfigLayer::from_vars(vars(&[
+            ("PATCHWORK_BACKEND", "offline"),
+            ("PATCHWORK_UNRELATED", "x"),
+            ("HOME", "/home/me"),
+        ]))
+        .unwrap();
+        assert_eq!(layer.backend, Some(Backend::Offline));
+        assert_eq!(layer, ConfigLayer { backend: Some(Backend::Offline), ..Default::default() });
+
+        let err = ConfigLayer::from_vars(vars(&[("PATCHWORK_MAX_THINK_CALLS", "many")])).unwrap_err();
+        assert_eq!(err.origin, "PATCHWORK_MAX_THINK_CALLS");
+    }
+
+    #[test]
+    fn test_args_layer_returns_other_arguments() {
+        let (layer, rest) = ConfigLayer::from_args(args(&[
+            "--shell=deny", "script.pw", "--backend", "offline", "--verbose",
+        ]))
+        .unwrap();
+        assert_eq!(layer.shell, Some(Permission::Deny));
+        assert_eq!(layer.backend, Some(Backend::Offline));
+        assert_eq!(rest, args(&["script.pw", "--verbose"]));
+
+        let err = ConfigLayer::from_args(args(&["--backend"])).unwrap_err();
+        assert_eq!(err.to_string(), "--backend: missing value");
+    }
+
+    #[test]
+    fn test_shell_approval_settings() {
+        let layer = ConfigLayer::from_json(
+            r#"{"capabilities": {"shell": "ask-first", "shell_allowlist": ["ls", "git"]}}"#,
+            "test.json",
+        )
+        .unwrap();
+        assert_eq!(layer.shell, Some(Permission::AskFirst));
+        assert_eq!(layer.shell_allowlist, Some(args(&["ls", "git"])));
+
+        let layer = ConfigLayer::from_vars(vars(&[("PATCHWORK_SHELL_ALLOWLIST", "ls, git,")])).unwrap();
+        assert_eq!(layer.shell_allowlist, Some(args(&["ls", "git"])));
+
+        let err = ConfigLayer::from_args(args(&["--file-write", "ask-first"])).unwrap_err();
+        assert_eq!(err.to_string(), "--file-write: ask-first is only supported for shell commands and eval_code");
+    }
+
+    #[test]
+    fn test_file_access_settings() {
+        let layer = ConfigLayer::from_json(
+            r#"{"capabilities": {"file_access": "unrestricted", "file_roots": ["/data"]}}"#,
+            "test.json",
+        )
+        .unwrap();
+        assert_eq!(layer.file_access, Some(FileAccess::Unrestricted));
+        assert_eq!(layer.file_roots, Some(vec![PathBuf::from("/data")]));
+
+        let (layer, _) = ConfigLayer::from_args(args(&["--file-roots", "/a:/b"])).unwrap();
+        assert_eq!(layer.file_roots, Some(vec![PathBuf::from("/a"), PathBuf::from("/b")]));
+
+        let mut config = Config::default();
+        assert_eq!(config.capabilities.file_access, FileAccess::Confined);
+        config.merge(&layer);
+        assert_eq!(config.capabilities.file_roots.len(), 2);
+    }
+
+    #[test]
+    fn test_budget_settings() {
+        let layer = ConfigLayer::from_json(
+            r#"{"limits": {"max_llm_calls": 10, "max_total_tokens": 50000, "max_cost_usd": 1.5}}"#,
+            "test.json",
+        )
+        .unwrap();
+        assert_eq!(layer.max_llm_calls, Some(10));
+        assert_eq!(layer.max_total_tokens, Some(50000));
+        assert_eq!(layer.max_cost_usd, Some(1.5));
+
+        let err = ConfigLayer::from_json(r#"{"limits": {"max_cost_usd": -1}}"#, "test.json").unwrap_err();
+        assert_eq!(err.origin, "test.json (limits.max_cost_usd)");
+
+        let env = ConfigLayer::from_vars(vars(&[("PATCHWORK_MAX_COST_USD", "0.25")])).unwrap();
+        let mut config = Config::default();
+        config.merge(&layer);
+        config.merge(&env);
+        assert_eq!(config.limits.max_llm_calls, Some(10));
+        assert_eq!(config.limits.max_cost_usd, Some(0.25));
+    }
+
+    #[test]
+    fn test_context_settings() {
+        let layer = ConfigLayer::from_json(
+            r#"{"summary_prompt": "Keep it short.", "limits": {"max_context_tokens": 8000}}"#,
+            "test.json",
+        )
+        .unwrap();
+        let (args_layer, _) = ConfigLayer::from_args(args(&["--max-context-tokens", "4000"])).unwrap();
+        let mut config = Config::default();
+        config.merge(&layer);
+        assert_eq!(config.summary_prompt.as_deref(), Some("Keep it short."));
+        assert_eq!(config.limits.max_context_tokens, Some(8000));
+        config.merge(&args_layer);
+        assert_eq!(config.limits.max_context_tokens, Some(4000));
+    }
+
+    #[test]
+    fn test_redact_settings() {
+        let user = ConfigLayer::from_json(r#"{"redact": ["aws-keys", {"path": "deploy.token"}]}"#, "user.json").unwrap();
+        let project = ConfigLayer::from_json(
+            r#"{"redact": [{"name": "ticket", "pattern": "TICKET-[0-9]+"}, "aws-keys"]}"#,
+            "project.json",
+        )
+        .unwrap();
+        let mut config = Config::default();
+        config.merge(&user);
+        config.merge(&project);
+        assert_eq!(config.redact.len(), 3);
+        assert_eq!(config.redact[1], RedactionRule::Path("deploy.token".to_string()));
+
+        let err = ConfigLayer::from_json(r#"{"redact": [{"name": "bad", "pattern": "("}]}"#, "test.json").unwrap_err();
+        assert_eq!(err.origin, "test.json (redact)");
+        assert!(ConfigLayer::from_json(r#"{"redact": ["phone-numbers"]}"#, "test.json").is_err());
+
+        let env = ConfigLayer::from_vars(vars(&[("PATCHWORK_REDACT", "emails, bearer-tokens")])).unwrap();
+        assert_eq!(env.redact.map(|rules| rules.len()), Some(2));
+    }
+
+    #[test]
+    fn test_model_chain_settings() {
+        let layer = ConfigLayer::from_json(
+            r#"{"models": ["big", "small"], "failover_on": ["rate_limit"], "limits": {"think_timeout_secs": 30, "approval_timeout_secs": 300}}"#,
+            "test.json",
+        )
+        .unwrap();
+        let mut config = Config::default();
+        assert_eq!(config.models.failover_on.len(), 3);
+        config.merge(&layer);
+        assert_eq!(config.models.models, vec!["big", "small"]);
+        assert_eq!(config.models.failover_on, vec![FailureClass::RateLimit]);
+        assert_eq!(config.limits.think_timeout_secs, Some(30));
+        assert_eq!(config.limits.approval_timeout_secs, Some(300));
+
+        let env = ConfigLayer::from_vars(vars(&[("PATCHWORK_SPILL_THRESHOLD_BYTES", "1048576")])).unwrap();
+        config.merge(&env);
+        assert_eq!(config.limits.spill_threshold_bytes, Some(1048576));
+
+        let (layer, _) = ConfigLayer::from_args(args(&["--failover-on", "timeout, error"])).unwrap();
+        assert_eq!(layer.failover_on, Some(vec![FailureClass::Timeout, FailureClass::Error]));
+
+        let err = ConfigLayer::from_json(r#"{"failover_on": ["outage"]}"#, "test.json").unwrap_err();
+        assert!(err.message.contains("unknown failure class"), "{}", err);
+    }
+
+    #[test]
+    fn test_telemetry_setting() {
+        let layer = ConfigLayer::from_json(r#"{"telemetry": "otlp:http://localhost:4318"}"#, "test.json").unwrap();
+        let mut config = Config::default();
+        assert_eq!(config.telemetry, Telemetry::Off);
+        config.merge(&layer);
+        assert_eq!(config.telemetry, Telemetry::Otlp("http://localhost:4318".to_string()));
+
+        let (layer, _) = ConfigLayer::from_args(args(&["--telemetry", "json:/tmp/events.jsonl"])).unwrap();
+        config.merge(&layer);
+        assert_eq!(config.telemetry, Telemetry::JsonFile(PathBuf::from("/tmp/events.jsonl")));
+        assert_eq!(config.telemetry.to_string(), "json:/tmp/events.jsonl");
+
+        let err = ConfigLayer::from_json(r#"{"telemetry": "statsd"}"#, "test.json").unwrap_err();
+        assert!(err.message.contains("unknown telemetry sink"), "{}", err);
+    }
+
+    #[test]
+    fn test_background_on_exit_setting() {
+        let layer = ConfigLayer::from_json(r#"{"background_on_exit": "wait"}"#, "test.json").unwrap();
+        let mut config = Config::default();
+        assert_eq!(config.background_on_exit, BackgroundPolicy::Kill);
+        config.merge(&layer);
+        assert_eq!(config.background_on_exit, BackgroundPolicy::Wait);
+
+        let (layer, _) = ConfigLayer::from_args(args(&["--background-on-exit", "kill"])).unwrap();
+        config.merge(&layer);
+        assert_eq!(config.background_on_exit, BackgroundPolicy::Kill);
+
+        let err = ConfigLayer::from_json(r#"{"background_on_exit": "detach"}"#, "test.json").unwrap_err();
+        assert!(err.message.contains("unknown background policy"), "{}", err);
+    }
+
+    #[test]
+    fn test_agent_routing_settings() {
+        let user = ConfigLayer::from_json(
+            r#"{"agents": {"claude": {"command": "claude-code-acp"}, "local": {"command": "ollama-acp"}}}"#,
+            "user.json",
+        )
+        .unwrap();
+        let project = ConfigLayer::from_json(
+            r#"{"agent": "local", "agents": {"local": {"command": "ollama-acp", "args": ["--model", "llama3"]}}}"#,
+            "patchwork.json",
+        )
+        .unwrap();
+        let mut config = Config::default();
+        config.merge(&user);
+        config.merge(&project);
+        assert_eq!(config.models.agent.as_deref(), Some("local"));
+        assert_eq!(config.agents.len(), 2);
+        assert_eq!(config.agents["local"].args, vec!["--model", "llama3"]);
+
+        let (layer, _) = ConfigLayer::from_args(args(&["--agent", "claude"])).unwrap();
+        config.merge(&layer);
+        assert_eq!(config.models.agent.as_deref(), Some("claude"));
+
+        let err = ConfigLayer::from_json(r#"{"agents": {"local": {"cmd": "ollama-acp"}}}"#, "test.json").unwrap_err();
+        assert!(err.message.contains("missing `command`"), "{}", err);
+    }
+
+    #[test]
+    fn test_strict_setting() {
+        let layer = ConfigLayer::from_json(r#"{"strict": true}"#, "test.json").unwrap();
+        let (args_layer, _) = ConfigLayer::from_args(args(&["--strict=false"])).unwrap();
+        let mut config = Config::default();
+        assert!(!config.strict);
+        config.merge(&layer);
+        assert!(config.strict);
+        config.merge(&args_layer);
+        assert!(!config.strict);
+
+        assert!(ConfigLayer::from_json(r#"{"strict": "yes"}"#, "test.json").is_err());
+        assert!(ConfigLayer::from_vars(vars(&[("PATCHWORK_STRICT", "maybe")])).is_err());
+    }
+
+    #[test]
+    fn test_lenient_shell_setting() {
+        let layer = ConfigLayer::from_json(r#"{"lenient_shell": true}"#, "test.json").unwrap();
+        let env_layer = ConfigLayer::from_vars(vars(&[("PATCHWORK_LENIENT_SHELL", "0")])).unwrap();
+        let mut config = Config::default();
+        assert!(!config.lenient_shell);
+        config.merge(&layer);
+        assert!(config.lenient_shell);
+        config.merge(&env_layer);
+        assert!(!config.lenient_shell);
+
+        assert!(ConfigLayer::from_args(args(&["--lenient-shell=sometimes"])).is_err());
+    }
+
+    #[test]
+    fn test_format_setting() {
+        let layer = ConfigLayer::from_json(r#"{"format": "json"}"#, "test.json").unwrap();
+        let (args_layer, _) = ConfigLayer::from_args(args(&["--format", "plain"])).unwrap();
+        let mut config = Config::default();
+        assert_eq!(config.format, OutputFormat::Pretty);
+        config.merge(&layer);
+        assert_eq!(config.format, OutputFormat::Json);
+        config.merge(&args_layer);
+        assert_eq!(config.format, OutputFormat::Plain);
+
+        let err = ConfigLayer::from_vars(vars(&[("PATCHWORK_FORMAT", "yaml")])).unwrap_err();
+        assert_eq!(err.origin, "PATCHWORK_FORMAT");
+
+        let (layer, rest) = ConfigLayer::from_args(args(&["--result-json=out.json", "main.pw"])).unwrap();
+        config.merge(&layer);
+        assert_eq!(config.result_json, Some(PathBuf::from("out.json")));
+        assert_eq!(rest, vec!["main.pw".to_string()]);
+    }
+
+    #[test]
+    fn test_prompt_variant_setting() {
+        let layer = ConfigLayer::from_json(r#"{"prompt_variant": "split:concise=90, detailed=10"}"#, "test.json").unwrap();
+        let mut config = Config::default();
+        assert_eq!(config.prompt_variant, VariantPolicy::First);
+        config.merge(&layer);
+        assert_eq!(
+            config.prompt_variant,
+            VariantPolicy::Split(vec![("concise".to_string(), 90), ("detailed".to_string(), 10)])
+        );
+        assert_eq!(config.prompt_variant.to_string(), "split:concise=90,detailed=10");
+
+        let env = ConfigLayer::from_vars(vars(&[("PATCHWORK_PROMPT_VARIANT", "pin:detailed")])).unwrap();
+        config.merge(&env);
+        assert_eq!(config.prompt_variant, VariantPolicy::Pin("detailed".to_string()));
+        let (cli, _) = ConfigLayer::from_args(args(&["--prompt-variant", "env:TRIAGE_VARIANT"])).unwrap();
+        config.merge(&cli);
+        assert_eq!(config.prompt_variant, VariantPolicy::Env("TRIAGE_VARIANT".to_string()));
+
+        assert!("pin:".parse::<VariantPolicy>().is_err());
+        assert!("split:concise".parse::<VariantPolicy>().is_err());
+        assert!("random".parse::<VariantPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_schedule_settings() {
+        let layer = ConfigLayer::from_json(r#"{"schedule": {"jitter": "30s"}}"#, "test.json").unwrap();
+        let (args_layer, _) = ConfigLayer::from_args(args(&["--every", "15m"])).unwrap();
+        let mut config = Config::default();
+        assert_eq!(config.schedule(), None);
+        config.merge(&layer);
+        assert_eq!(config.schedule(), None);
+        config.merge(&args_layer);
+        assert_eq!(
+            config.schedule(),
+            Some(Schedule::every(Duration::from_secs(900)).with_jitter(Duration::from_secs(30)))
+        );
+
+        let err = ConfigLayer::from_json(r#"{"schedule": {"every": "soon"}}"#, "test.json").unwrap_err();
+        assert_eq!(err.origin, "test.json (schedule.every)");
+        assert!(ConfigLayer::from_vars(vars(&[("PATCHWORK_EVERY", "5 fortnights")])).is_err());
+    }
+
+    #[test]
+    fn test_session_settings() {
+        let layer = ConfigLayer::from_json(r#"{"session": {"dir": "/tmp/runs", "ttl": "24h"}}"#, "test.json").unwrap();
+        let env = ConfigLayer::from_vars(vars(&[("PATCHWORK_KEEP_SESSION", "always")])).unwrap();
+        let mut config = Config::default();
+        assert_eq!(config.keep_session, KeepPolicy::OnFailure);
+        assert_eq!(config.session_root(), std::env::temp_dir().join("patchwork-sessions"));
+        config.merge(&layer);
+        config.merge(&env);
+        assert_eq!(config.session_root(), PathBuf::from("/tmp/runs"));
+        assert_eq!(config.session_ttl, Some(Duration::from_secs(86400)));
+        assert_eq!(config.keep_session, KeepPolicy::Always);
+
+        let err = ConfigLayer::from_args(args(&["--keep-session", "sometimes"])).unwrap_err();
+        assert_eq!(err.origin, "--keep-session");
+        let err = ConfigLayer::from_json(r#"{"session": {"keep_for": "1h"}}"#, "test.json").unwrap_err();
+        assert_eq!(err.origin, "test.json (session.keep_for)");
+    }
+
+    #[test]
+    fn test_restrict_capabilities() {
+        let parent = CapabilityPolicy {
+            shell: Permission::Deny,
+            shell_allowlist: vec!["ls".to_string()],
+            file_roots: vec![PathBuf::from("/data")],
+            ..Default::default()
+        };
+        let requested = CapabilityPolicy {
+            shell_allowlist: vec!["ls".to_string(), "rm".to_string()],
+            file_write: Permission::Deny,
+            file_access: FileAccess::Unrestricted,
+            file_roots: vec![PathBuf::from("/data/shared"), PathBuf::from("/etc")],
+            ..Default::default()
+        };
+        let policy = parent.restrict(&requested, Path::new("/work"));
+        assert_eq!(policy.shell, Permission::Deny);
+        assert_eq!(policy.shell_allowlist, vec!["ls"]);
+        assert_eq!(policy.file_write, Permission::Deny);
+        assert_eq!(policy.file_access, FileAccess::Confined);
+        assert_eq!(policy.file_roots, vec![PathBuf::from("/data/shared")]);
+
+        let limits = Limits { max_llm_calls: Some(10), max_cost_usd: Some(1.0), ..Default::default() };
+        let tighter = limits.tighter(&Limits { max_llm_calls: Some(3), max_think_calls: Some(5), ..Default::default() });
+        assert_eq!(tighter.max_llm_calls, Some(3));
+        assert_eq!(tighter.max_think_calls, Some(5));
+        assert_eq!(tighter.max_cost_usd, Some(1.0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_restrict_resolves_file_roots() {
+        let dir = TempDir::new().unwrap();
+        let granted = dir.path().join("granted");
+        std::fs::create_dir_all(granted.join("sub")).unwrap();
+        std::fs::create_dir(dir.path().join("outside")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("outside"), granted.join("link")).unwrap();
+
+        let parent = CapabilityPolicy { file_roots: vec![granted.clone()], ..Default::default() };
+        let requested = CapabilityPolicy {
+            file_roots: vec![
+                granted.join("../outside"),
+                granted.join("link"),
+                PathBuf::from("granted/sub"),
+                granted.join("sub/.."),
+            ],
+            ..Default::default()
+        };
+        let policy = parent.restrict(&requested, dir.path());
+        let granted = granted.canonicalize().unwrap();
+        assert_eq!(policy.file_roots, vec![granted.join("sub"), granted]);
+    }
+
+    #[test]
+    fn test_layer_precedence() {
+        let dir = TempDir::new().unwrap();
+        let user_file = dir.path().join("user.json");
+        std::fs::write(&user_file, r#"{"backend": "anthropic", "api_key": "user-key", "limits": {"max_loop_iterations": 5}}"#).unwrap();
+        std::fs::write(dir.path().join(PROJECT_CONFIG_FILE), r#"{"api_key": "project-key", "capabilities": {"shell": "deny"}}"#).unwrap();
+
+        let env = ConfigLayer::from_vars(vars(&[("PATCHWORK_SHELL", "allow"), ("PATCHWORK_BACKEND", "acp")])).unwrap();
+        let (cli, _) = ConfigLayer::from_args(args(&["--backend", "offline"])).unwrap();
+
+        let config = Config::load_layers(dir.path(), Some(user_file), &env, &cli).unwrap();
+        // user file < project file
+        assert_eq!(config.api_key.as_deref(), Some("project-key"));
+        assert_eq!(config.limits.max_loop_iterations, Some(5));
+        // project file < env
+        assert_eq!(config.capabilities.shell, Permission::Allow);
+        // env < cli
+        assert_eq!(config.backend, Backend::Offline);
+    }
+
+    #[test]
+    fn test_explicit_config_file_replaces_discovery() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(dir.path().join(PROJECT_CONFIG_FILE), r#"{"backend": "anthropic"}"#).unwrap();
+        std::fs::write(dir.path().join("ci.json"), r#"{"capabilities": {"file_write": "deny"}}"#).unwrap();
+
+        let (cli, _) = ConfigLayer::from_args(args(&["--config", "ci.json"])).unwrap();
+        let config = Config::load_layers(dir.path(), None, &ConfigLayer::default(), &cli).unwrap();
+        assert_eq!(config.backend, Backend::Acp);
+        assert_eq!(config.capabilities.file_write, Permission::Deny);
+
+        let (cli, _) = ConfigLayer::from_args(args(&["--config", "missing.json"])).unwrap();
+        assert!(Config::load_layers(dir.path(), None, &ConfigLayer::default(), &cli).is_err());
+    }
+}
@@ -0,0 +1,422 @@
+//! Statement coverage.
+//!
+//! With coverage enabled (`Interpreter::enable_coverage`), every program the
+//! interpreter runs has its statements registered up front and counted as
+//! they execute. The resulting `CoverageReport` is per line, since that is
+//! what lcov and most editors understand; a line's count is how many times
+//! statements starting on it ran. Reports from separate interpreters, such
+//! as one per test case, can be merged before writing them out:
+//!
+//! ```ignore
+//! let mut report = CoverageReport::default();
+//! for case in cases {
+//!     let mut interp = Interpreter::new();
+//!     interp.enable_coverage();
+//!     interp.set_source_name(case.path());
+//!     interp.eval(&case.source())?;
+//!     report.merge(interp.coverage().unwrap());
+//! }
+//! fs::write("lcov.info", report.to_lcov())?;
+//! ```
+//!
+//! Statements with no source text of their own, such as a bare `break`,
+//! are not tracked.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use patchwork_parser::ast::*;
+
+/// Line coverage of one source file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileCoverage {
+    pub name: String,
+    source: String,
+    /// 1-based line -> times a statement starting there ran.
+    lines: BTreeMap<usize, u64>,
+}
+
+impl FileCoverage {
+    fn new(name: &str, source: &str) -> Self {
+        Self { name: name.to_string(), source: source.to_string(), lines: BTreeMap::new() }
+    }
+
+    /// Each line with a statement on it and how often it ran, in order.
+    pub fn lines(&self) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.lines.iter().map(|(&line, &hits)| (line, hits))
+    }
+
+    /// Lines with statements that never ran.
+    pub fn missed(&self) -> Vec<usize> {
+        self.lines().filter(|&(_, hits)| hits == 0).map(|(line, _)| line).collect()
+    }
+
+    /// How many lines with statements ran at least once.
+    pub fn covered(&self) -> usize {
+        self.lines.values().filter(|&&hits| hits > 0).count()
+    }
+
+    /// How many lines have statements.
+    pub fn total(&self) -> usize {
+        self.lines.len()
+    }
+}
+
+/// Line coverage of every program run with coverage enabled.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CoverageReport {
+    files: Vec<FileCoverage>,
+}
+
+impl CoverageReport {
+    pub fn files(&self) -> &[FileCoverage] {
+        &self.files
+    }
+
+    pub fn file(&self, name: &str) -> Option<&FileCoverage> {
+        self.files.iter().find(|file| file.name == name)
+    }
+
+    /// Add the counts of `other` to this report. Files with the same name
+    /// are assumed to have the same source.
+    pub fn merge(&mut self, other: &CoverageReport) {
+        for file in &other.files {
+            let index = self.file_index(&file.name, &file.source);
+            for (line, hits) in file.lines() {
+                *self.files[index].lines.entry(line).or_insert(0) += hits;
+            }
+        }
+    }
+
+    fn file_index(&mut self, name: &str, source: &str) -> usize {
+        match self.files.iter().position(|file| file.name == name) {
+            Some(index) => index,
+            None => {
+                self.files.push(FileCoverage::new(name, source));
+                self.files.len() - 1
+            }
+        }
+    }
+
+    /// The report in lcov's tracefile format, for `genhtml` and CI services.
+    pub fn to_lcov(&self) -> String {
+        let mut out = String::new();
+        for file in &self.files {
+            let _ = writeln!(out, "TN:");
+            let _ = writeln!(out, "SF:{}", file.name);
+            for (line, hits) in file.lines() {
+                let _ = writeln!(out, "DA:{},{}", line, hits);
+            }
+            let _ = writeln!(out, "LF:{}", file.total());
+            let _ = writeln!(out, "LH:{}", file.covered());
+            let _ = writeln!(out, "end_of_record");
+        }
+        out
+    }
+
+    /// A standalone HTML page showing each file with its lines marked as
+    /// run or never run.
+    pub fn to_html(&self) -> String {
+        let mut out = String::from(concat!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Patchwork coverage</title>\n",
+            "<style>\n",
+            "body { font-family: sans-serif; }\n",
+            "pre { line-height: 1.4; }\n",
+            ".hit { background: #e6ffec; }\n",
+            ".miss { background: #ffebe9; }\n",
+            ".count { display: inline-block; width: 4em; color: #888; text-align: right; margin-right: 1em; }\n",
+            "</style>\n</head>\n<body>\n<h1>Patchwork coverage</h1>\n",
+        ));
+        for file in &self.files {
+            let _ = writeln!(
+                out,
+                "<h2>{}</h2>\n<p>{} of {} lines run</p>\n<pre>",
+                escape_html(&file.name),
+                file.covered(),
+                file.total()
+            );
+            for (index, text) in file.source.lines().enumerate() {
+                let (class, count) = match file.lines.get(&(index + 1)) {
+                    Some(0) => (" class=\"miss\"", "0".to_string()),
+                    Some(hits) => (" class=\"hit\"", hits.to_string()),
+                    None => ("", String::new()),
+                };
+                let _ = writeln!(
+                    out,
+                    "<span{}><span class=\"count\">{}</span>{}</span>",
+                    class,
+                    count,
+                    escape_html(text)
+                );
+            }
+            out.push_str("</pre>\n");
+        }
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Counts statements of the running program.
+#[derive(Debug, Default)]
+pub(crate) struct CoverageRecorder {
+    report: CoverageReport,
+    current: Option<Current>,
+}
+
+/// The program being counted.
+#[derive(Debug)]
+struct Current {
+    /// Address and length of the parsed source.
+    start: usize,
+    len: usize,
+    /// Byte offset where each line of the parsed source starts.
+    line_starts: Vec<usize>,
+    /// Index into `CoverageReport::files`.
+    file: usize,
+}
+
+impl CoverageRecorder {
+    pub(crate) fn report(&self) -> &CoverageReport {
+        &self.report
+    }
+
+    /// Start counting `program`, parsed from `source`. `display` is the
+    /// text to show in reports, which has the same lines as `source`.
+    pub(crate) fn begin(&mut self, name: &str, source: &str, display: &str, program: &Program) {
+        let file = self.report.file_index(name, display);
+        self.current = Some(Current {
+            start: source.as_ptr() as usize,
+            len: source.len(),
+            line_starts: std::iter::once(0).chain(source.match_indices('\n').map(|(i, _)| i + 1)).collect(),
+            file,
+        });
+
+        let mut statements = Vec::new();
+        for item in &program.items {
+            match item {
+                Item::Skill(SkillDecl { body, .. })
+                | Item::Worker(WorkerDecl { body, .. })
+                | Item::Function(FunctionDecl { body, .. }) => block_statements(body, &mut statements),
+                Item::Trait(decl) => {
+                    for method in &decl.methods {
+                        block_statements(&method.body, &mut statements);
+                    }
+                }
+                Item::Eval(decl) => {
+                    expr_statements(&decl.output, &mut statements);
+                    for check in &decl.checks {
+                        match check {
+                            EvalCheck::Contains(expr) | EvalCheck::Judge(expr) => expr_statements(expr, &mut statements),
+                            EvalCheck::Expect(_) => {}
+                        }
+                    }
+                }
+                Item::Import(_) | Item::Type(_) | Item::Var(_) => {}
+            }
+        }
+        for statement in statements {
+            if let Some(line) = self.line_of(statement) {
+                self.report.files[file].lines.entry(line).or_insert(0);
+            }
+        }
+    }
+
+    /// Stop counting; statements outside a program, such as REPL turns,
+    /// are ignored until the next `begin`.
+    pub(crate) fn end(&mut self) {
+        self.current = None;
+    }
+
+    /// Count one execution of `statement`.
+    pub(crate) fn hit(&mut self, statement: &Statement) {
+        if let (Some(line), Some(current)) = (self.line_of(statement), &self.current) {
+            *self.report.files[current.file].lines.entry(line).or_insert(0) += 1;
+        }
+    }
+
+    /// The 1-based line `statement` starts on, if it belongs to the
+    /// program being counted.
+    fn line_of(&self, statement: &Statement) -> Option<usize> {
+        let current = self.current.as_ref()?;
+        let offset = (statement_site(statement)?.as_ptr() as usize)
+            .checked_sub(current.start)
+            .filter(|&offset| offset < current.len)?;
+        Some(current.line_starts.partition_point(|&start| start <= offset))
+    }
+}
+
+/// Every statement in `block`, including those in nested blocks.
+fn block_statements<'a, 'input>(block: &'a Block<'input>, out: &mut Vec<&'a Statement<'input>>) {
+    for statement in &block.statements {
+        out.push(statement);
+        match statement {
+            Statement::VarDecl { init: Some(expr), .. }
+            | Statement::Expr(expr)
+            | Statement::Return(Some(expr)) => expr_statements(expr, out),
+            Statement::If { condition, then_block, else_block } => {
+                expr_statements(condition, out);
+                block_statements(then_block, out);
+                if let Some(else_block) = else_block {
+                    block_statements(else_block, out);
+                }
+            }
+            Statement::ForIn { iter: condition, body, .. } | Statement::While { condition, body } => {
+                expr_statements(condition, out);
+                block_statements(body, out);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Statements in blocks nested inside `expr`, such as `do { ... }`.
+fn expr_statements<'a, 'input>(expr: &'a Expr<'input>, out: &mut Vec<&'a Statement<'input>>) {
+    match expr {
+        Expr::Do(block) => block_statements(block, out),
+        Expr::Think(prompt) | Expr::Ask(prompt) | Expr::Approve(prompt) => prompt_statements(prompt, out),
+        Expr::Array(items) => items.iter().for_each(|item| expr_statements(item, out)),
+        Expr::Object(fields) => fields
+            .iter()
+            .filter_map(|field| field.value.as_ref())
+            .for_each(|value| expr_statements(value, out)),
+        Expr::Binary { left, right, .. }
+        | Expr::ShellPipe { left, right }
+        | Expr::ShellAnd { left, right }
+        | Expr::ShellOr { left, right }
+        | Expr::Index { object: left, index: right }
+        | Expr::ShellRedirect { command: left, target: right, .. } => {
+            expr_statements(left, out);
+            expr_statements(right, out);
+        }
+        Expr::Call { callee, args } => {
+            expr_statements(callee, out);
+            args.iter().for_each(|arg| expr_statements(arg, out));
+        }
+        Expr::Unary { operand: inner, .. }
+        | Expr::Member { object: inner, .. }
+        | Expr::PostIncrement(inner)
+        | Expr::PostDecrement(inner)
+        | Expr::Paren(inner)
+        | Expr::Await(inner)
+        | Expr::CommandSubst(inner)
+        | Expr::ShellBackground(inner) => expr_statements(inner, out),
+        _ => {}
+    }
+}
+
+/// Statements in blocks nested inside a prompt, including its variants.
+fn prompt_statements<'a, 'input>(prompt: &'a PromptBlock<'input>, out: &mut Vec<&'a Statement<'input>>) {
+    for item in &prompt.items {
+        match item {
+            PromptItem::Code(block) => block_statements(block, out),
+            PromptItem::Interpolation(expr) => expr_statements(expr, out),
+            PromptItem::Examples(fields) => fields
+                .iter()
+                .filter_map(|field| field.value.as_ref())
+                .for_each(|value| expr_statements(value, out)),
+            PromptItem::Variant { block, .. } => prompt_statements(block, out),
+            PromptItem::Text(_) => {}
+        }
+    }
+}
+
+/// The first piece of source text in a statement, which marks its line.
+fn statement_site<'input>(statement: &Statement<'input>) -> Option<&'input str> {
+    match statement {
+        Statement::VarDecl { pattern, init } => {
+            pattern.names().first().copied().or_else(|| init.as_ref().and_then(expr_site))
+        }
+        Statement::Expr(expr) | Statement::Return(Some(expr)) => expr_site(expr),
+        Statement::If { condition, .. } | Statement::While { condition, .. } => expr_site(condition),
+        Statement::ForIn { var, .. } => Some(*var),
+        Statement::Plan { steps } => steps.first().and_then(string_site),
+        Statement::Return(None) | Statement::Succeed | Statement::Break | Statement::TypeDecl { .. } => None,
+    }
+}
+
+fn expr_site<'input>(expr: &Expr<'input>) -> Option<&'input str> {
+    match expr {
+        Expr::Identifier(text) | Expr::Number(text) => Some(*text),
+        Expr::BareCommand { name, .. } => Some(*name),
+        Expr::String(lit) => string_site(lit),
+        Expr::Array(items) => items.iter().find_map(expr_site),
+        Expr::Object(fields) => fields.first().map(|field| field.key),
+        Expr::Binary { left, .. }
+        | Expr::ShellPipe { left, .. }
+        | Expr::ShellAnd { left, .. }
+        | Expr::ShellOr { left, .. }
+        | Expr::Call { callee: left, .. }
+        | Expr::Member { object: left, .. }
+        | Expr::Index { object: left, .. }
+        | Expr::ShellRedirect { command: left, .. }
+        | Expr::Unary { operand: left, .. }
+        | Expr::PostIncrement(left)
+        | Expr::PostDecrement(left)
+        | Expr::Paren(left)
+        | Expr::Await(left)
+        | Expr::CommandSubst(left)
+        | Expr::ShellBackground(left) => expr_site(left),
+        Expr::Think(prompt) | Expr::Ask(prompt) | Expr::Approve(prompt) => prompt.items.iter().find_map(|item| match item {
+            PromptItem::Text(text) => text.lines().map(str::trim).find(|line| !line.is_empty()),
+            PromptItem::Interpolation(expr) => expr_site(expr),
+            _ => None,
+        }),
+        Expr::Do(block) => block.statements.iter().find_map(statement_site),
+        Expr::True | Expr::False => None,
+    }
+}
+
+fn string_site<'input>(lit: &StringLiteral<'input>) -> Option<&'input str> {
+    lit.parts.iter().find_map(|part| match part {
+        StringPart::Text(text) => Some(*text),
+        StringPart::Interpolation(expr) => expr_site(expr),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(recorder: &mut CoverageRecorder, name: &str, source: &str, lines_run: &[usize]) {
+        let program = patchwork_parser::parse(source).unwrap();
+        recorder.begin(name, source, source, &program);
+        let mut statements = Vec::new();
+        for item in &program.items {
+            if let Item::Function(decl) = item {
+                block_statements(&decl.body, &mut statements);
+            }
+        }
+        for statement in statements {
+            if recorder.line_of(statement).is_some_and(|line| lines_run.contains(&line)) {
+                recorder.hit(statement);
+            }
+        }
+        recorder.end();
+    }
+
+    #[test]
+    fn test_lcov_and_merge() {
+        let source = "fun f(x) {\n  if x {\n    print(\"yes\")\n  } else {\n    print(\"no\")\n  }\n}\n";
+        let mut first = CoverageRecorder::default();
+        run(&mut first, "f.pw", source, &[2, 3]);
+        let file = first.report().file("f.pw").unwrap();
+        assert_eq!(file.lines().collect::<Vec<_>>(), vec![(2, 1), (3, 1), (5, 0)]);
+        assert_eq!(file.missed(), vec![5]);
+        assert_eq!(
+            first.report().to_lcov(),
+            "TN:\nSF:f.pw\nDA:2,1\nDA:3,1\nDA:5,0\nLF:3\nLH:2\nend_of_record\n"
+        );
+
+        let mut second = CoverageRecorder::default();
+        run(&mut second, "f.pw", source, &[2, 5]);
+        let mut report = first.report().clone();
+        report.merge(second.report());
+        let file = report.file("f.pw").unwrap();
+        assert_eq!(file.lines().collect::<Vec<_>>(), vec![(2, 2), (3, 1), (5, 1)]);
+        assert!(!report.to_html().contains("<span class=\"miss\">"));
+    }
+}
@@ -2,6 +2,8 @@
 
 use std::fmt;
 
+use patchwork_parser::diagnostics::{Diagnostic, Renderer};
+
 use crate::value::Value;
 
 /// Errors that can occur during interpretation.
@@ -14,6 +16,8 @@ pub enum Error {
     /// A Patchwork exception was thrown (via `throw` keyword).
     /// This propagates up the call stack using Rust's `?` operator.
     Exception(Value),
+    /// The run crossed one of its budget limits (LLM calls, tokens, or cost).
+    BudgetExceeded(String),
 }
 
 impl fmt::Display for Error {
@@ -21,9 +25,59 @@ impl fmt::Display for Error {
         match self {
             Error::Parse(msg) => write!(f, "Parse error: {}", msg),
             Error::Runtime(msg) => write!(f, "Runtime error: {}", msg),
-            Error::Exception(value) => write!(f, "Exception: {}", value.to_string_value()),
+            Error::Exception(value) => write!(f, "Exception: {}", exception_message(value)),
+            Error::BudgetExceeded(msg) => write!(f, "Budget exceeded: {}", msg),
         }
     }
 }
 
 impl std::error::Error for Error {}
+
+impl Error {
+    /// Convert this error into a diagnostic for rendering.
+    ///
+    /// Parse errors are already rendered against their source when they are
+    /// created, so their diagnostic carries the rendered text as a note.
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            Error::Parse(msg) => Diagnostic::error("Parse error").with_note(msg.clone()),
+            Error::Runtime(msg) => Diagnostic::error(msg.clone()),
+            Error::Exception(value) if is_shell_error(value) => {
+                Diagnostic::error(format!("Uncaught exception: {}", exception_message(value)))
+                    .with_help("handle the failure with `||`, or set `lenient_shell` and check `$?`")
+            }
+            Error::Exception(value) => {
+                Diagnostic::error(format!("Uncaught exception: {}", exception_message(value)))
+                    .with_help("catch the exception or check the condition that throws it")
+            }
+            Error::BudgetExceeded(msg) => Diagnostic::error(format!("Budget exceeded: {}", msg))
+                .with_help("raise the limit under `limits` in patchwork.json, or check `budget_remaining()` before calling the LLM"),
+        }
+    }
+
+    /// Render this error for display to a user.
+    pub fn render(&self, renderer: &Renderer) -> String {
+        match self {
+            // Already rendered with a source snippet
+            Error::Parse(msg) => msg.clone(),
+            _ => renderer.render(&self.to_diagnostic(), "<input>", ""),
+        }
+    }
+}
+
+/// How a thrown value reads in an error: an object's `message` field if it
+/// has one, such as a `ShellError`'s.
+fn exception_message(value: &Value) -> String {
+    match value {
+        Value::Object(fields) => match fields.get("message") {
+            Some(Value::String(message)) => message.clone(),
+            _ => value.to_string_value(),
+        },
+        _ => value.to_string_value(),
+    }
+}
+
+/// Is `value` the exception a failing shell command throws?
+pub(crate) fn is_shell_error(value: &Value) -> bool {
+    matches!(value, Value::Object(fields) if fields.get("type") == Some(&Value::String("ShellError".to_string())))
+}
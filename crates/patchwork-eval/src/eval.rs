@@ -8,16 +8,29 @@
 
 use std::collections::HashMap;
 use std::fs;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::io::Read;
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
+use indexmap::IndexMap;
 use patchwork_parser::ast::{
     Block, BinOp, CommandArg, Expr, ObjectPatternField, Pattern, Program,
-    RedirectOp, Statement, StringLiteral, StringPart, UnOp, PromptBlock, PromptItem,
+    RedirectOp, Statement, StringLiteral, StringPart, UnOp, PromptBlock, PromptItem, VarDeclItem,
 };
 
-use crate::agent::{AgentHandle, ThinkResponse};
-use crate::error::Error;
-use crate::runtime::{PlanEntry, PlanEntryStatus, PlanUpdate, Runtime};
+use crate::agent::{prompt_text, AgentHandle, Priority, PromptPart, ThinkOp, ThinkRequest, ThinkResponse};
+use crate::config::{BackgroundPolicy, CapabilityPolicy, Config, ConfigLayer, FailureClass, Permission};
+use crate::error::{is_shell_error, Error};
+use crate::interpreter::run_isolated;
+use crate::process::{process_id, BackgroundProcess, NextLine};
+use crate::runtime::{
+    display_command, CallMeta, PlanEntry, PlanEntryStatus, PlanUpdate, ProgressUpdate, Runtime, TranscriptEntry,
+};
+use crate::schema::Schema;
+use crate::telemetry::TelemetryEvent;
+use crate::timer;
 use crate::value::Value;
 
 /// Evaluate a complete program.
@@ -46,6 +59,8 @@ pub fn eval_block(
     let mut result = Value::Null;
 
     for stmt in &block.statements {
+        runtime.check_cancelled().map_err(Error::Runtime)?;
+        runtime.cover(stmt);
         result = eval_statement(stmt, runtime, agent)?;
     }
 
@@ -74,7 +89,7 @@ pub fn eval_statement(
         Statement::If { condition, then_block, else_block } => {
             let cond_value = eval_expr(condition, runtime, agent)?;
 
-            if cond_value.to_bool() {
+            if condition_value(&cond_value, runtime)? {
                 eval_block(then_block, runtime, agent)
             } else if let Some(else_blk) = else_block {
                 eval_block(else_blk, runtime, agent)
@@ -84,10 +99,13 @@ pub fn eval_statement(
         }
 
         Statement::ForIn { var, iter, body } => {
+            if let Some(id) = streamed_process(iter, runtime, agent) {
+                return eval_for_lines(var, id, body, runtime, agent);
+            }
             let iter_value = eval_expr(iter, runtime, agent)?;
 
             let items = match iter_value {
-                Value::Array(arr) => arr,
+                Value::Array(arr) | Value::Set(arr) | Value::Tuple(arr) => arr,
                 Value::String(s) => {
                     // Iterate over lines
                     s.lines().map(|line| Value::String(line.to_string())).collect()
@@ -122,7 +140,9 @@ pub fn eval_statement(
             }
 
             let mut result = Value::Null;
+            runtime.enter_loop();
             for (index, item) in items.into_iter().enumerate() {
+                runtime.set_loop_iteration(index as u64);
                 // Report: this item is now in_progress
                 if !item_strings.is_empty() {
                     let entries: Vec<PlanEntry> = item_strings.iter()
@@ -146,6 +166,7 @@ pub fn eval_statement(
                 result = eval_block(body, runtime, agent)?;
                 runtime.pop_scope();
             }
+            runtime.exit_loop();
 
             // Report final plan (all completed)
             if !item_strings.is_empty() {
@@ -163,15 +184,28 @@ pub fn eval_statement(
 
         Statement::While { condition, body } => {
             let mut result = Value::Null;
+            let mut iterations: u64 = 0;
+            runtime.enter_loop();
             loop {
                 let cond_value = eval_expr(condition, runtime, agent)?;
 
-                if !cond_value.to_bool() {
+                if !condition_value(&cond_value, runtime)? {
                     break;
                 }
 
+                iterations += 1;
+                if let Some(max) = runtime.limits().max_loop_iterations {
+                    if iterations > max {
+                        return Err(Error::Runtime(format!(
+                            "while loop exceeded the limit of {} iterations", max
+                        )));
+                    }
+                }
+
+                runtime.set_loop_iteration(iterations - 1);
                 result = eval_block(body, runtime, agent)?;
             }
+            runtime.exit_loop();
             Ok(result)
         }
 
@@ -192,13 +226,60 @@ pub fn eval_statement(
             Err(Error::Runtime("break outside of loop".to_string()))
         }
 
-        Statement::TypeDecl { .. } => {
-            // Type declarations are compile-time only
+        Statement::TypeDecl { name, type_expr } => {
+            // Declared types are also schemas for `validate`
+            runtime.define_schema(name, Schema::from_type_expr(type_expr));
+            Ok(Value::Null)
+        }
+
+        Statement::Plan { steps } => {
+            let steps = steps
+                .iter()
+                .map(|step| eval_string_literal(step, runtime, agent).map(|v| v.to_string_value()))
+                .collect::<Result<Vec<_>, Error>>()?;
+            runtime.start_plan(steps);
             Ok(Value::Null)
         }
     }
 }
 
+/// Load a file imported with `import data "path"`, defining `name` in the
+/// current scope. JSON files are parsed; markdown and text files are bound
+/// as strings.
+pub fn eval_data_import(name: &str, path: &str, runtime: &mut Runtime) -> Result<(), Error> {
+    let resolved = runtime.resolve_path(path).map_err(Error::Runtime)?;
+    let text = fs::read_to_string(&resolved)
+        .map_err(|e| Error::Runtime(format!("Failed to import {}: {}", resolved.display(), e)))?;
+    let value = match resolved.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => Value::from_json(&text)
+            .map_err(|e| Error::Runtime(format!("Failed to import {}: {}", path, e)))?,
+        Some("md" | "markdown" | "txt") => Value::String(text),
+        _ => {
+            return Err(Error::Runtime(format!(
+                "Cannot import {} as data (expected a .json, .md, or .txt file)", path
+            )))
+        }
+    };
+    runtime.define_var(name, value).map_err(Error::Runtime)
+}
+
+/// Initialize a module-level `var` or `const`, defining its names in the
+/// current scope.
+pub fn eval_module_var(
+    decl: &VarDeclItem,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<(), Error> {
+    let value = eval_expr(&decl.init, runtime, agent)?;
+    bind_pattern(&decl.pattern, value, runtime)?;
+    if decl.is_const {
+        for name in decl.pattern.names() {
+            runtime.mark_constant(name);
+        }
+    }
+    Ok(())
+}
+
 /// Bind a value to a pattern, defining variables in the runtime.
 fn bind_pattern(pattern: &Pattern, value: Value, runtime: &mut Runtime) -> Result<(), Error> {
     match pattern {
@@ -227,7 +308,7 @@ fn bind_pattern(pattern: &Pattern, value: Value, runtime: &mut Runtime) -> Resul
 
         Pattern::Array(patterns) => {
             let arr = match value {
-                Value::Array(a) => a,
+                Value::Array(a) | Value::Tuple(a) => a,
                 other => {
                     return Err(Error::Runtime(format!(
                         "Cannot destructure {} as array", type_name(&other)
@@ -260,10 +341,18 @@ pub fn eval_expr(
 ) -> Result<Value, Error> {
     match expr {
         Expr::Identifier(name) => {
-            let value = runtime.get_var(name)
-                .cloned()
-                .ok_or_else(|| Error::Runtime(format!("Undefined variable: {}", name)))?;
-            Ok(value)
+            // `$?`: the last shell command's exit status
+            if *name == "?" {
+                return Ok(runtime.last_status().map_or(Value::Null, |status| Value::Number(status as f64)));
+            }
+            if let Some(value) = runtime.lookup_var(name).map_err(Error::Runtime)? {
+                return Ok(value.into_owned());
+            }
+            // `session` is the host's session directory, unless a variable shadows it
+            match runtime.session() {
+                Some(session) if *name == "session" => Ok(session.to_value()),
+                _ => Err(Error::Runtime(format!("Undefined variable: {}", name))),
+            }
         }
 
         Expr::Number(s) => {
@@ -286,15 +375,16 @@ pub fn eval_expr(
         }
 
         Expr::Object(fields) => {
-            let mut map = std::collections::HashMap::new();
+            let mut map = IndexMap::new();
             for field in fields {
                 let value = match &field.value {
                     Some(expr) => eval_expr(expr, runtime, agent)?,
                     None => {
                         // Shorthand: {x} means {x: x}
-                        runtime.get_var(field.key)
-                            .cloned()
+                        runtime.lookup_var(field.key)
+                            .map_err(Error::Runtime)?
                             .ok_or_else(|| Error::Runtime(format!("Undefined variable: {}", field.key)))?
+                            .into_owned()
                     }
                 };
                 map.insert(field.key.to_string(), value);
@@ -312,9 +402,13 @@ pub fn eval_expr(
             let obj_value = eval_expr(object, runtime, agent)?;
 
             match obj_value {
-                Value::Object(map) => {
-                    Ok(map.get(*field).cloned().unwrap_or(Value::Null))
-                }
+                Value::Object(map) => match map.get(*field) {
+                    Some(value) => Ok(value.clone()),
+                    None if runtime.is_strict() => Err(Error::Runtime(format!(
+                        "Strict mode: object has no field '{}'", field
+                    ))),
+                    None => Ok(Value::Null),
+                },
                 other => Err(Error::Runtime(format!(
                     "Cannot access field '{}' on {}", field, type_name(&other)
                 )))
@@ -326,13 +420,23 @@ pub fn eval_expr(
             let idx_value = eval_expr(index, runtime, agent)?;
 
             match (obj_value, idx_value) {
-                (Value::Array(arr), Value::Number(n)) => {
+                (Value::Array(arr) | Value::Tuple(arr), Value::Number(n)) => {
                     let i = n as usize;
-                    Ok(arr.get(i).cloned().unwrap_or(Value::Null))
-                }
-                (Value::Object(map), Value::String(key)) => {
-                    Ok(map.get(&key).cloned().unwrap_or(Value::Null))
+                    match arr.get(i) {
+                        Some(value) => Ok(value.clone()),
+                        None if runtime.is_strict() => Err(Error::Runtime(format!(
+                            "Strict mode: index {} is out of range for length {}", n, arr.len()
+                        ))),
+                        None => Ok(Value::Null),
+                    }
                 }
+                (Value::Object(map), Value::String(key)) => match map.get(&key) {
+                    Some(value) => Ok(value.clone()),
+                    None if runtime.is_strict() => Err(Error::Runtime(format!(
+                        "Strict mode: object has no field '{}'", key
+                    ))),
+                    None => Ok(Value::Null),
+                },
                 (obj, idx) => Err(Error::Runtime(format!(
                     "Cannot index {} with {}", type_name(&obj), type_name(&idx)
                 )))
@@ -351,9 +455,11 @@ pub fn eval_expr(
             eval_expr(inner, runtime, agent)
         }
 
-        Expr::Think(prompt_block) => eval_think_block(prompt_block, runtime, agent),
+        Expr::Think(prompt_block) => eval_think_block(ThinkOp::Think, prompt_block, runtime, agent),
 
-        Expr::Ask(prompt_block) => eval_think_block(prompt_block, runtime, agent),
+        Expr::Ask(prompt_block) => eval_think_block(ThinkOp::Ask, prompt_block, runtime, agent),
+
+        Expr::Approve(prompt_block) => eval_approve(prompt_block, runtime, agent),
 
         Expr::Do(block) => eval_block(block, runtime, agent),
 
@@ -369,6 +475,8 @@ pub fn eval_expr(
             }
         }
 
+        Expr::ShellBackground(inner) => eval_background(inner, runtime, agent),
+
         Expr::ShellPipe { left, right } => {
             // For now, simplified pipe - just execute right with left's output
             // A proper implementation would use actual pipes
@@ -377,9 +485,10 @@ pub fn eval_expr(
         }
 
         Expr::ShellAnd { left, right } => {
+            // A failing left side throws as usual, unless the shell is lenient
             let left_result = eval_expr(left, runtime, agent)?;
 
-            if left_result.to_bool() {
+            if runtime.last_status() == Some(0) {
                 eval_expr(right, runtime, agent)
             } else {
                 Ok(left_result)
@@ -387,12 +496,12 @@ pub fn eval_expr(
         }
 
         Expr::ShellOr { left, right } => {
-            let left_result = eval_expr(left, runtime, agent)?;
-
-            if left_result.to_bool() {
-                Ok(left_result)
-            } else {
-                eval_expr(right, runtime, agent)
+            // `||` handles a failing left side, so it never throws
+            match eval_expr(left, runtime, agent) {
+                Ok(left_result) if runtime.last_status() == Some(0) => Ok(left_result),
+                Ok(_) => eval_expr(right, runtime, agent),
+                Err(Error::Exception(value)) if is_shell_error(&value) => eval_expr(right, runtime, agent),
+                Err(e) => Err(e),
             }
         }
 
@@ -461,66 +570,358 @@ fn process_escape_sequences(s: &str) -> String {
 ///
 /// If an agent is available, this blocks on the agent channel waiting for the
 /// LLM response. Otherwise, it returns a placeholder with the interpolated prompt.
+/// Either way, the prompt and its answer are added to the runtime's transcript.
 fn eval_think_block(
+    op: ThinkOp,
     prompt_block: &PromptBlock,
     runtime: &mut Runtime,
     agent: Option<&AgentHandle>,
 ) -> Result<Value, Error> {
-    // Interpolate the prompt text
-    let mut prompt_text = String::new();
+    runtime.record_think_call().map_err(Error::Runtime)?;
 
-    for item in &prompt_block.items {
+    let mut variant = None;
+    let items = select_variant(prompt_block, runtime, &mut variant);
+
+    // Interpolate the prompt
+    let mut parts = Vec::new();
+    // Consecutive `examples` sections are formatted together
+    let mut examples = Vec::new();
+
+    for item in items {
+        if !examples.is_empty() && !matches!(item, PromptItem::Examples(_)) {
+            push_text(&mut parts, &runtime.format_examples(&std::mem::take(&mut examples)));
+        }
         match item {
             PromptItem::Text(text) => {
-                prompt_text.push_str(text);
+                // Text is a slice of the source; its words are separated by
+                // single spaces, however the source laid them out
+                let words: Vec<&str> = text.split_whitespace().collect();
+                push_text(&mut parts, &words.join(" "));
             }
             PromptItem::Interpolation(expr) => {
-                let value = eval_expr(expr, runtime, agent)?;
-                prompt_text.push_str(&value.to_string_value());
+                let mut value = eval_expr(expr, runtime, agent)?;
+                // Path rules name bindings, which are only known here
+                if let Some(path) = binding_path(expr) {
+                    runtime.redact_interpolation(&path, &mut value);
+                }
+                parts.push(PromptPart::Value(value));
             }
             PromptItem::Code(block) => {
                 // Embedded code blocks - execute them
                 let _result = eval_block(block, runtime, agent)?;
             }
+            PromptItem::Examples(fields) => {
+                let mut example = Vec::new();
+                for field in fields {
+                    let value = match &field.value {
+                        Some(value) => eval_expr(value, runtime, agent)?,
+                        None => eval_expr(&Expr::Identifier(field.key), runtime, agent)?,
+                    };
+                    example.push((field.key.to_string(), value));
+                }
+                examples.push(example);
+            }
+            // select_variant replaced variants with their items
+            PromptItem::Variant { .. } => {}
+        }
+    }
+    if !examples.is_empty() {
+        push_text(&mut parts, &runtime.format_examples(&examples));
+    }
+
+    let prompt = Prompt { op, text: prompt_text(&parts), parts };
+    let response = request_think(&prompt, variant, runtime, agent)?;
+    runtime.record_transcript(TranscriptEntry {
+        kind: match op {
+            ThinkOp::Ask => "ask",
+            _ => "think",
+        },
+        prompt: prompt.text,
+        response: response.clone(),
+    });
+    compact_context(runtime, agent)?;
+    Ok(response)
+}
+
+/// The binding an expression reads, like `["deploy", "token"]` for
+/// `deploy.token`, or `None` if it isn't a variable or a field of one.
+fn binding_path<'a>(expr: &Expr<'a>) -> Option<Vec<&'a str>> {
+    match expr {
+        Expr::Identifier(name) => Some(vec![*name]),
+        Expr::Member { object, field } => {
+            let mut path = binding_path(object)?;
+            path.push(*field);
+            Some(path)
+        }
+        Expr::Paren(inner) => binding_path(inner),
+        _ => None,
+    }
+}
+
+/// A prompt ready to send, before its pattern rules are applied.
+struct Prompt {
+    op: ThinkOp,
+    text: String,
+    parts: Vec<PromptPart>,
+}
+
+/// Add `text` to a prompt, joining it to text just before it.
+fn push_text(parts: &mut Vec<PromptPart>, text: &str) {
+    match parts.last_mut() {
+        Some(PromptPart::Text(last)) => last.push_str(text),
+        _ => parts.push(PromptPart::Text(text.to_string())),
+    }
+}
+
+/// Fold the oldest think/ask blocks into a summary, written by a think
+/// call, once the transcript outgrows `max_context_tokens`.
+fn compact_context(runtime: &mut Runtime, agent: Option<&AgentHandle>) -> Result<(), Error> {
+    let Some((count, prompt)) = runtime.context_to_summarize() else {
+        return Ok(());
+    };
+    let prompt = Prompt { op: ThinkOp::Summarize, parts: vec![PromptPart::Text(prompt.clone())], text: prompt };
+    let summary = request_think(&prompt, None, runtime, agent)?;
+    runtime.fold_context(count, summary);
+    Ok(())
+}
+
+/// Evaluate an `approve` block: interpolate its summary of the pending
+/// action and wait for the approval handler to allow it.
+///
+/// Anything but an approval, including no answer before the approval
+/// timeout, stops the program.
+fn eval_approve(prompt_block: &PromptBlock, runtime: &mut Runtime, agent: Option<&AgentHandle>) -> Result<Value, Error> {
+    let mut summary = String::new();
+    for item in &prompt_block.items {
+        match item {
+            PromptItem::Text(text) => {
+                for (i, word) in text.split_whitespace().enumerate() {
+                    if i > 0 {
+                        summary.push(' ');
+                    }
+                    summary.push_str(word);
+                }
+            }
+            PromptItem::Interpolation(expr) => {
+                let value = eval_expr(expr, runtime, agent)?;
+                summary.push_str(&value.to_string_value());
+            }
+            PromptItem::Code(block) => {
+                eval_block(block, runtime, agent)?;
+            }
+            PromptItem::Examples(_) | PromptItem::Variant { .. } => {
+                return Err(Error::Runtime(
+                    "approve blocks can't have examples or variants; they describe an action, not a prompt".to_string(),
+                ))
+            }
+        }
+    }
+    runtime.request_gate_approval(summary.trim()).map_err(Error::Runtime)?;
+    Ok(Value::Null)
+}
+
+/// A prompt's items with its `variant` sections resolved: the variant the
+/// runtime's policy chooses is replaced by its items, and the others are
+/// dropped. The chosen name is stored in `chosen`.
+fn select_variant<'a, 'input>(
+    prompt_block: &'a PromptBlock<'input>,
+    runtime: &mut Runtime,
+    chosen: &mut Option<String>,
+) -> Vec<&'a PromptItem<'input>> {
+    let names: Vec<&str> = prompt_block
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            PromptItem::Variant { name, .. } => Some(*name),
+            _ => None,
+        })
+        .collect();
+    if names.is_empty() {
+        return prompt_block.items.iter().collect();
+    }
+    let name = names[runtime.choose_variant(&names)];
+    chosen.get_or_insert_with(|| name.to_string());
+
+    let mut items = Vec::new();
+    for item in &prompt_block.items {
+        match item {
+            PromptItem::Variant { name: n, block } if *n == name => {
+                items.extend(select_variant(block, runtime, chosen));
+            }
+            PromptItem::Variant { .. } => {}
+            item => items.push(item),
         }
     }
+    items
+}
 
+/// Send an interpolated prompt to the agent and wait for its answer.
+///
+/// With a model chain configured, each model is tried in order until one
+/// answers; failures outside the chain's `failover_on` classes end the
+/// block immediately.
+fn request_think(
+    prompt: &Prompt,
+    variant: Option<String>,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
     // If we have an agent, send the think request and block waiting for response
     if let Some(agent) = agent {
-        // Collect current variable bindings for context
-        let bindings: HashMap<String, Value> = HashMap::new(); // TODO: collect from runtime
-
-        // Send think request and get receiver for responses
-        let rx = agent
-            .think(prompt_text.clone(), bindings, "string".to_string())
-            .map_err(Error::Runtime)?;
-
-        // Block waiting for responses (following threadbare pattern)
-        for response in rx {
-            match response {
-                ThinkResponse::Do { index, result_tx } => {
-                    // The LLM invoked do(index) - we need recursive evaluation
-                    // For now, send back a placeholder (full implementation needs
-                    // access to think block children)
-                    let _ = result_tx.send(format!("do({}) not yet implemented", index));
-                }
-                ThinkResponse::Complete { result } => {
-                    // Think block completed - return the value
-                    return result.map_err(Error::Runtime);
+        let chain = runtime.model_chain().clone();
+        let models: Vec<Option<String>> = if chain.models.is_empty() {
+            vec![None]
+        } else {
+            chain.models.iter().cloned().map(Some).collect()
+        };
+
+        let mut failures = Vec::new();
+        for model in models {
+            match request_think_from(prompt, model.clone(), variant.clone(), runtime, agent)? {
+                Ok(value) => return Ok(value),
+                Err((class, message)) => {
+                    let message = match &model {
+                        Some(model) => format!("{}: {}", model, message),
+                        None => message,
+                    };
+                    if !chain.failover_on.contains(&class) {
+                        return Err(Error::Runtime(message));
+                    }
+                    failures.push(message);
                 }
             }
         }
-
-        // Channel closed without Complete - error
-        return Err(Error::Runtime("Think block terminated without completion".to_string()));
+        return Err(Error::Runtime(failures.join("; ")));
     }
 
     // No agent - return placeholder so tests can verify interpolation works
-    let mut result = HashMap::new();
-    result.insert("__think_prompt".to_string(), Value::String(prompt_text));
+    let mut result = IndexMap::new();
+    result.insert("__think_prompt".to_string(), Value::String(prompt.text.clone()));
     Ok(Value::Object(result))
 }
 
+/// Ask one model for an answer.
+///
+/// The outer error stops the program (budget, cancellation); the inner one
+/// is a failure of this model that the chain may recover from.
+fn request_think_from(
+    prompt: &Prompt,
+    model: Option<String>,
+    variant: Option<String>,
+    runtime: &mut Runtime,
+    agent: &AgentHandle,
+) -> Result<Result<Value, (FailureClass, String)>, Error> {
+    runtime.record_llm_call()?;
+    let started = Instant::now();
+    let mut meta = CallMeta { model: model.clone(), variant, ..CallMeta::default() };
+    let think_timeout = runtime.limits().think_timeout_secs.map(Duration::from_secs);
+
+    // Collect current variable bindings for context
+    let mut bindings: HashMap<String, Value> = HashMap::new(); // TODO: collect from runtime
+    let mut parts = prompt.parts.clone();
+    let redacted = runtime.redact_request(&prompt.text, &mut parts, &mut bindings);
+
+    // Send think request and get receiver for responses
+    let (response_tx, rx) = mpsc::channel();
+    let request = ThinkRequest {
+        op: prompt.op,
+        prompt: redacted,
+        parts,
+        bindings,
+        expect: Schema::String,
+        model,
+        agent: runtime.model_chain().agent.clone(),
+        timeout: think_timeout,
+        priority: match prompt.op {
+            ThinkOp::Ask => Priority::Interactive,
+            ThinkOp::Think | ThinkOp::Summarize => runtime.think_priority(),
+        },
+        response_tx,
+    };
+    // Kept for the next time it's asked, when the handle reuses answers
+    let answer_key = agent.answer_key(&request);
+    agent.think(request).map_err(Error::Runtime)?;
+
+    // Block waiting for responses (following threadbare pattern)
+    loop {
+        // Wait no longer than the think timeout or the outer deadline,
+        // whichever comes first
+        let wait = [
+            think_timeout.map(|timeout| timeout.saturating_sub(started.elapsed())),
+            runtime.time_remaining(),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let response = match wait {
+            Some(wait) => match rx.recv_timeout(wait) {
+                Ok(response) => response,
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    // Running out of the outer deadline stops the program;
+                    // the think timeout is a failure of this model
+                    runtime.check_deadline().map_err(Error::Runtime)?;
+                    let seconds = think_timeout.unwrap_or_default().as_secs();
+                    let message = format!("no answer within {} seconds", seconds);
+                    return Ok(Err((FailureClass::Timeout, message)));
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(response) => response,
+                Err(_) => break,
+            },
+        };
+        match response {
+            ThinkResponse::Do { index, result_tx } => {
+                // The LLM invoked do(index) - we need recursive evaluation
+                // For now, send back a placeholder (full implementation needs
+                // access to think block children)
+                let _ = result_tx.send(format!("do({}) not yet implemented", index));
+            }
+            ThinkResponse::Usage(usage) => {
+                meta.usage.input_tokens += usage.input_tokens;
+                meta.usage.output_tokens += usage.output_tokens;
+                if let Some(cost) = usage.cost_usd {
+                    meta.usage.cost_usd = Some(meta.usage.cost_usd.unwrap_or(0.0) + cost);
+                }
+                runtime.record_usage(usage)?;
+            }
+            ThinkResponse::Metadata { model, stop_reason } => {
+                meta.model = model.or(meta.model);
+                meta.stop_reason = stop_reason.or(meta.stop_reason);
+            }
+            ThinkResponse::Complete { result: Ok(value) } => {
+                // Think block completed - return the value
+                meta.value = value.clone();
+                meta.latency = started.elapsed();
+                if let Some(key) = answer_key {
+                    agent.keep_answer(key, value.clone(), meta.model.clone(), meta.stop_reason.clone());
+                }
+                runtime.record_call_meta(meta);
+                return Ok(Ok(value));
+            }
+            ThinkResponse::Complete { result: Err(message) } => {
+                return Ok(Err((classify_failure(&message), message)));
+            }
+        }
+    }
+
+    // Channel closed without Complete - error
+    Ok(Err((FailureClass::Error, "Think block terminated without completion".to_string())))
+}
+
+/// Guess a failure's class from the backend's error message.
+fn classify_failure(message: &str) -> FailureClass {
+    let message = message.to_lowercase();
+    if message.contains("rate limit") || message.contains("rate_limit") || message.contains("429") {
+        FailureClass::RateLimit
+    } else if message.contains("timed out") || message.contains("timeout") {
+        FailureClass::Timeout
+    } else {
+        FailureClass::Error
+    }
+}
+
 /// Evaluate a binary operation.
 fn eval_binary(
     op: &BinOp,
@@ -535,7 +936,7 @@ fn eval_binary(
 
         match left {
             Expr::Identifier(name) => {
-                runtime.set_var(name, value.clone()).map_err(Error::Runtime)?;
+                runtime.assign_var(name, value.clone()).map_err(Error::Runtime)?;
                 return Ok(value);
             }
             _ => return Err(Error::Runtime("Invalid assignment target".to_string())),
@@ -550,6 +951,13 @@ fn eval_binary(
             match (&left_val, &right_val) {
                 (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
                 (Value::String(a), Value::String(b)) => Value::String(format!("{}{}", a, b)),
+                (Value::String(_), _) | (_, Value::String(_)) if runtime.is_strict() => {
+                    return Err(Error::Runtime(format!(
+                        "Strict mode: cannot add {} and {}; use string interpolation",
+                        type_name(&left_val),
+                        type_name(&right_val)
+                    )))
+                }
                 (Value::String(a), b) => Value::String(format!("{}{}", a, b.to_string_value())),
                 (a, Value::String(b)) => Value::String(format!("{}{}", a.to_string_value(), b)),
                 _ => {
@@ -560,14 +968,22 @@ fn eval_binary(
             }
         }
         BinOp::Sub => num_op(&left_val, &right_val, |a, b| a - b)?,
-        BinOp::Mul => num_op(&left_val, &right_val, |a, b| a * b)?,
+        BinOp::Mul => match (&left_val, &right_val) {
+            // String repetition: "-" * 40
+            (Value::String(s), Value::Number(n)) | (Value::Number(n), Value::String(s)) => {
+                let count = count_arg(*n, "Repeat count")?;
+                check_built_len(s.len().checked_mul(count), "Repeated string")?;
+                Value::String(s.repeat(count))
+            }
+            _ => num_op(&left_val, &right_val, |a, b| a * b)?,
+        },
         BinOp::Div => num_op(&left_val, &right_val, |a, b| a / b)?,
         BinOp::Eq => Value::Boolean(values_equal(&left_val, &right_val)),
         BinOp::NotEq => Value::Boolean(!values_equal(&left_val, &right_val)),
         BinOp::Lt => compare_values(&left_val, &right_val, |ord| ord.is_lt())?,
         BinOp::Gt => compare_values(&left_val, &right_val, |ord| ord.is_gt())?,
-        BinOp::And => Value::Boolean(left_val.to_bool() && right_val.to_bool()),
-        BinOp::Or => Value::Boolean(left_val.to_bool() || right_val.to_bool()),
+        BinOp::And => Value::Boolean(condition_value(&left_val, runtime)? && condition_value(&right_val, runtime)?),
+        BinOp::Or => Value::Boolean(condition_value(&left_val, runtime)? || condition_value(&right_val, runtime)?),
         BinOp::Pipe => {
             // Should be handled as ShellPipe, not BinOp::Pipe
             return Err(Error::Runtime("Pipe operator not supported here".to_string()))
@@ -592,6 +1008,87 @@ fn eval_binary(
     Ok(result)
 }
 
+/// Format a number with grouped thousands, to `decimals` places if given.
+fn format_number(n: f64, decimals: Option<usize>, separator: &str) -> String {
+    if !n.is_finite() {
+        return Value::Number(n).to_string_value();
+    }
+    let text = match decimals {
+        Some(decimals) => format!("{:.*}", decimals, n),
+        None => Value::Number(n).to_string_value(),
+    };
+    let (sign, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", text.as_str()),
+    };
+    let (whole, fraction) = match unsigned.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (unsigned, None),
+    };
+
+    let mut grouped = String::new();
+    for (i, digit) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            grouped.push_str(separator);
+        }
+        grouped.push(digit);
+    }
+    match fraction {
+        Some(fraction) => format!("{}{}.{}", sign, grouped, fraction),
+        None => format!("{}{}", sign, grouped),
+    }
+}
+
+/// Read an array argument of the builtin `name`.
+fn array_arg<'a>(value: &'a Value, name: &str) -> Result<&'a [Value], Error> {
+    match value {
+        Value::Array(items) => Ok(items),
+        other => Err(Error::Runtime(format!("{}() expects an array, got {}", name, type_name(other)))),
+    }
+}
+
+/// Look up a dotted field path like `author.name`; missing fields are null.
+fn field_path<'a>(value: &'a Value, path: &str) -> &'a Value {
+    const NULL: &Value = &Value::Null;
+    path.split('.').try_fold(value, |value, field| match value {
+        Value::Object(fields) => fields.get(field),
+        _ => None,
+    })
+    .unwrap_or(NULL)
+}
+
+/// Read a non-negative whole number used as a count or width.
+fn count_arg(n: f64, what: &str) -> Result<usize, Error> {
+    if n.fract() != 0.0 || n < 0.0 || !n.is_finite() {
+        return Err(Error::Runtime(format!("{} must be a non-negative integer, got {}", what, n)));
+    }
+    Ok(n as usize)
+}
+
+/// The most decimals `to_fixed` and `format_number` will show.
+const MAX_DECIMALS: usize = 100;
+
+/// Read a count of decimals, up to `MAX_DECIMALS`.
+fn digits_arg(n: f64, what: &str) -> Result<usize, Error> {
+    let digits = count_arg(n, what)?;
+    if digits > MAX_DECIMALS {
+        return Err(Error::Runtime(format!("{} must be at most {}, got {}", what, MAX_DECIMALS, digits)));
+    }
+    Ok(digits)
+}
+
+/// The longest string, in bytes, that repetition or padding will build.
+const MAX_BUILT_STRING_LEN: usize = 1 << 30;
+
+/// Fail if a string `len` bytes long (`None` if that overflowed) is past
+/// `MAX_BUILT_STRING_LEN`.
+fn check_built_len(len: Option<usize>, what: &str) -> Result<(), Error> {
+    match len {
+        Some(len) if len <= MAX_BUILT_STRING_LEN => Ok(()),
+        _ => Err(Error::Runtime(format!("{} would be longer than {} bytes", what, MAX_BUILT_STRING_LEN))),
+    }
+}
+
 /// Numeric binary operation helper.
 fn num_op(left: &Value, right: &Value, op: fn(f64, f64) -> f64) -> Result<Value, Error> {
     match (left, right) {
@@ -610,9 +1107,10 @@ fn values_equal(a: &Value, b: &Value) -> bool {
         (Value::Boolean(a), Value::Boolean(b)) => a == b,
         (Value::Number(a), Value::Number(b)) => a == b,
         (Value::String(a), Value::String(b)) => a == b,
-        (Value::Array(a), Value::Array(b)) => {
+        (Value::Array(a), Value::Array(b)) | (Value::Tuple(a), Value::Tuple(b)) => {
             a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| values_equal(x, y))
         }
+        (Value::Set(_), Value::Set(_)) => a == b,
         _ => false,
     }
 }
@@ -642,7 +1140,7 @@ fn eval_unary(
     let value = eval_expr(operand, runtime, agent)?;
 
     match op {
-        UnOp::Not => Ok(Value::Boolean(!value.to_bool())),
+        UnOp::Not => Ok(Value::Boolean(!condition_value(&value, runtime)?)),
         UnOp::Neg => {
             match value {
                 Value::Number(n) => Ok(Value::Number(-n)),
@@ -662,20 +1160,238 @@ fn eval_call(
 ) -> Result<Value, Error> {
     // Check for builtin functions
     if let Expr::Identifier(name) = callee {
+        // schedule_at evaluates its second argument only once the time comes
+        if *name == "schedule_at" {
+            return eval_schedule_at(args, runtime, agent);
+        }
+        // in_context evaluates its second argument inside the forked context
+        if *name == "in_context" {
+            return eval_in_context(args, runtime, agent);
+        }
+        // with_timeout evaluates its second argument under a deadline
+        if *name == "with_timeout" {
+            return eval_with_timeout(args, runtime, agent);
+        }
+        // validate's second argument names a type
+        if *name == "validate" {
+            return eval_validate(args, runtime, agent);
+        }
+
         let mut arg_values = Vec::new();
         for arg in args {
             arg_values.push(eval_expr(arg, runtime, agent)?);
         }
 
+        if let Some(function) = runtime.host_function(name) {
+            return function.call(&arg_values).map_err(Error::Runtime);
+        }
+        if *name == "eval_patchwork" {
+            return eval_patchwork(&arg_values, runtime, agent);
+        }
+        if *name == "write" {
+            return runtime.perform_effect("write", name, |runtime| eval_builtin(name, &arg_values, runtime));
+        }
         return eval_builtin(name, &arg_values, runtime);
     }
 
+    // Host functions registered as `module.name`, such as `github.get_pr`
+    if let Expr::Member { object, field } = callee {
+        if let Expr::Identifier(module) = object.as_ref() {
+            let name = format!("{}.{}", module, field);
+            if runtime.host_function(&name).is_some() {
+                let mut arg_values = Vec::new();
+                for arg in args {
+                    arg_values.push(eval_expr(arg, runtime, agent)?);
+                }
+                let function = runtime.host_function(&name).expect("checked above");
+                return function.call(&arg_values).map_err(Error::Runtime);
+            }
+            if *module == "artifact" {
+                return eval_artifact(field, args, runtime, agent);
+            }
+        }
+        // Methods of a handle from `$(cmd &)`, such as `build.wait()`
+        if let Some(id) = process_id(&eval_expr(object, runtime, agent)?) {
+            return eval_process_method(id, field, args, runtime, agent);
+        }
+    }
+
     // For now, only builtins are supported
     Err(Error::Runtime("User-defined functions not yet implemented".to_string()))
 }
 
+/// Evaluate `artifact.write(name, content, mime?)` or `artifact.list()`,
+/// which keep the run's outputs in its session directory.
+fn eval_artifact(function: &str, args: &[Expr], runtime: &mut Runtime, agent: Option<&AgentHandle>) -> Result<Value, Error> {
+    let mut values = Vec::new();
+    for arg in args {
+        values.push(eval_expr(arg, runtime, agent)?);
+    }
+    if runtime.session().is_none() {
+        return Err(Error::Runtime(format!("artifact.{}(): no session directory to keep artifacts in", function)));
+    }
+    match function {
+        "write" => {
+            let (name, content, mime) = match values.as_slice() {
+                [name, content] => (name, content, None),
+                [name, content, mime] => (name, content, Some(mime.to_string_value())),
+                _ => return Err(Error::Runtime("artifact.write() takes 2 or 3 arguments".to_string())),
+            };
+            runtime.check_file_write().map_err(Error::Runtime)?;
+            let (name, content) = (name.to_string_value(), content.to_string_value());
+            runtime.perform_effect("artifact", function, |runtime| {
+                let session = runtime.session().expect("checked above");
+                let artifact = session.write_artifact(&name, &content, mime.as_deref()).map_err(Error::Runtime)?;
+                Ok(artifact.to_value())
+            })
+        }
+        "list" => {
+            if !values.is_empty() {
+                return Err(Error::Runtime("artifact.list() takes no arguments".to_string()));
+            }
+            let session = runtime.session().expect("checked above");
+            let artifacts = session.artifacts().map_err(Error::Runtime)?;
+            Ok(Value::Array(artifacts.iter().map(|a| a.to_value()).collect()))
+        }
+        other => Err(Error::Runtime(format!("Unknown function: artifact.{}", other))),
+    }
+}
+
+/// Evaluate `validate(value, Type)`: return the value if it matches the
+/// declared type, or fail listing every mismatch.
+fn eval_validate(args: &[Expr], runtime: &mut Runtime, agent: Option<&AgentHandle>) -> Result<Value, Error> {
+    let [value, schema] = args else {
+        return Err(Error::Runtime("validate() takes exactly 2 arguments".to_string()));
+    };
+    let value = eval_expr(value, runtime, agent)?;
+    let name = match schema {
+        Expr::Identifier(name) => name.to_string(),
+        other => eval_expr(other, runtime, agent)?.to_string_value(),
+    };
+    let mismatches = runtime.schemas().check(&name, &value).map_err(|e| Error::Runtime(format!("validate(): {}", e)))?;
+    if mismatches.is_empty() {
+        return Ok(value);
+    }
+    let lines: Vec<String> = mismatches.iter().map(ToString::to_string).collect();
+    Err(Error::Runtime(format!("validate(): value does not match {}:\n  {}", name, lines.join("\n  "))))
+}
+
+/// Evaluate `eval_patchwork(code, options?)`: run a snippet, usually one a
+/// model wrote, in an isolated interpreter and return its final value.
+///
+/// The snippet can't run shell commands, write files, send notifications,
+/// or evaluate code itself unless `options.capabilities` grants it, in the
+/// config file's format; `options.limits` tightens its budget. It never gets
+/// more than the calling program has.
+fn eval_patchwork(args: &[Value], runtime: &mut Runtime, agent: Option<&AgentHandle>) -> Result<Value, Error> {
+    let (code, options) = match args {
+        [code] => (code, None),
+        [code, options] => (code, Some(options)),
+        _ => return Err(Error::Runtime("eval_patchwork() takes 1 or 2 arguments".to_string())),
+    };
+    let Value::String(code) = code else {
+        return Err(Error::Runtime(format!("eval_patchwork(): code must be a string, got {}", type_name(code))));
+    };
+
+    let mut sandbox = Config {
+        capabilities: CapabilityPolicy {
+            shell: Permission::Deny,
+            file_write: Permission::Deny,
+            notify: Permission::Deny,
+            eval_code: Permission::Deny,
+            ..CapabilityPolicy::default()
+        },
+        ..Config::default()
+    };
+    match options {
+        None | Some(Value::Null) => {}
+        Some(Value::Object(fields)) => {
+            if let Some(key) = fields.keys().find(|key| !matches!(key.as_str(), "capabilities" | "limits")) {
+                return Err(Error::Runtime(format!("eval_patchwork(): unknown option `{}`", key)));
+            }
+            let options = options.expect("matched above").to_json_value().to_string();
+            let layer = ConfigLayer::from_json(&options, "eval_patchwork() options")
+                .map_err(|e| Error::Runtime(e.to_string()))?;
+            sandbox.merge(&layer);
+        }
+        Some(other) => {
+            return Err(Error::Runtime(format!(
+                "eval_patchwork(): options must be an object, got {}",
+                type_name(other)
+            )))
+        }
+    }
+
+    runtime.check_eval_code(code).map_err(Error::Runtime)?;
+    run_isolated(code, runtime, agent, &sandbox.capabilities, &sandbox.limits)
+}
+
+/// Evaluate `in_context(context, expr)`: evaluate `expr` with a forked
+/// context from `fork_context()` as the current conversation.
+fn eval_in_context(
+    args: &[Expr],
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    let [context, expr] = args else {
+        return Err(Error::Runtime("in_context() takes exactly 2 arguments".to_string()));
+    };
+    let id = context_handle(&eval_expr(context, runtime, agent)?)?;
+    runtime
+        .with_context(id, |runtime| eval_expr(expr, runtime, agent))
+        .map_err(Error::Runtime)?
+}
+
+/// Evaluate `with_timeout(duration, expr)`: evaluate `expr`, failing if it
+/// takes longer than `duration`. Think blocks, shell commands, and sleeps
+/// inside `expr` are cut off at the deadline.
+fn eval_with_timeout(
+    args: &[Expr],
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    let [duration, expr] = args else {
+        return Err(Error::Runtime("with_timeout() takes exactly 2 arguments".to_string()));
+    };
+    let duration = timer::parse_duration(&eval_expr(duration, runtime, agent)?).map_err(Error::Runtime)?;
+    runtime.with_timeout(duration, |runtime| {
+        let value = eval_expr(expr, runtime, agent)?;
+        runtime.check_deadline().map_err(Error::Runtime)?;
+        Ok(value)
+    })
+}
+
+/// Read a context handle returned by `fork_context()`.
+fn context_handle(value: &Value) -> Result<usize, Error> {
+    match value {
+        Value::Number(n) if n.fract() == 0.0 && *n >= 0.0 => Ok(*n as usize),
+        other => Err(Error::Runtime(format!(
+            "Expected a context from fork_context(), got {}",
+            type_name(other)
+        ))),
+    }
+}
+
+/// Evaluate `schedule_at(time, expr)`: wait until `time`, then evaluate `expr`.
+fn eval_schedule_at(
+    args: &[Expr],
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    let [time, expr] = args else {
+        return Err(Error::Runtime("schedule_at() takes exactly 2 arguments".to_string()));
+    };
+    let time = timer::parse_time(&eval_expr(time, runtime, agent)?).map_err(Error::Runtime)?;
+
+    // A time in the past runs immediately
+    if let Ok(delay) = time.duration_since(std::time::SystemTime::now()) {
+        runtime.sleep(delay).map_err(Error::Runtime)?;
+    }
+    eval_expr(expr, runtime, agent)
+}
+
 /// Evaluate a builtin function call.
-fn eval_builtin(name: &str, args: &[Value], runtime: &Runtime) -> Result<Value, Error> {
+fn eval_builtin(name: &str, args: &[Value], runtime: &mut Runtime) -> Result<Value, Error> {
     let result = match name {
         "cat" => {
             // cat(value) - serialize to pretty JSON
@@ -712,7 +1428,7 @@ fn eval_builtin(name: &str, args: &[Value], runtime: &Runtime) -> Result<Value,
                 return Err(Error::Runtime("len() takes exactly 1 argument".to_string()));
             }
             match &args[0] {
-                Value::Array(arr) => Value::Number(arr.len() as f64),
+                Value::Array(arr) | Value::Set(arr) | Value::Tuple(arr) => Value::Number(arr.len() as f64),
                 Value::String(s) => Value::Number(s.len() as f64),
                 Value::Object(obj) => Value::Number(obj.len() as f64),
                 other => return Err(Error::Runtime(format!("Cannot get length of {}", type_name(other)))),
@@ -747,19 +1463,28 @@ fn eval_builtin(name: &str, args: &[Value], runtime: &Runtime) -> Result<Value,
             }
         }
 
-        "typeof" => {
+        "typeof" | "type_of" => {
             if args.len() != 1 {
-                return Err(Error::Runtime("typeof() takes exactly 1 argument".to_string()));
+                return Err(Error::Runtime(format!("{}() takes exactly 1 argument", name)));
             }
             Value::String(type_name(&args[0]).to_string())
         }
 
+        "is_null" | "is_string" | "is_number" | "is_boolean" | "is_array" | "is_object" | "is_set"
+        | "is_tuple" => {
+            // is_<type>(value) - does the value have the named type?
+            if args.len() != 1 {
+                return Err(Error::Runtime(format!("{}() takes exactly 1 argument", name)));
+            }
+            Value::Boolean(name.strip_prefix("is_") == Some(type_name(&args[0])))
+        }
+
         "read" => {
             // read(path) - read file contents as string
             if args.len() != 1 {
                 return Err(Error::Runtime("read() takes exactly 1 argument".to_string()));
             }
-            let path = resolve_path(&args[0].to_string_value(), runtime);
+            let path = runtime.resolve_path(&args[0].to_string_value()).map_err(Error::Runtime)?;
             let contents = fs::read_to_string(&path)
                 .map_err(|e| Error::Runtime(format!("Failed to read {}: {}", path.display(), e)))?;
             Value::String(contents)
@@ -770,56 +1495,455 @@ fn eval_builtin(name: &str, args: &[Value], runtime: &Runtime) -> Result<Value,
             if args.len() != 2 {
                 return Err(Error::Runtime("write() takes exactly 2 arguments".to_string()));
             }
-            let path = resolve_path(&args[0].to_string_value(), runtime);
+            runtime.check_file_write().map_err(Error::Runtime)?;
+            let path = runtime.resolve_path(&args[0].to_string_value()).map_err(Error::Runtime)?;
             let content = args[1].to_string_value();
             fs::write(&path, content)
                 .map_err(|e| Error::Runtime(format!("Failed to write {}: {}", path.display(), e)))?;
             Value::Null
         }
 
-        _ => return Err(Error::Runtime(format!("Unknown function: {}", name))),
-    };
+        "set" => {
+            // set(items?) - the distinct items of an array, set, or tuple
+            match args {
+                [] => Value::Set(Vec::new()),
+                [Value::Array(items) | Value::Set(items) | Value::Tuple(items)] => Value::set(items.iter().cloned()),
+                _ => return Err(Error::Runtime("set() takes an optional array".to_string())),
+            }
+        }
 
-    Ok(result)
-}
+        "tuple" => {
+            // tuple(values...) - group values to return or destructure together
+            Value::Tuple(args.to_vec())
+        }
 
-/// Evaluate a bare shell command.
-fn eval_bare_command(
-    name: &str,
-    args: &[CommandArg],
-    runtime: &mut Runtime,
-    agent: Option<&AgentHandle>,
-) -> Result<Value, Error> {
-    let mut cmd_args = Vec::new();
-    for arg in args {
-        match arg {
-            CommandArg::Literal(s) => cmd_args.push(s.to_string()),
-            CommandArg::String(string_lit) => {
-                let value = eval_string_literal(string_lit, runtime, agent)?;
-                cmd_args.push(value.to_string_value());
+        "union" | "intersect" => {
+            // union(a, b) / intersect(a, b) - set operations, keeping a's order
+            let [Value::Set(a), Value::Set(b)] = args else {
+                return Err(Error::Runtime(format!("{}() takes two sets", name)));
+            };
+            if name == "union" {
+                Value::set(a.iter().chain(b).cloned())
+            } else {
+                Value::Set(a.iter().filter(|item| b.contains(item)).cloned().collect())
             }
         }
-    }
 
-    exec_command(name, &cmd_args, runtime)
-}
+        "contains" => {
+            // contains(collection, item) - membership in a set, array, or tuple;
+            // substring for strings; key for objects
+            let [collection, item] = args else {
+                return Err(Error::Runtime("contains() takes exactly 2 arguments".to_string()));
+            };
+            let found = match collection {
+                Value::Set(items) | Value::Array(items) | Value::Tuple(items) => items.contains(item),
+                Value::String(s) => s.contains(&item.to_string_value()),
+                Value::Object(fields) => fields.contains_key(&item.to_string_value()),
+                other => return Err(Error::Runtime(format!("Cannot search in {}", type_name(other)))),
+            };
+            Value::Boolean(found)
+        }
 
-/// Execute a shell command.
-fn exec_command(name: &str, args: &[String], runtime: &Runtime) -> Result<Value, Error> {
-    let output = Command::new(name)
-        .args(args)
-        .current_dir(runtime.working_dir())
-        .output()
-        .map_err(|e| Error::Runtime(format!("Failed to execute {}: {}", name, e)))?;
+        "group_by" => {
+            // group_by(items, key) - { key value: [items with it] }, key a field path, groups in key order
+            let [items, Value::String(key)] = args else {
+                return Err(Error::Runtime("group_by() takes an array and a field name".to_string()));
+            };
+            let mut groups: IndexMap<String, (&Value, Vec<Value>)> = IndexMap::new();
+            for item in array_arg(items, "group_by")? {
+                let value = field_path(item, key);
+                groups.entry(value.to_string_value()).or_insert_with(|| (value, Vec::new())).1.push(item.clone());
+            }
+            groups.sort_by(|_, (a, _), _, (b, _)| a.total_cmp(b));
+            Value::Object(groups.into_iter().map(|(group, (_, members))| (group, Value::Array(members))).collect())
+        }
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(Error::Runtime(format!(
-            "Command '{}' failed with exit code {:?}: {}",
-            name,
-            output.status.code(),
-            stderr.trim()
-        )));
+        "sort_by" => {
+            // sort_by(items, key?) - stable sort by a field path, or by the items themselves
+            let (items, key) = match args {
+                [items] => (items, None),
+                [items, Value::String(key)] => (items, Some(key.as_str())),
+                _ => return Err(Error::Runtime("sort_by() takes an array and an optional field name".to_string())),
+            };
+            let mut keyed: Vec<(&Value, &Value)> = array_arg(items, "sort_by")?
+                .iter()
+                .map(|item| (key.map_or(item, |key| field_path(item, key)), item))
+                .collect();
+            let mut mismatch = None;
+            // Keys of one type sort by `Value::total_cmp`; mixing types is likely a mistake
+            keyed.sort_by(|(a, _), (b, _)| {
+                if type_name(a) == type_name(b) {
+                    a.total_cmp(b)
+                } else {
+                    mismatch.get_or_insert_with(|| format!("Cannot compare {} and {}", type_name(a), type_name(b)));
+                    std::cmp::Ordering::Equal
+                }
+            });
+            if let Some(message) = mismatch {
+                return Err(Error::Runtime(format!("sort_by(): {}", message)));
+            }
+            Value::Array(keyed.into_iter().map(|(_, item)| item.clone()).collect())
+        }
+
+        "unique" => {
+            // unique(items) - drop repeated items, keeping the first of each
+            let [items] = args else {
+                return Err(Error::Runtime("unique() takes exactly 1 argument".to_string()));
+            };
+            let mut seen: Vec<Value> = Vec::new();
+            for item in array_arg(items, "unique")? {
+                if !seen.contains(item) {
+                    seen.push(item.clone());
+                }
+            }
+            Value::Array(seen)
+        }
+
+        "chunk" => {
+            // chunk(items, size) - consecutive arrays of at most `size` items
+            let [items, Value::Number(size)] = args else {
+                return Err(Error::Runtime("chunk() takes an array and a size".to_string()));
+            };
+            let size = count_arg(*size, "Chunk size")?;
+            if size == 0 {
+                return Err(Error::Runtime("Chunk size must be at least 1".to_string()));
+            }
+            let chunks = array_arg(items, "chunk")?.chunks(size).map(|chunk| Value::Array(chunk.to_vec()));
+            Value::Array(chunks.collect())
+        }
+
+        "flatten" => {
+            // flatten(items) - splice nested arrays in, one level deep
+            let [items] = args else {
+                return Err(Error::Runtime("flatten() takes exactly 1 argument".to_string()));
+            };
+            let mut flat = Vec::new();
+            for item in array_arg(items, "flatten")? {
+                match item {
+                    Value::Array(inner) => flat.extend(inner.iter().cloned()),
+                    other => flat.push(other.clone()),
+                }
+            }
+            Value::Array(flat)
+        }
+
+        "to_fixed" => {
+            // to_fixed(n, digits) - exactly `digits` decimals, rounded
+            let [Value::Number(n), Value::Number(digits)] = args else {
+                return Err(Error::Runtime("to_fixed() takes a number and a digit count".to_string()));
+            };
+            Value::String(format!("{:.*}", digits_arg(*digits, "Digit count")?, n))
+        }
+
+        "format_number" => {
+            // format_number(n, { decimals, thousands_sep }) - e.g. 1234.5 -> "1,234.50"
+            let (n, options) = match args {
+                [Value::Number(n)] => (*n, None),
+                [Value::Number(n), Value::Object(options)] => (*n, Some(options)),
+                _ => {
+                    return Err(Error::Runtime(
+                        "format_number() takes a number and an optional options object".to_string(),
+                    ))
+                }
+            };
+            let option = |key: &str| options.and_then(|options| options.get(key));
+            let digits = match option("decimals") {
+                None | Some(Value::Null) => None,
+                Some(Value::Number(d)) => Some(digits_arg(*d, "decimals")?),
+                Some(other) => return Err(Error::Runtime(format!("decimals must be a number, got {}", type_name(other)))),
+            };
+            let separator = match option("thousands_sep") {
+                None => ",".to_string(),
+                Some(Value::Null) => String::new(),
+                Some(sep) => sep.to_string_value(),
+            };
+            Value::String(format_number(n, digits, &separator))
+        }
+
+        "pad_left" | "pad_right" => {
+            // pad_left(text, width, fill = " ") - pad to `width` characters
+            let (text, width, fill) = match args {
+                [text, Value::Number(width)] => (text, *width, " ".to_string()),
+                [text, Value::Number(width), Value::String(fill)] => (text, *width, fill.clone()),
+                _ => {
+                    return Err(Error::Runtime(format!(
+                        "{}() takes a value, a width, and an optional fill string",
+                        name
+                    )))
+                }
+            };
+            let text = text.to_string_value();
+            let width = count_arg(width, "Width")?;
+            if fill.is_empty() {
+                return Err(Error::Runtime(format!("{}() fill must not be empty", name)));
+            }
+            // Fill characters may take several bytes each, so count bytes
+            let pad_chars = width.saturating_sub(text.chars().count());
+            let fill_chars = fill.chars().count();
+            let partial: usize = fill.chars().take(pad_chars % fill_chars).map(char::len_utf8).sum();
+            let padded_len = (pad_chars / fill_chars)
+                .checked_mul(fill.len())
+                .and_then(|len| len.checked_add(partial))
+                .and_then(|len| len.checked_add(text.len()));
+            check_built_len(padded_len, "Padded string")?;
+            let mut fill_chars = fill.chars().cycle();
+            let padding: String = (text.chars().count()..width).filter_map(|_| fill_chars.next()).collect();
+            if name == "pad_left" {
+                Value::String(padding + text.as_str())
+            } else {
+                Value::String(text + padding.as_str())
+            }
+        }
+
+        "truncate" => {
+            // truncate(text, n, ellipsis = "...") - at most n characters, ellipsis included
+            let (text, limit, ellipsis) = match args {
+                [text, Value::Number(n)] => (text, *n, "...".to_string()),
+                [text, Value::Number(n), Value::String(ellipsis)] => (text, *n, ellipsis.clone()),
+                _ => {
+                    return Err(Error::Runtime(
+                        "truncate() takes a value, a length, and an optional ellipsis".to_string(),
+                    ))
+                }
+            };
+            let text = text.to_string_value();
+            let limit = count_arg(limit, "Length")?;
+            if text.chars().count() <= limit {
+                Value::String(text)
+            } else {
+                let keep = limit.saturating_sub(ellipsis.chars().count());
+                let mut truncated: String = text.chars().take(keep).collect();
+                truncated.extend(ellipsis.chars().take(limit - keep));
+                Value::String(truncated)
+            }
+        }
+
+        "template" => {
+            // template(text, vars) - fill {name} placeholders from an object
+            if args.len() != 2 {
+                return Err(Error::Runtime("template() takes exactly 2 arguments".to_string()));
+            }
+            let text = args[0].to_string_value();
+            Value::String(fill_template(&text, &args[1]).map_err(Error::Runtime)?)
+        }
+
+        "render_template" => {
+            // render_template(path, vars) - template() with the text read from a file
+            if args.len() != 2 {
+                return Err(Error::Runtime("render_template() takes exactly 2 arguments".to_string()));
+            }
+            let path = runtime.resolve_path(&args[0].to_string_value()).map_err(Error::Runtime)?;
+            let text = fs::read_to_string(&path)
+                .map_err(|e| Error::Runtime(format!("Failed to read {}: {}", path.display(), e)))?;
+            let filled = fill_template(&text, &args[1])
+                .map_err(|e| Error::Runtime(format!("{}: {}", path.display(), e)))?;
+            Value::String(filled)
+        }
+
+        "include_prompt" => {
+            // include_prompt(path) - a prompt fragment, with its @include lines expanded
+            if args.len() != 1 {
+                return Err(Error::Runtime("include_prompt() takes exactly 1 argument".to_string()));
+            }
+            let path = runtime.resolve_path(&args[0].to_string_value()).map_err(Error::Runtime)?;
+            Value::String(expand_prompt_file(&path, runtime, &mut Vec::new()).map_err(Error::Runtime)?)
+        }
+
+        "progress" => {
+            // progress(fraction, message?) - report how far along a long workflow is
+            let (fraction, message) = match args {
+                [fraction] => (fraction, String::new()),
+                [fraction, message] => (fraction, message.to_string_value()),
+                _ => return Err(Error::Runtime("progress() takes 1 or 2 arguments".to_string())),
+            };
+            let fraction = match fraction {
+                Value::Number(n) if (0.0..=1.0).contains(n) => *n,
+                other => {
+                    return Err(Error::Runtime(format!(
+                        "progress() expects a fraction between 0 and 1, got {}",
+                        other.to_string_value()
+                    )))
+                }
+            };
+            runtime.report_progress(ProgressUpdate { fraction, message });
+            Value::Null
+        }
+
+        "warn" => {
+            // warn(message) - tell the user something without stopping
+            if args.len() != 1 {
+                return Err(Error::Runtime("warn() takes exactly 1 argument".to_string()));
+            }
+            runtime.warn("warn", args[0].to_string_value());
+            Value::Null
+        }
+
+        "step_done" => {
+            // step_done() - check off the current step of the declared plan
+            if !args.is_empty() {
+                return Err(Error::Runtime("step_done() takes no arguments".to_string()));
+            }
+            runtime.complete_plan_step().map_err(Error::Runtime)?;
+            Value::Null
+        }
+
+        "sleep" => {
+            // sleep(duration) - seconds, or a string like "30s" or "500ms"
+            if args.len() != 1 {
+                return Err(Error::Runtime("sleep() takes exactly 1 argument".to_string()));
+            }
+            let duration = timer::parse_duration(&args[0]).map_err(Error::Runtime)?;
+            runtime.sleep(duration).map_err(Error::Runtime)?;
+            Value::Null
+        }
+
+        // wait_all(handles, options?) - join background processes, collecting every failure
+        "wait_all" => wait_all(args, runtime)?,
+
+        // wait_any(handles, timeout?) - the first background process to exit
+        "wait_any" => wait_any(args, runtime)?,
+
+        "temp_file" | "temp_dir" => {
+            // temp_file(prefix?) / temp_dir(prefix?) - a fresh scratch path in the session's tmp/
+            let prefix = match args {
+                [] => "tmp".to_string(),
+                [prefix] => prefix.to_string_value(),
+                _ => return Err(Error::Runtime(format!("{}() takes at most 1 argument", name))),
+            };
+            runtime.check_file_write().map_err(Error::Runtime)?;
+            let Some(session) = runtime.session() else {
+                return Err(Error::Runtime(format!("{}(): no session directory to keep temp files in", name)));
+            };
+            let path = if name == "temp_file" { session.temp_file(&prefix) } else { session.temp_dir(&prefix) };
+            Value::String(path.map_err(Error::Runtime)?.display().to_string())
+        }
+
+        "now" => {
+            // now() - the current time in Unix seconds
+            if !args.is_empty() {
+                return Err(Error::Runtime("now() takes no arguments".to_string()));
+            }
+            Value::Number(timer::now_seconds())
+        }
+
+        "budget_remaining" => {
+            // budget_remaining() - { llm_calls, total_tokens, cost_usd } left, null if unlimited
+            if !args.is_empty() {
+                return Err(Error::Runtime("budget_remaining() takes no arguments".to_string()));
+            }
+            runtime.budget_remaining()
+        }
+
+        "last_call_meta" => {
+            // last_call_meta() - { value, model, tokens_in, tokens_out, latency_ms, stop_reason, variant }
+            if !args.is_empty() {
+                return Err(Error::Runtime("last_call_meta() takes no arguments".to_string()));
+            }
+            runtime.last_call_meta().map(CallMeta::to_value).unwrap_or(Value::Null)
+        }
+
+        "fork_context" => {
+            // fork_context() - branch the conversation, returning a handle for in_context()
+            if !args.is_empty() {
+                return Err(Error::Runtime("fork_context() takes no arguments".to_string()));
+            }
+            Value::Number(runtime.fork_context() as f64)
+        }
+
+        "merge_context" => {
+            // merge_context(context) - add a branch's think/ask blocks to this conversation
+            if args.len() != 1 {
+                return Err(Error::Runtime("merge_context() takes exactly 1 argument".to_string()));
+            }
+            runtime.merge_context(context_handle(&args[0])?).map_err(Error::Runtime)?;
+            Value::Null
+        }
+
+        "history" => {
+            // history() - every think/ask block so far, as { kind, prompt, response }
+            if !args.is_empty() {
+                return Err(Error::Runtime("history() takes no arguments".to_string()));
+            }
+            Value::Array(runtime.transcript().iter().map(TranscriptEntry::to_value).collect())
+        }
+
+        "last_response" => {
+            // last_response() - the answer to the most recent think/ask block
+            if !args.is_empty() {
+                return Err(Error::Runtime("last_response() takes no arguments".to_string()));
+            }
+            runtime.transcript().last().map(|entry| entry.response.clone()).unwrap_or(Value::Null)
+        }
+
+        "pin" => {
+            // pin(fact) - keep a fact in the conversation however much of it is summarized
+            if args.len() != 1 {
+                return Err(Error::Runtime("pin() takes exactly 1 argument".to_string()));
+            }
+            runtime.pin_fact(args[0].to_string_value());
+            Value::Null
+        }
+
+        "pinned" => {
+            // pinned() - the facts pinned so far
+            if !args.is_empty() {
+                return Err(Error::Runtime("pinned() takes no arguments".to_string()));
+            }
+            Value::Array(runtime.pinned_facts().iter().cloned().map(Value::String).collect())
+        }
+
+        _ => return Err(Error::Runtime(format!("Unknown function: {}", name))),
+    };
+
+    Ok(result)
+}
+
+/// Evaluate a bare shell command.
+fn eval_bare_command(
+    name: &str,
+    args: &[CommandArg],
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    let cmd_args = eval_command_args(args, runtime, agent)?;
+    // A replayed command isn't run again; it succeeded the first time
+    runtime.set_last_status(Some(0));
+    runtime.perform_effect("shell", name, |runtime| exec_command(name, &cmd_args, runtime))
+}
+
+/// Evaluate the arguments of a shell command, interpolating strings.
+fn eval_command_args(args: &[CommandArg], runtime: &mut Runtime, agent: Option<&AgentHandle>) -> Result<Vec<String>, Error> {
+    let mut cmd_args = Vec::new();
+    for arg in args {
+        match arg {
+            CommandArg::Literal(s) => cmd_args.push(s.to_string()),
+            CommandArg::String(string_lit) => {
+                let value = eval_string_literal(string_lit, runtime, agent)?;
+                cmd_args.push(value.to_string_value());
+            }
+        }
+    }
+    Ok(cmd_args)
+}
+
+/// Execute a shell command.
+fn exec_command(name: &str, args: &[String], runtime: &mut Runtime) -> Result<Value, Error> {
+    runtime.check_shell(name, args).map_err(Error::Runtime)?;
+
+    let mut command = Command::new(name);
+    command.args(args).current_dir(runtime.working_dir());
+    let started = Instant::now();
+    let output = command_output(&mut command, runtime)?
+        .map_err(|e| Error::Runtime(format!("Failed to execute {}: {}", name, e)))?;
+    runtime.record_telemetry(TelemetryEvent::ShellExec {
+        command: display_command(name, args),
+        status: output.status.code(),
+        duration: started.elapsed(),
+    });
+
+    runtime.set_last_status(output.status.code());
+    if !output.status.success() && !runtime.is_lenient_shell() {
+        return Err(shell_error(name, output.status, &output.stderr));
     }
 
     let stdout = String::from_utf8_lossy(&output.stdout);
@@ -837,6 +1961,378 @@ fn exec_command(name: &str, args: &[String], runtime: &Runtime) -> Result<Value,
     Ok(Value::String(stdout.into_owned()))
 }
 
+/// The exception a command that exits unsuccessfully throws: a
+/// `ShellError` object with the command, its exit status (null if it was
+/// killed by a signal), and its standard error.
+fn shell_error(command: &str, status: std::process::ExitStatus, stderr: &[u8]) -> Error {
+    let stderr = String::from_utf8_lossy(stderr).trim().to_string();
+    let code = status.code().map_or(Value::Null, |code| Value::Number(code as f64));
+    let message = format!("Command '{}' failed with exit code {}: {}", command, code.to_string_value(), stderr);
+    let mut fields = IndexMap::new();
+    fields.insert("type".to_string(), Value::String("ShellError".to_string()));
+    fields.insert("command".to_string(), Value::String(command.to_string()));
+    fields.insert("status".to_string(), code);
+    fields.insert("stderr".to_string(), Value::String(stderr));
+    fields.insert("message".to_string(), Value::String(message));
+    Error::Exception(Value::Object(fields))
+}
+
+/// Start a command without waiting for it, returning a handle to its
+/// process; see `process`.
+fn eval_background(command: &Expr, runtime: &mut Runtime, agent: Option<&AgentHandle>) -> Result<Value, Error> {
+    let Expr::BareCommand { name, args } = command else {
+        return Err(Error::Runtime("Only a single command can run in the background".to_string()));
+    };
+    let args = eval_command_args(args, runtime, agent)?;
+    runtime.check_shell(name, &args).map_err(Error::Runtime)?;
+
+    let mut command = Command::new(name);
+    command.args(&args).current_dir(runtime.working_dir());
+    let process = BackgroundProcess::spawn(&mut command, display_command(name, &args))
+        .map_err(|e| Error::Runtime(format!("Failed to execute {}: {}", name, e)))?;
+    Ok(runtime.processes().add(process))
+}
+
+/// The background process whose lines a `for` loop over `iter` reads as
+/// they come, if `iter` is `handle.lines()` for a handle in a variable.
+fn streamed_process(iter: &Expr, runtime: &mut Runtime, agent: Option<&AgentHandle>) -> Option<u64> {
+    let Expr::Call { callee, args } = iter else {
+        return None;
+    };
+    match callee.as_ref() {
+        // Reading a variable has no effects, so it is fine to read it
+        // again if this isn't a handle after all
+        Expr::Member { object, field: "lines" } if args.is_empty() && matches!(object.as_ref(), Expr::Identifier(_)) => {
+            process_id(&eval_expr(object, runtime, agent).ok()?)
+        }
+        _ => None,
+    }
+}
+
+/// Run a `for` loop over the lines a background process writes, as it
+/// writes them.
+fn eval_for_lines(
+    var: &str,
+    id: u64,
+    body: &Block,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    let mut result = Value::Null;
+    let mut index = 0;
+    runtime.enter_loop();
+    while let Some(line) = next_process_line(id, runtime)? {
+        runtime.set_loop_iteration(index);
+        index += 1;
+        runtime.push_scope();
+        runtime.define_var(var, Value::String(line)).map_err(Error::Runtime)?;
+        result = eval_block(body, runtime, agent)?;
+        runtime.pop_scope();
+    }
+    runtime.exit_loop();
+    Ok(result)
+}
+
+/// Wait for the next line a background process writes, or `None` once it
+/// closes its output. Kills the process if the program is cancelled or
+/// runs out of time first.
+fn next_process_line(id: u64, runtime: &mut Runtime) -> Result<Option<String>, Error> {
+    loop {
+        match runtime.processes().get(id).map_err(Error::Runtime)?.next_line(Duration::from_millis(10)) {
+            NextLine::Line(line) => return Ok(Some(line)),
+            NextLine::Done => return Ok(None),
+            NextLine::Pending => {}
+        }
+        if let Err(message) = runtime.check_cancelled().and_then(|()| runtime.check_deadline()) {
+            runtime.processes().remove(id).map_err(Error::Runtime)?.kill();
+            return Err(Error::Runtime(message));
+        }
+    }
+}
+
+/// Evaluate `lines()`, `wait()`, or `kill()` on a background process handle.
+fn eval_process_method(
+    id: u64,
+    method: &str,
+    args: &[Expr],
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<Value, Error> {
+    for arg in args {
+        eval_expr(arg, runtime, agent)?;
+    }
+    if !args.is_empty() {
+        return Err(Error::Runtime(format!("{}() takes no arguments", method)));
+    }
+    match method {
+        "lines" => {
+            let mut lines = Vec::new();
+            while let Some(line) = next_process_line(id, runtime)? {
+                lines.push(Value::String(line));
+            }
+            Ok(Value::Array(lines))
+        }
+        "wait" => {
+            let (_, status) = wait_for_exit(&[id], None, runtime)?.expect("no timeout");
+            finish_process(id, status, runtime)
+        }
+        "kill" => {
+            runtime.processes().remove(id).map_err(Error::Runtime)?.kill();
+            Ok(Value::Null)
+        }
+        other => Err(Error::Runtime(format!("Unknown method on a background process: {}()", other))),
+    }
+}
+
+/// Wait until one of the background processes `ids` exits, returning its
+/// index in `ids` and how it exited, or `None` once `timeout` passes.
+/// Kills them all if the program is cancelled or runs out of time first.
+fn wait_for_exit(
+    ids: &[u64],
+    timeout: Option<Duration>,
+    runtime: &mut Runtime,
+) -> Result<Option<(usize, std::process::ExitStatus)>, Error> {
+    let started = Instant::now();
+    loop {
+        for (index, &id) in ids.iter().enumerate() {
+            let process = runtime.processes().get(id).map_err(Error::Runtime)?;
+            match process.try_wait() {
+                Ok(Some(status)) => return Ok(Some((index, status))),
+                Ok(None) => {}
+                Err(e) => return Err(Error::Runtime(format!("Failed to wait for {}: {}", process.command(), e))),
+            }
+        }
+        if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+            return Ok(None);
+        }
+        if let Err(message) = runtime.check_cancelled().and_then(|()| runtime.sleep(Duration::from_millis(10))) {
+            kill_processes(ids, runtime);
+            return Err(Error::Runtime(message));
+        }
+    }
+}
+
+/// Kill whichever of the background processes `ids` are still running.
+fn kill_processes(ids: &[u64], runtime: &mut Runtime) {
+    for &id in ids {
+        if let Ok(mut process) = runtime.processes().remove(id) {
+            process.kill();
+        }
+    }
+}
+
+/// The output of a background process that has exited, or a `ShellError`
+/// if it exited with an error and the shell isn't lenient.
+fn finish_process(id: u64, status: std::process::ExitStatus, runtime: &mut Runtime) -> Result<Value, Error> {
+    let mut process = runtime.processes().remove(id).map_err(Error::Runtime)?;
+    let (stdout, stderr) = process.finish();
+    runtime.set_last_status(status.code());
+    if !status.success() && !runtime.is_lenient_shell() {
+        return Err(shell_error(process.command(), status, &stderr));
+    }
+    Ok(Value::String(stdout))
+}
+
+/// The process numbers of a list of handles from `$(cmd &)`.
+fn process_ids(handles: &Value, function: &str) -> Result<Vec<u64>, Error> {
+    let Value::Array(handles) = handles else {
+        return Err(Error::Runtime(format!("{}() takes a list of background processes, got {}", function, type_name(handles))));
+    };
+    handles
+        .iter()
+        .map(|handle| {
+            process_id(handle).ok_or_else(|| {
+                Error::Runtime(format!("{}(): expected a background process from $(cmd &), got {}", function, type_name(handle)))
+            })
+        })
+        .collect()
+}
+
+/// `wait_all(handles, { fail_fast })`: wait for every background process
+/// and return their outputs in order.
+///
+/// By default every process runs to the end, and if any failed, a
+/// `WaitAllError` is thrown with each one's `ShellError` in `errors` and
+/// the outputs of the rest in `results`. With `fail_fast`, the first
+/// failure is thrown as it happens and the others are killed.
+fn wait_all(args: &[Value], runtime: &mut Runtime) -> Result<Value, Error> {
+    let (handles, options) = match args {
+        [handles] => (handles, None),
+        [handles, Value::Object(options)] => (handles, Some(options)),
+        _ => {
+            return Err(Error::Runtime(
+                "wait_all() takes a list of background processes and an optional options object".to_string(),
+            ))
+        }
+    };
+    let fail_fast = match options.and_then(|options| options.get("fail_fast")) {
+        None | Some(Value::Null) => false,
+        Some(Value::Boolean(fail_fast)) => *fail_fast,
+        Some(other) => return Err(Error::Runtime(format!("fail_fast must be a boolean, got {}", type_name(other)))),
+    };
+    let ids = process_ids(handles, "wait_all")?;
+
+    let mut results = vec![Value::Null; ids.len()];
+    let mut errors = Vec::new();
+    let mut pending: Vec<usize> = (0..ids.len()).collect();
+    while !pending.is_empty() {
+        let waiting: Vec<u64> = pending.iter().map(|&index| ids[index]).collect();
+        let (next, status) = wait_for_exit(&waiting, None, runtime)?.expect("no timeout");
+        let index = pending.remove(next);
+        match finish_process(ids[index], status, runtime) {
+            Ok(output) => results[index] = output,
+            Err(Error::Exception(error)) if fail_fast => {
+                let rest: Vec<u64> = pending.iter().map(|&index| ids[index]).collect();
+                kill_processes(&rest, runtime);
+                return Err(Error::Exception(error));
+            }
+            Err(Error::Exception(error)) => errors.push(error),
+            Err(e) => return Err(e),
+        }
+    }
+    if errors.is_empty() {
+        return Ok(Value::Array(results));
+    }
+
+    let first = match &errors[0] {
+        Value::Object(fields) => fields.get("message").map(Value::to_string_value).unwrap_or_default(),
+        other => other.to_string_value(),
+    };
+    let message = format!("{} of {} background commands failed; the first: {}", errors.len(), ids.len(), first);
+    let mut fields = IndexMap::new();
+    fields.insert("type".to_string(), Value::String("WaitAllError".to_string()));
+    fields.insert("message".to_string(), Value::String(message));
+    fields.insert("errors".to_string(), Value::Array(errors));
+    fields.insert("results".to_string(), Value::Array(results));
+    Err(Error::Exception(Value::Object(fields)))
+}
+
+/// Deal with the background processes still running when a program ends,
+/// as the runtime's `BackgroundPolicy` says: kill them, with a warning for
+/// each, or wait for them like `wait_all`. Processes that have already
+/// exited, but weren't waited for, are let go either way.
+pub(crate) fn finish_background(runtime: &mut Runtime) -> Result<(), Error> {
+    if runtime.background_policy() == BackgroundPolicy::Wait {
+        let handles = runtime.processes().handles();
+        if !handles.is_empty() {
+            wait_all(&[Value::Array(handles)], runtime)?;
+        }
+        return Ok(());
+    }
+    for mut process in runtime.processes().drain() {
+        if let Ok(None) = process.try_wait() {
+            process.kill();
+            let message = format!("`{}` was still running when the program ended, so it was killed", process.command());
+            runtime.warn("background", message);
+        }
+    }
+    Ok(())
+}
+
+/// `wait_any(handles, timeout?)`: wait for the first of several background
+/// processes to exit, returning `{ index, output }` for it, or null if
+/// none has within `timeout`. The others keep running.
+fn wait_any(args: &[Value], runtime: &mut Runtime) -> Result<Value, Error> {
+    let (handles, timeout) = match args {
+        [handles] | [handles, Value::Null] => (handles, None),
+        [handles, timeout] => (handles, Some(timer::parse_duration(timeout).map_err(Error::Runtime)?)),
+        _ => {
+            return Err(Error::Runtime(
+                "wait_any() takes a list of background processes and an optional timeout".to_string(),
+            ))
+        }
+    };
+    let ids = process_ids(handles, "wait_any")?;
+    if ids.is_empty() {
+        return Err(Error::Runtime("wait_any() needs at least one background process".to_string()));
+    }
+    let Some((index, status)) = wait_for_exit(&ids, timeout, runtime)? else {
+        return Ok(Value::Null);
+    };
+    let output = finish_process(ids[index], status, runtime)?;
+    let mut fields = IndexMap::new();
+    fields.insert("index".to_string(), Value::Number(index as f64));
+    fields.insert("output".to_string(), output);
+    Ok(Value::Object(fields))
+}
+
+/// Run a command to completion and collect its output, killing it if the
+/// deadline passes or the program is cancelled first.
+///
+/// The outer error stops the program; the inner one is a failure to run the
+/// command at all.
+fn command_output(command: &mut Command, runtime: &Runtime) -> Result<std::io::Result<Output>, Error> {
+    if runtime.deadline().is_none() {
+        return Ok(command.output());
+    }
+
+    let mut child = match command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => return Ok(Err(e)),
+    };
+    // Drain the pipes on their own threads so a chatty command can't block
+    // on a full pipe while we wait for it
+    fn drain(pipe: Option<impl Read + Send + 'static>) -> std::thread::JoinHandle<Vec<u8>> {
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            buf
+        })
+    }
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) => {}
+            Err(e) => return Ok(Err(e)),
+        }
+        let stopped = runtime
+            .check_cancelled()
+            .and_then(|()| runtime.sleep(Duration::from_millis(10)));
+        if let Err(message) = stopped {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(Error::Runtime(message));
+        }
+    };
+
+    Ok(Ok(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    }))
+}
+
+/// Perform the file write of a `>` or `>>` redirect as a journaled effect,
+/// keyed by the command being redirected.
+fn redirect_effect(
+    command: &Expr,
+    runtime: &mut Runtime,
+    write: impl FnOnce(&mut Runtime) -> Result<Value, Error>,
+) -> Result<Value, Error> {
+    let site = match command {
+        Expr::BareCommand { name, .. } | Expr::Identifier(name) => Some(*name),
+        Expr::Call { callee, .. } => match callee.as_ref() {
+            Expr::Identifier(name) => Some(*name),
+            _ => None,
+        },
+        _ => None,
+    };
+    match site {
+        Some(site) => runtime.perform_effect("redirect", site, write),
+        None => write(runtime),
+    }
+}
+
 /// Evaluate a shell redirect expression.
 fn eval_shell_redirect(
     command: &Expr,
@@ -850,7 +2346,7 @@ fn eval_shell_redirect(
             // Read from file and use as input
             // For `json < "file.json"`, we read the file and parse as JSON
             let target_value = eval_expr(target, runtime, agent)?;
-            let path = resolve_path(&target_value.to_string_value(), runtime);
+            let path = runtime.resolve_path(&target_value.to_string_value()).map_err(Error::Runtime)?;
             let contents = fs::read_to_string(&path)
                 .map_err(|e| Error::Runtime(format!("Failed to read {}: {}", path.display(), e)))?;
 
@@ -873,9 +2369,10 @@ fn eval_shell_redirect(
 
         RedirectOp::Out => {
             // Write command output to file
+            runtime.check_file_write().map_err(Error::Runtime)?;
             let cmd_result = eval_expr(command, runtime, agent)?;
             let target_value = eval_expr(target, runtime, agent)?;
-            let path = resolve_path(&target_value.to_string_value(), runtime);
+            let path = runtime.resolve_path(&target_value.to_string_value()).map_err(Error::Runtime)?;
 
             // If the command was cat(), write as JSON
             let content = if let Expr::Call { callee, .. } = command {
@@ -888,25 +2385,27 @@ fn eval_shell_redirect(
                 cmd_result.to_string_value()
             };
 
-            fs::write(&path, content)
-                .map_err(|e| Error::Runtime(format!("Failed to write {}: {}", path.display(), e)))?;
-
-            Ok(Value::Null)
+            redirect_effect(command, runtime, |_| {
+                fs::write(&path, content)
+                    .map_err(|e| Error::Runtime(format!("Failed to write {}: {}", path.display(), e)))?;
+                Ok(Value::Null)
+            })
         }
 
         RedirectOp::Append => {
             // Append command output to file
+            runtime.check_file_write().map_err(Error::Runtime)?;
             let cmd_result = eval_expr(command, runtime, agent)?;
             let target_value = eval_expr(target, runtime, agent)?;
-            let path = resolve_path(&target_value.to_string_value(), runtime);
-
-            let existing = fs::read_to_string(&path).unwrap_or_default();
-            let content = format!("{}{}", existing, cmd_result.to_string_value());
+            let path = runtime.resolve_path(&target_value.to_string_value()).map_err(Error::Runtime)?;
 
-            fs::write(&path, content)
-                .map_err(|e| Error::Runtime(format!("Failed to write {}: {}", path.display(), e)))?;
-
-            Ok(Value::Null)
+            redirect_effect(command, runtime, |_| {
+                let existing = fs::read_to_string(&path).unwrap_or_default();
+                let content = format!("{}{}", existing, cmd_result.to_string_value());
+                fs::write(&path, content)
+                    .map_err(|e| Error::Runtime(format!("Failed to write {}: {}", path.display(), e)))?;
+                Ok(Value::Null)
+            })
         }
 
         RedirectOp::ErrOut | RedirectOp::ErrToOut => {
@@ -916,18 +2415,113 @@ fn eval_shell_redirect(
     }
 }
 
-/// Resolve a path relative to the runtime's working directory.
-fn resolve_path(path: &str, runtime: &Runtime) -> std::path::PathBuf {
-    let p = std::path::Path::new(path);
-    if p.is_absolute() {
-        p.to_path_buf()
-    } else {
-        runtime.working_dir().join(p)
+/// Read a prompt fragment, replacing each `@include("path")` line with the
+/// named file's contents.
+///
+/// Included paths are relative to the including file. `stack` holds the
+/// files being expanded, to reject include cycles.
+fn expand_prompt_file(path: &Path, runtime: &Runtime, stack: &mut Vec<PathBuf>) -> Result<String, String> {
+    if stack.iter().any(|p| p == path) {
+        return Err(format!("Prompt include cycle at {}", path.display()));
+    }
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    stack.push(path.to_path_buf());
+    let mut output = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let include = line
+            .trim()
+            .strip_prefix("@include(")
+            .and_then(|rest| rest.strip_suffix(')'))
+            .and_then(|arg| arg.trim().strip_prefix('"')?.strip_suffix('"'));
+        match include {
+            Some(target) => {
+                let target = runtime.resolve_path(&base.join(target).to_string_lossy())?;
+                let fragment = expand_prompt_file(&target, runtime, stack)?;
+                output.push_str(fragment.trim_end_matches('\n'));
+                if line.ends_with('\n') {
+                    output.push('\n');
+                }
+            }
+            None => output.push_str(line),
+        }
+    }
+    stack.pop();
+
+    // Fragments are spliced into prompts, so drop the file's final newline
+    if output.ends_with('\n') {
+        output.pop();
+    }
+    Ok(output)
+}
+
+/// Substitute `{name}` placeholders in a template with fields of `vars`.
+///
+/// Placeholders may name nested fields (`{user.name}`), and `{{` / `}}`
+/// produce literal braces. Braces around anything that isn't a field path,
+/// like a JSON example in a prompt, are left alone.
+fn fill_template(template: &str, vars: &Value) -> Result<String, String> {
+    let Value::Object(_) = vars else {
+        return Err(format!("Template variables must be an object, got {}", type_name(vars)));
+    };
+
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(at) = rest.find(['{', '}']) {
+        output.push_str(&rest[..at]);
+        rest = &rest[at..];
+
+        if let Some(after) = rest.strip_prefix("{{").or_else(|| rest.strip_prefix("}}")) {
+            output.push_str(&rest[..1]);
+            rest = after;
+            continue;
+        }
+
+        let path = rest[1..].find('}').map(|end| &rest[1..end + 1]).filter(|path| {
+            rest.starts_with('{')
+                && !path.is_empty()
+                && path.split('.').all(|part| {
+                    part.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                        && part.chars().all(|c| c.is_alphanumeric() || c == '_')
+                })
+        });
+        let Some(path) = path else {
+            output.push_str(&rest[..1]);
+            rest = &rest[1..];
+            continue;
+        };
+
+        let mut value = vars;
+        for part in path.split('.') {
+            value = match value {
+                Value::Object(fields) => fields.get(part),
+                _ => None,
+            }
+            .ok_or_else(|| format!("Missing template variable: {}", path))?;
+        }
+        output.push_str(&value.to_string_value());
+        rest = &rest[path.len() + 2..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Read a value used as a condition. Strict mode only accepts booleans and
+/// null, instead of treating empty strings and arrays as false.
+fn condition_value(value: &Value, runtime: &Runtime) -> Result<bool, Error> {
+    if runtime.is_strict() && !matches!(value, Value::Boolean(_) | Value::Null) {
+        return Err(Error::Runtime(format!(
+            "Strict mode: {} used as a condition; compare it explicitly",
+            type_name(value)
+        )));
     }
+    Ok(value.to_bool())
 }
 
 /// Get the type name of a value for error messages.
-fn type_name(value: &Value) -> &'static str {
+pub(crate) fn type_name(value: &Value) -> &'static str {
     match value {
         Value::Null => "null",
         Value::String(_) => "string",
@@ -935,6 +2529,8 @@ fn type_name(value: &Value) -> &'static str {
         Value::Boolean(_) => "boolean",
         Value::Array(_) => "array",
         Value::Object(_) => "object",
+        Value::Set(_) => "set",
+        Value::Tuple(_) => "tuple",
     }
 }
 
@@ -1041,13 +2637,13 @@ mod tests {
 
     #[test]
     fn test_eval_builtin_cat() {
-        let rt = Runtime::default();
+        let mut rt = Runtime::default();
         let input = Value::Object(
             [("name".to_string(), Value::String("test".to_string()))]
                 .into_iter()
                 .collect(),
         );
-        let value = eval_builtin("cat", &[input], &rt).unwrap();
+        let value = eval_builtin("cat", &[input], &mut rt).unwrap();
         if let Value::String(s) = value {
             assert!(s.contains("\"name\""));
             assert!(s.contains("\"test\""));
@@ -1058,8 +2654,8 @@ mod tests {
 
     #[test]
     fn test_eval_builtin_json() {
-        let rt = Runtime::default();
-        let value = eval_builtin("json", &[Value::String(r#"{"x": 1}"#.to_string())], &rt).unwrap();
+        let mut rt = Runtime::default();
+        let value = eval_builtin("json", &[Value::String(r#"{"x": 1}"#.to_string())], &mut rt).unwrap();
         if let Value::Object(obj) = value {
             assert_eq!(obj.get("x"), Some(&Value::Number(1.0)));
         } else {
@@ -1084,4 +2680,48 @@ mod tests {
             other => panic!("Expected Exception, got {:?}", other),
         }
     }
+
+    #[test]
+    fn test_fill_template() {
+        let vars = Value::from_json(r#"{"name": "Ada", "user": {"role": "admin"}, "n": 3}"#).unwrap();
+        assert_eq!(
+            fill_template("Hi {name} ({user.role}), {n} new", &vars),
+            Ok("Hi Ada (admin), 3 new".to_string())
+        );
+        assert_eq!(
+            fill_template(r#"{{name}} and {"key": 1}"#, &vars),
+            Ok(r#"{name} and {"key": 1}"#.to_string())
+        );
+        assert_eq!(
+            fill_template("Hi {nickname}", &vars),
+            Err("Missing template variable: nickname".to_string())
+        );
+        assert!(fill_template("Hi", &Value::Null).is_err());
+    }
+
+    #[test]
+    fn test_eval_patchwork() {
+        let mut rt = make_runtime();
+        let code = |code: &str| Value::String(code.to_string());
+        let value = eval_patchwork(&[code("var n = 20\nn * 2 + 2")], &mut rt, None).unwrap();
+        assert_eq!(value, Value::Number(42.0));
+
+        // The snippet can't write files unless the options grant it
+        let result = eval_patchwork(&[code(r#"write("out.txt", "hi")"#)], &mut rt, None);
+        assert!(matches!(&result, Err(Error::Runtime(msg)) if msg.contains("capability policy")), "{:?}", result);
+
+        let options = Value::from_json(r#"{"limits": {"max_think_calls": 1}}"#).unwrap();
+        let result = eval_patchwork(&[code("think { a }\nthink { b }"), options], &mut rt, None);
+        assert!(matches!(&result, Err(Error::Runtime(msg)) if msg.contains("limit of 1 think blocks")), "{:?}", result);
+
+        let options = Value::from_json(r#"{"timeout": 5}"#).unwrap();
+        let result = eval_patchwork(&[code("1"), options], &mut rt, None);
+        assert!(matches!(&result, Err(Error::Runtime(msg)) if msg.contains("unknown option `timeout`")), "{:?}", result);
+
+        let mut config = Config::default();
+        config.capabilities.eval_code = Permission::Deny;
+        rt.apply_config(&config);
+        let result = eval_patchwork(&[code("1")], &mut rt, None);
+        assert!(matches!(&result, Err(Error::Runtime(msg)) if msg.contains("capability policy")), "{:?}", result);
+    }
 }
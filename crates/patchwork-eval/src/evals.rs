@@ -0,0 +1,305 @@
+//! Scoring LLM output across a dataset.
+//!
+//! An `eval` declaration scores a prompt's output on every case of a
+//! dataset, so prompt changes can be regression-tested even though the
+//! output never matches a snapshot exactly:
+//!
+//! ```text
+//! import data "./issues.json"
+//!
+//! eval "summary quality" {
+//!     input issues
+//!     output think { Summarize ${input.body} in one line. }
+//!     expect_contains input.component
+//!     judge think { Rate from 0 to 1 how well "${output}" summarizes: ${input.body} }
+//! }
+//! ```
+//!
+//! `Interpreter::run_evals` runs every eval in a program and returns an
+//! `EvalReport`, whose `table` is what a host prints. Each check scores a
+//! case from 0 to 1:
+//!
+//! - `expect` scores 1 if the output matches a declared type
+//! - `expect_contains` scores 1 if the output contains the text, ignoring case
+//! - `judge` scores whatever its expression answers: a number, a boolean,
+//!   text holding a number (as a model usually answers), or an object with
+//!   a `score` field
+//!
+//! A case passes when every check scores at least `PASS_SCORE`. A case whose
+//! output or checks fail with an error scores 0 and keeps the error; running
+//! out of budget or being cancelled stops the whole run.
+
+use std::fmt::Write;
+
+use patchwork_parser::ast::{EvalCheck, EvalDecl};
+
+use crate::agent::AgentHandle;
+use crate::error::Error;
+use crate::eval::eval_expr;
+use crate::runtime::{Runtime, CANCELLED_MESSAGE, DEADLINE_MESSAGE};
+use crate::value::Value;
+
+/// The lowest score a check can give a passing case.
+pub const PASS_SCORE: f64 = 0.5;
+
+/// One case of an eval.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseResult {
+    pub input: Value,
+    /// The output, unless computing it failed.
+    pub output: Option<Value>,
+    /// Each check's score, in declaration order.
+    pub scores: Vec<f64>,
+    pub error: Option<String>,
+}
+
+impl CaseResult {
+    /// The mean of the checks' scores; 0 after an error.
+    pub fn score(&self) -> f64 {
+        if self.error.is_some() {
+            0.0
+        } else if self.scores.is_empty() {
+            1.0
+        } else {
+            self.scores.iter().sum::<f64>() / self.scores.len() as f64
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.scores.iter().all(|score| *score >= PASS_SCORE)
+    }
+}
+
+/// The cases of one `eval` declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalResult {
+    pub name: String,
+    pub cases: Vec<CaseResult>,
+}
+
+impl EvalResult {
+    /// The mean case score.
+    pub fn score(&self) -> f64 {
+        if self.cases.is_empty() {
+            return 0.0;
+        }
+        self.cases.iter().map(CaseResult::score).sum::<f64>() / self.cases.len() as f64
+    }
+
+    pub fn passed(&self) -> usize {
+        self.cases.iter().filter(|case| case.passed()).count()
+    }
+
+    pub fn errors(&self) -> usize {
+        self.cases.iter().filter(|case| case.error.is_some()).count()
+    }
+}
+
+/// Every eval in a program.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct EvalReport {
+    pub evals: Vec<EvalResult>,
+}
+
+impl EvalReport {
+    /// Did every case of every eval pass?
+    pub fn passed(&self) -> bool {
+        self.evals.iter().all(|eval| eval.passed() == eval.cases.len())
+    }
+
+    /// One row per eval:
+    ///
+    /// ```text
+    /// eval             cases  passed  errors  score
+    /// summary quality      2       1       0   0.65
+    /// ```
+    pub fn table(&self) -> String {
+        let width = self.evals.iter().map(|eval| eval.name.chars().count()).max().unwrap_or(0).max(4);
+        let mut out = String::new();
+        let _ = writeln!(out, "{:<width$}  cases  passed  errors  score", "eval", width = width);
+        for eval in &self.evals {
+            let _ = writeln!(
+                out,
+                "{:<width$}  {:>5}  {:>6}  {:>6}  {:>5.2}",
+                eval.name,
+                eval.cases.len(),
+                eval.passed(),
+                eval.errors(),
+                eval.score(),
+                width = width
+            );
+        }
+        out
+    }
+}
+
+/// Run one eval over its dataset.
+pub(crate) fn run_eval(decl: &EvalDecl, runtime: &mut Runtime, agent: Option<&AgentHandle>) -> Result<EvalResult, Error> {
+    let cases = match eval_expr(&decl.input, runtime, agent)? {
+        Value::Array(cases) => cases,
+        other => {
+            return Err(Error::Runtime(format!(
+                "eval \"{}\": input must be an array of cases, got {}",
+                decl.name,
+                other.to_string_value()
+            )))
+        }
+    };
+    let mut result = EvalResult { name: decl.name.to_string(), cases: Vec::new() };
+    for input in cases {
+        runtime.check_cancelled().map_err(Error::Runtime)?;
+        let mut case = CaseResult { input, output: None, scores: Vec::new(), error: None };
+        // A scope per case, matching the one the resolver gives `input`
+        // and `output`
+        runtime.push_scope();
+        let scored = score_case(decl, &mut case, runtime, agent);
+        runtime.pop_scope();
+        match scored {
+            Ok(()) => {}
+            Err(e) if is_fatal(&e) => return Err(e),
+            Err(e) => case.error = Some(e.to_string()),
+        }
+        result.cases.push(case);
+    }
+    Ok(result)
+}
+
+fn score_case(
+    decl: &EvalDecl,
+    case: &mut CaseResult,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+) -> Result<(), Error> {
+    runtime.define_var("input", case.input.clone()).map_err(Error::Runtime)?;
+    let output = eval_expr(&decl.output, runtime, agent)?;
+    case.output = Some(output.clone());
+    let text = output.to_string_value().to_lowercase();
+    runtime.define_var("output", output).map_err(Error::Runtime)?;
+
+    for check in &decl.checks {
+        let score = match check {
+            EvalCheck::Contains(expr) => {
+                let expected = eval_expr(expr, runtime, agent)?.to_string_value().to_lowercase();
+                if text.contains(&expected) { 1.0 } else { 0.0 }
+            }
+            EvalCheck::Judge(expr) => judge_score(&eval_expr(expr, runtime, agent)?)?,
+            EvalCheck::Expect(type_name) => {
+                let output = case.output.as_ref().expect("computed above");
+                let mismatches = runtime.schemas().check(type_name, output).map_err(Error::Runtime)?;
+                if mismatches.is_empty() { 1.0 } else { 0.0 }
+            }
+        };
+        case.scores.push(score);
+    }
+    Ok(())
+}
+
+/// Read a judge's answer as a score from 0 to 1.
+fn judge_score(answer: &Value) -> Result<f64, Error> {
+    let score = match answer {
+        Value::Number(n) => Some(*n),
+        Value::Boolean(b) => Some(if *b { 1.0 } else { 0.0 }),
+        Value::String(s) => s.trim().parse::<f64>().ok(),
+        Value::Object(fields) if fields.contains_key("score") => return judge_score(&fields["score"]),
+        _ => None,
+    };
+    match score {
+        Some(score) if score.is_finite() => Ok(score.clamp(0.0, 1.0)),
+        _ => Err(Error::Runtime(format!(
+            "judge answered `{}`, which is not a score from 0 to 1",
+            answer.to_string_value()
+        ))),
+    }
+}
+
+/// Errors that stop the whole run instead of failing one case.
+fn is_fatal(error: &Error) -> bool {
+    match error {
+        Error::BudgetExceeded(_) => true,
+        Error::Runtime(msg) => msg.contains(CANCELLED_MESSAGE) || msg.contains(DEADLINE_MESSAGE),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+
+    #[test]
+    fn test_run_evals() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<crate::ThinkRequest>();
+        let agent = std::thread::spawn(move || {
+            while let Some(request) = rx.blocking_recv() {
+                // Judges rate everything 0.8; summaries echo the prompt
+                let answer = if request.prompt.contains("Rate") { "0.8".to_string() } else { request.prompt.clone() };
+                let result = Ok(Value::String(answer));
+                let _ = request.response_tx.send(crate::ThinkResponse::Complete { result });
+            }
+        });
+
+        let mut interp = Interpreter::with_agent(AgentHandle::new(tx));
+        let report = interp
+            .run_evals(
+                r#"
+                const CASES = [
+                    { issue: "App crashes on start", keyword: "CRASH" },
+                    { issue: "Typo in the guide", keyword: "docs" }
+                ]
+
+                eval "summary quality" {
+                    input CASES
+                    output think { Summarize ${input.issue} in one line. }
+                    expect_contains input.keyword
+                    judge think { Rate from 0 to 1 how well "${output}" summarizes it. }
+                }
+                "#,
+            )
+            .unwrap();
+
+        let eval = &report.evals[0];
+        assert_eq!(eval.name, "summary quality");
+        assert_eq!(eval.cases[0].scores, vec![1.0, 0.8]);
+        assert_eq!(eval.cases[1].scores, vec![0.0, 0.8]);
+        assert_eq!(eval.passed(), 1);
+        assert!(!report.passed());
+        assert!((eval.score() - 0.65).abs() < 1e-9);
+        assert_eq!(
+            report.table(),
+            "eval             cases  passed  errors  score\nsummary quality      2       1       0   0.65\n"
+        );
+
+        drop(interp);
+        agent.join().unwrap();
+    }
+
+    #[test]
+    fn test_expect_type() {
+        let mut interp = Interpreter::new();
+        let report = interp
+            .run_evals(
+                r#"
+                type Label = { name: string, confidence: number }
+
+                eval "labels" {
+                    input [{ name: "bug", confidence: 0.9 }, { name: "docs" }]
+                    output input
+                    expect Label
+                }
+                "#,
+            )
+            .unwrap();
+        let scores: Vec<_> = report.evals[0].cases.iter().map(|case| case.scores.clone()).collect();
+        assert_eq!(scores, vec![vec![1.0], vec![0.0]]);
+    }
+
+    #[test]
+    fn test_judge_scores() {
+        assert_eq!(judge_score(&Value::String(" 0.7\n".to_string())).unwrap(), 0.7);
+        assert_eq!(judge_score(&Value::Boolean(true)).unwrap(), 1.0);
+        assert_eq!(judge_score(&Value::Number(7.0)).unwrap(), 1.0);
+        let object = Value::from_json(r#"{"score": 0.25, "reason": "vague"}"#).unwrap();
+        assert_eq!(judge_score(&object).unwrap(), 0.25);
+        assert!(judge_score(&Value::String("pretty good".to_string())).is_err());
+    }
+}
@@ -0,0 +1,498 @@
+//! Front matter: what a program needs from the host that runs it, written
+//! at the top of the file so it travels with the program.
+//!
+//! ```text
+//! ---
+//! # Defaults for entry point parameters the caller leaves out
+//! params:
+//!   audience: engineers
+//!   days: 7
+//! # Models to try when the host's config names none
+//! model: [claude-sonnet, claude-haiku]
+//! # Limits on top of the host's, with the same names as in the config
+//! limits:
+//!   max_llm_calls: 20
+//! # Capabilities the program can't do without
+//! requires: [shell, file_write]
+//! ---
+//! skill report(audience, days) { ... }
+//! ```
+//!
+//! The block is YAML, of the kind people write by hand: nested mappings
+//! and `- item` lists, `[a, b]` and `{ a: 1 }` collections, quoted and
+//! plain scalars, and `#` comments. Anchors, tags, and multi-line strings
+//! aren't supported.
+//!
+//! Front matter can only narrow what a host allows: its limits apply as
+//! well as the host's, and a required capability the policy denies fails
+//! the run up front instead of partway through.
+
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Number, Value as JsonValue};
+
+use crate::config::{CapabilityPolicy, Config, ConfigLayer, Limits, Permission};
+use crate::value::Value;
+
+/// A program's front matter.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FrontMatter {
+    /// Defaults for entry point parameters, by parameter name.
+    pub params: BTreeMap<String, Value>,
+    /// Models think blocks try, first to last, when the host's config names none.
+    pub models: Vec<String>,
+    /// Limits the program runs within, as well as the host's.
+    pub limits: Limits,
+    /// Capabilities the program needs, by their config names, e.g. `shell`.
+    pub requires: Vec<String>,
+}
+
+impl FrontMatter {
+    /// Read the front matter at the start of `source`, if it has any.
+    pub fn from_source(source: &str) -> Result<Option<FrontMatter>, String> {
+        let Some(block) = patchwork_parser::front_matter::find(source) else {
+            return Ok(None);
+        };
+        let first_line = source[..block.offset].lines().count() + 1;
+        Self::parse(block.text, first_line).map(Some)
+    }
+
+    /// Read front matter from the text between its delimiters, which
+    /// starts on line `first_line` of the file.
+    pub fn parse(text: &str, first_line: usize) -> Result<FrontMatter, String> {
+        let root = parse_yaml(text, first_line).map_err(|e| format!("front matter {}", e))?;
+        let root = match root {
+            JsonValue::Null => return Ok(FrontMatter::default()),
+            JsonValue::Object(root) => root,
+            _ => return Err("front matter: expected `key: value` lines".to_string()),
+        };
+
+        let mut front = FrontMatter::default();
+        for (key, value) in root {
+            match key.as_str() {
+                "params" => {
+                    let JsonValue::Object(params) = value else {
+                        return Err("front matter (params): expected a mapping of parameter names".to_string());
+                    };
+                    front.params = params.into_iter().map(|(name, value)| (name, Value::from_json_value(value))).collect();
+                }
+                "model" => {
+                    front.models = match value {
+                        JsonValue::String(model) => vec![model],
+                        JsonValue::Array(models) => models
+                            .into_iter()
+                            .map(|model| match model {
+                                JsonValue::String(model) => Ok(model),
+                                _ => Err("front matter (model): expected model names".to_string()),
+                            })
+                            .collect::<Result<_, _>>()?,
+                        _ => return Err("front matter (model): expected a model name or a list of them".to_string()),
+                    }
+                }
+                "limits" => {
+                    // Read like the config file's `limits`, so they are named and checked the same
+                    let mut limits = Map::new();
+                    limits.insert("limits".to_string(), value);
+                    let layer = ConfigLayer::from_value(&JsonValue::Object(limits), "front matter")
+                        .map_err(|e| e.to_string())?;
+                    let mut config = Config::default();
+                    config.merge(&layer);
+                    front.limits = config.limits;
+                }
+                "requires" => {
+                    let known = CapabilityPolicy::default();
+                    let names = match value {
+                        JsonValue::Array(names) => names,
+                        name => vec![name],
+                    };
+                    for name in names {
+                        match name {
+                            JsonValue::String(name) if known.permission(&name).is_some() => front.requires.push(name),
+                            name => return Err(format!("front matter (requires): unknown capability {}", name)),
+                        }
+                    }
+                }
+                _ => return Err(format!("front matter ({}): unknown key", key)),
+            }
+        }
+        Ok(front)
+    }
+
+    /// Check that `policy` doesn't deny any capability this program
+    /// requires. Those it only allows after asking are fine; the program
+    /// will ask when it gets there.
+    pub fn check(&self, policy: &CapabilityPolicy) -> Result<(), String> {
+        for name in &self.requires {
+            if policy.permission(name) == Some(Permission::Deny) {
+                return Err(format!("the program requires `{}`, which the capability policy denies", name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A line of the block with something on it, comments removed.
+struct Line<'a> {
+    number: usize,
+    indent: usize,
+    text: &'a str,
+}
+
+/// Read a YAML document as JSON. Errors start with the line they are on.
+fn parse_yaml(text: &str, first_line: usize) -> Result<JsonValue, String> {
+    let mut lines = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let number = first_line + i;
+        let content = strip_comment(line);
+        if content.trim().is_empty() {
+            continue;
+        }
+        if content.starts_with('\t') {
+            return Err(format!("line {}: indent with spaces, not tabs", number));
+        }
+        let indent = content.len() - content.trim_start().len();
+        lines.push(Line { number, indent, text: content.trim() });
+    }
+
+    let Some(first) = lines.first() else {
+        return Ok(JsonValue::Null);
+    };
+    let mut pos = 0;
+    let value = block(&lines, &mut pos, first.indent)?;
+    match lines.get(pos) {
+        Some(line) => Err(format!("line {}: unexpected indentation", line.number)),
+        None => Ok(value),
+    }
+}
+
+/// `line` without a trailing `# comment`. A `#` starts a comment at the
+/// start of the line or after whitespace, outside quoted scalars.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            // Quotes only open a scalar, so apostrophes in plain text are just text
+            None if (c == '"' || c == '\'') && (previous.is_whitespace() || "[{,".contains(previous)) => {
+                quote = Some(c)
+            }
+            None if c == '#' && previous.is_whitespace() => return &line[..i],
+            None => {}
+        }
+        previous = c;
+    }
+    line
+}
+
+/// A mapping or list made of the lines at `indent`, starting at `pos`.
+fn block(lines: &[Line], pos: &mut usize, indent: usize) -> Result<JsonValue, String> {
+    if list_item(lines[*pos].text).is_none() {
+        return mapping(lines, pos, indent);
+    }
+    let items = list(lines, pos, indent)?;
+    match lines.get(*pos).filter(|line| line.indent == indent) {
+        Some(line) => Err(format!("line {}: expected `- item`", line.number)),
+        None => Ok(items),
+    }
+}
+
+/// The `- item` lines at `indent`, starting at `pos`.
+fn list(lines: &[Line], pos: &mut usize, indent: usize) -> Result<JsonValue, String> {
+    let mut items = Vec::new();
+    while let Some(line) = lines.get(*pos).filter(|line| line.indent == indent) {
+        let Some(rest) = list_item(line.text) else {
+            break;
+        };
+        *pos += 1;
+        let item = if rest.is_empty() { nested(lines, pos, indent)? } else { scalar(rest, line.number)? };
+        items.push(item);
+    }
+    Ok(JsonValue::Array(items))
+}
+
+fn mapping(lines: &[Line], pos: &mut usize, indent: usize) -> Result<JsonValue, String> {
+    let mut map = Map::new();
+    while let Some(line) = lines.get(*pos).filter(|line| line.indent == indent) {
+        let (key, rest) = split_key(line.text, line.number)?;
+        *pos += 1;
+        let value = if !rest.is_empty() {
+            scalar(rest, line.number)?
+        } else if lines.get(*pos).is_some_and(|next| next.indent == indent && list_item(next.text).is_some()) {
+            // A list may sit at its key's own indentation
+            list(lines, pos, indent)?
+        } else {
+            nested(lines, pos, indent)?
+        };
+        if map.insert(key.clone(), value).is_some() {
+            return Err(format!("line {}: `{}` is set twice", line.number, key));
+        }
+    }
+    Ok(JsonValue::Object(map))
+}
+
+/// The block indented under the line before `pos`, or null if there is none.
+fn nested(lines: &[Line], pos: &mut usize, indent: usize) -> Result<JsonValue, String> {
+    match lines.get(*pos) {
+        Some(next) if next.indent > indent => block(lines, pos, next.indent),
+        _ => Ok(JsonValue::Null),
+    }
+}
+
+/// The rest of a `- item` line, if it is one.
+fn list_item(text: &str) -> Option<&str> {
+    match text.strip_prefix('-') {
+        Some(rest) if rest.is_empty() || rest.starts_with(' ') => Some(rest.trim_start()),
+        _ => None,
+    }
+}
+
+/// Split `key: value` into the key and the (possibly empty) value.
+fn split_key(text: &str, number: usize) -> Result<(String, &str), String> {
+    let mut reader = Flow { text, pos: 0, number };
+    let key = reader.key()?;
+    match reader.rest().strip_prefix(':') {
+        Some(rest) if rest.is_empty() || rest.starts_with(' ') => Ok((key, rest.trim())),
+        _ => Err(format!("line {}: expected `key: value`", number)),
+    }
+}
+
+/// A value written on one line, which must use all of `text`.
+fn scalar(text: &str, number: usize) -> Result<JsonValue, String> {
+    let mut reader = Flow { text, pos: 0, number };
+    let value = reader.value(false)?;
+    match reader.rest() {
+        "" => Ok(value),
+        rest => Err(format!("line {}: unexpected `{}`", number, rest)),
+    }
+}
+
+/// Reads values written on one line: scalars and `[...]` and `{...}`
+/// collections.
+struct Flow<'a> {
+    text: &'a str,
+    pos: usize,
+    number: usize,
+}
+
+impl<'a> Flow<'a> {
+    /// What is left, without leading spaces.
+    fn rest(&mut self) -> &'a str {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+        &self.text[self.pos..]
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("line {}: {}", self.number, message)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.rest().starts_with(c);
+        if found {
+            self.pos += c.len_utf8();
+        }
+        found
+    }
+
+    /// A value; `in_collection` stops plain scalars at `,`, `]`, and `}`.
+    fn value(&mut self, in_collection: bool) -> Result<JsonValue, String> {
+        if self.eat('[') {
+            let mut items = Vec::new();
+            while !self.eat(']') {
+                items.push(self.value(true)?);
+                if !self.eat(',') && !self.rest().starts_with(']') {
+                    return Err(self.error("expected `,` or `]`"));
+                }
+            }
+            return Ok(JsonValue::Array(items));
+        }
+        if self.eat('{') {
+            let mut map = Map::new();
+            while !self.eat('}') {
+                let key = self.key()?;
+                if !self.eat(':') {
+                    return Err(self.error("expected `:`"));
+                }
+                let value = self.value(true)?;
+                map.insert(key, value);
+                if !self.eat(',') && !self.rest().starts_with('}') {
+                    return Err(self.error("expected `,` or `}`"));
+                }
+            }
+            return Ok(JsonValue::Object(map));
+        }
+        match self.rest().chars().next() {
+            Some('"' | '\'') => self.quoted().map(JsonValue::String),
+            Some(_) => {
+                let stops: &[char] = if in_collection { &[',', ']', '}'] } else { &[] };
+                let text = self.plain(stops);
+                Ok(plain_value(text))
+            }
+            None => Err(self.error("expected a value")),
+        }
+    }
+
+    /// A mapping key: quoted, or plain up to the `:`.
+    fn key(&mut self) -> Result<String, String> {
+        match self.rest().chars().next() {
+            Some('"' | '\'') => self.quoted(),
+            _ => match self.plain(&[':', ',', '}']) {
+                "" => Err(self.error("expected a key")),
+                key => Ok(key.to_string()),
+            },
+        }
+    }
+
+    /// Plain text up to the end or one of `stops`, trimmed.
+    fn plain(&mut self, stops: &[char]) -> &'a str {
+        let rest = self.rest();
+        let len = rest.find(|c| stops.contains(&c)).unwrap_or(rest.len());
+        let start = self.pos;
+        self.pos += len;
+        self.text[start..start + len].trim_end()
+    }
+
+    /// A `"double"` quoted string, with JSON escapes, or a `'single'`
+    /// quoted one, where `''` is a quote.
+    fn quoted(&mut self) -> Result<String, String> {
+        let rest = self.rest();
+        let quote = rest.chars().next().expect("callers check for a quote");
+        let mut escaped = false;
+        let mut end = None;
+        let mut chars = rest.char_indices().skip(1).peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if quote == '"' => escaped = true,
+                '\'' if quote == '\'' && chars.peek().is_some_and(|&(_, next)| next == '\'') => {
+                    chars.next();
+                }
+                c if c == quote => {
+                    end = Some(i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let end = end.ok_or_else(|| self.error("unterminated string"))?;
+        let literal = &rest[..=end];
+        self.pos += end + 1;
+        if quote == '"' {
+            serde_json::from_str(literal).map_err(|e| self.error(&format!("invalid string: {}", e)))
+        } else {
+            Ok(literal[1..end].replace("''", "'"))
+        }
+    }
+}
+
+/// The value of a plain scalar: null, a boolean, a number, or else a string.
+fn plain_value(text: &str) -> JsonValue {
+    match text {
+        "" | "~" | "null" => return JsonValue::Null,
+        "true" => return JsonValue::Bool(true),
+        "false" => return JsonValue::Bool(false),
+        _ => {}
+    }
+    // Only text that starts like a number; `inf` and `nan` stay strings
+    let numeric = text.trim_start_matches(['-', '+']).starts_with(|c: char| c.is_ascii_digit());
+    if numeric {
+        if let Ok(n) = text.parse::<i64>() {
+            return JsonValue::Number(n.into());
+        }
+        if let Some(n) = text.parse::<f64>().ok().and_then(Number::from_f64) {
+            return JsonValue::Number(n);
+        }
+    }
+    JsonValue::String(text.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_yaml() {
+        let text = "\
+# Settings
+name: weekly report  # trailing comment
+count: 3
+ratio: -0.5
+quoted: \"a # not a comment\\n\"
+single: 'it''s'
+plain: it's here # gone
+empty:
+tags:
+- one
+- 'two'
+nested:
+  flags: [true, false, ~]
+  inline: { a: 1, \"b c\": [x, y] }
+  items:
+    - 1
+    -
+      deep: yes
+";
+        let value = parse_yaml(text, 1).unwrap();
+        let expected = serde_json::json!({
+            "name": "weekly report",
+            "count": 3,
+            "ratio": -0.5,
+            "quoted": "a # not a comment\n",
+            "single": "it's",
+            "plain": "it's here",
+            "empty": null,
+            "tags": ["one", "two"],
+            "nested": {
+                "flags": [true, false, null],
+                "inline": { "a": 1, "b c": ["x", "y"] },
+                "items": [1, { "deep": "yes" }],
+            },
+        });
+        assert_eq!(value, expected);
+
+        assert_eq!(parse_yaml("a: 1\n  b: 2\n", 2).unwrap_err(), "line 3: unexpected indentation");
+        assert_eq!(parse_yaml("a: 1\na: 2\n", 2).unwrap_err(), "line 3: `a` is set twice");
+        assert_eq!(parse_yaml("a: [1, 2\n", 2).unwrap_err(), "line 2: expected `,` or `]`");
+        assert_eq!(parse_yaml("just text\n", 2).unwrap_err(), "line 2: expected `key: value`");
+        assert_eq!(parse_yaml("- a\nb: 1\n", 2).unwrap_err(), "line 3: expected `- item`");
+        assert_eq!(parse_yaml("\n# nothing\n", 2).unwrap(), JsonValue::Null);
+    }
+
+    #[test]
+    fn test_front_matter() {
+        let source = "---\nparams:\n  days: 7\nmodel: fast\nlimits:\n  max_llm_calls: 20\nrequires: [shell]\n---\nskill main(days) {}\n";
+        let front = FrontMatter::from_source(source).unwrap().unwrap();
+        assert_eq!(front.params.get("days"), Some(&Value::Number(7.0)));
+        assert_eq!(front.models, vec!["fast".to_string()]);
+        assert_eq!(front.limits, Limits { max_llm_calls: Some(20), ..Default::default() });
+        assert_eq!(front.requires, vec!["shell".to_string()]);
+        assert_eq!(FrontMatter::from_source("skill main() {}\n").unwrap(), None);
+
+        // Lists may sit at their key's indentation, with more keys after them
+        let source = "---\nmodel:\n- fast\n- careful\nrequires:\n- shell\nparams:\n  days: 7\n---\n";
+        let front = FrontMatter::from_source(source).unwrap().unwrap();
+        assert_eq!(front.models, vec!["fast".to_string(), "careful".to_string()]);
+        assert_eq!(front.requires, vec!["shell".to_string()]);
+        assert_eq!(front.params.get("days"), Some(&Value::Number(7.0)));
+
+        let policy = CapabilityPolicy { shell: Permission::Deny, ..Default::default() };
+        assert_eq!(front.check(&policy).unwrap_err(), "the program requires `shell`, which the capability policy denies");
+        let policy = CapabilityPolicy { shell: Permission::AskFirst, ..Default::default() };
+        assert!(front.check(&policy).is_ok());
+
+        // Errors name the line in the file
+        let err = FrontMatter::from_source("---\nmodel: fast\n  extra: 1\n---\n").unwrap_err();
+        assert_eq!(err, "front matter line 3: unexpected indentation");
+        let err = FrontMatter::from_source("---\nlimits:\n  max_llm_calls: lots\n---\n").unwrap_err();
+        assert_eq!(err, "front matter (limits.max_llm_calls): expected a non-negative integer");
+        let err = FrontMatter::from_source("---\nrequires: [teleport]\n---\n").unwrap_err();
+        assert_eq!(err, "front matter (requires): unknown capability \"teleport\"");
+        let err = FrontMatter::from_source("---\ntitle: Report\n---\n").unwrap_err();
+        assert_eq!(err, "front matter (title): unknown key");
+    }
+}
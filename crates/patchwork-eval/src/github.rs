@@ -0,0 +1,301 @@
+//! The optional `std.github` module.
+//!
+//! A host that wants GitHub automation registers the module's functions:
+//!
+//! ```ignore
+//! for function in GitHub::from_env().functions() {
+//!     interp.register_fn(function)?;
+//! }
+//! ```
+//!
+//! after which a program can use them without shelling out to curl:
+//!
+//! ```text
+//! import std.github
+//!
+//! for var issue in github.list_issues("owner/repo") {
+//!     github.comment("owner/repo", issue.number, "Triaged")
+//! }
+//! ```
+//!
+//! | function                                  | returns                  |
+//! |-------------------------------------------|--------------------------|
+//! | `list_issues(repo)`                       | open issues (not PRs)    |
+//! | `get_pr(repo, number)`                    | one pull request         |
+//! | `comment(repo, number, body)`             | the new comment          |
+//! | `create_branch(repo, name, from)`         | the new branch           |
+//!
+//! Requests go to the REST API through `curl`, authenticated with
+//! `GITHUB_TOKEN` when it is set. Results are trimmed to the fields
+//! programs use, with users and labels flattened to their names.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+use indexmap::IndexMap;
+
+use crate::host::{HostFunction, ValueType};
+use crate::value::Value;
+
+const DEFAULT_API_URL: &str = "https://api.github.com";
+
+type Transport = dyn Fn(&str, &str, Option<&str>) -> Result<(u16, String), String> + Send + Sync;
+
+/// A GitHub REST client whose operations are exposed as host functions.
+#[derive(Clone)]
+pub struct GitHub {
+    api_url: String,
+    transport: Arc<Transport>,
+}
+
+impl GitHub {
+    /// A client for github.com, authenticated with `token` if given.
+    pub fn new(token: Option<String>) -> Self {
+        let transport = move |method: &str, url: &str, body: Option<&str>| curl(token.as_deref(), method, url, body);
+        Self { api_url: DEFAULT_API_URL.to_string(), transport: Arc::new(transport) }
+    }
+
+    /// A client using `GITHUB_TOKEN`, and `GITHUB_API_URL` for GitHub
+    /// Enterprise.
+    pub fn from_env() -> Self {
+        let mut github = Self::new(std::env::var("GITHUB_TOKEN").ok().filter(|t| !t.is_empty()));
+        if let Ok(url) = std::env::var("GITHUB_API_URL") {
+            github.api_url = url;
+        }
+        github
+    }
+
+    pub fn with_api_url(mut self, url: impl Into<String>) -> Self {
+        self.api_url = url.into();
+        self
+    }
+
+    /// Send requests through `transport` instead of curl. It receives the
+    /// method, URL, and JSON body, and returns the status and response body.
+    pub fn with_transport(
+        mut self,
+        transport: impl Fn(&str, &str, Option<&str>) -> Result<(u16, String), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
+    /// The module's functions, named `github.<function>`.
+    pub fn functions(&self) -> Vec<HostFunction> {
+        let github = self.clone();
+        let list_issues = HostFunction::new("github.list_issues", move |args| github.list_issues(&args[0]))
+            .with_param("repo", ValueType::String)
+            .with_return(ValueType::Array)
+            .with_doc("List a repository's open issues, excluding pull requests.");
+
+        let github = self.clone();
+        let get_pr = HostFunction::new("github.get_pr", move |args| github.get_pr(&args[0], &args[1]))
+            .with_param("repo", ValueType::String)
+            .with_param("number", ValueType::Number)
+            .with_return(ValueType::Object)
+            .with_doc("Fetch a pull request.");
+
+        let github = self.clone();
+        let comment = HostFunction::new("github.comment", move |args| github.comment(&args[0], &args[1], &args[2]))
+            .with_param("repo", ValueType::String)
+            .with_param("number", ValueType::Number)
+            .with_param("body", ValueType::String)
+            .with_return(ValueType::Object)
+            .with_doc("Comment on an issue or pull request.");
+
+        let github = self.clone();
+        let create_branch =
+            HostFunction::new("github.create_branch", move |args| github.create_branch(&args[0], &args[1], &args[2]))
+                .with_param("repo", ValueType::String)
+                .with_param("name", ValueType::String)
+                .with_param("from", ValueType::String)
+                .with_return(ValueType::Object)
+                .with_doc("Create a branch pointing at the head of another branch.");
+
+        vec![list_issues, get_pr, comment, create_branch]
+    }
+
+    fn list_issues(&self, repo: &Value) -> Result<Value, String> {
+        let path = format!("/repos/{}/issues?state=open&per_page=100", repo.to_string_value());
+        let Value::Array(items) = self.request("GET", &path, None)? else {
+            return Err("github.list_issues(): expected a list of issues".to_string());
+        };
+        // The issues endpoint includes pull requests; they carry a `pull_request` field
+        let issues = items
+            .iter()
+            .filter(|item| field(item, "pull_request") == Value::Null)
+            .map(issue)
+            .collect();
+        Ok(Value::Array(issues))
+    }
+
+    fn get_pr(&self, repo: &Value, number: &Value) -> Result<Value, String> {
+        let path = format!("/repos/{}/pulls/{}", repo.to_string_value(), number.to_string_value());
+        let pr = self.request("GET", &path, None)?;
+        let mut fields = summary(&pr, &["number", "title", "state", "body", "draft", "merged"]);
+        fields.insert("url".to_string(), field(&pr, "html_url"));
+        fields.insert("author".to_string(), field(&field(&pr, "user"), "login"));
+        fields.insert("head".to_string(), field(&field(&pr, "head"), "ref"));
+        fields.insert("base".to_string(), field(&field(&pr, "base"), "ref"));
+        Ok(Value::Object(fields))
+    }
+
+    fn comment(&self, repo: &Value, number: &Value, body: &Value) -> Result<Value, String> {
+        let path = format!("/repos/{}/issues/{}/comments", repo.to_string_value(), number.to_string_value());
+        let request = object(&[("body", body.clone())]);
+        let comment = self.request("POST", &path, Some(&request))?;
+        let mut fields = summary(&comment, &["id", "body"]);
+        fields.insert("url".to_string(), field(&comment, "html_url"));
+        Ok(Value::Object(fields))
+    }
+
+    fn create_branch(&self, repo: &Value, name: &Value, from: &Value) -> Result<Value, String> {
+        let repo = repo.to_string_value();
+        let base = self.request("GET", &format!("/repos/{}/git/ref/heads/{}", repo, from.to_string_value()), None)?;
+        let sha = field(&field(&base, "object"), "sha");
+        let request = object(&[
+            ("ref", Value::String(format!("refs/heads/{}", name.to_string_value()))),
+            ("sha", sha.clone()),
+        ]);
+        self.request("POST", &format!("/repos/{}/git/refs", repo), Some(&request))?;
+        Ok(object(&[("name", name.clone()), ("sha", sha)]))
+    }
+
+    /// Send a request and parse the JSON response, failing on an error status.
+    fn request(&self, method: &str, path: &str, body: Option<&Value>) -> Result<Value, String> {
+        let url = format!("{}{}", self.api_url.trim_end_matches('/'), path);
+        let body = body.map(|body| body.to_json_value().to_string());
+        let (status, text) = (self.transport)(method, &url, body.as_deref())?;
+        let response = if text.trim().is_empty() { Value::Null } else { Value::from_json(&text)? };
+        if status >= 400 {
+            let message = match field(&response, "message") {
+                Value::Null => text.trim().to_string(),
+                message => message.to_string_value(),
+            };
+            return Err(format!("GitHub {} {} failed ({}): {}", method, path, status, message));
+        }
+        Ok(response)
+    }
+}
+
+/// Send a request with the `curl` command-line tool.
+fn curl(token: Option<&str>, method: &str, url: &str, body: Option<&str>) -> Result<(u16, String), String> {
+    let mut command = Command::new("curl");
+    command
+        .args(["-sS", "-X", method, "-w", "\n%{http_code}"])
+        .args(["-H", "Accept: application/vnd.github+json"])
+        .args(["-H", "User-Agent: patchwork"]);
+    if let Some(token) = token {
+        command.args(["-H", &format!("Authorization: Bearer {}", token)]);
+    }
+    if body.is_some() {
+        command.args(["-H", "Content-Type: application/json", "--data-binary", "@-"]);
+    }
+    let mut child = command
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run curl: {}", e))?;
+    if let (Some(mut stdin), Some(body)) = (child.stdin.take(), body) {
+        stdin.write_all(body.as_bytes()).map_err(|e| format!("Failed to run curl: {}", e))?;
+    }
+    let output = child.wait_with_output().map_err(|e| format!("Failed to run curl: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("curl failed: {}", String::from_utf8_lossy(&output.stderr).trim()));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (text, status) = stdout.rsplit_once('\n').unwrap_or(("", &stdout));
+    let status = status.trim().parse().map_err(|_| format!("curl returned no status for {}", url))?;
+    Ok((status, text.to_string()))
+}
+
+fn field(value: &Value, name: &str) -> Value {
+    match value {
+        Value::Object(fields) => fields.get(name).cloned().unwrap_or(Value::Null),
+        _ => Value::Null,
+    }
+}
+
+fn object(fields: &[(&str, Value)]) -> Value {
+    Value::Object(fields.iter().map(|(name, value)| (name.to_string(), value.clone())).collect())
+}
+
+/// Copy the named fields of a response object.
+fn summary(value: &Value, names: &[&str]) -> IndexMap<String, Value> {
+    names.iter().map(|name| (name.to_string(), field(value, name))).collect()
+}
+
+fn issue(item: &Value) -> Value {
+    let mut fields = summary(item, &["number", "title", "state", "body"]);
+    fields.insert("url".to_string(), field(item, "html_url"));
+    fields.insert("author".to_string(), field(&field(item, "user"), "login"));
+    let labels = match field(item, "labels") {
+        Value::Array(labels) => labels.iter().map(|label| field(label, "name")).collect(),
+        _ => Vec::new(),
+    };
+    fields.insert("labels".to_string(), Value::Array(labels));
+    Value::Object(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_github_functions() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        let github = GitHub::new(None).with_transport(move |method, url, body| {
+            log.lock().unwrap().push(format!("{} {} {}", method, url, body.unwrap_or("")));
+            Ok(match (method, url) {
+                ("GET", "https://api.github.com/repos/o/r/issues?state=open&per_page=100") => (
+                    200,
+                    r#"[{"number": 1, "title": "Bug", "state": "open", "user": {"login": "ann"},
+                        "labels": [{"name": "bug"}], "html_url": "u1", "body": "b"},
+                       {"number": 2, "title": "PR", "pull_request": {}}]"#
+                        .to_string(),
+                ),
+                ("POST", "https://api.github.com/repos/o/r/issues/1/comments") => {
+                    (201, r#"{"id": 9, "body": "Triaged", "html_url": "c9"}"#.to_string())
+                }
+                _ => (404, r#"{"message": "Not Found"}"#.to_string()),
+            })
+        });
+
+        let mut interp = Interpreter::new();
+        for function in github.functions() {
+            interp.register_fn(function).unwrap();
+        }
+        let result = interp
+            .eval(
+                r#"{
+                    var issues = github.list_issues("o/r")
+                    var comment = github.comment("o/r", issues[0].number, "Triaged")
+                    var summary = [len(issues), issues[0].author, issues[0].labels[0], comment.id]
+                    summary
+                }"#,
+            )
+            .unwrap();
+        assert_eq!(
+            result,
+            Value::Array(vec![
+                Value::Number(1.0),
+                Value::String("ann".to_string()),
+                Value::String("bug".to_string()),
+                Value::Number(9.0),
+            ])
+        );
+        assert_eq!(
+            requests.lock().unwrap()[1],
+            r#"POST https://api.github.com/repos/o/r/issues/1/comments {"body":"Triaged"}"#
+        );
+
+        let err = github.get_pr(&Value::String("o/r".to_string()), &Value::Number(5.0)).unwrap_err();
+        assert_eq!(err, "GitHub GET /repos/o/r/pulls/5 failed (404): Not Found");
+    }
+}
@@ -0,0 +1,170 @@
+//! Native functions registered by the embedding host.
+//!
+//! A host (an editor integration, a test harness, a service embedding the
+//! interpreter) can expose its own operations to Patchwork programs:
+//!
+//! ```ignore
+//! interp.register_fn(
+//!     HostFunction::new("lookup_ticket", |args| fetch_ticket(&args[0].to_string_value()))
+//!         .with_param("id", ValueType::String)
+//!         .with_return(ValueType::Object)
+//!         .with_doc("Fetch a ticket from the tracker by id."),
+//! )?;
+//! ```
+//!
+//! Registered functions are called like builtins. Their declared signatures
+//! are checked on every call and listed by `Runtime::host_functions`, so
+//! hosts can offer them for completion, tool use, and generated docs.
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::value::Value;
+
+/// The type of a host function parameter or result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    Any,
+    Null,
+    String,
+    Number,
+    Boolean,
+    Array,
+    Object,
+}
+
+impl ValueType {
+    /// Does `value` have this type?
+    pub fn matches(self, value: &Value) -> bool {
+        matches!(
+            (self, value),
+            (ValueType::Any, _)
+                | (ValueType::Null, Value::Null)
+                | (ValueType::String, Value::String(_))
+                | (ValueType::Number, Value::Number(_))
+                | (ValueType::Boolean, Value::Boolean(_))
+                | (ValueType::Array, Value::Array(_))
+                | (ValueType::Object, Value::Object(_))
+        )
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ValueType::Any => "any",
+            ValueType::Null => "null",
+            ValueType::String => "string",
+            ValueType::Number => "number",
+            ValueType::Boolean => "boolean",
+            ValueType::Array => "array",
+            ValueType::Object => "object",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+type NativeFn = dyn Fn(&[Value]) -> Result<Value, String> + Send + Sync;
+
+/// A native function callable from Patchwork code.
+#[derive(Clone)]
+pub struct HostFunction {
+    pub name: String,
+    pub params: Vec<(String, ValueType)>,
+    pub returns: ValueType,
+    /// One-line description for completion and generated docs.
+    pub doc: Option<String>,
+    func: Arc<NativeFn>,
+}
+
+impl HostFunction {
+    /// A function taking no parameters and returning any value; add its
+    /// signature with the `with_*` methods.
+    pub fn new(
+        name: impl Into<String>,
+        func: impl Fn(&[Value]) -> Result<Value, String> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            params: Vec::new(),
+            returns: ValueType::Any,
+            doc: None,
+            func: Arc::new(func),
+        }
+    }
+
+    pub fn with_param(mut self, name: impl Into<String>, ty: ValueType) -> Self {
+        self.params.push((name.into(), ty));
+        self
+    }
+
+    pub fn with_return(mut self, ty: ValueType) -> Self {
+        self.returns = ty;
+        self
+    }
+
+    pub fn with_doc(mut self, doc: impl Into<String>) -> Self {
+        self.doc = Some(doc.into());
+        self
+    }
+
+    /// The signature as it would be written in Patchwork, e.g.
+    /// `lookup_ticket(id: string) -> object`.
+    pub fn signature(&self) -> String {
+        let params: Vec<String> = self.params.iter().map(|(name, ty)| format!("{}: {}", name, ty)).collect();
+        format!("{}({}) -> {}", self.name, params.join(", "), self.returns)
+    }
+
+    /// Call the function, checking its arguments and result against the
+    /// declared signature.
+    pub fn call(&self, args: &[Value]) -> Result<Value, String> {
+        if args.len() != self.params.len() {
+            return Err(format!(
+                "{}() takes {} argument{}, got {}",
+                self.name,
+                self.params.len(),
+                if self.params.len() == 1 { "" } else { "s" },
+                args.len()
+            ));
+        }
+        for ((name, ty), arg) in self.params.iter().zip(args) {
+            if !ty.matches(arg) {
+                return Err(format!("{}(): `{}` must be a {}", self.name, name, ty));
+            }
+        }
+        let result = (self.func)(args)?;
+        if !self.returns.matches(&result) {
+            return Err(format!("{}() returned a value that is not a {}", self.name, self.returns));
+        }
+        Ok(result)
+    }
+}
+
+impl fmt::Debug for HostFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostFunction").field("signature", &self.signature()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_is_checked() {
+        let double = HostFunction::new("double", |args| match &args[0] {
+            Value::Number(n) => Ok(Value::Number(n * 2.0)),
+            _ => unreachable!("checked by the signature"),
+        })
+        .with_param("n", ValueType::Number)
+        .with_return(ValueType::Number);
+
+        assert_eq!(double.signature(), "double(n: number) -> number");
+        assert_eq!(double.call(&[Value::Number(2.0)]), Ok(Value::Number(4.0)));
+        assert_eq!(
+            double.call(&[Value::String("2".into())]),
+            Err("double(): `n` must be a number".to_string())
+        );
+        assert!(double.call(&[]).is_err());
+    }
+}
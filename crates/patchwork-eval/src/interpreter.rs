@@ -3,14 +3,32 @@
 //! This module provides a synchronous interpreter for Patchwork code.
 //! Think blocks block on channel operations waiting for LLM responses.
 
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
 
 use patchwork_parser::ast::{Expr, Statement};
+use patchwork_parser::diagnostics::{Diagnostic, Renderer};
+use patchwork_parser::resolve::resolve;
 
-use crate::agent::AgentHandle;
+use crate::agent::{AgentHandle, Priority};
+use crate::config::{BackgroundPolicy, CapabilityPolicy, Config, Limits};
+use crate::coverage::CoverageReport;
 use crate::error::Error;
 use crate::eval;
-use crate::runtime::{PlanReporter, PrintSink, Runtime, ThoughtReporter};
+use crate::evals::{self, EvalReport};
+use crate::front_matter::FrontMatter;
+use crate::host::HostFunction;
+use crate::journal::EffectJournal;
+use crate::program::ProgramInfo;
+use crate::runtime::{
+    ApprovalHandler, PlanReporter, PrintSink, ProgressReporter, Runtime, ThoughtReporter, WarningReporter,
+};
+use crate::schema::Schema;
+use crate::session::{write_private, Session};
+use crate::telemetry::TelemetrySink;
+use crate::timer::CancellationToken;
 use crate::value::Value;
 
 /// The Patchwork interpreter.
@@ -22,8 +40,16 @@ pub struct Interpreter {
     runtime: Runtime,
     /// Optional agent handle for think blocks.
     agent: Option<AgentHandle>,
+    /// Entry points of the most recently loaded program.
+    program: Option<ProgramInfo>,
+    /// Source of the most recently loaded program, for `call`.
+    loaded: Option<String>,
+    /// What coverage reports call the programs passed to `eval`.
+    source_name: String,
 }
 
+const DEFAULT_SOURCE_NAME: &str = "<input>";
+
 impl Interpreter {
     /// Create a new interpreter without an agent.
     ///
@@ -32,6 +58,9 @@ impl Interpreter {
         Self {
             runtime: Runtime::default(),
             agent: None,
+            program: None,
+            loaded: None,
+            source_name: DEFAULT_SOURCE_NAME.to_string(),
         }
     }
 
@@ -42,6 +71,9 @@ impl Interpreter {
         Self {
             runtime: Runtime::default(),
             agent: Some(agent),
+            program: None,
+            loaded: None,
+            source_name: DEFAULT_SOURCE_NAME.to_string(),
         }
     }
 
@@ -50,6 +82,9 @@ impl Interpreter {
         Self {
             runtime: Runtime::new(working_dir),
             agent: Some(agent),
+            program: None,
+            loaded: None,
+            source_name: DEFAULT_SOURCE_NAME.to_string(),
         }
     }
 
@@ -58,6 +93,9 @@ impl Interpreter {
         Self {
             runtime: Runtime::new(working_dir),
             agent: None,
+            program: None,
+            loaded: None,
+            source_name: DEFAULT_SOURCE_NAME.to_string(),
         }
     }
 
@@ -97,6 +135,141 @@ impl Interpreter {
         self.runtime.set_thought_reporter(reporter);
     }
 
+    /// Set a progress reporter for `progress(fraction, message)` calls.
+    pub fn set_progress_reporter(&mut self, reporter: ProgressReporter) {
+        self.runtime.set_progress_reporter(reporter);
+    }
+
+    /// Set a reporter for warnings from `warn()` and the runtime.
+    ///
+    /// Without one, warnings are written to stderr.
+    pub fn set_warning_reporter(&mut self, reporter: WarningReporter) {
+        self.runtime.set_warning_reporter(reporter);
+    }
+
+    /// Report LLM calls, shell commands, and finished runs to `sink`,
+    /// overriding the `telemetry` setting.
+    pub fn set_telemetry_sink(&mut self, sink: Arc<dyn TelemetrySink>) {
+        self.runtime.set_telemetry_sink(sink);
+    }
+
+    /// Give programs a session directory, visible to them as `session`.
+    ///
+    /// The session stays with the interpreter across runs until
+    /// `take_session` removes it for the host to finish.
+    pub fn set_session(&mut self, session: Session) {
+        self.runtime.set_session(session);
+    }
+
+    pub fn take_session(&mut self) -> Option<Session> {
+        self.runtime.take_session()
+    }
+
+    /// Set a handler for approving shell commands.
+    ///
+    /// When the shell policy is `ask-first`, each command not already
+    /// allowed is sent to this channel and waits for a decision.
+    pub fn set_approval_handler(&mut self, handler: ApprovalHandler) {
+        self.runtime.set_approval_handler(handler);
+    }
+
+    /// Use a cancellation token created by the host.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.runtime.set_cancellation_token(token);
+    }
+
+    /// Get a token that cancels this interpreter's program.
+    ///
+    /// Cancelling stops execution at the next statement and wakes any
+    /// pending `sleep()` or `schedule_at()` immediately.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.runtime.cancellation_token().clone()
+    }
+
+    /// Require programs to finish by `deadline`, or `None` for no deadline.
+    ///
+    /// Think blocks, shell commands, and sleeps that would run past the
+    /// deadline are cut off, and the program fails with "Deadline exceeded".
+    pub fn set_deadline(&mut self, deadline: Option<std::time::Instant>) {
+        self.runtime.set_deadline(deadline);
+    }
+
+    /// Turn strict mode on or off.
+    ///
+    /// In strict mode, conditions must be booleans or null, `+` only joins
+    /// a string with another string, and reading a missing object field or
+    /// an out-of-range index is an error instead of `null`.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.runtime.set_strict(strict);
+    }
+
+    /// Turn lenient shell mode on or off.
+    ///
+    /// By default a shell command that exits with a non-zero status throws
+    /// a `ShellError`. In lenient mode it returns its output, and the
+    /// program checks `$?` itself.
+    pub fn set_lenient_shell(&mut self, lenient: bool) {
+        self.runtime.set_lenient_shell(lenient);
+    }
+
+    /// Set what happens to commands started with `$(cmd &)` that are still
+    /// running when a program ends: by default they are killed, with a
+    /// warning, and with `BackgroundPolicy::Wait` the program waits for them.
+    pub fn set_background_policy(&mut self, policy: BackgroundPolicy) {
+        self.runtime.set_background_policy(policy);
+    }
+
+    /// Set the priority of this program's think blocks.
+    ///
+    /// Hosts running batch fan-outs give their workers
+    /// `Priority::Background`, so that when they share a `ThinkQueue` with
+    /// an interactive session, the session's requests go first.
+    pub fn set_think_priority(&mut self, priority: Priority) {
+        self.runtime.set_think_priority(priority);
+    }
+
+    /// Shell commands and file writes performed by the last `eval`, with
+    /// their idempotency keys.
+    pub fn effect_journal(&self) -> &EffectJournal {
+        self.runtime.effect_journal()
+    }
+
+    /// Resume or replay a run: effects recorded in `journal` by an earlier
+    /// evaluation of the same program return their recorded result instead
+    /// of being performed again.
+    pub fn set_replay_journal(&mut self, journal: EffectJournal) {
+        self.runtime.set_replay_journal(journal);
+    }
+
+    /// Count which statements run in every later `eval`, for `coverage`.
+    pub fn enable_coverage(&mut self) {
+        self.runtime.enable_coverage();
+    }
+
+    /// Name the programs passed to `eval` in coverage reports, usually by
+    /// their file path. Defaults to `<input>`.
+    pub fn set_source_name(&mut self, name: impl Into<String>) {
+        self.source_name = name.into();
+    }
+
+    /// Statement coverage since `enable_coverage`, or `None` if it was
+    /// never enabled.
+    pub fn coverage(&self) -> Option<&CoverageReport> {
+        self.runtime.coverage()
+    }
+
+    /// Make a native function callable from Patchwork code.
+    ///
+    /// See `HostFunction`; fails if the name belongs to a builtin.
+    pub fn register_fn(&mut self, function: HostFunction) -> Result<(), String> {
+        self.runtime.register_fn(function)
+    }
+
+    /// Apply the capability policy and limits from a loaded config.
+    pub fn configure(&mut self, config: &Config) {
+        self.runtime.apply_config(config);
+    }
+
     /// Evaluate Patchwork code.
     ///
     /// Parses and executes the code, returning the final value or an error.
@@ -105,7 +278,8 @@ impl Interpreter {
     pub fn eval(&mut self, code: &str) -> crate::Result<Value> {
         // For ACP, bare blocks `{ ... }` need to be wrapped in a skill to be valid
         let wrapped_code;
-        let code_to_parse = if code.trim_start().starts_with('{') {
+        let is_block = code.trim_start().starts_with('{');
+        let code_to_parse = if is_block {
             wrapped_code = format!("skill __main__() {}", code);
             &wrapped_code
         } else {
@@ -116,9 +290,16 @@ impl Interpreter {
         match patchwork_parser::parse(code_to_parse) {
             Ok(ast) => {
                 eprintln!("[patchwork-eval] Parsed AST: {:?}", ast);
+                let mut front = None;
+                if !is_block {
+                    let info = describe(&ast, code_to_parse)?;
+                    front = info.front_matter.clone();
+                    self.program = Some(info);
+                    self.loaded = Some(code.to_string());
+                }
 
                 // Execute the program - look for the __main__ skill or evaluate items
-                self.execute_program(&ast)
+                self.run_module(&ast, code_to_parse, code, front.as_ref(), |interp| interp.execute_program(&ast))
             }
             Err(e) => {
                 let msg = format_parse_error(&e, code_to_parse);
@@ -127,7 +308,355 @@ impl Interpreter {
         }
     }
 
+    /// Run the skill, worker, or function called `name` in the program most
+    /// recently passed to `load` or `eval`, with `args` bound to its
+    /// parameters in order.
+    ///
+    /// Like `eval`, the module-level variables are initialized first, and
+    /// afresh for every call. Parameters the caller leaves out take their
+    /// defaults from the program's front matter. `ProgramInfo::default_entry`
+    /// names the entry to run when the caller has no preference.
+    pub fn call(&mut self, name: &str, args: Vec<Value>) -> crate::Result<Value> {
+        use patchwork_parser::Item;
+
+        let Some(code) = self.loaded.clone() else {
+            return Err(Error::Runtime(format!("can't call `{}`: no program is loaded", name)));
+        };
+        let ast = patchwork_parser::parse(&code).map_err(|e| Error::Parse(format_parse_error(&e, &code)))?;
+        let entry = ast.items.iter().find_map(|item| match item {
+            Item::Skill(skill) if skill.name == name => Some((&skill.params, &skill.body, &skill.annotations[..])),
+            Item::Worker(worker) if worker.name == name => Some((&worker.params, &worker.body, &[][..])),
+            Item::Function(func) if func.name == name => Some((&func.params, &func.body, &func.annotations[..])),
+            _ => None,
+        });
+        let Some((params, body, annotations)) = entry else {
+            let info = ProgramInfo::from_program(&ast, &code);
+            let names: Vec<&str> = info.entries.iter().map(|entry| entry.name.as_str()).collect();
+            let declared = if names.is_empty() { "none".to_string() } else { names.join(", ") };
+            return Err(Error::Runtime(format!("no entry point named `{}` (the program declares {})", name, declared)));
+        };
+        let front = self.program.as_ref().and_then(|info| info.front_matter.clone());
+        let given = args.len();
+        let mut args = args;
+        for param in params.iter().skip(given) {
+            match front.as_ref().and_then(|front| front.params.get(param.name)) {
+                Some(default) => args.push(default.clone()),
+                None => break,
+            }
+        }
+        if args.len() != params.len() {
+            let noun = if params.len() == 1 { "argument" } else { "arguments" };
+            return Err(Error::Runtime(format!("`{}` takes {} {}, got {}", name, params.len(), noun, given)));
+        }
+
+        // `@agent name` sends the entry's think blocks to that agent; the
+        // chain is put back when the module finishes
+        let agent = annotations
+            .iter()
+            .find(|annotation| annotation.name == "agent")
+            .and_then(|annotation| annotation.args.first());
+        let skill = format!("{}::{}", self.source_name, name);
+
+        self.run_module(&ast, &code, &code, front.as_ref(), |interp| {
+            if let Some(agent) = agent {
+                let mut models = interp.runtime.model_chain().clone();
+                models.agent = Some(agent.to_string());
+                interp.runtime.set_model_chain(models);
+            }
+            // `@concurrency`, `@timeout`, and `@capabilities`, as the
+            // resolver read them; one it couldn't read stops the run, rather
+            // than running without the limit it asked for
+            let symbols = interp.runtime.symbols();
+            if let Some(invalid) = symbols.into_iter().flat_map(|s| s.invalid_annotations()).find(|i| i.skill == name) {
+                return Err(Error::Runtime(format!("can't run `{}`: {}", name, invalid.message)));
+            }
+            let settings = symbols.and_then(|symbols| symbols.skill_settings(name)).cloned().unwrap_or_default();
+            interp.runtime.with_skill_settings(&skill, &settings, |runtime| {
+                // The parameters get a scope of their own around the body's
+                runtime.push_scope();
+                let result = params
+                    .iter()
+                    .zip(args)
+                    .try_for_each(|(param, arg)| runtime.define_var(param.name, arg).map_err(Error::Runtime))
+                    .and_then(|()| eval::eval_block(body, runtime, interp.agent.as_ref()));
+                runtime.pop_scope();
+                result
+            })
+        })
+    }
+
+    /// Initialize the module-level variables of `ast`, parsed from `code`,
+    /// and then `run` it, with names resolved and coverage recorded under
+    /// `display`, the code as the user wrote it. The program's front matter
+    /// applies until it finishes.
+    fn run_module(
+        &mut self,
+        ast: &patchwork_parser::Program,
+        code: &str,
+        display: &str,
+        front: Option<&FrontMatter>,
+        run: impl FnOnce(&mut Self) -> crate::Result<Value>,
+    ) -> crate::Result<Value> {
+        let started = Instant::now();
+        let (limits, models) = (*self.runtime.limits(), self.runtime.model_chain().clone());
+        if let Some(front) = front {
+            self.runtime.apply_front_matter(front).map_err(Error::Runtime)?;
+        }
+
+        // Resolve names so variables can be found by slot
+        let resolved = resolve(ast, code);
+        let init_order = resolved.symbols.initialization_order();
+        self.runtime.set_symbols(Some(resolved.symbols));
+        self.runtime.set_source(Some(code));
+        self.runtime.begin_coverage(&self.source_name, code, display, ast);
+
+        // Module-level variables live in a scope of their own, so
+        // evaluating the same program again starts fresh
+        self.runtime.push_scope();
+        let result = init_order
+            .map_err(Error::Runtime)
+            .and_then(|order| self.initialize_module(ast, &order))
+            .and_then(|()| run(self));
+        self.runtime.pop_scope();
+        // Background commands don't outlive the program: they are killed
+        // or waited for, and if the program failed, killed regardless
+        let result = match result {
+            Ok(value) => eval::finish_background(&mut self.runtime).map(|()| value),
+            Err(e) => {
+                drop(self.runtime.processes().drain());
+                Err(e)
+            }
+        };
+        self.runtime.end_coverage();
+        self.runtime.set_source(None);
+        self.runtime.set_symbols(None);
+        self.runtime.record_run(&result, started.elapsed());
+        self.runtime.set_limits(limits);
+        self.runtime.set_model_chain(models);
+        result
+    }
+
+    /// Evaluate one turn of an interactive session, such as a REPL line or
+    /// an ACP code-mode message.
+    ///
+    /// Unlike `eval`, variables declared at the top level of `code` stay
+    /// defined for later turns (a later `var` with the same name shadows
+    /// them), and the result is the value of the final statement if it is
+    /// an expression, or null otherwise.
+    pub fn eval_interactive(&mut self, code: &str) -> crate::Result<Value> {
+        let wrapped_code = if code.trim_start().starts_with('{') {
+            format!("skill __main__() {}", code)
+        } else {
+            format!("skill __main__() {{\n{}\n}}", code)
+        };
+
+        let ast = patchwork_parser::parse(&wrapped_code)
+            .map_err(|e| Error::Parse(format_parse_error(&e, &wrapped_code)))?;
+        let body = ast.items.iter().find_map(|item| match item {
+            patchwork_parser::Item::Skill(skill) if skill.name == "__main__" => Some(&skill.body),
+            _ => None,
+        });
+        let Some(body) = body else {
+            return Ok(Value::Null);
+        };
+
+        // Each turn gets a scope, so the resolver's slots for the body match
+        // it; its variables then move to the scope below to outlive the turn.
+        let resolved = resolve(&ast, &wrapped_code);
+        self.runtime.set_symbols(Some(resolved.symbols));
+        self.runtime.push_scope();
+        let mut result = Ok(Value::Null);
+        for stmt in &body.statements {
+            result = self.runtime.check_cancelled().map_err(Error::Runtime).and_then(|()| {
+                eval::eval_statement(stmt, &mut self.runtime, self.agent.as_ref())
+            });
+            if result.is_err() {
+                break;
+            }
+        }
+        self.runtime.merge_scope();
+        self.runtime.set_symbols(None);
+
+        match body.statements.last() {
+            Some(Statement::Expr(_)) => result,
+            _ => result.map(|_| Value::Null),
+        }
+    }
+
+    /// Evaluate an untrusted snippet, such as code an LLM wrote, in a fresh
+    /// environment.
+    ///
+    /// The snippet sees none of this interpreter's variables or host
+    /// functions. It may do only what both this interpreter's policy and
+    /// `capabilities` allow, within `limits` and the rest of this
+    /// interpreter's budget, which its LLM calls are charged to. Like
+    /// `eval_interactive`, the result is the value of its final statement
+    /// if that is an expression.
+    pub fn spawn_isolated(&mut self, code: &str, capabilities: CapabilityPolicy, limits: Limits) -> crate::Result<Value> {
+        run_isolated(code, &mut self.runtime, self.agent.as_ref(), &capabilities, &limits)
+    }
+
+    /// Load data imports, then run the module-level `var` and `const`
+    /// initializers, each once, in the dependency order computed by the
+    /// resolver.
+    fn initialize_module(&mut self, program: &patchwork_parser::Program, order: &[usize]) -> crate::Result<()> {
+        for item in &program.items {
+            if let patchwork_parser::Item::Type(decl) = item {
+                self.runtime.define_schema(decl.name, Schema::from_type_expr(&decl.type_expr));
+            }
+        }
+        for item in &program.items {
+            if let patchwork_parser::Item::Import(patchwork_parser::ImportDecl {
+                path: patchwork_parser::ImportPath::Data { name, path },
+            }) = item
+            {
+                eval::eval_data_import(name, path, &mut self.runtime)?;
+            }
+        }
+        for &index in order {
+            if let patchwork_parser::Item::Var(decl) = &program.items[index] {
+                eval::eval_module_var(decl, &mut self.runtime, self.agent.as_ref())?;
+            }
+        }
+        Ok(())
+    }
+
     /// Execute a parsed program.
+    /// Parse a program without running it, and describe its entry points.
+    ///
+    /// The description stays available from `program_info` until another
+    /// program is loaded or evaluated.
+    pub fn load(&mut self, code: &str) -> crate::Result<&ProgramInfo> {
+        let ast = patchwork_parser::parse(code).map_err(|e| Error::Parse(format_parse_error(&e, code)))?;
+        let info = describe(&ast, code)?;
+        self.loaded = Some(code.to_string());
+        Ok(self.program.insert(info))
+    }
+
+    /// Re-read a module that changed on disk during a live session.
+    ///
+    /// `path` is resolved against the working directory. The module's type
+    /// declarations replace any earlier ones with the same names, and its
+    /// entry points become the ones `program_info` describes, so a host
+    /// advertising them picks up the edit. The session's variables are
+    /// kept. A module that no longer parses is an error and changes
+    /// nothing, so a half-finished edit can't break the session.
+    pub fn reload_module(&mut self, path: &Path) -> crate::Result<&ProgramInfo> {
+        let path = self.runtime.working_dir().join(path);
+        let code = fs::read_to_string(&path)
+            .map_err(|e| Error::Runtime(format!("Failed to read {}: {}", path.display(), e)))?;
+        let ast = patchwork_parser::parse(&code).map_err(|e| Error::Parse(format_parse_error(&e, &code)))?;
+        let info = describe(&ast, &code)?;
+        for item in &ast.items {
+            if let patchwork_parser::Item::Type(decl) = item {
+                self.runtime.define_schema(decl.name, Schema::from_type_expr(&decl.type_expr));
+            }
+        }
+        self.loaded = Some(code.clone());
+        Ok(self.program.insert(info))
+    }
+
+    /// Run every `eval` declaration in a program and score its cases.
+    ///
+    /// Module-level variables and data imports are initialized first, so
+    /// datasets can come from either.
+    pub fn run_evals(&mut self, code: &str) -> crate::Result<EvalReport> {
+        let ast = patchwork_parser::parse(code).map_err(|e| Error::Parse(format_parse_error(&e, code)))?;
+        let resolved = resolve(&ast, code);
+        let init_order = resolved.symbols.initialization_order();
+        self.runtime.set_symbols(Some(resolved.symbols));
+        self.runtime.set_source(Some(code));
+
+        self.runtime.push_scope();
+        let result = init_order
+            .map_err(Error::Runtime)
+            .and_then(|order| self.initialize_module(&ast, &order))
+            .and_then(|()| {
+                let mut report = EvalReport::default();
+                for item in &ast.items {
+                    if let patchwork_parser::Item::Eval(decl) = item {
+                        report.evals.push(evals::run_eval(decl, &mut self.runtime, self.agent.as_ref())?);
+                    }
+                }
+                Ok(report)
+            });
+        self.runtime.pop_scope();
+        self.runtime.set_source(None);
+        self.runtime.set_symbols(None);
+        result
+    }
+
+    /// The skills, workers, and functions declared by the most recently
+    /// loaded program, with their parameters and doc comments.
+    ///
+    /// Bare `{ ... }` blocks declare nothing, so evaluating one leaves the
+    /// previous description in place.
+    pub fn program_info(&self) -> Option<&ProgramInfo> {
+        self.program.as_ref()
+    }
+
+    /// Every variable in scope and its value, sorted by name.
+    pub fn variables(&self) -> crate::Result<Vec<(String, Value)>> {
+        self.runtime.variables().map_err(Error::Runtime)
+    }
+
+    /// Save the variables of an interactive session as JSON, for `restore`.
+    ///
+    /// Values are stored as JSON, so sets and tuples come back as arrays.
+    pub fn snapshot(&self) -> crate::Result<String> {
+        let variables: serde_json::Map<String, serde_json::Value> = self
+            .variables()?
+            .into_iter()
+            .map(|(name, value)| (name, value.to_json_value()))
+            .collect();
+        let snapshot = serde_json::json!({ "variables": variables });
+        Ok(serde_json::to_string_pretty(&snapshot).unwrap_or_default())
+    }
+
+    /// Define the variables saved by `snapshot` for later turns of an
+    /// interactive session, shadowing any with the same names.
+    pub fn restore(&mut self, snapshot: &str) -> crate::Result<()> {
+        let snapshot: serde_json::Value = serde_json::from_str(snapshot)
+            .map_err(|e| Error::Runtime(format!("Invalid session snapshot: {}", e)))?;
+        let Some(variables) = snapshot.get("variables").and_then(|v| v.as_object()) else {
+            return Err(Error::Runtime("Invalid session snapshot: missing `variables`".to_string()));
+        };
+        // Like an interactive turn's, the restored variables replace any
+        // with the same names
+        self.runtime.push_scope();
+        let result = variables.iter().try_for_each(|(name, value)| {
+            self.runtime.define_var(name, Value::from_json_value(value.clone())).map_err(Error::Runtime)
+        });
+        self.runtime.merge_scope();
+        result
+    }
+
+    /// Restore the variables a session saved with `save_session`,
+    /// returning whether it had any.
+    pub fn resume_session(&mut self) -> crate::Result<bool> {
+        let Some(path) = self.runtime.session().map(Session::state_file) else {
+            return Ok(false);
+        };
+        let snapshot = match fs::read_to_string(&path) {
+            Ok(snapshot) => snapshot,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(Error::Runtime(format!("Failed to read {}: {}", path.display(), e))),
+        };
+        self.restore(&snapshot)?;
+        Ok(true)
+    }
+
+    /// Save the variables of an interactive session in its session
+    /// directory, for the next host to open it to `resume_session`. Only
+    /// the user who saved them can read them.
+    pub fn save_session(&self) -> crate::Result<()> {
+        let Some(path) = self.runtime.session().map(Session::state_file) else {
+            return Err(Error::Runtime("no session to save variables in".to_string()));
+        };
+        write_private(&path, &self.snapshot()?)
+            .map_err(|e| Error::Runtime(format!("Failed to write {}: {}", path.display(), e)))
+    }
+
     fn execute_program(&mut self, program: &patchwork_parser::Program) -> crate::Result<Value> {
         use patchwork_parser::Item;
 
@@ -170,62 +699,38 @@ impl Default for Interpreter {
     }
 }
 
-/// Format a parse error with source context.
-fn format_parse_error(error: &patchwork_parser::ParseError, source: &str) -> String {
-    use patchwork_parser::ParseError;
-
-    let (message, span) = match error {
-        ParseError::LexerError { message, span, .. } => (message.clone(), *span),
-        ParseError::UnexpectedToken { message, span, .. } => (message.clone(), *span),
+/// Run `code` in a runtime made by `Runtime::isolated`, charging what it
+/// spent to `runtime`.
+pub(crate) fn run_isolated(
+    code: &str,
+    runtime: &mut Runtime,
+    agent: Option<&AgentHandle>,
+    capabilities: &CapabilityPolicy,
+    limits: &Limits,
+) -> crate::Result<Value> {
+    let mut sandbox = Interpreter {
+        runtime: runtime.isolated(capabilities, limits),
+        agent: agent.cloned(),
+        program: None,
+        loaded: None,
+        source_name: DEFAULT_SOURCE_NAME.to_string(),
     };
-
-    // If we have a span, add line/column information and a source snippet
-    if let Some((start, end)) = span {
-        let (line, col) = byte_offset_to_line_col(source, start);
-        let source_line = get_source_line(source, line);
-
-        // Build error message with context
-        let mut result = format!("at line {}, column {}: {}\n", line, col, message);
-
-        // Add the source line
-        result.push_str(&format!("  {}\n", source_line));
-
-        // Add the error indicator
-        let spaces = " ".repeat(col.saturating_sub(1) + 2);
-        let carets = "^".repeat((end - start).max(1));
-        result.push_str(&format!("{}{}", spaces, carets));
-
-        result
-    } else {
-        message
-    }
+    let result = sandbox.eval_interactive(code);
+    runtime.absorb_usage(&sandbox.runtime)?;
+    result
 }
 
-/// Convert a byte offset to line and column numbers (1-indexed).
-fn byte_offset_to_line_col(source: &str, offset: usize) -> (usize, usize) {
-    let mut line = 1;
-    let mut line_start = 0;
-
-    for (i, c) in source.char_indices() {
-        if i >= offset {
-            break;
-        }
-        if c == '\n' {
-            line += 1;
-            line_start = i + 1;
-        }
-    }
-
-    let col = offset - line_start + 1;
-    (line, col)
+/// Describe the entry points of `program`, parsed from `code`, and read
+/// its front matter.
+fn describe(program: &patchwork_parser::Program, code: &str) -> crate::Result<ProgramInfo> {
+    let front = FrontMatter::from_source(code).map_err(Error::Parse)?;
+    Ok(ProgramInfo::from_program(program, code).with_front_matter(front))
 }
 
-/// Get the source line at the given line number (1-indexed).
-fn get_source_line(source: &str, line_num: usize) -> &str {
-    source
-        .lines()
-        .nth(line_num.saturating_sub(1))
-        .unwrap_or("")
+/// Format a parse error with source context.
+pub(crate) fn format_parse_error(error: &patchwork_parser::ParseError, source: &str) -> String {
+    let diagnostic = Diagnostic::from(error);
+    Renderer::plain().render(&diagnostic, "<input>", source)
 }
 
 #[cfg(test)]
@@ -289,6 +794,245 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_eval_shadowed_variables() {
+        let mut interp = Interpreter::new();
+        let code = r#"{
+            var x = 10
+            var total = 0
+            for var x in [1, 2] {
+                total = total + x
+            }
+            total + x
+        }"#;
+        let result = interp.eval(code);
+        assert!(result.is_ok(), "Eval failed: {:?}", result);
+        if let Ok(Value::Number(n)) = result {
+            assert_eq!(n, 13.0);
+        } else {
+            panic!("Expected Number(13), got {:?}", result);
+        }
+    }
+
+    #[test]
+    fn test_config_writes_result_json() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("result.json");
+        let mut interp = Interpreter::new();
+        interp.configure(&Config { result_json: Some(path.clone()), ..Config::default() });
+
+        interp.eval("{\n    1 + 2\n}").unwrap();
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["outcome"], "success");
+        assert_eq!(json["value"], 3.0);
+
+        // Each run replaces the last one's result
+        assert!(interp.eval("{\n    missing\n}").is_err());
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["outcome"], "failed");
+        assert_eq!(json["exit_code"], 1);
+    }
+
+    #[test]
+    fn test_config_denies_shell_commands() {
+        let mut interp = Interpreter::new();
+        let mut config = Config::default();
+        config.capabilities.shell = crate::config::Permission::Deny;
+        interp.configure(&config);
+
+        let result = interp.eval("{\n    $ echo hello\n}");
+        match result {
+            Err(Error::Runtime(msg)) => assert!(msg.contains("capability policy"), "{}", msg),
+            other => panic!("Expected policy error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_background_command_lines() {
+        let mut interp = Interpreter::new();
+        let code = "{\n    var seen = \"\"\n    var p = $(sh -c \"echo one; echo two\" &)\n    for var line in p.lines() {\n        seen = seen + line + \";\"\n    }\n    p.wait()\n    seen\n}";
+        assert_eq!(interp.eval(code).unwrap(), Value::String("one;two;".to_string()));
+
+        let code = "{\n    var p = $(sh -c \"echo partial; exit 3\" &)\n    p.wait()\n}";
+        match interp.eval(code) {
+            Err(Error::Exception(Value::Object(fields))) => assert_eq!(fields["status"], Value::Number(3.0)),
+            other => panic!("Expected the command to fail, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_background_commands_at_program_end() {
+        let mut interp = Interpreter::new();
+        let code = "{\n    var done = $(sh -c \"echo done\" &)\n    $ sleep 0.1\n    var slow = $(sh -c \"sleep 5\" &)\n    \"ok\"\n}";
+        let started = std::time::Instant::now();
+        assert_eq!(interp.eval(code).unwrap(), Value::String("ok".to_string()));
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        // Only the command still running is warned about
+        let warnings = interp.runtime().warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code, "background");
+        assert!(warnings[0].message.contains("sleep 5"), "{}", warnings[0].message);
+
+        // Waiting for them fails the program if any of them fail
+        interp.set_background_policy(BackgroundPolicy::Wait);
+        let code = "{\n    var a = $(sh -c \"sleep 0.1; exit 1\" &)\n    var b = $(sh -c \"echo b\" &)\n    \"ok\"\n}";
+        match interp.eval(code) {
+            Err(Error::Exception(Value::Object(fields))) => {
+                assert_eq!(fields["type"], Value::String("WaitAllError".to_string()));
+                assert_eq!(fields["results"], Value::Array(vec![Value::Null, Value::String("b\n".to_string())]));
+            }
+            other => panic!("Expected a WaitAllError, got {:?}", other),
+        }
+        assert!(interp.runtime_mut().processes().handles().is_empty());
+
+        // A program that fails kills them without waiting
+        let code = "{\n    var slow = $(sh -c \"sleep 5\" &)\n    throw \"stop\"\n}";
+        let started = std::time::Instant::now();
+        assert!(interp.eval(code).is_err());
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_shell_exit_status() {
+        let mut interp = Interpreter::new();
+        match interp.eval("{\n    $ sh -c \"echo oops >&2; exit 3\"\n}") {
+            Err(Error::Exception(Value::Object(fields))) => {
+                assert_eq!(fields["type"], Value::String("ShellError".to_string()));
+                assert_eq!(fields["status"], Value::Number(3.0));
+                assert_eq!(fields["stderr"], Value::String("oops".to_string()));
+            }
+            other => panic!("Expected a ShellError, got {:?}", other),
+        }
+
+        // `||` handles the failure instead of throwing
+        let code = "{\n    var out = $(sh -c \"exit 1\" || echo recovered)\n    var status = $?\n    \"${out} ${status}\"\n}";
+        assert_eq!(interp.eval(code).unwrap(), Value::String("recovered 0".to_string()));
+
+        // In lenient mode the program checks `$?` itself
+        interp.set_lenient_shell(true);
+        let code = "{\n    var out = $(sh -c \"echo partial; exit 3\")\n    var status = $?\n    \"${out} ${status}\"\n}";
+        assert_eq!(interp.eval(code).unwrap(), Value::String("partial 3".to_string()));
+        let code = "{\n    var out = $(sh -c \"exit 2\" && echo skipped)\n    var status = $?\n    status\n}";
+        assert_eq!(interp.eval(code).unwrap(), Value::Number(2.0));
+    }
+
+    #[test]
+    fn test_wait_all_and_wait_any() {
+        let mut interp = Interpreter::new();
+        let code = "{\n    var a = $(sh -c \"sleep 0.2; echo a\" &)\n    var b = $(sh -c \"echo b\" &)\n    wait_all([a, b])\n}";
+        let outputs = vec![Value::String("a\n".to_string()), Value::String("b\n".to_string())];
+        assert_eq!(interp.eval(code).unwrap(), Value::Array(outputs));
+
+        // Every failure is collected once the others finish
+        let code = "{\n    var a = $(sh -c \"exit 1\" &)\n    var b = $(sh -c \"echo b\" &)\n    var c = $(sh -c \"exit 2\" &)\n    wait_all([a, b, c])\n}";
+        match interp.eval(code) {
+            Err(Error::Exception(Value::Object(fields))) => {
+                assert_eq!(fields["type"], Value::String("WaitAllError".to_string()));
+                let Value::Array(errors) = &fields["errors"] else { panic!("Expected a list of errors") };
+                assert_eq!(errors.len(), 2);
+                let results = vec![Value::Null, Value::String("b\n".to_string()), Value::Null];
+                assert_eq!(fields["results"], Value::Array(results));
+            }
+            other => panic!("Expected a WaitAllError, got {:?}", other),
+        }
+
+        // With fail_fast the first failure is thrown and the rest killed
+        let code = "{\n    var a = $(sh -c \"sleep 5\" &)\n    var b = $(sh -c \"exit 4\" &)\n    wait_all([a, b], { fail_fast: true })\n}";
+        let started = std::time::Instant::now();
+        match interp.eval(code) {
+            Err(Error::Exception(Value::Object(fields))) => assert_eq!(fields["status"], Value::Number(4.0)),
+            other => panic!("Expected a ShellError, got {:?}", other),
+        }
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+
+        let code = "{\n    var slow = $(sh -c \"sleep 5\" &)\n    var fast = $(sh -c \"echo fast\" &)\n    var first = wait_any([slow, fast])\n    slow.kill()\n    first\n}";
+        let Value::Object(first) = interp.eval(code).unwrap() else { panic!("Expected an object") };
+        assert_eq!(first["index"], Value::Number(1.0));
+        assert_eq!(first["output"], Value::String("fast\n".to_string()));
+
+        let code = "{\n    var slow = $(sh -c \"sleep 5\" &)\n    var first = wait_any([slow], \"50ms\")\n    slow.kill()\n    first\n}";
+        assert_eq!(interp.eval(code).unwrap(), Value::Null);
+    }
+
+    #[test]
+    fn test_approve_block() {
+        let mut interp = Interpreter::new();
+        let (tx, rx) = std::sync::mpsc::channel::<crate::ApprovalRequest>();
+        interp.set_approval_handler(tx);
+        let approver = std::thread::spawn(move || {
+            let mut summaries = Vec::new();
+            for (request, decision) in rx.iter().zip([crate::ApprovalDecision::AllowOnce, crate::ApprovalDecision::Deny]) {
+                summaries.push(request.summary);
+                request.response_tx.send(decision).unwrap();
+            }
+            summaries
+        });
+
+        let result = interp.eval("{\n    approve { Delete the stale\n        branches }\n    var done = true\n    done\n}");
+        assert_eq!(result.unwrap(), Value::Boolean(true));
+        match interp.eval("{\n    approve { Push to main }\n}") {
+            Err(Error::Runtime(msg)) => assert_eq!(msg, "`Push to main` was not approved"),
+            other => panic!("Expected a denial, got {:?}", other),
+        }
+
+        drop(interp);
+        assert_eq!(approver.join().unwrap(), vec!["Delete the stale branches", "Push to main"]);
+    }
+
+    #[test]
+    fn test_spawn_isolated() {
+        let mut interp = Interpreter::new();
+        let mut config = Config::default();
+        config.limits.max_think_calls = Some(3);
+        interp.configure(&config);
+        interp.eval_interactive("var secret = 42").unwrap();
+
+        let deny_shell = CapabilityPolicy { shell: crate::config::Permission::Deny, ..Default::default() };
+        let value = interp.spawn_isolated("var x = 2\nx * 21", deny_shell.clone(), Limits::default()).unwrap();
+        assert_eq!(value, Value::Number(42.0));
+
+        // The snippet sees none of the caller's variables
+        match interp.spawn_isolated("secret", deny_shell.clone(), Limits::default()) {
+            Err(Error::Runtime(msg)) => assert_eq!(msg, "Undefined variable: secret"),
+            other => panic!("Expected an undefined variable, got {:?}", other),
+        }
+
+        match interp.spawn_isolated("$ echo hello", deny_shell.clone(), Limits::default()) {
+            Err(Error::Runtime(msg)) => assert!(msg.contains("capability policy"), "{}", msg),
+            other => panic!("Expected policy error, got {:?}", other),
+        }
+
+        // Think blocks count against the caller's limit
+        interp.spawn_isolated("think { one }", deny_shell.clone(), Limits::default()).unwrap();
+        let result = interp.spawn_isolated("think { two }\nthink { three }\nthink { four }", deny_shell, Limits::default());
+        match result {
+            Err(Error::Runtime(msg)) => assert!(msg.contains("limit of 2 think blocks"), "{}", msg),
+            other => panic!("Expected limit error, got {:?}", other),
+        }
+        assert!(interp.eval_interactive("think { five }").is_err());
+    }
+
+    #[test]
+    fn test_config_limits_loop_iterations() {
+        let mut interp = Interpreter::new();
+        let mut config = Config::default();
+        config.limits.max_loop_iterations = Some(10);
+        interp.configure(&config);
+
+        let code = r#"{
+            var n = 0
+            while (true) {
+                n = n + 1
+            }
+        }"#;
+        match interp.eval(code) {
+            Err(Error::Runtime(msg)) => assert!(msg.contains("limit of 10 iterations"), "{}", msg),
+            other => panic!("Expected limit error, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_eval_json_parse_from_file() {
         use std::io::Write;
@@ -299,7 +1043,8 @@ mod tests {
         writeln!(file, r#"{{"name": "test", "value": 123}}"#).unwrap();
         let path = file.path().to_str().unwrap();
 
-        let mut interp = Interpreter::new();
+        // File access is confined to the working directory
+        let mut interp = Interpreter::with_working_dir(std::env::temp_dir());
         let code = format!(r#"{{
             var text = read("{}")
             var data = json(text)
@@ -325,7 +1070,8 @@ mod tests {
         writeln!(file, r#"{{"name": "test", "value": 123}}"#).unwrap();
         let path = file.path().to_str().unwrap();
 
-        let mut interp = Interpreter::new();
+        // File access is confined to the working directory
+        let mut interp = Interpreter::with_working_dir(std::env::temp_dir());
         // Test using read() + json() - the standard pattern
         let code = format!(r#"{{
             var text = read("{}")
@@ -342,6 +1088,18 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_read_outside_working_dir_is_refused() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let mut interp = Interpreter::with_working_dir(temp_dir.path().to_path_buf());
+        match interp.eval("{\n    read(\"../outside.txt\")\n}") {
+            Err(Error::Runtime(msg)) => assert!(msg.contains("outside the working directory"), "{}", msg),
+            other => panic!("Expected confinement error, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_eval_cat_function() {
         let mut interp = Interpreter::new();
@@ -538,6 +1296,680 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_think_block_formats_examples() {
+        let mut interp = Interpreter::new();
+        let code = r#"{
+            var long_review = "This product is wonderful and I would buy it again"
+            think {
+                Classify the sentiment.
+                examples { input: "Great!", output: "positive" }
+                examples { input: long_review, output: "positive" }
+                Answer with one word.
+            }
+        }"#;
+        let Ok(Value::Object(obj)) = interp.eval(code) else {
+            panic!("Expected Object with __think_prompt");
+        };
+        let prompt = obj["__think_prompt"].to_string_value();
+        assert!(
+            prompt.contains("Examples:\n\nInput: Great!\nOutput: positive\n\nInput: This product"),
+            "{}",
+            prompt
+        );
+        assert!(prompt.ends_with("\n\nAnswer with one word."), "{}", prompt);
+
+        // A tight budget keeps only the examples that fit
+        let mut config = Config::default();
+        config.limits.max_example_tokens = Some(15);
+        interp.configure(&config);
+        let Ok(Value::Object(obj)) = interp.eval(code) else {
+            panic!("Expected Object with __think_prompt");
+        };
+        let prompt = obj["__think_prompt"].to_string_value();
+        assert!(prompt.contains("Input: Great!"), "{}", prompt);
+        assert!(!prompt.contains("This product"), "{}", prompt);
+        let warning = interp.runtime().warnings().last().unwrap();
+        assert_eq!(warning.code, "prompt-truncated");
+        assert_eq!(warning.message, "left out 1 of 2 examples to stay within the token limit");
+    }
+
+    #[test]
+    fn test_think_block_variants() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<crate::ThinkRequest>();
+        let agent = std::thread::spawn(move || {
+            while let Some(request) = rx.blocking_recv() {
+                let result = Ok(Value::String(request.prompt.clone()));
+                let _ = request.response_tx.send(crate::ThinkResponse::Complete { result });
+            }
+        });
+
+        let mut interp = Interpreter::with_agent(AgentHandle::new(tx));
+        let code = r#"{
+            var issue = "the build is red"
+            var answer = think {
+                Read the issue.
+                variant concise { Summarize $issue in one line. }
+                variant detailed { Summarize $issue in a paragraph. }
+            }
+            [answer, last_call_meta().variant]
+        }"#;
+        let run = |interp: &mut Interpreter| match interp.eval(code) {
+            Ok(Value::Array(items)) => (items[0].to_string_value(), items[1].to_string_value()),
+            other => panic!("Expected array, got {:?}", other),
+        };
+
+        // The first variant by default
+        let (prompt, variant) = run(&mut interp);
+        assert!(prompt.contains("Read the issue."), "{}", prompt);
+        assert!(prompt.contains("the build is red") && prompt.ends_with("in one line."), "{}", prompt);
+        assert!(!prompt.contains("paragraph"), "{}", prompt);
+        assert_eq!(variant, "concise");
+
+        let mut config = Config { prompt_variant: crate::VariantPolicy::Pin("detailed".to_string()), ..Config::default() };
+        interp.configure(&config);
+        let (prompt, variant) = run(&mut interp);
+        assert!(prompt.ends_with("in a paragraph."), "{}", prompt);
+        assert_eq!(variant, "detailed");
+
+        // A split with all the weight on one variant always picks it
+        config.prompt_variant = "split:concise=0,detailed=1".parse().unwrap();
+        interp.configure(&config);
+        assert_eq!(run(&mut interp).1, "detailed");
+
+        // Unknown names fall back to the first variant, with a warning
+        config.prompt_variant = crate::VariantPolicy::Pin("verbose".to_string());
+        interp.configure(&config);
+        assert_eq!(run(&mut interp).1, "concise");
+        let warning = interp.runtime().warnings().last().unwrap();
+        assert_eq!(warning.message, "no prompt variant named `verbose`; using `concise`");
+
+        drop(interp);
+        agent.join().unwrap();
+    }
+
+    #[test]
+    fn test_history_builtins() {
+        let mut interp = Interpreter::new();
+        let code = r#"{
+            var before = last_response()
+            var first = think {
+                Summarize the plan.
+            }
+            var second = ask {
+                Does this look right?
+            }
+            var log = history()
+            var summary = [before, len(log), log[0].kind, log[1].kind]
+            summary
+        }"#;
+        let result = interp.eval(code);
+        assert!(result.is_ok(), "Eval failed: {:?}", result);
+        assert_eq!(
+            result.unwrap(),
+            Value::Array(vec![
+                Value::Null,
+                Value::Number(2.0),
+                Value::String("think".to_string()),
+                Value::String("ask".to_string()),
+            ])
+        );
+        let last = interp.runtime().transcript().last().unwrap();
+        assert!(last.prompt.contains("Does this look right?"));
+    }
+
+    #[test]
+    fn test_context_summarization() {
+        let mut interp = Interpreter::new();
+        let mut config = Config::default();
+        config.limits.max_context_tokens = Some(25);
+        interp.configure(&config);
+        let code = r#"{
+            pin("deploy to staging only")
+            var a = think { Step one of the plan. }
+            var b = think { Step two of the plan. }
+            var c = think { Step three of the plan. }
+            var log = history()
+            var summary = [len(log), log[0].kind, log[1].kind, pinned()]
+            summary
+        }"#;
+        let result = interp.eval(code);
+        assert!(result.is_ok(), "Eval failed: {:?}", result);
+        assert_eq!(
+            result.unwrap(),
+            Value::Array(vec![
+                Value::Number(2.0),
+                Value::String("summary".to_string()),
+                Value::String("think".to_string()),
+                Value::Array(vec![Value::String("deploy to staging only".to_string())]),
+            ])
+        );
+        // Without an agent the summary is the placeholder holding its prompt
+        let Value::Object(summary) = &interp.runtime().transcript()[0].response else {
+            panic!("expected the placeholder object");
+        };
+        let summary = summary["__think_prompt"].to_string_value();
+        assert!(summary.contains("- deploy to staging only"));
+        assert!(summary.contains("Step two of the plan."));
+    }
+
+    #[test]
+    fn test_prompts_are_redacted() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<crate::ThinkRequest>();
+        let agent = std::thread::spawn(move || {
+            while let Some(request) = rx.blocking_recv() {
+                let result = Ok(Value::String(request.prompt.clone()));
+                let _ = request.response_tx.send(crate::ThinkResponse::Complete { result });
+            }
+        });
+
+        let mut interp = Interpreter::with_agent(AgentHandle::new(tx));
+        let (warn_tx, _warn_rx) = std::sync::mpsc::channel();
+        interp.set_warning_reporter(warn_tx);
+        let config = Config { redact: vec![crate::RedactionRule::preset("emails").unwrap()], ..Config::default() };
+        interp.configure(&config);
+        let code = r#"{
+            var owner = "ops@example.com"
+            var sent = think { Page ${owner} about the outage. }
+            sent
+        }"#;
+        let result = interp.eval(code);
+        assert!(result.is_ok(), "Eval failed: {:?}", result);
+        let sent = result.unwrap().to_string_value();
+        assert!(sent.contains("[REDACTED:emails]"), "{}", sent);
+        assert!(!sent.contains("ops@example.com"), "{}", sent);
+        let redactions = interp.runtime().redactions();
+        assert_eq!(redactions.len(), 1);
+        assert_eq!(redactions[0].rule, "emails");
+        let warning = interp.runtime().warnings().last().unwrap();
+        assert_eq!(warning.message, "removed 1 match of `emails` from the prompt");
+
+        drop(interp);
+        agent.join().unwrap();
+    }
+
+    #[test]
+    fn test_interpolated_bindings_are_redacted() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<crate::ThinkRequest>();
+        let agent = std::thread::spawn(move || {
+            while let Some(request) = rx.blocking_recv() {
+                let result = Ok(Value::String(request.prompt.clone()));
+                let _ = request.response_tx.send(crate::ThinkResponse::Complete { result });
+            }
+        });
+
+        let mut interp = Interpreter::with_agent(AgentHandle::new(tx));
+        let (warn_tx, _warn_rx) = std::sync::mpsc::channel();
+        interp.set_warning_reporter(warn_tx);
+        let config = Config { redact: vec![crate::RedactionRule::Path("deploy.token".to_string())], ..Config::default() };
+        interp.configure(&config);
+        let code = r#"{
+            var deploy = { token: "s3cret", region: "us-east-1" }
+            var sent = think { deploy to ${deploy.region} with ${deploy.token}, settings ${deploy} }
+            sent
+        }"#;
+        let result = interp.eval(code);
+        assert!(result.is_ok(), "Eval failed: {:?}", result);
+        let sent = result.unwrap().to_string_value();
+        assert!(sent.contains("us-east-1"), "{}", sent);
+        assert!(sent.contains("[REDACTED:deploy.token]"), "{}", sent);
+        assert!(!sent.contains("s3cret"), "{}", sent);
+        let redactions = interp.runtime().redactions();
+        assert_eq!(redactions.len(), 2);
+        assert!(redactions.iter().all(|r| r.rule == "deploy.token"));
+        let warning = interp.runtime().warnings().last().unwrap();
+        assert_eq!(warning.message, "removed 1 match of `deploy.token` from the prompt");
+
+        drop(interp);
+        agent.join().unwrap();
+    }
+
+    #[test]
+    fn test_think_request_fields() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<crate::ThinkRequest>();
+        let (seen_tx, seen_rx) = std::sync::mpsc::channel();
+        let agent = std::thread::spawn(move || {
+            while let Some(request) = rx.blocking_recv() {
+                let result = Ok(Value::String("ok".to_string()));
+                let _ = request.response_tx.send(crate::ThinkResponse::Complete { result });
+                let _ = seen_tx.send((request.op, request.prompt, request.parts, request.expect, request.timeout));
+            }
+        });
+
+        let mut interp = Interpreter::with_agent(AgentHandle::new(tx));
+        let mut config = Config::default();
+        config.limits.think_timeout_secs = Some(30);
+        interp.configure(&config);
+        let code = r#"{
+            var count = 3
+            ask { Review ${count} files. }
+        }"#;
+        let result = interp.eval(code);
+        assert!(result.is_ok(), "Eval failed: {:?}", result);
+
+        let (op, prompt, parts, expect, timeout) = seen_rx.recv().unwrap();
+        assert_eq!(op, crate::ThinkOp::Ask);
+        let [crate::PromptPart::Text(before), crate::PromptPart::Value(count), crate::PromptPart::Text(after)] = parts.as_slice()
+        else {
+            panic!("Expected text, value, text, got {:?}", parts);
+        };
+        assert_eq!(*count, Value::Number(3.0));
+        assert_eq!(prompt, format!("{}3{}", before, after));
+        assert!(before.starts_with("Review") && after.ends_with("files."), "{}", prompt);
+        assert_eq!(expect, crate::Schema::String);
+        assert_eq!(timeout, Some(std::time::Duration::from_secs(30)));
+
+        drop(interp);
+        agent.join().unwrap();
+    }
+
+    #[test]
+    fn test_last_call_meta() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<crate::ThinkRequest>();
+        let agent = std::thread::spawn(move || {
+            while let Some(request) = rx.blocking_recv() {
+                let usage = crate::Usage { input_tokens: 12, output_tokens: 3, cost_usd: None };
+                let _ = request.response_tx.send(crate::ThinkResponse::Usage(usage));
+                let _ = request.response_tx.send(crate::ThinkResponse::Metadata {
+                    model: Some("test-model".to_string()),
+                    stop_reason: Some("end_turn".to_string()),
+                });
+                let _ = request.response_tx.send(crate::ThinkResponse::Complete {
+                    result: Ok(Value::String("yes".to_string())),
+                });
+            }
+        });
+
+        let mut interp = Interpreter::with_agent(AgentHandle::new(tx));
+        let code = r#"{
+            var before = last_call_meta()
+            var answer = think {
+                Is this a test?
+            }
+            var meta = last_call_meta()
+            var summary = [before, meta.value, meta.model, meta.tokens_in, meta.tokens_out, meta.stop_reason]
+            summary
+        }"#;
+        let result = interp.eval(code);
+        assert!(result.is_ok(), "Eval failed: {:?}", result);
+        assert_eq!(
+            result.unwrap(),
+            Value::Array(vec![
+                Value::Null,
+                Value::String("yes".to_string()),
+                Value::String("test-model".to_string()),
+                Value::Number(12.0),
+                Value::Number(3.0),
+                Value::String("end_turn".to_string()),
+            ])
+        );
+
+        drop(interp);
+        agent.join().unwrap();
+    }
+
+    #[test]
+    fn test_model_chain_fails_over() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<crate::ThinkRequest>();
+        let agent = std::thread::spawn(move || {
+            let mut asked = Vec::new();
+            while let Some(request) = rx.blocking_recv() {
+                let model = request.model.clone().unwrap_or_default();
+                let result = match model.as_str() {
+                    "big" => Err("429: rate limit exceeded".to_string()),
+                    "small" => Ok(Value::String("from small".to_string())),
+                    _ => Err("invalid request".to_string()),
+                };
+                let _ = request.response_tx.send(crate::ThinkResponse::Complete { result });
+                asked.push(model);
+            }
+            asked
+        });
+
+        let mut interp = Interpreter::with_agent(AgentHandle::new(tx));
+        let mut config = Config::default();
+        config.models.models = vec!["big".to_string(), "small".to_string()];
+        interp.configure(&config);
+        let code = r#"{
+            var answer = think {
+                Which model am I talking to?
+            }
+            answer
+        }"#;
+        let result = interp.eval(code);
+        assert_eq!(result.unwrap(), Value::String("from small".to_string()));
+        assert_eq!(interp.runtime().last_call_meta().unwrap().model.as_deref(), Some("small"));
+
+        // A class outside the policy fails the block on the first model
+        config.models.models = vec!["unknown".to_string(), "small".to_string()];
+        config.models.failover_on = vec![crate::FailureClass::RateLimit];
+        interp.configure(&config);
+        let result = interp.eval(code);
+        assert!(matches!(&result, Err(Error::Runtime(msg)) if msg.contains("invalid request")), "{:?}", result);
+
+        drop(interp);
+        assert_eq!(agent.join().unwrap(), vec!["big", "small", "unknown"]);
+    }
+
+    #[test]
+    fn test_think_routes_to_agent() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<crate::ThinkRequest>();
+        let agent = std::thread::spawn(move || {
+            let mut routed = Vec::new();
+            while let Some(request) = rx.blocking_recv() {
+                let result = Ok(Value::String("ok".to_string()));
+                let _ = request.response_tx.send(crate::ThinkResponse::Complete { result });
+                routed.push(request.agent);
+            }
+            routed
+        });
+
+        let mut interp = Interpreter::with_agent(AgentHandle::new(tx));
+        let mut config = Config::default();
+        config.models.agent = Some("claude".to_string());
+        interp.configure(&config);
+        let code = "@agent local\nskill triage() {\n  var answer = think {\n    Triage this.\n  }\n  return answer\n}\n\nskill review() {\n  var answer = think {\n    Review this.\n  }\n  return answer\n}\n";
+        interp.load(code).unwrap();
+        interp.call("triage", vec![]).unwrap();
+        interp.call("review", vec![]).unwrap();
+
+        drop(interp);
+        let routed = agent.join().unwrap();
+        assert_eq!(routed, vec![Some("local".to_string()), Some("claude".to_string())]);
+    }
+
+    #[test]
+    fn test_eval_interactive_keeps_bindings() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval_interactive("var x = 40").unwrap(), Value::Null);
+        assert_eq!(interp.eval_interactive("var y = x + 1\ny + 1").unwrap(), Value::Number(42.0));
+
+        // Redeclaring in a later turn shadows the earlier binding
+        interp.eval_interactive("var x = \"again\"").unwrap();
+        assert_eq!(interp.eval_interactive("x").unwrap(), Value::String("again".to_string()));
+
+        // Block input, as sent by ACP code mode
+        assert_eq!(interp.eval_interactive("{ y * 2 }").unwrap(), Value::Number(82.0));
+
+        // A failed turn keeps the bindings made before the error
+        assert!(interp.eval_interactive("var z = 1\nmissing").is_err());
+        assert_eq!(interp.eval_interactive("z").unwrap(), Value::Number(1.0));
+    }
+
+    #[test]
+    fn test_replay_skips_journaled_effects() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let code = "{\n    for var name in [\"a\", \"b\"] {\n        write(\"${name}.txt\", name)\n    }\n}";
+
+        let mut first = Interpreter::with_working_dir(temp_dir.path().to_path_buf());
+        first.eval(code).unwrap();
+        let journal = first.effect_journal().clone();
+        let keys: Vec<&str> = journal.records().iter().map(|r| r.key.as_str()).collect();
+        // Offsets are into the source as parsed, with the block wrapped in a skill
+        let offset = "skill __main__() ".len() + code.find("write").unwrap();
+        assert_eq!(keys, vec![format!("write:{}@0", offset), format!("write:{}@1", offset)]);
+
+        // Replaying with the journal performs neither write again
+        std::fs::remove_file(temp_dir.path().join("a.txt")).unwrap();
+        let mut replay = Interpreter::with_working_dir(temp_dir.path().to_path_buf());
+        replay.set_replay_journal(journal.clone());
+        replay.eval(code).unwrap();
+        assert!(!temp_dir.path().join("a.txt").exists());
+        assert_eq!(replay.effect_journal(), &journal);
+    }
+
+    #[test]
+    fn test_call_entry_points() {
+        let mut interp = Interpreter::new();
+        let err = interp.call("greet", vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "Runtime error: can't call `greet`: no program is loaded");
+
+        let code = "var calls = 0\n\nskill greet(name) {\n  calls = calls + 1\n  return \"Hello, ${name} (${calls})\"\n}\n\nexport default skill count(items) {\n  return len(items)\n}\n";
+        interp.load(code).unwrap();
+        let greeting = interp.call("greet", vec![Value::String("Ada".to_string())]).unwrap();
+        assert_eq!(greeting, Value::String("Hello, Ada (1)".to_string()));
+        // Module-level variables start fresh on every call
+        let greeting = interp.call("greet", vec![Value::String("Grace".to_string())]).unwrap();
+        assert_eq!(greeting, Value::String("Hello, Grace (1)".to_string()));
+        let items = Value::Array(vec![Value::Number(1.0), Value::Number(2.0)]);
+        assert_eq!(interp.call("count", vec![items]).unwrap(), Value::Number(2.0));
+        assert_eq!(interp.program_info().unwrap().default_entry().unwrap().name, "count");
+
+        let err = interp.call("summarize", vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "Runtime error: no entry point named `summarize` (the program declares greet, count)");
+        let err = interp.call("greet", vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "Runtime error: `greet` takes 1 argument, got 0");
+    }
+
+    #[test]
+    fn test_call_applies_skill_annotations() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut interp = Interpreter::with_working_dir(temp_dir.path().to_path_buf());
+        let code = concat!(
+            "@capabilities(shell)\nskill readonly() {\n  write(\"notes.txt\", \"hi\")\n}\n\n",
+            "@timeout(50ms)\nskill slow() {\n  sleep(5)\n}\n\n",
+            "@concurrency(0)\nskill stuck() {}\n\n",
+            "@concurrency(1)\nskill save() {\n  write(\"notes.txt\", \"hi\")\n  return 1\n}\n",
+        );
+        interp.load(code).unwrap();
+
+        let err = interp.call("readonly", vec![]).unwrap_err();
+        assert!(err.to_string().contains("Writing files is disabled"), "{}", err);
+        let err = interp.call("slow", vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "Runtime error: Deadline exceeded");
+        let err = interp.call("stuck", vec![]).unwrap_err();
+        assert!(err.to_string().starts_with("Runtime error: can't run `stuck`: `@concurrency`"), "{}", err);
+
+        // The settings end with the skill's run
+        assert_eq!(interp.call("save", vec![]).unwrap(), Value::Number(1.0));
+        assert!(temp_dir.path().join("notes.txt").exists());
+    }
+
+    #[test]
+    fn test_front_matter_applies_to_its_runs() {
+        let mut interp = Interpreter::new();
+        let code = "---\nparams:\n  greeting: Hello\nlimits:\n  max_loop_iterations: 5\n---\nskill greet(name, greeting) {\n  return \"${greeting}, ${name}\"\n}\n\nskill spin() {\n  var n = 0\n  while (true) {\n    n = n + 1\n  }\n}\n";
+        interp.load(code).unwrap();
+        let greeting = interp.call("greet", vec![Value::String("Ada".to_string())]).unwrap();
+        assert_eq!(greeting, Value::String("Hello, Ada".to_string()));
+        let greeting = interp.call("greet", vec![Value::String("Ada".to_string()), Value::String("Hi".to_string())]).unwrap();
+        assert_eq!(greeting, Value::String("Hi, Ada".to_string()));
+        let err = interp.call("greet", vec![]).unwrap_err();
+        assert_eq!(err.to_string(), "Runtime error: `greet` takes 2 arguments, got 0");
+
+        match interp.call("spin", vec![]) {
+            Err(Error::Runtime(msg)) => assert!(msg.contains("limit of 5 iterations"), "{}", msg),
+            other => panic!("Expected limit error, got {:?}", other),
+        }
+        // The host's settings are back once the run ends
+        assert_eq!(interp.runtime().limits().max_loop_iterations, None);
+
+        let mut config = Config::default();
+        config.capabilities.shell = crate::config::Permission::Deny;
+        interp.configure(&config);
+        let err = interp.eval("---\nrequires: shell\n---\nskill __main__() {}\n").unwrap_err();
+        assert_eq!(err.to_string(), "Runtime error: the program requires `shell`, which the capability policy denies");
+        let err = interp.load("---\nparams: [days]\n---\n").unwrap_err();
+        assert_eq!(err.to_string(), "Parse error: front matter (params): expected a mapping of parameter names");
+    }
+
+    #[test]
+    fn test_module_constants() {
+        let mut interp = Interpreter::new();
+        let code = "const GREETING = \"Hello, \" + NAME\nconst NAME = \"world\"\nvar count = 0\n\nskill __main__() {\n  count = count + 1\n  return \"${GREETING} (${count})\"\n}";
+        assert_eq!(interp.eval(code).unwrap(), Value::String("Hello, world (1)".to_string()));
+        // Each evaluation initializes the module afresh
+        assert_eq!(interp.eval(code).unwrap(), Value::String("Hello, world (1)".to_string()));
+
+        // Module-level variables are read and written by slot from nested scopes
+        let code = "var total = 0\nskill __main__() {\n  for var i in [1, 2, 3] {\n    var total_before = total\n    total = total_before + i\n  }\n  return total\n}";
+        assert_eq!(interp.eval(code).unwrap(), Value::Number(6.0));
+
+        let err = interp.eval("const LIMIT = 3\nskill __main__() {\n  LIMIT = 4\n}").unwrap_err();
+        assert!(err.to_string().contains("Cannot assign to constant 'LIMIT'"), "{}", err);
+
+        let err = interp.eval("const A = B\nconst B = A\nskill __main__() {\n  A\n}").unwrap_err();
+        assert!(err.to_string().contains("A -> B -> A"), "{}", err);
+    }
+
+    #[test]
+    fn test_strict_mode_rejects_coercions() {
+        let mut interp = Interpreter::new();
+        let lenient = [
+            ("{ if \"yes\" { 1 } else { 2 } }", Value::Number(1.0)),
+            ("{ var xs = []\n !xs }", Value::Boolean(true)),
+            ("{ \"n = \" + 3 }", Value::String("n = 3".to_string())),
+            ("{ var o = { a: 1 }\n o.b }", Value::Null),
+            ("{ var xs = [1, 2]\n xs[5] }", Value::Null),
+        ];
+        for (code, expected) in &lenient {
+            assert_eq!(&interp.eval(code).unwrap(), expected, "{}", code);
+        }
+
+        interp.set_strict(true);
+        for (code, _) in &lenient {
+            let err = interp.eval(code).unwrap_err();
+            assert!(err.to_string().contains("Strict mode"), "{}: {}", code, err);
+        }
+
+        // Comparisons and interpolation are still fine
+        assert_eq!(interp.eval("{ var xs = []\n if len(xs) == 0 { 1 } else { 2 } }").unwrap(), Value::Number(1.0));
+        assert_eq!(interp.eval("{ var n = 3\n \"n = ${n}\" }").unwrap(), Value::String("n = 3".to_string()));
+    }
+
+    #[test]
+    fn test_coverage_counts_statements() {
+        let mut interp = Interpreter::new();
+        assert!(interp.coverage().is_none());
+        interp.enable_coverage();
+        interp.set_source_name("tests/retry.pw");
+
+        let code = "{\n  var n = 0\n  while (n < 3) {\n    n = n + 1\n  }\n  if n > 5 {\n    n = 0\n  }\n  n\n}";
+        assert_eq!(interp.eval(code).unwrap(), Value::Number(3.0));
+
+        let report = interp.coverage().unwrap();
+        let file = report.file("tests/retry.pw").unwrap();
+        assert_eq!(
+            file.lines().collect::<Vec<_>>(),
+            vec![(2, 1), (3, 1), (4, 3), (6, 1), (7, 0), (9, 1)]
+        );
+        assert_eq!(file.missed(), vec![7]);
+        assert!(report.to_html().contains("<span class=\"miss\"><span class=\"count\">0</span>    n = 0</span>"));
+    }
+
+    #[test]
+    fn test_spilled_values_are_transparent() {
+        let mut interp = Interpreter::new();
+        let mut config = Config::default();
+        config.limits.spill_threshold_bytes = Some(256);
+        interp.configure(&config);
+
+        let code = "{\n  var line = \"0123456789\" * 100\n  var lines = [line, line]\n  line = line + \"!\"\n  \"${len(line)} ${len(lines)}\"\n}";
+        assert_eq!(interp.eval(code).unwrap(), Value::String("1001 2".to_string()));
+        assert_eq!(interp.runtime().spilled_bytes(), 0);
+    }
+
+    #[test]
+    fn test_program_info() {
+        use crate::EntryKind;
+
+        let mut interp = Interpreter::new();
+        assert!(interp.program_info().is_none());
+
+        let info = interp.load("# Triage new issues.\nexport skill triage(repo: string) {}\nworker label(issue) {}\n").unwrap();
+        assert_eq!(info.entries.len(), 2);
+        assert_eq!(info.entries[0].doc.as_deref(), Some("Triage new issues."));
+        assert_eq!(info.entries[1].kind, EntryKind::Worker);
+
+        // Bare blocks keep the loaded program's description
+        interp.eval("{ 1 }").unwrap();
+        assert!(interp.program_info().unwrap().get("triage").is_some());
+
+        interp.eval("fun __main__() {\n  1\n}").unwrap();
+        assert!(interp.program_info().unwrap().get("triage").is_none());
+
+        assert!(interp.load("skill broken(").is_err());
+    }
+
+    #[test]
+    fn test_host_functions_are_callable() {
+        use crate::ValueType;
+
+        let mut interp = Interpreter::new();
+        let lookup = HostFunction::new("lookup_ticket", |args| {
+            let mut ticket = crate::IndexMap::new();
+            ticket.insert("id".to_string(), args[0].clone());
+            ticket.insert("status".to_string(), Value::String("open".to_string()));
+            Ok(Value::Object(ticket))
+        })
+        .with_param("id", ValueType::String)
+        .with_return(ValueType::Object)
+        .with_doc("Fetch a ticket by id.");
+        interp.register_fn(lookup).unwrap();
+
+        let result = interp.eval(r#"{
+            var ticket = lookup_ticket("PW-7")
+            ticket.status
+        }"#);
+        assert_eq!(result.unwrap(), Value::String("open".to_string()));
+
+        let result = interp.eval(r#"{ lookup_ticket(7) }"#);
+        assert!(matches!(&result, Err(Error::Runtime(msg)) if msg.contains("`id` must be a string")), "{:?}", result);
+
+        let listed: Vec<String> = interp.runtime().host_functions().map(HostFunction::signature).collect();
+        assert_eq!(listed, vec!["lookup_ticket(id: string) -> object"]);
+        assert!(interp.register_fn(HostFunction::new("print", |_| Ok(Value::Null))).is_err());
+    }
+
+    #[test]
+    fn test_sleep_is_cancellable() {
+        let mut interp = Interpreter::new();
+        let token = interp.cancellation_token();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            token.cancel();
+        });
+
+        let started = std::time::Instant::now();
+        let result = interp.eval("{\n    sleep(\"1h\")\n}");
+        canceller.join().unwrap();
+        match result {
+            Err(Error::Runtime(msg)) => assert!(msg.contains("cancelled"), "{}", msg),
+            other => panic!("Expected cancellation, got {:?}", other),
+        }
+        assert!(started.elapsed() < std::time::Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_schedule_at_runs_expression_when_due() {
+        let mut interp = Interpreter::new();
+        let code = r#"{
+            var start = now()
+            var result = schedule_at(start + 0.05, "done at " + typeof(now()))
+            result
+        }"#;
+        let result = interp.eval(code);
+        assert!(result.is_ok(), "Eval failed: {:?}", result);
+        assert_eq!(result.unwrap(), Value::String("done at number".to_string()));
+    }
+
+    #[test]
+    fn test_parse_error_is_rendered() {
+        let mut interp = Interpreter::new();
+        let result = interp.eval("{\n    var = 5\n}");
+        match result {
+            Err(Error::Parse(msg)) => {
+                assert!(msg.starts_with("error: "), "Missing header: {}", msg);
+                assert!(msg.contains("<input>:2:"), "Missing location: {}", msg);
+                assert!(msg.contains("var = 5"), "Missing source line: {}", msg);
+                assert!(msg.contains('^'), "Missing underline: {}", msg);
+            }
+            other => panic!("Expected Parse error, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_exception_propagation() {
         let mut interp = Interpreter::new();
@@ -601,6 +2033,83 @@ mod tests {
         assert_eq!(last.entries[2].content, "c");
     }
 
+    #[test]
+    fn test_progress_reporting() {
+        use crate::runtime::ProgressUpdate;
+        use std::sync::mpsc;
+
+        let (progress_tx, progress_rx) = mpsc::channel::<ProgressUpdate>();
+        let mut interp = Interpreter::new();
+        interp.set_progress_reporter(progress_tx);
+
+        let code = r#"{
+            progress(0.5, "Summarizing")
+            progress(1)
+        }"#;
+        interp.eval(code).unwrap();
+
+        let updates: Vec<ProgressUpdate> = progress_rx.try_iter().collect();
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].render_bar(10), "[#####-----]  50% Summarizing");
+        assert_eq!(updates[1].render_bar(4), "[####] 100%");
+
+        assert!(interp.eval("{ progress(2) }").is_err());
+    }
+
+    #[test]
+    fn test_warnings() {
+        use crate::runtime::Warning;
+        use std::sync::mpsc;
+
+        let (warning_tx, warning_rx) = mpsc::channel::<Warning>();
+        let mut interp = Interpreter::new();
+        interp.set_warning_reporter(warning_tx);
+
+        let code = r#"{
+            warn("3 issues had no labels")
+            "done"
+        }"#;
+        assert_eq!(interp.eval(code).unwrap(), Value::String("done".to_string()));
+
+        let warnings: Vec<Warning> = warning_rx.try_iter().collect();
+        assert_eq!(warnings, interp.runtime().warnings());
+        assert_eq!(warnings[0].to_string(), "warning[warn]: 3 issues had no labels");
+    }
+
+    #[test]
+    fn test_plan_steps_reporting() {
+        use crate::runtime::{PlanEntryStatus, PlanUpdate};
+        use std::sync::mpsc;
+
+        let (plan_tx, plan_rx) = mpsc::channel::<PlanUpdate>();
+        let mut interp = Interpreter::new();
+        interp.set_plan_reporter(plan_tx);
+
+        let code = r#"{
+            plan { step "collect commits"; step "summarize"; step "write report" }
+            step_done()
+            step_done()
+        }"#;
+        interp.eval(code).unwrap();
+
+        let statuses: Vec<Vec<PlanEntryStatus>> = plan_rx
+            .try_iter()
+            .map(|update| update.entries.iter().map(|e| e.status).collect())
+            .collect();
+        use PlanEntryStatus::*;
+        assert_eq!(
+            statuses,
+            vec![
+                vec![InProgress, Pending, Pending],
+                vec![Completed, InProgress, Pending],
+                vec![Completed, Completed, InProgress],
+            ]
+        );
+
+        assert!(interp.eval("{ step_done() }").is_err());
+        assert!(interp.eval("{ plan { step \"only\" }\n step_done()\n step_done() }").is_err());
+    }
+
     #[test]
     fn test_for_loop_thought_reporting() {
         use crate::runtime::ThoughtChunk;
@@ -0,0 +1,122 @@
+//! Idempotency keys for effectful operations.
+//!
+//! Every shell command and file write a program performs gets a key naming
+//! where it happened: its kind, its byte offset in the source, and the
+//! iteration of each enclosing loop. `"shell:120@2.0"` is the command at
+//! offset 120 during the third iteration of the outer loop and the first of
+//! the inner one.
+//!
+//! The runtime records each completed effect and its result in an
+//! `EffectJournal`. A host that restores a run (replaying a recording, or
+//! resuming from a checkpoint) hands the previous journal back with
+//! `Interpreter::set_replay_journal`, and effects already in it return their
+//! recorded result instead of running a second time.
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value as JsonValue};
+
+use crate::value::Value;
+
+/// One effect that completed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectRecord {
+    /// Idempotency key: kind, source offset, and loop iterations.
+    pub key: String,
+    /// What kind of effect this was, e.g. `shell` or `write`.
+    pub kind: String,
+    /// What the operation returned.
+    pub result: Value,
+}
+
+/// Effects performed by a run, oldest first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EffectJournal {
+    records: Vec<EffectRecord>,
+    /// Key -> index into `records`.
+    index: HashMap<String, usize>,
+}
+
+impl EffectJournal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn records(&self) -> &[EffectRecord] {
+        &self.records
+    }
+
+    /// The effect recorded under `key`, if any.
+    pub fn get(&self, key: &str) -> Option<&EffectRecord> {
+        self.index.get(key).map(|&i| &self.records[i])
+    }
+
+    /// Record a completed effect, replacing any earlier record with the same key.
+    pub fn record(&mut self, record: EffectRecord) {
+        match self.index.get(&record.key) {
+            Some(&i) => self.records[i] = record,
+            None => {
+                self.index.insert(record.key.clone(), self.records.len());
+                self.records.push(record);
+            }
+        }
+    }
+
+    /// Serialize as a JSON array of `{key, kind, result}` objects.
+    pub fn to_json(&self) -> String {
+        let records: Vec<JsonValue> = self
+            .records
+            .iter()
+            .map(|r| json!({ "key": r.key, "kind": r.kind, "result": r.result.to_json_value() }))
+            .collect();
+        serde_json::to_string_pretty(&records).unwrap_or_else(|_| "[]".to_string())
+    }
+
+    /// Read a journal written by `to_json`.
+    pub fn from_json(s: &str) -> Result<Self, String> {
+        let records: Vec<JsonValue> =
+            serde_json::from_str(s).map_err(|e| format!("Invalid effect journal: {}", e))?;
+        let mut journal = Self::new();
+        for record in records {
+            let field = |name: &str| {
+                record
+                    .get(name)
+                    .and_then(JsonValue::as_str)
+                    .map(str::to_string)
+                    .ok_or_else(|| format!("Invalid effect journal: record without a `{}`", name))
+            };
+            journal.record(EffectRecord {
+                key: field("key")?,
+                kind: field("kind")?,
+                result: Value::from_json_value(record.get("result").cloned().unwrap_or(JsonValue::Null)),
+            });
+        }
+        Ok(journal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_round_trips_through_json() {
+        let mut journal = EffectJournal::new();
+        journal.record(EffectRecord {
+            key: "shell:12@0".to_string(),
+            kind: "shell".to_string(),
+            result: Value::String("ok\n".to_string()),
+        });
+        journal.record(EffectRecord {
+            key: "write:40".to_string(),
+            kind: "write".to_string(),
+            result: Value::Null,
+        });
+
+        let restored = EffectJournal::from_json(&journal.to_json()).unwrap();
+        assert_eq!(restored, journal);
+        assert_eq!(restored.get("shell:12@0").unwrap().result, Value::String("ok\n".to_string()));
+        assert!(restored.get("shell:12@1").is_none());
+        assert!(EffectJournal::from_json("[{\"kind\": \"shell\"}]").is_err());
+    }
+}
@@ -5,18 +5,76 @@
 //! modeled as `Error::Exception(Value)` and propagate using Rust's `?` operator.
 
 mod agent;
+mod cache;
+mod config;
+mod coverage;
 mod error;
 mod eval;
+mod evals;
+mod front_matter;
+mod github;
+mod host;
 mod interpreter;
+mod journal;
+mod notify;
+mod outcome;
+mod process;
+mod program;
+mod queue;
+mod redact;
+mod render;
+mod repl;
 mod runtime;
+mod sandbox;
+mod schedule;
+mod schema;
+mod session;
+mod slots;
+mod spill;
+mod tasklog;
+mod telemetry;
+mod timer;
 mod value;
 
-pub use agent::{AgentHandle, ThinkRequest, ThinkResponse};
+pub use agent::{AgentHandle, Priority, PromptPart, ThinkOp, ThinkRequest, ThinkResponse, Usage};
+pub use cache::ThinkCache;
+pub use config::{
+    AgentCommand, Backend, BackgroundPolicy, CacheScope, CapabilityPolicy, Config, ConfigError, ConfigLayer,
+    FailureClass, FileAccess, Limits, ModelChain, Permission, Telemetry, VariantPolicy, PROJECT_CONFIG_FILE,
+};
+pub use coverage::{CoverageReport, FileCoverage};
 pub use error::Error;
 pub use eval::{eval_block, eval_expr, eval_statement};
+pub use evals::{CaseResult, EvalReport, EvalResult, PASS_SCORE};
+pub use front_matter::FrontMatter;
+pub use github::GitHub;
+pub use host::{HostFunction, ValueType};
 pub use interpreter::Interpreter;
-pub use runtime::{PlanEntry, PlanEntryStatus, PlanReporter, PlanUpdate, PrintSink, Runtime, ThoughtChunk, ThoughtReporter};
+pub use journal::{EffectJournal, EffectRecord};
+pub use notify::{Delivery, Notifier, Smtp};
+pub use outcome::{Outcome, RunResult};
+pub use program::{EntryKind, EntryPoint, ParamInfo, ProgramInfo};
+pub use queue::{QueueMetrics, ThinkQueue};
+pub use redact::{RedactionEvent, RedactionRule, RedactionTarget, Redactor};
+pub use render::{OutputFormat, ValueRenderer};
+pub use repl::MetaCommand;
+pub use runtime::{
+    ApprovalDecision, ApprovalHandler, ApprovalRequest, CallMeta, PlanEntry, PlanEntryStatus,
+    PlanReporter, PlanUpdate, PrintSink, ProgressReporter, ProgressUpdate, Runtime, ThoughtChunk,
+    ThoughtReporter, TranscriptEntry, Warning, WarningReporter,
+};
+pub use sandbox::{refused_requests, CapabilityRequest, SandboxPolicy, WORKSPACE_POLICY_FILE};
+pub use schedule::{Checkpoint, Schedule, Scheduler};
+pub use schema::{Mismatch, Schema, SchemaField, Schemas};
+pub use session::{remove_expired, Artifact, KeepPolicy, Session, SESSION_SUBDIRS};
+pub use tasklog::{Interleaving, TaskLogs};
+pub use telemetry::{sink_for, JsonFileSink, NoopSink, OtlpSink, TelemetryEvent, TelemetrySink};
+pub use timer::CancellationToken;
 pub use value::Value;
+pub use patchwork_parser::diagnostics;
+pub use patchwork_parser::document;
+/// The map a `Value::Object` holds its fields in.
+pub use indexmap::IndexMap;
 
 /// Result type for interpreter operations.
 pub type Result<T> = std::result::Result<T, Error>;
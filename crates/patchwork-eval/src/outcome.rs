@@ -0,0 +1,204 @@
+//! Exit codes and machine-readable results.
+//!
+//! CI jobs run Patchwork programs and need to tell kinds of failure apart
+//! without scraping stderr. Every host maps a run's result to the same exit
+//! code:
+//!
+//! | code | outcome                                           |
+//! |------|---------------------------------------------------|
+//! | 0    | success                                           |
+//! | 1    | the program failed (runtime error, budget limit)  |
+//! | 2    | an exception was thrown and never caught          |
+//! | 3    | the program did not parse                         |
+//! | 4    | the run was cancelled or its deadline passed      |
+//!
+//! With `--result-json <file>` (or `PATCHWORK_RESULT_JSON`) the host also
+//! writes a `RunResult` there: the outcome, the final value or error, LLM
+//! usage, any warnings, and the artifacts the run stored.
+
+use std::fmt;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::agent::Usage;
+use crate::error::Error;
+use crate::runtime::{Runtime, CANCELLED_MESSAGE, DEADLINE_MESSAGE};
+use crate::session::Artifact;
+use crate::value::Value;
+
+/// How a run ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    Failed,
+    Exception,
+    ParseError,
+    Cancelled,
+}
+
+impl Outcome {
+    /// Classify a run's result.
+    pub fn of(result: &crate::Result<Value>) -> Self {
+        match result {
+            Ok(_) => Outcome::Success,
+            Err(Error::Parse(_)) => Outcome::ParseError,
+            Err(Error::Exception(_)) => Outcome::Exception,
+            Err(Error::Runtime(msg)) if msg.contains(CANCELLED_MESSAGE) || msg.contains(DEADLINE_MESSAGE) => {
+                Outcome::Cancelled
+            }
+            Err(Error::Runtime(_)) | Err(Error::BudgetExceeded(_)) => Outcome::Failed,
+        }
+    }
+
+    /// The process exit code for this outcome.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Outcome::Success => 0,
+            Outcome::Failed => 1,
+            Outcome::Exception => 2,
+            Outcome::ParseError => 3,
+            Outcome::Cancelled => 4,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Success => write!(f, "success"),
+            Outcome::Failed => write!(f, "failed"),
+            Outcome::Exception => write!(f, "exception"),
+            Outcome::ParseError => write!(f, "parse_error"),
+            Outcome::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+/// Everything a CI job needs to know about a finished run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunResult {
+    pub outcome: Outcome,
+    /// The program's final value, on success.
+    pub value: Option<Value>,
+    /// The error message, on failure.
+    pub error: Option<String>,
+    /// The thrown value, for an uncaught exception.
+    pub exception: Option<Value>,
+    pub usage: Usage,
+    pub llm_calls: u64,
+    pub duration: Duration,
+    /// Warnings raised during the run, formatted for display.
+    pub warnings: Vec<String>,
+    /// Outputs the run stored with `artifact.write`.
+    pub artifacts: Vec<Artifact>,
+}
+
+impl RunResult {
+    /// Summarize a run from its result and the runtime it ran in.
+    pub fn new(result: &crate::Result<Value>, runtime: &Runtime, duration: Duration) -> Self {
+        let (value, error, exception) = match result {
+            Ok(value) => (Some(value.clone()), None, None),
+            Err(e) => {
+                let exception = match e {
+                    Error::Exception(thrown) => Some(thrown.clone()),
+                    _ => None,
+                };
+                (None, Some(e.to_string()), exception)
+            }
+        };
+        Self {
+            outcome: Outcome::of(result),
+            value,
+            error,
+            exception,
+            usage: runtime.usage(),
+            llm_calls: runtime.llm_calls(),
+            duration,
+            warnings: runtime.warnings().iter().map(ToString::to_string).collect(),
+            // A manifest that can't be read has nothing to show
+            artifacts: runtime.session().and_then(|session| session.artifacts().ok()).unwrap_or_default(),
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.outcome.exit_code()
+    }
+
+    /// The result as a JSON object.
+    ///
+    /// ```json
+    /// {
+    ///   "outcome": "success",
+    ///   "exit_code": 0,
+    ///   "value": {"summary": "..."},
+    ///   "error": null,
+    ///   "exception": null,
+    ///   "usage": {"llm_calls": 2, "input_tokens": 900, "output_tokens": 120, "cost_usd": 0.01},
+    ///   "duration_ms": 5310,
+    ///   "warnings": ["warning[warn]: 3 issues had no labels"],
+    ///   "artifacts": [{"name": "report.md", "path": "/tmp/.../report.md", "mime": "text/markdown", "size": 812}]
+    /// }
+    /// ```
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.to_json_value()).unwrap_or_default()
+    }
+
+    /// The result as a JSON value, laid out as in `to_json`.
+    pub fn to_json_value(&self) -> serde_json::Value {
+        let value = |value: &Option<Value>| value.as_ref().map_or(serde_json::Value::Null, Value::to_json_value);
+        serde_json::json!({
+            "outcome": self.outcome.to_string(),
+            "exit_code": self.exit_code(),
+            "value": value(&self.value),
+            "error": self.error,
+            "exception": value(&self.exception),
+            "usage": {
+                "llm_calls": self.llm_calls,
+                "input_tokens": self.usage.input_tokens,
+                "output_tokens": self.usage.output_tokens,
+                "cost_usd": self.usage.cost_usd,
+            },
+            "duration_ms": self.duration.as_millis() as u64,
+            "warnings": self.warnings,
+            "artifacts": self.artifacts.iter().map(|a| a.to_value().to_json_value()).collect::<Vec<_>>(),
+        })
+    }
+
+    /// Write the JSON result to `path`.
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_json())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_codes() {
+        let code = |result: crate::Result<Value>| Outcome::of(&result).exit_code();
+        assert_eq!(code(Ok(Value::Null)), 0);
+        assert_eq!(code(Err(Error::Runtime("Undefined variable: x".to_string()))), 1);
+        assert_eq!(code(Err(Error::BudgetExceeded("reached the limit".to_string()))), 1);
+        assert_eq!(code(Err(Error::Exception(Value::String("boom".to_string())))), 2);
+        assert_eq!(code(Err(Error::Parse("unexpected token".to_string()))), 3);
+        assert_eq!(code(Err(Error::Runtime(CANCELLED_MESSAGE.to_string()))), 4);
+        assert_eq!(code(Err(Error::Runtime(DEADLINE_MESSAGE.to_string()))), 4);
+    }
+
+    #[test]
+    fn test_result_json() {
+        let runtime = Runtime::default();
+        let result = Err(Error::Exception(Value::String("boom".to_string())));
+        let run = RunResult::new(&result, &runtime, Duration::from_millis(12));
+        let json: serde_json::Value = serde_json::from_str(&run.to_json()).unwrap();
+        assert_eq!(json["outcome"], "exception");
+        assert_eq!(json["exit_code"], 2);
+        assert_eq!(json["exception"], "boom");
+        assert_eq!(json["value"], serde_json::Value::Null);
+        assert_eq!(json["usage"]["llm_calls"], 0);
+        assert_eq!(json["duration_ms"], 12);
+        assert_eq!(json["warnings"], serde_json::json!([]));
+        assert_eq!(json["artifacts"], serde_json::json!([]));
+    }
+}
@@ -0,0 +1,247 @@
+//! Shell commands running in the background.
+//!
+//! `$(cmd &)` starts a command without waiting for it and returns a handle
+//! to its process, so a program can follow a build or test run as it goes:
+//!
+//! ```text
+//! var tests = $(cargo test &)
+//! for var line in tests.lines() {
+//!   print(line)
+//! }
+//! tests.wait()
+//! ```
+//!
+//! In a `for` loop, `lines()` yields each line of the command's output as
+//! soon as it is written, and the loop ends when the command closes its
+//! output. Anywhere else it waits for the output to end and returns the
+//! lines not read yet. `wait()` waits for the command to exit and returns
+//! the output not read yet, failing like any command that exits with an
+//! error. `kill()` stops it.
+//!
+//! When a program ends with commands still running, the
+//! `background_on_exit` setting decides what happens to them: by default
+//! they are killed, with a `background` warning for each, and with `wait`
+//! the program waits for them as `wait_all` would, failing if any fail. A
+//! program that fails kills them either way, as does dropping the runtime.
+//!
+//! Several commands can be joined at once. `wait_all(handles)` waits for all
+//! of them and returns their outputs in order; if any failed, it throws a
+//! `WaitAllError` carrying every failure once the rest have finished, or
+//! with `{ fail_fast: true }`, the first failure straight away, killing the
+//! others. `wait_any(handles, timeout?)` returns `{ index, output }` for the
+//! first to exit, or null if none has within the timeout.
+//!
+//! The handle is an object `{ process, command, pid }`; `process` is the
+//! number the runtime knows the command by.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use indexmap::IndexMap;
+
+use crate::value::Value;
+
+/// A command started with `$(cmd &)`.
+#[derive(Debug)]
+pub(crate) struct BackgroundProcess {
+    /// The command line, for messages.
+    command: String,
+    child: Child,
+    /// Lines of standard output, read on a thread of their own so the
+    /// command never blocks on a full pipe.
+    lines: Receiver<String>,
+    /// Standard error, collected on a thread of its own.
+    stderr: Option<JoinHandle<Vec<u8>>>,
+}
+
+/// What `BackgroundProcess::next_line` found.
+pub(crate) enum NextLine {
+    Line(String),
+    /// Nothing yet, but the command may still write more.
+    Pending,
+    /// The command closed its output.
+    Done,
+}
+
+impl BackgroundProcess {
+    /// Start `command`, which `display` describes.
+    pub(crate) fn spawn(command: &mut Command, display: String) -> io::Result<Self> {
+        let mut child = command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (line_tx, lines) = mpsc::channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            let mut buf = Vec::new();
+            loop {
+                buf.clear();
+                match reader.read_until(b'\n', &mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+                let line = String::from_utf8_lossy(&buf);
+                let line = line.strip_suffix('\n').unwrap_or(&line);
+                let line = line.strip_suffix('\r').unwrap_or(line);
+                if line_tx.send(line.to_string()).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        let stderr = thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = stderr.read_to_end(&mut buf);
+            buf
+        });
+        Ok(Self { command: display, child, lines, stderr: Some(stderr) })
+    }
+
+    pub(crate) fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// The next line of output, waiting up to `timeout` for one.
+    pub(crate) fn next_line(&self, timeout: Duration) -> NextLine {
+        match self.lines.recv_timeout(timeout) {
+            Ok(line) => NextLine::Line(line),
+            Err(RecvTimeoutError::Timeout) => NextLine::Pending,
+            Err(RecvTimeoutError::Disconnected) => NextLine::Done,
+        }
+    }
+
+    /// How the command exited, if it has.
+    pub(crate) fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        self.child.try_wait()
+    }
+
+    /// Once the command has exited, the lines of output not read yet,
+    /// joined, and its standard error.
+    pub(crate) fn finish(&mut self) -> (String, Vec<u8>) {
+        let mut stdout = String::new();
+        for line in self.lines.iter() {
+            stdout.push_str(&line);
+            stdout.push('\n');
+        }
+        let stderr = self.stderr.take().and_then(|stderr| stderr.join().ok()).unwrap_or_default();
+        (stdout, stderr)
+    }
+
+    pub(crate) fn kill(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+
+    /// The handle programs see.
+    fn to_value(&self, id: u64) -> Value {
+        let mut fields = IndexMap::new();
+        fields.insert("process".to_string(), Value::Number(id as f64));
+        fields.insert("command".to_string(), Value::String(self.command.clone()));
+        fields.insert("pid".to_string(), Value::Number(self.child.id() as f64));
+        Value::Object(fields)
+    }
+}
+
+impl Drop for BackgroundProcess {
+    fn drop(&mut self) {
+        // Nothing can read its output any more
+        if let Ok(None) = self.child.try_wait() {
+            self.kill();
+        }
+    }
+}
+
+/// The background processes a runtime started and hasn't waited for.
+#[derive(Debug, Default)]
+pub(crate) struct Processes {
+    running: HashMap<u64, BackgroundProcess>,
+    next_id: u64,
+}
+
+impl Processes {
+    /// Keep `process`, returning the handle for the program.
+    pub(crate) fn add(&mut self, process: BackgroundProcess) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        let handle = process.to_value(id);
+        self.running.insert(id, process);
+        handle
+    }
+
+    pub(crate) fn get(&mut self, id: u64) -> Result<&mut BackgroundProcess, String> {
+        self.running.get_mut(&id).ok_or_else(|| finished(id))
+    }
+
+    pub(crate) fn remove(&mut self, id: u64) -> Result<BackgroundProcess, String> {
+        self.running.remove(&id).ok_or_else(|| finished(id))
+    }
+
+    /// Handles to every process, in the order they were started.
+    pub(crate) fn handles(&self) -> Vec<Value> {
+        let mut ids: Vec<&u64> = self.running.keys().collect();
+        ids.sort();
+        ids.into_iter().map(|&id| self.running[&id].to_value(id)).collect()
+    }
+
+    /// Give up every process, in the order they were started. Dropping one
+    /// kills it if it is still running.
+    pub(crate) fn drain(&mut self) -> Vec<BackgroundProcess> {
+        let mut running: Vec<(u64, BackgroundProcess)> = self.running.drain().collect();
+        running.sort_by_key(|(id, _)| *id);
+        running.into_iter().map(|(_, process)| process).collect()
+    }
+}
+
+fn finished(id: u64) -> String {
+    format!("Background process {} has already been waited for or killed", id)
+}
+
+/// The process number in a handle from `$(cmd &)`, if `value` is one.
+pub(crate) fn process_id(value: &Value) -> Option<u64> {
+    let Value::Object(fields) = value else {
+        return None;
+    };
+    match (fields.get("process"), fields.get("pid")) {
+        (Some(Value::Number(id)), Some(Value::Number(_))) if id.fract() == 0.0 && *id >= 0.0 => Some(*id as u64),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_background_process_lines() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo one; echo two >&2; printf 'two\\nthree'"]);
+        let mut processes = Processes::default();
+        let handle = processes.add(BackgroundProcess::spawn(&mut command, "sh -c ...".to_string()).unwrap());
+        let id = process_id(&handle).unwrap();
+
+        let process = processes.get(id).unwrap();
+        let mut lines = Vec::new();
+        loop {
+            match process.next_line(Duration::from_millis(100)) {
+                NextLine::Line(line) => lines.push(line),
+                NextLine::Pending => {}
+                NextLine::Done => break,
+            }
+        }
+        // The last line needn't end in a newline
+        assert_eq!(lines, vec!["one", "two", "three"]);
+
+        let mut process = processes.remove(id).unwrap();
+        let status = loop {
+            if let Some(status) = process.try_wait().unwrap() {
+                break status;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert!(status.success());
+        assert_eq!(process.finish(), (String::new(), b"two\n".to_vec()));
+        assert!(processes.get(id).is_err());
+    }
+}
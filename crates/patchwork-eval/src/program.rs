@@ -0,0 +1,244 @@
+//! What a loaded program declares.
+//!
+//! Hosts advertise a program's entry points (ACP slash commands, MCP tools)
+//! from a `ProgramInfo` instead of parsing the source again. Each entry
+//! carries its parameters and the `#` comment lines directly above its
+//! declaration:
+//!
+//! ```text
+//! # Summarize recent commits.
+//! # Returns a markdown report.
+//! export skill summarize(repo: string, days) { ... }
+//! ```
+//!
+//! A program may declare any number of entry points; hosts pick one by name
+//! and run it with `Interpreter::call`. Defaults for their parameters come
+//! from the program's front matter; see `FrontMatter`.
+
+use patchwork_parser::ast::{Item, Param, Program, TypeExpr};
+
+use crate::front_matter::FrontMatter;
+use crate::value::Value;
+
+/// The kind of declaration an entry point comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    Skill,
+    Worker,
+    Function,
+}
+
+/// A declared parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParamInfo {
+    pub name: String,
+    /// The type annotation as written, e.g. `[string]`.
+    pub type_ann: Option<String>,
+    /// What `Interpreter::call` passes when the caller leaves it out.
+    pub default: Option<Value>,
+}
+
+/// A skill, worker, or function declared at the top level of a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryPoint {
+    pub kind: EntryKind,
+    pub name: String,
+    pub params: Vec<ParamInfo>,
+    /// The comment block directly above the declaration, without the `#`s.
+    pub doc: Option<String>,
+    pub is_exported: bool,
+    pub is_default: bool,
+}
+
+/// The entry points of a program, in declaration order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProgramInfo {
+    pub entries: Vec<EntryPoint>,
+    pub front_matter: Option<FrontMatter>,
+}
+
+impl ProgramInfo {
+    /// Collect the entry points of `program`, which was parsed from `source`.
+    pub fn from_program(program: &Program, source: &str) -> Self {
+        let entries = program
+            .items
+            .iter()
+            .filter_map(|item| {
+                let (kind, name, params, is_exported, is_default) = match item {
+                    Item::Skill(s) => (EntryKind::Skill, s.name, &s.params, s.is_exported, s.is_default),
+                    Item::Worker(w) => (EntryKind::Worker, w.name, &w.params, w.is_exported, w.is_default),
+                    Item::Function(f) => (EntryKind::Function, f.name, &f.params, f.is_exported, f.is_default),
+                    _ => return None,
+                };
+                Some(EntryPoint {
+                    kind,
+                    name: name.to_string(),
+                    params: params.iter().map(param_info).collect(),
+                    doc: doc_comment(name, source),
+                    is_exported,
+                    is_default,
+                })
+            })
+            .collect();
+        Self { entries, front_matter: None }
+    }
+
+    /// Attach the program's front matter, filling in parameter defaults.
+    pub fn with_front_matter(mut self, front_matter: Option<FrontMatter>) -> Self {
+        if let Some(front) = &front_matter {
+            for param in self.entries.iter_mut().flat_map(|entry| entry.params.iter_mut()) {
+                param.default = front.params.get(&param.name).cloned();
+            }
+        }
+        self.front_matter = front_matter;
+        self
+    }
+
+    /// The entry point named `name`, if the program declares one.
+    pub fn get(&self, name: &str) -> Option<&EntryPoint> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// The entry point to run when the caller doesn't name one: the one
+    /// declared `default`, or else the program's only skill.
+    pub fn default_entry(&self) -> Option<&EntryPoint> {
+        if let Some(entry) = self.entries.iter().find(|entry| entry.is_default) {
+            return Some(entry);
+        }
+        let mut skills = self.entries.iter().filter(|entry| entry.kind == EntryKind::Skill);
+        match (skills.next(), skills.next()) {
+            (Some(only), None) => Some(only),
+            _ => None,
+        }
+    }
+}
+
+fn param_info(param: &Param) -> ParamInfo {
+    ParamInfo {
+        name: param.name.to_string(),
+        type_ann: param.type_ann.as_ref().map(format_type),
+        default: None,
+    }
+}
+
+/// Write a type annotation back out as source text.
+fn format_type(ty: &TypeExpr) -> String {
+    match ty {
+        TypeExpr::Name(name) => name.to_string(),
+        TypeExpr::Literal(text) => format!("\"{}\"", text),
+        TypeExpr::Array(element) => format!("[{}]", format_type(element)),
+        TypeExpr::Union(types) => types.iter().map(format_type).collect::<Vec<_>>().join(" | "),
+        TypeExpr::Object(fields) => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|f| format!("{}{}: {}", f.key, if f.optional { "?" } else { "" }, format_type(&f.type_expr)))
+                .collect();
+            format!("{{ {} }}", fields.join(", "))
+        }
+    }
+}
+
+/// The `#` lines directly above the line declaring `name`, which is a slice
+/// of `source`.
+fn doc_comment(name: &str, source: &str) -> Option<String> {
+    let offset = (name.as_ptr() as usize).checked_sub(source.as_ptr() as usize)?;
+    if offset > source.len() {
+        return None;
+    }
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+
+    let mut lines: Vec<&str> = source[..line_start]
+        .lines()
+        .rev()
+        .map(str::trim)
+        .take_while(|line| line.starts_with('#'))
+        .map(|line| {
+            let text = &line[1..];
+            text.strip_prefix(' ').unwrap_or(text)
+        })
+        .collect();
+    if lines.is_empty() {
+        return None;
+    }
+    lines.reverse();
+    Some(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_points_with_docs() {
+        let source = r#"
+# Not attached: a blank line follows.
+
+# Summarize recent commits.
+#
+# Returns a markdown report.
+export skill summarize(repo: string, days, labels: [string], mode: "brief" | "full") {}
+
+type Report = { title: string }
+
+worker review(report: Report) {}
+fun helper() {}
+"#;
+        let program = patchwork_parser::parse(source).unwrap();
+        let info = ProgramInfo::from_program(&program, source);
+
+        let names: Vec<&str> = info.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["summarize", "review", "helper"]);
+
+        let summarize = info.get("summarize").unwrap();
+        assert_eq!(summarize.kind, EntryKind::Skill);
+        assert!(summarize.is_exported);
+        assert_eq!(summarize.doc.as_deref(), Some("Summarize recent commits.\n\nReturns a markdown report."));
+        let params: Vec<(&str, Option<&str>)> = summarize
+            .params
+            .iter()
+            .map(|p| (p.name.as_str(), p.type_ann.as_deref()))
+            .collect();
+        assert_eq!(
+            params,
+            vec![
+                ("repo", Some("string")),
+                ("days", None),
+                ("labels", Some("[string]")),
+                ("mode", Some("\"brief\" | \"full\"")),
+            ]
+        );
+
+        assert_eq!(info.get("review").unwrap().kind, EntryKind::Worker);
+        assert_eq!(info.get("review").unwrap().doc, None);
+        assert_eq!(info.get("helper").unwrap().kind, EntryKind::Function);
+        assert!(info.get("Report").is_none());
+        // The only skill is the default
+        assert_eq!(info.default_entry().unwrap().name, "summarize");
+    }
+
+    #[test]
+    fn test_default_entry() {
+        let entries = |source: &str| ProgramInfo::from_program(&patchwork_parser::parse(source).unwrap(), source);
+        let info = entries("skill triage() {}
+export default skill summarize_release() {}
+fun helper() {}
+");
+        assert_eq!(info.default_entry().unwrap().name, "summarize_release");
+        let info = entries("skill triage() {}
+skill summarize_release() {}
+");
+        assert_eq!(info.default_entry(), None);
+    }
+
+    #[test]
+    fn test_param_defaults_from_front_matter() {
+        let source = "---\nparams:\n  days: 7\n---\n# Weekly digest.\nskill digest(repo, days) {}\n";
+        let front = FrontMatter::from_source(source).unwrap();
+        let info = ProgramInfo::from_program(&patchwork_parser::parse(source).unwrap(), source).with_front_matter(front);
+        let digest = info.get("digest").unwrap();
+        assert_eq!(digest.doc.as_deref(), Some("Weekly digest."));
+        assert_eq!(digest.params[0].default, None);
+        assert_eq!(digest.params[1].default, Some(Value::Number(7.0)));
+        assert!(info.front_matter.is_some());
+    }
+}
@@ -0,0 +1,214 @@
+//! Think requests from many programs, answered most urgent first.
+//!
+//! A host running several interpreters against one backend, such as an
+//! interactive session next to a batch fan-out, puts a `ThinkQueue` between
+//! them. The queue lets at most `capacity` requests through to the backend
+//! at a time. When one is answered, the next to go is the oldest waiting
+//! request of the highest priority: an `ask` someone is waiting on, then
+//! foreground `think` blocks, then background work. Interpreters pick their
+//! priority with `Interpreter::set_think_priority`.
+//!
+//! ```ignore
+//! let (backend_tx, backend_rx) = tokio::sync::mpsc::unbounded_channel();
+//! let queue = ThinkQueue::new(backend_tx, 4);
+//! let mut session = Interpreter::with_agent(queue.handle());
+//! let mut worker = Interpreter::with_agent(queue.handle());
+//! worker.set_think_priority(Priority::Background);
+//! ```
+//!
+//! `metrics` reports how many requests are waiting at each priority and how
+//! many the backend is working on, for hosts to show or export.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+
+use crate::agent::{AgentHandle, Priority, ThinkRequest, ThinkResponse};
+
+/// A snapshot of a `ThinkQueue`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct QueueMetrics {
+    /// Requests waiting for the backend, by priority.
+    pub waiting: HashMap<Priority, usize>,
+    /// Requests the backend is working on.
+    pub in_flight: usize,
+    /// The most requests that have been waiting at once.
+    pub peak_depth: usize,
+}
+
+impl QueueMetrics {
+    /// Requests waiting for the backend, at every priority.
+    pub fn depth(&self) -> usize {
+        self.waiting.values().sum()
+    }
+}
+
+/// Passes think requests to a backend a few at a time, by priority.
+#[derive(Clone)]
+pub struct ThinkQueue {
+    state: Arc<Mutex<QueueState>>,
+    intake: UnboundedSender<ThinkRequest>,
+}
+
+struct QueueState {
+    backend: UnboundedSender<ThinkRequest>,
+    capacity: usize,
+    /// Waiting requests, oldest first, one queue per priority in
+    /// `Priority::ALL` order.
+    waiting: [VecDeque<ThinkRequest>; 3],
+    in_flight: usize,
+    peak_depth: usize,
+}
+
+impl ThinkQueue {
+    /// A queue passing requests to `backend`, at most `capacity` at a time.
+    pub fn new(backend: UnboundedSender<ThinkRequest>, capacity: usize) -> Self {
+        let state = Arc::new(Mutex::new(QueueState {
+            backend,
+            capacity: capacity.max(1),
+            waiting: Default::default(),
+            in_flight: 0,
+            peak_depth: 0,
+        }));
+        let (intake, mut requests) = unbounded_channel::<ThinkRequest>();
+        let queued = state.clone();
+        thread::spawn(move || {
+            while let Some(request) = requests.blocking_recv() {
+                let mut state = queued.lock().unwrap();
+                state.waiting[request.priority as usize].push_back(request);
+                let depth = state.depth();
+                state.peak_depth = state.peak_depth.max(depth);
+                dispatch(&mut state, &queued);
+            }
+        });
+        Self { state, intake }
+    }
+
+    /// A handle for an interpreter to send its requests through the queue.
+    pub fn handle(&self) -> AgentHandle {
+        AgentHandle::new(self.intake.clone())
+    }
+
+    pub fn metrics(&self) -> QueueMetrics {
+        let state = self.state.lock().unwrap();
+        QueueMetrics {
+            waiting: Priority::ALL.into_iter().zip(state.waiting.iter().map(VecDeque::len)).collect(),
+            in_flight: state.in_flight,
+            peak_depth: state.peak_depth,
+        }
+    }
+}
+
+impl QueueState {
+    fn depth(&self) -> usize {
+        self.waiting.iter().map(VecDeque::len).sum()
+    }
+}
+
+/// Send waiting requests to the backend, most urgent first, while it has
+/// room for them.
+fn dispatch(state: &mut QueueState, shared: &Arc<Mutex<QueueState>>) {
+    while state.in_flight < state.capacity {
+        let Some(mut request) = state.waiting.iter_mut().find_map(VecDeque::pop_front) else {
+            return;
+        };
+        // Watch the answers go by, to free the slot once the last one has
+        let (response_tx, responses) = mpsc::channel();
+        let program = std::mem::replace(&mut request.response_tx, response_tx);
+        if state.backend.send(request).is_err() {
+            // The backend is gone; dropping the request tells the program
+            continue;
+        }
+        state.in_flight += 1;
+        let shared = shared.clone();
+        thread::spawn(move || {
+            for response in responses {
+                let done = matches!(response, ThinkResponse::Complete { .. });
+                // The program may have stopped waiting, but the slot is
+                // taken until the backend is done
+                let _ = program.send(response);
+                if done {
+                    break;
+                }
+            }
+            let mut state = shared.lock().unwrap();
+            state.in_flight -= 1;
+            dispatch(&mut state, &shared);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::ThinkOp;
+    use crate::schema::Schema;
+    use crate::value::Value;
+
+    fn request(prompt: &str, priority: Priority) -> (ThinkRequest, mpsc::Receiver<ThinkResponse>) {
+        let (response_tx, rx) = mpsc::channel();
+        let request = ThinkRequest {
+            op: ThinkOp::Think,
+            prompt: prompt.to_string(),
+            parts: Vec::new(),
+            bindings: HashMap::new(),
+            expect: Schema::String,
+            model: None,
+            agent: None,
+            timeout: None,
+            priority,
+            response_tx,
+        };
+        (request, rx)
+    }
+
+    #[test]
+    fn test_think_queue_priority() {
+        let (backend_tx, mut backend_rx) = unbounded_channel();
+        let queue = ThinkQueue::new(backend_tx, 1);
+        let agent = queue.handle();
+
+        let (first, first_rx) = request("first", Priority::Background);
+        agent.think(first).unwrap();
+        let first = backend_rx.blocking_recv().unwrap();
+        assert_eq!(first.prompt, "first");
+
+        // With the backend busy, later requests wait their turn
+        let mut answers = Vec::new();
+        for (prompt, priority) in
+            [("batch", Priority::Background), ("think", Priority::Foreground), ("ask", Priority::Interactive)]
+        {
+            let (request, rx) = request(prompt, priority);
+            agent.think(request).unwrap();
+            answers.push(rx);
+        }
+        while queue.metrics().depth() < 3 {
+            thread::yield_now();
+        }
+        let metrics = queue.metrics();
+        assert_eq!(metrics.in_flight, 1);
+        assert_eq!(metrics.waiting[&Priority::Interactive], 1);
+        assert_eq!(metrics.peak_depth, 3);
+
+        let complete = |request: ThinkRequest| {
+            let result = Ok(Value::String(request.prompt.clone()));
+            request.response_tx.send(ThinkResponse::Complete { result }).unwrap();
+        };
+        complete(first);
+        assert!(matches!(first_rx.recv().unwrap(), ThinkResponse::Complete { .. }));
+
+        let mut order = Vec::new();
+        for _ in 0..3 {
+            let next = backend_rx.blocking_recv().unwrap();
+            order.push(next.prompt.clone());
+            complete(next);
+        }
+        assert_eq!(order, vec!["ask", "think", "batch"]);
+        for rx in answers {
+            assert!(matches!(rx.recv().unwrap(), ThinkResponse::Complete { result: Ok(_) }));
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! Rendering values for people.
+//!
+//! Hosts show a program's result (and REPL turns show each value) through a
+//! `ValueRenderer`, so `--format` means the same thing everywhere:
+//!
+//! - `pretty`, the default: Patchwork literal syntax, optionally colored,
+//!   broken over lines when long, with huge strings and arrays cut short as
+//!   `… 120 more`.
+//! - `plain`: the text `print()` would show, cut short the same way.
+//! - `json`: the complete value as JSON, for other programs to read.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::value::Value;
+
+const RESET: &str = "\x1b[0m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const MAGENTA: &str = "\x1b[35m";
+const CYAN: &str = "\x1b[36m";
+const DIM: &str = "\x1b[2m";
+
+/// Lines longer than this are broken up in `pretty` output.
+const WIDTH: usize = 80;
+
+/// How a host prints values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Pretty,
+    Plain,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(OutputFormat::Pretty),
+            "plain" => Ok(OutputFormat::Plain),
+            "json" => Ok(OutputFormat::Json),
+            other => Err(format!("unknown output format `{}` (expected pretty, plain, or json)", other)),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Pretty => write!(f, "pretty"),
+            OutputFormat::Plain => write!(f, "plain"),
+            OutputFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Renders values in an `OutputFormat`.
+#[derive(Debug, Clone, Copy)]
+pub struct ValueRenderer {
+    pub format: OutputFormat,
+    /// Use ANSI colors in `pretty` output.
+    pub color: bool,
+    /// Characters of a string shown before cutting it short.
+    pub max_string_chars: usize,
+    /// Items of an array, set, or object shown before cutting it short.
+    pub max_items: usize,
+}
+
+impl Default for ValueRenderer {
+    fn default() -> Self {
+        Self::new(OutputFormat::default())
+    }
+}
+
+impl ValueRenderer {
+    /// A renderer without colors.
+    pub fn new(format: OutputFormat) -> Self {
+        Self { format, color: false, max_string_chars: 2000, max_items: 100 }
+    }
+
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn render(&self, value: &Value) -> String {
+        match self.format {
+            OutputFormat::Json => value.to_json(),
+            OutputFormat::Plain => self.plain(value),
+            OutputFormat::Pretty => {
+                let mut out = String::new();
+                self.pretty(&mut out, value, 0);
+                out
+            }
+        }
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn more(&self, count: usize) -> String {
+        self.paint(DIM, &format!("… {} more", count))
+    }
+
+    fn truncate<'a>(&self, text: &'a str) -> (&'a str, usize) {
+        match text.char_indices().nth(self.max_string_chars) {
+            Some((cut, _)) => (&text[..cut], text[cut..].chars().count()),
+            None => (text, 0),
+        }
+    }
+
+    fn plain(&self, value: &Value) -> String {
+        match value {
+            Value::String(s) => match self.truncate(s) {
+                (text, 0) => text.to_string(),
+                (text, rest) => format!("{}{}", text, self.more(rest)),
+            },
+            Value::Array(items) | Value::Set(items) if items.len() > self.max_items => {
+                let shown: Vec<String> = items[..self.max_items].iter().map(|v| self.plain(v)).collect();
+                format!("{}, {}", shown.join(", "), self.more(items.len() - self.max_items))
+            }
+            other => other.to_string_value(),
+        }
+    }
+
+    fn pretty(&self, out: &mut String, value: &Value, indent: usize) {
+        match value {
+            Value::Null => out.push_str(&self.paint(MAGENTA, "null")),
+            Value::Boolean(b) => out.push_str(&self.paint(MAGENTA, &b.to_string())),
+            Value::Number(_) => out.push_str(&self.paint(YELLOW, &value.to_string_value())),
+            Value::String(s) => {
+                let (text, rest) = self.truncate(s);
+                out.push_str(&self.paint(GREEN, &format!("{:?}", text)));
+                if rest > 0 {
+                    out.push(' ');
+                    out.push_str(&self.more(rest));
+                }
+            }
+            Value::Array(items) => self.pretty_items(out, "[", "]", items, indent),
+            Value::Set(items) => self.pretty_items(out, "set([", "])", items, indent),
+            Value::Tuple(items) => self.pretty_items(out, "tuple([", "])", items, indent),
+            Value::Object(fields) => {
+                let entries: Vec<(String, &Value)> =
+                    fields.iter().map(|(key, value)| (self.paint(CYAN, key), value)).collect();
+                self.pretty_entries(out, "{", "}", &entries, indent);
+            }
+        }
+    }
+
+    fn pretty_items(&self, out: &mut String, open: &str, close: &str, items: &[Value], indent: usize) {
+        let entries: Vec<(String, &Value)> = items.iter().map(|item| (String::new(), item)).collect();
+        self.pretty_entries(out, open, close, &entries, indent);
+    }
+
+    /// Write `open entry, entry close` on one line if it fits, or one entry
+    /// per line if not. Entries with a non-empty label are `label: value`.
+    fn pretty_entries(
+        &self,
+        out: &mut String,
+        open: &str,
+        close: &str,
+        entries: &[(String, &Value)],
+        indent: usize,
+    ) {
+        let hidden = entries.len().saturating_sub(self.max_items);
+        let mut parts: Vec<String> = entries[..entries.len() - hidden]
+            .iter()
+            .map(|(label, value)| {
+                let mut part = String::new();
+                if !label.is_empty() {
+                    part.push_str(label);
+                    part.push_str(": ");
+                }
+                self.pretty(&mut part, value, indent + 1);
+                part
+            })
+            .collect();
+        if hidden > 0 {
+            parts.push(self.more(hidden));
+        }
+        if parts.is_empty() {
+            out.push_str(open);
+            out.push_str(close);
+            return;
+        }
+
+        // Objects put spaces inside their braces
+        let pad = if open == "{" { " " } else { "" };
+        let one_line = format!("{}{}{}{}{}", open, pad, parts.join(", "), pad, close);
+        if visible_width(&one_line) + indent * 2 <= WIDTH && !one_line.contains('\n') {
+            out.push_str(&one_line);
+            return;
+        }
+        let inner = "  ".repeat(indent + 1);
+        out.push_str(open);
+        out.push('\n');
+        for part in parts {
+            out.push_str(&inner);
+            out.push_str(&part);
+            out.push_str(",\n");
+        }
+        out.push_str(&"  ".repeat(indent));
+        out.push_str(close);
+    }
+}
+
+/// Width of `text` on a terminal, not counting ANSI escapes.
+fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut in_escape = false;
+    for c in text.chars() {
+        match c {
+            '\x1b' => in_escape = true,
+            'm' if in_escape => in_escape = false,
+            _ if in_escape => {}
+            _ => width += 1,
+        }
+    }
+    width
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indexmap::IndexMap;
+
+    #[test]
+    fn test_formats_and_truncation() {
+        let mut fields = IndexMap::new();
+        fields.insert("name".to_string(), Value::String("pw".to_string()));
+        fields.insert("tags".to_string(), Value::Array(vec![Value::Number(1.0), Value::Null]));
+        let object = Value::Object(fields);
+
+        let pretty = ValueRenderer::new(OutputFormat::Pretty);
+        assert_eq!(pretty.render(&object), "{ name: \"pw\", tags: [1, null] }");
+        assert_eq!(
+            ValueRenderer::new(OutputFormat::Pretty).with_color(true).render(&Value::Number(2.5)),
+            "\x1b[33m2.5\x1b[0m"
+        );
+
+        let short = ValueRenderer { max_string_chars: 3, max_items: 2, ..pretty };
+        assert_eq!(short.render(&Value::String("abcdef".to_string())), "\"abc\" … 3 more");
+        let numbers = Value::Array((0..5).map(|n| Value::Number(n as f64)).collect());
+        assert_eq!(short.render(&numbers), "[0, 1, … 3 more]");
+        let plain = ValueRenderer { format: OutputFormat::Plain, ..short };
+        assert_eq!(plain.render(&Value::String("abcdef".to_string())), "abc… 3 more");
+        assert_eq!(plain.render(&numbers), "0, 1, … 3 more");
+
+        let long = Value::Array((0..30).map(|n| Value::String(format!("item {}", n))).collect());
+        let rendered = pretty.render(&long);
+        assert!(rendered.starts_with("[\n  \"item 0\",\n  \"item 1\",\n"));
+        assert!(rendered.ends_with("  \"item 29\",\n]"));
+
+        let json = ValueRenderer::new(OutputFormat::Json);
+        assert_eq!(json.render(&Value::String("abcdef".to_string())), "\"abcdef\"");
+        assert_eq!("json".parse::<OutputFormat>(), Ok(OutputFormat::Json));
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+}
@@ -0,0 +1,207 @@
+//! Meta-commands for interactive sessions.
+//!
+//! A REPL line starting with `:` is a command to the session rather than
+//! Patchwork code:
+//!
+//! ```text
+//! :vars                  list the variables in scope
+//! :type <expr>           evaluate an expression and show its type
+//! :ast <code>            show the syntax tree of a snippet without running it
+//! :time <expr>           evaluate an expression and show how long it took
+//! :load <file>           run a .pw file, or restore a session saved as .json
+//! :reload <file>         re-read a module's declarations after editing it
+//! :save <file.json>      save the session's variables
+//! :help                  list these commands
+//! ```
+//!
+//! Relative paths are resolved against the interpreter's working directory.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use patchwork_parser::ast_dump::dump_block;
+
+use crate::error::Error;
+use crate::eval::type_name;
+use crate::interpreter::{format_parse_error, Interpreter};
+use crate::render::ValueRenderer;
+
+/// A parsed meta-command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetaCommand {
+    Vars,
+    Type(String),
+    Ast(String),
+    Time(String),
+    Load(PathBuf),
+    Reload(PathBuf),
+    Save(PathBuf),
+    Help,
+}
+
+const HELP: &str = "\
+:vars                  list the variables in scope
+:type <expr>           evaluate an expression and show its type
+:ast <code>            show the syntax tree of a snippet without running it
+:time <expr>           evaluate an expression and show how long it took
+:load <file>           run a .pw file, or restore a session saved as .json
+:reload <file>         re-read a module's declarations after editing it
+:save <file.json>      save the session's variables
+:help                  list these commands";
+
+impl MetaCommand {
+    /// Parse a REPL line. Returns `None` if the line is code rather than a
+    /// command, and an error for an unknown command or missing argument.
+    pub fn parse(line: &str) -> Option<Result<Self, String>> {
+        let rest = line.trim().strip_prefix(':')?;
+        let (name, arg) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let arg = arg.trim();
+        let needs_arg = |make: fn(String) -> MetaCommand| {
+            if arg.is_empty() {
+                Err(format!("`:{}` needs an argument; see `:help`", name))
+            } else {
+                Ok(make(arg.to_string()))
+            }
+        };
+        Some(match name {
+            "vars" => Ok(MetaCommand::Vars),
+            "type" => needs_arg(MetaCommand::Type),
+            "ast" => needs_arg(MetaCommand::Ast),
+            "time" => needs_arg(MetaCommand::Time),
+            "load" => needs_arg(|path| MetaCommand::Load(path.into())),
+            "reload" => needs_arg(|path| MetaCommand::Reload(path.into())),
+            "save" => needs_arg(|path| MetaCommand::Save(path.into())),
+            "help" | "?" => Ok(MetaCommand::Help),
+            other => Err(format!("unknown command `:{}`; see `:help`", other)),
+        })
+    }
+}
+
+impl Interpreter {
+    /// Run a meta-command, returning the text to show the user. Values are
+    /// shown with `output`, the same renderer the REPL uses for results.
+    pub fn run_meta_command(&mut self, command: &MetaCommand, output: &ValueRenderer) -> crate::Result<String> {
+        match command {
+            MetaCommand::Vars => {
+                let variables = self.variables()?;
+                if variables.is_empty() {
+                    return Ok("(no variables)".to_string());
+                }
+                let lines: Vec<String> = variables
+                    .iter()
+                    .map(|(name, value)| format!("{}: {} = {}", name, type_name(value), output.render(value)))
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            MetaCommand::Type(expr) => {
+                let value = self.eval_interactive(expr)?;
+                Ok(type_name(&value).to_string())
+            }
+            MetaCommand::Ast(code) => {
+                let wrapped = format!("skill __main__() {{\n{}\n}}", code);
+                let ast = patchwork_parser::parse(&wrapped)
+                    .map_err(|e| Error::Parse(format_parse_error(&e, &wrapped)))?;
+                let body = ast.items.iter().find_map(|item| match item {
+                    patchwork_parser::Item::Skill(skill) => Some(&skill.body),
+                    _ => None,
+                });
+                Ok(body.map(dump_block).unwrap_or_default().trim_end().to_string())
+            }
+            MetaCommand::Time(expr) => {
+                let start = Instant::now();
+                let value = self.eval_interactive(expr)?;
+                let elapsed = start.elapsed();
+                Ok(format!("{}\n({:.1} ms)", output.render(&value), elapsed.as_secs_f64() * 1000.0))
+            }
+            MetaCommand::Load(path) => {
+                let path = self.runtime().working_dir().join(path);
+                let text = fs::read_to_string(&path)
+                    .map_err(|e| Error::Runtime(format!("Failed to read {}: {}", path.display(), e)))?;
+                if path.extension().is_some_and(|ext| ext == "json") {
+                    self.restore(&text)?;
+                    Ok(format!("restored session from {}", path.display()))
+                } else {
+                    Ok(output.render(&self.eval_interactive(&text)?))
+                }
+            }
+            MetaCommand::Reload(path) => {
+                let info = self.reload_module(path)?;
+                let names: Vec<&str> = info.entries.iter().map(|entry| entry.name.as_str()).collect();
+                if names.is_empty() {
+                    Ok(format!("reloaded {}", path.display()))
+                } else {
+                    Ok(format!("reloaded {}: {}", path.display(), names.join(", ")))
+                }
+            }
+            MetaCommand::Save(path) => {
+                let path = self.runtime().working_dir().join(path);
+                let count = self.variables()?.len();
+                fs::write(&path, self.snapshot()?)
+                    .map_err(|e| Error::Runtime(format!("Failed to write {}: {}", path.display(), e)))?;
+                Ok(format!("saved {} variables to {}", count, path.display()))
+            }
+            MetaCommand::Help => Ok(HELP.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Value;
+
+    fn run(interp: &mut Interpreter, line: &str) -> crate::Result<String> {
+        let command = MetaCommand::parse(line).unwrap().map_err(Error::Runtime)?;
+        interp.run_meta_command(&command, &ValueRenderer::default())
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(MetaCommand::parse("var x = 1"), None);
+        assert_eq!(MetaCommand::parse(" :vars "), Some(Ok(MetaCommand::Vars)));
+        assert_eq!(MetaCommand::parse(":type  x + 1"), Some(Ok(MetaCommand::Type("x + 1".to_string()))));
+        assert_eq!(MetaCommand::parse(":save s.json"), Some(Ok(MetaCommand::Save("s.json".into()))));
+        assert!(MetaCommand::parse(":type").unwrap().is_err());
+        assert!(MetaCommand::parse(":frobnicate").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_session_commands() {
+        let dir = std::env::temp_dir().join(format!("patchwork-repl-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut interp = Interpreter::with_working_dir(dir.clone());
+
+        assert_eq!(run(&mut interp, ":vars").unwrap(), "(no variables)");
+        interp.eval_interactive("var name = \"pw\"\nvar n = 2").unwrap();
+        assert_eq!(run(&mut interp, ":vars").unwrap(), "n: number = 2\nname: string = \"pw\"");
+        assert_eq!(run(&mut interp, ":type n + 1").unwrap(), "number");
+        assert!(run(&mut interp, ":time n * 21").unwrap().starts_with("42\n("));
+        assert!(run(&mut interp, ":ast n + 1").unwrap().contains("Block:"));
+        assert!(run(&mut interp, ":ast n +").is_err());
+
+        fs::write(dir.join("more.pw"), "var extra = [n, n]").unwrap();
+        run(&mut interp, ":load more.pw").unwrap();
+        assert!(run(&mut interp, ":save session.json").unwrap().starts_with("saved 3 variables"));
+
+        fs::write(dir.join("skills.pw"), "type Label = string\nskill triage() {}").unwrap();
+        assert_eq!(run(&mut interp, ":reload skills.pw").unwrap(), "reloaded skills.pw: triage");
+        fs::write(dir.join("skills.pw"), "type Label = number\nskill triage() {}\nfun summarize(text) {}").unwrap();
+        assert_eq!(run(&mut interp, ":reload skills.pw").unwrap(), "reloaded skills.pw: triage, summarize");
+        assert_eq!(interp.eval_interactive("validate(3, Label)").unwrap(), Value::Number(3.0));
+        // A broken edit leaves the last good version in place
+        fs::write(dir.join("skills.pw"), "skill triage( {").unwrap();
+        assert!(run(&mut interp, ":reload skills.pw").is_err());
+        assert_eq!(interp.program_info().unwrap().entries.len(), 2);
+        assert_eq!(interp.variables().unwrap().len(), 3);
+
+        let mut fresh = Interpreter::with_working_dir(dir.clone());
+        run(&mut fresh, ":load session.json").unwrap();
+        assert_eq!(
+            fresh.eval_interactive("extra").unwrap(),
+            Value::Array(vec![Value::Number(2.0), Value::Number(2.0)])
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,11 +1,60 @@
 //! Runtime environment for the Patchwork interpreter.
 
-use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::mpsc::Sender;
+use std::borrow::Cow;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use indexmap::IndexMap;
+use patchwork_parser::ast::{Program, Statement};
+use patchwork_parser::resolve::{Resolution, SkillSettings, SymbolKind, SymbolTable, BUILTINS};
+
+use crate::agent::{Priority, PromptPart, Usage};
+use crate::config::{
+    BackgroundPolicy, CapabilityPolicy, Config, FileAccess, Limits, ModelChain, Permission, Telemetry, VariantPolicy,
+};
+use crate::coverage::{CoverageRecorder, CoverageReport};
+use crate::error::Error;
+use crate::front_matter::FrontMatter;
+use crate::host::HostFunction;
+use crate::journal::{EffectJournal, EffectRecord};
+use crate::outcome::{Outcome, RunResult};
+use crate::process::Processes;
+use crate::redact::{RedactionEvent, Redactor};
+use crate::schema::{Schema, Schemas};
+use crate::session::Session;
+use crate::slots::SkillSlots;
+use crate::spill::{SpillFile, SpillStore};
+use crate::telemetry::{sink_for, NoopSink, TelemetryEvent, TelemetrySink};
+use crate::timer::CancellationToken;
 use crate::value::Value;
 
+/// Error message when the host cancels the program.
+pub(crate) const CANCELLED_MESSAGE: &str = "Execution was cancelled";
+
+/// Error message when the program's deadline passes.
+pub(crate) const DEADLINE_MESSAGE: &str = "Deadline exceeded";
+
+/// Rough token count of `text`, at four characters per token.
+fn estimate_tokens(text: &str) -> u64 {
+    text.chars().count().div_ceil(4) as u64
+}
+
+/// Instructions for the think call that summarizes older conversation,
+/// unless the config sets `summary_prompt`.
+const DEFAULT_SUMMARY_PROMPT: &str = "Summarize the conversation below so it can stand in for it. \
+Keep every decision, result, and open question; drop the wording.";
+
+/// Rough token count of a transcript entry's prompt and answer.
+fn entry_tokens(entry: &TranscriptEntry) -> u64 {
+    estimate_tokens(&entry.prompt) + estimate_tokens(&entry.response.to_string_value())
+}
+
 /// A sink for print output, allowing redirection away from stdout.
 pub type PrintSink = Sender<String>;
 
@@ -46,44 +95,399 @@ pub struct ThoughtChunk {
 /// A sink for thought chunks, allowing the ACP proxy to stream agent reasoning.
 pub type ThoughtReporter = Sender<ThoughtChunk>;
 
+/// A progress report from `progress(fraction, message)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgressUpdate {
+    /// How much of the work is done, from 0.0 to 1.0.
+    pub fraction: f64,
+    /// What the program is doing now.
+    pub message: String,
+}
+
+impl ProgressUpdate {
+    /// Render as a text progress bar with `width` cells, e.g.
+    /// `[#####-----]  50% Summarizing`.
+    pub fn render_bar(&self, width: usize) -> String {
+        let filled = ((self.fraction * width as f64).round() as usize).min(width);
+        let percent = (self.fraction * 100.0).round() as u32;
+        let bar = format!("[{}{}] {:>3}%", "#".repeat(filled), "-".repeat(width - filled), percent);
+        if self.message.is_empty() {
+            bar
+        } else {
+            format!("{} {}", bar, self.message)
+        }
+    }
+}
+
+/// A sink for progress reports, which hosts show as a progress bar.
+pub type ProgressReporter = Sender<ProgressUpdate>;
+
+/// Something the user should know about that doesn't stop the program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// What raised it: `"warn"` for the program's own `warn()` calls, or a
+    /// short name for the runtime's, like `"prompt-truncated"`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "warning[{}]: {}", self.code, self.message)
+    }
+}
+
+/// A sink for warnings. Hosts show them alongside output.
+pub type WarningReporter = Sender<Warning>;
+
+/// One think or ask block from the running program, with its answer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscriptEntry {
+    /// `"think"` or `"ask"`, or `"summary"` for older entries folded
+    /// together by `fold_context`.
+    pub kind: &'static str,
+    /// The interpolated prompt that was sent.
+    pub prompt: String,
+    /// The value the block evaluated to.
+    pub response: Value,
+}
+
+impl TranscriptEntry {
+    /// The entry as a Patchwork object: `{ kind, prompt, response }`.
+    pub fn to_value(&self) -> Value {
+        let mut fields = IndexMap::new();
+        fields.insert("kind".to_string(), Value::String(self.kind.to_string()));
+        fields.insert("prompt".to_string(), Value::String(self.prompt.clone()));
+        fields.insert("response".to_string(), self.response.clone());
+        Value::Object(fields)
+    }
+}
+
+/// A branch of the conversation made by `fork_context()`.
+#[derive(Debug, Clone, PartialEq)]
+struct ForkedContext {
+    /// Entries shared with the context it was forked from.
+    base: usize,
+    transcript: Vec<TranscriptEntry>,
+}
+
+/// What the backend reported about the most recent LLM call.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CallMeta {
+    /// The value the think/ask block evaluated to.
+    pub value: Value,
+    pub model: Option<String>,
+    pub usage: Usage,
+    /// Wall-clock time from sending the request to the final answer.
+    pub latency: Duration,
+    pub stop_reason: Option<String>,
+    /// The prompt variant the call used, if its block declared any.
+    pub variant: Option<String>,
+}
+
+impl CallMeta {
+    /// The metadata as a Patchwork object:
+    /// `{ value, model, tokens_in, tokens_out, latency_ms, stop_reason, variant }`.
+    pub fn to_value(&self) -> Value {
+        let text = |s: &Option<String>| s.clone().map(Value::String).unwrap_or(Value::Null);
+        let mut fields = IndexMap::new();
+        fields.insert("value".to_string(), self.value.clone());
+        fields.insert("model".to_string(), text(&self.model));
+        fields.insert("tokens_in".to_string(), Value::Number(self.usage.input_tokens as f64));
+        fields.insert("tokens_out".to_string(), Value::Number(self.usage.output_tokens as f64));
+        fields.insert("latency_ms".to_string(), Value::Number(self.latency.as_millis() as f64));
+        fields.insert("stop_reason".to_string(), text(&self.stop_reason));
+        fields.insert("variant".to_string(), text(&self.variant));
+        Value::Object(fields)
+    }
+}
+
+/// An action waiting for the user's approval: a shell command under the
+/// `ask-first` policy, or an `approve { ... }` gate.
+#[derive(Debug)]
+pub struct ApprovalRequest {
+    /// What will happen: the exact command line that will run, or the
+    /// gate's description of the pending action.
+    pub summary: String,
+    /// The program a shell command runs; "always allow" remembers this
+    /// name. `None` for gates, which ask every time.
+    pub program: Option<String>,
+    /// Channel to send the user's decision back on.
+    pub response_tx: mpsc::Sender<ApprovalDecision>,
+}
+
+/// The user's answer to an `ApprovalRequest`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApprovalDecision {
+    /// Go ahead with this action.
+    AllowOnce,
+    /// Run this command, and any later command running the same program.
+    /// A gate treats it like `AllowOnce`.
+    AlwaysAllow,
+    /// Don't go ahead.
+    Deny,
+}
+
+/// A sink for approval requests, allowing the host to ask the user before
+/// running shell commands under the `ask-first` policy and at `approve`
+/// gates.
+pub type ApprovalHandler = Sender<ApprovalRequest>;
+
+/// A single lexical scope.
+///
+/// Variables are kept in declaration order, so the slot indices computed by
+/// the resolver address them directly.
+#[derive(Debug, Default)]
+struct Scope {
+    names: Vec<String>,
+    values: Vec<Binding>,
+    /// Names bound by a module-level `const`, which cannot be reassigned.
+    constants: Vec<String>,
+}
+
+/// A variable's value: in memory, or spilled to disk because it was large.
+#[derive(Debug)]
+enum Binding {
+    Value(Value),
+    Spilled(SpillFile),
+}
+
+impl Binding {
+    fn read(&self) -> Result<Cow<'_, Value>, String> {
+        match self {
+            Binding::Value(value) => Ok(Cow::Borrowed(value)),
+            Binding::Spilled(file) => file.load().map(Cow::Owned),
+        }
+    }
+}
+
+impl Scope {
+    fn position(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+}
+
 /// The runtime environment for executing Patchwork code.
 ///
 /// Holds variable bindings and execution context like the working directory.
 #[derive(Debug)]
 pub struct Runtime {
     /// Variable bindings, organized as a stack of scopes.
-    /// Inner vec is the most recent scope, outer vec contains parent scopes.
-    scopes: Vec<HashMap<String, Value>>,
+    /// The last scope is the most recent, earlier ones are parent scopes.
+    scopes: Vec<Scope>,
+    /// Resolved names for the program being executed, used to look up
+    /// variables by slot instead of by name.
+    symbols: Option<SymbolTable>,
+    /// Where each module-level variable was defined, as (scope, slot),
+    /// indexed by symbol. Their slots depend on initialization order, so
+    /// they are recorded at definition rather than by the resolver.
+    global_slots: Vec<Option<(usize, usize)>>,
     /// Current working directory for file operations and shell commands.
     working_dir: PathBuf,
+    /// The run's session directory, if the host started one.
+    session: Option<Session>,
     /// Optional sink for print output. If None, prints go to stdout.
     print_sink: Option<PrintSink>,
     /// Optional sink for plan updates. If None, no plan reporting.
     plan_reporter: Option<PlanReporter>,
     /// Optional sink for thought chunks. If None, no thought streaming.
     thought_reporter: Option<ThoughtReporter>,
+    /// Optional sink for `progress()` reports. If None, they are dropped.
+    progress_reporter: Option<ProgressReporter>,
+    /// Optional sink for warnings. If None, they go to stderr.
+    warning_reporter: Option<WarningReporter>,
+    /// Warnings raised so far.
+    warnings: Vec<Warning>,
+    /// Optional sink for shell approval requests. If None, commands that
+    /// need approval are refused.
+    approval_handler: Option<ApprovalHandler>,
+    /// Programs the user chose to always allow during this session.
+    always_allowed: HashSet<String>,
+    /// What the running program is allowed to do.
+    capabilities: CapabilityPolicy,
+    /// Resource limits for the running program.
+    limits: Limits,
+    /// Models think blocks try, in order.
+    models: ModelChain,
+    /// How think blocks choose among their prompt variants.
+    variant_policy: VariantPolicy,
+    /// Think/ask blocks evaluated so far, checked against `limits`.
+    think_calls: u64,
+    /// Requests sent to the LLM so far, checked against the budget.
+    llm_calls: u64,
+    /// Tokens and cost reported by the LLM so far, checked against the budget.
+    usage: Usage,
+    /// Completed think/ask blocks, oldest first.
+    transcript: Vec<TranscriptEntry>,
+    /// Replaces `DEFAULT_SUMMARY_PROMPT` when summarizing the transcript.
+    summary_prompt: Option<String>,
+    /// Facts from `pin()`, repeated in every summary so they aren't lost.
+    pinned: Vec<String>,
+    /// Removes secrets from prompts before they are sent.
+    redactor: Redactor,
+    /// Every time `redactor` removed something, oldest first.
+    redactions: Vec<RedactionEvent>,
+    /// How many `with_context` calls are running; forked contexts aren't
+    /// summarized, so `merge_context` can find what they added.
+    context_depth: usize,
+    /// Metadata for the most recent LLM call, if any.
+    last_call: Option<CallMeta>,
+    /// Forked conversation contexts, indexed by the handle `fork_context` returned.
+    contexts: Vec<ForkedContext>,
+    /// Native functions registered by the host, by name.
+    host_functions: BTreeMap<String, HostFunction>,
+    /// Types the program declared, for `validate`.
+    schemas: Schemas,
+    /// Set by the host to stop the program; interrupts sleeps.
+    cancellation: CancellationToken,
+    /// When the program, or the `with_timeout` block being evaluated, must
+    /// finish. Bounds sleeps, think blocks, and shell commands.
+    deadline: Option<Instant>,
+    /// Address range of the running program's source text, used to turn
+    /// AST slices into offsets for idempotency keys.
+    source: Option<(usize, usize)>,
+    /// Zero-based iteration of each loop being executed, outermost first.
+    loop_iterations: Vec<u64>,
+    /// Effects performed by the running program.
+    journal: EffectJournal,
+    /// Effects performed by an earlier run, which are not performed again.
+    replay: EffectJournal,
+    /// Steps of the most recent `plan { ... }` declaration.
+    plan: Vec<String>,
+    /// How many of those steps `step_done()` has checked off.
+    plan_done: usize,
+    /// Report implicit coercions (truthiness of non-booleans, string
+    /// concatenation with other types, reads of missing fields) as errors.
+    strict: bool,
+    /// Let shell commands that exit with an error return their output
+    /// instead of throwing a `ShellError`.
+    lenient_shell: bool,
+    /// Priority of this program's think blocks when many programs share a
+    /// `ThinkQueue`. `ask` is always interactive.
+    think_priority: Priority,
+    /// Exit status of the most recent shell command, read as `$?`. `None`
+    /// before the first command, or if it was killed by a signal.
+    last_status: Option<i32>,
+    /// Files holding variable values too large to keep in memory.
+    spill: SpillStore,
+    /// Commands started with `$(cmd &)` that haven't been waited for.
+    processes: Processes,
+    /// What happens to those still running when a program ends.
+    background_policy: BackgroundPolicy,
+    /// Statement counts, once coverage is enabled.
+    coverage: Option<CoverageRecorder>,
+    /// Where LLM calls, shell commands, and finished runs are reported.
+    telemetry: Arc<dyn TelemetrySink>,
+    /// File each finished run's `RunResult` is written to.
+    result_json: Option<PathBuf>,
 }
 
 impl Runtime {
     /// Create a new runtime with the given working directory.
     pub fn new(working_dir: PathBuf) -> Self {
         Self {
-            scopes: vec![HashMap::new()],
+            scopes: vec![Scope::default()],
+            symbols: None,
+            global_slots: Vec::new(),
             working_dir,
+            session: None,
             print_sink: None,
             plan_reporter: None,
             thought_reporter: None,
+            progress_reporter: None,
+            warning_reporter: None,
+            warnings: Vec::new(),
+            approval_handler: None,
+            always_allowed: HashSet::new(),
+            capabilities: CapabilityPolicy::default(),
+            limits: Limits::default(),
+            models: ModelChain::default(),
+            variant_policy: VariantPolicy::default(),
+            think_calls: 0,
+            llm_calls: 0,
+            usage: Usage::default(),
+            transcript: Vec::new(),
+            summary_prompt: None,
+            pinned: Vec::new(),
+            redactor: Redactor::default(),
+            redactions: Vec::new(),
+            context_depth: 0,
+            last_call: None,
+            contexts: Vec::new(),
+            host_functions: BTreeMap::new(),
+            schemas: Schemas::default(),
+            cancellation: CancellationToken::new(),
+            deadline: None,
+            source: None,
+            loop_iterations: Vec::new(),
+            journal: EffectJournal::new(),
+            replay: EffectJournal::new(),
+            plan: Vec::new(),
+            plan_done: 0,
+            strict: false,
+            lenient_shell: false,
+            think_priority: Priority::default(),
+            last_status: None,
+            spill: SpillStore::default(),
+            processes: Processes::default(),
+            background_policy: BackgroundPolicy::default(),
+            coverage: None,
+            telemetry: Arc::new(NoopSink),
+            result_json: None,
         }
     }
 
     /// Create a new runtime with a print sink for output redirection.
     pub fn with_print_sink(working_dir: PathBuf, print_sink: PrintSink) -> Self {
         Self {
-            scopes: vec![HashMap::new()],
+            scopes: vec![Scope::default()],
+            symbols: None,
+            global_slots: Vec::new(),
             working_dir,
+            session: None,
             print_sink: Some(print_sink),
             plan_reporter: None,
             thought_reporter: None,
+            progress_reporter: None,
+            warning_reporter: None,
+            warnings: Vec::new(),
+            approval_handler: None,
+            always_allowed: HashSet::new(),
+            capabilities: CapabilityPolicy::default(),
+            limits: Limits::default(),
+            models: ModelChain::default(),
+            variant_policy: VariantPolicy::default(),
+            think_calls: 0,
+            llm_calls: 0,
+            usage: Usage::default(),
+            transcript: Vec::new(),
+            summary_prompt: None,
+            pinned: Vec::new(),
+            redactor: Redactor::default(),
+            redactions: Vec::new(),
+            context_depth: 0,
+            last_call: None,
+            contexts: Vec::new(),
+            host_functions: BTreeMap::new(),
+            schemas: Schemas::default(),
+            cancellation: CancellationToken::new(),
+            deadline: None,
+            source: None,
+            loop_iterations: Vec::new(),
+            journal: EffectJournal::new(),
+            replay: EffectJournal::new(),
+            plan: Vec::new(),
+            plan_done: 0,
+            strict: false,
+            lenient_shell: false,
+            think_priority: Priority::default(),
+            last_status: None,
+            spill: SpillStore::default(),
+            processes: Processes::default(),
+            background_policy: BackgroundPolicy::default(),
+            coverage: None,
+            telemetry: Arc::new(NoopSink),
+            result_json: None,
         }
     }
 
@@ -102,6 +506,16 @@ impl Runtime {
         self.thought_reporter = Some(reporter);
     }
 
+    /// Set the progress reporter for `progress()` updates.
+    pub fn set_progress_reporter(&mut self, reporter: ProgressReporter) {
+        self.progress_reporter = Some(reporter);
+    }
+
+    /// Set the handler asked to approve shell commands.
+    pub fn set_approval_handler(&mut self, handler: ApprovalHandler) {
+        self.approval_handler = Some(handler);
+    }
+
     /// Send a print message to the sink, or stdout if no sink is configured.
     ///
     /// Returns Ok(()) on success, or Err if the channel is disconnected.
@@ -124,6 +538,44 @@ impl Runtime {
         }
     }
 
+    /// Declare the program's plan, replacing any earlier one, and report it
+    /// with the first step in progress.
+    pub fn start_plan(&mut self, steps: Vec<String>) {
+        self.plan = steps;
+        self.plan_done = 0;
+        self.report_plan_steps();
+    }
+
+    /// Check off the current plan step and report the next one as in progress.
+    pub fn complete_plan_step(&mut self) -> Result<(), String> {
+        if self.plan.is_empty() {
+            return Err("step_done() called without a plan".to_string());
+        }
+        if self.plan_done == self.plan.len() {
+            return Err(format!("step_done() called after all {} plan steps were done", self.plan.len()));
+        }
+        self.plan_done += 1;
+        self.report_plan_steps();
+        Ok(())
+    }
+
+    fn report_plan_steps(&self) {
+        let entries = self
+            .plan
+            .iter()
+            .enumerate()
+            .map(|(i, content)| PlanEntry {
+                content: content.clone(),
+                status: match i.cmp(&self.plan_done) {
+                    std::cmp::Ordering::Less => PlanEntryStatus::Completed,
+                    std::cmp::Ordering::Equal => PlanEntryStatus::InProgress,
+                    std::cmp::Ordering::Greater => PlanEntryStatus::Pending,
+                },
+            })
+            .collect();
+        self.report_plan(PlanUpdate { entries });
+    }
+
     /// Send a thought chunk to the reporter, if configured.
     ///
     /// Silently does nothing if no reporter is configured.
@@ -134,19 +586,944 @@ impl Runtime {
         }
     }
 
+    /// Send a progress update to the reporter, if configured.
+    ///
+    /// Silently does nothing if no reporter is configured.
+    pub fn report_progress(&self, update: ProgressUpdate) {
+        if let Some(ref reporter) = self.progress_reporter {
+            // Ignore errors - if the channel is disconnected, we just don't report
+            let _ = reporter.send(update);
+        }
+    }
+
+    /// Set the sink for warnings.
+    pub fn set_warning_reporter(&mut self, reporter: WarningReporter) {
+        self.warning_reporter = Some(reporter);
+    }
+
+    /// Raise a warning without stopping the program.
+    ///
+    /// It is sent to the warning reporter, or written to stderr if none is
+    /// configured, and kept for `warnings()`.
+    pub fn warn(&mut self, code: &'static str, message: impl Into<String>) {
+        let warning = Warning { code, message: message.into() };
+        match self.warning_reporter {
+            // Ignore errors - if the channel is disconnected, we just don't report
+            Some(ref reporter) => {
+                let _ = reporter.send(warning.clone());
+            }
+            None => eprintln!("{}", warning),
+        }
+        self.warnings.push(warning);
+    }
+
+    /// Warnings raised so far, oldest first.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
     /// Get the current working directory.
     pub fn working_dir(&self) -> &PathBuf {
         &self.working_dir
     }
 
+    /// Give the program a session directory, visible to it as `session`.
+    pub fn set_session(&mut self, session: Session) {
+        self.session = Some(session);
+    }
+
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    /// Remove the session, for the host to finish once the run is over.
+    pub fn take_session(&mut self) -> Option<Session> {
+        self.session.take()
+    }
+
+    /// Apply the capability policy and limits from a loaded config.
+    pub fn apply_config(&mut self, config: &Config) {
+        self.capabilities = config.capabilities.clone();
+        self.limits = config.limits;
+        self.models = config.models.clone();
+        self.variant_policy = config.prompt_variant.clone();
+        self.summary_prompt = config.summary_prompt.clone();
+        match Redactor::new(&config.redact) {
+            Ok(redactor) => self.redactor = redactor,
+            // Config files are checked when read; this is a host's own mistake
+            Err(e) => self.warn("redaction", e),
+        }
+        // `off` leaves a sink the host set itself
+        if config.telemetry != Telemetry::Off {
+            match sink_for(&config.telemetry) {
+                Ok(sink) => self.telemetry = sink,
+                Err(e) => self.warn("telemetry", e),
+            }
+        }
+        self.strict = config.strict;
+        self.lenient_shell = config.lenient_shell;
+        self.background_policy = config.background_on_exit;
+        self.result_json = config.result_json.clone();
+    }
+
+    /// A fresh runtime for running untrusted code on this runtime's behalf.
+    ///
+    /// It starts with no variables, host functions, session, or journal.
+    /// It shares the working directory, print sink, approval handler,
+    /// cancellation, deadline, and redaction rules. It may do only what both this runtime's
+    /// policy and `capabilities` allow, within `limits` and what is left of
+    /// this runtime's budget. Pass it to `absorb_usage` afterwards so its
+    /// spending counts here too.
+    pub fn isolated(&self, capabilities: &CapabilityPolicy, limits: &Limits) -> Runtime {
+        let mut child = Runtime::new(self.working_dir.clone());
+        child.print_sink = self.print_sink.clone();
+        child.warning_reporter = self.warning_reporter.clone();
+        child.approval_handler = self.approval_handler.clone();
+        child.cancellation = self.cancellation.clone();
+        child.deadline = self.deadline;
+        child.capabilities = self.capabilities.restrict(capabilities, &self.working_dir);
+        let remaining = Limits {
+            max_think_calls: self.limits.max_think_calls.map(|max| max.saturating_sub(self.think_calls)),
+            max_llm_calls: self.limits.max_llm_calls.map(|max| max.saturating_sub(self.llm_calls)),
+            max_total_tokens: self.limits.max_total_tokens.map(|max| max.saturating_sub(self.usage.total_tokens())),
+            max_cost_usd: self.limits.max_cost_usd.map(|max| (max - self.usage.cost_usd.unwrap_or(0.0)).max(0.0)),
+            ..self.limits
+        };
+        child.limits = remaining.tighter(limits);
+        child.models = self.models.clone();
+        child.variant_policy = self.variant_policy.clone();
+        child.redactor = self.redactor.clone();
+        child.strict = self.strict;
+        child.lenient_shell = self.lenient_shell;
+        child.think_priority = self.think_priority;
+        child.telemetry = self.telemetry.clone();
+        child
+    }
+
+    /// Count the think blocks, LLM calls, and usage of a runtime made by
+    /// `isolated` against this runtime's limits.
+    pub fn absorb_usage(&mut self, child: &Runtime) -> Result<(), Error> {
+        self.think_calls += child.think_calls;
+        self.llm_calls += child.llm_calls;
+        self.record_usage(child.usage)
+    }
+
+    /// Turn strict mode on or off.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Is strict mode on?
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Turn lenient shell mode on or off.
+    pub fn set_lenient_shell(&mut self, lenient: bool) {
+        self.lenient_shell = lenient;
+    }
+
+    /// Do shell commands that exit with an error return instead of throwing?
+    pub fn is_lenient_shell(&self) -> bool {
+        self.lenient_shell
+    }
+
+    /// Set the priority of this program's think blocks.
+    pub fn set_think_priority(&mut self, priority: Priority) {
+        self.think_priority = priority;
+    }
+
+    /// The priority of this program's think blocks.
+    pub fn think_priority(&self) -> Priority {
+        self.think_priority
+    }
+
+    /// Record the exit status of a shell command for `$?`.
+    pub fn set_last_status(&mut self, status: Option<i32>) {
+        self.last_status = status;
+    }
+
+    /// Exit status of the most recent shell command.
+    pub fn last_status(&self) -> Option<i32> {
+        self.last_status
+    }
+
+    /// The resource limits in effect.
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// The models think blocks try, in order.
+    pub fn model_chain(&self) -> &ModelChain {
+        &self.models
+    }
+
+    pub fn set_model_chain(&mut self, models: ModelChain) {
+        self.models = models;
+    }
+
+    /// Run within a program's front matter: fail if the capability policy
+    /// denies something it requires, add its limits to the config's, and
+    /// try its models if the config names none.
+    pub fn apply_front_matter(&mut self, front: &FrontMatter) -> Result<(), String> {
+        front.check(&self.capabilities)?;
+        self.limits = self.limits.tighter(&front.limits);
+        if self.models.models.is_empty() {
+            self.models.models = front.models.clone();
+        }
+        Ok(())
+    }
+
+    pub fn set_variant_policy(&mut self, policy: VariantPolicy) {
+        self.variant_policy = policy;
+    }
+
+    /// Pick which of a think block's variants this call uses, by index.
+    /// `names` must not be empty. A policy naming a variant the block
+    /// doesn't have falls back to the first, with a warning.
+    pub fn choose_variant(&mut self, names: &[&str]) -> usize {
+        let wanted = match &self.variant_policy {
+            VariantPolicy::Pin(name) => Some(name.clone()),
+            VariantPolicy::Env(var) => std::env::var(var).ok().map(|name| name.trim().to_string()),
+            VariantPolicy::First | VariantPolicy::Split(_) => None,
+        };
+        if let Some(wanted) = wanted {
+            if let Some(index) = names.iter().position(|n| *n == wanted) {
+                return index;
+            }
+            self.warn(
+                "unknown-variant",
+                format!("no prompt variant named `{}`; using `{}`", wanted, names[0]),
+            );
+            return 0;
+        }
+        match &self.variant_policy {
+            VariantPolicy::First | VariantPolicy::Pin(_) | VariantPolicy::Env(_) => 0,
+            VariantPolicy::Split(weights) => {
+                let weights: Vec<u64> = if weights.is_empty() {
+                    vec![1; names.len()]
+                } else {
+                    names
+                        .iter()
+                        .map(|name| weights.iter().find(|(n, _)| n == name).map_or(0, |(_, w)| *w as u64))
+                        .collect()
+                };
+                let total: u64 = weights.iter().sum();
+                if total == 0 {
+                    return 0;
+                }
+                // RandomState is seeded randomly per instance; a fresh one
+                // per call is enough for splitting traffic
+                let mut roll = RandomState::new().build_hasher().finish() % total;
+                for (index, weight) in weights.iter().enumerate() {
+                    if roll < *weight {
+                        return index;
+                    }
+                    roll -= weight;
+                }
+                0
+            }
+        }
+    }
+
+    /// Check that running `program` with `args` is allowed.
+    ///
+    /// Under the `ask-first` policy this blocks until the approval handler
+    /// answers, unless the program is allowlisted or was always-allowed
+    /// earlier in the session.
+    pub fn check_shell(&mut self, program: &str, args: &[String]) -> Result<(), String> {
+        if self.capabilities.shell_allowlist.iter().any(|p| p == program) {
+            return Ok(());
+        }
+        match self.capabilities.shell {
+            Permission::Allow => Ok(()),
+            Permission::Deny => Err("Shell commands are disabled by the capability policy".to_string()),
+            Permission::AskFirst if self.always_allowed.contains(program) => Ok(()),
+            Permission::AskFirst => self.request_approval(program, args),
+        }
+    }
+
+    /// Ask the approval handler whether a command may run.
+    fn request_approval(&mut self, program: &str, args: &[String]) -> Result<(), String> {
+        let command = display_command(program, args);
+        if self.approval_handler.is_none() {
+            return Err(format!("Shell command `{}` needs approval, but no one is available to approve it", command));
+        }
+        match self.await_approval(&command, Some(program))? {
+            ApprovalDecision::AllowOnce => Ok(()),
+            ApprovalDecision::AlwaysAllow => {
+                self.always_allowed.insert(program.to_string());
+                Ok(())
+            }
+            ApprovalDecision::Deny => Err(format!("Shell command `{}` was not approved", command)),
+        }
+    }
+
+    /// Block at an `approve { ... }` gate until the approval handler allows
+    /// the action `summary` describes.
+    pub fn request_gate_approval(&mut self, summary: &str) -> Result<(), String> {
+        if self.approval_handler.is_none() {
+            return Err(format!("`{}` needs approval, but no one is available to approve it", summary));
+        }
+        match self.await_approval(summary, None)? {
+            ApprovalDecision::AllowOnce | ApprovalDecision::AlwaysAllow => Ok(()),
+            ApprovalDecision::Deny => Err(format!("`{}` was not approved", summary)),
+        }
+    }
+
+    /// Send an approval request and wait for the answer.
+    ///
+    /// Waits no longer than `approval_timeout_secs` or the time left before
+    /// the deadline. No answer in time, like a dropped response channel,
+    /// counts as a refusal.
+    fn await_approval(&self, summary: &str, program: Option<&str>) -> Result<ApprovalDecision, String> {
+        let handler = self.approval_handler.as_ref().expect("callers check for a handler");
+        let (response_tx, response_rx) = mpsc::channel();
+        handler
+            .send(ApprovalRequest {
+                summary: summary.to_string(),
+                program: program.map(str::to_string),
+                response_tx,
+            })
+            .map_err(|e| format!("Approval channel disconnected: {}", e))?;
+
+        let timeout = [self.limits.approval_timeout_secs.map(Duration::from_secs), self.time_remaining()]
+            .into_iter()
+            .flatten()
+            .min();
+        let decision = match timeout {
+            Some(timeout) => response_rx.recv_timeout(timeout).ok(),
+            None => response_rx.recv().ok(),
+        };
+        Ok(decision.unwrap_or(ApprovalDecision::Deny))
+    }
+
+    /// Commands running in the background.
+    pub(crate) fn processes(&mut self) -> &mut Processes {
+        &mut self.processes
+    }
+
+    /// Set what happens to background commands still running when a
+    /// program ends.
+    pub fn set_background_policy(&mut self, policy: BackgroundPolicy) {
+        self.background_policy = policy;
+    }
+
+    pub fn background_policy(&self) -> BackgroundPolicy {
+        self.background_policy
+    }
+
+    /// Remember that `program` may run without asking for the rest of the session.
+    pub fn always_allow(&mut self, program: &str) {
+        self.always_allowed.insert(program.to_string());
+    }
+
+    /// Check that `eval_patchwork()` may run `code`, asking the approval
+    /// handler first under the `ask-first` policy.
+    pub fn check_eval_code(&mut self, code: &str) -> Result<(), String> {
+        match self.capabilities.eval_code {
+            Permission::Allow => Ok(()),
+            Permission::Deny => Err("Running generated code is disabled by the capability policy".to_string()),
+            Permission::AskFirst => self.request_gate_approval(&format!("Run this generated code:\n{}", code.trim())),
+        }
+    }
+
+    /// Check that writing files is allowed.
+    pub fn check_file_write(&self) -> Result<(), String> {
+        match self.capabilities.file_write {
+            Permission::Allow => Ok(()),
+            // Config loading rejects ask-first for file writes; treat it as deny
+            Permission::AskFirst | Permission::Deny => {
+                Err("Writing files is disabled by the capability policy".to_string())
+            }
+        }
+    }
+
+    /// Resolve a path named by the program against the working directory.
+    ///
+    /// Under `FileAccess::Confined`, the path must land inside the working
+    /// directory, the session directory, or one of the configured
+    /// `file_roots` once symlinks are resolved, so a link inside the work
+    /// dir can't be used to escape it.
+    pub fn resolve_path(&self, path: &str) -> Result<PathBuf, String> {
+        let joined = self.working_dir.join(path);
+        if self.capabilities.file_access == FileAccess::Unrestricted {
+            return Ok(joined);
+        }
+
+        let resolved = resolve_symlinks(&joined)
+            .ok_or_else(|| format!("Path {} climbs above the root directory", path))?;
+        let session_dir = self.session.as_ref().map(|session| session.dir().to_path_buf());
+        let roots = std::iter::once(&self.working_dir).chain(&session_dir).chain(&self.capabilities.file_roots);
+        for root in roots {
+            // A root that climbs above `/` grants nothing
+            if resolve_symlinks(&self.working_dir.join(root)).is_some_and(|root| resolved.starts_with(root)) {
+                return Ok(resolved);
+            }
+        }
+        Err(format!(
+            "Path {} is outside the working directory; add it to `file_roots` or set `file_access` to `unrestricted`",
+            path
+        ))
+    }
+
+    /// Count a think/ask block, failing if it exceeds `max_think_calls`.
+    pub fn record_think_call(&mut self) -> Result<(), String> {
+        self.think_calls += 1;
+        match self.limits.max_think_calls {
+            Some(max) if self.think_calls > max => {
+                Err(format!("Exceeded the limit of {} think blocks", max))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Count a request to the LLM, failing if the budget is already spent.
+    pub fn record_llm_call(&mut self) -> Result<(), Error> {
+        if let Some(max) = self.limits.max_llm_calls {
+            if self.llm_calls >= max {
+                return Err(Error::BudgetExceeded(format!("reached the limit of {} LLM calls", max)));
+            }
+        }
+        self.check_usage()?;
+        self.llm_calls += 1;
+        Ok(())
+    }
+
+    /// Add an LLM call's usage, failing once it crosses the token or cost budget.
+    pub fn record_usage(&mut self, usage: Usage) -> Result<(), Error> {
+        self.usage.input_tokens += usage.input_tokens;
+        self.usage.output_tokens += usage.output_tokens;
+        if let Some(cost) = usage.cost_usd {
+            self.usage.cost_usd = Some(self.usage.cost_usd.unwrap_or(0.0) + cost);
+        }
+        self.check_usage()
+    }
+
+    fn check_usage(&self) -> Result<(), Error> {
+        let tokens = self.usage.total_tokens();
+        if let Some(max) = self.limits.max_total_tokens {
+            if tokens >= max {
+                return Err(Error::BudgetExceeded(format!(
+                    "used {} tokens of the {} token limit",
+                    tokens, max
+                )));
+            }
+        }
+        let cost = self.usage.cost_usd.unwrap_or(0.0);
+        if let Some(max) = self.limits.max_cost_usd {
+            if cost >= max {
+                return Err(Error::BudgetExceeded(format!(
+                    "spent ${:.4} of the ${:.2} cost limit",
+                    cost, max
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// What is left of each budget limit, with `null` for unlimited ones.
+    pub fn budget_remaining(&self) -> Value {
+        let remaining = |max: Option<u64>, used: u64| match max {
+            Some(max) => Value::Number(max.saturating_sub(used) as f64),
+            None => Value::Null,
+        };
+        let mut budget = IndexMap::new();
+        budget.insert("llm_calls".to_string(), remaining(self.limits.max_llm_calls, self.llm_calls));
+        budget.insert(
+            "total_tokens".to_string(),
+            remaining(self.limits.max_total_tokens, self.usage.total_tokens()),
+        );
+        let cost = match self.limits.max_cost_usd {
+            Some(max) => Value::Number((max - self.usage.cost_usd.unwrap_or(0.0)).max(0.0)),
+            None => Value::Null,
+        };
+        budget.insert("cost_usd".to_string(), cost);
+        Value::Object(budget)
+    }
+
+    /// Format few-shot examples as a prompt section, one `Key: value` line
+    /// per field. Trailing examples are dropped, with a warning, if the
+    /// section would exceed `max_example_tokens` or the tokens left in the
+    /// budget.
+    pub fn format_examples(&mut self, examples: &[Vec<(String, Value)>]) -> String {
+        let remaining = self.limits.max_total_tokens.map(|max| max.saturating_sub(self.usage.total_tokens()));
+        let budget = match (self.limits.max_example_tokens, remaining) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        let mut section = String::from("\n\nExamples:\n");
+        let mut included = 0;
+        for example in examples {
+            let mut text = String::from("\n");
+            for (key, value) in example {
+                let mut label = key.replace('_', " ");
+                if let Some(first) = label.get(..1) {
+                    label.replace_range(..1, &first.to_uppercase());
+                }
+                text.push_str(&format!("{}: {}\n", label, value.to_string_value()));
+            }
+            if budget.is_some_and(|budget| estimate_tokens(&section) + estimate_tokens(&text) > budget) {
+                break;
+            }
+            section.push_str(&text);
+            included += 1;
+        }
+        if included < examples.len() {
+            self.warn(
+                "prompt-truncated",
+                format!(
+                    "left out {} of {} examples to stay within the token limit",
+                    examples.len() - included,
+                    examples.len()
+                ),
+            );
+        }
+        if included == 0 {
+            return String::new();
+        }
+        section.push('\n');
+        section
+    }
+
+    /// Tokens and cost used by this run's LLM calls so far.
+    pub fn usage(&self) -> Usage {
+        self.usage
+    }
+
+    /// Number of LLM requests made so far.
+    pub fn llm_calls(&self) -> u64 {
+        self.llm_calls
+    }
+
+    /// Record a completed think/ask block in the transcript.
+    pub fn record_transcript(&mut self, entry: TranscriptEntry) {
+        self.transcript.push(entry);
+    }
+
+    /// Completed think/ask blocks, oldest first.
+    pub fn transcript(&self) -> &[TranscriptEntry] {
+        &self.transcript
+    }
+
+    /// Apply the redaction rules to a request about to be sent to the LLM,
+    /// returning the prompt to send. Each rule that fires is recorded and
+    /// reported as a `redacted` warning.
+    pub(crate) fn redact_request(
+        &mut self,
+        prompt: &str,
+        parts: &mut [PromptPart],
+        bindings: &mut HashMap<String, Value>,
+    ) -> String {
+        if self.redactor.is_empty() {
+            return prompt.to_string();
+        }
+        let mut events = Vec::new();
+        let prompt = self.redactor.redact_prompt(prompt, &mut events);
+        self.redactor.redact_parts(parts);
+        self.redactor.redact_bindings(bindings, &mut events);
+        self.record_redactions(events);
+        prompt
+    }
+
+    /// Apply the path rules to a value interpolated into a prompt from the
+    /// binding at `path`, like `["deploy", "token"]` for `${deploy.token}`.
+    pub(crate) fn redact_interpolation(&mut self, path: &[&str], value: &mut Value) {
+        let mut events = Vec::new();
+        self.redactor.redact_interpolation(path, value, &mut events);
+        self.record_redactions(events);
+    }
+
+    /// Record redactions, reporting each as a `redacted` warning.
+    fn record_redactions(&mut self, events: Vec<RedactionEvent>) {
+        for event in events {
+            let noun = if event.count == 1 { "match" } else { "matches" };
+            let message = format!("removed {} {} of `{}` from the {}", event.count, noun, event.rule, event.target);
+            self.warn("redacted", message);
+            self.redactions.push(event);
+        }
+    }
+
+    /// What the redaction rules removed so far, oldest first.
+    pub fn redactions(&self) -> &[RedactionEvent] {
+        &self.redactions
+    }
+
+    /// Keep `fact` in the conversation however much of it is summarized.
+    pub fn pin_fact(&mut self, fact: String) {
+        if !self.pinned.contains(&fact) {
+            self.pinned.push(fact);
+        }
+    }
+
+    /// Facts pinned so far, oldest first.
+    pub fn pinned_facts(&self) -> &[String] {
+        &self.pinned
+    }
+
+    /// Estimated tokens in the transcript.
+    pub fn context_tokens(&self) -> u64 {
+        self.transcript.iter().map(entry_tokens).sum()
+    }
+
+    /// If the transcript holds more than `max_context_tokens`, how many of
+    /// its oldest entries to fold together and the prompt that asks for
+    /// their summary.
+    ///
+    /// The newest entries that fit in half the window are kept as they are,
+    /// but at least the most recent one, so a summary always has room.
+    pub(crate) fn context_to_summarize(&self) -> Option<(usize, String)> {
+        let max = self.limits.max_context_tokens?;
+        if self.context_depth > 0 || self.context_tokens() <= max {
+            return None;
+        }
+        let mut kept = 0;
+        let mut tokens = 0;
+        for entry in self.transcript.iter().rev() {
+            tokens += entry_tokens(entry);
+            if kept > 0 && tokens > max / 2 {
+                break;
+            }
+            kept += 1;
+        }
+        let count = self.transcript.len() - kept;
+        if count == 0 {
+            return None;
+        }
+
+        let mut prompt = self.summary_prompt.as_deref().unwrap_or(DEFAULT_SUMMARY_PROMPT).to_string();
+        if !self.pinned.is_empty() {
+            prompt.push_str("\n\nFacts to keep exactly:\n");
+            for fact in &self.pinned {
+                prompt.push_str(&format!("- {}\n", fact));
+            }
+        }
+        prompt.push_str("\n\nConversation:\n");
+        for entry in &self.transcript[..count] {
+            if entry.kind == "summary" {
+                prompt.push_str(&format!("summary: {}\n", entry.response.to_string_value()));
+            } else {
+                prompt.push_str(&format!("{}: {}\nanswer: {}\n", entry.kind, entry.prompt, entry.response.to_string_value()));
+            }
+        }
+        Some((count, prompt))
+    }
+
+    /// Replace the oldest `count` transcript entries with one summary entry.
+    pub(crate) fn fold_context(&mut self, count: usize, summary: Value) {
+        let count = count.min(self.transcript.len());
+        let entry = TranscriptEntry { kind: "summary", prompt: String::new(), response: summary };
+        self.transcript.splice(..count, [entry]);
+    }
+
+    /// Branch the conversation: a new context starting from the current
+    /// transcript, returning its handle.
+    pub fn fork_context(&mut self) -> usize {
+        self.contexts.push(ForkedContext {
+            base: self.transcript.len(),
+            transcript: self.transcript.clone(),
+        });
+        self.contexts.len() - 1
+    }
+
+    /// Run `f` with the forked context `id` as the current conversation.
+    ///
+    /// Think blocks inside `f` are added to the branch, not to the context
+    /// that was current before.
+    pub fn with_context<T>(&mut self, id: usize, f: impl FnOnce(&mut Self) -> T) -> Result<T, String> {
+        let Some(context) = self.contexts.get_mut(id) else {
+            return Err(format!("Unknown context: {}", id));
+        };
+        std::mem::swap(&mut self.transcript, &mut context.transcript);
+        self.context_depth += 1;
+        let result = f(self);
+        self.context_depth -= 1;
+        std::mem::swap(&mut self.transcript, &mut self.contexts[id].transcript);
+        Ok(result)
+    }
+
+    /// Add what happened in the forked context `id` since it was forked to
+    /// the current conversation.
+    pub fn merge_context(&mut self, id: usize) -> Result<(), String> {
+        let context = self.contexts.get(id).ok_or_else(|| format!("Unknown context: {}", id))?;
+        let branch = context.transcript.get(context.base..).unwrap_or_default();
+        self.transcript.extend_from_slice(branch);
+        Ok(())
+    }
+
+    /// Remember the metadata of a completed LLM call, and report it.
+    pub fn record_call_meta(&mut self, meta: CallMeta) {
+        self.telemetry.record(&TelemetryEvent::llm_call(&meta));
+        self.last_call = Some(meta);
+    }
+
+    /// Set where LLM calls, shell commands, and finished runs are reported.
+    pub fn set_telemetry_sink(&mut self, sink: Arc<dyn TelemetrySink>) {
+        self.telemetry = sink;
+    }
+
+    /// Report something the program did to the telemetry sink.
+    pub fn record_telemetry(&self, event: TelemetryEvent) {
+        self.telemetry.record(&event);
+    }
+
+    /// Report a finished run: its error, if it failed, then its summary.
+    /// The summary is also written to the config's `result_json` file.
+    pub fn record_run(&mut self, result: &crate::Result<Value>, duration: Duration) {
+        if let Err(e) = result {
+            self.telemetry.record(&TelemetryEvent::Error { outcome: Outcome::of(result), message: e.to_string() });
+        }
+        let run = RunResult::new(result, self, duration);
+        let written = self.result_json.as_ref().map(|path| {
+            run.write(path).map_err(|e| format!("Failed to write the run result to {}: {}", path.display(), e))
+        });
+        if let Some(Err(message)) = written {
+            self.warn("result-json", message);
+        }
+        self.telemetry.record(&TelemetryEvent::RunSummary(run));
+    }
+
+    /// Metadata for the most recent LLM call, if any was made.
+    pub fn last_call_meta(&self) -> Option<&CallMeta> {
+        self.last_call.as_ref()
+    }
+
+    /// Make a native function callable from Patchwork code.
+    ///
+    /// Fails if the name is taken by a builtin; registering the same name
+    /// again replaces the earlier function.
+    pub fn register_fn(&mut self, function: HostFunction) -> Result<(), String> {
+        if BUILTINS.contains(&function.name.as_str()) {
+            return Err(format!("`{}` is a builtin function and can't be replaced", function.name));
+        }
+        self.host_functions.insert(function.name.clone(), function);
+        Ok(())
+    }
+
+    /// The registered host function called `name`, if any.
+    pub fn host_function(&self, name: &str) -> Option<&HostFunction> {
+        self.host_functions.get(name)
+    }
+
+    /// Declare a type `validate` can check values against.
+    pub fn define_schema(&mut self, name: &str, schema: Schema) {
+        self.schemas.define(name, schema);
+    }
+
+    /// The types declared so far.
+    pub fn schemas(&self) -> &Schemas {
+        &self.schemas
+    }
+
+    /// Every registered host function, sorted by name.
+    pub fn host_functions(&self) -> impl Iterator<Item = &HostFunction> {
+        self.host_functions.values()
+    }
+
+    /// Share a cancellation token with the host.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.cancellation = token;
+    }
+
+    /// The token that cancels this runtime's program.
+    pub fn cancellation_token(&self) -> &CancellationToken {
+        &self.cancellation
+    }
+
+    /// Fail if the host has cancelled the program or its deadline has passed.
+    pub fn check_cancelled(&self) -> Result<(), String> {
+        if self.cancellation.is_cancelled() {
+            Err(CANCELLED_MESSAGE.to_string())
+        } else {
+            self.check_deadline()
+        }
+    }
+
+    /// Set when the program must finish, or `None` for no deadline.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// The active deadline, if any.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Time left before the deadline, or `None` if there is no deadline.
+    pub fn time_remaining(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Fail if the deadline has passed.
+    pub fn check_deadline(&self) -> Result<(), String> {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(DEADLINE_MESSAGE.to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Run `f` with a deadline `timeout` from now. An earlier deadline that
+    /// is already active still applies, and a timeout too long to have a
+    /// deadline leaves the outer one as it is.
+    pub fn with_timeout<T>(&mut self, timeout: Duration, f: impl FnOnce(&mut Self) -> T) -> T {
+        let outer = self.deadline;
+        self.deadline = match (outer, Instant::now().checked_add(timeout)) {
+            (Some(outer), Some(inner)) => Some(outer.min(inner)),
+            (outer, inner) => outer.or(inner),
+        };
+        let result = f(self);
+        self.deadline = outer;
+        result
+    }
+
+    /// Run `f` under what a skill's annotations ask for: once fewer than its
+    /// `concurrency` runs are in progress, within its `timeout`, and with
+    /// only the capabilities it names. `skill` tells the skill apart from
+    /// those of other programs; waiting for a run to finish doesn't count
+    /// against the timeout.
+    pub fn with_skill_settings<T>(
+        &mut self,
+        skill: &str,
+        settings: &SkillSettings,
+        f: impl FnOnce(&mut Self) -> crate::Result<T>,
+    ) -> crate::Result<T> {
+        let slots = SkillSlots::global();
+        let _slot = match settings.concurrency {
+            Some(limit) => Some(slots.acquire(skill, limit, || self.check_cancelled()).map_err(Error::Runtime)?),
+            None => None,
+        };
+        let capabilities = self.capabilities.clone();
+        if let Some(names) = &settings.capabilities {
+            self.capabilities = capabilities.keep_only(names);
+        }
+        let result = match settings.timeout {
+            Some(timeout) => self.with_timeout(timeout, f),
+            None => f(self),
+        };
+        self.capabilities = capabilities;
+        result
+    }
+
+    /// Block for `duration`, failing early if the program is cancelled or
+    /// the deadline comes first.
+    pub fn sleep(&self, duration: Duration) -> Result<(), String> {
+        let duration = match self.time_remaining() {
+            Some(remaining) => duration.min(remaining),
+            None => duration,
+        };
+        if self.cancellation.wait_timeout(duration) {
+            return Err(CANCELLED_MESSAGE.to_string());
+        }
+        self.check_deadline()
+    }
+
     /// Set the current working directory.
     pub fn set_working_dir(&mut self, dir: PathBuf) {
         self.working_dir = dir;
     }
 
+    /// The symbol table of the program running, if any.
+    pub fn symbols(&self) -> Option<&SymbolTable> {
+        self.symbols.as_ref()
+    }
+
+    /// Install the symbol table for the program about to run.
+    ///
+    /// Pass `None` once the program finishes; the table is keyed by the
+    /// program's identifiers and is meaningless for any other AST.
+    pub fn set_symbols(&mut self, symbols: Option<SymbolTable>) {
+        self.symbols = symbols;
+        self.global_slots.clear();
+    }
+
+    /// Install the source text of the program about to run, starting a new
+    /// effect journal and clearing any earlier plan. Pass `None` once the
+    /// program finishes.
+    pub fn set_source(&mut self, source: Option<&str>) {
+        self.source = source.map(|s| (s.as_ptr() as usize, s.len()));
+        if source.is_some() {
+            self.journal = EffectJournal::new();
+            self.loop_iterations.clear();
+            self.plan.clear();
+            self.plan_done = 0;
+        }
+    }
+
+    /// Enter a loop; its first iteration is 0.
+    pub fn enter_loop(&mut self) {
+        self.loop_iterations.push(0);
+    }
+
+    /// Record that the innermost loop is on iteration `n`.
+    pub fn set_loop_iteration(&mut self, n: u64) {
+        if let Some(current) = self.loop_iterations.last_mut() {
+            *current = n;
+        }
+    }
+
+    /// Leave the innermost loop.
+    pub fn exit_loop(&mut self) {
+        self.loop_iterations.pop();
+    }
+
+    /// Effects performed by the current (or most recent) program.
+    pub fn effect_journal(&self) -> &EffectJournal {
+        &self.journal
+    }
+
+    /// Skip the effects in `journal`, performed by an earlier run of the
+    /// same program, returning their recorded results instead.
+    pub fn set_replay_journal(&mut self, journal: EffectJournal) {
+        self.replay = journal;
+    }
+
+    /// Perform an effectful operation at most once across replays.
+    ///
+    /// `site` is a slice of the program's source (such as the command name)
+    /// that marks where the operation is written. If the replay journal
+    /// already has this effect, its recorded result is returned and
+    /// `perform` is not called. Operations outside the program's source,
+    /// such as REPL turns, are always performed and not journaled.
+    pub fn perform_effect(
+        &mut self,
+        kind: &str,
+        site: &str,
+        perform: impl FnOnce(&mut Self) -> Result<Value, Error>,
+    ) -> Result<Value, Error> {
+        let Some(key) = self.effect_key(kind, site) else {
+            return perform(self);
+        };
+        let result = match self.replay.get(&key) {
+            Some(record) => record.result.clone(),
+            None => perform(self)?,
+        };
+        self.journal.record(EffectRecord {
+            key,
+            kind: kind.to_string(),
+            result: result.clone(),
+        });
+        Ok(result)
+    }
+
+    /// The idempotency key for an effect written at `site`.
+    fn effect_key(&self, kind: &str, site: &str) -> Option<String> {
+        let (start, len) = self.source?;
+        let offset = (site.as_ptr() as usize).checked_sub(start).filter(|&offset| offset < len)?;
+        let mut key = format!("{}:{}", kind, offset);
+        for (depth, iteration) in self.loop_iterations.iter().enumerate() {
+            key.push(if depth == 0 { '@' } else { '.' });
+            key.push_str(&iteration.to_string());
+        }
+        Some(key)
+    }
+
     /// Push a new scope onto the scope stack (entering a block).
     pub fn push_scope(&mut self) {
-        self.scopes.push(HashMap::new());
+        self.scopes.push(Scope::default());
     }
 
     /// Pop the current scope from the stack (leaving a block).
@@ -156,29 +1533,174 @@ impl Runtime {
         }
     }
 
+    /// Pop the current scope, moving its variables into the scope below,
+    /// where each replaces any variable of the same name. Interactive
+    /// sessions keep a turn's variables this way without a scope per turn.
+    pub fn merge_scope(&mut self) {
+        if self.scopes.len() <= 1 {
+            return;
+        }
+        let scope = self.scopes.pop().expect("scope stack should never be empty");
+        let parent = self.scopes.last_mut().expect("scope stack should never be empty");
+        parent.constants.retain(|name| !scope.names.contains(name));
+        parent.constants.extend(scope.constants);
+        for (name, binding) in scope.names.into_iter().zip(scope.values) {
+            match parent.position(&name) {
+                Some(i) => parent.values[i] = binding,
+                None => {
+                    parent.names.push(name);
+                    parent.values.push(binding);
+                }
+            }
+        }
+    }
+
     /// Define a new variable in the current scope.
     ///
     /// Returns an error if the variable already exists in the current scope.
     pub fn define_var(&mut self, name: &str, value: Value) -> Result<(), String> {
-        let current_scope = self.scopes.last_mut()
+        let current_scope = self.scopes.last()
             .expect("scope stack should never be empty");
 
-        if current_scope.contains_key(name) {
+        if current_scope.position(name).is_some() {
             return Err(format!("Variable '{}' already defined in this scope", name));
         }
 
-        current_scope.insert(name.to_string(), value);
+        let binding = self.bind(value);
+        let current_scope = self.scopes.last_mut()
+            .expect("scope stack should never be empty");
+        current_scope.names.push(name.to_string());
+        current_scope.values.push(binding);
+        self.record_global_slot(name);
         Ok(())
     }
 
-    /// Get the value of a variable, searching from innermost to outermost scope.
-    pub fn get_var(&self, name: &str) -> Option<&Value> {
+    /// If `ident` declares a module-level variable, remember the slot it
+    /// was just defined in.
+    fn record_global_slot(&mut self, ident: &str) {
+        let Some(symbols) = &self.symbols else {
+            return;
+        };
+        let Some(id) = symbols.declared_by(ident) else {
+            return;
+        };
+        if !matches!(symbols.symbol(id).kind, SymbolKind::ModuleVar | SymbolKind::Const) {
+            return;
+        }
+        if self.global_slots.len() <= id.0 {
+            self.global_slots.resize(id.0 + 1, None);
+        }
+        let scope = self.scopes.len() - 1;
+        self.global_slots[id.0] = Some((scope, self.scopes[scope].values.len() - 1));
+    }
+
+    /// Store a value for a variable, spilling it to disk if it is a string
+    /// or array at least `limits.spill_threshold_bytes` large.
+    ///
+    /// Spilling is best effort: if the file can't be written, the value
+    /// stays in memory.
+    fn bind(&mut self, value: Value) -> Binding {
+        let Some(threshold) = self.limits.spill_threshold_bytes else {
+            return Binding::Value(value);
+        };
+        if !matches!(value, Value::String(_) | Value::Array(_)) || (value.approx_size() as u64) < threshold {
+            return Binding::Value(value);
+        }
+        match self.spill.spill(&value) {
+            Ok(file) => Binding::Spilled(file),
+            Err(_) => Binding::Value(value),
+        }
+    }
+
+    /// Approximate bytes held in memory by variables in every scope.
+    pub fn memory_usage(&self) -> usize {
+        self.bindings()
+            .map(|binding| match binding {
+                Binding::Value(value) => value.approx_size(),
+                Binding::Spilled(_) => 0,
+            })
+            .sum()
+    }
+
+    /// Approximate in-memory size of the variable values spilled to disk.
+    pub fn spilled_bytes(&self) -> usize {
+        self.bindings()
+            .map(|binding| match binding {
+                Binding::Value(_) => 0,
+                Binding::Spilled(file) => file.size(),
+            })
+            .sum()
+    }
+
+    /// Count which statements run from now on.
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert_with(CoverageRecorder::default);
+    }
+
+    /// Statement coverage of the programs run since `enable_coverage`.
+    pub fn coverage(&self) -> Option<&CoverageReport> {
+        self.coverage.as_ref().map(CoverageRecorder::report)
+    }
+
+    /// Register the statements of `program`, about to run, for coverage.
+    /// `display` is its text as written, before any wrapping for parsing.
+    pub(crate) fn begin_coverage(&mut self, name: &str, source: &str, display: &str, program: &Program) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.begin(name, source, display, program);
+        }
+    }
+
+    pub(crate) fn end_coverage(&mut self) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.end();
+        }
+    }
+
+    /// Count one execution of `statement` if coverage is enabled.
+    pub fn cover(&mut self, statement: &Statement) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.hit(statement);
+        }
+    }
+
+    fn bindings(&self) -> impl Iterator<Item = &Binding> {
+        self.scopes.iter().flat_map(|scope| &scope.values)
+    }
+
+    /// Every variable in scope and its value, sorted by name. Where a name
+    /// is declared in more than one scope, the innermost declaration wins.
+    ///
+    /// Fails only if a variable was spilled to disk and can't be read back.
+    pub fn variables(&self) -> Result<Vec<(String, Value)>, String> {
+        let mut variables: BTreeMap<&str, Value> = BTreeMap::new();
         for scope in self.scopes.iter().rev() {
-            if let Some(value) = scope.get(name) {
-                return Some(value);
+            for (name, binding) in scope.names.iter().zip(&scope.values) {
+                if !variables.contains_key(name.as_str()) {
+                    variables.insert(name, binding.read()?.into_owned());
+                }
             }
         }
-        None
+        Ok(variables.into_iter().map(|(name, value)| (name.to_string(), value)).collect())
+    }
+
+    /// Mark a variable in the current scope as constant, so later
+    /// assignments to it fail.
+    pub fn mark_constant(&mut self, name: &str) {
+        let current_scope = self.scopes.last_mut()
+            .expect("scope stack should never be empty");
+        current_scope.constants.push(name.to_string());
+    }
+
+    /// Get the value of a variable, searching from innermost to outermost scope.
+    ///
+    /// Fails only if the variable was spilled to disk and can't be read back.
+    pub fn get_var(&self, name: &str) -> Result<Option<Cow<'_, Value>>, String> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.position(name).map(|i| &scope.values[i]))
+            .map(Binding::read)
+            .transpose()
     }
 
     /// Set the value of an existing variable.
@@ -186,24 +1708,153 @@ impl Runtime {
     /// Searches from innermost to outermost scope for the variable.
     /// Returns an error if the variable doesn't exist.
     pub fn set_var(&mut self, name: &str, value: Value) -> Result<(), String> {
-        for scope in self.scopes.iter_mut().rev() {
-            if scope.contains_key(name) {
-                scope.insert(name.to_string(), value);
-                return Ok(());
+        let Some(scope) = self.scopes.iter().rposition(|scope| scope.position(name).is_some()) else {
+            return Err(format!("Variable '{}' not defined", name));
+        };
+        if self.scopes[scope].constants.iter().any(|c| c == name) {
+            return Err(format!("Cannot assign to constant '{}'", name));
+        }
+        let binding = self.bind(value);
+        let scope = &mut self.scopes[scope];
+        if let Some(i) = scope.position(name) {
+            scope.values[i] = binding;
+        }
+        Ok(())
+    }
+
+    /// Get the value of the variable named by an identifier from the AST.
+    ///
+    /// Uses the resolved slot when one is available, falling back to a
+    /// search by name.
+    pub fn lookup_var(&self, ident: &str) -> Result<Option<Cow<'_, Value>>, String> {
+        match self.resolve_slot(ident) {
+            Some((scope, slot)) => self.scopes[scope].values[slot].read().map(Some),
+            None => self.get_var(ident),
+        }
+    }
+
+    /// Assign to the variable named by an identifier from the AST.
+    pub fn assign_var(&mut self, ident: &str, value: Value) -> Result<(), String> {
+        match self.resolve_slot(ident) {
+            Some((scope, _)) if self.scopes[scope].constants.iter().any(|c| c == ident) => {
+                Err(format!("Cannot assign to constant '{}'", ident))
+            }
+            Some((scope, slot)) => {
+                self.scopes[scope].values[slot] = self.bind(value);
+                Ok(())
             }
+            None => self.set_var(ident, value),
         }
-        Err(format!("Variable '{}' not defined", name))
     }
+
+    /// Find the (scope, slot) holding the variable `ident` refers to: the
+    /// one the resolver assigned to a local, or the one a module-level
+    /// variable was defined in.
+    ///
+    /// The slot is only trusted if it holds a variable of the same name, so
+    /// a runtime whose scopes diverge from the resolver's (e.g. a redefinition
+    /// error was swallowed) still behaves correctly.
+    fn resolve_slot(&self, ident: &str) -> Option<(usize, usize)> {
+        let (scope, slot) = match self.symbols.as_ref()?.resolution(ident)? {
+            Resolution::Local { hops, slot, .. } => (self.scopes.len().checked_sub(hops + 1)?, slot),
+            Resolution::Global(id) => (*self.global_slots.get(id.0)?)?,
+            Resolution::Builtin | Resolution::Unresolved => return None,
+        };
+        (self.scopes.get(scope)?.names.get(slot)? == ident).then_some((scope, slot))
+    }
+}
+
+/// Resolve symlinks, `.` and `..` in a path, as far as it exists. A
+/// relative path is taken from the current directory first.
+///
+/// Unlike `fs::canonicalize`, this accepts paths whose tail doesn't exist yet
+/// (such as a file about to be written): each existing prefix is
+/// canonicalized, and the missing remainder is appended as written.
+/// Returns `None` if a `..` climbs above the root directory.
+pub(crate) fn resolve_symlinks(path: &Path) -> Option<PathBuf> {
+    let mut resolved = PathBuf::new();
+    for component in std::path::absolute(path).ok()?.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved = resolved.parent()?.to_path_buf();
+            }
+            other => {
+                resolved.push(other);
+                if let Ok(canonical) = resolved.canonicalize() {
+                    resolved = canonical;
+                }
+            }
+        }
+    }
+    Some(resolved)
+}
+
+/// Format a command line for display, quoting arguments that need it.
+pub(crate) fn display_command(program: &str, args: &[String]) -> String {
+    let mut command = program.to_string();
+    for arg in args {
+        command.push(' ');
+        if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '\'' || c == '"') {
+            command.push_str(&format!("{:?}", arg));
+        } else {
+            command.push_str(arg);
+        }
+    }
+    command
 }
 
 impl Default for Runtime {
     fn default() -> Self {
         Self {
-            scopes: vec![HashMap::new()],
+            scopes: vec![Scope::default()],
+            symbols: None,
+            global_slots: Vec::new(),
             working_dir: std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/")),
+            session: None,
             print_sink: None,
             plan_reporter: None,
             thought_reporter: None,
+            progress_reporter: None,
+            warning_reporter: None,
+            warnings: Vec::new(),
+            approval_handler: None,
+            always_allowed: HashSet::new(),
+            capabilities: CapabilityPolicy::default(),
+            limits: Limits::default(),
+            models: ModelChain::default(),
+            variant_policy: VariantPolicy::default(),
+            think_calls: 0,
+            llm_calls: 0,
+            usage: Usage::default(),
+            transcript: Vec::new(),
+            summary_prompt: None,
+            pinned: Vec::new(),
+            redactor: Redactor::default(),
+            redactions: Vec::new(),
+            context_depth: 0,
+            last_call: None,
+            contexts: Vec::new(),
+            host_functions: BTreeMap::new(),
+            schemas: Schemas::default(),
+            cancellation: CancellationToken::new(),
+            deadline: None,
+            source: None,
+            loop_iterations: Vec::new(),
+            journal: EffectJournal::new(),
+            replay: EffectJournal::new(),
+            plan: Vec::new(),
+            plan_done: 0,
+            strict: false,
+            lenient_shell: false,
+            think_priority: Priority::default(),
+            last_status: None,
+            spill: SpillStore::default(),
+            processes: Processes::default(),
+            background_policy: BackgroundPolicy::default(),
+            coverage: None,
+            telemetry: Arc::new(NoopSink),
+            result_json: None,
         }
     }
 }
@@ -216,13 +1867,13 @@ mod tests {
     fn test_define_and_get_var() {
         let mut rt = Runtime::default();
         rt.define_var("x", Value::Number(42.0)).unwrap();
-        assert_eq!(rt.get_var("x"), Some(&Value::Number(42.0)));
+        assert_eq!(rt.get_var("x").unwrap().as_deref(), Some(&Value::Number(42.0)));
     }
 
     #[test]
     fn test_undefined_var() {
         let rt = Runtime::default();
-        assert_eq!(rt.get_var("x"), None);
+        assert_eq!(rt.get_var("x").unwrap(), None);
     }
 
     #[test]
@@ -230,7 +1881,7 @@ mod tests {
         let mut rt = Runtime::default();
         rt.define_var("x", Value::Number(1.0)).unwrap();
         rt.set_var("x", Value::Number(2.0)).unwrap();
-        assert_eq!(rt.get_var("x"), Some(&Value::Number(2.0)));
+        assert_eq!(rt.get_var("x").unwrap().as_deref(), Some(&Value::Number(2.0)));
     }
 
     #[test]
@@ -247,10 +1898,10 @@ mod tests {
 
         rt.push_scope();
         rt.define_var("x", Value::Number(2.0)).unwrap();
-        assert_eq!(rt.get_var("x"), Some(&Value::Number(2.0)));
+        assert_eq!(rt.get_var("x").unwrap().as_deref(), Some(&Value::Number(2.0)));
 
         rt.pop_scope();
-        assert_eq!(rt.get_var("x"), Some(&Value::Number(1.0)));
+        assert_eq!(rt.get_var("x").unwrap().as_deref(), Some(&Value::Number(1.0)));
     }
 
     #[test]
@@ -259,6 +1910,252 @@ mod tests {
         rt.define_var("x", Value::Number(1.0)).unwrap();
 
         rt.push_scope();
-        assert_eq!(rt.get_var("x"), Some(&Value::Number(1.0)));
+        assert_eq!(rt.get_var("x").unwrap().as_deref(), Some(&Value::Number(1.0)));
+    }
+
+    #[test]
+    fn test_ask_first_shell_approval() {
+        let mut rt = Runtime::default();
+        rt.capabilities.shell = Permission::AskFirst;
+        rt.capabilities.shell_allowlist = vec!["ls".to_string()];
+        let args = vec!["commit".to_string(), "-m".to_string(), "two words".to_string()];
+
+        // Without a handler, only allowlisted programs run
+        assert!(rt.check_shell("ls", &[]).is_ok());
+        assert!(rt.check_shell("git", &args).is_err());
+
+        let (tx, rx) = mpsc::channel::<ApprovalRequest>();
+        rt.set_approval_handler(tx);
+        let approver = std::thread::spawn(move || {
+            let mut commands = Vec::new();
+            for (request, decision) in rx.iter().zip([ApprovalDecision::Deny, ApprovalDecision::AlwaysAllow]) {
+                commands.push(request.summary);
+                request.response_tx.send(decision).unwrap();
+            }
+            commands
+        });
+
+        assert!(rt.check_shell("git", &args).is_err());
+        assert!(rt.check_shell("git", &args).is_ok());
+        // Remembered for the rest of the session; the approver isn't asked again
+        assert!(rt.check_shell("git", &["status".to_string()]).is_ok());
+        rt.approval_handler = None;
+
+        let commands = approver.join().unwrap();
+        assert_eq!(commands, vec![r#"git commit -m "two words""#; 2]);
+    }
+
+    #[test]
+    fn test_gate_approval() {
+        let mut rt = Runtime::default();
+        assert!(rt.request_gate_approval("Delete the branch").is_err());
+
+        let (tx, rx) = mpsc::channel::<ApprovalRequest>();
+        rt.set_approval_handler(tx);
+        let approver = std::thread::spawn(move || {
+            let request = rx.recv().unwrap();
+            request.response_tx.send(ApprovalDecision::AlwaysAllow).unwrap();
+            // Hold the next request without answering it
+            let unanswered = rx.recv().unwrap();
+            (request.summary, request.program, unanswered.summary)
+        });
+
+        assert!(rt.request_gate_approval("Delete the branch").is_ok());
+        rt.limits.approval_timeout_secs = Some(0);
+        let err = rt.request_gate_approval("Push to main").unwrap_err();
+        assert_eq!(err, "`Push to main` was not approved");
+        rt.approval_handler = None;
+
+        let (summary, program, unanswered) = approver.join().unwrap();
+        assert_eq!(summary, "Delete the branch");
+        assert_eq!(program, None);
+        assert_eq!(unanswered, "Push to main");
+    }
+
+    #[test]
+    fn test_confined_paths() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let work = dir.path().join("work");
+        let shared = dir.path().join("shared");
+        std::fs::create_dir(&work).unwrap();
+        std::fs::create_dir(&shared).unwrap();
+
+        let mut rt = Runtime::new(work.clone());
+        assert!(rt.resolve_path("notes/new.txt").is_ok());
+        assert!(rt.resolve_path("../shared/data.json").is_err());
+        assert!(rt.resolve_path(shared.to_str().unwrap()).is_err());
+
+        rt.capabilities.file_roots = vec![shared.clone()];
+        assert!(rt.resolve_path("../shared/data.json").is_ok());
+
+        rt.capabilities.file_access = FileAccess::Unrestricted;
+        assert!(rt.resolve_path("/etc/hosts").is_ok());
+    }
+
+    #[test]
+    fn test_confined_paths_from_relative_working_dir() {
+        let mut rt = Runtime::new(PathBuf::from("."));
+        assert!(rt.resolve_path("notes/new.txt").is_ok());
+        assert!(rt.resolve_path("../outside.txt").is_err());
+
+        // A `..` above `/` neither reaches a path nor grants a root
+        rt.capabilities.file_roots = vec![PathBuf::from("/..")];
+        assert!(rt.resolve_path("/etc/hosts").is_err());
+        assert!(rt.resolve_path("/../etc/hosts").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_confined_paths_follow_symlinks() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let work = dir.path().join("work");
+        std::fs::create_dir(&work).unwrap();
+        std::os::unix::fs::symlink(dir.path(), work.join("escape")).unwrap();
+        std::os::unix::fs::symlink(".", work.join("here")).unwrap();
+
+        let rt = Runtime::new(work);
+        assert!(rt.resolve_path("escape/secret.txt").is_err());
+        assert!(rt.resolve_path("here/file.txt").is_ok());
+    }
+
+    #[test]
+    fn test_merge_scope_keeps_variables() {
+        let mut rt = Runtime::default();
+        rt.define_var("x", Value::Number(1.0)).unwrap();
+        for turn in 0..3 {
+            rt.push_scope();
+            rt.define_var("x", Value::Number(turn as f64)).unwrap();
+            rt.define_var(&format!("y{}", turn), Value::Null).unwrap();
+            rt.merge_scope();
+        }
+        assert_eq!(rt.scopes.len(), 1);
+        assert_eq!(rt.get_var("x").unwrap().as_deref(), Some(&Value::Number(2.0)));
+        let names: Vec<String> = rt.variables().unwrap().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["x", "y0", "y1", "y2"]);
+    }
+
+    #[test]
+    fn test_lookup_without_symbols_uses_names() {
+        let mut rt = Runtime::default();
+        rt.define_var("x", Value::Number(1.0)).unwrap();
+        rt.push_scope();
+        rt.assign_var("x", Value::Number(2.0)).unwrap();
+        assert_eq!(rt.lookup_var("x").unwrap().as_deref(), Some(&Value::Number(2.0)));
+    }
+
+    #[test]
+    fn test_large_values_spill_to_disk() {
+        let mut rt = Runtime::default();
+        rt.limits.spill_threshold_bytes = Some(1024);
+        let big = Value::String("x".repeat(4096));
+
+        rt.define_var("small", Value::String("hi".to_string())).unwrap();
+        rt.define_var("big", big.clone()).unwrap();
+        assert!(rt.spilled_bytes() >= 4096);
+        assert!(rt.memory_usage() < 1024);
+        assert_eq!(rt.get_var("big").unwrap().as_deref(), Some(&big));
+
+        // Reassigning a small value brings the variable back into memory
+        rt.set_var("big", Value::Number(1.0)).unwrap();
+        assert_eq!(rt.spilled_bytes(), 0);
+        assert_eq!(rt.get_var("big").unwrap().as_deref(), Some(&Value::Number(1.0)));
+    }
+
+    #[test]
+    fn test_budget_limits() {
+        let mut rt = Runtime::default();
+        rt.limits.max_llm_calls = Some(2);
+        rt.limits.max_cost_usd = Some(0.10);
+
+        rt.record_llm_call().unwrap();
+        rt.record_usage(Usage { input_tokens: 100, output_tokens: 20, cost_usd: Some(0.04) }).unwrap();
+        let Value::Object(remaining) = rt.budget_remaining() else { panic!("expected an object") };
+        assert_eq!(remaining.get("llm_calls"), Some(&Value::Number(1.0)));
+        assert_eq!(remaining.get("total_tokens"), Some(&Value::Null));
+
+        rt.record_llm_call().unwrap();
+        let err = rt.record_usage(Usage { input_tokens: 100, output_tokens: 20, cost_usd: Some(0.07) });
+        assert!(matches!(err, Err(Error::BudgetExceeded(_))), "{:?}", err);
+        assert!(matches!(rt.record_llm_call(), Err(Error::BudgetExceeded(_))));
+    }
+
+    #[test]
+    fn test_nested_timeouts_keep_the_earlier_deadline() {
+        let mut runtime = Runtime::new(PathBuf::from("."));
+        assert_eq!(runtime.time_remaining(), None);
+
+        runtime.with_timeout(Duration::from_secs(60), |runtime| {
+            let outer = runtime.deadline().unwrap();
+            runtime.with_timeout(Duration::from_secs(3600), |runtime| {
+                assert_eq!(runtime.deadline(), Some(outer));
+            });
+            runtime.with_timeout(Duration::from_millis(1), |runtime| {
+                assert!(runtime.deadline().unwrap() < outer);
+                assert_eq!(runtime.sleep(Duration::from_secs(60)), Err(DEADLINE_MESSAGE.to_string()));
+                assert!(runtime.check_cancelled().is_err());
+            });
+            runtime.with_timeout(Duration::MAX, |runtime| {
+                assert_eq!(runtime.deadline(), Some(outer));
+            });
+            assert_eq!(runtime.deadline(), Some(outer));
+            assert!(runtime.check_deadline().is_ok());
+        });
+        assert_eq!(runtime.deadline(), None);
+
+        // A timeout past the end of the clock means no deadline
+        runtime.with_timeout(Duration::MAX, |runtime| {
+            assert_eq!(runtime.deadline(), None);
+        });
+    }
+
+    #[test]
+    fn test_fork_and_merge_context() {
+        let entry = |prompt: &str| TranscriptEntry {
+            kind: "think",
+            prompt: prompt.to_string(),
+            response: Value::Null,
+        };
+        let mut rt = Runtime::default();
+        rt.record_transcript(entry("shared"));
+
+        let a = rt.fork_context();
+        let b = rt.fork_context();
+        rt.with_context(a, |rt| rt.record_transcript(entry("a"))).unwrap();
+        rt.with_context(b, |rt| rt.record_transcript(entry("b"))).unwrap();
+        assert_eq!(rt.transcript().len(), 1);
+
+        rt.merge_context(b).unwrap();
+        let prompts: Vec<_> = rt.transcript().iter().map(|e| e.prompt.as_str()).collect();
+        assert_eq!(prompts, vec!["shared", "b"]);
+        assert!(rt.merge_context(7).is_err());
+    }
+
+    #[test]
+    fn test_context_to_summarize() {
+        let entry = |prompt: &str| TranscriptEntry {
+            kind: "think",
+            prompt: prompt.to_string(),
+            response: Value::String("ok".to_string()),
+        };
+        let mut rt = Runtime::default();
+        rt.limits.max_context_tokens = Some(20);
+        rt.pin_fact("the repo is patchwork".to_string());
+        rt.pin_fact("the repo is patchwork".to_string());
+        assert_eq!(rt.pinned_facts().len(), 1);
+        for prompt in ["first question asked here", "second question asked here", "third question asked here"] {
+            rt.record_transcript(entry(prompt));
+        }
+        assert_eq!(rt.context_tokens(), 24);
+
+        let (count, prompt) = rt.context_to_summarize().unwrap();
+        assert_eq!(count, 2);
+        assert!(prompt.contains("- the repo is patchwork"));
+        assert!(prompt.contains("think: second question asked here\nanswer: ok"));
+        assert!(!prompt.contains("third"));
+
+        rt.fold_context(count, Value::String("two questions".to_string()));
+        let kinds: Vec<_> = rt.transcript().iter().map(|e| e.kind).collect();
+        assert_eq!(kinds, vec!["summary", "think"]);
+        assert!(rt.context_to_summarize().is_none());
     }
 }
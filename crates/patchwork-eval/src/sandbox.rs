@@ -0,0 +1,513 @@
+//! Workspace sandbox policy, and the capabilities a program asks for.
+//!
+//! A workspace can cap what the programs run in it may do with a
+//! `.patchwork/policy.toml`, in the config file's `capabilities` and
+//! `limits` format:
+//!
+//! ```toml
+//! [capabilities]
+//! shell = "deny"
+//! shell_allowlist = ["git", "ls"]   # may run even though shell is denied
+//! file_write = "ask-first"
+//!
+//! [limits]
+//! max_llm_calls = 20
+//! ```
+//!
+//! A policy only narrows the host's config: each permission becomes the
+//! stricter of the two, limits the smaller, and settings the policy leaves
+//! out keep the config's. Hosts can build one from elsewhere too, such as
+//! the ACP proxy from the capabilities its client advertises.
+//!
+//! The file is read as TOML, of the kind people write by hand: `[section]`
+//! headers, `key = value` lines, strings, numbers, booleans, one-line
+//! arrays, and `#` comments.
+//!
+//! `refused_requests` lists what a program asks for that a policy denies
+//! outright: shell commands, file writes, notifications, generated code,
+//! and the capabilities its front matter `requires`. A host can then turn
+//! the program away before it runs instead of partway through.
+
+use std::path::Path;
+
+use patchwork_parser::ast::{
+    Block, CommandArg, EvalCheck, Expr, ImportPath, Item, ObjectField, Program, PromptBlock, PromptItem, RedirectOp,
+    Statement, StringLiteral, StringPart,
+};
+use serde_json::{Map, Number, Value as JsonValue};
+
+use crate::config::{CapabilityPolicy, Config, ConfigError, ConfigLayer, FileAccess, Limits, Permission};
+use crate::front_matter::FrontMatter;
+
+/// Where a workspace's policy lives, relative to its root.
+pub const WORKSPACE_POLICY_FILE: &str = ".patchwork/policy.toml";
+
+/// The most programs may do, laid over a host's config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SandboxPolicy {
+    pub capabilities: CapabilityPolicy,
+    pub limits: Limits,
+}
+
+impl SandboxPolicy {
+    /// A policy that takes nothing away.
+    pub fn unrestricted() -> Self {
+        Self {
+            capabilities: CapabilityPolicy { file_access: FileAccess::Unrestricted, ..CapabilityPolicy::default() },
+            limits: Limits::default(),
+        }
+    }
+
+    /// The policy of the workspace at `root`, or an unrestricted one if it
+    /// has none.
+    pub fn load(root: &Path) -> Result<Self, ConfigError> {
+        let path = root.join(WORKSPACE_POLICY_FILE);
+        if !path.is_file() {
+            return Ok(Self::unrestricted());
+        }
+        let origin = path.display().to_string();
+        let text = std::fs::read_to_string(&path).map_err(|e| ConfigError::new(&origin, e.to_string()))?;
+        Self::from_toml(&text, &origin)
+    }
+
+    /// Read a policy from the contents of a policy file.
+    pub fn from_toml(text: &str, origin: &str) -> Result<Self, ConfigError> {
+        let root = parse_toml(text, origin)?;
+        let mut sections = root.as_object().into_iter().flat_map(|root| root.keys());
+        if let Some(key) = sections.find(|key| !matches!(key.as_str(), "capabilities" | "limits")) {
+            return Err(ConfigError::new(format!("{} ({})", origin, key), "expected only [capabilities] and [limits]"));
+        }
+        let layer = ConfigLayer::from_value(&root, origin)?;
+        let unrestricted = Self::unrestricted();
+        let mut ceiling =
+            Config { capabilities: unrestricted.capabilities, limits: unrestricted.limits, ..Config::default() };
+        ceiling.merge(&layer);
+        Ok(Self { capabilities: ceiling.capabilities, limits: ceiling.limits })
+    }
+
+    /// Narrow `config` to what this policy allows, for a host running in
+    /// `working_dir`.
+    pub fn apply(&self, config: &mut Config, working_dir: &Path) {
+        // Where the policy leaves shell or file access open, the config's
+        // own allowlist and roots stand
+        let mut ceiling = self.capabilities.clone();
+        if ceiling.shell == Permission::Allow {
+            ceiling.shell_allowlist = config.capabilities.shell_allowlist.clone();
+        }
+        if ceiling.file_access == FileAccess::Unrestricted {
+            ceiling.file_roots = config.capabilities.file_roots.clone();
+        }
+        config.capabilities = config.capabilities.restrict(&ceiling, working_dir);
+        config.limits = config.limits.tighter(&self.limits);
+    }
+}
+
+/// Something a program asks to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapabilityRequest {
+    /// The capability, by its config name, e.g. `shell`.
+    pub capability: String,
+    /// What asks for it, e.g. `` `git` `` for a shell command.
+    pub what: String,
+    /// The line it is on, counting from 1.
+    pub line: Option<usize>,
+}
+
+/// What `code` asks for that `policy` denies outright. Capabilities that
+/// are asked for first aren't refused; a person answers when the program
+/// uses them. Code that doesn't parse asks for nothing here; running it
+/// reports the error.
+///
+/// Code starting with `{` is a block, as in `Interpreter::eval`.
+pub fn refused_requests(code: &str, policy: &CapabilityPolicy) -> Vec<CapabilityRequest> {
+    let wrapped;
+    let source = if code.trim_start().starts_with('{') {
+        wrapped = format!("skill __main__() {}", code);
+        wrapped.as_str()
+    } else {
+        code
+    };
+    let mut requests = Requests { source, found: Vec::new() };
+    if let Ok(program) = patchwork_parser::parse(source) {
+        requests.program(&program);
+    }
+    if let Ok(Some(front)) = FrontMatter::from_source(code) {
+        for name in front.requires {
+            let what = "front matter `requires`".to_string();
+            requests.found.push(CapabilityRequest { capability: name, what, line: None });
+        }
+    }
+
+    let mut refused: Vec<CapabilityRequest> = Vec::new();
+    for request in requests.found {
+        let denied = match request.capability.as_str() {
+            // `what` is the program's name in backquotes
+            "shell" => {
+                policy.shell == Permission::Deny
+                    && !policy.shell_allowlist.iter().any(|program| request.what == format!("`{}`", program))
+            }
+            name => policy.permission(name) == Some(Permission::Deny),
+        };
+        if denied && !refused.contains(&request) {
+            refused.push(request);
+        }
+    }
+    refused
+}
+
+/// Walks a program collecting what it asks for.
+struct Requests<'s> {
+    source: &'s str,
+    found: Vec<CapabilityRequest>,
+}
+
+impl<'s> Requests<'s> {
+    fn add(&mut self, capability: &str, what: String, site: &str) {
+        let line = (site.as_ptr() as usize)
+            .checked_sub(self.source.as_ptr() as usize)
+            .filter(|&offset| offset <= self.source.len())
+            .map(|offset| self.source[..offset].matches('\n').count() + 1);
+        self.found.push(CapabilityRequest { capability: capability.to_string(), what, line });
+    }
+
+    fn program(&mut self, program: &Program) {
+        for item in &program.items {
+            match item {
+                Item::Import(decl) => {
+                    if let ImportPath::Simple(parts) = &decl.path {
+                        if parts.len() == 2 && parts[0] == "std" && parts[1] == "notify" {
+                            self.add("notify", "`import std.notify`".to_string(), parts[0]);
+                        }
+                    }
+                }
+                Item::Skill(decl) => self.block(&decl.body),
+                Item::Worker(decl) => self.block(&decl.body),
+                Item::Function(decl) => self.block(&decl.body),
+                Item::Trait(decl) => decl.methods.iter().for_each(|method| self.block(&method.body)),
+                Item::Var(decl) => self.expr(&decl.init),
+                Item::Eval(decl) => {
+                    self.expr(&decl.input);
+                    self.expr(&decl.output);
+                    for check in &decl.checks {
+                        if let EvalCheck::Contains(expr) | EvalCheck::Judge(expr) = check {
+                            self.expr(expr);
+                        }
+                    }
+                }
+                Item::Type(_) => {}
+            }
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        for statement in &block.statements {
+            match statement {
+                Statement::VarDecl { init: Some(expr), .. } | Statement::Expr(expr) | Statement::Return(Some(expr)) => {
+                    self.expr(expr)
+                }
+                Statement::If { condition, then_block, else_block } => {
+                    self.expr(condition);
+                    self.block(then_block);
+                    if let Some(else_block) = else_block {
+                        self.block(else_block);
+                    }
+                }
+                Statement::ForIn { iter: condition, body, .. } | Statement::While { condition, body } => {
+                    self.expr(condition);
+                    self.block(body);
+                }
+                Statement::Plan { steps } => steps.iter().for_each(|step| self.string(step)),
+                _ => {}
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::BareCommand { name, args } => {
+                self.add("shell", format!("`{}`", name), name);
+                for arg in args {
+                    if let CommandArg::String(string) = arg {
+                        self.string(string);
+                    }
+                }
+            }
+            Expr::ShellRedirect { command, op, target } => {
+                if matches!(op, RedirectOp::Out | RedirectOp::Append | RedirectOp::ErrOut) {
+                    if let Some(site) = command_name(command) {
+                        self.add("file_write", "output redirection".to_string(), site);
+                    }
+                }
+                self.expr(command);
+                self.expr(target);
+            }
+            Expr::Call { callee, args } => {
+                if let Expr::Identifier(name) = callee.as_ref() {
+                    match *name {
+                        "write" => self.add("file_write", "`write()`".to_string(), name),
+                        "eval_patchwork" => self.add("eval_code", "`eval_patchwork()`".to_string(), name),
+                        _ => {}
+                    }
+                }
+                self.expr(callee);
+                args.iter().for_each(|arg| self.expr(arg));
+            }
+            Expr::String(string) => self.string(string),
+            Expr::Array(items) => items.iter().for_each(|item| self.expr(item)),
+            Expr::Object(fields) => self.fields(fields),
+            Expr::Binary { left, right, .. }
+            | Expr::ShellPipe { left, right }
+            | Expr::ShellAnd { left, right }
+            | Expr::ShellOr { left, right }
+            | Expr::Index { object: left, index: right } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            Expr::Unary { operand: inner, .. }
+            | Expr::Member { object: inner, .. }
+            | Expr::PostIncrement(inner)
+            | Expr::PostDecrement(inner)
+            | Expr::Paren(inner)
+            | Expr::Await(inner)
+            | Expr::CommandSubst(inner)
+            | Expr::ShellBackground(inner) => self.expr(inner),
+            Expr::Think(prompt) | Expr::Ask(prompt) | Expr::Approve(prompt) => self.prompt(prompt),
+            Expr::Do(block) => self.block(block),
+            Expr::Identifier(_) | Expr::Number(_) | Expr::True | Expr::False => {}
+        }
+    }
+
+    fn string(&mut self, string: &StringLiteral) {
+        for part in &string.parts {
+            if let StringPart::Interpolation(expr) = part {
+                self.expr(expr);
+            }
+        }
+    }
+
+    fn fields(&mut self, fields: &[ObjectField]) {
+        for value in fields.iter().filter_map(|field| field.value.as_ref()) {
+            self.expr(value);
+        }
+    }
+
+    fn prompt(&mut self, prompt: &PromptBlock) {
+        for item in &prompt.items {
+            match item {
+                PromptItem::Interpolation(expr) => self.expr(expr),
+                PromptItem::Code(block) => self.block(block),
+                PromptItem::Examples(fields) => self.fields(fields),
+                PromptItem::Variant { block, .. } => self.prompt(block),
+                PromptItem::Text(_) => {}
+            }
+        }
+    }
+}
+
+/// The name of the program a shell expression runs first, for its position.
+fn command_name<'a>(expr: &Expr<'a>) -> Option<&'a str> {
+    match expr {
+        Expr::BareCommand { name, .. } => Some(name),
+        Expr::ShellPipe { left, .. } | Expr::ShellAnd { left, .. } | Expr::ShellOr { left, .. } => command_name(left),
+        Expr::ShellRedirect { command, .. } => command_name(command),
+        Expr::CommandSubst(inner) | Expr::ShellBackground(inner) | Expr::Paren(inner) => command_name(inner),
+        _ => None,
+    }
+}
+
+/// Read a policy file as JSON, laid out as in a config file.
+fn parse_toml(text: &str, origin: &str) -> Result<JsonValue, ConfigError> {
+    let mut root = Map::new();
+    let mut section: Option<String> = None;
+    for (i, line) in text.lines().enumerate() {
+        let err = |message: String| ConfigError::new(format!("{}:{}", origin, i + 1), message);
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+            let name = name.trim().to_string();
+            if root.insert(name.clone(), JsonValue::Object(Map::new())).is_some() {
+                return Err(err(format!("[{}] appears twice", name)));
+            }
+            section = Some(name);
+            continue;
+        }
+        let (key, value) =
+            line.split_once('=').ok_or_else(|| err("expected `key = value` or `[section]`".to_string()))?;
+        let key = key.trim().trim_matches('"').to_string();
+        let mut rest = value.trim();
+        let value = toml_value(&mut rest).map_err(err)?;
+        if !rest.trim().is_empty() {
+            return Err(err(format!("unexpected `{}` after the value", rest.trim())));
+        }
+        let table = match &section {
+            Some(name) => root.get_mut(name).and_then(JsonValue::as_object_mut).expect("inserted with its header"),
+            None => &mut root,
+        };
+        if table.insert(key.clone(), value).is_some() {
+            return Err(err(format!("`{}` is set twice", key)));
+        }
+    }
+    Ok(JsonValue::Object(root))
+}
+
+/// `line` without a `#` comment, if it has one outside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+/// Read one value from the start of `rest`, leaving what follows it.
+fn toml_value(rest: &mut &str) -> Result<JsonValue, String> {
+    *rest = rest.trim_start();
+    if let Some(after) = rest.strip_prefix('"') {
+        let mut text = String::new();
+        let mut chars = after.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    *rest = &after[i + 1..];
+                    return Ok(JsonValue::String(text));
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => text.push('\n'),
+                    Some('t') => text.push('\t'),
+                    Some(c @ ('"' | '\\')) => text.push(c),
+                    _ => return Err("unsupported escape in string".to_string()),
+                },
+                c => text.push(c),
+            }
+        }
+        return Err("unterminated string".to_string());
+    }
+    if let Some(after) = rest.strip_prefix('\'') {
+        let end = after.find('\'').ok_or("unterminated string")?;
+        *rest = &after[end + 1..];
+        return Ok(JsonValue::String(after[..end].to_string()));
+    }
+    if let Some(after) = rest.strip_prefix('[') {
+        *rest = after;
+        let mut items = Vec::new();
+        loop {
+            *rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                *rest = after;
+                return Ok(JsonValue::Array(items));
+            }
+            items.push(toml_value(rest)?);
+            *rest = rest.trim_start();
+            match rest.strip_prefix(',') {
+                Some(after) => *rest = after,
+                None if rest.starts_with(']') => {}
+                None => return Err("expected `,` or `]` in array (arrays must fit on one line)".to_string()),
+            }
+        }
+    }
+    let end = rest.find(|c: char| c == ',' || c == ']' || c.is_whitespace()).unwrap_or(rest.len());
+    let (token, after) = rest.split_at(end);
+    *rest = after;
+    match token {
+        "true" => Ok(JsonValue::Bool(true)),
+        "false" => Ok(JsonValue::Bool(false)),
+        "" => Err("expected a value".to_string()),
+        token => {
+            let digits = token.replace('_', "");
+            if let Ok(n) = digits.parse::<i64>() {
+                return Ok(JsonValue::from(n));
+            }
+            digits
+                .parse::<f64>()
+                .ok()
+                .and_then(Number::from_f64)
+                .map(JsonValue::Number)
+                .ok_or_else(|| format!("unexpected `{}`; quote strings", token))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_workspace_policy_narrows_config() {
+        let policy = SandboxPolicy::from_toml(
+            concat!(
+                "# No surprises in this repo\n",
+                "[capabilities]\n",
+                "shell = \"deny\"\n",
+                "shell_allowlist = [\"git\", 'ls']  # read-only tools\n",
+                "\n",
+                "[limits]\n",
+                "max_llm_calls = 20\n",
+            ),
+            "policy.toml",
+        )
+        .unwrap();
+        let mut config = Config::default();
+        config.capabilities.file_roots = vec!["/data".into()];
+        config.limits.max_llm_calls = Some(50);
+        policy.apply(&mut config, Path::new("/work"));
+        assert_eq!(config.capabilities.shell, Permission::Deny);
+        assert_eq!(config.capabilities.shell_allowlist, vec!["git", "ls"]);
+        assert_eq!(config.capabilities.file_write, Permission::Allow);
+        assert_eq!(config.capabilities.file_roots, vec![std::path::PathBuf::from("/data")]);
+        assert_eq!(config.limits.max_llm_calls, Some(20));
+
+        // A policy can't loosen the config
+        let loose = SandboxPolicy::from_toml("[capabilities]\nfile_write = \"allow\"\n", "policy.toml").unwrap();
+        config.capabilities.file_write = Permission::Deny;
+        loose.apply(&mut config, Path::new("/work"));
+        assert_eq!(config.capabilities.file_write, Permission::Deny);
+
+        let err = SandboxPolicy::from_toml("[capabilities]\nshell = deny\n", "policy.toml").unwrap_err();
+        assert_eq!(err.to_string(), "policy.toml:2: unexpected `deny`; quote strings");
+        let err = SandboxPolicy::from_toml("[session]\nkeep = \"always\"\n", "policy.toml").unwrap_err();
+        assert!(err.message.contains("only [capabilities] and [limits]"), "{}", err);
+    }
+
+    #[test]
+    fn test_refused_requests() {
+        let policy = CapabilityPolicy {
+            shell: Permission::Deny,
+            shell_allowlist: vec!["git".to_string()],
+            file_write: Permission::Deny,
+            eval_code: Permission::AskFirst,
+            ..CapabilityPolicy::default()
+        };
+        let code = concat!(
+            "{\n",
+            "  var status = $(git status)\n",
+            "  $ rm -rf build\n",
+            "  write(\"out.txt\", status)\n",
+            "  eval_patchwork(\"1 + 1\")\n",
+            "}",
+        );
+        let refused = refused_requests(code, &policy);
+        let lines: Vec<(&str, &str, Option<usize>)> =
+            refused.iter().map(|r| (r.capability.as_str(), r.what.as_str(), r.line)).collect();
+        assert_eq!(lines, vec![("shell", "`rm`", Some(3)), ("file_write", "`write()`", Some(4))]);
+
+        let code = "---\nrequires: [notify]\n---\nimport std.notify\n\nskill main() {}\n";
+        let policy = CapabilityPolicy { notify: Permission::Deny, ..CapabilityPolicy::default() };
+        let refused = refused_requests(code, &policy);
+        let whats: Vec<&str> = refused.iter().map(|r| r.what.as_str()).collect();
+        assert_eq!(whats, vec!["`import std.notify`", "front matter `requires`"]);
+        assert_eq!(refused[0].line, Some(4));
+    }
+}
@@ -0,0 +1,282 @@
+//! Running a program on a recurring schedule.
+//!
+//! `--every 15m` turns a one-shot host into a long-running one that reruns
+//! the program for jobs like nightly triage. The `Scheduler` owns the loop:
+//!
+//! - runs never overlap: a run that outlasts the interval skips the ticks it
+//!   missed instead of queueing them, and a lock file next to the checkpoint
+//!   keeps two processes from running the same job at once
+//! - `--jitter 1m` delays each run by a random amount up to that long, so
+//!   many jobs on the same interval don't all start together
+//! - after every run the checkpoint file records the run count and start
+//!   time, so a restarted host picks up the schedule where it left off
+//!   rather than running again immediately
+
+use std::collections::hash_map::RandomState;
+use std::fs::{self, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::outcome::Outcome;
+use crate::timer::CancellationToken;
+
+/// How often to run, and how much random delay to add to each run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schedule {
+    pub every: Duration,
+    pub jitter: Duration,
+}
+
+impl Schedule {
+    pub fn every(every: Duration) -> Self {
+        Self { every, jitter: Duration::ZERO }
+    }
+
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// A random delay in `[0, jitter)`.
+    fn jitter_delay(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return Duration::ZERO;
+        }
+        // RandomState is seeded randomly per instance, which is all the
+        // randomness spreading start times needs
+        let random = RandomState::new().build_hasher().finish();
+        let nanos = self.jitter.as_nanos() as u64;
+        Duration::from_nanos(random % nanos.max(1))
+    }
+}
+
+/// What the scheduler remembers between runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Checkpoint {
+    /// Runs completed so far.
+    pub runs: u64,
+    /// When the most recent run started.
+    pub last_started: Option<SystemTime>,
+    /// Exit code of the most recent run.
+    pub last_exit_code: Option<i32>,
+}
+
+impl Checkpoint {
+    /// Read a checkpoint file. A missing file is a fresh checkpoint.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        let json: serde_json::Value = serde_json::from_str(&text)
+            .map_err(|e| format!("Invalid checkpoint {}: {}", path.display(), e))?;
+        let last_started = match json["last_started"].as_f64() {
+            Some(seconds) => Some(
+                Duration::try_from_secs_f64(seconds.max(0.0))
+                    .ok()
+                    .and_then(|since_epoch| UNIX_EPOCH.checked_add(since_epoch))
+                    .ok_or_else(|| format!("Invalid checkpoint {}: last_started is out of range", path.display()))?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            runs: json["runs"].as_u64().unwrap_or(0),
+            last_started,
+            last_exit_code: json["last_exit_code"].as_i64().map(|code| code as i32),
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let started = self
+            .last_started
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs_f64());
+        let json = serde_json::json!({
+            "runs": self.runs,
+            "last_started": started,
+            "last_exit_code": self.last_exit_code,
+        });
+        let text = serde_json::to_string_pretty(&json).unwrap_or_default();
+        fs::write(path, text).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+}
+
+/// Runs a job on a `Schedule` until cancelled.
+pub struct Scheduler {
+    schedule: Schedule,
+    checkpoint_path: Option<PathBuf>,
+    cancellation: CancellationToken,
+}
+
+impl Scheduler {
+    pub fn new(schedule: Schedule) -> Self {
+        Self { schedule, checkpoint_path: None, cancellation: CancellationToken::new() }
+    }
+
+    /// Persist progress to `path`, and lock `path` with `.lock` appended
+    /// while a run is in progress.
+    pub fn with_checkpoint(mut self, path: impl Into<PathBuf>) -> Self {
+        self.checkpoint_path = Some(path.into());
+        self
+    }
+
+    /// Share a cancellation token with the host; cancelling stops the loop
+    /// after the current run.
+    pub fn with_cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancellation = token;
+        self
+    }
+
+    /// Call `job` once per tick until cancelled, passing the run number
+    /// (starting at 1). Returns the final checkpoint.
+    pub fn run(&self, mut job: impl FnMut(u64) -> Outcome) -> Result<Checkpoint, String> {
+        let mut checkpoint = match &self.checkpoint_path {
+            Some(path) => Checkpoint::load(path)?,
+            None => Checkpoint::default(),
+        };
+        loop {
+            // Resume the schedule from the last run, even across restarts
+            let due = checkpoint.last_started.map_or(SystemTime::now(), |last| last + self.schedule.every);
+            let wait = due.duration_since(SystemTime::now()).unwrap_or_default() + self.schedule.jitter_delay();
+            if self.cancellation.wait_timeout(wait) {
+                return Ok(checkpoint);
+            }
+
+            let started = SystemTime::now();
+            let lock = match &self.checkpoint_path {
+                Some(path) => RunLock::acquire(path)?,
+                None => None,
+            };
+            if self.checkpoint_path.is_none() || lock.is_some() {
+                let outcome = job(checkpoint.runs + 1);
+                checkpoint.runs += 1;
+                checkpoint.last_exit_code = Some(outcome.exit_code());
+            }
+            checkpoint.last_started = Some(skip_missed(started, self.schedule.every));
+            if let Some(path) = &self.checkpoint_path {
+                checkpoint.save(path)?;
+            }
+            drop(lock);
+        }
+    }
+}
+
+/// The latest tick at or before now, counting from `started` by `every`,
+/// so a run that took longer than the interval doesn't cause a burst of
+/// catch-up runs.
+fn skip_missed(started: SystemTime, every: Duration) -> SystemTime {
+    let elapsed = SystemTime::now().duration_since(started).unwrap_or_default();
+    if every.is_zero() || elapsed < every {
+        return started;
+    }
+    let missed = (elapsed.as_nanos() / every.as_nanos()) as u32;
+    started + every * missed
+}
+
+/// A lock file held for the length of a run.
+struct RunLock {
+    path: PathBuf,
+}
+
+impl RunLock {
+    /// Take the lock for `checkpoint`, or return `None` if another process
+    /// holds it.
+    fn acquire(checkpoint: &Path) -> Result<Option<Self>, String> {
+        let mut path = checkpoint.as_os_str().to_owned();
+        path.push(".lock");
+        let path = PathBuf::from(path);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => Ok(Some(Self { path })),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(None),
+            Err(e) => Err(format!("Failed to create {}: {}", path.display(), e)),
+        }
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_runs_until_cancelled_and_checkpoints() {
+        let dir = TempDir::new().unwrap();
+        let checkpoint = dir.path().join("job.json");
+        let token = CancellationToken::new();
+        let scheduler = Scheduler::new(Schedule::every(Duration::from_millis(5)))
+            .with_checkpoint(&checkpoint)
+            .with_cancellation_token(token.clone());
+
+        let mut seen = Vec::new();
+        let result = scheduler
+            .run(|run| {
+                seen.push(run);
+                if run == 3 {
+                    token.cancel();
+                }
+                Outcome::Success
+            })
+            .unwrap();
+        assert_eq!(seen, vec![1, 2, 3]);
+        assert_eq!(result.runs, 3);
+        let saved = Checkpoint::load(&checkpoint).unwrap();
+        assert_eq!((saved.runs, saved.last_exit_code), (3, Some(0)));
+        assert!(saved.last_started.is_some());
+        assert!(!dir.path().join("job.json.lock").exists());
+    }
+
+    #[test]
+    fn test_out_of_range_checkpoint_is_invalid() {
+        let dir = TempDir::new().unwrap();
+        let checkpoint = dir.path().join("job.json");
+        fs::write(&checkpoint, r#"{ "runs": 2, "last_started": 1e300 }"#).unwrap();
+        let err = Checkpoint::load(&checkpoint).unwrap_err();
+        assert!(err.starts_with("Invalid checkpoint"), "{}", err);
+        assert!(err.ends_with("last_started is out of range"), "{}", err);
+    }
+
+    #[test]
+    fn test_locked_job_is_skipped() {
+        let dir = TempDir::new().unwrap();
+        let checkpoint = dir.path().join("job.json");
+        let held = RunLock::acquire(&checkpoint).unwrap();
+        assert!(held.is_some());
+        assert!(RunLock::acquire(&checkpoint).unwrap().is_none());
+
+        let token = CancellationToken::new();
+        let scheduler = Scheduler::new(Schedule::every(Duration::from_millis(5)))
+            .with_checkpoint(&checkpoint)
+            .with_cancellation_token(token.clone());
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(30));
+            token.cancel();
+        });
+        let mut ticks = 0;
+        let result = scheduler
+            .run(|_| {
+                ticks += 1;
+                Outcome::Success
+            })
+            .unwrap();
+        canceller.join().unwrap();
+        assert_eq!(ticks, 0);
+        assert_eq!(result.runs, 0);
+    }
+
+    #[test]
+    fn test_skip_missed_ticks() {
+        let every = Duration::from_secs(60);
+        let started = SystemTime::now() - Duration::from_secs(150);
+        assert_eq!(skip_missed(started, every), started + Duration::from_secs(120));
+        let recent = SystemTime::now();
+        assert_eq!(skip_missed(recent, every), recent);
+    }
+}
@@ -0,0 +1,283 @@
+//! Checking values against declared types at runtime.
+//!
+//! A `type` declaration is also a schema: the same declaration that
+//! annotates variables can check an LLM's answer or a loaded file before
+//! the program relies on its shape.
+//!
+//! ```text
+//! type Finding = { file: string, line: int, severity: "low" | "high" }
+//! type Report = { summary: string, findings: [Finding] }
+//!
+//! var report = validate(think { ... }, Report)
+//!
+//! eval "report shape" {
+//!     input CASES
+//!     output think { ... }
+//!     expect Report
+//! }
+//! ```
+//!
+//! `validate` returns the value unchanged, or fails listing every mismatch
+//! with its path, such as `$.findings[2].line: expected int, got string`.
+//! Objects may have fields their type doesn't mention.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use patchwork_parser::ast::TypeExpr;
+
+use crate::eval::type_name;
+use crate::value::Value;
+
+/// How many named types can refer to each other before a value is reached,
+/// which stops aliases like `type A = B` and `type B = A` from looping.
+const MAX_ALIAS_DEPTH: usize = 64;
+
+/// A type, owned so it outlives the program's source.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schema {
+    String,
+    Number,
+    /// A number with no fractional part.
+    Int,
+    Boolean,
+    Null,
+    /// Exactly this string.
+    Literal(String),
+    Array(Box<Schema>),
+    Object(Vec<SchemaField>),
+    Union(Vec<Schema>),
+    /// Another declared type, looked up when a value is checked.
+    Named(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaField {
+    pub key: String,
+    pub schema: Schema,
+    pub optional: bool,
+}
+
+impl Schema {
+    pub fn from_type_expr(expr: &TypeExpr) -> Self {
+        match expr {
+            TypeExpr::Name(name) => match *name {
+                "string" => Schema::String,
+                "number" | "float" => Schema::Number,
+                "int" => Schema::Int,
+                "bool" | "boolean" => Schema::Boolean,
+                "null" => Schema::Null,
+                other => Schema::Named(other.to_string()),
+            },
+            TypeExpr::Literal(text) => Schema::Literal(text.to_string()),
+            TypeExpr::Array(element) => Schema::Array(Box::new(Schema::from_type_expr(element))),
+            TypeExpr::Object(fields) => Schema::Object(
+                fields
+                    .iter()
+                    .map(|field| SchemaField {
+                        key: field.key.to_string(),
+                        schema: Schema::from_type_expr(&field.type_expr),
+                        optional: field.optional,
+                    })
+                    .collect(),
+            ),
+            TypeExpr::Union(types) => Schema::Union(types.iter().map(Schema::from_type_expr).collect()),
+        }
+    }
+}
+
+impl fmt::Display for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Schema::String => write!(f, "string"),
+            Schema::Number => write!(f, "number"),
+            Schema::Int => write!(f, "int"),
+            Schema::Boolean => write!(f, "boolean"),
+            Schema::Null => write!(f, "null"),
+            Schema::Literal(text) => write!(f, "\"{}\"", text),
+            Schema::Array(element) => write!(f, "[{}]", element),
+            Schema::Object(fields) => {
+                write!(f, "{{ ")?;
+                for (i, field) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}{}: {}", field.key, if field.optional { "?" } else { "" }, field.schema)?;
+                }
+                write!(f, " }}")
+            }
+            Schema::Union(members) => {
+                let members: Vec<String> = members.iter().map(ToString::to_string).collect();
+                write!(f, "{}", members.join(" | "))
+            }
+            Schema::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// One place a value differs from its schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Where in the value, like `$.findings[2].line`.
+    pub path: String,
+    pub expected: String,
+    /// What was there instead, or `None` for a missing field.
+    pub found: Option<String>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.found {
+            Some(found) => write!(f, "{}: expected {}, got {}", self.path, self.expected, found),
+            None => write!(f, "{}: missing field of type {}", self.path, self.expected),
+        }
+    }
+}
+
+/// The types a program declared, by name.
+#[derive(Debug, Clone, Default)]
+pub struct Schemas {
+    schemas: HashMap<String, Schema>,
+}
+
+impl Schemas {
+    pub fn define(&mut self, name: &str, schema: Schema) {
+        self.schemas.insert(name.to_string(), schema);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Schema> {
+        self.schemas.get(name)
+    }
+
+    /// Check `value` against the type called `name`, returning every
+    /// mismatch. Fails if no such type was declared.
+    pub fn check(&self, name: &str, value: &Value) -> Result<Vec<Mismatch>, String> {
+        let schema = Schema::from_type_expr(&TypeExpr::Name(name));
+        if let Schema::Named(name) = &schema {
+            if self.get(name).is_none() {
+                return Err(format!("Unknown type: {}", name));
+            }
+        }
+        let mut mismatches = Vec::new();
+        self.check_value(&schema, value, "$".to_string(), 0, &mut mismatches);
+        Ok(mismatches)
+    }
+
+    fn check_value(&self, schema: &Schema, value: &Value, path: String, depth: usize, out: &mut Vec<Mismatch>) {
+        let mut mismatch = |expected: String| out.push(Mismatch { path: path.clone(), expected, found: Some(found(value)) });
+        match (schema, value) {
+            (Schema::String, Value::String(_))
+            | (Schema::Number, Value::Number(_))
+            | (Schema::Boolean, Value::Boolean(_))
+            | (Schema::Null, Value::Null) => {}
+            (Schema::Int, Value::Number(n)) if n.fract() == 0.0 => {}
+            (Schema::Literal(text), Value::String(s)) if s == text => {}
+            (Schema::Array(element), Value::Array(items)) => {
+                for (i, item) in items.iter().enumerate() {
+                    self.check_value(element, item, format!("{}[{}]", path, i), 0, out);
+                }
+            }
+            (Schema::Object(fields), Value::Object(values)) => {
+                for field in fields {
+                    let field_path = field_path(&path, &field.key);
+                    match values.get(&field.key) {
+                        Some(value) => self.check_value(&field.schema, value, field_path, 0, out),
+                        None if field.optional => {}
+                        None => out.push(Mismatch { path: field_path, expected: field.schema.to_string(), found: None }),
+                    }
+                }
+            }
+            (Schema::Union(members), _) => {
+                // A value matching any member matches; otherwise report the
+                // union as a whole rather than every member's complaints
+                let matches = |member: &Schema| {
+                    let mut inner = Vec::new();
+                    self.check_value(member, value, path.clone(), depth, &mut inner);
+                    inner.is_empty()
+                };
+                if !members.iter().any(matches) {
+                    mismatch(schema.to_string());
+                }
+            }
+            (Schema::Named(name), _) => match self.get(name) {
+                Some(_) if depth >= MAX_ALIAS_DEPTH => mismatch(format!("{} (its definition never ends)", name)),
+                Some(named) => self.check_value(named, value, path, depth + 1, out),
+                None => mismatch(format!("{} (an undeclared type)", name)),
+            },
+            _ => mismatch(schema.to_string()),
+        }
+    }
+}
+
+/// Describe a value for a mismatch: strings by their text, since literal
+/// types compare it, and everything else by its type.
+fn found(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("\"{}\"", s),
+        Value::Number(n) if n.fract() != 0.0 => format!("number {}", n),
+        other => type_name(other).to_string(),
+    }
+}
+
+fn field_path(path: &str, key: &str) -> String {
+    let plain = key.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if plain {
+        format!("{}.{}", path, key)
+    } else {
+        format!("{}[{:?}]", path, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+
+    #[test]
+    fn test_mismatch_paths() {
+        let mut interp = Interpreter::new();
+        let result = interp.eval(
+            r#"{
+                type Finding = { file: string, line: int, severity: "low" | "high" }
+                type Report = { summary: string, findings: [Finding] }
+                var report = {
+                    findings: [
+                        { file: "a.rs", line: 3, severity: "low" },
+                        { file: "b.rs", line: "7", severity: "urgent" }
+                    ]
+                }
+                validate(report, Report)
+            }"#,
+        );
+        let Err(crate::Error::Runtime(message)) = result else {
+            panic!("expected a validation error, got {:?}", result);
+        };
+        assert_eq!(
+            message,
+            "validate(): value does not match Report:\n  \
+             $.summary: missing field of type string\n  \
+             $.findings[1].line: expected int, got \"7\"\n  \
+             $.findings[1].severity: expected \"low\" | \"high\", got \"urgent\""
+        );
+
+        let value = interp
+            .eval(r#"{
+                type Point = { x: number, y: number }
+                validate({ x: 1, y: 2.5, label: "extra fields are fine" }, Point)
+            }"#)
+            .unwrap();
+        assert!(matches!(value, Value::Object(_)));
+    }
+
+    #[test]
+    fn test_recursive_aliases() {
+        let mut schemas = Schemas::default();
+        schemas.define("A", Schema::Named("B".to_string()));
+        schemas.define("B", Schema::Named("A".to_string()));
+        let mismatches = schemas.check("A", &Value::Null).unwrap();
+        assert_eq!(mismatches.len(), 1);
+        assert!(schemas.check("Missing", &Value::Null).is_err());
+        assert!(schemas.check("int", &Value::Number(2.0)).unwrap().is_empty());
+    }
+}
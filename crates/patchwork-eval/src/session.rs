@@ -0,0 +1,731 @@
+//! Per-run session directories.
+//!
+//! A host gives each run a directory of its own, laid out as
+//!
+//! ```text
+//! <session root>/<id>/
+//!   session.json      id, timestamp, and dir, for tools inspecting it
+//!   artifacts.json    the manifest of artifacts
+//!   mailboxes/        messages between workers
+//!   artifacts/        files the run produces
+//!   logs/             logs the run writes
+//!   tmp/              scratch files from temp_file() and temp_dir()
+//! ```
+//!
+//! Programs see it as the `session` value, with `id`, `dir`, and
+//! `timestamp` (Unix seconds) fields, so they can put files there:
+//!
+//! ```text
+//! write("${session.dir}/artifacts/report.md", report)
+//! ```
+//!
+//! The root is `session.dir` in the config, or `patchwork-sessions` under
+//! the system temp directory. When the run ends, `session.keep` decides
+//! whether the directory stays: `always`, `never`, or `on-failure` (the
+//! default), which keeps it only to debug a run that didn't succeed.
+//! A session holding artifacts is kept under `on-failure` too, since they
+//! are the run's results. With `session.ttl` set, starting a session also
+//! removes sessions nothing has been written to for that long, so kept
+//! directories don't pile up while a long run that is still writing stays.
+//!
+//! A named session, from `Session::attach`, lives at `<session root>/<name>/`
+//! and outlives its runs: it is always kept, `session.ttl` leaves it alone,
+//! and hosts save the variables of an interactive session in its
+//! `state.json` when a turn ends. Attaching to the name again, from a REPL
+//! started with `--session <name>` or from the ACP proxy when the client
+//! names the session, picks up where the last turn left off, so work can
+//! move between editor and terminal. Two hosts attached at once each save
+//! their own variables; the last to save wins. `Session::resume` opens a
+//! session the same way without naming it, as the ACP proxy does for its
+//! other sessions, so `session.ttl` removes it once it is no longer used.
+//!
+//! Sessions can hold secrets, such as saved variables, so their
+//! directories are readable only by the user who made them.
+//!
+//! `temp_file(prefix)` and `temp_dir(prefix)` make fresh scratch paths in
+//! `tmp/`, so programs needn't invent their own under `/tmp`. They go when
+//! the session does, and stay with it when it is kept to debug a failure.
+//!
+//! Artifacts are outputs meant for the person who started the run, written
+//! with `artifact.write(name, content, mime)` and listed in a manifest that
+//! `artifact.list()` returns. Hosts show them when the run ends: the result
+//! JSON lists them, and the ACP proxy links to them.
+
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use indexmap::IndexMap;
+
+use crate::config::Config;
+use crate::outcome::Outcome;
+use crate::value::Value;
+
+/// The directories every session has.
+pub const SESSION_SUBDIRS: [&str; 4] = ["mailboxes", "artifacts", "logs", "tmp"];
+
+/// Name of the file describing a session, inside its directory. Only
+/// directories holding one are considered when expired sessions are removed.
+const SESSION_FILE: &str = "session.json";
+
+/// Name of the file holding a named session's variables between runs.
+const STATE_FILE: &str = "state.json";
+
+/// Name of the artifact manifest, inside the session directory.
+const MANIFEST_FILE: &str = "artifacts.json";
+
+/// Distinguishes sessions started in the same process in the same second.
+static NEXT_SESSION: AtomicU64 = AtomicU64::new(0);
+
+/// Whether a session's directory is kept when its run ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeepPolicy {
+    Always,
+    /// Keep it unless the run succeeded.
+    #[default]
+    OnFailure,
+    Never,
+}
+
+impl FromStr for KeepPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(KeepPolicy::Always),
+            "on-failure" => Ok(KeepPolicy::OnFailure),
+            "never" => Ok(KeepPolicy::Never),
+            other => Err(format!(
+                "unknown keep policy `{}` (expected always, on-failure, or never)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for KeepPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeepPolicy::Always => write!(f, "always"),
+            KeepPolicy::OnFailure => write!(f, "on-failure"),
+            KeepPolicy::Never => write!(f, "never"),
+        }
+    }
+}
+
+/// A file a run produced for its user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artifact {
+    /// Path relative to the `artifacts` directory.
+    pub name: String,
+    pub path: PathBuf,
+    pub mime: String,
+    /// Size in bytes.
+    pub size: u64,
+}
+
+impl Artifact {
+    /// The artifact as programs see it.
+    pub fn to_value(&self) -> Value {
+        Value::Object(IndexMap::from([
+            ("name".to_string(), Value::String(self.name.clone())),
+            ("path".to_string(), Value::String(self.path.display().to_string())),
+            ("mime".to_string(), Value::String(self.mime.clone())),
+            ("size".to_string(), Value::Number(self.size as f64)),
+        ]))
+    }
+
+    fn from_json(json: &serde_json::Value) -> Option<Self> {
+        Some(Self {
+            name: json["name"].as_str()?.to_string(),
+            path: PathBuf::from(json["path"].as_str()?),
+            mime: json["mime"].as_str()?.to_string(),
+            size: json["size"].as_u64()?,
+        })
+    }
+}
+
+impl fmt::Display for Artifact {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}, {} bytes): {}", self.name, self.mime, self.size, self.path.display())
+    }
+}
+
+/// A run's session directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    id: String,
+    dir: PathBuf,
+    timestamp: SystemTime,
+    keep: KeepPolicy,
+    /// Made by `attach`, so kept for later runs.
+    named: bool,
+}
+
+impl Session {
+    /// Create a new session directory under `root`.
+    pub fn create(root: &Path, keep: KeepPolicy) -> Result<Self, String> {
+        let timestamp = SystemTime::now();
+        let seconds = timestamp.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let id = format!("{}-{}-{}", seconds, std::process::id(), NEXT_SESSION.fetch_add(1, Ordering::Relaxed));
+        let dir = root.join(&id);
+        create_subdirs(&dir)?;
+        let session = Self { id, dir, timestamp, keep, named: false };
+        session.write_session_file()?;
+        Ok(session)
+    }
+
+    /// Open the session called `name` under the root `config` describes,
+    /// creating it if there isn't one yet.
+    pub fn attach(config: &Config, name: &str) -> Result<Self, String> {
+        Self::open(config, name, true)
+    }
+
+    /// Open the unnamed session `id` under the root `config` describes,
+    /// creating it if there isn't one yet. It is kept between runs like a
+    /// named session, but expires under `session.ttl` like any other, so
+    /// expired sessions are removed first.
+    pub fn resume(config: &Config, id: &str) -> Result<Self, String> {
+        if let Some(ttl) = config.session_ttl {
+            remove_expired(&config.session_root(), ttl)?;
+        }
+        Self::open(config, id, false)
+    }
+
+    fn open(config: &Config, id: &str, named: bool) -> Result<Self, String> {
+        if !is_plain_name(id) {
+            return Err(format!("Invalid session name {:?}: expected a name without `/` or `..`", id));
+        }
+        let dir = config.session_root().join(id);
+        create_subdirs(&dir)?;
+        let existing = started_at(&dir);
+        let session = Self {
+            id: id.to_string(),
+            dir,
+            timestamp: existing.unwrap_or_else(SystemTime::now),
+            keep: KeepPolicy::Always,
+            named,
+        };
+        if existing.is_none() {
+            session.write_session_file()?;
+        }
+        Ok(session)
+    }
+
+    fn write_session_file(&self) -> Result<(), String> {
+        let mut json = self.to_value().to_json_value();
+        if self.named {
+            json["named"] = serde_json::Value::Bool(true);
+        }
+        let text = serde_json::to_string_pretty(&json).unwrap_or_default();
+        let path = self.dir.join(SESSION_FILE);
+        write_private(&path, &text).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Start a session as `config` describes: remove expired sessions from
+    /// the root, then create a new one there.
+    pub fn start(config: &Config) -> Result<Self, String> {
+        let root = config.session_root();
+        if let Some(ttl) = config.session_ttl {
+            remove_expired(&root, ttl)?;
+        }
+        Self::create(&root, config.keep_session)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+
+    pub fn mailboxes_dir(&self) -> PathBuf {
+        self.dir.join("mailboxes")
+    }
+
+    pub fn artifacts_dir(&self) -> PathBuf {
+        self.dir.join("artifacts")
+    }
+
+    pub fn logs_dir(&self) -> PathBuf {
+        self.dir.join("logs")
+    }
+
+    pub fn tmp_dir(&self) -> PathBuf {
+        self.dir.join("tmp")
+    }
+
+    /// Is this a named session from `attach`?
+    pub fn is_named(&self) -> bool {
+        self.named
+    }
+
+    /// Where a session from `attach` or `resume` keeps its variables
+    /// between runs.
+    pub fn state_file(&self) -> PathBuf {
+        self.dir.join(STATE_FILE)
+    }
+
+    /// Create an empty scratch file in `tmp/` named after `prefix`,
+    /// returning its path.
+    pub fn temp_file(&self, prefix: &str) -> Result<PathBuf, String> {
+        self.create_temp(prefix, |path| fs::OpenOptions::new().write(true).create_new(true).open(path).map(drop))
+    }
+
+    /// Create an empty scratch directory in `tmp/` named after `prefix`,
+    /// returning its path.
+    pub fn temp_dir(&self, prefix: &str) -> Result<PathBuf, String> {
+        self.create_temp(prefix, |path| fs::create_dir(path))
+    }
+
+    /// Create `<prefix>-<n>` in `tmp/` for the first `n` not taken.
+    fn create_temp(&self, prefix: &str, create: impl Fn(&Path) -> std::io::Result<()>) -> Result<PathBuf, String> {
+        if !is_plain_name(prefix) {
+            return Err(format!("Invalid temp prefix {:?}: expected a name without `/` or `..`", prefix));
+        }
+        let tmp = self.tmp_dir();
+        fs::create_dir_all(&tmp).map_err(|e| format!("Failed to create {}: {}", tmp.display(), e))?;
+        let mut n = 0;
+        loop {
+            let path = tmp.join(format!("{}-{}", prefix, n));
+            match create(&path) {
+                Ok(()) => return Ok(path),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => n += 1,
+                Err(e) => return Err(format!("Failed to create {}: {}", path.display(), e)),
+            }
+        }
+    }
+
+    /// The `session` value programs see.
+    pub fn to_value(&self) -> Value {
+        let seconds = self.timestamp.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0);
+        Value::Object(IndexMap::from([
+            ("id".to_string(), Value::String(self.id.clone())),
+            ("dir".to_string(), Value::String(self.dir.display().to_string())),
+            ("timestamp".to_string(), Value::Number(seconds)),
+        ]))
+    }
+
+    /// Store `content` as the artifact `name`, replacing any artifact of
+    /// that name, and record it in the manifest. Without a `mime` type, one
+    /// is guessed from the name's extension.
+    pub fn write_artifact(&self, name: &str, content: &str, mime: Option<&str>) -> Result<Artifact, String> {
+        let relative = Path::new(name);
+        let plain = !name.is_empty() && relative.components().all(|c| matches!(c, Component::Normal(_)));
+        if !plain {
+            return Err(format!("Invalid artifact name {:?}: expected a relative path without `..`", name));
+        }
+        let path = self.artifacts_dir().join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        write_private(&path, content).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+        let artifact = Artifact {
+            name: name.to_string(),
+            path,
+            mime: mime.map_or_else(|| guess_mime(name).to_string(), str::to_string),
+            size: content.len() as u64,
+        };
+        let mut artifacts = self.artifacts()?;
+        artifacts.retain(|a| a.name != artifact.name);
+        artifacts.push(artifact.clone());
+        let manifest: Vec<serde_json::Value> = artifacts
+            .iter()
+            .map(|a| {
+                serde_json::json!({
+                    "name": a.name,
+                    "path": a.path.display().to_string(),
+                    "mime": a.mime,
+                    "size": a.size,
+                })
+            })
+            .collect();
+        let manifest_path = self.dir.join(MANIFEST_FILE);
+        let text = serde_json::to_string_pretty(&manifest).unwrap_or_default();
+        write_private(&manifest_path, &text)
+            .map_err(|e| format!("Failed to write {}: {}", manifest_path.display(), e))?;
+        Ok(artifact)
+    }
+
+    /// The artifacts stored so far, least recently written first.
+    pub fn artifacts(&self) -> Result<Vec<Artifact>, String> {
+        let path = self.dir.join(MANIFEST_FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&text).map_err(|e| format!("Invalid artifact manifest {}: {}", path.display(), e))?;
+        json.as_array()
+            .and_then(|entries| entries.iter().map(Artifact::from_json).collect())
+            .ok_or_else(|| format!("Invalid artifact manifest {}: expected a list of artifacts", path.display()))
+    }
+
+    /// End the session after a run with `outcome`, removing its directory
+    /// unless the keep policy says otherwise. Returns whether it was kept.
+    pub fn finish(self, outcome: Outcome) -> Result<bool, String> {
+        let keep = match self.keep {
+            KeepPolicy::Always => true,
+            KeepPolicy::OnFailure => outcome != Outcome::Success || !self.artifacts()?.is_empty(),
+            KeepPolicy::Never => false,
+        };
+        if !keep {
+            fs::remove_dir_all(&self.dir).map_err(|e| format!("Failed to remove {}: {}", self.dir.display(), e))?;
+        }
+        Ok(keep)
+    }
+}
+
+/// Create a session directory and its subdirectories, and any missing
+/// parents, readable only by this user.
+fn create_subdirs(dir: &Path) -> Result<(), String> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    for subdir in SESSION_SUBDIRS {
+        let path = dir.join(subdir);
+        builder.create(&path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Write `contents` to the file at `path`, creating it readable only by
+/// this user.
+pub(crate) fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents.as_bytes())
+}
+
+/// Is `name` a single path component, safe to join to a directory?
+fn is_plain_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!((components.next(), components.next()), (Some(Component::Normal(_)), None))
+}
+
+/// The MIME type for an artifact named `name`, by its extension.
+fn guess_mime(name: &str) -> &'static str {
+    let extension = Path::new(name).extension().and_then(|e| e.to_str()).unwrap_or("");
+    match extension.to_ascii_lowercase().as_str() {
+        "md" | "markdown" => "text/markdown",
+        "json" => "application/json",
+        "html" | "htm" => "text/html",
+        "csv" => "text/csv",
+        "xml" => "application/xml",
+        "yaml" | "yml" => "application/yaml",
+        "svg" => "image/svg+xml",
+        "diff" | "patch" => "text/x-diff",
+        _ => "text/plain",
+    }
+}
+
+/// Remove the sessions under `root` that have been idle for more than
+/// `ttl`, returning their directories. A missing root has none. Named
+/// sessions stay until someone removes them.
+pub fn remove_expired(root: &Path, ttl: Duration) -> Result<Vec<PathBuf>, String> {
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read {}: {}", root.display(), e)),
+    };
+    let now = SystemTime::now();
+    let mut removed = Vec::new();
+    for entry in entries.flatten() {
+        let dir = entry.path();
+        let Some(started) = started_at(&dir) else {
+            continue;
+        };
+        if is_named(&dir) {
+            continue;
+        }
+        if now.duration_since(last_active(&dir, started)).unwrap_or_default() > ttl {
+            // Another host may be removing it at the same time
+            match fs::remove_dir_all(&dir) {
+                Ok(()) => removed.push(dir),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(format!("Failed to remove {}: {}", dir.display(), e)),
+            }
+        }
+    }
+    Ok(removed)
+}
+
+/// The description of the session in `dir`, if `dir` is a session.
+fn session_file(dir: &Path) -> Option<serde_json::Value> {
+    let text = fs::read_to_string(dir.join(SESSION_FILE)).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+/// When the session in `dir` started, if `dir` is a session.
+fn started_at(dir: &Path) -> Option<SystemTime> {
+    let json = session_file(dir)?;
+    let seconds = json["timestamp"].as_f64()?;
+    UNIX_EPOCH.checked_add(Duration::try_from_secs_f64(seconds).ok()?)
+}
+
+/// When the session in `dir` was last written to: the newest modification
+/// time of anything in it, or when it started if that is later.
+fn last_active(dir: &Path, started: SystemTime) -> SystemTime {
+    let mut latest = started;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if let Ok(modified) = metadata.modified() {
+            latest = latest.max(modified);
+        }
+        if metadata.is_dir() {
+            if let Ok(entries) = fs::read_dir(&path) {
+                pending.extend(entries.flatten().map(|entry| entry.path()));
+            }
+        }
+    }
+    latest
+}
+
+/// Is the session in `dir` a named one?
+fn is_named(dir: &Path) -> bool {
+    session_file(dir).is_some_and(|json| json["named"] == serde_json::Value::Bool(true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_session_layout_and_value() {
+        let root = TempDir::new().unwrap();
+        let session = Session::create(root.path(), KeepPolicy::Always).unwrap();
+        for subdir in SESSION_SUBDIRS {
+            assert!(session.dir().join(subdir).is_dir(), "missing {}", subdir);
+        }
+        assert!(started_at(session.dir()).is_some());
+
+        let mut interp = Interpreter::new();
+        interp.set_session(session.clone());
+        let result = interp.eval("{ [session.id, session.dir] }").unwrap();
+        assert_eq!(
+            result,
+            Value::Array(vec![
+                Value::String(session.id().to_string()),
+                Value::String(session.dir().display().to_string()),
+            ])
+        );
+        // A variable named `session` shadows it
+        let result = interp.eval(r#"{ var session = "mine"
+            session }"#).unwrap();
+        assert_eq!(result, Value::String("mine".to_string()));
+    }
+
+    #[test]
+    fn test_keep_policies() {
+        let root = TempDir::new().unwrap();
+        let finish = |keep: KeepPolicy, outcome: Outcome| {
+            let session = Session::create(root.path(), keep).unwrap();
+            let dir = session.dir().to_path_buf();
+            let kept = session.finish(outcome).unwrap();
+            assert_eq!(kept, dir.exists());
+            kept
+        };
+        assert!(finish(KeepPolicy::Always, Outcome::Success));
+        assert!(!finish(KeepPolicy::Never, Outcome::Failed));
+        assert!(!finish(KeepPolicy::OnFailure, Outcome::Success));
+        assert!(finish(KeepPolicy::OnFailure, Outcome::Exception));
+    }
+
+    #[test]
+    fn test_artifacts() {
+        let root = TempDir::new().unwrap();
+        let session = Session::create(root.path(), KeepPolicy::OnFailure).unwrap();
+        let mut interp = Interpreter::new();
+        interp.set_session(session.clone());
+        let result = interp
+            .eval(
+                r##"{
+                    artifact.write("report.md", "# Done")
+                    artifact.write("data/rows.csv", "a,b", "text/plain")
+                    artifact.write("report.md", "# Done twice")
+                    artifact.list()
+                }"##,
+            )
+            .unwrap();
+        let Value::Array(listed) = result else { panic!("expected a list, got {:?}", result) };
+        assert_eq!(listed.len(), 2);
+
+        let artifacts = session.artifacts().unwrap();
+        let names: Vec<_> = artifacts.iter().map(|a| (a.name.as_str(), a.mime.as_str(), a.size)).collect();
+        assert_eq!(names, vec![("data/rows.csv", "text/plain", 3), ("report.md", "text/markdown", 12)]);
+        assert_eq!(fs::read_to_string(session.artifacts_dir().join("report.md")).unwrap(), "# Done twice");
+        assert!(session.write_artifact("../escape.txt", "", None).is_err());
+
+        // Artifacts are the run's results, so a successful run keeps them
+        let dir = session.dir().to_path_buf();
+        assert!(interp.take_session().unwrap().finish(Outcome::Success).unwrap());
+        assert!(dir.join("artifacts/report.md").exists());
+
+        let result = interp.eval(r#"{ artifact.list() }"#);
+        assert!(matches!(&result, Err(crate::Error::Runtime(msg)) if msg.contains("no session")), "{:?}", result);
+    }
+
+    #[test]
+    fn test_temp_paths() {
+        let root = TempDir::new().unwrap();
+        let session = Session::create(root.path(), KeepPolicy::OnFailure).unwrap();
+        let mut interp = Interpreter::new();
+        interp.set_session(session.clone());
+        let result = interp
+            .eval(
+                r#"{
+                    var notes = temp_file("notes")
+                    write(notes, "scratch")
+                    [notes, temp_file("notes"), temp_dir()]
+                }"#,
+            )
+            .unwrap();
+        let tmp = session.tmp_dir();
+        let path = |name: &str| Value::String(tmp.join(name).display().to_string());
+        assert_eq!(result, Value::Array(vec![path("notes-0"), path("notes-1"), path("tmp-0")]));
+        assert_eq!(fs::read_to_string(tmp.join("notes-0")).unwrap(), "scratch");
+        assert!(tmp.join("tmp-0").is_dir());
+        assert!(session.temp_file("../escape").is_err());
+
+        // Scratch files go with the session
+        let dir = session.dir().to_path_buf();
+        assert!(!interp.take_session().unwrap().finish(Outcome::Success).unwrap());
+        assert!(!dir.exists());
+    }
+
+    /// Make everything in `dir` look last written long ago.
+    fn backdate(dir: &Path) {
+        for entry in fs::read_dir(dir).unwrap().flatten() {
+            if entry.path().is_dir() {
+                backdate(&entry.path());
+            } else {
+                fs::File::open(entry.path()).unwrap().set_modified(UNIX_EPOCH + Duration::from_secs(1000)).unwrap();
+            }
+        }
+        fs::File::open(dir).unwrap().set_modified(UNIX_EPOCH + Duration::from_secs(1000)).unwrap();
+    }
+
+    #[test]
+    fn test_remove_expired() {
+        let root = TempDir::new().unwrap();
+        let old = Session::create(root.path(), KeepPolicy::Always).unwrap();
+        let json = serde_json::json!({ "id": old.id(), "timestamp": 1000.0 });
+        fs::write(old.dir().join(SESSION_FILE), json.to_string()).unwrap();
+        backdate(old.dir());
+        let recent = Session::create(root.path(), KeepPolicy::Always).unwrap();
+        // A session that started long ago but is still being written to stays
+        let running = Session::create(root.path(), KeepPolicy::Always).unwrap();
+        let json = serde_json::json!({ "id": running.id(), "timestamp": 1000.0 });
+        fs::write(running.dir().join(SESSION_FILE), json.to_string()).unwrap();
+        backdate(running.dir());
+        fs::write(running.logs_dir().join("run.log"), "still going").unwrap();
+        // Directories that aren't sessions are left alone
+        fs::create_dir(root.path().join("unrelated")).unwrap();
+        // So are sessions whose timestamp is out of range
+        let corrupt = Session::create(root.path(), KeepPolicy::Always).unwrap();
+        let json = serde_json::json!({ "id": corrupt.id(), "timestamp": 1e300 });
+        fs::write(corrupt.dir().join(SESSION_FILE), json.to_string()).unwrap();
+        assert_eq!(started_at(corrupt.dir()), None);
+
+        let removed = remove_expired(root.path(), Duration::from_secs(3600)).unwrap();
+        assert_eq!(removed, vec![old.dir().to_path_buf()]);
+        assert!(recent.dir().exists());
+        assert!(running.dir().exists());
+        assert!(corrupt.dir().exists());
+        assert!(root.path().join("unrelated").exists());
+        assert!(remove_expired(&root.path().join("missing"), Duration::ZERO).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_named_sessions() {
+        let root = TempDir::new().unwrap();
+        let config = Config { session_dir: Some(root.path().to_path_buf()), ..Config::default() };
+        let session = Session::attach(&config, "mywork").unwrap();
+        assert_eq!(session.dir(), root.path().join("mywork"));
+        assert!(Session::attach(&config, "../mywork").is_err());
+
+        let mut interp = Interpreter::new();
+        interp.set_session(session);
+        interp.eval_interactive("var issue = 42").unwrap();
+        interp.save_session().unwrap();
+        assert!(interp.take_session().unwrap().finish(Outcome::Success).unwrap());
+
+        // Old as it is, the named session outlives expiry
+        let removed = remove_expired(root.path(), Duration::ZERO).unwrap();
+        assert!(removed.is_empty());
+
+        let mut later = Interpreter::new();
+        later.set_session(Session::attach(&config, "mywork").unwrap());
+        assert!(later.resume_session().unwrap());
+        assert_eq!(later.eval_interactive("issue + 1").unwrap(), Value::Number(43.0));
+
+        let mut fresh = Interpreter::new();
+        fresh.set_session(Session::attach(&config, "other").unwrap());
+        assert!(!fresh.resume_session().unwrap());
+    }
+
+    #[test]
+    fn test_resumed_sessions() {
+        let root = TempDir::new().unwrap();
+        let config = Config { session_dir: Some(root.path().to_path_buf()), ..Config::default() };
+        let session = Session::resume(&config, "acp-1").unwrap();
+        assert!(!session.is_named());
+
+        let mut interp = Interpreter::new();
+        interp.set_session(session);
+        interp.eval_interactive("var token = \"secret\"").unwrap();
+        interp.save_session().unwrap();
+        assert!(interp.take_session().unwrap().finish(Outcome::Success).unwrap());
+
+        let mut later = Interpreter::new();
+        later.set_session(Session::resume(&config, "acp-1").unwrap());
+        assert!(later.resume_session().unwrap());
+        assert_eq!(later.eval_interactive("token").unwrap(), Value::String("secret".to_string()));
+
+        // Unlike a named session, it expires once nothing uses it
+        backdate(&root.path().join("acp-1"));
+        let removed = remove_expired(root.path(), Duration::ZERO).unwrap();
+        assert_eq!(removed, vec![root.path().join("acp-1")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_sessions_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let root = TempDir::new().unwrap();
+        let session = Session::create(root.path(), KeepPolicy::Always).unwrap();
+        assert_eq!(mode(session.dir()), 0o700);
+        assert_eq!(mode(&session.tmp_dir()), 0o700);
+        assert_eq!(mode(&session.dir().join(SESSION_FILE)), 0o600);
+        let artifact = session.write_artifact("report.md", "# Report", None).unwrap();
+        assert_eq!(mode(&artifact.path), 0o600);
+        assert_eq!(mode(&session.dir().join(MANIFEST_FILE)), 0o600);
+
+        let mut interp = Interpreter::new();
+        interp.set_session(session);
+        interp.eval_interactive("var token = \"secret\"").unwrap();
+        interp.save_session().unwrap();
+        assert_eq!(mode(&interp.take_session().unwrap().state_file()), 0o600);
+    }
+}
@@ -0,0 +1,103 @@
+//! Limits on how many runs of a skill are in progress at once.
+//!
+//! A skill annotated `@concurrency(n)` runs at most `n` times at once across
+//! every interpreter in the process, such as the sessions an ACP proxy
+//! serves. A run past the limit waits for one of the others to finish,
+//! unless its program is cancelled or reaches its deadline first.
+
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::time::Duration;
+
+/// How often a waiting run checks whether its program was cancelled.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Runs in progress, by skill.
+#[derive(Debug, Default)]
+pub(crate) struct SkillSlots {
+    running: Mutex<HashMap<String, usize>>,
+    freed: Condvar,
+}
+
+/// A run's slot, given back when dropped.
+#[derive(Debug)]
+pub(crate) struct Slot<'a> {
+    slots: &'a SkillSlots,
+    skill: String,
+}
+
+impl SkillSlots {
+    /// The slots shared by every interpreter in the process.
+    pub(crate) fn global() -> &'static SkillSlots {
+        static SLOTS: OnceLock<SkillSlots> = OnceLock::new();
+        SLOTS.get_or_init(SkillSlots::default)
+    }
+
+    /// Wait until fewer than `limit` runs of `skill` are in progress, then
+    /// take a slot. `check` is called while waiting, and an error from it,
+    /// such as the program being cancelled, stops the wait.
+    pub(crate) fn acquire(
+        &self,
+        skill: &str,
+        limit: usize,
+        check: impl Fn() -> Result<(), String>,
+    ) -> Result<Slot<'_>, String> {
+        let mut running = self.running.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            let count = running.entry(skill.to_string()).or_default();
+            if *count < limit {
+                *count += 1;
+                return Ok(Slot { slots: self, skill: skill.to_string() });
+            }
+            check()?;
+            running = self.freed.wait_timeout(running, POLL_INTERVAL).unwrap_or_else(|e| e.into_inner()).0;
+        }
+    }
+
+    /// Runs of `skill` in progress.
+    #[cfg(test)]
+    fn running(&self, skill: &str) -> usize {
+        self.running.lock().unwrap().get(skill).copied().unwrap_or(0)
+    }
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        let mut running = self.slots.running.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = running.get_mut(&self.skill) {
+            *count -= 1;
+            if *count == 0 {
+                running.remove(&self.skill);
+            }
+        }
+        self.slots.freed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slots_wait_for_a_free_run() {
+        let slots = SkillSlots::default();
+        let first = slots.acquire("deploy", 1, || Ok(())).unwrap();
+        assert_eq!(slots.running("deploy"), 1);
+
+        // A second run waits, so a check that gives up stops it
+        let err = slots.acquire("deploy", 1, || Err("Cancelled".to_string())).unwrap_err();
+        assert_eq!(err, "Cancelled");
+        // Other skills have slots of their own
+        let other = slots.acquire("report", 1, || Ok(())).unwrap();
+
+        std::thread::scope(|scope| {
+            let waiting = scope.spawn(|| slots.acquire("deploy", 1, || Ok(())).map(|_| ()));
+            std::thread::sleep(Duration::from_millis(20));
+            drop(first);
+            waiting.join().unwrap().unwrap();
+        });
+        drop(other);
+        assert_eq!(slots.running("deploy"), 0);
+        assert_eq!(slots.running("report"), 0);
+    }
+}
@@ -0,0 +1,170 @@
+//! Disk-backed storage for very large variable values.
+//!
+//! Programs that read whole repositories into variables can hold hundreds
+//! of megabytes per session. When `limits.spill_threshold_bytes` is set,
+//! the runtime moves any string or array bound to a variable that is at
+//! least that large into a file under the system temp directory, and reads
+//! it back each time the variable is used. Programs see the same value
+//! either way.
+//!
+//! Spill files are removed when their variable goes out of scope, or is
+//! reassigned, and the directory holding them when the runtime is dropped.
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde_json::{json, Value as JsonValue};
+
+use crate::value::Value;
+
+/// Distinguishes the spill directories of runtimes in the same process.
+static NEXT_STORE: AtomicU64 = AtomicU64::new(0);
+
+/// Where a runtime writes spilled values. The directory is created on the
+/// first spill.
+#[derive(Debug, Default)]
+pub(crate) struct SpillStore {
+    dir: Option<PathBuf>,
+    next_file: u64,
+}
+
+impl SpillStore {
+    /// Write `value` to a new spill file.
+    pub(crate) fn spill(&mut self, value: &Value) -> std::io::Result<SpillFile> {
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let dir = std::env::temp_dir().join(format!(
+                    "patchwork-spill-{}-{}",
+                    std::process::id(),
+                    NEXT_STORE.fetch_add(1, Ordering::Relaxed)
+                ));
+                fs::create_dir_all(&dir)?;
+                self.dir.insert(dir).clone()
+            }
+        };
+        let path = dir.join(format!("{}.json", self.next_file));
+        self.next_file += 1;
+        fs::write(&path, encode(value).to_string())?;
+        Ok(SpillFile { path, size: value.approx_size() })
+    }
+}
+
+impl Drop for SpillStore {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// A value stored on disk. The file is deleted when this is dropped.
+#[derive(Debug)]
+pub(crate) struct SpillFile {
+    path: PathBuf,
+    /// Approximate in-memory size of the value, as `Value::approx_size`.
+    size: usize,
+}
+
+impl SpillFile {
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Read the value back.
+    pub(crate) fn load(&self) -> Result<Value, String> {
+        let text = fs::read_to_string(&self.path)
+            .map_err(|e| format!("Failed to read spilled value {}: {}", self.path.display(), e))?;
+        let json: JsonValue = serde_json::from_str(&text)
+            .map_err(|e| format!("Corrupt spilled value {}: {}", self.path.display(), e))?;
+        Ok(decode(json))
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Encode a value as JSON without losing sets, tuples, or non-finite
+/// numbers, which plain JSON cannot represent. Those become single-key
+/// objects, and objects themselves are wrapped so the two cannot collide.
+fn encode(value: &Value) -> JsonValue {
+    match value {
+        Value::Null => JsonValue::Null,
+        Value::Boolean(b) => JsonValue::Bool(*b),
+        Value::Number(n) => match serde_json::Number::from_f64(*n) {
+            Some(n) => JsonValue::Number(n),
+            None => json!({ "number": n.to_string() }),
+        },
+        Value::String(s) => JsonValue::String(s.clone()),
+        Value::Array(items) => JsonValue::Array(items.iter().map(encode).collect()),
+        Value::Set(items) => json!({ "set": items.iter().map(encode).collect::<Vec<_>>() }),
+        Value::Tuple(items) => json!({ "tuple": items.iter().map(encode).collect::<Vec<_>>() }),
+        Value::Object(map) => {
+            let map: serde_json::Map<String, JsonValue> =
+                map.iter().map(|(k, v)| (k.clone(), encode(v))).collect();
+            json!({ "object": map })
+        }
+    }
+}
+
+fn decode(json: JsonValue) -> Value {
+    match json {
+        JsonValue::Null => Value::Null,
+        JsonValue::Bool(b) => Value::Boolean(b),
+        JsonValue::Number(n) => Value::Number(n.as_f64().unwrap_or(0.0)),
+        JsonValue::String(s) => Value::String(s),
+        JsonValue::Array(items) => Value::Array(items.into_iter().map(decode).collect()),
+        JsonValue::Object(map) => {
+            let Some((tag, inner)) = map.into_iter().next() else {
+                return Value::Null;
+            };
+            let items = |inner: JsonValue| match inner {
+                JsonValue::Array(items) => items.into_iter().map(decode).collect(),
+                _ => Vec::new(),
+            };
+            match (tag.as_str(), inner) {
+                ("number", JsonValue::String(n)) => Value::Number(n.parse().unwrap_or(f64::NAN)),
+                ("set", inner) => Value::Set(items(inner)),
+                ("tuple", inner) => Value::Tuple(items(inner)),
+                ("object", JsonValue::Object(map)) => {
+                    Value::Object(map.into_iter().map(|(k, v)| (k, decode(v))).collect())
+                }
+                _ => Value::Null,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indexmap::IndexMap;
+
+    #[test]
+    fn test_spilled_values_round_trip() {
+        let mut object = IndexMap::new();
+        object.insert("set".to_string(), Value::set([Value::Number(1.0), Value::Number(2.0)]));
+        object.insert("inf".to_string(), Value::Number(f64::INFINITY));
+        let value = Value::Array(vec![
+            Value::String("line".repeat(100)),
+            Value::Tuple(vec![Value::Null, Value::Boolean(true)]),
+            Value::Object(object),
+        ]);
+
+        let mut store = SpillStore::default();
+        let file = store.spill(&value).unwrap();
+        assert_eq!(file.load().unwrap(), value);
+        assert_eq!(file.size(), value.approx_size());
+
+        let path = file.path.clone();
+        drop(file);
+        assert!(!path.exists());
+        let dir = store.dir.clone().unwrap();
+        drop(store);
+        assert!(!dir.exists());
+    }
+}
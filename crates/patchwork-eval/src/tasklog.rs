@@ -0,0 +1,138 @@
+//! Print output from tasks that run at the same time.
+//!
+//! A host that runs several interpreters at once, such as a fan-out of
+//! workers, gives each one a print sink from `TaskLogs::sink`. Each task's
+//! output is captured on its own and forwarded to the host's sink with the
+//! task's name in front of every line:
+//!
+//! ```text
+//! [triage-1] fetched 12 issues
+//! [triage-2] fetched 9 issues
+//! [triage-1] labelled 12 issues
+//! ```
+//!
+//! `Interleaving::Lines` forwards lines as they are printed, so tasks take
+//! turns line by line. `Interleaving::Grouped` holds a task's lines until it
+//! finishes, when the interpreter holding its sink is dropped, and forwards
+//! them as one block.
+
+use std::collections::BTreeMap;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use crate::runtime::PrintSink;
+
+/// How lines from different tasks are mixed in the host's output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Interleaving {
+    /// Forward each line as soon as it is printed.
+    #[default]
+    Lines,
+    /// Forward each task's output together once the task finishes.
+    Grouped,
+}
+
+/// Captures and prefixes the print output of concurrent tasks.
+pub struct TaskLogs {
+    out: PrintSink,
+    interleaving: Interleaving,
+    /// Every message each task printed, by task name.
+    captured: Arc<Mutex<BTreeMap<String, Vec<String>>>>,
+    forwarders: Vec<JoinHandle<()>>,
+}
+
+impl TaskLogs {
+    pub fn new(out: PrintSink, interleaving: Interleaving) -> Self {
+        Self { out, interleaving, captured: Arc::default(), forwarders: Vec::new() }
+    }
+
+    /// A print sink for the task called `name`, to pass to its
+    /// interpreter's `set_print_sink`. Tasks sharing a name share a capture.
+    pub fn sink(&mut self, name: &str) -> PrintSink {
+        let (tx, rx) = mpsc::channel::<String>();
+        let out = self.out.clone();
+        let interleaving = self.interleaving;
+        let captured = self.captured.clone();
+        let name = name.to_string();
+        captured.lock().unwrap().entry(name.clone()).or_default();
+
+        self.forwarders.push(std::thread::spawn(move || {
+            let mut held = Vec::new();
+            // Runs until every clone of the task's sink is dropped
+            for message in rx {
+                for line in message.split('\n') {
+                    let line = format!("[{}] {}", name, line);
+                    match interleaving {
+                        Interleaving::Lines => {
+                            let _ = out.send(line);
+                        }
+                        Interleaving::Grouped => held.push(line),
+                    }
+                }
+                captured.lock().unwrap().entry(name.clone()).or_default().push(message);
+            }
+            // One message, so no other task's lines land in the middle
+            if !held.is_empty() {
+                let _ = out.send(held.join("\n"));
+            }
+        }));
+        tx
+    }
+
+    /// Wait for every task to finish and its output to be forwarded, and
+    /// return what each task printed, without prefixes, by name.
+    ///
+    /// A task is finished once every clone of its sink is dropped, so drop
+    /// the interpreters first.
+    pub fn finish(self) -> BTreeMap<String, Vec<String>> {
+        for forwarder in self.forwarders {
+            let _ = forwarder.join();
+        }
+        std::mem::take(&mut *self.captured.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Interpreter;
+
+    fn run_tasks(interleaving: Interleaving) -> (Vec<String>, BTreeMap<String, Vec<String>>) {
+        let (out_tx, out_rx) = mpsc::channel();
+        let mut logs = TaskLogs::new(out_tx, interleaving);
+        let workers: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|name| {
+                let sink = logs.sink(name);
+                std::thread::spawn(move || {
+                    let mut interp = Interpreter::new();
+                    interp.set_print_sink(sink);
+                    interp.eval(&format!("{{ print(\"{} one\")\n print(\"{} two\\nthree\") }}", name, name)).unwrap();
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        let captured = logs.finish();
+        (out_rx.try_iter().collect(), captured)
+    }
+
+    #[test]
+    fn test_prefixed_lines() {
+        let (out, captured) = run_tasks(Interleaving::Lines);
+        assert_eq!(out.len(), 6);
+        let a: Vec<&str> = out.iter().filter(|line| line.starts_with("[a] ")).map(String::as_str).collect();
+        assert_eq!(a, vec!["[a] a one", "[a] a two", "[a] three"]);
+        assert_eq!(captured["b"], vec!["b one".to_string(), "b two\nthree".to_string()]);
+    }
+
+    #[test]
+    fn test_grouped_output() {
+        let (out, _) = run_tasks(Interleaving::Grouped);
+        let mut out = out;
+        out.sort();
+        assert_eq!(out, vec!["[a] a one\n[a] a two\n[a] three", "[b] b one\n[b] b two\n[b] three"]);
+    }
+}
@@ -0,0 +1,325 @@
+//! Telemetry: what runs did, for an organization's own observability stack.
+//!
+//! The runtime reports four kinds of event to a `TelemetrySink`:
+//!
+//! - `llm_call`: a think or ask block was answered, with its model, token
+//!   usage, and latency
+//! - `shell_exec`: a shell command ran, with its exit status and duration
+//! - `error`: a run ended in an error
+//! - `run_summary`: a run finished, with its outcome and totals
+//!
+//! Hosts choose a built-in sink with the `telemetry` setting, or install
+//! their own with `Interpreter::set_telemetry_sink`:
+//!
+//! ```ignore
+//! #[derive(Debug)]
+//! struct Metrics;
+//!
+//! impl TelemetrySink for Metrics {
+//!     fn record(&self, event: &TelemetryEvent) {
+//!         statsd::increment(event.name());
+//!     }
+//! }
+//!
+//! interp.set_telemetry_sink(Arc::new(Metrics));
+//! ```
+//!
+//! A config whose `telemetry` is `off` leaves a host's own sink in place.
+//!
+//! The built-in sinks record what happened, not what was said: prompts,
+//! answers, and a run's value are left out. A sink must not fail the
+//! program, so one that can't deliver an event drops it.
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::json;
+
+use crate::agent::Usage;
+use crate::config::Telemetry;
+use crate::outcome::{Outcome, RunResult};
+use crate::runtime::CallMeta;
+
+/// Something the runtime did.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TelemetryEvent {
+    /// A think or ask block was answered.
+    LlmCall {
+        model: Option<String>,
+        usage: Usage,
+        latency: Duration,
+        stop_reason: Option<String>,
+        /// The prompt variant the block used, if it declared any.
+        variant: Option<String>,
+    },
+    /// A shell command ran to completion.
+    ShellExec {
+        /// The command line, as it would be typed.
+        command: String,
+        /// Its exit status, or None if it was killed by a signal.
+        status: Option<i32>,
+        duration: Duration,
+    },
+    /// A run ended in an error.
+    Error { outcome: Outcome, message: String },
+    /// A run finished.
+    RunSummary(RunResult),
+}
+
+impl TelemetryEvent {
+    /// The event's kind: `llm_call`, `shell_exec`, `error`, or `run_summary`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TelemetryEvent::LlmCall { .. } => "llm_call",
+            TelemetryEvent::ShellExec { .. } => "shell_exec",
+            TelemetryEvent::Error { .. } => "error",
+            TelemetryEvent::RunSummary(_) => "run_summary",
+        }
+    }
+
+    /// An event reporting `meta`.
+    pub fn llm_call(meta: &CallMeta) -> Self {
+        TelemetryEvent::LlmCall {
+            model: meta.model.clone(),
+            usage: meta.usage,
+            latency: meta.latency,
+            stop_reason: meta.stop_reason.clone(),
+            variant: meta.variant.clone(),
+        }
+    }
+
+    /// The event as a flat JSON object, with its kind under `event`:
+    ///
+    /// ```json
+    /// {"event": "shell_exec", "command": "git status", "status": 0, "duration_ms": 12}
+    /// ```
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            TelemetryEvent::LlmCall { model, usage, latency, stop_reason, variant } => json!({
+                "event": self.name(),
+                "model": model,
+                "input_tokens": usage.input_tokens,
+                "output_tokens": usage.output_tokens,
+                "cost_usd": usage.cost_usd,
+                "latency_ms": latency.as_millis() as u64,
+                "stop_reason": stop_reason,
+                "variant": variant,
+            }),
+            TelemetryEvent::ShellExec { command, status, duration } => json!({
+                "event": self.name(),
+                "command": command,
+                "status": status,
+                "duration_ms": duration.as_millis() as u64,
+            }),
+            TelemetryEvent::Error { outcome, message } => json!({
+                "event": self.name(),
+                "outcome": outcome.to_string(),
+                "message": message,
+            }),
+            TelemetryEvent::RunSummary(run) => json!({
+                "event": self.name(),
+                "outcome": run.outcome.to_string(),
+                "exit_code": run.exit_code(),
+                "llm_calls": run.llm_calls,
+                "input_tokens": run.usage.input_tokens,
+                "output_tokens": run.usage.output_tokens,
+                "cost_usd": run.usage.cost_usd,
+                "duration_ms": run.duration.as_millis() as u64,
+                "warnings": run.warnings.len(),
+                "artifacts": run.artifacts.len(),
+            }),
+        }
+    }
+}
+
+/// Where the runtime reports what it did.
+///
+/// `record` is called on the thread running the program, so a sink that
+/// does slow work should hand it off.
+pub trait TelemetrySink: fmt::Debug + Send + Sync {
+    fn record(&self, event: &TelemetryEvent);
+}
+
+/// The sink a `telemetry` setting names.
+pub fn sink_for(telemetry: &Telemetry) -> Result<Arc<dyn TelemetrySink>, String> {
+    Ok(match telemetry {
+        Telemetry::Off => Arc::new(NoopSink),
+        Telemetry::JsonFile(path) => Arc::new(JsonFileSink::open(path)?),
+        Telemetry::Otlp(endpoint) => Arc::new(OtlpSink::new(endpoint)),
+    })
+}
+
+/// Reports nothing; the runtime's sink until a host sets one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopSink;
+
+impl TelemetrySink for NoopSink {
+    fn record(&self, _event: &TelemetryEvent) {}
+}
+
+/// Appends each event to a file as a line of JSON, with its time in
+/// milliseconds since the epoch under `time_ms`.
+#[derive(Debug)]
+pub struct JsonFileSink {
+    file: Mutex<File>,
+}
+
+impl JsonFileSink {
+    /// Append to the file at `path`, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("can't open telemetry file {}: {}", path.display(), e))?;
+        Ok(Self { file: Mutex::new(file) })
+    }
+}
+
+impl TelemetrySink for JsonFileSink {
+    fn record(&self, event: &TelemetryEvent) {
+        let mut line = event.to_json();
+        line["time_ms"] = json!((unix_nanos(SystemTime::now()) / 1_000_000) as u64);
+        // One write per line, so concurrent runs don't interleave
+        let _ = self.file.lock().unwrap().write_all(format!("{}\n", line).as_bytes());
+    }
+}
+
+/// Sends each event to an OpenTelemetry collector as a log record, over
+/// OTLP/HTTP with JSON encoding. The record's body is the event's kind and
+/// its attributes are the event's fields. Posted with `curl`, waiting at
+/// most five seconds.
+#[derive(Debug, Clone)]
+pub struct OtlpSink {
+    /// The collector's logs URL, ending in `/v1/logs`.
+    url: String,
+}
+
+impl OtlpSink {
+    /// A sink for the collector at `endpoint`, such as
+    /// `http://localhost:4318`.
+    pub fn new(endpoint: &str) -> Self {
+        let endpoint = endpoint.trim_end_matches('/');
+        let url = if endpoint.ends_with("/v1/logs") { endpoint.to_string() } else { format!("{}/v1/logs", endpoint) };
+        Self { url }
+    }
+
+    /// The OTLP export request carrying `event`.
+    fn request(&self, event: &TelemetryEvent, time: SystemTime) -> serde_json::Value {
+        let fields = event.to_json();
+        let attributes: Vec<serde_json::Value> = fields
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(key, value)| key.as_str() != "event" && !value.is_null())
+            .map(|(key, value)| json!({ "key": format!("patchwork.{}", key), "value": any_value(value) }))
+            .collect();
+        let severity = match event {
+            TelemetryEvent::Error { .. } => ("ERROR", 17),
+            _ => ("INFO", 9),
+        };
+        json!({
+            "resourceLogs": [{
+                "resource": {
+                    "attributes": [{ "key": "service.name", "value": { "stringValue": "patchwork" } }]
+                },
+                "scopeLogs": [{
+                    "scope": { "name": "patchwork", "version": env!("CARGO_PKG_VERSION") },
+                    "logRecords": [{
+                        // 64-bit integers are strings in OTLP's JSON encoding
+                        "timeUnixNano": unix_nanos(time).to_string(),
+                        "severityText": severity.0,
+                        "severityNumber": severity.1,
+                        "body": { "stringValue": event.name() },
+                        "attributes": attributes,
+                    }]
+                }]
+            }]
+        })
+    }
+}
+
+impl TelemetrySink for OtlpSink {
+    fn record(&self, event: &TelemetryEvent) {
+        let body = self.request(event, SystemTime::now()).to_string();
+        let child = Command::new("curl")
+            .args(["-sS", "-f", "--max-time", "5", "-X", "POST", "-H", "Content-Type: application/json"])
+            .args(["--data-binary", "@-", &self.url])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        if let Ok(mut child) = child {
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = stdin.write_all(body.as_bytes());
+            }
+            let _ = child.wait();
+        }
+    }
+}
+
+/// A JSON value as an OTLP `AnyValue`.
+fn any_value(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Bool(b) => json!({ "boolValue": b }),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => json!({ "intValue": i.to_string() }),
+            None => json!({ "doubleValue": n.as_f64() }),
+        },
+        serde_json::Value::String(s) => json!({ "stringValue": s }),
+        other => json!({ "stringValue": other.to_string() }),
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell_exec() -> TelemetryEvent {
+        let command = "git status".to_string();
+        TelemetryEvent::ShellExec { command, status: Some(0), duration: Duration::from_millis(12) }
+    }
+
+    #[test]
+    fn test_json_file_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.jsonl");
+        let sink = sink_for(&Telemetry::JsonFile(path.clone())).unwrap();
+        sink.record(&shell_exec());
+        sink.record(&TelemetryEvent::Error { outcome: Outcome::Failed, message: "Undefined variable: x".to_string() });
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["event"], "shell_exec");
+        assert_eq!(lines[0]["command"], "git status");
+        assert_eq!(lines[0]["duration_ms"], 12);
+        assert!(lines[0]["time_ms"].as_u64().is_some());
+        assert_eq!(lines[1]["event"], "error");
+        assert_eq!(lines[1]["outcome"], "failed");
+    }
+
+    #[test]
+    fn test_otlp_request() {
+        let sink = OtlpSink::new("http://localhost:4318/");
+        assert_eq!(sink.url, "http://localhost:4318/v1/logs");
+        let request = sink.request(&shell_exec(), UNIX_EPOCH + Duration::from_secs(2));
+        let record = &request["resourceLogs"][0]["scopeLogs"][0]["logRecords"][0];
+        assert_eq!(record["timeUnixNano"], "2000000000");
+        assert_eq!(record["severityText"], "INFO");
+        assert_eq!(record["body"]["stringValue"], "shell_exec");
+        assert!(record["attributes"]
+            .as_array()
+            .unwrap()
+            .contains(&json!({ "key": "patchwork.status", "value": { "intValue": "0" } })));
+    }
+}
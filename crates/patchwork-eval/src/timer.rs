@@ -0,0 +1,237 @@
+//! Timers and cancellation for the Patchwork interpreter.
+//!
+//! `sleep(duration)` and `schedule_at(time, expr)` block the interpreter
+//! thread, but never uninterruptibly: every wait goes through a
+//! `CancellationToken`, so a host that cancels the run (for example when the
+//! user presses stop in the editor) wakes the sleeping program immediately.
+//!
+//! Durations are seconds as a number, or a string with a unit suffix:
+//! `"500ms"`, `"30s"`, `"5m"`, `"2h"`. Times are Unix timestamps in seconds,
+//! or RFC 3339 strings like `"2025-06-01T09:30:00Z"`.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::value::Value;
+
+/// A flag a host sets to stop a running program.
+///
+/// Clones share the same flag. Cancelling wakes every thread blocked in
+/// `wait_timeout`.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<TokenState>,
+}
+
+#[derive(Debug, Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    lock: Mutex<()>,
+    wakeup: Condvar,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the program, waking any pending sleeps.
+    pub fn cancel(&self) {
+        let _guard = self.inner.lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.wakeup.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Block for `duration`, returning early if cancelled.
+    ///
+    /// Returns `true` if the wait was cut short by cancellation.
+    pub fn wait_timeout(&self, duration: Duration) -> bool {
+        // A wait too long to have a deadline only ends when cancelled
+        let deadline = std::time::Instant::now().checked_add(duration);
+        let mut guard = self.inner.lock.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            if self.is_cancelled() {
+                return true;
+            }
+            // Condvars wake spuriously, so loop until the deadline or a cancel
+            guard = match deadline {
+                Some(deadline) => {
+                    let now = std::time::Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    self.inner
+                        .wakeup
+                        .wait_timeout(guard, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+                None => self.inner.wakeup.wait(guard).unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+    }
+}
+
+/// Read a duration argument: seconds, or a string like `"30s"`.
+pub fn parse_duration(value: &Value) -> Result<Duration, String> {
+    let seconds = match value {
+        Value::Number(n) => *n,
+        Value::String(s) => {
+            let s = s.trim();
+            let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+            let (number, unit) = s.split_at(split);
+            let n: f64 = number
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid duration: {:?}", s))?;
+            match unit {
+                "ms" => n / 1000.0,
+                "" | "s" => n,
+                "m" => n * 60.0,
+                "h" => n * 3600.0,
+                other => return Err(format!("Unknown duration unit {:?} (expected ms, s, m, or h)", other)),
+            }
+        }
+        _ => return Err("Duration must be a number of seconds or a string like \"30s\"".to_string()),
+    };
+    if !seconds.is_finite() || seconds < 0.0 {
+        return Err(format!("Invalid duration: {}", value.to_string_value()));
+    }
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("Duration is too long: {}", value.to_string_value()))
+}
+
+/// Read a point in time: Unix seconds, or an RFC 3339 timestamp.
+pub fn parse_time(value: &Value) -> Result<SystemTime, String> {
+    let seconds = match value {
+        Value::Number(n) if n.is_finite() => *n,
+        Value::String(s) => parse_rfc3339(s).ok_or_else(|| format!("Invalid timestamp: {:?}", s))?,
+        _ => return Err("Time must be Unix seconds or an RFC 3339 timestamp".to_string()),
+    };
+    if seconds < 0.0 {
+        return Err("Times before 1970 are not supported".to_string());
+    }
+    Duration::try_from_secs_f64(seconds)
+        .ok()
+        .and_then(|since_epoch| UNIX_EPOCH.checked_add(since_epoch))
+        .ok_or_else(|| format!("Timestamp is out of range: {}", value.to_string_value()))
+}
+
+/// The current time as Unix seconds.
+pub fn now_seconds() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// Parse `YYYY-MM-DDTHH:MM:SS[.frac](Z|±HH:MM)` into Unix seconds.
+fn parse_rfc3339(s: &str) -> Option<f64> {
+    let (date, time) = s.split_once(['T', 't', ' '])?;
+
+    let mut date_parts = date.splitn(3, '-');
+    let year: i64 = date_parts.next()?.parse().ok()?;
+    let month: u32 = date_parts.next()?.parse().ok()?;
+    let day: u32 = date_parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Split the UTC offset off the end of the time
+    let (clock, offset_seconds) = if let Some(clock) = time.strip_suffix(['Z', 'z']) {
+        (clock, 0)
+    } else {
+        let sign_at = time.rfind(['+', '-'])?;
+        let (clock, offset) = time.split_at(sign_at);
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let (hours, minutes) = offset[1..].split_once(':')?;
+        let offset: i64 = hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60;
+        (clock, sign * offset)
+    };
+
+    let mut clock_parts = clock.splitn(3, ':');
+    let hour: u32 = clock_parts.next()?.parse().ok()?;
+    let minute: u32 = clock_parts.next()?.parse().ok()?;
+    let second: f64 = clock_parts.next()?.parse().ok()?;
+    if hour > 23 || minute > 59 || !(0.0..61.0).contains(&second) {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    let seconds = days * 86400 + i64::from(hour) * 3600 + i64::from(minute) * 60 - offset_seconds;
+    Some(seconds as f64 + second)
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // Howard Hinnant's algorithm, with years starting in March
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration(&Value::Number(1.5)), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration(&Value::String("250ms".into())), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration(&Value::String("30s".into())), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration(&Value::String("2m".into())), Ok(Duration::from_secs(120)));
+        assert!(parse_duration(&Value::String("2 weeks".into())).is_err());
+        assert!(parse_duration(&Value::Number(-1.0)).is_err());
+        assert_eq!(
+            parse_duration(&Value::Number(1e29)),
+            Err("Duration is too long: 100000000000000000000000000000".to_string())
+        );
+        assert!(parse_duration(&Value::String("1e300h".into())).is_err());
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let at = |s: &str| {
+            parse_time(&Value::String(s.to_string()))
+                .map(|t| t.duration_since(UNIX_EPOCH).unwrap().as_secs_f64())
+        };
+        assert_eq!(at("1970-01-01T00:00:00Z"), Ok(0.0));
+        assert_eq!(at("2000-03-01T00:00:00Z"), Ok(951868800.0));
+        assert_eq!(at("2024-02-29T12:30:00.5Z"), Ok(1709209800.5));
+        assert_eq!(at("2024-02-29T14:30:00+02:00"), Ok(1709209800.0));
+        assert!(at("next tuesday").is_err());
+        assert!(at("2024-13-01T00:00:00Z").is_err());
+        assert!(parse_time(&Value::Number(1e19)).is_err());
+        assert!(parse_time(&Value::Number(1e300)).is_err());
+    }
+
+    #[test]
+    fn test_cancel_wakes_sleeper() {
+        let token = CancellationToken::new();
+        let sleeper = {
+            let token = token.clone();
+            std::thread::spawn(move || token.wait_timeout(Duration::from_secs(60)))
+        };
+        token.cancel();
+        assert!(sleeper.join().unwrap());
+        assert!(token.wait_timeout(Duration::from_secs(60)));
+        assert!(!CancellationToken::new().wait_timeout(Duration::from_millis(1)));
+
+        // A wait past the end of the clock still ends on cancel
+        let token = CancellationToken::new();
+        let sleeper = {
+            let token = token.clone();
+            std::thread::spawn(move || token.wait_timeout(Duration::MAX))
+        };
+        token.cancel();
+        assert!(sleeper.join().unwrap());
+    }
+}
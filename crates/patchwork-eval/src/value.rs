@@ -1,14 +1,20 @@
 //! Runtime values for the Patchwork interpreter.
+//!
+//! Objects keep their fields in insertion order, and `Value::total_cmp`
+//! orders any two values, so the same program prints, sorts, and renders
+//! prompts the same way on every run and platform.
 
-use std::collections::HashMap;
+use std::cmp::Ordering;
 use std::fmt;
 
+use indexmap::IndexMap;
 use serde_json::Value as JsonValue;
 
 /// A runtime value in the Patchwork language.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Default)]
 pub enum Value {
     /// The null value.
+    #[default]
     Null,
     /// A string value.
     String(String),
@@ -18,11 +24,26 @@ pub enum Value {
     Boolean(bool),
     /// An array of values.
     Array(Vec<Value>),
-    /// An object with string keys.
-    Object(HashMap<String, Value>),
+    /// An object with string keys, in the order they were added.
+    Object(IndexMap<String, Value>),
+    /// Distinct values, kept in insertion order. Build with `Value::set`.
+    Set(Vec<Value>),
+    /// A fixed group of values, such as several results returned together.
+    Tuple(Vec<Value>),
 }
 
 impl Value {
+    /// Build a set, dropping repeated items.
+    pub fn set(items: impl IntoIterator<Item = Value>) -> Value {
+        let mut distinct: Vec<Value> = Vec::new();
+        for item in items {
+            if !distinct.contains(&item) {
+                distinct.push(item);
+            }
+        }
+        Value::Set(distinct)
+    }
+
     /// Coerce this value to a string.
     pub fn to_string_value(&self) -> String {
         match self {
@@ -37,14 +58,18 @@ impl Value {
                     // Integer-like numbers without decimal point
                     format!("{}", *n as i64)
                 } else {
-                    format!("{}", n)
+                    format_float(*n)
                 }
             }
             Value::Boolean(b) => if *b { "true" } else { "false" }.to_string(),
-            Value::Array(arr) => {
+            Value::Array(arr) | Value::Set(arr) => {
                 let items: Vec<String> = arr.iter().map(|v| v.to_string_value()).collect();
                 items.join(", ")
             }
+            Value::Tuple(items) => {
+                let items: Vec<String> = items.iter().map(|v| v.to_string_value()).collect();
+                format!("({})", items.join(", "))
+            }
             Value::Object(_) => "[object Object]".to_string(),
         }
     }
@@ -56,8 +81,8 @@ impl Value {
             Value::String(s) => !s.is_empty(),
             Value::Number(n) => *n != 0.0 && !n.is_nan(),
             Value::Boolean(b) => *b,
-            Value::Array(arr) => !arr.is_empty(),
-            Value::Object(_) => true,
+            Value::Array(arr) | Value::Set(arr) => !arr.is_empty(),
+            Value::Object(_) | Value::Tuple(_) => true,
         }
     }
 
@@ -66,6 +91,19 @@ impl Value {
         matches!(self, Value::Null)
     }
 
+    /// Approximately how many bytes this value occupies in memory.
+    pub fn approx_size(&self) -> usize {
+        let heap = match self {
+            Value::Null | Value::Number(_) | Value::Boolean(_) => 0,
+            Value::String(s) => s.capacity(),
+            Value::Array(items) | Value::Set(items) | Value::Tuple(items) => {
+                items.iter().map(Value::approx_size).sum()
+            }
+            Value::Object(map) => map.iter().map(|(k, v)| k.capacity() + v.approx_size()).sum(),
+        };
+        std::mem::size_of::<Value>() + heap
+    }
+
     /// Parse a JSON string into a Value.
     pub fn from_json(s: &str) -> Result<Value, String> {
         let json: JsonValue = serde_json::from_str(s)
@@ -74,7 +112,7 @@ impl Value {
     }
 
     /// Convert a serde_json Value to our Value type.
-    fn from_json_value(json: JsonValue) -> Value {
+    pub(crate) fn from_json_value(json: JsonValue) -> Value {
         match json {
             JsonValue::Null => Value::Null,
             JsonValue::Bool(b) => Value::Boolean(b),
@@ -99,7 +137,7 @@ impl Value {
     }
 
     /// Convert this Value to a serde_json Value.
-    fn to_json_value(&self) -> JsonValue {
+    pub(crate) fn to_json_value(&self) -> JsonValue {
         match self {
             Value::Null => JsonValue::Null,
             Value::Boolean(b) => JsonValue::Bool(*b),
@@ -109,7 +147,8 @@ impl Value {
                     .unwrap_or(JsonValue::Null)
             }
             Value::String(s) => JsonValue::String(s.clone()),
-            Value::Array(arr) => {
+            // JSON has no sets or tuples, so they become arrays
+            Value::Array(arr) | Value::Set(arr) | Value::Tuple(arr) => {
                 JsonValue::Array(arr.iter().map(|v| v.to_json_value()).collect())
             }
             Value::Object(obj) => {
@@ -122,14 +161,139 @@ impl Value {
     }
 }
 
+/// Print a float with at most 15 significant digits, so binary rounding
+/// noise like `0.1 + 0.2 = 0.30000000000000004` prints as `0.3`.
+fn format_float(n: f64) -> String {
+    let magnitude = n.abs().log10().floor() as i32;
+    if !(-6..15).contains(&magnitude) {
+        return format!("{}", n);
+    }
+    let decimals = (14 - magnitude).max(0) as usize;
+    let text = format!("{:.*}", decimals, n);
+    let text = if text.contains('.') { text.trim_end_matches('0').trim_end_matches('.') } else { &text };
+    if text == "-0" { "0".to_string() } else { text.to_string() }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Array(a), Value::Array(b)) | (Value::Tuple(a), Value::Tuple(b)) => a == b,
+            (Value::Object(a), Value::Object(b)) => a == b,
+            // Sets are equal when they hold the same items, in any order
+            (Value::Set(a), Value::Set(b)) => a.len() == b.len() && a.iter().all(|item| b.contains(item)),
+            _ => false,
+        }
+    }
+}
+
+impl Value {
+    /// A total order on values, independent of locale and platform.
+    ///
+    /// Values of different types order by type: null, booleans, numbers,
+    /// strings, arrays, tuples, sets, then objects. Numbers order
+    /// numerically, with NaN after every other number; strings by code
+    /// point; arrays and tuples item by item. Sets compare their items in
+    /// sorted order and objects their fields sorted by key, so like
+    /// equality, neither depends on insertion order.
+    pub fn total_cmp(&self, other: &Value) -> Ordering {
+        fn rank(value: &Value) -> u8 {
+            match value {
+                Value::Null => 0,
+                Value::Boolean(_) => 1,
+                Value::Number(_) => 2,
+                Value::String(_) => 3,
+                Value::Array(_) => 4,
+                Value::Tuple(_) => 5,
+                Value::Set(_) => 6,
+                Value::Object(_) => 7,
+            }
+        }
+        fn items(a: &[Value], b: &[Value]) -> Ordering {
+            a.iter().zip(b).map(|(x, y)| x.total_cmp(y)).find(|o| o.is_ne()).unwrap_or(a.len().cmp(&b.len()))
+        }
+        fn sorted(items: &[Value]) -> Vec<Value> {
+            let mut items = items.to_vec();
+            items.sort_by(Value::total_cmp);
+            items
+        }
+        match (self, other) {
+            (Value::Boolean(a), Value::Boolean(b)) => a.cmp(b),
+            // 0 and -0 are equal; NaN is past infinity
+            (Value::Number(a), Value::Number(b)) => {
+                a.partial_cmp(b).unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+            }
+            (Value::String(a), Value::String(b)) => a.cmp(b),
+            (Value::Array(a), Value::Array(b)) | (Value::Tuple(a), Value::Tuple(b)) => items(a, b),
+            (Value::Set(a), Value::Set(b)) => items(&sorted(a), &sorted(b)),
+            (Value::Object(a), Value::Object(b)) => {
+                let mut a: Vec<_> = a.iter().collect();
+                let mut b: Vec<_> = b.iter().collect();
+                a.sort_by(|x, y| x.0.cmp(y.0));
+                b.sort_by(|x, y| x.0.cmp(y.0));
+                a.iter()
+                    .zip(&b)
+                    .map(|((ka, va), (kb, vb))| ka.cmp(kb).then_with(|| va.total_cmp(vb)))
+                    .find(|o| o.is_ne())
+                    .unwrap_or(a.len().cmp(&b.len()))
+            }
+            (a, b) => rank(a).cmp(&rank(b)),
+        }
+    }
+}
+
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.to_string_value())
     }
 }
 
-impl Default for Value {
-    fn default() -> Self {
-        Value::Null
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_float_display_hides_rounding_noise() {
+        let show = |n: f64| Value::Number(n).to_string_value();
+        assert_eq!(show(0.1 + 0.2), "0.3");
+        assert_eq!(show(2.5), "2.5");
+        assert_eq!(show(-1.0 / 3.0), "-0.333333333333333");
+        assert_eq!(show(1234.5678), "1234.5678");
+        assert_eq!(show(1e-7), "0.0000001");
+        assert_eq!(show(42.0), "42");
+    }
+
+    #[test]
+    fn test_total_order() {
+        let object = |fields: &[(&str, f64)]| {
+            Value::Object(fields.iter().map(|(k, v)| (k.to_string(), Value::Number(*v))).collect())
+        };
+        let mut values = vec![
+            Value::String("b".to_string()),
+            Value::Number(f64::NAN),
+            object(&[("a", 2.0)]),
+            Value::Number(-1.0),
+            Value::Null,
+            Value::Array(vec![Value::Number(1.0), Value::Number(2.0)]),
+            Value::String("B".to_string()),
+            Value::Boolean(true),
+            Value::Array(vec![Value::Number(1.0)]),
+            Value::Number(3.0),
+        ];
+        values.sort_by(Value::total_cmp);
+        let shown: Vec<String> = values.iter().map(|v| v.to_json_value().to_string()).collect();
+        assert_eq!(shown, ["null", "true", "-1.0", "3.0", "null", "\"B\"", "\"b\"", "[1.0]", "[1.0,2.0]", "{\"a\":2.0}"]);
+        assert!(values[4].total_cmp(&Value::Number(f64::INFINITY)).is_gt());
+        assert!(Value::Number(0.0).total_cmp(&Value::Number(-0.0)).is_eq());
+
+        // Field order doesn't matter for ordering or equality, but is kept
+        let ab = object(&[("a", 1.0), ("b", 2.0)]);
+        let ba = object(&[("b", 2.0), ("a", 1.0)]);
+        assert!(ab.total_cmp(&ba).is_eq());
+        assert_eq!(ab, ba);
+        assert_eq!(ba.to_json_value().to_string(), r#"{"b":2.0,"a":1.0}"#);
     }
 }
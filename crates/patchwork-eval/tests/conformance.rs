@@ -0,0 +1,146 @@
+//! Interpreter conformance suite.
+//!
+//! Every `tests/cases/<name>.pw` file at the repository root is run through
+//! the interpreter with no agent attached, so think blocks return their
+//! interpolated prompt instead of calling an LLM. Its sidecar
+//! `<name>.expected.json` says what should happen; every key is optional:
+//!
+//! ```json
+//! {
+//!   "description": "what the case covers",
+//!   "result": 6,
+//!   "output": ["printed line", "another"],
+//!   "error": { "kind": "runtime", "message": "Undefined variable" }
+//! }
+//! ```
+//!
+//! `result` is compared as a Patchwork value, `output` against the lines
+//! passed to `print()`, and `error` against the failure: `kind` is one of
+//! `parse`, `runtime`, `exception`, or `budget`, and `message` must appear in the
+//! error text. A case without `error` must succeed.
+//!
+//! Cases run with `tests/cases` as the working directory, so they can read
+//! fixture files next to them.
+//!
+//! Set `PATCHWORK_COVERAGE` to a directory to also write statement coverage
+//! of the cases there, as `lcov.info` and `coverage.html`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+
+use patchwork_eval::{CoverageReport, Error, Interpreter, Value};
+
+fn cases_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../../tests/cases")
+}
+
+/// Run one case, returning a description of every mismatch.
+fn run_case(
+    source: &Path,
+    expected: &serde_json::Value,
+    coverage: Option<&mut CoverageReport>,
+) -> Vec<String> {
+    let code = fs::read_to_string(source).unwrap();
+    let (print_tx, print_rx) = mpsc::channel();
+    let mut interp = Interpreter::with_working_dir(cases_dir());
+    interp.set_print_sink(print_tx);
+    if coverage.is_some() {
+        interp.enable_coverage();
+        interp.set_source_name(format!("tests/cases/{}", source.file_name().unwrap().to_string_lossy()));
+    }
+
+    let outcome = interp.eval(&code);
+    if let (Some(report), Some(case)) = (coverage, interp.coverage()) {
+        report.merge(case);
+    }
+    drop(interp);
+    let output: Vec<String> = print_rx.try_iter().collect();
+
+    let mut failures = Vec::new();
+    match (&outcome, expected.get("error")) {
+        (Ok(_), Some(error)) => failures.push(format!("expected error {}, but it succeeded", error)),
+        (Err(e), None) => failures.push(format!("unexpected error: {}", e)),
+        (Err(e), Some(error)) => {
+            let kind = match e {
+                Error::Parse(_) => "parse",
+                Error::Runtime(_) => "runtime",
+                Error::Exception(_) => "exception",
+                Error::BudgetExceeded(_) => "budget",
+            };
+            if let Some(expected_kind) = error.get("kind").and_then(|k| k.as_str()) {
+                if kind != expected_kind {
+                    failures.push(format!("expected a {} error, got {}: {}", expected_kind, kind, e));
+                }
+            }
+            if let Some(message) = error.get("message").and_then(|m| m.as_str()) {
+                if !e.to_string().contains(message) {
+                    failures.push(format!("expected error containing {:?}, got: {}", message, e));
+                }
+            }
+        }
+        (Ok(_), None) => {}
+    }
+
+    if let (Ok(value), Some(result)) = (&outcome, expected.get("result")) {
+        let expected_value = Value::from_json(&result.to_string()).unwrap();
+        if *value != expected_value {
+            failures.push(format!("expected result {}, got {}", expected_value.to_json(), value.to_json()));
+        }
+    }
+
+    if let Some(lines) = expected.get("output") {
+        let lines: Vec<String> = serde_json::from_value(lines.clone())
+            .expect("`output` should be an array of strings");
+        if output != lines {
+            failures.push(format!("expected output {:?}, got {:?}", lines, output));
+        }
+    }
+
+    failures
+}
+
+#[test]
+fn conformance_cases() {
+    let mut sources: Vec<PathBuf> = fs::read_dir(cases_dir())
+        .expect("tests/cases should exist")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pw"))
+        .collect();
+    sources.sort();
+    assert!(!sources.is_empty(), "no conformance cases found");
+
+    let coverage_dir = std::env::var_os("PATCHWORK_COVERAGE").map(PathBuf::from);
+    let mut coverage = CoverageReport::default();
+
+    let mut failures = Vec::new();
+    for source in &sources {
+        let sidecar = source.with_extension("expected.json");
+        let expected: serde_json::Value = match fs::read_to_string(&sidecar) {
+            Ok(text) => serde_json::from_str(&text)
+                .unwrap_or_else(|e| panic!("{}: {}", sidecar.display(), e)),
+            Err(_) => {
+                failures.push(format!("{}: missing {}", source.display(), sidecar.display()));
+                continue;
+            }
+        };
+        let name = source.file_stem().unwrap().to_string_lossy();
+        for failure in run_case(source, &expected, coverage_dir.as_ref().map(|_| &mut coverage)) {
+            failures.push(format!("{}: {}", name, failure));
+        }
+    }
+
+    if let Some(dir) = &coverage_dir {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("lcov.info"), coverage.to_lcov()).unwrap();
+        fs::write(dir.join("coverage.html"), coverage.to_html()).unwrap();
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} conformance failures in {} cases:\n{}",
+        failures.len(),
+        sources.len(),
+        failures.join("\n")
+    );
+}
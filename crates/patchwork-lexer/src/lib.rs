@@ -6,93 +6,11 @@ mod lexer {
     include!(concat!(env!("OUT_DIR"), "/lexer.rs"));
 }
 
+mod modes;
+
 // Re-export the main types
 pub use lexer::{Mode, Rule, LexData};
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum DelimiterType {
-    Brace,  // Waiting for }
-    Paren,  // Waiting for )
-}
-
-/// Context for tracking lexer state transitions
-#[derive(Debug, Clone)]
-pub struct LexerContext {
-    /// Stack of mode states for handling nesting
-    mode_stack: Vec<Mode>,
-    /// Stack of brace depths for each nested context
-    depth_stack: Vec<usize>,
-    /// Stack of delimiter types (what are we waiting for to close this context)
-    delimiter_stack: Vec<DelimiterType>,
-    /// Last token seen (for lookahead)
-    last_token: Option<Rule>,
-    /// Track if we just saw a Dollar in InString mode (for interpolation)
-    in_string_interpolation: bool,
-    /// Track if we just saw a Dollar in Prompt mode (for interpolation)
-    in_prompt_interpolation: bool,
-    /// Track if we just saw a Dollar in Shell mode (for interpolation)
-    in_shell_interpolation: bool,
-    /// Track if we're in shell mode (for command parsing)
-    in_shell_mode: bool,
-    /// Track if we should return to Shell mode after yielding current token
-    return_to_shell: bool,
-}
-
-impl LexerContext {
-    fn new() -> Self {
-        Self {
-            mode_stack: vec![],
-            depth_stack: vec![],
-            delimiter_stack: vec![],
-            last_token: None,
-            in_string_interpolation: false,
-            in_prompt_interpolation: false,
-            in_shell_interpolation: false,
-            in_shell_mode: false,
-            return_to_shell: false,
-        }
-    }
-
-    fn push_mode(&mut self, mode: Mode, delimiter: DelimiterType) {
-        self.mode_stack.push(mode);
-        self.depth_stack.push(1);
-        self.delimiter_stack.push(delimiter);
-    }
-
-    fn pop_mode(&mut self) -> Option<Mode> {
-        self.depth_stack.pop();
-        self.delimiter_stack.pop();
-        self.mode_stack.pop()
-    }
-
-    #[allow(dead_code)]
-    fn current_depth(&self) -> usize {
-        self.depth_stack.last().copied().unwrap_or(0)
-    }
-
-    fn increment_depth(&mut self) {
-        if let Some(depth) = self.depth_stack.last_mut() {
-            *depth += 1;
-        }
-    }
-
-    fn decrement_depth(&mut self) -> usize {
-        if let Some(depth) = self.depth_stack.last_mut() {
-            if *depth > 0 {
-                *depth -= 1;
-            }
-            *depth
-        } else {
-            0
-        }
-    }
-}
-
-impl Default for LexerContext {
-    fn default() -> Self {
-        Self::new()
-    }
-}
+pub use modes::{LexerContext, ModeMachine};
 
 /// Token produced by the patchwork lexer
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -143,341 +61,12 @@ where
         context: &mut Self::Context,
         rule: <Self::LexerData as LexerData>::LexerRule,
     ) -> Result<(), ParlexError> {
-        // Handle state transitions BEFORE yielding token
-        // This ensures the mode is set correctly before the next token is read
-        match rule {
-            Rule::StringStart => {
-                // Entering a string - transition to InString mode
-                let span = lexer.span();
-                let token = PatchworkToken::new(rule, Some(span));
-                lexer.yield_token(token);
-
-                context.push_mode(Mode::InString, DelimiterType::Brace);  // Waiting for StringEnd "
-                lexer.begin(Mode::InString);
-                context.last_token = None;
-                return Ok(());
-            }
-            Rule::StringEnd => {
-                // Exiting a string - pop back to previous mode
-                let span = lexer.span();
-                let token = PatchworkToken::new(rule, Some(span));
-                lexer.yield_token(token);
-
-                if let Some(_) = context.pop_mode() {
-                    // Return to the mode before the string
-                    if let Some(&parent_mode) = context.mode_stack.last() {
-                        lexer.begin(parent_mode);
-                    } else {
-                        // Back to Code mode
-                        lexer.begin(Mode::Code);
-                    }
-                }
-                context.last_token = None;
-                context.in_string_interpolation = false;
-                return Ok(());
-            }
-            Rule::Dollar if context.last_token == Some(Rule::LParen) && lexer.mode() == Mode::Code => {
-                // ($ pattern - enter Shell mode for shell expression
-                let span = lexer.span();
-                let token = PatchworkToken::new(rule, Some(span));
-                lexer.yield_token(token);
-
-                // Enter Shell mode - will exit on matching )
-                context.push_mode(Mode::Shell, DelimiterType::Paren);
-                lexer.begin(Mode::Shell);
-                context.in_shell_mode = true;
-                context.last_token = None;
-                return Ok(());
-            }
-            Rule::Dollar => {
-                // When we see $ in InString or Prompt mode, we need to check what follows
-                // If it's { or (, we'll handle that in LBrace/LParen
-                // If it's an identifier, we temporarily switch to Code mode
-                // In Code mode, $ followed by whitespace enters Shell mode
-                // In Shell mode, $ followed by identifier stays in Shell (identifier is now active in Shell)
-                let span = lexer.span();
-                let token = PatchworkToken::new(rule, Some(span));
-                lexer.yield_token(token);
-
-                // Mark that we're in interpolation mode - next token should be in Code mode
-                match lexer.mode() {
-                    Mode::InString => {
-                        context.in_string_interpolation = true;
-                        lexer.begin(Mode::Code);
-                    }
-                    Mode::Prompt => {
-                        context.in_prompt_interpolation = true;
-                        lexer.begin(Mode::Code);
-                    }
-                    Mode::Shell => {
-                        // $ in Shell mode for variable interpolation
-                        // Stay in Shell mode - the grammar will handle dollar shell_arg
-                    }
-                    Mode::Code => {
-                        // $ in Code mode might start shell mode
-                        // We'll check next token (Whitespace or LParen) to decide
-                    }
-                }
-                context.last_token = Some(rule);
-                return Ok(());
-            }
-            Rule::Whitespace if context.last_token == Some(Rule::Dollar) && lexer.mode() == Mode::Code => {
-                // $ followed by whitespace in Code mode → enter Shell mode
-                let span = lexer.span();
-                let token = PatchworkToken::new(rule, Some(span));
-                lexer.yield_token(token);
-
-                context.push_mode(Mode::Shell, DelimiterType::Brace);  // Will exit on newline
-                lexer.begin(Mode::Shell);
-                context.in_shell_mode = true;
-                context.last_token = None;
-                return Ok(());
-            }
-            Rule::Identifier if context.in_string_interpolation && context.last_token == Some(Rule::Dollar) => {
-                // We're tokenizing an identifier directly after $ in a string (simple $id case)
-                // This is NOT ${...}, so return to InString mode after identifier
-                let span = lexer.span();
-                let token = PatchworkToken::new(rule, Some(span));
-                lexer.yield_token(token);
-
-                // Return to InString mode
-                context.in_string_interpolation = false;
-                lexer.begin(Mode::InString);
-                context.last_token = None;
-                return Ok(());
-            }
-            Rule::Identifier if context.in_prompt_interpolation && context.last_token == Some(Rule::Dollar) => {
-                // We're tokenizing an identifier directly after $ in a prompt (simple $id case)
-                // This is NOT ${...}, so return to Prompt mode after identifier
-                let span = lexer.span();
-                let token = PatchworkToken::new(rule, Some(span));
-                lexer.yield_token(token);
-
-                // Return to Prompt mode
-                context.in_prompt_interpolation = false;
-                lexer.begin(Mode::Prompt);
-                context.last_token = None;
-                return Ok(());
-            }
-            Rule::Think | Rule::Ask => {
-                // When we see think/ask, record it. On next LBrace, transition to Prompt
-                context.last_token = Some(rule);
-            }
-            Rule::Do => {
-                // When we see do in Prompt state, record it. On next LBrace, transition to Code
-                context.last_token = Some(rule);
-            }
-            Rule::LBrace => {
-                // First yield the token
-                let span = lexer.span();
-                let token = PatchworkToken::new(rule, Some(span));
-                lexer.yield_token(token);
-
-                // Then check if this follows a context operator and transition states
-                match context.last_token {
-                    Some(Rule::Think) | Some(Rule::Ask) => {
-                        // Transition Code -> Prompt
-                        context.push_mode(Mode::Prompt, DelimiterType::Brace);
-                        lexer.begin(Mode::Prompt);
-                    }
-                    Some(Rule::Do) if lexer.mode() == Mode::Prompt => {
-                        // Transition Prompt -> Code
-                        context.push_mode(Mode::Code, DelimiterType::Brace);
-                        lexer.begin(Mode::Code);
-                    }
-                    Some(Rule::Dollar) if context.in_string_interpolation => {
-                        // ${expression} in string - stay in Code mode and track depth
-                        context.push_mode(Mode::Code, DelimiterType::Brace);
-                        // Stay in Code mode (already there from Dollar handling)
-                    }
-                    Some(Rule::Dollar) if context.in_prompt_interpolation => {
-                        // ${expression} in prompt - stay in Code mode and track depth
-                        context.push_mode(Mode::Code, DelimiterType::Brace);
-                        // Stay in Code mode (already there from Dollar handling)
-                    }
-                    Some(Rule::Dollar) if lexer.mode() == Mode::Shell || context.in_shell_mode => {
-                        // ${expression} in shell mode - switch to Code mode temporarily
-                        context.in_shell_interpolation = true;
-                        context.push_mode(Mode::Code, DelimiterType::Brace);
-                        lexer.begin(Mode::Code);
-                    }
-                    _ => {
-                        // Just increment depth for nested braces
-                        context.increment_depth();
-                    }
-                }
-                context.last_token = None;
-                return Ok(());
-            }
-            Rule::LParen if context.last_token == Some(Rule::Dollar) => {
-                // $(...) - behavior depends on current mode
-                let span = lexer.span();
-                let token = PatchworkToken::new(rule, Some(span));
-                lexer.yield_token(token);
-
-                // In Code mode OR Prompt mode: $(command) enters Shell mode for command substitution
-                // In InString mode: $(expr) stays in Code mode for expression (to support nested expressions)
-                if !context.in_string_interpolation || context.in_prompt_interpolation {
-                    // Code/Prompt mode: enter Shell mode for command substitution
-                    context.push_mode(Mode::Shell, DelimiterType::Paren);
-                    lexer.begin(Mode::Shell);
-                    context.in_shell_mode = true;
-                } else {
-                    // InString mode only: stay in Code mode for nested expressions like "${func($(cmd))}"
-                    context.push_mode(Mode::Code, DelimiterType::Paren);
-                    // Already in Code mode from Dollar handling
-                }
-                context.last_token = None;
-                return Ok(());
-            }
-            Rule::LParen if lexer.mode() == Mode::Code => {
-                // Track LParen to detect ($ pattern
-                context.last_token = Some(rule);
-            }
-            Rule::RParen if context.in_shell_mode && context.delimiter_stack.last() == Some(&DelimiterType::Paren) => {
-                // ) in shell mode with Paren delimiter → exit shell mode
-                let span = lexer.span();
-                let token = PatchworkToken::new(rule, Some(span));
-                lexer.yield_token(token);
-
-                let depth = context.decrement_depth();
-                if depth == 0 {
-                    if let Some(_) = context.pop_mode() {
-                        context.in_shell_mode = false;
-                        // Return to parent mode
-                        if let Some(&parent_mode) = context.mode_stack.last() {
-                            lexer.begin(parent_mode);
-                        } else {
-                            // Back to Code mode
-                            if context.in_string_interpolation {
-                                context.in_string_interpolation = false;
-                                lexer.begin(Mode::InString);
-                            } else if context.in_prompt_interpolation {
-                                context.in_prompt_interpolation = false;
-                                lexer.begin(Mode::Prompt);
-                            } else {
-                                lexer.begin(Mode::Code);
-                            }
-                        }
-                    }
-                }
-                context.last_token = None;
-                return Ok(());
-            }
-            Rule::RParen if context.in_string_interpolation || context.in_prompt_interpolation => {
-                let span = lexer.span();
-                let token = PatchworkToken::new(rule, Some(span));
-                lexer.yield_token(token);
-
-                // Only handle closing of $(command) - check if top of delimiter stack is Paren
-                if context.delimiter_stack.last() == Some(&DelimiterType::Paren) {
-                    let depth = context.decrement_depth();
-                    if depth == 0 {
-                        if let Some(_) = context.pop_mode() {
-                            // Check if we're still in a nested interpolation context
-                            if let Some(&parent_mode) = context.mode_stack.last() {
-                                // Still nested - return to parent mode (could be Code from ${...})
-                                lexer.begin(parent_mode);
-                            } else {
-                                // No more nesting - return to original mode (InString or Prompt)
-                                if context.in_string_interpolation {
-                                    context.in_string_interpolation = false;
-                                    lexer.begin(Mode::InString);
-                                } else if context.in_prompt_interpolation {
-                                    context.in_prompt_interpolation = false;
-                                    lexer.begin(Mode::Prompt);
-                                }
-                            }
-                        }
-                    }
-                }
-                // Otherwise this is just a normal RParen in an expression like ${func(...)}
-                context.last_token = None;
-                return Ok(());
-            }
-            Rule::RBrace => {
-                // First yield the token
-                let span = lexer.span();
-                let token = PatchworkToken::new(rule, Some(span));
-                lexer.yield_token(token);
-
-                // Then decrement depth and potentially pop mode
-                let depth = context.decrement_depth();
-                if depth == 0 {
-                    // Pop back to previous mode
-                    if let Some(_prev_mode) = context.pop_mode() {
-                        // If we had a mode on stack, we need to return to the mode before that
-                        if let Some(&parent_mode) = context.mode_stack.last() {
-                            // Returning to a parent mode after closing interpolation/block
-                            // Clear the interpolation flag if we're done with interpolation
-                            if parent_mode == Mode::InString && context.in_string_interpolation {
-                                // Finished ${...} or $(...) in string, back to parent string
-                                context.in_string_interpolation = false;
-                            } else if parent_mode == Mode::Prompt && context.in_prompt_interpolation {
-                                // Finished ${...} or $(...) in prompt, back to parent prompt
-                                context.in_prompt_interpolation = false;
-                            } else if parent_mode == Mode::Shell && context.in_shell_interpolation {
-                                // Finished ${...} in shell, back to parent shell
-                                context.in_shell_interpolation = false;
-                            }
-                            lexer.begin(parent_mode);
-                        } else {
-                            // Back to Code, InString, Prompt, or Shell mode
-                            if context.in_string_interpolation {
-                                // Closing ${...} - return to InString
-                                context.in_string_interpolation = false;
-                                lexer.begin(Mode::InString);
-                            } else if context.in_prompt_interpolation {
-                                // Closing ${...} - return to Prompt
-                                context.in_prompt_interpolation = false;
-                                lexer.begin(Mode::Prompt);
-                            } else if context.in_shell_interpolation {
-                                // Closing ${...} - return to Shell
-                                context.in_shell_interpolation = false;
-                                lexer.begin(Mode::Shell);
-                            } else {
-                                lexer.begin(Mode::Code);
-                            }
-                        }
-                    }
-                }
-                context.last_token = None;
-                return Ok(());
-            }
-            Rule::Newline if context.in_shell_mode => {
-                // Newline in shell mode → exit shell mode (unless backslash-escaped)
-                let span = lexer.span();
-                let token = PatchworkToken::new(rule, Some(span));
-                lexer.yield_token(token);
-
-                // Check if last token was backslash (line continuation)
-                if context.last_token != Some(Rule::ShellBackslash) {
-                    // Not escaped - exit shell mode
-                    if let Some(_) = context.pop_mode() {
-                        if let Some(&parent_mode) = context.mode_stack.last() {
-                            lexer.begin(parent_mode);
-                        } else {
-                            lexer.begin(Mode::Code);
-                        }
-                    }
-                    context.in_shell_mode = false;
-                }
-                context.last_token = None;
-                return Ok(());
-            }
-            Rule::Whitespace | Rule::Newline => {
-                // Keep last token for whitespace - don't clear it
-            }
-            _ => {
-                // Clear last token for any other token
-                context.last_token = None;
-            }
-        }
-
-        // Yield token for all other rules
         let span = lexer.span();
-        let token = PatchworkToken::new(rule, Some(span));
-        lexer.yield_token(token);
+        lexer.yield_token(PatchworkToken::new(rule, Some(span)));
+        // Switch modes now, before the next token is read
+        if let Some(mode) = context.advance(lexer.mode(), rule) {
+            lexer.begin(mode);
+        }
         Ok(())
     }
 }
@@ -613,13 +202,13 @@ mod tests {
 
     #[test]
     fn test_numbers() -> Result<(), ParlexError> {
-        // Note: Float literals tokenize as Number Dot Number - parser will handle this
-        let tokens = collect_tokens("123 456 0 42")?;
+        let tokens = collect_tokens("123 456 0 42 0.25")?;
 
         assert_eq!(tokens, vec![
             Rule::Number, Rule::Whitespace,
             Rule::Number, Rule::Whitespace,
             Rule::Number, Rule::Whitespace,
+            Rule::Number, Rule::Whitespace,
             Rule::Number,
             Rule::End
         ]);
@@ -840,6 +429,29 @@ var session_id = "historian-${timestamp}""#;
         Ok(())
     }
 
+    #[test]
+    fn test_approve_block() -> Result<(), ParlexError> {
+        let input = "approve { Delete $n branches }";
+        let tokens = collect_tokens(input)?;
+
+        assert_eq!(tokens, vec![
+            Rule::Approve,
+            Rule::Whitespace,
+            Rule::LBrace,
+            Rule::Whitespace,
+            Rule::PromptText,  // "Delete"
+            Rule::Whitespace,
+            Rule::Dollar,
+            Rule::Identifier,  // "n"
+            Rule::Whitespace,
+            Rule::PromptText,  // "branches"
+            Rule::Whitespace,
+            Rule::RBrace,
+            Rule::End
+        ]);
+        Ok(())
+    }
+
     #[test]
     fn test_simple_ask_block() -> Result<(), ParlexError> {
         let input = "ask { What should I do? }";
@@ -1463,7 +1075,7 @@ var session_id = "historian-${timestamp}""#;
         assert!(tokens.contains(&Rule::RBrace));
         assert!(tokens.contains(&Rule::LParen));
         assert!(tokens.contains(&Rule::RParen));
-        assert!(tokens.contains(&Rule::Plus) == false);  // count not used in expression here
+        assert!(!tokens.contains(&Rule::Plus));  // count not used in expression here
         assert!(tokens.contains(&Rule::End));
         Ok(())
     }
@@ -1550,4 +1162,25 @@ var session_id = "historian-${timestamp}""#;
         assert!(tokens.contains(&Rule::Newline));
         Ok(())
     }
+
+    #[test]
+    fn test_mode_machine_follows_lexer() -> Result<(), ParlexError> {
+        // Replaying the lexer's tokens should put each one in the mode
+        // that lexes it
+        let tokens = collect_tokens("var x = think { Say ${name} } + \"a${b}c\"\n$ echo hi\n")?;
+        let mut modes = ModeMachine::new();
+        let mut seen = Vec::new();
+        for rule in tokens {
+            seen.push((modes.mode(), rule));
+            modes.advance(rule);
+        }
+        assert!(seen.contains(&(Mode::Prompt, Rule::PromptText)));
+        assert!(seen.contains(&(Mode::Code, Rule::Identifier)));
+        assert!(seen.contains(&(Mode::InString, Rule::StringText)));
+        assert!(seen.contains(&(Mode::Shell, Rule::ShellArg)));
+        assert!(seen.iter().all(|(mode, rule)| *rule != Rule::PromptText || *mode == Mode::Prompt));
+        assert_eq!(modes.mode(), Mode::Code);
+        assert_eq!(modes.context().nesting(), 0);
+        Ok(())
+    }
 }
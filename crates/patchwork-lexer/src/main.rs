@@ -76,28 +76,15 @@ fn main() {
     }
 }
 
-/// Convert line/column position to byte offset
+/// Convert line/column position to byte offset (columns count bytes)
 fn position_to_offset(input: &str, line: usize, column: usize) -> usize {
-    let mut current_line = 0;
-    let mut offset = 0;
-
-    for (i, ch) in input.char_indices() {
-        if current_line == line {
-            let mut col = 0;
-            for (j, _) in input[offset..].char_indices() {
-                if col == column {
-                    return offset + j;
-                }
-                col += 1;
-            }
-            return offset + input[offset..].len();
-        }
-
-        if ch == '\n' {
-            current_line += 1;
-            offset = i + 1;
+    let line_start = if line == 0 {
+        0
+    } else {
+        match input.match_indices('\n').nth(line - 1) {
+            Some((i, _)) => i + 1,
+            None => return input.len(),
         }
-    }
-
-    offset
+    };
+    (line_start + column).min(input.len())
 }
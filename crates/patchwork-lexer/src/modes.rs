@@ -0,0 +1,443 @@
+//! The lexer's mode state machine.
+//!
+//! Patchwork is lexed in four modes: `Code`, `Prompt` (the body of a
+//! `think`, `ask`, or `approve` block), `Shell` (a `$ command` line or a
+//! `$(...)` substitution), and `InString`. Which mode applies to the next
+//! token depends only on the tokens before it, so the transitions live here,
+//! apart from the generated lexer, where other tokenizers can reuse them.
+//! A tree-sitter scanner, a syntax highlighter, or a semantic tokenizer
+//! feeds each token it lexes to a `ModeMachine` and lexes the next one in
+//! the mode it returns, and stays in step with the real lexer:
+//!
+//! ```
+//! use patchwork_lexer::{Mode, ModeMachine, Rule};
+//!
+//! let mut modes = ModeMachine::new();
+//! assert_eq!(modes.advance(Rule::Think), Mode::Code);
+//! assert_eq!(modes.advance(Rule::Whitespace), Mode::Code);
+//! assert_eq!(modes.advance(Rule::LBrace), Mode::Prompt);
+//! assert_eq!(modes.advance(Rule::PromptText), Mode::Prompt);
+//! assert_eq!(modes.advance(Rule::RBrace), Mode::Code);
+//! ```
+
+use crate::{Mode, Rule};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DelimiterType {
+    Brace,  // Waiting for }
+    Paren,  // Waiting for )
+}
+
+/// Context for tracking lexer state transitions
+#[derive(Debug, Clone)]
+pub struct LexerContext {
+    /// Stack of mode states for handling nesting
+    mode_stack: Vec<Mode>,
+    /// Stack of brace depths for each nested context
+    depth_stack: Vec<usize>,
+    /// Stack of delimiter types (what are we waiting for to close this context)
+    delimiter_stack: Vec<DelimiterType>,
+    /// Last token seen (for lookahead)
+    last_token: Option<Rule>,
+    /// Track if we just saw a Dollar in InString mode (for interpolation)
+    in_string_interpolation: bool,
+    /// Track if we just saw a Dollar in Prompt mode (for interpolation)
+    in_prompt_interpolation: bool,
+    /// Track if we just saw a Dollar in Shell mode (for interpolation)
+    in_shell_interpolation: bool,
+    /// Track if we're in shell mode (for command parsing)
+    in_shell_mode: bool,
+}
+
+impl LexerContext {
+    pub fn new() -> Self {
+        Self {
+            mode_stack: vec![],
+            depth_stack: vec![],
+            delimiter_stack: vec![],
+            last_token: None,
+            in_string_interpolation: false,
+            in_prompt_interpolation: false,
+            in_shell_interpolation: false,
+            in_shell_mode: false,
+        }
+    }
+
+    fn push_mode(&mut self, mode: Mode, delimiter: DelimiterType) {
+        self.mode_stack.push(mode);
+        self.depth_stack.push(1);
+        self.delimiter_stack.push(delimiter);
+    }
+
+    fn pop_mode(&mut self) -> Option<Mode> {
+        self.depth_stack.pop();
+        self.delimiter_stack.pop();
+        self.mode_stack.pop()
+    }
+
+    /// Record that the lexer produced a `rule` token while in `mode`, and
+    /// return the mode to switch to for the next token, if it changes.
+    ///
+    /// Whitespace and newlines must be fed too: `$ ` starts a shell
+    /// command only when whitespace follows the `$`.
+    pub fn advance(&mut self, mode: Mode, rule: Rule) -> Option<Mode> {
+        let mut next = None;
+        match rule {
+            Rule::StringStart => {
+                // Entering a string - transition to InString mode
+                self.push_mode(Mode::InString, DelimiterType::Brace);  // Waiting for StringEnd "
+                next = Some(Mode::InString);
+                self.last_token = None;
+                return next;
+            }
+            Rule::StringEnd => {
+                // Exiting a string - pop back to previous mode
+                if self.pop_mode().is_some() {
+                    // Return to the mode before the string
+                    if let Some(&parent_mode) = self.mode_stack.last() {
+                        next = Some(parent_mode);
+                    } else {
+                        // Back to Code mode
+                        next = Some(Mode::Code);
+                    }
+                }
+                self.last_token = None;
+                self.in_string_interpolation = false;
+                return next;
+            }
+            Rule::Dollar if self.last_token == Some(Rule::LParen) && mode == Mode::Code => {
+                // ($ pattern - enter Shell mode for shell expression
+                // Enter Shell mode - will exit on matching )
+                self.push_mode(Mode::Shell, DelimiterType::Paren);
+                next = Some(Mode::Shell);
+                self.in_shell_mode = true;
+                self.last_token = None;
+                return next;
+            }
+            Rule::Dollar => {
+                // When we see $ in InString or Prompt mode, we need to check what follows
+                // If it's { or (, we'll handle that in LBrace/LParen
+                // If it's an identifier, we temporarily switch to Code mode
+                // In Code mode, $ followed by whitespace enters Shell mode
+                // In Shell mode, $ followed by identifier stays in Shell (identifier is now active in Shell)
+                // Mark that we're in interpolation mode - next token should be in Code mode
+                match mode {
+                    Mode::InString => {
+                        self.in_string_interpolation = true;
+                        next = Some(Mode::Code);
+                    }
+                    Mode::Prompt => {
+                        self.in_prompt_interpolation = true;
+                        next = Some(Mode::Code);
+                    }
+                    Mode::Shell => {
+                        // $ in Shell mode for variable interpolation
+                        // Stay in Shell mode - the grammar will handle dollar shell_arg
+                    }
+                    Mode::Code => {
+                        // $ in Code mode might start shell mode
+                        // We'll check next token (Whitespace or LParen) to decide
+                    }
+                }
+                self.last_token = Some(rule);
+                return next;
+            }
+            Rule::Whitespace if self.last_token == Some(Rule::Dollar) && mode == Mode::Code => {
+                // $ followed by whitespace in Code mode → enter Shell mode
+                self.push_mode(Mode::Shell, DelimiterType::Brace);  // Will exit on newline
+                next = Some(Mode::Shell);
+                self.in_shell_mode = true;
+                self.last_token = None;
+                return next;
+            }
+            Rule::Identifier if self.in_string_interpolation && self.last_token == Some(Rule::Dollar) => {
+                // We're tokenizing an identifier directly after $ in a string (simple $id case)
+                // This is NOT ${...}, so return to InString mode after identifier
+                // Return to InString mode
+                self.in_string_interpolation = false;
+                next = Some(Mode::InString);
+                self.last_token = None;
+                return next;
+            }
+            Rule::Identifier if self.in_prompt_interpolation && self.last_token == Some(Rule::Dollar) => {
+                // We're tokenizing an identifier directly after $ in a prompt (simple $id case)
+                // This is NOT ${...}, so return to Prompt mode after identifier
+                // Return to Prompt mode
+                self.in_prompt_interpolation = false;
+                next = Some(Mode::Prompt);
+                self.last_token = None;
+                return next;
+            }
+            Rule::Think | Rule::Ask | Rule::Approve => {
+                // When we see think/ask/approve, record it. On next LBrace, transition to Prompt
+                self.last_token = Some(rule);
+            }
+            Rule::Do => {
+                // When we see do in Prompt state, record it. On next LBrace, transition to Code
+                self.last_token = Some(rule);
+            }
+            Rule::Examples => {
+                // `examples {` includes its brace, so transition Prompt -> Code now
+                self.push_mode(Mode::Code, DelimiterType::Brace);
+                next = Some(Mode::Code);
+                self.last_token = None;
+                return next;
+            }
+            Rule::Variant => {
+                // `variant name {` includes its brace; the variant's body is
+                // more prompt, so stay in Prompt mode and track depth
+                self.increment_depth();
+                self.last_token = None;
+                return next;
+            }
+            Rule::LBrace => {
+                // Then check if this follows a context operator and transition states
+                match self.last_token {
+                    Some(Rule::Think) | Some(Rule::Ask) | Some(Rule::Approve) => {
+                        // Transition Code -> Prompt
+                        self.push_mode(Mode::Prompt, DelimiterType::Brace);
+                        next = Some(Mode::Prompt);
+                    }
+                    Some(Rule::Do) if mode == Mode::Prompt => {
+                        // Transition Prompt -> Code
+                        self.push_mode(Mode::Code, DelimiterType::Brace);
+                        next = Some(Mode::Code);
+                    }
+                    Some(Rule::Dollar) if self.in_string_interpolation => {
+                        // ${expression} in string - stay in Code mode and track depth
+                        self.push_mode(Mode::Code, DelimiterType::Brace);
+                        // Stay in Code mode (already there from Dollar handling)
+                    }
+                    Some(Rule::Dollar) if self.in_prompt_interpolation => {
+                        // ${expression} in prompt - stay in Code mode and track depth
+                        self.push_mode(Mode::Code, DelimiterType::Brace);
+                        // Stay in Code mode (already there from Dollar handling)
+                    }
+                    Some(Rule::Dollar) if mode == Mode::Shell || self.in_shell_mode => {
+                        // ${expression} in shell mode - switch to Code mode temporarily
+                        self.in_shell_interpolation = true;
+                        self.push_mode(Mode::Code, DelimiterType::Brace);
+                        next = Some(Mode::Code);
+                    }
+                    _ => {
+                        // Just increment depth for nested braces
+                        self.increment_depth();
+                    }
+                }
+                self.last_token = None;
+                return next;
+            }
+            Rule::LParen if self.last_token == Some(Rule::Dollar) => {
+                // $(...) - behavior depends on current mode
+                // In Code mode OR Prompt mode: $(command) enters Shell mode for command substitution
+                // In InString mode: $(expr) stays in Code mode for expression (to support nested expressions)
+                if !self.in_string_interpolation || self.in_prompt_interpolation {
+                    // Code/Prompt mode: enter Shell mode for command substitution
+                    self.push_mode(Mode::Shell, DelimiterType::Paren);
+                    next = Some(Mode::Shell);
+                    self.in_shell_mode = true;
+                } else {
+                    // InString mode only: stay in Code mode for nested expressions like "${func($(cmd))}"
+                    self.push_mode(Mode::Code, DelimiterType::Paren);
+                    // Already in Code mode from Dollar handling
+                }
+                self.last_token = None;
+                return next;
+            }
+            Rule::LParen if mode == Mode::Code => {
+                // Track LParen to detect ($ pattern
+                self.last_token = Some(rule);
+            }
+            Rule::RParen if self.in_shell_mode && self.delimiter_stack.last() == Some(&DelimiterType::Paren) => {
+                // ) in shell mode with Paren delimiter → exit shell mode
+                let depth = self.decrement_depth();
+                if depth == 0 && self.pop_mode().is_some() {
+                    self.in_shell_mode = false;
+                    // Return to parent mode
+                    if let Some(&parent_mode) = self.mode_stack.last() {
+                        next = Some(parent_mode);
+                    } else {
+                        // Back to Code mode
+                        if self.in_string_interpolation {
+                            self.in_string_interpolation = false;
+                            next = Some(Mode::InString);
+                        } else if self.in_prompt_interpolation {
+                            self.in_prompt_interpolation = false;
+                            next = Some(Mode::Prompt);
+                        } else {
+                            next = Some(Mode::Code);
+                        }
+                    }
+                }
+                self.last_token = None;
+                return next;
+            }
+            Rule::RParen if self.in_string_interpolation || self.in_prompt_interpolation => {
+                // Only handle closing of $(command) - check if top of delimiter stack is Paren
+                if self.delimiter_stack.last() == Some(&DelimiterType::Paren) {
+                    let depth = self.decrement_depth();
+                    if depth == 0 && self.pop_mode().is_some() {
+                        // Check if we're still in a nested interpolation context
+                        if let Some(&parent_mode) = self.mode_stack.last() {
+                            // Still nested - return to parent mode (could be Code from ${...})
+                            next = Some(parent_mode);
+                        } else {
+                            // No more nesting - return to original mode (InString or Prompt)
+                            if self.in_string_interpolation {
+                                self.in_string_interpolation = false;
+                                next = Some(Mode::InString);
+                            } else if self.in_prompt_interpolation {
+                                self.in_prompt_interpolation = false;
+                                next = Some(Mode::Prompt);
+                            }
+                        }
+                    }
+                }
+                // Otherwise this is just a normal RParen in an expression like ${func(...)}
+                self.last_token = None;
+                return next;
+            }
+            Rule::RBrace => {
+                // Then decrement depth and potentially pop mode
+                let depth = self.decrement_depth();
+                if depth == 0 {
+                    // Pop back to previous mode
+                    if let Some(_prev_mode) = self.pop_mode() {
+                        // If we had a mode on stack, we need to return to the mode before that
+                        if let Some(&parent_mode) = self.mode_stack.last() {
+                            // Returning to a parent mode after closing interpolation/block
+                            // Clear the interpolation flag if we're done with interpolation
+                            if parent_mode == Mode::InString && self.in_string_interpolation {
+                                // Finished ${...} or $(...) in string, back to parent string
+                                self.in_string_interpolation = false;
+                            } else if parent_mode == Mode::Prompt && self.in_prompt_interpolation {
+                                // Finished ${...} or $(...) in prompt, back to parent prompt
+                                self.in_prompt_interpolation = false;
+                            } else if parent_mode == Mode::Shell && self.in_shell_interpolation {
+                                // Finished ${...} in shell, back to parent shell
+                                self.in_shell_interpolation = false;
+                            }
+                            next = Some(parent_mode);
+                        } else {
+                            // Back to Code, InString, Prompt, or Shell mode
+                            if self.in_string_interpolation {
+                                // Closing ${...} - return to InString
+                                self.in_string_interpolation = false;
+                                next = Some(Mode::InString);
+                            } else if self.in_prompt_interpolation {
+                                // Closing ${...} - return to Prompt
+                                self.in_prompt_interpolation = false;
+                                next = Some(Mode::Prompt);
+                            } else if self.in_shell_interpolation {
+                                // Closing ${...} - return to Shell
+                                self.in_shell_interpolation = false;
+                                next = Some(Mode::Shell);
+                            } else {
+                                next = Some(Mode::Code);
+                            }
+                        }
+                    }
+                }
+                self.last_token = None;
+                return next;
+            }
+            Rule::Newline if self.in_shell_mode => {
+                // Newline in shell mode → exit shell mode (unless backslash-escaped)
+                // Check if last token was backslash (line continuation)
+                if self.last_token != Some(Rule::ShellBackslash) {
+                    // Not escaped - exit shell mode
+                    if self.pop_mode().is_some() {
+                        if let Some(&parent_mode) = self.mode_stack.last() {
+                            next = Some(parent_mode);
+                        } else {
+                            next = Some(Mode::Code);
+                        }
+                    }
+                    self.in_shell_mode = false;
+                }
+                self.last_token = None;
+                return next;
+            }
+            Rule::Whitespace | Rule::Newline => {
+                // Keep last token for whitespace - don't clear it
+            }
+            _ => {
+                // Clear last token for any other token
+                self.last_token = None;
+            }
+        }
+        next
+    }
+
+    /// How many modes are nested inside the outermost code, such as a
+    /// string inside an interpolation inside a prompt.
+    pub fn nesting(&self) -> usize {
+        self.mode_stack.len()
+    }
+
+    #[allow(dead_code)]
+    fn current_depth(&self) -> usize {
+        self.depth_stack.last().copied().unwrap_or(0)
+    }
+
+    fn increment_depth(&mut self) {
+        if let Some(depth) = self.depth_stack.last_mut() {
+            *depth += 1;
+        }
+    }
+
+    fn decrement_depth(&mut self) -> usize {
+        if let Some(depth) = self.depth_stack.last_mut() {
+            if *depth > 0 {
+                *depth -= 1;
+            }
+            *depth
+        } else {
+            0
+        }
+    }
+}
+
+impl Default for LexerContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A `LexerContext` together with the current mode: the whole state an
+/// external tokenizer needs to lex Patchwork the way `PatchworkLexer` does.
+#[derive(Debug, Clone)]
+pub struct ModeMachine {
+    mode: Mode,
+    context: LexerContext,
+}
+
+impl ModeMachine {
+    /// A machine at the start of a file, in `Code` mode.
+    pub fn new() -> Self {
+        Self { mode: Mode::Code, context: LexerContext::new() }
+    }
+
+    /// The mode to lex the next token in.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Feed the token just lexed, returning the mode for the next one.
+    pub fn advance(&mut self, rule: Rule) -> Mode {
+        if let Some(mode) = self.context.advance(self.mode, rule) {
+            self.mode = mode;
+        }
+        self.mode
+    }
+
+    pub fn context(&self) -> &LexerContext {
+        &self.context
+    }
+}
+
+impl Default for ModeMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,86 @@
+use patchwork_lint::graph::Graph;
+use patchwork_parser::diagnostics::{Diagnostic, Renderer};
+use patchwork_parser::parse;
+use patchwork_parser::resolve::resolve;
+use std::env;
+use std::fs;
+use std::io::IsTerminal;
+use std::process;
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {} [--dot] [--imports] <file.pw>...", program);
+    eprintln!();
+    eprintln!("Print the call graph of a set of patchwork files and the declarations");
+    eprintln!("no export can reach.");
+    eprintln!();
+    eprintln!("  --dot      print Graphviz DOT instead of text");
+    eprintln!("  --imports  print the import graph instead of the call graph");
+    process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut dot = false;
+    let mut imports = false;
+    let mut filenames = Vec::new();
+    for arg in &args[1..] {
+        match arg.as_str() {
+            "--dot" => dot = true,
+            "--imports" => imports = true,
+            flag if flag.starts_with("--") => usage(&args[0]),
+            filename => filenames.push(filename),
+        }
+    }
+    if filenames.is_empty() {
+        usage(&args[0]);
+    }
+
+    // Read every file up front; modules are named by their path without `.pw`
+    let mut sources = Vec::new();
+    for filename in filenames {
+        match fs::read_to_string(filename) {
+            Ok(content) => sources.push((filename, content)),
+            Err(e) => {
+                eprintln!("Error reading file '{}': {}", filename, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let mut graph = Graph::new();
+    for (filename, input) in &sources {
+        let program = match parse(input) {
+            Ok(prog) => prog,
+            Err(e) => {
+                let renderer = if std::io::stderr().is_terminal() {
+                    Renderer::colored()
+                } else {
+                    Renderer::plain()
+                };
+                eprint!("{}", renderer.render(&Diagnostic::from(&e), filename, input));
+                process::exit(1);
+            }
+        };
+        let module = filename.strip_prefix("./").unwrap_or(filename);
+        let module = module.strip_suffix(".pw").unwrap_or(module);
+        graph.add_module(module, &resolve(&program, input));
+    }
+
+    match (dot, imports) {
+        (true, false) => print!("{}", graph.to_dot()),
+        (true, true) => print!("{}", graph.imports_to_dot()),
+        (false, true) => {
+            for edge in graph.imports() {
+                println!("{} -> {}", edge.from, edge.to);
+            }
+        }
+        (false, false) => {
+            for (from, to) in graph.calls() {
+                println!("{}:{} -> {}:{}", from.module, from.name, to.module, to.name);
+            }
+            for node in graph.dead_code() {
+                println!("unreachable: {}:{}", node.module, node.name);
+            }
+        }
+    }
+}
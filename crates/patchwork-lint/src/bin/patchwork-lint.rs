@@ -0,0 +1,102 @@
+use patchwork_lint::{LintConfig, Linter, Registry};
+use patchwork_parser::diagnostics::{Diagnostic, Renderer, Severity};
+use std::env;
+use std::fs;
+use std::io::IsTerminal;
+use std::process;
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {} [--fix] [--config <lint.json>] <file.pw>...", program);
+    eprintln!();
+    eprintln!("Lint patchwork files. Exits with status 1 if any finding is an error.");
+    eprintln!();
+    eprintln!("  --fix            apply safe fixes in place and report what is left");
+    eprintln!("  --config <file>  rule levels, e.g. {{\"shadowing\": \"allow\"}}");
+    process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut fix = false;
+    let mut config = None;
+    let mut filenames = Vec::new();
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--fix" => fix = true,
+            "--config" => match rest.next() {
+                Some(path) => config = Some(path),
+                None => usage(&args[0]),
+            },
+            flag if flag.starts_with("--") => usage(&args[0]),
+            filename => filenames.push(filename),
+        }
+    }
+    if filenames.is_empty() {
+        usage(&args[0]);
+    }
+
+    let config = match config {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|e| e.to_string());
+            match text.and_then(|text| LintConfig::from_json(&text)) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("Error reading lint config '{}': {}", path, e);
+                    process::exit(1);
+                }
+            }
+        }
+        None => LintConfig::default(),
+    };
+    let linter = match Linter::new(Registry::default(), config) {
+        Ok(linter) => linter,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let renderer = if std::io::stderr().is_terminal() {
+        Renderer::colored()
+    } else {
+        Renderer::plain()
+    };
+
+    let mut failed = false;
+    for filename in filenames {
+        let input = match fs::read_to_string(filename) {
+            Ok(content) => content,
+            Err(e) => {
+                eprintln!("Error reading file '{}': {}", filename, e);
+                process::exit(1);
+            }
+        };
+
+        let (source, found) = if fix {
+            let fixed = linter.fix(&input);
+            if !fixed.applied.is_empty() {
+                if let Err(e) = fs::write(filename, &fixed.source) {
+                    eprintln!("Error writing file '{}': {}", filename, e);
+                    process::exit(1);
+                }
+                eprintln!("{}: applied {} fix(es)", filename, fixed.applied.len());
+            }
+            (fixed.source, fixed.remaining)
+        } else {
+            let found = linter.lint_source(&input);
+            (input, found)
+        };
+
+        let diagnostics = match found {
+            Ok(diagnostics) => diagnostics,
+            Err(e) => vec![Diagnostic::from(&e)],
+        };
+        for diagnostic in &diagnostics {
+            eprint!("{}", renderer.render(diagnostic, filename, &source));
+            failed |= diagnostic.severity == Severity::Error;
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
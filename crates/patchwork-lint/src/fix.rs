@@ -0,0 +1,135 @@
+//! Applying the fixes that rules attach to their diagnostics.
+//!
+//! Fixes are edits to the source text, so everything they don't touch,
+//! comments and layout included, stays as it was. `Linter::fix` lints,
+//! applies every fix that doesn't overlap another, and lints again until
+//! nothing is left to fix, since removing one import can leave the list
+//! ready for the next, and a missing `,` has to be fixed before the file
+//! parses at all.
+
+use patchwork_parser::diagnostics::{Diagnostic, Edit, Fix};
+use patchwork_parser::parse;
+use patchwork_parser::resolve::resolve;
+use patchwork_parser::ParseError;
+
+use crate::Linter;
+
+/// Rounds of linting and fixing before `Linter::fix` gives up on reaching a
+/// file with nothing left to fix.
+const MAX_ROUNDS: usize = 10;
+
+/// The result of `Linter::fix`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fixed {
+    /// The source with the fixes applied.
+    pub source: String,
+    /// What each applied fix did, in the order they were applied.
+    pub applied: Vec<String>,
+    /// The findings left after fixing, or the parse error that stopped it.
+    pub remaining: Result<Vec<Diagnostic>, ParseError>,
+}
+
+impl Linter {
+    /// Parse, resolve, and lint `source`.
+    pub fn lint_source(&self, source: &str) -> Result<Vec<Diagnostic>, ParseError> {
+        let program = parse(source)?;
+        Ok(self.lint(&resolve(&program, source), source))
+    }
+
+    /// Apply every fix the linter finds in `source`, and the fix for a
+    /// missing separator if it doesn't parse.
+    pub fn fix(&self, source: &str) -> Fixed {
+        let mut text = source.to_string();
+        let mut applied = Vec::new();
+        for _ in 0..MAX_ROUNDS {
+            let found = self.lint_source(&text);
+            let fixes: Vec<Fix> = match &found {
+                Ok(diagnostics) => diagnostics.iter().filter_map(|d| d.fix.clone()).collect(),
+                Err(error) => parse_error_fix(error, &text).into_iter().collect(),
+            };
+            if fixes.is_empty() {
+                return Fixed { source: text, applied, remaining: found };
+            }
+            let (next, used) = apply_fixes(&text, &fixes);
+            applied.extend(used.into_iter().map(|fix| fix.message.clone()));
+            text = next;
+        }
+        let remaining = self.lint_source(&text);
+        Fixed { source: text, applied, remaining }
+    }
+}
+
+/// Apply `fixes` to `source`, returning the new text and the fixes used.
+///
+/// Fixes are taken in source order; one with an edit overlapping an edit
+/// already taken is skipped, to be found again by the next lint.
+pub fn apply_fixes<'a>(source: &str, fixes: &'a [Fix]) -> (String, Vec<&'a Fix>) {
+    let mut order: Vec<&Fix> = fixes.iter().filter(|fix| !fix.edits.is_empty()).collect();
+    order.sort_by_key(|fix| fix.edits.iter().map(|edit| edit.span.0).min());
+
+    let mut used: Vec<&Fix> = Vec::new();
+    let mut edits: Vec<&Edit> = Vec::new();
+    for fix in order {
+        let overlaps = fix.edits.iter().any(|edit| {
+            edits.iter().any(|taken| {
+                let (a, b) = (edit.span, taken.span);
+                (a.0 < b.1 && b.0 < a.1) || a.0 == b.0
+            })
+        });
+        if !overlaps {
+            edits.extend(fix.edits.iter());
+            used.push(fix);
+        }
+    }
+
+    let mut text = source.to_string();
+    edits.sort_by_key(|edit| std::cmp::Reverse(edit.span.0));
+    for edit in edits {
+        text.replace_range(edit.span.0..edit.span.1, &edit.replacement);
+    }
+    (text, used)
+}
+
+/// The fix for a parse error, when there is a safe one: a `,` missing
+/// between two items is inserted after the first.
+pub fn parse_error_fix(error: &ParseError, source: &str) -> Option<Fix> {
+    let ParseError::UnexpectedToken { span, expected, .. } = error else {
+        return None;
+    };
+    if !expected.iter().any(|terminal| terminal == "\",\"") {
+        return None;
+    }
+    let at = source.get(..span.0)?.trim_end().len();
+    Some(Fix::replace("insert the missing `,`", (at, at), ","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_fixes_skips_overlaps() {
+        let edit = |start, end, text: &str| Edit { span: (start, end), replacement: text.to_string() };
+        let fixes = vec![
+            Fix::new("second", vec![edit(4, 5, "E")]),
+            Fix::new("first", vec![edit(0, 2, "AB"), edit(6, 6, "!")]),
+            Fix::new("overlapping", vec![edit(1, 3, "x")]),
+        ];
+        let (text, used) = apply_fixes("abcdef", &fixes);
+        assert_eq!(text, "ABcdEf!");
+        let used: Vec<&str> = used.iter().map(|fix| fix.message.as_str()).collect();
+        assert_eq!(used, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn test_fix_until_clean() {
+        let source = "import ./{a, b}\nskill main(x) {\n  var items = [1 2]\n  if x == null {\n    print(items)\n  }\n}\n";
+        let fixed = Linter::default().fix(source);
+        assert_eq!(
+            fixed.source,
+            "skill main(x) {\n  var items = [1, 2]\n  if is_null(x) {\n    print(items)\n  }\n}\n"
+        );
+        assert_eq!(fixed.applied.len(), 4);
+        assert_eq!(fixed.remaining, Ok(Vec::new()));
+    }
+}
@@ -0,0 +1,444 @@
+//! Call and import graphs across a set of modules.
+//!
+//! Add each module of a workflow library with `Graph::add_module`, naming it
+//! by its path without the `.pw` extension (`review/analyst`). Relative
+//! imports are resolved against those names, so `import ./{analyst}` in
+//! `review/historian` refers to `review/analyst`, and calls such as
+//! `analyst(...)` or `helper.default(...)` become edges to that module's
+//! exports. Modules can be added in any order.
+//!
+//! Exported and default declarations are the graph's roots. Anything they
+//! cannot reach, directly or through other modules, is reported by
+//! `Graph::dead_code`.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use patchwork_parser::ast::*;
+use patchwork_parser::resolve::{Resolution, ResolvedProgram, SymbolKind};
+
+use crate::walk::{walk_block, Visitor};
+
+/// The kind of declaration a node comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Skill,
+    Worker,
+    Function,
+    /// A method of a trait, named `Trait.method`.
+    Method,
+}
+
+/// A callable declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub module: String,
+    pub name: String,
+    pub kind: NodeKind,
+    /// Exported itself, or a method of an exported trait.
+    pub is_exported: bool,
+    pub is_default: bool,
+}
+
+/// An `import` in one module of another module or data file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportEdge {
+    pub from: String,
+    /// A module name like `review/analyst`, a library path like `std.log`,
+    /// or a data file path like `review/fixtures/policies.json`.
+    pub to: String,
+}
+
+/// What a use of a name points at, before every module has been added.
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    /// A declaration in the same module.
+    Local(String),
+    /// An export of another module; `None` is its default export.
+    Export { module: String, name: Option<String> },
+}
+
+/// The call and import graphs of a set of modules.
+#[derive(Debug, Clone, Default)]
+pub struct Graph {
+    nodes: Vec<Node>,
+    /// Node index -> what it uses.
+    uses: Vec<(usize, Target)>,
+    imports: Vec<ImportEdge>,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the module named `module`.
+    pub fn add_module(&mut self, module: &str, program: &ResolvedProgram) {
+        let dir = module.rsplit_once('/').map_or("", |(dir, _)| dir);
+        let mut imported = Vec::new();
+        for item in &program.program.items {
+            let Item::Import(decl) = item else {
+                continue;
+            };
+            match &decl.path {
+                ImportPath::Simple(parts) => {
+                    let path = parts.join(".");
+                    if let Some(name) = parts.last() {
+                        imported.push((*name, path.clone()));
+                    }
+                    self.imports.push(ImportEdge { from: module.to_string(), to: path });
+                }
+                ImportPath::RelativeMulti(names) => {
+                    for name in names {
+                        let path = join(dir, name);
+                        imported.push((*name, path.clone()));
+                        self.imports.push(ImportEdge { from: module.to_string(), to: path });
+                    }
+                }
+                ImportPath::Data { path, .. } => {
+                    self.imports.push(ImportEdge { from: module.to_string(), to: join(dir, path) })
+                }
+            }
+        }
+
+        for item in &program.program.items {
+            match item {
+                Item::Skill(decl) => {
+                    self.add_callable(module, decl.name, NodeKind::Skill, decl.is_exported, decl.is_default)
+                        .walk(program, &imported, None, &decl.body);
+                }
+                Item::Worker(decl) => {
+                    self.add_callable(module, decl.name, NodeKind::Worker, decl.is_exported, decl.is_default)
+                        .walk(program, &imported, None, &decl.body);
+                }
+                Item::Function(decl) => {
+                    self.add_callable(module, decl.name, NodeKind::Function, decl.is_exported, decl.is_default)
+                        .walk(program, &imported, None, &decl.body);
+                }
+                Item::Trait(decl) => {
+                    for method in &decl.methods {
+                        let name = format!("{}.{}", decl.name, method.name);
+                        self.add_callable(module, &name, NodeKind::Method, decl.is_exported, false)
+                            .walk(program, &imported, Some(decl.name), &method.body);
+                    }
+                }
+                Item::Import(_) | Item::Type(_) | Item::Var(_) | Item::Eval(_) => {}
+            }
+        }
+    }
+
+    fn add_callable(
+        &mut self,
+        module: &str,
+        name: &str,
+        kind: NodeKind,
+        is_exported: bool,
+        is_default: bool,
+    ) -> Uses<'_> {
+        self.nodes.push(Node {
+            module: module.to_string(),
+            name: name.to_string(),
+            kind,
+            is_exported,
+            is_default,
+        });
+        let node = self.nodes.len() - 1;
+        Uses { graph: self, node }
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    pub fn imports(&self) -> &[ImportEdge] {
+        &self.imports
+    }
+
+    /// Every call (or other use) of one declaration by another, once each.
+    /// Uses of modules that were never added are left out.
+    pub fn calls(&self) -> Vec<(&Node, &Node)> {
+        self.edges().into_iter().map(|(from, to)| (&self.nodes[from], &self.nodes[to])).collect()
+    }
+
+    /// The declarations `node` uses directly.
+    pub fn callees(&self, node: &Node) -> Vec<&Node> {
+        self.calls().into_iter().filter(|(from, _)| *from == node).map(|(_, to)| to).collect()
+    }
+
+    /// The declarations `node` is used by directly.
+    pub fn callers(&self, node: &Node) -> Vec<&Node> {
+        self.calls().into_iter().filter(|(_, to)| *to == node).map(|(from, _)| from).collect()
+    }
+
+    /// Declarations no exported or default declaration can reach.
+    pub fn dead_code(&self) -> Vec<&Node> {
+        let edges = self.edges();
+        let mut live: HashSet<usize> = (0..self.nodes.len())
+            .filter(|&i| self.nodes[i].is_exported || self.nodes[i].is_default)
+            .collect();
+        let mut pending: Vec<usize> = live.iter().copied().collect();
+        while let Some(node) = pending.pop() {
+            for &(_, to) in edges.iter().filter(|(from, _)| *from == node) {
+                if live.insert(to) {
+                    pending.push(to);
+                }
+            }
+        }
+        (0..self.nodes.len()).filter(|i| !live.contains(i)).map(|i| &self.nodes[i]).collect()
+    }
+
+    /// The call graph in Graphviz DOT format, one cluster per module. Dead
+    /// code is drawn in gray.
+    pub fn to_dot(&self) -> String {
+        let dead: Vec<&Node> = self.dead_code();
+        let mut modules: Vec<&str> = self.nodes.iter().map(|n| n.module.as_str()).collect();
+        modules.dedup();
+
+        let mut out = String::from("digraph calls {\n");
+        for (i, module) in modules.iter().enumerate() {
+            let _ = writeln!(out, "  subgraph cluster_{} {{", i);
+            let _ = writeln!(out, "    label={:?};", module);
+            for node in self.nodes.iter().filter(|n| n.module == *module) {
+                let shape = match node.kind {
+                    NodeKind::Skill => "box",
+                    NodeKind::Worker => "component",
+                    NodeKind::Function | NodeKind::Method => "ellipse",
+                };
+                let color = if dead.contains(&node) { ", color=gray, fontcolor=gray" } else { "" };
+                let _ = writeln!(out, "    {:?} [label={:?}, shape={}{}];", node_id(node), node.name, shape, color);
+            }
+            out.push_str("  }\n");
+        }
+        for (from, to) in self.calls() {
+            let _ = writeln!(out, "  {:?} -> {:?};", node_id(from), node_id(to));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// The import graph in Graphviz DOT format.
+    pub fn imports_to_dot(&self) -> String {
+        let mut out = String::from("digraph imports {\n");
+        for edge in &self.imports {
+            let _ = writeln!(out, "  {:?} -> {:?};", edge.from, edge.to);
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn edges(&self) -> Vec<(usize, usize)> {
+        let mut edges = Vec::new();
+        for (from, target) in &self.uses {
+            let module = &self.nodes[*from].module;
+            let to = self.nodes.iter().position(|node| match target {
+                Target::Local(name) => node.module == *module && node.name == *name,
+                Target::Export { module, name: None } => node.module == *module && node.is_default,
+                Target::Export { module, name: Some(name) } => {
+                    node.module == *module && node.name == *name && node.is_exported
+                }
+            });
+            if let Some(to) = to {
+                if !edges.contains(&(*from, to)) {
+                    edges.push((*from, to));
+                }
+            }
+        }
+        edges
+    }
+}
+
+fn node_id(node: &Node) -> String {
+    format!("{}:{}", node.module, node.name)
+}
+
+/// Resolve `path` against the directory `dir`, both `/`-separated.
+fn join(dir: &str, path: &str) -> String {
+    let mut parts: Vec<&str> = dir.split('/').filter(|p| !p.is_empty()).collect();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+/// Records what one node's body uses.
+struct Uses<'g> {
+    graph: &'g mut Graph,
+    node: usize,
+}
+
+impl Uses<'_> {
+    fn walk<'input>(
+        self,
+        program: &ResolvedProgram,
+        imported: &[(&str, String)],
+        trait_name: Option<&str>,
+        body: &Block<'input>,
+    ) {
+        let mut collect = Collect { program, imported, trait_name, found: Vec::new() };
+        walk_block(body, &mut collect);
+        let node = self.node;
+        self.graph.uses.extend(collect.found.into_iter().map(|target| (node, target)));
+    }
+}
+
+struct Collect<'a> {
+    program: &'a ResolvedProgram<'a, 'a>,
+    imported: &'a [(&'a str, String)],
+    trait_name: Option<&'a str>,
+    found: Vec<Target>,
+}
+
+impl Collect<'_> {
+    /// The module an identifier refers to, if it names an import.
+    fn module_of(&self, ident: &str) -> Option<String> {
+        let id = self.program.symbols.resolution(ident)?.symbol()?;
+        let symbol = self.program.symbols.symbol(id);
+        if symbol.kind != SymbolKind::Import {
+            return None;
+        }
+        self.imported.iter().find(|(name, _)| *name == symbol.name).map(|(_, module)| module.clone())
+    }
+}
+
+impl<'input> Visitor<'input> for Collect<'_> {
+    fn expr(&mut self, expr: &Expr<'input>) {
+        match expr {
+            Expr::Identifier(ident) => {
+                if let Some(Resolution::Global(id)) = self.program.symbols.resolution(ident) {
+                    let symbol = self.program.symbols.symbol(id);
+                    if matches!(symbol.kind, SymbolKind::Skill | SymbolKind::Worker | SymbolKind::Function) {
+                        self.found.push(Target::Local(symbol.name.clone()));
+                    }
+                }
+            }
+            Expr::Call { callee, .. } => {
+                if let Expr::Identifier(ident) = callee.as_ref() {
+                    if let Some(module) = self.module_of(ident) {
+                        self.found.push(Target::Export { module, name: None });
+                    }
+                }
+            }
+            Expr::Member { object, field } => {
+                let Expr::Identifier(ident) = object.as_ref() else {
+                    return;
+                };
+                if *ident == "self" {
+                    if let Some(trait_name) = self.trait_name {
+                        self.found.push(Target::Local(format!("{}.{}", trait_name, field)));
+                    }
+                } else if let Some(module) = self.module_of(ident) {
+                    let name = (*field != "default").then(|| field.to_string());
+                    self.found.push(Target::Export { module, name });
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use patchwork_parser::parse;
+    use patchwork_parser::resolve::resolve;
+
+    fn add(graph: &mut Graph, module: &str, source: &str) {
+        let program = parse(source).unwrap();
+        graph.add_module(module, &resolve(&program, source));
+    }
+
+    fn names(nodes: Vec<&Node>) -> Vec<String> {
+        nodes.into_iter().map(node_id).collect()
+    }
+
+    #[test]
+    fn test_calls_across_modules() {
+        let mut graph = Graph::new();
+        add(
+            &mut graph,
+            "review/main",
+            r#"
+import ./{helper}
+import std.log
+
+export default worker main() {
+    var msg = greet("hi")
+    return helper.default(msg) + helper.extra()
+}
+
+fun greet(text) {
+    return text
+}
+
+fun unused() {
+    return orphan()
+}
+
+fun orphan() {}
+"#,
+        );
+        add(
+            &mut graph,
+            "review/helper",
+            r#"
+export default worker helper(message: string) {
+    return message
+}
+
+export fun extra() {
+    return ""
+}
+
+fun stale() {}
+"#,
+        );
+
+        let calls: Vec<(String, String)> =
+            graph.calls().into_iter().map(|(from, to)| (node_id(from), node_id(to))).collect();
+        assert_eq!(
+            calls,
+            vec![
+                ("review/main:main".to_string(), "review/main:greet".to_string()),
+                ("review/main:main".to_string(), "review/helper:helper".to_string()),
+                ("review/main:main".to_string(), "review/helper:extra".to_string()),
+                ("review/main:unused".to_string(), "review/main:orphan".to_string()),
+            ]
+        );
+        assert_eq!(
+            names(graph.dead_code()),
+            vec!["review/main:unused", "review/main:orphan", "review/helper:stale"]
+        );
+
+        let imports: Vec<&str> = graph.imports().iter().map(|edge| edge.to.as_str()).collect();
+        assert_eq!(imports, vec!["review/helper", "std.log"]);
+
+        let dot = graph.to_dot();
+        assert!(dot.contains(r#""review/main:main" -> "review/helper:helper";"#));
+        assert!(dot.contains(r#""review/helper:stale" [label="stale", shape=ellipse, color=gray, fontcolor=gray];"#));
+    }
+
+    #[test]
+    fn test_trait_methods_call_each_other() {
+        let mut graph = Graph::new();
+        add(
+            &mut graph,
+            "agent",
+            r#"
+export default trait Agent: Base {
+    fun run() {
+        self.plan()
+    }
+    fun plan() {}
+}
+"#,
+        );
+        assert_eq!(graph.callees(&graph.nodes()[0]).len(), 1);
+        assert!(graph.dead_code().is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+//! Lints for Patchwork programs.
+//!
+//! A lint is a check for code that runs but is probably a mistake: a
+//! variable that hides another, an import nothing uses, a prompt with no
+//! text. Each check is a `Rule`; a `Registry` holds the rules to run and a
+//! `LintConfig` says how seriously to take each one. `patchwork check`, the
+//! LSP, and pre-commit hooks all lint through the same `Linter`:
+//!
+//! ```ignore
+//! let linter = Linter::new(Registry::default(), LintConfig::from_json(r#"{"shadowing": "allow"}"#)?);
+//! for diagnostic in linter.lint(&resolve(&program, source), source) {
+//!     eprintln!("{}", Renderer::plain().render(&diagnostic, "main.pw", source));
+//! }
+//! ```
+//!
+//! Rules report `Diagnostic`s whose code is the rule's name, so a host can
+//! always tell lints apart from parse and type errors.
+//!
+//! A rule can attach a `Fix` to a finding when the change is certain to be
+//! what was meant, like deleting an unused import. `Linter::fix` applies
+//! them, for `patchwork-lint --fix` and the LSP's code actions.
+//!
+//! `extract_function` and `extract_skill` are refactorings of a selection,
+//! returned as a `Fix` for the LSP to offer.
+//!
+//! The `graph` module builds call and import graphs across a library of
+//! modules, for finding dead code and drawing with `patchwork-graph --dot`.
+
+mod fix;
+pub mod graph;
+mod refactor;
+mod rules;
+mod walk;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use patchwork_parser::diagnostics::{Diagnostic, Severity};
+use patchwork_parser::resolve::ResolvedProgram;
+
+pub use fix::{apply_fixes, parse_error_fix, Fixed};
+pub use refactor::{extract_function, extract_skill};
+pub use rules::{EmptyPrompt, NullComparison, Shadowing, UnreachableCode, UnusedImport};
+
+/// How to treat a rule's findings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// Don't run the rule.
+    Allow,
+    /// Report findings as warnings.
+    Warn,
+    /// Report findings as errors.
+    Deny,
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Level::Allow),
+            "warn" => Ok(Level::Warn),
+            "deny" => Ok(Level::Deny),
+            other => Err(format!("unknown lint level `{}` (expected allow, warn, or deny)", other)),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Allow => "allow",
+            Level::Warn => "warn",
+            Level::Deny => "deny",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// One lint check.
+pub trait Rule: Send + Sync {
+    /// Stable name, used in configs and as the diagnostic code.
+    fn name(&self) -> &'static str;
+
+    /// One-line description for `--help` output and docs.
+    fn description(&self) -> &'static str;
+
+    /// The level when the config doesn't mention this rule.
+    fn default_level(&self) -> Level {
+        Level::Warn
+    }
+
+    /// Report findings in the program. The linter sets each diagnostic's
+    /// severity and code, so rules only fill in the message and spans.
+    fn check(&self, cx: &LintContext, report: &mut Vec<Diagnostic>);
+}
+
+/// What a rule gets to look at.
+pub struct LintContext<'a, 'input> {
+    pub program: &'a ResolvedProgram<'a, 'input>,
+    pub source: &'input str,
+}
+
+impl<'a, 'input> LintContext<'a, 'input> {
+    pub fn new(program: &'a ResolvedProgram<'a, 'input>, source: &'input str) -> Self {
+        Self { program, source }
+    }
+
+    /// Where `text`, a slice of the source taken from the AST, sits in it.
+    pub fn span(&self, text: &str) -> Option<(usize, usize)> {
+        let start = (text.as_ptr() as usize).checked_sub(self.source.as_ptr() as usize)?;
+        (start + text.len() <= self.source.len()).then_some((start, start + text.len()))
+    }
+}
+
+/// The rules to run.
+pub struct Registry {
+    rules: Vec<Box<dyn Rule>>,
+}
+
+impl Registry {
+    /// A registry with no rules.
+    pub fn empty() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    /// Add a rule, replacing any rule with the same name.
+    pub fn register(&mut self, rule: impl Rule + 'static) {
+        self.rules.retain(|r| r.name() != rule.name());
+        self.rules.push(Box::new(rule));
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = &dyn Rule> {
+        self.rules.iter().map(|r| r.as_ref())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Rule> {
+        self.rules().find(|r| r.name() == name)
+    }
+}
+
+/// The built-in rules.
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(Shadowing);
+        registry.register(UnusedImport);
+        registry.register(EmptyPrompt);
+        registry.register(NullComparison);
+        registry.register(UnreachableCode);
+        registry
+    }
+}
+
+/// Per-rule levels, overriding each rule's default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LintConfig {
+    levels: HashMap<String, Level>,
+}
+
+impl LintConfig {
+    /// Read a JSON object mapping rule names to levels, e.g.
+    /// `{"shadowing": "allow", "unreachable-code": "deny"}`.
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        let root: serde_json::Value =
+            serde_json::from_str(text).map_err(|e| format!("invalid lint config: {}", e))?;
+        let root = root.as_object().ok_or("invalid lint config: expected a JSON object")?;
+        let mut config = Self::default();
+        for (rule, level) in root {
+            let level = level
+                .as_str()
+                .ok_or_else(|| format!("invalid lint config: `{}` should be allow, warn, or deny", rule))?;
+            config.set(rule, level.parse()?);
+        }
+        Ok(config)
+    }
+
+    pub fn set(&mut self, rule: &str, level: Level) {
+        self.levels.insert(rule.to_string(), level);
+    }
+
+    /// The level for `rule` under this config.
+    pub fn level(&self, rule: &dyn Rule) -> Level {
+        self.levels.get(rule.name()).copied().unwrap_or_else(|| rule.default_level())
+    }
+}
+
+/// Runs a registry's rules under a config.
+#[derive(Default)]
+pub struct Linter {
+    registry: Registry,
+    config: LintConfig,
+}
+
+impl Linter {
+    /// Fails if the config names a rule the registry doesn't have, which is
+    /// usually a typo.
+    pub fn new(registry: Registry, config: LintConfig) -> Result<Self, String> {
+        if let Some(unknown) = config.levels.keys().find(|name| registry.get(name).is_none()) {
+            return Err(format!("unknown lint rule `{}`", unknown));
+        }
+        Ok(Self { registry, config })
+    }
+
+    /// Lint `program`, which was resolved from `source`, returning findings
+    /// sorted by position.
+    pub fn lint(&self, program: &ResolvedProgram, source: &str) -> Vec<Diagnostic> {
+        let cx = LintContext::new(program, source);
+        let mut diagnostics = Vec::new();
+        for rule in self.registry.rules() {
+            let severity = match self.config.level(rule) {
+                Level::Allow => continue,
+                Level::Warn => Severity::Warning,
+                Level::Deny => Severity::Error,
+            };
+            let mut found = Vec::new();
+            rule.check(&cx, &mut found);
+            diagnostics.extend(found.into_iter().map(|mut diagnostic| {
+                diagnostic.severity = severity;
+                diagnostic.code = Some(rule.name().to_string());
+                diagnostic
+            }));
+        }
+        diagnostics.sort_by_key(|d| d.primary_span().map_or(0, |(start, _)| start));
+        diagnostics
+    }
+}
+
+/// Lint a resolved program with the built-in rules at their default levels.
+pub fn lint(program: &ResolvedProgram, source: &str) -> Vec<Diagnostic> {
+    Linter::default().lint(program, source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use patchwork_parser::parse;
+    use patchwork_parser::resolve::resolve;
+
+    fn codes(source: &str, linter: &Linter) -> Vec<String> {
+        let program = parse(source).unwrap();
+        linter
+            .lint(&resolve(&program, source), source)
+            .into_iter()
+            .map(|d| d.code.unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_config_sets_levels() {
+        let source = "import ./{helpers}\nskill main(x) {\n  var x = 1\n}\n";
+        assert_eq!(codes(source, &Linter::default()), vec!["unused-import", "shadowing"]);
+
+        let config = LintConfig::from_json(r#"{"shadowing": "allow", "unused-import": "deny"}"#).unwrap();
+        let linter = Linter::new(Registry::default(), config).unwrap();
+        let program = parse(source).unwrap();
+        let diagnostics = linter.lint(&resolve(&program, source), source);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+
+        assert!(LintConfig::from_json(r#"{"shadowing": "loud"}"#).is_err());
+        let typo = LintConfig::from_json(r#"{"shadowin": "allow"}"#).unwrap();
+        assert!(Linter::new(Registry::default(), typo).is_err());
+    }
+
+    #[test]
+    fn test_rules_are_explained() {
+        for rule in Registry::default().rules() {
+            let explanation = patchwork_parser::explain::lookup(rule.name());
+            assert_eq!(explanation.map(|e| e.reporter), Some("lint"), "{} needs an explanation", rule.name());
+        }
+    }
+}
@@ -0,0 +1,317 @@
+//! Refactorings for a selection of the source, offered by the LSP as code
+//! actions.
+//!
+//! `extract_function` lifts the statements on the selected lines into a new
+//! `fun` at the end of the file and calls it in their place. Local variables
+//! the statements read become its parameters, and a variable they declare
+//! that later code reads is returned and declared again at the call site:
+//!
+//! ```text
+//! var total = 0                      var total = 0
+//! var doubled = total * 2     =>     var doubled = extracted(total)
+//! print(doubled)                     print(doubled)
+//!
+//!                                    fun extracted(total) {
+//!                                        var doubled = total * 2
+//!                                        return doubled
+//!                                    }
+//! ```
+//!
+//! `extract_skill` lifts the paragraph of a `think` or `ask` prompt on the
+//! selected lines into a new `skill` that asks the model that paragraph on
+//! its own, and interpolates its answer where the paragraph was.
+//!
+//! Both give up, returning `None`, when the selection isn't something they
+//! can lift without changing what the rest of the code sees: statements
+//! that `return` or assign to variables outside the selection, or that
+//! declare more than one variable used after it.
+
+use patchwork_parser::ast::*;
+use patchwork_parser::diagnostics::{Edit, Fix};
+use patchwork_parser::parse;
+use patchwork_parser::resolve::{resolve, Resolution, SymbolTable};
+
+use crate::walk::{walk_block, walk_program, Visitor};
+
+/// Lift the statements on the lines `selection` touches into a new function.
+pub fn extract_function(source: &str, selection: (usize, usize)) -> Option<Fix> {
+    let (start, end) = selected_lines(source, selection)?;
+    let lines = &source[start..end];
+
+    // The lines must be whole statements that don't leave the function early
+    let wrapped = format!("fun __extracted__() {{\n{}\n}}", lines);
+    let extracted = parse(&wrapped).ok()?;
+    let Some(Item::Function(decl)) = extracted.items.first() else {
+        return None;
+    };
+    if decl.body.statements.is_empty() || decl.body.statements.iter().any(|s| matches!(s, Statement::Break)) {
+        return None;
+    }
+    let mut exits = Exits(false);
+    walk_block(&decl.body, &mut exits);
+    if exits.0 {
+        return None;
+    }
+
+    let program = parse(source).ok()?;
+    let symbols = resolve(&program, source).symbols;
+    let (params, mut outputs) = free_variables(&symbols, (start, end))?;
+    if outputs.len() > 1 {
+        return None;
+    }
+    let output = outputs.pop();
+
+    let name = fresh_name(&symbols, "extracted");
+    let indent = &lines[..lines.len() - lines.trim_start().len()];
+    let unit = indent_unit(source);
+    let mut body = reindent(lines, indent, unit);
+    let call = format!("{}({})", name, params.join(", "));
+    let call = match &output {
+        Some(output) => {
+            body.push_str(&format!("{}return {}\n", unit, output));
+            format!("{}var {} = {}", indent, output, call)
+        }
+        None => format!("{}{}", indent, call),
+    };
+    let function = format!("fun {}({}) {{\n{}}}\n", name, params.join(", "), body);
+    finish(source, format!("extract into function `{}`", name), (start, end), call, function)
+}
+
+/// Lift the paragraph of a prompt on the lines `selection` touches into a
+/// new skill.
+pub fn extract_skill(source: &str, selection: (usize, usize)) -> Option<Fix> {
+    let (start, end) = selected_lines(source, selection)?;
+    let lines = &source[start..end];
+    let paragraph = (start + lines.len() - lines.trim_start().len(), start + lines.trim_end().len());
+
+    let program = parse(source).ok()?;
+    let mut prompts = Prompts { source, found: None, paragraph };
+    walk_program(&program, &mut prompts);
+    let keyword = prompts.found?;
+
+    // Only prompt text and interpolations can move, not `do` blocks,
+    // examples, or variants
+    let wrapped = format!("skill __extracted__() {{\n{} {{\n{}\n}}\n}}", keyword, lines);
+    let extracted = parse(&wrapped).ok()?;
+    let Some(Item::Skill(decl)) = extracted.items.first() else {
+        return None;
+    };
+    let [Statement::Expr(Expr::Think(prompt) | Expr::Ask(prompt))] = decl.body.statements.as_slice() else {
+        return None;
+    };
+    let plain = |item: &PromptItem| matches!(item, PromptItem::Text(_) | PromptItem::Interpolation(_));
+    if !prompt.items.iter().all(plain) {
+        return None;
+    }
+
+    let symbols = resolve(&program, source).symbols;
+    let (params, _) = free_variables(&symbols, paragraph)?;
+    let name = fresh_name(&symbols, "extracted_skill");
+    let indent = &lines[..lines.len() - lines.trim_start().len()];
+    let unit = indent_unit(source);
+    let body = reindent(lines, indent, &unit.repeat(2));
+    let skill = format!(
+        "skill {}({}) {{\n{}{} {{\n{}{}}}\n}}\n",
+        name,
+        params.join(", "),
+        unit,
+        keyword,
+        body,
+        unit
+    );
+    let call = format!("${{{}({})}}", name, params.join(", "));
+    finish(source, format!("extract into skill `{}`", name), paragraph, call, skill)
+}
+
+/// The whole lines the byte range `(start, end)` touches, without the
+/// final newline, or `None` if they are blank.
+fn selected_lines(source: &str, (start, end): (usize, usize)) -> Option<(usize, usize)> {
+    if start >= end || end > source.len() || !source.is_char_boundary(start) || !source.is_char_boundary(end) {
+        return None;
+    }
+    // A selection ending at the start of a line doesn't include that line
+    let end = if source[..end].ends_with('\n') { end - 1 } else { end };
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[end..].find('\n').map_or(source.len(), |i| end + i);
+    (!source[line_start..line_end].trim().is_empty()).then_some((line_start, line_end))
+}
+
+/// The local variables read in `range` but declared before it, in order of
+/// first use, and those declared in it and read after it. `None` if code in
+/// `range` assigns to a variable declared outside it.
+fn free_variables(symbols: &SymbolTable, (start, end): (usize, usize)) -> Option<(Vec<String>, Vec<String>)> {
+    let inside = |span: Option<(usize, usize)>| span.is_some_and(|(s, e)| start <= s && e <= end);
+    let mut params: Vec<String> = Vec::new();
+    let mut outputs: Vec<String> = Vec::new();
+    for reference in symbols.references() {
+        let Resolution::Local { symbol, .. } = reference.resolution else {
+            continue;
+        };
+        let declared = symbols.symbol(symbol);
+        match (inside(reference.span), inside(declared.span)) {
+            (true, false) if reference.is_write => return None,
+            (true, false) if !params.contains(&reference.name) => params.push(reference.name.clone()),
+            (false, true)
+                if reference.span.is_some_and(|(s, _)| s >= end) && !outputs.contains(&declared.name) =>
+            {
+                outputs.push(declared.name.clone())
+            }
+            _ => {}
+        }
+    }
+    Some((params, outputs))
+}
+
+/// `base`, or `base_2`, `base_3`, ... if something is already called that.
+fn fresh_name(symbols: &SymbolTable, base: &str) -> String {
+    let taken = |name: &str| symbols.symbols().iter().any(|s| s.name == name);
+    if !taken(base) {
+        return base.to_string();
+    }
+    (2..).map(|n| format!("{}_{}", base, n)).find(|name| !taken(name)).expect("some name is free")
+}
+
+/// The indentation the file uses for one level, or four spaces.
+fn indent_unit(source: &str) -> &str {
+    source
+        .lines()
+        .map(|line| &line[..line.len() - line.trim_start().len()])
+        .find(|indent| !indent.is_empty())
+        .map(|indent| if indent.starts_with('\t') { "\t" } else { indent })
+        .unwrap_or("    ")
+}
+
+/// `lines` with `indent` taken off the front of each and `prefix` put in
+/// its place, each ending in a newline.
+fn reindent(lines: &str, indent: &str, prefix: &str) -> String {
+    lines
+        .lines()
+        .map(|line| match line.strip_prefix(indent) {
+            _ if line.trim().is_empty() => "\n".to_string(),
+            Some(rest) => format!("{}{}\n", prefix, rest),
+            None => format!("{}{}\n", prefix, line.trim_start()),
+        })
+        .collect()
+}
+
+/// Replace `range` with `call` and add `item` at the end of the file, if
+/// the result still parses.
+fn finish(source: &str, message: String, range: (usize, usize), call: String, item: String) -> Option<Fix> {
+    let separator = if source.ends_with('\n') { "\n" } else { "\n\n" };
+    let edits = vec![
+        Edit { span: range, replacement: call },
+        Edit { span: (source.len(), source.len()), replacement: format!("{}{}", separator, item) },
+    ];
+    let fix = Fix::new(message, edits);
+    let (result, _) = crate::fix::apply_fixes(source, std::slice::from_ref(&fix));
+    parse(&result).is_ok().then_some(fix)
+}
+
+/// Notes whether any block returns or succeeds.
+struct Exits(bool);
+
+impl<'input> Visitor<'input> for Exits {
+    fn block(&mut self, block: &Block<'input>) {
+        self.0 |= block.statements.iter().any(|s| matches!(s, Statement::Return(_) | Statement::Succeed));
+    }
+}
+
+/// Finds the `think` or `ask` prompt whose text holds `paragraph`.
+struct Prompts<'s> {
+    source: &'s str,
+    found: Option<&'static str>,
+    paragraph: (usize, usize),
+}
+
+impl<'input> Visitor<'input> for Prompts<'_> {
+    fn expr(&mut self, expr: &Expr<'input>) {
+        let (keyword, prompt) = match expr {
+            Expr::Think(prompt) => ("think", prompt),
+            Expr::Ask(prompt) => ("ask", prompt),
+            _ => return,
+        };
+        let base = self.source.as_ptr() as usize;
+        let spans: Vec<(usize, usize)> = prompt
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                PromptItem::Text(text) => {
+                    let start = (text.as_ptr() as usize).checked_sub(base)?;
+                    Some((start, start + text.len()))
+                }
+                _ => None,
+            })
+            .collect();
+        let (Some(first), Some(last)) = (spans.first(), spans.last()) else {
+            return;
+        };
+        if first.0 <= self.paragraph.0 && self.paragraph.1 <= last.1 {
+            self.found = Some(keyword);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fix::apply_fixes;
+
+    fn apply(source: &str, fix: Option<Fix>) -> String {
+        let fix = fix.expect("the refactoring should apply");
+        apply_fixes(source, std::slice::from_ref(&fix)).0
+    }
+
+    fn select(source: &str, from: &str, to: &str) -> (usize, usize) {
+        let start = source.find(from).unwrap();
+        let end = source[start..].find(to).unwrap() + start + to.len();
+        (start, end)
+    }
+
+    #[test]
+    fn test_extract_function() {
+        let source = "skill main(items) {\n    var total = len(items)\n    var doubled = total * 2\n    print(doubled)\n}\n";
+        let fix = extract_function(source, select(source, "var doubled", "* 2"));
+        assert_eq!(
+            apply(source, fix),
+            "skill main(items) {\n    var total = len(items)\n    var doubled = extracted(total)\n    print(doubled)\n}\n\n\
+             fun extracted(total) {\n    var doubled = total * 2\n    return doubled\n}\n"
+        );
+
+        let fix = extract_function(source, select(source, "print", ")"));
+        assert_eq!(
+            apply(source, fix),
+            "skill main(items) {\n    var total = len(items)\n    var doubled = total * 2\n    extracted(doubled)\n}\n\n\
+             fun extracted(doubled) {\n    print(doubled)\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_extract_function_refusals() {
+        // Assigns to a variable declared outside the selection
+        let source = "skill main() {\n  var n = 0\n  n = n + 1\n  print(n)\n}\n";
+        assert!(extract_function(source, select(source, "n = n", "1")).is_none());
+        // Leaves the function early
+        let source = "fun f(x) {\n  if x { return 1 }\n  return 2\n}\n";
+        assert!(extract_function(source, select(source, "if x", "}")).is_none());
+        // Half of a statement
+        let source = "fun f(x) {\n  if x {\n    print(x)\n  }\n}\n";
+        assert!(extract_function(source, select(source, "if x", "print")).is_none());
+        // Two variables used afterwards
+        let source = "fun f() {\n  var a = 1\n  var b = 2\n  print(a + b)\n}\n";
+        assert!(extract_function(source, select(source, "var a", "= 2")).is_none());
+    }
+
+    #[test]
+    fn test_extract_skill() {
+        let source = "skill main(task) {\n  var plan = think {\n    Plan the work.\n\n    Consider ${task} carefully.\n  }\n}\n";
+        let fix = extract_skill(source, select(source, "Consider", "carefully"));
+        assert_eq!(
+            apply(source, fix),
+            "skill main(task) {\n  var plan = think {\n    Plan the work.\n\n    ${extracted_skill(task)}\n  }\n}\n\n\
+             skill extracted_skill(task) {\n  think {\n    Consider ${task} carefully.\n  }\n}\n"
+        );
+
+        // Code isn't prompt text
+        assert!(extract_skill(source, select(source, "var plan", "{")).is_none());
+    }
+}
@@ -0,0 +1,425 @@
+//! The built-in rules.
+
+use patchwork_parser::ast::*;
+use patchwork_parser::diagnostics::{Diagnostic, Fix};
+use patchwork_parser::resolve::{SymbolId, SymbolKind};
+
+use crate::walk::{first_text, walk_program, Visitor};
+use crate::{LintContext, Rule};
+
+/// A local variable declared with the same name as one already in scope.
+pub struct Shadowing;
+
+impl Rule for Shadowing {
+    fn name(&self) -> &'static str {
+        "shadowing"
+    }
+
+    fn description(&self) -> &'static str {
+        "a variable hides another variable with the same name"
+    }
+
+    fn check(&self, cx: &LintContext, report: &mut Vec<Diagnostic>) {
+        struct Scopes<'a, 'input> {
+            cx: &'a LintContext<'a, 'input>,
+            scopes: Vec<Vec<&'input str>>,
+            report: &'a mut Vec<Diagnostic>,
+        }
+
+        impl<'input> Visitor<'input> for Scopes<'_, 'input> {
+            fn enter_scope(&mut self) {
+                self.scopes.push(Vec::new());
+            }
+
+            fn exit_scope(&mut self) {
+                self.scopes.pop();
+            }
+
+            fn declare(&mut self, name: &'input str) {
+                let earlier = self.scopes.iter().flatten().rev().find(|earlier| **earlier == name);
+                if let (Some(earlier), Some(span)) = (earlier, self.cx.span(name)) {
+                    let mut diagnostic = Diagnostic::warning(format!("`{}` shadows an earlier variable", name))
+                        .with_label(span, "declared again here");
+                    if let Some(earlier) = self.cx.span(earlier) {
+                        diagnostic = diagnostic.with_label(earlier, "first declared here");
+                    }
+                    self.report.push(diagnostic.with_help("rename one of the variables"));
+                }
+                if let Some(scope) = self.scopes.last_mut() {
+                    scope.push(name);
+                }
+            }
+        }
+
+        walk_program(cx.program.program, &mut Scopes { cx, scopes: Vec::new(), report });
+    }
+}
+
+/// An import whose name is never used.
+pub struct UnusedImport;
+
+impl Rule for UnusedImport {
+    fn name(&self) -> &'static str {
+        "unused-import"
+    }
+
+    fn description(&self) -> &'static str {
+        "an imported name is never used"
+    }
+
+    fn check(&self, cx: &LintContext, report: &mut Vec<Diagnostic>) {
+        let symbols = &cx.program.symbols;
+        for (index, symbol) in symbols.symbols().iter().enumerate() {
+            if symbol.kind != SymbolKind::Import {
+                continue;
+            }
+            let id = SymbolId(index);
+            if symbols.references_to(id).next().is_some() {
+                continue;
+            }
+            let mut diagnostic = Diagnostic::warning(format!("`{}` is imported but never used", symbol.name));
+            if let Some(span) = symbol.span {
+                diagnostic = diagnostic.with_label(span, "unused import");
+                if let Some(fix) = remove_import(cx.source, span) {
+                    diagnostic = diagnostic.with_fix(fix);
+                }
+            }
+            report.push(diagnostic.with_help("remove the import"));
+        }
+    }
+}
+
+/// The fix removing the imported name at `span`: the name and a comma from
+/// a braced list, or the whole import when it names nothing else.
+fn remove_import(source: &str, (start, end): (usize, usize)) -> Option<Fix> {
+    // The keyword starts its line, unlike `import` in a path like `./imports/x`
+    let keyword = source[..start].rmatch_indices("import").map(|(i, _)| i).find(|&i| {
+        let line_start = source[..i].rfind('\n').map_or(0, |n| n + 1);
+        source[line_start..i].trim().is_empty()
+    })?;
+    let head = &source[keyword..start];
+    let statement_end = if let Some(open) = head.rfind('{') {
+        let open = keyword + open;
+        let close = end + source[end..].find('}')?;
+        let names = source[open + 1..close].split(',').filter(|name| !name.trim().is_empty()).count();
+        if names > 1 {
+            let after = &source[end..close];
+            if let Some(rest) = after.trim_start().strip_prefix(',') {
+                // `a, b` loses `a, `
+                let skip = after.len() - rest.trim_start().len();
+                return Some(Fix::replace("remove the import", (start, end + skip), ""));
+            }
+            // The last name loses the comma before it
+            let comma = open + source[open..start].rfind(',')?;
+            return Some(Fix::replace("remove the import", (comma, end), ""));
+        }
+        close + 1
+    } else if head.contains('"') {
+        // A data import: the name is part of the quoted path
+        end + source[end..].find('"')? + 1
+    } else {
+        end
+    };
+
+    // Take the whole line when the import is alone on it
+    let line_start = source[..keyword].rfind('\n').map_or(0, |n| n + 1);
+    let line_end = source[statement_end..].find('\n').map_or(source.len(), |n| statement_end + n + 1);
+    let span = if source[line_start..keyword].trim().is_empty() && source[statement_end..line_end].trim().is_empty() {
+        (line_start, line_end)
+    } else {
+        (keyword, statement_end)
+    };
+    Some(Fix::replace("remove the import", span, ""))
+}
+
+/// A `think` or `ask` block with no prompt in it.
+pub struct EmptyPrompt;
+
+impl Rule for EmptyPrompt {
+    fn name(&self) -> &'static str {
+        "empty-prompt"
+    }
+
+    fn description(&self) -> &'static str {
+        "a `think` or `ask` block has no text or interpolations"
+    }
+
+    fn check(&self, cx: &LintContext, report: &mut Vec<Diagnostic>) {
+        struct Prompts<'a, 'input> {
+            cx: &'a LintContext<'a, 'input>,
+            report: &'a mut Vec<Diagnostic>,
+        }
+
+        impl<'input> Visitor<'input> for Prompts<'_, 'input> {
+            fn expr(&mut self, expr: &Expr<'input>) {
+                let (keyword, prompt) = match expr {
+                    Expr::Think(prompt) => ("think", prompt),
+                    Expr::Ask(prompt) => ("ask", prompt),
+                    _ => return,
+                };
+                let empty = prompt.items.iter().all(|item| matches!(item, PromptItem::Text(text) if text.trim().is_empty()));
+                if !empty {
+                    return;
+                }
+                let mut diagnostic = Diagnostic::warning(format!("empty `{}` block", keyword))
+                    .with_help("an empty prompt still costs a model call; add instructions or remove it");
+                let at = prompt.items.iter().find_map(|item| match item {
+                    PromptItem::Text(text) => self.cx.span(text),
+                    _ => None,
+                });
+                if let Some(span) = at {
+                    diagnostic = diagnostic.with_label(span, "nothing to send to the model");
+                }
+                self.report.push(diagnostic);
+            }
+        }
+
+        walk_program(cx.program.program, &mut Prompts { cx, report });
+    }
+}
+
+/// `x == null` or `x != null`. Patchwork has no `null` literal, so these
+/// look up a variable called `null`; `is_null(x)` is the test that works.
+pub struct NullComparison;
+
+impl Rule for NullComparison {
+    fn name(&self) -> &'static str {
+        "null-comparison"
+    }
+
+    fn description(&self) -> &'static str {
+        "a value is compared with `null`, which is not a literal; use `is_null`"
+    }
+
+    fn check(&self, cx: &LintContext, report: &mut Vec<Diagnostic>) {
+        struct Comparisons<'a, 'input> {
+            cx: &'a LintContext<'a, 'input>,
+            report: &'a mut Vec<Diagnostic>,
+        }
+
+        impl<'input> Visitor<'input> for Comparisons<'_, 'input> {
+            fn expr(&mut self, expr: &Expr<'input>) {
+                let Expr::Binary { op: op @ (BinOp::Eq | BinOp::NotEq), left, right } = expr else {
+                    return;
+                };
+                let null_name = |expr: &Expr<'input>| match expr {
+                    Expr::Identifier(name) if *name == "null" => Some(*name),
+                    _ => None,
+                };
+                let (value, null) = match (null_name(left), null_name(right)) {
+                    (_, Some(null)) => (left.as_ref(), null),
+                    (Some(null), None) => (right.as_ref(), null),
+                    (None, None) => return,
+                };
+                let not = if *op == BinOp::NotEq { "!" } else { "" };
+                let mut diagnostic = Diagnostic::warning("comparison with `null`, which is not a value")
+                    .with_help(format!("use `{}is_null(...)`", not));
+                let Some(null_span) = self.cx.span(null) else {
+                    self.report.push(diagnostic);
+                    return;
+                };
+                diagnostic = diagnostic.with_label(null_span, "looked up as a variable");
+                // Only operands whose whole text is known can be moved into the call
+                if let Some(value_span) = operand_span(self.cx, value) {
+                    let span = (value_span.0.min(null_span.0), value_span.1.max(null_span.1));
+                    let call = format!("{}is_null({})", not, &self.cx.source[value_span.0..value_span.1]);
+                    diagnostic = diagnostic.with_fix(Fix::replace(format!("use `{}is_null`", not), span, call));
+                }
+                self.report.push(diagnostic);
+            }
+        }
+
+        // A program that declares `null` means its own variable
+        if cx.program.symbols.symbols().iter().any(|symbol| symbol.name == "null") {
+            return;
+        }
+        walk_program(cx.program.program, &mut Comparisons { cx, report });
+    }
+}
+
+/// The source range of a name, number, or field access like `a.b.c`.
+fn operand_span(cx: &LintContext, expr: &Expr) -> Option<(usize, usize)> {
+    match expr {
+        Expr::Identifier(text) | Expr::Number(text) => cx.span(text),
+        Expr::Member { object, field } => Some((operand_span(cx, object)?.0, cx.span(field)?.1)),
+        _ => None,
+    }
+}
+
+/// Statements after a `return`, `break`, `succeed`, or `throw` in the same
+/// block.
+pub struct UnreachableCode;
+
+impl Rule for UnreachableCode {
+    fn name(&self) -> &'static str {
+        "unreachable-code"
+    }
+
+    fn description(&self) -> &'static str {
+        "a statement can never run because an earlier one always leaves the block"
+    }
+
+    fn check(&self, cx: &LintContext, report: &mut Vec<Diagnostic>) {
+        struct Blocks<'a, 'input> {
+            cx: &'a LintContext<'a, 'input>,
+            report: &'a mut Vec<Diagnostic>,
+        }
+
+        impl<'input> Visitor<'input> for Blocks<'_, 'input> {
+            fn block(&mut self, block: &Block<'input>) {
+                let Some(exit) = block.statements.iter().position(leaves_block) else {
+                    return;
+                };
+                let Some(next) = block.statements.get(exit + 1) else {
+                    return;
+                };
+                let mut diagnostic = Diagnostic::warning("unreachable statement");
+                if let Some(span) = first_text(next).and_then(|text| self.cx.span(text)) {
+                    diagnostic = diagnostic.with_label(span, "this never runs");
+                }
+                self.report.push(diagnostic.with_note(format!(
+                    "the `{}` before it always leaves the block",
+                    exit_keyword(&block.statements[exit])
+                )));
+            }
+        }
+
+        walk_program(cx.program.program, &mut Blocks { cx, report });
+    }
+}
+
+fn leaves_block(statement: &Statement) -> bool {
+    matches!(
+        statement,
+        Statement::Return(_)
+            | Statement::Break
+            | Statement::Succeed
+            | Statement::Expr(Expr::Unary { op: UnOp::Throw, .. })
+    )
+}
+
+fn exit_keyword(statement: &Statement) -> &'static str {
+    match statement {
+        Statement::Return(_) => "return",
+        Statement::Break => "break",
+        Statement::Succeed => "succeed",
+        _ => "throw",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use patchwork_parser::parse;
+    use patchwork_parser::resolve::resolve;
+
+    fn run(rule: impl Rule, source: &str) -> Vec<Diagnostic> {
+        let program = parse(source).unwrap();
+        let resolved = resolve(&program, source);
+        let mut report = Vec::new();
+        rule.check(&LintContext::new(&resolved, source), &mut report);
+        report
+    }
+
+    fn spans(report: &[Diagnostic], source: &str) -> Vec<String> {
+        report
+            .iter()
+            .map(|d| {
+                let (start, end) = d.primary_span().unwrap();
+                source[start..end].to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_shadowing() {
+        let source = r#"
+skill main(items) {
+  var total = 0
+  for var item in items {
+    var total = 1
+    var item = 2
+  }
+  if true {
+    var fresh = 1
+  }
+  var fresh = 2
+}
+fun other(total) {}
+"#;
+        let report = run(Shadowing, source);
+        assert_eq!(report.len(), 2);
+        assert_eq!(report[0].message, "`total` shadows an earlier variable");
+        assert_eq!(report[0].labels.len(), 2);
+        assert_eq!(report[1].message, "`item` shadows an earlier variable");
+    }
+
+    #[test]
+    fn test_unused_import() {
+        let source = "import ./{used, unused}\nskill main() {\n  used.run()\n}\n";
+        let report = run(UnusedImport, source);
+        assert_eq!(spans(&report, source), vec!["unused"]);
+    }
+
+    #[test]
+    fn test_empty_prompt() {
+        let source = "skill main(x) {\n  think {\n  }\n  ask { $x }\n  think { Summarize. }\n}\n";
+        let report = run(EmptyPrompt, source);
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].message, "empty `think` block");
+    }
+
+    #[test]
+    fn test_unused_import_fixes() {
+        let fixed = |source: &str| {
+            let report = run(UnusedImport, source);
+            let fixes: Vec<Fix> = report.into_iter().filter_map(|d| d.fix).collect();
+            crate::fix::apply_fixes(source, &fixes[..1]).0
+        };
+        let body = "skill main() {\n  used.run()\n}\n";
+        assert_eq!(fixed(&format!("import ./{{unused, used}}\n{}", body)), format!("import ./{{used}}\n{}", body));
+        assert_eq!(fixed(&format!("import ./{{used, unused}}\n{}", body)), format!("import ./{{used}}\n{}", body));
+        assert_eq!(fixed(&format!("import std.log\nimport ./{{used}}\n{}", body)), format!("import ./{{used}}\n{}", body));
+        assert_eq!(
+            fixed(&format!("import ./{{used}}\nimport imports.helpers\n{}", body)),
+            format!("import ./{{used}}\n{}", body)
+        );
+    }
+
+    #[test]
+    fn test_null_comparison() {
+        let source = "skill main(x) {\n  if x.name != null { print(x) }\n  var y = null == x\n  var z = len(x) == null\n}\n";
+        let report = run(NullComparison, source);
+        assert_eq!(spans(&report, source), vec!["null", "null", "null"]);
+        let fixes: Vec<Fix> = report.into_iter().filter_map(|d| d.fix).collect();
+        assert_eq!(fixes.len(), 2);
+        let (fixed, _) = crate::fix::apply_fixes(source, &fixes);
+        assert!(fixed.contains("if !is_null(x.name) {"), "{}", fixed);
+        assert!(fixed.contains("var y = is_null(x)"), "{}", fixed);
+
+        let declared = "var null = 0\nskill main(x) {\n  print(x == null)\n}\n";
+        assert!(run(NullComparison, declared).is_empty());
+    }
+
+    #[test]
+    fn test_unreachable_code() {
+        let source = r#"
+fun f(xs) {
+  for var x in xs {
+    break
+    print(x)
+  }
+  return 1
+  print("after")
+  print("twice")
+}
+fun g() {
+  throw "no"
+}
+"#;
+        let report = run(UnreachableCode, source);
+        assert_eq!(spans(&report, source), vec!["print", "print"]);
+        assert_eq!(report[0].notes, vec!["the `return` before it always leaves the block"]);
+        assert_eq!(report[1].notes, vec!["the `break` before it always leaves the block"]);
+    }
+}
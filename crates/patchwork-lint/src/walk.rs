@@ -0,0 +1,226 @@
+//! A read-only walk over the AST for rules that need more than the symbol
+//! table. Scopes are entered and left exactly where the resolver pushes and
+//! pops them, so a rule tracking declarations sees the same nesting.
+
+use patchwork_parser::ast::*;
+
+/// Callbacks for `walk_program`. Every method does nothing by default.
+pub(crate) trait Visitor<'input> {
+    fn enter_scope(&mut self) {}
+    fn exit_scope(&mut self) {}
+    /// A local variable: a parameter, `var` binding, or loop variable.
+    fn declare(&mut self, _name: &'input str) {}
+    /// Called before the block's statements are walked.
+    fn block(&mut self, _block: &Block<'input>) {}
+    /// Called before the expression's children are walked.
+    fn expr(&mut self, _expr: &Expr<'input>) {}
+}
+
+pub(crate) fn walk_program<'input>(program: &Program<'input>, v: &mut impl Visitor<'input>) {
+    for item in &program.items {
+        match item {
+            Item::Skill(SkillDecl { params, body, .. })
+            | Item::Worker(WorkerDecl { params, body, .. })
+            | Item::Function(FunctionDecl { params, body, .. }) => walk_callable(params, body, v),
+            Item::Trait(decl) => {
+                for method in &decl.methods {
+                    walk_callable(&method.params, &method.body, v);
+                }
+            }
+            Item::Var(decl) => walk_expr(&decl.init, v),
+            Item::Eval(decl) => {
+                walk_expr(&decl.input, v);
+                v.enter_scope();
+                v.declare("input");
+                walk_expr(&decl.output, v);
+                v.declare("output");
+                for check in &decl.checks {
+                    match check {
+                        EvalCheck::Contains(expr) | EvalCheck::Judge(expr) => walk_expr(expr, v),
+                        EvalCheck::Expect(_) => {}
+                    }
+                }
+                v.exit_scope();
+            }
+            Item::Import(_) | Item::Type(_) => {}
+        }
+    }
+}
+
+fn walk_callable<'input>(params: &[Param<'input>], body: &Block<'input>, v: &mut impl Visitor<'input>) {
+    v.enter_scope();
+    for param in params {
+        v.declare(param.name);
+    }
+    walk_block(body, v);
+    v.exit_scope();
+}
+
+pub(crate) fn walk_block<'input>(block: &Block<'input>, v: &mut impl Visitor<'input>) {
+    v.block(block);
+    v.enter_scope();
+    for statement in &block.statements {
+        walk_statement(statement, v);
+    }
+    v.exit_scope();
+}
+
+pub(crate) fn walk_statement<'input>(statement: &Statement<'input>, v: &mut impl Visitor<'input>) {
+    match statement {
+        Statement::VarDecl { pattern, init } => {
+            if let Some(init) = init {
+                walk_expr(init, v);
+            }
+            for name in pattern.names() {
+                v.declare(name);
+            }
+        }
+        Statement::Expr(expr) => walk_expr(expr, v),
+        Statement::If { condition, then_block, else_block } => {
+            walk_expr(condition, v);
+            walk_block(then_block, v);
+            if let Some(else_block) = else_block {
+                walk_block(else_block, v);
+            }
+        }
+        Statement::ForIn { var, iter, body } => {
+            walk_expr(iter, v);
+            v.enter_scope();
+            v.declare(var);
+            walk_block(body, v);
+            v.exit_scope();
+        }
+        Statement::While { condition, body } => {
+            walk_expr(condition, v);
+            walk_block(body, v);
+        }
+        Statement::Return(expr) => {
+            if let Some(expr) = expr {
+                walk_expr(expr, v);
+            }
+        }
+        Statement::Plan { steps } => {
+            for step in steps {
+                walk_string(step, v);
+            }
+        }
+        Statement::Succeed | Statement::Break | Statement::TypeDecl { .. } => {}
+    }
+}
+
+pub(crate) fn walk_expr<'input>(expr: &Expr<'input>, v: &mut impl Visitor<'input>) {
+    v.expr(expr);
+    match expr {
+        Expr::Identifier(_) | Expr::Number(_) | Expr::True | Expr::False => {}
+        Expr::String(lit) => walk_string(lit, v),
+        Expr::Array(items) => {
+            for item in items {
+                walk_expr(item, v);
+            }
+        }
+        Expr::Object(fields) => {
+            for value in fields.iter().filter_map(|field| field.value.as_ref()) {
+                walk_expr(value, v);
+            }
+        }
+        Expr::Binary { left, right, .. }
+        | Expr::ShellPipe { left, right }
+        | Expr::ShellAnd { left, right }
+        | Expr::ShellOr { left, right } => {
+            walk_expr(left, v);
+            walk_expr(right, v);
+        }
+        Expr::Unary { operand, .. } => walk_expr(operand, v),
+        Expr::Call { callee, args } => {
+            walk_expr(callee, v);
+            for arg in args {
+                walk_expr(arg, v);
+            }
+        }
+        Expr::Member { object, .. } => walk_expr(object, v),
+        Expr::Index { object, index } => {
+            walk_expr(object, v);
+            walk_expr(index, v);
+        }
+        Expr::PostIncrement(inner)
+        | Expr::PostDecrement(inner)
+        | Expr::Paren(inner)
+        | Expr::Await(inner)
+        | Expr::CommandSubst(inner)
+        | Expr::ShellBackground(inner) => walk_expr(inner, v),
+        Expr::Think(prompt) | Expr::Ask(prompt) | Expr::Approve(prompt) => walk_prompt(prompt, v),
+        Expr::Do(block) => walk_block(block, v),
+        Expr::BareCommand { args, .. } => {
+            for arg in args {
+                if let CommandArg::String(lit) = arg {
+                    walk_string(lit, v);
+                }
+            }
+        }
+        Expr::ShellRedirect { command, target, .. } => {
+            walk_expr(command, v);
+            walk_expr(target, v);
+        }
+    }
+}
+
+fn walk_prompt<'input>(prompt: &PromptBlock<'input>, v: &mut impl Visitor<'input>) {
+    for item in &prompt.items {
+        match item {
+            PromptItem::Text(_) => {}
+            PromptItem::Interpolation(expr) => walk_expr(expr, v),
+            PromptItem::Code(block) => walk_block(block, v),
+            PromptItem::Examples(fields) => {
+                for value in fields.iter().filter_map(|field| field.value.as_ref()) {
+                    walk_expr(value, v);
+                }
+            }
+            PromptItem::Variant { block, .. } => walk_prompt(block, v),
+        }
+    }
+}
+
+fn walk_string<'input>(lit: &StringLiteral<'input>, v: &mut impl Visitor<'input>) {
+    for part in &lit.parts {
+        if let StringPart::Interpolation(expr) = part {
+            walk_expr(expr, v);
+        }
+    }
+}
+
+/// The first piece of source text inside a statement, to point a
+/// diagnostic at. `None` for statements with no text of their own, such as
+/// a bare `break`.
+pub(crate) fn first_text<'input>(statement: &Statement<'input>) -> Option<&'input str> {
+    #[derive(Default)]
+    struct First<'input>(Option<&'input str>);
+
+    impl<'input> Visitor<'input> for First<'input> {
+        fn declare(&mut self, name: &'input str) {
+            self.0.get_or_insert(name);
+        }
+
+        fn expr(&mut self, expr: &Expr<'input>) {
+            let text = match expr {
+                Expr::Identifier(text) | Expr::Number(text) => Some(*text),
+                Expr::BareCommand { name, .. } => Some(*name),
+                Expr::String(lit) => lit.parts.iter().find_map(|part| match part {
+                    StringPart::Text(text) => Some(*text),
+                    StringPart::Interpolation(_) => None,
+                }),
+                Expr::Think(prompt) | Expr::Ask(prompt) | Expr::Approve(prompt) => prompt.items.iter().find_map(|item| match item {
+                    PromptItem::Text(text) => text.lines().map(str::trim).find(|line| !line.is_empty()),
+                    _ => None,
+                }),
+                _ => None,
+            };
+            if let Some(text) = text {
+                self.0.get_or_insert(text);
+            }
+        }
+    }
+
+    let mut first = First::default();
+    walk_statement(statement, &mut first);
+    first.0
+}
@@ -1,4 +1,8 @@
+use patchwork_check::{check_resolved, Binding, BindingKind, CheckResult};
+use patchwork_lint::{extract_function, extract_skill, parse_error_fix, Linter};
+use patchwork_parser::diagnostics::Fix;
 use patchwork_parser::parse;
+use patchwork_parser::resolve::{resolve, SymbolTable};
 use patchwork_parser::ParseError;
 use regex::Regex;
 use once_cell::sync::Lazy;
@@ -23,7 +27,7 @@ impl Backend {
     }
 
     async fn publish_diagnostics(&self, uri: Url, text: String) {
-        let diagnostics = compute_diagnostics(&text);
+        let diagnostics = compute_diagnostics(&uri, &text);
         let _ = self
             .client
             .publish_diagnostics(uri, diagnostics, None)
@@ -41,6 +45,10 @@ impl LanguageServer for Backend {
                 )),
                 hover_provider: Some(HoverProviderCapability::Simple(true)),
                 completion_provider: Some(CompletionOptions::default()),
+                inlay_hint_provider: Some(OneOf::Left(true)),
+                definition_provider: Some(OneOf::Left(true)),
+                references_provider: Some(OneOf::Left(true)),
+                code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
                 ..ServerCapabilities::default()
             },
             server_info: Some(ServerInfo {
@@ -94,7 +102,8 @@ impl LanguageServer for Backend {
         };
 
         if let Some((range, word)) = word_at_position(&text, position) {
-            let contents = hover_contents_for(&word);
+            let binding = binding_at(&text, position_to_byte_offset(&text, position));
+            let contents = hover_contents_for(&word, binding.as_ref());
             return Ok(Some(Hover {
                 contents,
                 range: Some(range),
@@ -135,30 +144,252 @@ impl LanguageServer for Backend {
 
         Ok(Some(CompletionResponse::Array(items)))
     }
+
+    async fn inlay_hint(
+        &self,
+        params: InlayHintParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<Vec<InlayHint>>> {
+        let docs = self.documents.read().await;
+        let Some(text) = docs.get(&params.text_document.uri) else {
+            return Ok(None);
+        };
+        let Some(check) = check_text(text) else {
+            return Ok(None);
+        };
+        Ok(Some(type_inlay_hints(text, &check)))
+    }
+
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<GotoDefinitionResponse>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let docs = self.documents.read().await;
+        let Some(text) = docs.get(&uri) else {
+            return Ok(None);
+        };
+        let Some(symbols) = resolve_text(text) else {
+            return Ok(None);
+        };
+
+        let offset = position_to_byte_offset(text, position);
+        let span = symbols
+            .symbol_at(offset)
+            .and_then(|id| symbols.symbol(id).span);
+        Ok(span.map(|span| {
+            GotoDefinitionResponse::Scalar(Location::new(uri.clone(), span_to_range(text, span)))
+        }))
+    }
+
+    async fn references(
+        &self,
+        params: ReferenceParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<Vec<Location>>> {
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+
+        let docs = self.documents.read().await;
+        let Some(text) = docs.get(&uri) else {
+            return Ok(None);
+        };
+        let Some(symbols) = resolve_text(text) else {
+            return Ok(None);
+        };
+
+        let offset = position_to_byte_offset(text, position);
+        let Some(id) = symbols.symbol_at(offset) else {
+            return Ok(None);
+        };
+
+        let mut spans = Vec::new();
+        if params.context.include_declaration {
+            spans.extend(symbols.symbol(id).span);
+        }
+        spans.extend(symbols.references_to(id).filter_map(|r| r.span));
+        Ok(Some(
+            spans
+                .into_iter()
+                .map(|span| Location::new(uri.clone(), span_to_range(text, span)))
+                .collect(),
+        ))
+    }
+
+    async fn code_action(
+        &self,
+        params: CodeActionParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<CodeActionResponse>> {
+        let uri = params.text_document.uri;
+        let docs = self.documents.read().await;
+        let Some(text) = docs.get(&uri) else {
+            return Ok(None);
+        };
+        let range = (
+            position_to_byte_offset(text, params.range.start),
+            position_to_byte_offset(text, params.range.end),
+        );
+        Ok(Some(code_actions(&uri, text, range)))
+    }
 }
 
-fn compute_diagnostics(text: &str) -> Vec<Diagnostic> {
-    match parse(text) {
-        Ok(_) => Vec::new(),
-        Err(err) => vec![diagnostic_from_error(err, text)],
+/// Quick fixes for the findings touching `range`, one action applying
+/// every fix in the file, and the refactorings that apply to the selection.
+fn code_actions(uri: &Url, text: &str, (start, end): (usize, usize)) -> CodeActionResponse {
+    let touches = |span: Option<(usize, usize)>| span.is_some_and(|(s, e)| s <= end && start <= e);
+    let linter = Linter::default();
+    let fixes: Vec<Fix> = match linter.lint_source(text) {
+        Ok(diagnostics) => diagnostics
+            .into_iter()
+            .filter(|d| touches(d.primary_span()))
+            .filter_map(|d| d.fix)
+            .collect(),
+        Err(err) => parse_error_fix(&err, text)
+            .filter(|_| touches(err.span()))
+            .into_iter()
+            .collect(),
+    };
+    let mut actions: CodeActionResponse = fixes
+        .into_iter()
+        .map(|fix| code_action_for(uri, text, fix, CodeActionKind::QUICKFIX))
+        .collect();
+
+    let fixed = linter.fix(text);
+    if fixed.applied.len() > 1 {
+        let fix = Fix::replace("Fix all auto-fixable problems", (0, text.len()), fixed.source);
+        actions.push(code_action_for(uri, text, fix, CodeActionKind::SOURCE_FIX_ALL));
+    }
+
+    for fix in [extract_function(text, (start, end)), extract_skill(text, (start, end))].into_iter().flatten() {
+        actions.push(code_action_for(uri, text, fix, CodeActionKind::REFACTOR_EXTRACT));
+    }
+    actions
+}
+
+fn code_action_for(uri: &Url, text: &str, fix: Fix, kind: CodeActionKind) -> CodeActionOrCommand {
+    let edits = fix
+        .edits
+        .into_iter()
+        .map(|edit| TextEdit::new(span_to_range(text, edit.span), edit.replacement))
+        .collect();
+    let mut title = fix.message;
+    if let Some(first) = title.get(..1) {
+        title = first.to_uppercase() + &title[1..];
+    }
+    // Refactorings are a choice, not a fix for a problem
+    let is_preferred = kind != CodeActionKind::REFACTOR_EXTRACT;
+    CodeActionOrCommand::CodeAction(CodeAction {
+        title,
+        kind: Some(kind),
+        edit: Some(WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), edits)])),
+            ..WorkspaceEdit::default()
+        }),
+        is_preferred: Some(is_preferred),
+        ..CodeAction::default()
+    })
+}
+
+/// Names the interpreter provides without a declaration the resolver can see.
+const RUNTIME_NAMES: &[&str] = &["session", "artifact"];
+
+fn compute_diagnostics(uri: &Url, text: &str) -> Vec<Diagnostic> {
+    let program = match parse(text) {
+        Ok(program) => program,
+        Err(err) => return vec![diagnostic_from_error(err, text)],
+    };
+    let resolved = resolve(&program, text);
+    let mut found = undefined_names(&resolved.symbols);
+    found.extend(invalid_annotations(&resolved.symbols));
+    found.extend(check_resolved(&resolved).diagnostics);
+    found.extend(patchwork_lint::lint(&resolved, text));
+    found.sort_by_key(|d| d.primary_span().map_or(0, |(start, _)| start));
+    found.into_iter().map(|diagnostic| diagnostic_from_lint(diagnostic, uri, text)).collect()
+}
+
+/// A diagnostic for every name the resolver couldn't find a declaration
+/// for. Hosts can register functions the editor can't see, so these are
+/// warnings rather than errors.
+fn undefined_names(symbols: &SymbolTable) -> Vec<patchwork_parser::diagnostics::Diagnostic> {
+    symbols
+        .unresolved()
+        .filter(|reference| !RUNTIME_NAMES.contains(&reference.name.as_str()))
+        .filter_map(|reference| {
+            let span = reference.span?;
+            let diagnostic = patchwork_parser::diagnostics::Diagnostic::warning(format!(
+                "Undefined name `{}`",
+                reference.name
+            ));
+            Some(diagnostic.with_code("undefined-name").with_label(span, ""))
+        })
+        .collect()
+}
+
+/// An error for every skill annotation, such as `@timeout(soon)`, whose
+/// arguments the resolver couldn't read. The skill fails to run with it.
+fn invalid_annotations(symbols: &SymbolTable) -> Vec<patchwork_parser::diagnostics::Diagnostic> {
+    symbols
+        .invalid_annotations()
+        .iter()
+        .filter_map(|invalid| {
+            let diagnostic = patchwork_parser::diagnostics::Diagnostic::error(invalid.message.clone());
+            Some(diagnostic.with_code("invalid-annotation").with_label(invalid.span?, ""))
+        })
+        .collect()
+}
+
+/// Convert a lint, checker, or resolver diagnostic. Labels after the first
+/// become related information, such as where a misused name is declared.
+fn diagnostic_from_lint(lint: patchwork_parser::diagnostics::Diagnostic, uri: &Url, text: &str) -> Diagnostic {
+    let (start, end) = lint.primary_span().unwrap_or((0, 0));
+    let severity = match lint.severity {
+        patchwork_parser::diagnostics::Severity::Error => DiagnosticSeverity::ERROR,
+        patchwork_parser::diagnostics::Severity::Warning => DiagnosticSeverity::WARNING,
+        patchwork_parser::diagnostics::Severity::Note => DiagnosticSeverity::INFORMATION,
+    };
+    let mut message = lint.message;
+    for note in lint.notes {
+        message.push_str(&format!("\nnote: {}", note));
+    }
+    for help in lint.help {
+        message.push_str(&format!("\nhelp: {}", help));
+    }
+    let related: Vec<DiagnosticRelatedInformation> = lint
+        .labels
+        .iter()
+        .skip(1)
+        .map(|label| DiagnosticRelatedInformation {
+            location: Location::new(uri.clone(), span_to_range(text, label.span)),
+            message: label.message.clone(),
+        })
+        .collect();
+
+    Diagnostic {
+        range: Range {
+            start: byte_offset_to_position(text, start),
+            end: byte_offset_to_position(text, end.max(start + 1)),
+        },
+        severity: Some(severity),
+        code: lint.code.map(NumberOrString::String),
+        code_description: None,
+        source: Some("patchwork".to_string()),
+        message,
+        related_information: (!related.is_empty()).then_some(related),
+        tags: None,
+        data: None,
     }
 }
 
 fn diagnostic_from_error(err: ParseError, text: &str) -> Diagnostic {
-    let (message, byte_offset, span) = match err {
-        ParseError::LexerError {
-            message,
-            byte_offset,
-            span,
-        } => (message, byte_offset, span),
-        ParseError::UnexpectedToken {
-            message,
-            byte_offset,
-            span,
-        } => (message, byte_offset, span),
+    let mut message = match &err {
+        ParseError::Lex(lex) => lex.message.clone(),
+        other => other.to_string(),
     };
+    if let Some(hint) = err.hint() {
+        message = format!("{}\nhelp: {}", message, hint);
+    }
 
-    let range = if let Some((start, end)) = span {
+    let range = if let Some((start, end)) = err.span() {
         let start_pos = byte_offset_to_position(text, start);
         // Ensure the range spans at least one character to avoid zero-length diagnostics.
         let end_pos = byte_offset_to_position(text, if end <= start { start + 1 } else { end });
@@ -166,9 +397,6 @@ fn diagnostic_from_error(err: ParseError, text: &str) -> Diagnostic {
             start: start_pos,
             end: end_pos,
         }
-    } else if let Some(pos) = byte_offset {
-        let p = byte_offset_to_position(text, pos);
-        Range { start: p, end: p }
     } else {
         Range {
             start: Position::new(0, 0),
@@ -227,6 +455,33 @@ fn byte_offset_to_position(text: &str, byte_offset: usize) -> Position {
     Position::new(line as u32, col as u32)
 }
 
+fn position_to_byte_offset(text: &str, position: Position) -> usize {
+    let mut line = 0;
+    let mut col = 0;
+    for (idx, ch) in text.char_indices() {
+        if line == position.line && col == position.character {
+            return idx;
+        }
+        if ch == '\n' {
+            if line == position.line {
+                return idx;
+            }
+            line += 1;
+            col = 0;
+        } else {
+            col += 1;
+        }
+    }
+    text.len()
+}
+
+fn span_to_range(text: &str, (start, end): (usize, usize)) -> Range {
+    Range {
+        start: byte_offset_to_position(text, start),
+        end: byte_offset_to_position(text, end),
+    }
+}
+
 fn word_at_position(text: &str, position: Position) -> Option<(Range, String)> {
     let Position { line, character } = position;
     let line = line as usize;
@@ -288,7 +543,7 @@ fn prefix_at_position(text: &str, position: Position) -> Option<(Range, String)>
 
 static IDENT_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"[A-Za-z_][A-Za-z0-9_]*").unwrap());
 static KEYWORDS: &[&str] = &[
-    "worker", "trait", "skill", "task", "fun", "type", "var", "if", "else", "for", "while",
+    "worker", "trait", "skill", "task", "fun", "type", "var", "const", "if", "else", "for", "while",
     "await", "return", "succeed", "fail", "break", "continue", "import", "from", "export",
     "think", "ask", "do", "self", "true", "false",
 ];
@@ -301,14 +556,67 @@ fn collect_identifiers(text: &str) -> Vec<String> {
     seen.into_iter().collect()
 }
 
-fn hover_contents_for(symbol: &str) -> HoverContents {
+fn hover_contents_for(symbol: &str, binding: Option<&Binding>) -> HoverContents {
     if KEYWORDS.contains(&symbol) {
-        HoverContents::Scalar(MarkedString::String(format!("keyword `{symbol}`")))
-    } else {
-        HoverContents::Scalar(MarkedString::String(format!("identifier `{symbol}`")))
+        return HoverContents::Scalar(MarkedString::String(format!("keyword `{symbol}`")));
+    }
+
+    match binding.filter(|b| !b.ty.is_unknown()) {
+        Some(binding) => HoverContents::Scalar(MarkedString::String(format!(
+            "`{}: {}`",
+            symbol, binding.ty
+        ))),
+        None => HoverContents::Scalar(MarkedString::String(format!("identifier `{symbol}`"))),
     }
 }
 
+/// Run the type checker over a document, if it parses.
+fn check_text(text: &str) -> Option<CheckResult> {
+    let program = parse(text).ok()?;
+    Some(check_resolved(&resolve(&program, text)))
+}
+
+/// The checker's binding for the symbol declared or referenced at a byte
+/// offset, if the document parses.
+fn binding_at(text: &str, offset: usize) -> Option<Binding> {
+    let program = parse(text).ok()?;
+    let resolved = resolve(&program, text);
+    let id = resolved.symbols.symbol_at(offset)?;
+    check_resolved(&resolved)
+        .bindings
+        .into_iter()
+        .find(|b| b.symbol == Some(id))
+}
+
+/// Resolve names in a document, if it parses.
+fn resolve_text(text: &str) -> Option<SymbolTable> {
+    let program = parse(text).ok()?;
+    Some(resolve(&program, text).symbols)
+}
+
+/// Inlay hints showing inferred types after unannotated variable declarations.
+fn type_inlay_hints(text: &str, check: &CheckResult) -> Vec<InlayHint> {
+    check
+        .bindings
+        .iter()
+        .filter(|b| matches!(b.kind, BindingKind::Var | BindingKind::LoopVar))
+        .filter(|b| !b.annotated && !b.ty.is_unknown())
+        .filter_map(|b| {
+            let (_, end) = b.span?;
+            Some(InlayHint {
+                position: byte_offset_to_position(text, end),
+                label: InlayHintLabel::String(format!(": {}", b.ty)),
+                kind: Some(InlayHintKind::TYPE),
+                text_edits: None,
+                tooltip: None,
+                padding_left: None,
+                padding_right: None,
+                data: None,
+            })
+        })
+        .collect()
+}
+
 fn symbol_kind(name: &str) -> Option<CompletionItemKind> {
     if KEYWORDS.contains(&name) {
         Some(CompletionItemKind::KEYWORD)
@@ -328,6 +636,6 @@ fn symbol_detail(name: &str) -> Option<String> {
 #[tokio::main]
 async fn main() {
     let (stdin, stdout) = (tokio::io::stdin(), tokio::io::stdout());
-    let (service, socket) = LspService::new(|client| Backend::new(client));
+    let (service, socket) = LspService::new(Backend::new);
     Server::new(stdin, stdout, socket).serve(service).await;
 }
@@ -1,71 +1,42 @@
 use patchwork_lexer::{LexerContext, PatchworkToken, Rule};
 use parlex::ParlexError;
 use std::any::Any;
+use std::collections::VecDeque;
 use try_next::TryNextWithContext;
+use crate::error::{LexError, ParseError};
 use crate::token::ParserToken;
 
-/// Build a lookup table of line start byte offsets
-fn build_line_starts(input: &str) -> Vec<usize> {
-    let mut line_starts = vec![0]; // Line 0 starts at byte 0
-    for (i, ch) in input.char_indices() {
-        if ch == '\n' {
-            line_starts.push(i + 1); // Next line starts after the newline
-        }
-    }
-    line_starts
+/// Converts the lexer's line/column positions to byte offsets.
+///
+/// Columns count bytes from the start of the line, so an offset is the
+/// line's start plus the column; only the line starts need computing.
+struct Positions {
+    len: usize,
+    /// Byte offset of the start of each line.
+    line_starts: Vec<usize>,
 }
 
-/// Convert line/column position to byte offset using precomputed line starts
-fn position_to_offset(input: &str, line_starts: &[usize], line: usize, column: usize) -> usize {
-    // Get the start of the requested line
-    let line_start = if line < line_starts.len() {
-        line_starts[line]
-    } else {
-        // Line beyond end of file
-        return input.len();
-    };
+impl Positions {
+    fn new(input: &str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(input.bytes().enumerate().filter(|&(_, b)| b == b'\n').map(|(i, _)| i + 1));
+        Self { len: input.len(), line_starts }
+    }
 
-    // Walk forward by `column` characters from line start
-    let mut col = 0;
-    for (offset, _) in input[line_start..].char_indices() {
-        if col == column {
-            return line_start + offset;
+    /// The byte offset of `column` bytes past the start of `line`, or the
+    /// end of the input if that is past it.
+    fn offset(&self, line: usize, column: usize) -> usize {
+        match self.line_starts.get(line) {
+            Some(&line_start) => (line_start + column).min(self.len),
+            None => self.len,
         }
-        col += 1;
     }
 
-    // Column beyond end of line
-    line_start + input[line_start..].len()
-}
-
-/// Error type for the parser
-#[derive(Debug)]
-pub enum ParseError {
-    LexerError {
-        message: String,
-        byte_offset: Option<usize>,
-        span: Option<(usize, usize)>,
-    },
-    UnexpectedToken {
-        message: String,
-        byte_offset: Option<usize>,
-        span: Option<(usize, usize)>,
-    },
-}
-
-impl std::fmt::Display for ParseError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            ParseError::LexerError { message, .. } => write!(f, "Lexer error: {}", message),
-            ParseError::UnexpectedToken { message, .. } => {
-                write!(f, "Unexpected token: {}", message)
-            }
-        }
+    fn span(&self, span: &parlex::Span) -> (usize, usize) {
+        (self.offset(span.start.line, span.start.column), self.offset(span.end.line, span.end.column))
     }
 }
 
-impl std::error::Error for ParseError {}
-
 /// Adapter that wraps a patchwork lexer and produces tokens in lalrpop format
 /// Implements Iterator<Item = Result<Spanned<ParserToken, usize>, ParseError>>
 pub struct LexerAdapter<'input, L>
@@ -75,8 +46,9 @@ where
     input: &'input str,
     lexer: L,
     context: LexerContext,
-    /// Precomputed byte offsets of line starts for efficient position->offset conversion
-    line_starts: Vec<usize>,
+    positions: Positions,
+    /// Tokens lexed ahead while finding the end of a run of prompt text.
+    pending: VecDeque<Lexed>,
 }
 
 impl<'input, L> LexerAdapter<'input, L>
@@ -84,12 +56,12 @@ where
     L: TryNextWithContext<LexerContext, Item = PatchworkToken, Error: std::fmt::Display>,
 {
     pub fn new(input: &'input str, lexer: L) -> Self {
-        let line_starts = build_line_starts(input);
         Self {
             input,
             lexer,
             context: LexerContext::default(),
-            line_starts,
+            positions: Positions::new(input),
+            pending: VecDeque::new(),
         }
     }
 
@@ -112,11 +84,18 @@ where
             Rule::Dollar => ParserToken::Dollar,
             Rule::Think => ParserToken::Think,
             Rule::Ask => ParserToken::Ask,
+            Rule::Approve => ParserToken::Approve,
             Rule::Do => ParserToken::Do,
+            Rule::Examples => ParserToken::Examples,
+            Rule::Variant => {
+                let name = text["variant".len()..].trim_end_matches('{').trim();
+                ParserToken::Variant(name)
+            }
             Rule::Import => ParserToken::Import,
             Rule::Export => ParserToken::Export,
             Rule::From => ParserToken::From,
             Rule::Var => ParserToken::Var,
+            Rule::Const => ParserToken::Const,
             Rule::If => ParserToken::If,
             Rule::Else => ParserToken::Else,
             Rule::For => ParserToken::For,
@@ -200,13 +179,14 @@ where
     }
 }
 
-impl<'input, L> Iterator for LexerAdapter<'input, L>
+type Lexed = Result<(usize, Rule, usize), ParseError>;
+
+impl<'input, L> LexerAdapter<'input, L>
 where
     L: TryNextWithContext<LexerContext, Item = PatchworkToken, Error: std::fmt::Display + Any + 'static>,
 {
-    type Item = Result<(usize, ParserToken<'input>, usize), ParseError>;
-
-    fn next(&mut self) -> Option<Self::Item> {
+    /// The next token the parser should see, as a rule and byte span.
+    fn lex(&mut self) -> Option<Lexed> {
         loop {
             match self.lexer.try_next_with_context(&mut self.context) {
                 Ok(Some(token)) => {
@@ -215,13 +195,10 @@ where
                         continue;
                     }
 
-                    let span = token.span.unwrap_or_else(|| {
-                        // Default span if none provided
-                        parlex::Span { start: parlex::Position::default(), end: parlex::Position::default() }
-                    });
-                    // Convert line/column positions to byte offsets using cached line starts
-                    let start = position_to_offset(self.input, &self.line_starts, span.start.line, span.start.column);
-                    let end = position_to_offset(self.input, &self.line_starts, span.end.line, span.end.column);
+                    let (start, end) = match &token.span {
+                        Some(span) => self.positions.span(span),
+                        None => (0, 0),
+                    };
 
                     // Workaround for lexer span tracking bug in prompt mode with interpolation
                     // If we get an invalid span, skip this token and try the next one
@@ -230,32 +207,65 @@ where
                         continue;
                     }
 
-                    let parser_token = self.convert_token(token.rule, start, end);
-                    return Some(Ok((start, parser_token, end)));
+                    return Some(Ok((start, token.rule, end)));
                 }
                 Ok(None) => return None,
                 Err(e) => {
-                    let span = extract_span(&e).map(|s| {
-                        let start = position_to_offset(
-                            self.input,
-                            &self.line_starts,
-                            s.start.line,
-                            s.start.column,
-                        );
-                        let end =
-                            position_to_offset(self.input, &self.line_starts, s.end.line, s.end.column);
-                        (start, end)
-                    });
+                    let span = extract_span(&e).map(|s| self.positions.span(&s));
 
-                    return Some(Err(ParseError::LexerError {
-                        message: e.to_string(),
-                        byte_offset: span.as_ref().map(|(s, _)| *s),
-                        span,
-                    }));
+                    return Some(Err(ParseError::Lex(LexError { message: e.to_string(), span })));
                 }
             }
         }
     }
+
+    /// Extend a prompt word to the end of the run of words it starts.
+    ///
+    /// The lexer emits one token per word of prompt text, so a long think
+    /// block would become tens of thousands of tokens and AST nodes. A run
+    /// of words, with the whitespace and newlines between them, becomes a
+    /// single token instead: a slice of the source that is only split into
+    /// words when the prompt is evaluated. Newlines after the last word and
+    /// the token that ended the run are kept for the following calls.
+    fn extend_prompt_run(&mut self, mut end: usize) -> usize {
+        let mut newlines = Vec::new();
+        loop {
+            match self.lex() {
+                Some(Ok((start, Rule::Newline, newline_end))) => newlines.push(Ok((start, Rule::Newline, newline_end))),
+                Some(Ok((_, Rule::PromptText, word_end))) => {
+                    end = word_end;
+                    newlines.clear();
+                }
+                other => {
+                    self.pending.extend(newlines);
+                    self.pending.extend(other);
+                    return end;
+                }
+            }
+        }
+    }
+}
+
+impl<'input, L> Iterator for LexerAdapter<'input, L>
+where
+    L: TryNextWithContext<LexerContext, Item = PatchworkToken, Error: std::fmt::Display + Any + 'static>,
+{
+    type Item = Result<(usize, ParserToken<'input>, usize), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let lexed = match self.pending.pop_front() {
+            Some(lexed) => lexed,
+            None => self.lex()?,
+        };
+        let (start, rule, mut end) = match lexed {
+            Ok(token) => token,
+            Err(e) => return Some(Err(e)),
+        };
+        if rule == Rule::PromptText {
+            end = self.extend_prompt_run(end);
+        }
+        Some(Ok((start, self.convert_token(rule, start, end), end)))
+    }
 }
 
 fn extract_span(err: &dyn Any) -> Option<parlex::Span> {
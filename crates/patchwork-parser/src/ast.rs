@@ -1,7 +1,7 @@
-/// Abstract Syntax Tree types for patchwork
-///
-/// These types represent the parsed structure of patchwork programs.
-/// All types carry a lifetime 'input for zero-copy string slices.
+//! Abstract Syntax Tree types for patchwork
+//!
+//! These types represent the parsed structure of patchwork programs.
+//! All types carry a lifetime 'input for zero-copy string slices.
 
 /// A complete patchwork program
 #[derive(Debug, Clone, PartialEq)]
@@ -9,7 +9,7 @@ pub struct Program<'input> {
     pub items: Vec<Item<'input>>,
 }
 
-/// Top-level item (import, skill, worker, trait, function, or type declaration)
+/// Top-level item (import, skill, worker, trait, function, type, or eval declaration)
 #[derive(Debug, Clone, PartialEq)]
 pub enum Item<'input> {
     Import(ImportDecl<'input>),
@@ -18,6 +18,8 @@ pub enum Item<'input> {
     Trait(TraitDecl<'input>),
     Function(FunctionDecl<'input>),
     Type(TypeDeclItem<'input>),
+    Var(VarDeclItem<'input>),
+    Eval(EvalDecl<'input>),
 }
 
 /// Import declaration: `import std.log` or `import ./{analyst, narrator}`
@@ -26,13 +28,20 @@ pub struct ImportDecl<'input> {
     pub path: ImportPath<'input>,
 }
 
-/// Import path - either simple dotted path or relative multi-import
+/// Import path - simple dotted path, relative multi-import, or data file
 #[derive(Debug, Clone, PartialEq)]
 pub enum ImportPath<'input> {
     /// Simple path: `std.log` or `./foo`
     Simple(Vec<&'input str>),
     /// Relative multi-import: `./{analyst, narrator, scribe}`
     RelativeMulti(Vec<&'input str>),
+    /// Data file: `import data "./fixtures/policies.json"` binds `policies`
+    /// to the file's contents, loaded when the module is.
+    Data {
+        /// The file name without its extension.
+        name: &'input str,
+        path: &'input str,
+    },
 }
 
 /// Skill declaration: `skill name(params) { body }`
@@ -41,6 +50,7 @@ pub struct SkillDecl<'input> {
     pub name: &'input str,
     pub params: Vec<Param<'input>>,
     pub body: Block<'input>,
+    pub annotations: Vec<Annotation<'input>>,
     pub is_exported: bool,
     pub is_default: bool,
 }
@@ -76,11 +86,48 @@ pub struct FunctionDecl<'input> {
     pub is_default: bool,
 }
 
-/// Annotation: `@skill` or `@command`
+/// Annotation: `@skill`, `@command`, `@agent name`, or `@timeout(120s)`
 #[derive(Debug, Clone, PartialEq)]
 pub struct Annotation<'input> {
     pub name: &'input str,
-    pub arg: Option<&'input str>,
+    /// `@agent name` has one argument, like `@agent(name)`.
+    pub args: Vec<&'input str>,
+}
+
+/// Evaluation of LLM output over a dataset:
+///
+/// ```text
+/// eval "summary quality" {
+///     input cases
+///     output think { Summarize ${input.issue} in one line. }
+///     expect_contains input.keyword
+///     judge think { Rate from 0 to 1 how well "${output}" summarizes ${input.issue}. }
+/// }
+/// ```
+///
+/// `output` is computed once per element of `input`, with the element bound
+/// to `input`; the checks then see both names. `eval`, `input`, `output`,
+/// `expect`, `expect_contains`, and `judge` are contextual, so they stay
+/// usable as identifiers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalDecl<'input> {
+    pub name: &'input str,
+    /// The dataset: an array with one element per case.
+    pub input: Expr<'input>,
+    /// The output being evaluated.
+    pub output: Expr<'input>,
+    pub checks: Vec<EvalCheck<'input>>,
+}
+
+/// One way an eval scores its output.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalCheck<'input> {
+    /// `expect_contains expr`: the output contains the text.
+    Contains(Expr<'input>),
+    /// `judge expr`: usually a think block answering with a score from 0 to 1.
+    Judge(Expr<'input>),
+    /// `expect Type`: the output matches a declared type.
+    Expect(&'input str),
 }
 
 /// Type declaration: `type name = TypeExpr`
@@ -90,6 +137,18 @@ pub struct TypeDeclItem<'input> {
     pub type_expr: TypeExpr<'input>,
 }
 
+/// Module-level variable: `var limit = 10` or `const MODELS = [...]`
+///
+/// Initialized once, before the program's entry point runs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarDeclItem<'input> {
+    pub pattern: Pattern<'input>,
+    pub init: Expr<'input>,
+    /// Declared with `const`: the names it binds cannot be reassigned.
+    pub is_const: bool,
+    pub is_exported: bool,
+}
+
 /// Function/task/skill parameter
 #[derive(Debug, Clone, PartialEq)]
 pub struct Param<'input> {
@@ -119,6 +178,18 @@ pub enum Pattern<'input> {
     Array(Vec<Pattern<'input>>),
 }
 
+impl<'input> Pattern<'input> {
+    /// The names this pattern binds, in source order.
+    pub fn names(&self) -> Vec<&'input str> {
+        match self {
+            Pattern::Identifier { name, .. } => vec![*name],
+            Pattern::Ignore => Vec::new(),
+            Pattern::Object(fields) => fields.iter().flat_map(|f| f.pattern.names()).collect(),
+            Pattern::Array(patterns) => patterns.iter().flat_map(|p| p.names()).collect(),
+        }
+    }
+}
+
 /// Field in an object destructuring pattern
 #[derive(Debug, Clone, PartialEq)]
 pub struct ObjectPatternField<'input> {
@@ -168,6 +239,12 @@ pub enum Statement<'input> {
         name: &'input str,
         type_expr: TypeExpr<'input>,
     },
+    /// Plan declaration: `plan { step "collect"; step "summarize" }`
+    ///
+    /// Steps are reported to observers and checked off by `step_done()`.
+    Plan {
+        steps: Vec<StringLiteral<'input>>,
+    },
 }
 
 /// Type expression
@@ -319,6 +396,9 @@ pub enum Expr<'input> {
     Think(PromptBlock<'input>),
     /// Ask expression: `ask { ... }`
     Ask(PromptBlock<'input>),
+    /// Approval gate: `approve { ... }`. The prompt text summarizes the
+    /// action, and the program stops unless a person approves it.
+    Approve(PromptBlock<'input>),
     /// Do expression: `do { ... }`
     Do(Block<'input>),
     /// Bare command invocation: `mkdir -p work_dir`
@@ -329,6 +409,10 @@ pub enum Expr<'input> {
     /// Command substitution: `$(shell_expr)`
     /// Executes shell expression and returns stdout as string
     CommandSubst(Box<Expr<'input>>),
+    /// Background command: `$(cmd &)`
+    /// Starts the command without waiting for it and returns a handle to
+    /// its process, whose output can be read as it is written
+    ShellBackground(Box<Expr<'input>>),
     /// Shell pipe: `cmd1 | cmd2`
     ShellPipe {
         left: Box<Expr<'input>>,
@@ -369,10 +453,19 @@ pub struct PromptBlock<'input> {
 /// Item within a prompt block
 #[derive(Debug, Clone, PartialEq)]
 pub enum PromptItem<'input> {
-    /// Raw prompt text
+    /// Raw prompt text: usually a slice of the source running from one word
+    /// to the last before the next interpolation or block, whitespace and
+    /// newlines included. Its words are joined by single spaces when the
+    /// prompt is evaluated.
     Text(&'input str),
     /// Variable or expression interpolation: `$var` or `${expr}`
     Interpolation(Expr<'input>),
     /// Embedded code block: `do { ... }`
     Code(Block<'input>),
+    /// Few-shot example section: `examples { input: ..., output: ... }`.
+    /// Consecutive sections form one list of examples.
+    Examples(Vec<ObjectField<'input>>),
+    /// Named prompt variant: `variant concise { ... }`. Each call uses one
+    /// of a block's variants, chosen by the runtime's variant policy.
+    Variant { name: &'input str, block: PromptBlock<'input> },
 }
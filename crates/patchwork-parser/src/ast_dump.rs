@@ -1,6 +1,6 @@
-/// AST dumping utilities for testing and debugging
-///
-/// Provides human-readable tree representations of AST nodes.
+//! AST dumping utilities for testing and debugging
+//!
+//! Provides human-readable tree representations of AST nodes.
 
 use crate::ast::*;
 use std::fmt::Write as FmtWrite;
@@ -12,6 +12,13 @@ pub fn dump_program(program: &Program) -> String {
     out
 }
 
+/// Dump a block AST as a pretty-printed tree
+pub fn dump_block(block: &Block) -> String {
+    let mut out = String::new();
+    write_block(&mut out, block, 0).unwrap();
+    out
+}
+
 fn write_program(out: &mut String, program: &Program, indent: usize) -> std::fmt::Result {
     writeln!(out, "{}Program:", "  ".repeat(indent))?;
     for item in &program.items {
@@ -64,6 +71,34 @@ fn write_item(out: &mut String, item: &Item, indent: usize) -> std::fmt::Result
             writeln!(out, "{}Type: {} =", prefix, decl.name)?;
             write_type_expr(out, &decl.type_expr, indent + 1)?;
         }
+        Item::Var(decl) => {
+            let mut modifiers = String::new();
+            if decl.is_exported { modifiers.push_str("export "); }
+            let keyword = if decl.is_const { "Const" } else { "Var" };
+            writeln!(out, "{}{}{}:", prefix, modifiers, keyword)?;
+            write_pattern(out, &decl.pattern, indent + 1)?;
+            writeln!(out, "{}  Init:", prefix)?;
+            write_expr(out, &decl.init, indent + 2)?;
+        }
+        Item::Eval(decl) => {
+            writeln!(out, "{}Eval: {:?}", prefix, decl.name)?;
+            writeln!(out, "{}  Input:", prefix)?;
+            write_expr(out, &decl.input, indent + 2)?;
+            writeln!(out, "{}  Output:", prefix)?;
+            write_expr(out, &decl.output, indent + 2)?;
+            for check in &decl.checks {
+                let (label, expr) = match check {
+                    EvalCheck::Contains(expr) => ("ExpectContains", expr),
+                    EvalCheck::Judge(expr) => ("Judge", expr),
+                    EvalCheck::Expect(name) => {
+                        writeln!(out, "{}  Expect: {}", prefix, name)?;
+                        continue;
+                    }
+                };
+                writeln!(out, "{}  {}:", prefix, label)?;
+                write_expr(out, expr, indent + 2)?;
+            }
+        }
     }
     Ok(())
 }
@@ -77,6 +112,9 @@ fn write_import_path(out: &mut String, path: &ImportPath, indent: usize) -> std:
         ImportPath::RelativeMulti(names) => {
             writeln!(out, "{}RelativeMulti: ./{{{}}}", prefix, names.join(", "))?;
         }
+        ImportPath::Data { name, path } => {
+            writeln!(out, "{}Data: {} = {:?}", prefix, name, path)?;
+        }
     }
     Ok(())
 }
@@ -177,6 +215,13 @@ fn write_statement(out: &mut String, stmt: &Statement, indent: usize) -> std::fm
             writeln!(out, "{}TypeDecl: {} =", prefix, name)?;
             write_type_expr(out, type_expr, indent + 1)?;
         }
+        Statement::Plan { steps } => {
+            writeln!(out, "{}Plan:", prefix)?;
+            for step in steps {
+                writeln!(out, "{}  Step:", prefix)?;
+                write_string_literal(out, step, indent + 2)?;
+            }
+        }
     }
     Ok(())
 }
@@ -293,6 +338,10 @@ fn write_expr(out: &mut String, expr: &Expr, indent: usize) -> std::fmt::Result
             writeln!(out, "{}Ask:", prefix)?;
             write_prompt_block(out, prompt, indent + 1)?;
         }
+        Expr::Approve(prompt) => {
+            writeln!(out, "{}Approve:", prefix)?;
+            write_prompt_block(out, prompt, indent + 1)?;
+        }
         Expr::BareCommand { name, args } => {
             writeln!(out, "{}BareCommand: {}", prefix, name)?;
             if !args.is_empty() {
@@ -306,6 +355,10 @@ fn write_expr(out: &mut String, expr: &Expr, indent: usize) -> std::fmt::Result
             writeln!(out, "{}CommandSubst:", prefix)?;
             write_expr(out, e, indent + 1)?;
         }
+        Expr::ShellBackground(e) => {
+            writeln!(out, "{}ShellBackground:", prefix)?;
+            write_expr(out, e, indent + 1)?;
+        }
         Expr::ShellPipe { left, right } => {
             writeln!(out, "{}ShellPipe:", prefix)?;
             writeln!(out, "{}  Left:", prefix)?;
@@ -389,6 +442,21 @@ fn write_prompt_block(out: &mut String, prompt: &PromptBlock, indent: usize) ->
                 writeln!(out, "{}Code:", prefix)?;
                 write_block(out, block, indent + 1)?;
             }
+            PromptItem::Examples(fields) => {
+                writeln!(out, "{}Examples:", prefix)?;
+                for field in fields {
+                    if let Some(value) = &field.value {
+                        writeln!(out, "{}  {}: ", prefix, field.key)?;
+                        write_expr(out, value, indent + 2)?;
+                    } else {
+                        writeln!(out, "{}  {} (shorthand)", prefix, field.key)?;
+                    }
+                }
+            }
+            PromptItem::Variant { name, block } => {
+                writeln!(out, "{}Variant: {}", prefix, name)?;
+                write_prompt_block(out, block, indent + 1)?;
+            }
         }
     }
     Ok(())
@@ -0,0 +1,37 @@
+use patchwork_parser::explain::{lookup, EXPLANATIONS};
+use std::env;
+use std::process;
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {} <CODE>", program);
+    eprintln!("       {} --list", program);
+    eprintln!();
+    eprintln!("Explain a diagnostic code reported by the resolver, the checker, a lint");
+    eprintln!("rule, or the runtime: what it means, common causes, and a fix.");
+    eprintln!();
+    eprintln!("  --list  list every code with a summary");
+    process::exit(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let [_, arg] = args.as_slice() else {
+        usage(&args[0]);
+    };
+    match arg.as_str() {
+        "--list" => {
+            let width = EXPLANATIONS.iter().map(|explanation| explanation.code.len()).max().unwrap_or(0);
+            for explanation in EXPLANATIONS {
+                println!("{:width$}  {} ({})", explanation.code, explanation.summary, explanation.reporter);
+            }
+        }
+        flag if flag.starts_with("--") => usage(&args[0]),
+        code => match lookup(code) {
+            Some(explanation) => print!("{}", explanation.render()),
+            None => {
+                eprintln!("No diagnostic code `{}`; `{} --list` shows them all.", code, args[0]);
+                process::exit(1);
+            }
+        },
+    }
+}
@@ -0,0 +1,53 @@
+use patchwork_parser::grammar::Grammar;
+use patchwork_parser::railroad;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+fn usage(program: &str) -> ! {
+    eprintln!("Usage: {} [--svg <dir>]", program);
+    eprintln!();
+    eprintln!("Print the Patchwork grammar as EBNF, read from the lalrpop definition");
+    eprintln!("the parser is built from.");
+    eprintln!();
+    eprintln!("  --svg <dir>  also write a railroad diagram of each rule, and an");
+    eprintln!("               index.html with all of them, to <dir>");
+    process::exit(1);
+}
+
+fn write(path: &Path, contents: &str) {
+    if let Err(e) = fs::write(path, contents) {
+        eprintln!("Error writing file '{}': {}", path.display(), e);
+        process::exit(1);
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let mut svg = None;
+    let mut rest = args[1..].iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--svg" => match rest.next() {
+                Some(dir) => svg = Some(Path::new(dir)),
+                None => usage(&args[0]),
+            },
+            _ => usage(&args[0]),
+        }
+    }
+
+    let grammar = Grammar::patchwork();
+    print!("/* The Patchwork grammar, generated from patchwork.lalrpop by patchwork-grammar. */\n{}", grammar.to_ebnf());
+
+    if let Some(dir) = svg {
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("Error creating directory '{}': {}", dir.display(), e);
+            process::exit(1);
+        }
+        for rule in &grammar.rules {
+            write(&dir.join(format!("{}.svg", rule.name)), &railroad::diagram(rule));
+        }
+        write(&dir.join("index.html"), &railroad::index(&grammar));
+    }
+}
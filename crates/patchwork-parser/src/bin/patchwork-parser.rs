@@ -1,15 +1,18 @@
-use patchwork_parser::{parse, ast_dump::dump_program};
+use patchwork_parser::{parse, parse_document, ast_dump::dump_program};
+use patchwork_parser::diagnostics::{Diagnostic, Renderer};
 use std::env;
 use std::fs;
+use std::io::IsTerminal;
 use std::process;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     if args.len() != 2 {
-        eprintln!("Usage: {} <file.pw>", args[0]);
+        eprintln!("Usage: {} <file.pw | file.md>", args[0]);
         eprintln!();
         eprintln!("Parse a patchwork file and dump its AST structure");
+        eprintln!("For a markdown file, parse and dump each ```patchwork block");
         process::exit(1);
     }
 
@@ -24,12 +27,33 @@ fn main() {
         }
     };
 
+    let renderer = if std::io::stderr().is_terminal() {
+        Renderer::colored()
+    } else {
+        Renderer::plain()
+    };
+
+    // A markdown document: every block is parsed, and errors point into the document
+    if filename.ends_with(".md") {
+        let mut failed = false;
+        for parsed in parse_document(&input) {
+            println!("# block at line {}", parsed.block.line);
+            match parsed.program {
+                Ok(program) => println!("{}", dump_program(&program)),
+                Err(e) => {
+                    eprint!("{}", renderer.render(&Diagnostic::from(&e), filename, &input));
+                    failed = true;
+                }
+            }
+        }
+        process::exit(if failed { 1 } else { 0 });
+    }
+
     // Parse
     let program = match parse(&input) {
         Ok(prog) => prog,
         Err(e) => {
-            eprintln!("Parse error in '{}':", filename);
-            eprintln!("{:?}", e);
+            eprint!("{}", renderer.render(&Diagnostic::from(&e), filename, &input));
             process::exit(1);
         }
     };
@@ -0,0 +1,365 @@
+//! Rendered diagnostics for parse and runtime errors.
+//!
+//! A `Diagnostic` describes a problem with a severity, a headline message,
+//! optional labeled source spans, and trailing notes/help lines. The
+//! `Renderer` turns it into an Ariadne-style report with line numbers and
+//! caret underlines:
+//!
+//! ```text
+//! error: Unexpected token
+//!    ╭─[main.pw:2:9]
+//!    │
+//!  2 │     var = 5
+//!    │         ^ expected a pattern
+//!    │
+//!    = help: variable declarations look like `var name = value`
+//! ───╯
+//! ```
+//!
+//! The same renderer is used by the parser binary, the interpreter, and the
+//! ACP proxy so that errors look identical no matter where they surface.
+
+use std::fmt::Write as FmtWrite;
+
+use crate::error::ParseError;
+
+/// How serious a diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl Severity {
+    fn label(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+        }
+    }
+
+    fn color(&self) -> &'static str {
+        match self {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+            Severity::Note => CYAN,
+        }
+    }
+}
+
+/// A labeled byte range in the source text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    /// Byte offsets `(start, end)` into the source.
+    pub span: (usize, usize),
+    /// Message printed next to the underline (may be empty).
+    pub message: String,
+}
+
+/// Replace a byte range of the source with new text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    /// Byte offsets `(start, end)` into the source; equal for an insertion.
+    pub span: (usize, usize),
+    pub replacement: String,
+}
+
+/// A change that resolves a diagnostic and is safe to apply unreviewed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fix {
+    /// What the fix does, e.g. "remove the import".
+    pub message: String,
+    /// Non-overlapping edits, all applied together.
+    pub edits: Vec<Edit>,
+}
+
+impl Fix {
+    pub fn new(message: impl Into<String>, edits: Vec<Edit>) -> Self {
+        Self { message: message.into(), edits }
+    }
+
+    /// A fix made of one edit.
+    pub fn replace(message: impl Into<String>, span: (usize, usize), replacement: impl Into<String>) -> Self {
+        Self::new(message, vec![Edit { span, replacement: replacement.into() }])
+    }
+}
+
+/// A single reportable problem.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Optional stable error code, e.g. `PW0001`.
+    pub code: Option<String>,
+    pub message: String,
+    /// Source spans to underline. The first label is the primary location.
+    pub labels: Vec<Label>,
+    /// Free-form notes printed after the snippet.
+    pub notes: Vec<String>,
+    /// Suggestions printed after the notes.
+    pub help: Vec<String>,
+    /// An automatic fix, for `--fix` and editor code actions.
+    pub fix: Option<Fix>,
+}
+
+impl Diagnostic {
+    /// Create a diagnostic with the given severity and message.
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            code: None,
+            message: message.into(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+            help: Vec::new(),
+            fix: None,
+        }
+    }
+
+    /// Create an error diagnostic.
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    /// Create a warning diagnostic.
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    /// Attach an error code.
+    pub fn with_code(mut self, code: impl Into<String>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+
+    /// Attach a labeled span.
+    pub fn with_label(mut self, span: (usize, usize), message: impl Into<String>) -> Self {
+        self.labels.push(Label { span, message: message.into() });
+        self
+    }
+
+    /// Attach a note line.
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Attach a help line.
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help.push(help.into());
+        self
+    }
+
+    /// Attach an automatic fix.
+    pub fn with_fix(mut self, fix: Fix) -> Self {
+        self.fix = Some(fix);
+        self
+    }
+
+    /// The primary span, if any label was attached.
+    pub fn primary_span(&self) -> Option<(usize, usize)> {
+        self.labels.first().map(|l| l.span)
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        let (headline, message) = match error {
+            ParseError::Lex(lex) => ("Lexer error", lex.message.clone()),
+            other => ("Syntax error", other.to_string()),
+        };
+
+        let diag = Diagnostic::error(headline);
+        let diag = match error.span() {
+            Some(span) => diag.with_label(span, message),
+            None => diag.with_note(message),
+        };
+        match error.hint() {
+            Some(hint) => diag.with_help(hint),
+            None => diag,
+        }
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const BLUE: &str = "\x1b[34m";
+const CYAN: &str = "\x1b[36m";
+
+/// Renders diagnostics against a source file.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Renderer {
+    color: bool,
+}
+
+impl Renderer {
+    /// A renderer producing plain text.
+    pub fn plain() -> Self {
+        Self { color: false }
+    }
+
+    /// A renderer producing ANSI-colored text.
+    pub fn colored() -> Self {
+        Self { color: true }
+    }
+
+    /// Render a diagnostic. `source_name` is shown in the location header.
+    pub fn render(&self, diag: &Diagnostic, source_name: &str, source: &str) -> String {
+        let mut out = String::new();
+        self.write(&mut out, diag, source_name, source).unwrap();
+        out
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn write(&self, out: &mut String, diag: &Diagnostic, source_name: &str, source: &str) -> std::fmt::Result {
+        // Header: `error[PW0001]: message`
+        let mut header = diag.severity.label().to_string();
+        if let Some(code) = &diag.code {
+            header.push_str(&format!("[{}]", code));
+        }
+        let header = self.paint(&format!("{}{}", BOLD, diag.severity.color()), &header);
+        writeln!(out, "{}: {}", header, self.paint(BOLD, &diag.message))?;
+
+        // Width of the line-number gutter
+        let max_line = diag
+            .labels
+            .iter()
+            .map(|l| line_col(source, l.span.0).0)
+            .max()
+            .unwrap_or(1);
+        let gutter = max_line.to_string().len() + 1;
+        let pad = " ".repeat(gutter);
+        let bar = self.paint(BLUE, "│");
+
+        if let Some((start, _)) = diag.primary_span() {
+            let (line, col) = line_col(source, start);
+            writeln!(out, "{} {}", pad, self.paint(BLUE, &format!("╭─[{}:{}:{}]", source_name, line, col)))?;
+            writeln!(out, "{} {}", pad, bar)?;
+
+            for label in &diag.labels {
+                let (start, end) = label.span;
+                let (line, col) = line_col(source, start);
+                let text = source.lines().nth(line - 1).unwrap_or("");
+
+                // Underline at most to the end of the first line of the span
+                let line_chars = text.chars().count();
+                let span_chars = source
+                    .get(start..end.max(start))
+                    .map(|s| s.lines().next().unwrap_or("").chars().count())
+                    .unwrap_or(0);
+                let width = span_chars.min(line_chars.saturating_sub(col - 1)).max(1);
+
+                let number = self.paint(BLUE, &format!("{:>w$}", line, w = gutter));
+                writeln!(out, "{} {} {}", number, bar, text)?;
+
+                let carets = self.paint(diag.severity.color(), &"^".repeat(width));
+                let indent = " ".repeat(col - 1);
+                if label.message.is_empty() {
+                    writeln!(out, "{} {} {}{}", pad, bar, indent, carets)?;
+                } else {
+                    writeln!(out, "{} {} {}{} {}", pad, bar, indent, carets, label.message)?;
+                }
+            }
+
+            if !diag.notes.is_empty() || !diag.help.is_empty() {
+                writeln!(out, "{} {}", pad, bar)?;
+            }
+        }
+
+        for note in &diag.notes {
+            writeln!(out, "{} = {}: {}", pad, self.paint(BOLD, "note"), note)?;
+        }
+        for help in &diag.help {
+            writeln!(out, "{} = {}: {}", pad, self.paint(BOLD, "help"), help)?;
+        }
+
+        if diag.primary_span().is_some() {
+            writeln!(out, "{}", self.paint(BLUE, &format!("{}╯", "─".repeat(gutter + 1))))?;
+        }
+        Ok(())
+    }
+}
+
+/// Convert a byte offset to 1-indexed (line, column), counting columns in chars.
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let mut line = 1;
+    let mut col = 1;
+    for (i, c) in source.char_indices() {
+        if i >= offset {
+            break;
+        }
+        if c == '\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
+        }
+    }
+    (line, col)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_col() {
+        let source = "abc\ndef\nghi";
+        assert_eq!(line_col(source, 0), (1, 1));
+        assert_eq!(line_col(source, 2), (1, 3));
+        assert_eq!(line_col(source, 4), (2, 1));
+        assert_eq!(line_col(source, 9), (3, 2));
+    }
+
+    #[test]
+    fn test_render_with_label() {
+        let source = "var x = 1\nvar = 5\n";
+        let diag = Diagnostic::error("Unexpected token")
+            .with_code("PW0001")
+            .with_label((14, 15), "expected a pattern")
+            .with_help("variable declarations look like `var name = value`");
+        let rendered = Renderer::plain().render(&diag, "main.pw", source);
+
+        assert!(rendered.starts_with("error[PW0001]: Unexpected token\n"), "{}", rendered);
+        assert!(rendered.contains("╭─[main.pw:2:5]"), "{}", rendered);
+        assert!(rendered.contains(" 2 │ var = 5"), "{}", rendered);
+        assert!(rendered.contains("│     ^ expected a pattern"), "{}", rendered);
+        assert!(rendered.contains("= help: variable declarations"), "{}", rendered);
+    }
+
+    #[test]
+    fn test_render_without_span() {
+        let diag = Diagnostic::error("Undefined variable: x").with_note("declare it with `var x`");
+        let rendered = Renderer::plain().render(&diag, "main.pw", "");
+        assert_eq!(rendered, "error: Undefined variable: x\n   = note: declare it with `var x`\n");
+    }
+
+    #[test]
+    fn test_render_colored_contains_escapes() {
+        let diag = Diagnostic::warning("careful");
+        let rendered = Renderer::colored().render(&diag, "main.pw", "");
+        assert!(rendered.contains("\x1b["));
+        assert!(rendered.contains("warning"));
+    }
+
+    #[test]
+    fn test_from_parse_error() {
+        let source = "skill main() {\n  var = 5\n}";
+        let err = crate::parse(source).unwrap_err();
+        let diag = Diagnostic::from(&err);
+        assert_eq!(diag.severity, Severity::Error);
+        assert!(diag.primary_span().is_some());
+        let rendered = Renderer::plain().render(&diag, "test.pw", source);
+        assert!(rendered.contains("test.pw:2:"), "{}", rendered);
+    }
+}
@@ -0,0 +1,158 @@
+//! Markdown documents with Patchwork in fenced code blocks.
+//!
+//! ````text
+//! Tidy up the branch before review:
+//!
+//! ```patchwork
+//! $ git fetch origin
+//! $ git rebase origin/main
+//! ```
+//! ````
+//!
+//! Only blocks fenced as `patchwork` are code; prose and blocks in other
+//! languages are skipped. Fences follow CommonMark: a run of three or more
+//! backticks or tildes, indented at most three spaces, closed by a run of
+//! the same character at least as long. A block that is never closed runs
+//! to the end of the document, and a `patchwork` fence inside another
+//! block, such as a markdown example, doesn't open one.
+//!
+//! `parse_document` parses each block on its own, so one that doesn't parse
+//! doesn't hide the others. A block's code is a slice of the document, so
+//! the identifiers in its AST are too, and its errors point into the
+//! document rather than the block.
+
+use crate::ast::Program;
+use crate::error::ParseError;
+
+/// A fenced `patchwork` block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeBlock<'input> {
+    /// The lines between the fences.
+    pub code: &'input str,
+    /// Byte offset of `code` in the document.
+    pub offset: usize,
+    /// Line of the document `code` starts on, counting from 1.
+    pub line: usize,
+}
+
+/// A block and what it parsed to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedBlock<'input> {
+    pub block: CodeBlock<'input>,
+    /// The block's program, or why it didn't parse, with spans in the document.
+    pub program: Result<Program<'input>, ParseError>,
+}
+
+/// An opening fence whose block hasn't closed yet.
+struct Open {
+    marker: char,
+    len: usize,
+    is_patchwork: bool,
+    offset: usize,
+    line: usize,
+}
+
+/// The `patchwork` blocks of `input`, in document order.
+pub fn find_blocks(input: &str) -> Vec<CodeBlock<'_>> {
+    let mut blocks = Vec::new();
+    let mut open: Option<Open> = None;
+    let mut offset = 0;
+    for (index, line) in input.split_inclusive('\n').enumerate() {
+        let end = offset + line.len();
+        match &open {
+            None => {
+                if let Some((marker, len, info)) = fence(line) {
+                    let language = info.split_whitespace().next().unwrap_or("");
+                    let is_patchwork = language.eq_ignore_ascii_case("patchwork");
+                    open = Some(Open { marker, len, is_patchwork, offset: end, line: index + 2 });
+                }
+            }
+            Some(block) => {
+                let closes = |(marker, len, info): (char, usize, &str)| {
+                    marker == block.marker && len >= block.len && info.is_empty()
+                };
+                if fence(line).is_some_and(closes) {
+                    if block.is_patchwork {
+                        let code = &input[block.offset..offset];
+                        blocks.push(CodeBlock { code, offset: block.offset, line: block.line });
+                    }
+                    open = None;
+                }
+            }
+        }
+        offset = end;
+    }
+    if let Some(block) = open.filter(|block| block.is_patchwork) {
+        blocks.push(CodeBlock { code: &input[block.offset..], offset: block.offset, line: block.line });
+    }
+    blocks
+}
+
+/// Parse each `patchwork` block of the markdown document `input`.
+pub fn parse_document(input: &str) -> Vec<ParsedBlock<'_>> {
+    find_blocks(input)
+        .into_iter()
+        .map(|block| {
+            let program = crate::parse(block.code).map_err(|e| e.offset_by(block.offset));
+            ParsedBlock { block, program }
+        })
+        .collect()
+}
+
+/// The marker character, run length, and info string of a fence line.
+fn fence(line: &str) -> Option<(char, usize, &str)> {
+    let text = line.trim_start_matches(' ');
+    if line.len() - text.len() > 3 {
+        return None;
+    }
+    let marker = text.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = text.len() - text.trim_start_matches(marker).len();
+    let info = text[len..].trim();
+    // A backtick in the info string makes the line inline code, not a fence
+    (len >= 3 && !(marker == '`' && info.contains('`'))).then_some((marker, len, info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Item;
+
+    #[test]
+    fn test_parse_document() {
+        let input = concat!(
+            "# Release\n\nFirst, the helper:\n\n",
+            "```patchwork\nfun greet(name) {\n  return name\n}\n```\n\n",
+            "```bash\ncargo test\n```\n\n",
+            "````markdown\n```patchwork\nnot code\n```\n````\n\n",
+            "Then a broken block:\n\n",
+            "~~~ Patchwork title=\"release\"\nskill release( {\n~~~\n\n",
+            "And one left open:\n\n```patchwork\nskill ship() {}\n",
+        );
+        let blocks = parse_document(input);
+        assert_eq!(blocks.len(), 3);
+
+        let first = &blocks[0];
+        assert_eq!(first.block.line, 6);
+        assert_eq!(&input[first.block.offset..][..9], "fun greet");
+        let Ok(program) = &first.program else { panic!("{:?}", first.program) };
+        let Item::Function(greet) = &program.items[0] else { panic!("expected a function") };
+        // Identifiers are slices of the document
+        assert_eq!(greet.name.as_ptr(), input[first.block.offset + 4..].as_ptr());
+
+        let err = blocks[1].program.as_ref().unwrap_err();
+        let (start, _) = err.span().unwrap();
+        assert!(start >= blocks[1].block.offset && start <= blocks[1].block.offset + blocks[1].block.code.len());
+
+        assert_eq!(blocks[2].block.code, "skill ship() {}\n");
+        assert!(blocks[2].program.is_ok());
+    }
+
+    #[test]
+    fn test_fences() {
+        assert_eq!(fence("```patchwork\n"), Some(('`', 3, "patchwork")));
+        assert_eq!(fence("   ~~~~\n"), Some(('~', 4, "")));
+        assert_eq!(fence("    ```\n"), None);
+        assert_eq!(fence("``not a fence``\n"), None);
+        assert_eq!(fence("``` `inline` ```\n"), None);
+    }
+}
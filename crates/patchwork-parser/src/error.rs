@@ -0,0 +1,222 @@
+//! Errors from parsing a program.
+//!
+//! Each kind of failure is its own `ParseError` variant, carrying the byte
+//! span it points at and what the parser found and expected there, so
+//! hosts can build diagnostics, quick fixes, and tests without matching on
+//! message text. `ParseError::hint` suggests how to fix the most common
+//! mistakes; lexer failures keep the lexer's error as their `source`.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::version::{Feature, LanguageVersion};
+
+/// Why a program failed to parse.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// The lexer couldn't turn the input into tokens.
+    Lex(LexError),
+    /// Input the lexer only matched as an error token, such as a stray
+    /// character.
+    InvalidToken { offset: usize },
+    /// A token the grammar doesn't allow where it appears.
+    UnexpectedToken {
+        span: (usize, usize),
+        /// The token's kind, like `RBrace` or `Identifier`.
+        kind: String,
+        /// The token's source text; empty for synthesized tokens.
+        text: String,
+        /// The terminals the grammar would have accepted, as named in the
+        /// grammar, like `"}"` or `Identifier`.
+        expected: Vec<String>,
+    },
+    /// The input ended where the grammar expected more.
+    UnexpectedEof { offset: usize, expected: Vec<String> },
+    /// A token after the end of a complete program.
+    ExtraToken { span: (usize, usize), kind: String, text: String },
+    /// A word where a contextual keyword was needed, like `evaluate`
+    /// before an eval's name and block.
+    ExpectedKeyword {
+        span: (usize, usize),
+        expected: Vec<&'static str>,
+        found: String,
+        /// Where the keyword belongs, like "in a plan".
+        context: &'static str,
+    },
+    /// A construct that parsed but breaks one of its rules, like an `eval`
+    /// without an `input`.
+    Invalid { span: (usize, usize), message: String },
+    /// A `#patchwork` pragma that doesn't name a version.
+    InvalidPragma { span: (usize, usize), pragma: String },
+    /// A `#patchwork` pragma naming a version newer than this parser.
+    UnsupportedVersion { span: (usize, usize), version: LanguageVersion },
+    /// Syntax the file's language version doesn't include.
+    FeatureUnavailable {
+        span: Option<(usize, usize)>,
+        feature: Feature,
+        version: LanguageVersion,
+    },
+}
+
+/// A failure reported by the lexer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexError {
+    pub message: String,
+    pub span: Option<(usize, usize)>,
+}
+
+impl ParseError {
+    /// The byte range the error points at, if it points anywhere.
+    pub fn span(&self) -> Option<(usize, usize)> {
+        match self {
+            ParseError::Lex(error) => error.span,
+            ParseError::InvalidToken { offset } | ParseError::UnexpectedEof { offset, .. } => Some((*offset, *offset)),
+            ParseError::UnexpectedToken { span, .. }
+            | ParseError::ExtraToken { span, .. }
+            | ParseError::ExpectedKeyword { span, .. }
+            | ParseError::Invalid { span, .. }
+            | ParseError::InvalidPragma { span, .. }
+            | ParseError::UnsupportedVersion { span, .. } => Some(*span),
+            ParseError::FeatureUnavailable { span, .. } => *span,
+        }
+    }
+
+    /// The error with its spans moved `by` bytes later, for code parsed
+    /// out of a larger text.
+    pub fn offset_by(mut self, by: usize) -> Self {
+        let shift = |span: &mut (usize, usize)| *span = (span.0 + by, span.1 + by);
+        match &mut self {
+            ParseError::Lex(error) => error.span.iter_mut().for_each(shift),
+            ParseError::InvalidToken { offset } | ParseError::UnexpectedEof { offset, .. } => *offset += by,
+            ParseError::UnexpectedToken { span, .. }
+            | ParseError::ExtraToken { span, .. }
+            | ParseError::ExpectedKeyword { span, .. }
+            | ParseError::Invalid { span, .. }
+            | ParseError::InvalidPragma { span, .. }
+            | ParseError::UnsupportedVersion { span, .. } => shift(span),
+            ParseError::FeatureUnavailable { span, .. } => span.iter_mut().for_each(shift),
+        }
+        self
+    }
+
+    /// A suggestion for fixing the error, when there is a likely one.
+    pub fn hint(&self) -> Option<String> {
+        let expects = |expected: &[String], terminal: &str| expected.iter().any(|e| e == &format!("\"{}\"", terminal));
+        match self {
+            ParseError::UnexpectedEof { expected, .. } if expects(expected, "}") => {
+                Some("a block is missing its closing `}`".to_string())
+            }
+            ParseError::UnexpectedEof { expected, .. } if expects(expected, ")") => {
+                Some("a call or parenthesized expression is missing its closing `)`".to_string())
+            }
+            ParseError::UnexpectedEof { expected, .. } if expects(expected, "]") => {
+                Some("an array is missing its closing `]`".to_string())
+            }
+            ParseError::UnexpectedToken { kind, .. } if kind == "RBrace" => {
+                Some("this `}` has no matching `{`, or something before it is unfinished".to_string())
+            }
+            ParseError::UnexpectedToken { expected, .. } if expects(expected, ",") => {
+                Some("separate items with `,`".to_string())
+            }
+            ParseError::ExtraToken { .. } => {
+                Some("check for an unbalanced `}` earlier in the file".to_string())
+            }
+            ParseError::InvalidPragma { .. } => {
+                Some(format!("write the pragma as `#patchwork {}`", LanguageVersion::LATEST))
+            }
+            ParseError::UnsupportedVersion { .. } => {
+                Some(format!("pin a version up to {}, or use a newer parser", LanguageVersion::LATEST))
+            }
+            ParseError::FeatureUnavailable { feature, .. } => Some(format!(
+                "change the `#patchwork` pragma to {} or later",
+                feature.introduced_in()
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Lex(_) => write!(f, "could not split the input into tokens"),
+            ParseError::InvalidToken { .. } => write!(f, "invalid token"),
+            ParseError::UnexpectedToken { kind, text, expected, .. } => {
+                write!(f, "unexpected {}", describe(kind, text))?;
+                write_expected(f, expected)
+            }
+            ParseError::UnexpectedEof { expected, .. } => {
+                write!(f, "unexpected end of file")?;
+                write_expected(f, expected)
+            }
+            ParseError::ExtraToken { kind, text, .. } => {
+                write!(f, "unexpected {} after the end of the program", describe(kind, text))
+            }
+            ParseError::ExpectedKeyword { expected, found, context, .. } => {
+                let expected: Vec<String> = expected.iter().map(|keyword| format!("`{}`", keyword)).collect();
+                let expected = match expected.split_last() {
+                    Some((last, rest)) if !rest.is_empty() => format!("{}, or {}", rest.join(", "), last),
+                    _ => expected.concat(),
+                };
+                write!(f, "expected {} {}, found `{}`", expected, context, found)
+            }
+            ParseError::Invalid { message, .. } => write!(f, "{}", message),
+            ParseError::InvalidPragma { pragma, .. } => {
+                write!(f, "invalid version pragma `{}`, expected `#patchwork MAJOR.MINOR`", pragma)
+            }
+            ParseError::UnsupportedVersion { version, .. } => write!(
+                f,
+                "this file is written for patchwork {}, but this parser only supports up to {}",
+                version,
+                LanguageVersion::LATEST
+            ),
+            ParseError::FeatureUnavailable { feature, version, .. } => write!(
+                f,
+                "{} requires patchwork {}, but this file is parsed as {}",
+                feature.name(),
+                feature.introduced_in(),
+                version
+            ),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Lex(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<LexError> for ParseError {
+    fn from(error: LexError) -> Self {
+        ParseError::Lex(error)
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for LexError {}
+
+/// A token for a message: its text if it has any, else its kind.
+fn describe(kind: &str, text: &str) -> String {
+    if text.trim().is_empty() {
+        kind.to_string()
+    } else {
+        format!("`{}`", text)
+    }
+}
+
+fn write_expected(f: &mut fmt::Formatter<'_>, expected: &[String]) -> fmt::Result {
+    match expected {
+        [] => Ok(()),
+        [only] => write!(f, ", expected {}", only),
+        _ => write!(f, ", expected one of {}", expected.join(", ")),
+    }
+}
@@ -0,0 +1,355 @@
+//! Long-form explanations of diagnostic codes, for `patchwork-explain`.
+//!
+//! Every code a diagnostic or warning can carry has an entry here, whichever
+//! pass reports it: the resolver, the checker, a lint rule, or the runtime.
+//! An entry says what the code means, what usually causes it, and shows
+//! code that reports it next to the same code fixed:
+//!
+//! ```text
+//! $ patchwork-explain TY_ARITY
+//! TY_ARITY: wrong number of arguments (checker)
+//! ...
+//! ```
+//!
+//! Parse errors and runtime errors don't carry codes; their messages and
+//! help lines explain them where they are reported.
+
+/// What a diagnostic code means.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Explanation {
+    pub code: &'static str,
+    /// What reports it: `resolver`, `checker`, `lint`, or `runtime`.
+    pub reporter: &'static str,
+    /// A few words, for lists of codes.
+    pub summary: &'static str,
+    pub description: &'static str,
+    pub causes: &'static [&'static str],
+    /// Code that reports it.
+    pub example: &'static str,
+    /// The same code, fixed.
+    pub fixed: &'static str,
+}
+
+impl Explanation {
+    /// The explanation as text, the way `patchwork-explain` prints it.
+    pub fn render(&self) -> String {
+        let mut out = format!("{}: {} ({})\n\n{}\n", self.code, self.summary, self.reporter, self.description);
+        out.push_str("\nCommon causes:\n");
+        for cause in self.causes {
+            out.push_str(&format!("  - {}\n", cause));
+        }
+        out.push_str("\nFor example:\n\n");
+        out.push_str(&indent(self.example));
+        out.push_str("\nFixed:\n\n");
+        out.push_str(&indent(self.fixed));
+        out
+    }
+}
+
+fn indent(code: &str) -> String {
+    code.lines().map(|line| if line.is_empty() { "\n".to_string() } else { format!("    {}\n", line) }).collect()
+}
+
+/// The explanation of `code`, ignoring case, so `ty_arity` finds `TY_ARITY`.
+pub fn lookup(code: &str) -> Option<&'static Explanation> {
+    EXPLANATIONS.iter().find(|explanation| explanation.code.eq_ignore_ascii_case(code.trim()))
+}
+
+/// Every code, grouped by what reports it.
+pub const EXPLANATIONS: &[Explanation] = &[
+    Explanation {
+        code: "undefined-name",
+        reporter: "resolver",
+        summary: "undefined name",
+        description: "A name is used that no declaration, import, or parameter in scope binds. Hosts can \
+register functions the editor can't see, so this is a warning; if nothing provides the name when the \
+program runs, looking it up fails there.",
+        causes: &[
+            "a misspelled variable or function name",
+            "a variable used outside the block that declares it",
+            "a function from a module that isn't imported",
+        ],
+        example: "skill main() {\n    var total = 3\n    print(totl)\n}",
+        fixed: "skill main() {\n    var total = 3\n    print(total)\n}",
+    },
+    Explanation {
+        code: "invalid-annotation",
+        reporter: "resolver",
+        summary: "a skill annotation has arguments it can't use",
+        description: "`@concurrency`, `@timeout`, and `@capabilities` set what the runtime enforces while a \
+skill runs, and one of them has arguments that don't fit it. Rather than run without the limit it asks \
+for, the skill fails to start.",
+        causes: &[
+            "`@concurrency` without a positive whole number",
+            "`@timeout` without a unit of ms, s, m, or h",
+            "`@capabilities` naming something other than shell, file_write, notify, or eval_code",
+        ],
+        example: "@timeout(2 minutes)\nskill deploy() {\n    $ ./deploy.sh\n}",
+        fixed: "@timeout(2m)\nskill deploy() {\n    $ ./deploy.sh\n}",
+    },
+    Explanation {
+        code: "TY_NOT_CALLABLE",
+        reporter: "checker",
+        summary: "calling a value that isn't a function",
+        description: "A call's callee is a value the checker knows is not a function, such as a number, \
+string, array, or object. The call would fail when it runs.",
+        causes: &[
+            "a variable with the same name as a function hides it",
+            "calling a field that holds data, like `config.name()`",
+        ],
+        example: concat!(
+            "fun count(items) {\n    return len(items)\n}\n\n",
+            "skill main() {\n    var count = 3\n    count([1, 2])\n}"
+        ),
+        fixed: "fun count(items) {\n    return len(items)\n}\n\nskill main() {\n    var n = 3\n    count([1, 2])\n}",
+    },
+    Explanation {
+        code: "TY_BAD_INDEX",
+        reporter: "checker",
+        summary: "indexing with the wrong kind of key",
+        description: "An index expression uses a key the value can't be indexed by: arrays and strings \
+take numbers and objects take strings. Indexing anything else, like a number, always fails.",
+        causes: &[
+            "indexing a string or array with a field name instead of a position",
+            "indexing an object with a number",
+            "a value that isn't the array or object it was expected to be",
+        ],
+        example: "skill main() {\n    var s = \"hello\"\n    var c = s[\"x\"]\n}",
+        fixed: "skill main() {\n    var s = \"hello\"\n    var c = s[0]\n}",
+    },
+    Explanation {
+        code: "TY_ARITY",
+        reporter: "checker",
+        summary: "wrong number of arguments",
+        description: "A function, skill, worker, or builtin is called with more or fewer arguments than it \
+declares. Patchwork has no default arguments, so every parameter must be passed.",
+        causes: &[
+            "a parameter was added to or removed from a declaration, but not its calls",
+            "passing an options object's fields as separate arguments",
+            "a builtin like `read()` called without its path",
+        ],
+        example: concat!(
+            "fun greet(name) {\n    print(\"Hello, ${name}\")\n}\n",
+            "\nskill main() {\n    greet(\"Ada\", \"Lovelace\")\n}"
+        ),
+        fixed: "fun greet(name) {\n    print(\"Hello, ${name}\")\n}\n\nskill main() {\n    greet(\"Ada Lovelace\")\n}",
+    },
+    Explanation {
+        code: "TY_MISMATCH",
+        reporter: "checker",
+        summary: "value doesn't match its declared type",
+        description: "A value's type doesn't match the type annotation it is assigned, passed, or \
+destructured to, or an eval's output doesn't match the type its `expect` check names.",
+        causes: &[
+            "assigning a string to a variable annotated as `number`",
+            "passing an argument of the wrong type to an annotated parameter",
+            "destructuring a string or number as an object or array",
+        ],
+        example: "fun double(n: number) {\n    return n * 2\n}\n\nskill main() {\n    double(\"4\")\n}",
+        fixed: "fun double(n: number) {\n    return n * 2\n}\n\nskill main() {\n    double(4)\n}",
+    },
+    Explanation {
+        code: "TY_BAD_OPERAND",
+        reporter: "checker",
+        summary: "operator applied to the wrong types",
+        description: "An operator is used on values it can't work with: arithmetic needs numbers, `+` \
+needs numbers or strings, ordering compares numbers or strings, ranges need numbers, `for` needs \
+something to iterate over, and fields can only be read from objects.",
+        causes: &[
+            "arithmetic on a string read from a file or command without converting it",
+            "reading a field of an array or string",
+            "looping over a number instead of a range like `0...n`",
+        ],
+        example: "skill main() {\n    var n = 3\n    for var i in n {\n        print(i)\n    }\n}",
+        fixed: "skill main() {\n    var n = 3\n    for var i in 0...n {\n        print(i)\n    }\n}",
+    },
+    Explanation {
+        code: "TY_ASSIGN_CONST",
+        reporter: "checker",
+        summary: "assigning to a constant",
+        description: "A name declared with `const` is assigned after its declaration. Constants keep the \
+value they are declared with for the whole run.",
+        causes: &[
+            "a module-level setting that some code needs to change",
+            "reusing a constant's name for a new local value",
+        ],
+        example: "const LIMIT = 10\n\nskill main() {\n    LIMIT = 20\n}",
+        fixed: "var limit = 10\n\nskill main() {\n    limit = 20\n}",
+    },
+    Explanation {
+        code: "shadowing",
+        reporter: "lint",
+        summary: "variable hides another",
+        description: "A local variable is declared with the same name as one already in scope, so the \
+outer one can't be reached until the inner one's block ends. Usually an assignment was meant.",
+        causes: &[
+            "writing `var x = ...` to update `x`",
+            "reusing a common name like `result` in a nested block",
+        ],
+        example: "skill main() {\n    var count = 0\n    if true {\n        var count = 1\n    }\n}",
+        fixed: "skill main() {\n    var count = 0\n    if true {\n        count = 1\n    }\n}",
+    },
+    Explanation {
+        code: "unused-import",
+        reporter: "lint",
+        summary: "import nothing uses",
+        description: "An imported name is never used in the module. `patchwork-lint --fix` and the LSP's \
+quick fix remove it.",
+        causes: &[
+            "the code that used the import was removed or moved",
+            "importing a module whose functions are all called through another",
+        ],
+        example: "import ./{analyst, narrator}\n\nskill main() {\n    analyst()\n}",
+        fixed: "import ./{analyst}\n\nskill main() {\n    analyst()\n}",
+    },
+    Explanation {
+        code: "empty-prompt",
+        reporter: "lint",
+        summary: "think or ask block with no prompt",
+        description: "A `think` or `ask` block has no prompt text or interpolation in it, so the model or \
+person is asked nothing.",
+        causes: &[
+            "a placeholder block left from drafting",
+            "a prompt moved into a variable without interpolating it back",
+        ],
+        example: "skill main() {\n    var question = \"Summarize the README\"\n    var answer = think { }\n}",
+        fixed: "skill main() {\n    var question = \"Summarize the README\"\n    var answer = think { ${question} }\n}",
+    },
+    Explanation {
+        code: "null-comparison",
+        reporter: "lint",
+        summary: "comparison with `null`",
+        description: "Patchwork has no `null` literal, so `x == null` looks up a variable called `null`. \
+`is_null(x)` is the test that works; the lint's fix rewrites simple comparisons to it.",
+        causes: &["habits from JavaScript or Java"],
+        example: "skill main() {\n    var user = lookup()\n    if user == null {\n        print(\"missing\")\n    }\n}",
+        fixed: "skill main() {\n    var user = lookup()\n    if is_null(user) {\n        print(\"missing\")\n    }\n}",
+    },
+    Explanation {
+        code: "unreachable-code",
+        reporter: "lint",
+        summary: "statements that never run",
+        description: "Statements follow a `return`, `break`, `succeed`, or `throw` in the same block, so \
+they can never run.",
+        causes: &[
+            "debugging code left after an early return",
+            "a `return` meant to be inside an `if`",
+        ],
+        example: "fun check(n) {\n    return n > 0\n    print(\"checked\")\n}",
+        fixed: "fun check(n) {\n    print(\"checked\")\n    return n > 0\n}",
+    },
+    Explanation {
+        code: "warn",
+        reporter: "runtime",
+        summary: "the program's own warning",
+        description: "The program called `warn()`. Its message is the program's; hosts show it next to the \
+output without stopping the run.",
+        causes: &["a condition the program's author wanted surfaced"],
+        example: "skill main() {\n    warn(\"3 issues had no labels\")\n}",
+        fixed: concat!(
+            "skill main() {\n    # Report it as output instead, or remove the call\n",
+            "    print(\"3 issues had no labels\")\n}"
+        ),
+    },
+    Explanation {
+        code: "unknown-variant",
+        reporter: "runtime",
+        summary: "no prompt variant by that name",
+        description: "The `prompt_variant` setting pins a variant, directly or through an environment \
+variable, that a prompt block doesn't have. The block uses its first variant instead.",
+        causes: &[
+            "a variant renamed in the program but not in the config",
+            "the environment variable named by `prompt_variant` holds a stale name",
+        ],
+        example: concat!(
+            "# patchwork.json: { \"prompt_variant\": \"terse\" }\nthink {\n",
+            "    variant concise { Summarize ${text}. }\n}"
+        ),
+        fixed: concat!(
+            "# patchwork.json: { \"prompt_variant\": \"concise\" }\nthink {\n",
+            "    variant concise { Summarize ${text}. }\n}"
+        ),
+    },
+    Explanation {
+        code: "prompt-truncated",
+        reporter: "runtime",
+        summary: "examples left out of a prompt",
+        description: "A prompt's `examples` sections came to more estimated tokens than \
+`limits.max_example_tokens` allows, so the examples past the limit were left out of the prompt.",
+        causes: &[
+            "long examples, or many of them",
+            "a `max_example_tokens` limit set low for a prompt that needs its examples",
+        ],
+        example: "# patchwork.json: { \"limits\": { \"max_example_tokens\": 50 } }",
+        fixed: "# patchwork.json: { \"limits\": { \"max_example_tokens\": 500 } }",
+    },
+    Explanation {
+        code: "redacted",
+        reporter: "runtime",
+        summary: "text removed from a prompt",
+        description: "A `redact` rule matched text in a prompt or its bindings, and the matches were \
+replaced before the request was sent. The warning names the rule and where it matched.",
+        causes: &[
+            "a secret, like an API key, interpolated into a prompt",
+            "a rule's pattern that matches more than intended",
+        ],
+        example: "skill main() {\n    var token = read(\"token.txt\")\n    think { Why does ${token} fail? }\n}",
+        fixed: "skill main() {\n    think { Why does the GitHub token fail? }\n}",
+    },
+    Explanation {
+        code: "redaction",
+        reporter: "runtime",
+        summary: "redaction rules couldn't be used",
+        description: "A host passed `redact` rules that couldn't be compiled, such as an invalid pattern, \
+so the run goes on with the rules it had before. Config files are checked when read, so this comes \
+from settings a host built itself.",
+        causes: &["an invalid regular expression in a rule built by the host"],
+        example: "{ \"redact\": [{ \"name\": \"key\", \"pattern\": \"sk-[\" }] }",
+        fixed: "{ \"redact\": [{ \"name\": \"key\", \"pattern\": \"sk-[A-Za-z0-9]+\" }] }",
+    },
+    Explanation {
+        code: "telemetry",
+        reporter: "runtime",
+        summary: "the telemetry sink couldn't be set up",
+        description: "The `telemetry` setting names a JSON file that couldn't be opened for appending, so the \
+run goes on reporting to the sink it had before.",
+        causes: &["a directory in the file's path doesn't exist", "the file isn't writable"],
+        example: "{ \"telemetry\": \"json:/var/log/missing/patchwork.jsonl\" }",
+        fixed: "{ \"telemetry\": \"json:.patchwork/telemetry.jsonl\" }",
+    },
+    Explanation {
+        code: "background",
+        reporter: "runtime",
+        summary: "a background command was killed when the program ended",
+        description: "A command started with `$(cmd &)` was still running when the program finished, \
+and nothing had waited for it, so it was stopped and its output thrown away. Wait for it with \
+`wait()` or `wait_all()`, kill it yourself, or set `background_on_exit` to `wait`.",
+        causes: &["a handle that is never waited for", "a `wait_any()` whose other commands are left running"],
+        example: "skill main() {\n    var build = $(cargo build &)\n    print(\"started\")\n}",
+        fixed: "skill main() {\n    var build = $(cargo build &)\n    print(\"started\")\n    build.wait()\n}",
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explanations() {
+        let arity = lookup("ty_arity").expect("TY_ARITY is explained");
+        let text = arity.render();
+        assert!(text.starts_with("TY_ARITY: wrong number of arguments (checker)\n\n"));
+        assert!(text.contains("\nCommon causes:\n  - "));
+        assert!(text.contains("\nFixed:\n\n    fun greet(name) {\n"));
+        assert!(lookup("RT_TIMEOUT").is_none());
+
+        for (i, explanation) in EXPLANATIONS.iter().enumerate() {
+            assert!(
+                EXPLANATIONS[..i].iter().all(|other| other.code != explanation.code),
+                "{} is explained twice",
+                explanation.code
+            );
+            assert!(!explanation.causes.is_empty(), "{} lists no causes", explanation.code);
+        }
+    }
+}
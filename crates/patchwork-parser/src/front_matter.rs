@@ -0,0 +1,60 @@
+//! Front matter: settings for the loader at the very start of a file,
+//! between two `---` lines.
+//!
+//! ```text
+//! ---
+//! model: fast
+//! params:
+//!   audience: engineers
+//! ---
+//! skill main(audience) { ... }
+//! ```
+//!
+//! The parser only finds the block and skips it, lexing blank lines in its
+//! place so offsets and line numbers still match the file. What the block
+//! means is up to the host; the interpreter reads it as YAML.
+
+/// A front matter block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrontMatter<'input> {
+    /// The lines between the delimiters.
+    pub text: &'input str,
+    /// Byte offset of `text` in the file.
+    pub offset: usize,
+    /// Byte offset just past the closing delimiter's line.
+    pub end: usize,
+}
+
+/// The front matter block at the start of `input`, if it has one.
+///
+/// The opening `---` must be the first line. A block that is never closed
+/// isn't front matter, and is left for the parser to reject.
+pub fn find(input: &str) -> Option<FrontMatter<'_>> {
+    let mut lines = input.split_inclusive('\n');
+    let first = lines.next()?;
+    if first.trim_end() != "---" {
+        return None;
+    }
+    let offset = first.len();
+    let mut end = offset;
+    for line in lines {
+        let start = end;
+        end += line.len();
+        if line.trim_end() == "---" {
+            return Some(FrontMatter { text: &input[offset..start], offset, end });
+        }
+    }
+    None
+}
+
+/// `input` with its front matter, if any, replaced by blank lines.
+pub(crate) fn blank(input: &str) -> Option<String> {
+    let block = find(input)?;
+    // A multi-byte character becomes as many spaces, keeping offsets the same
+    let mut blanked: String = input[..block.end]
+        .chars()
+        .map(|c| if c == '\n' { "\n".to_string() } else { " ".repeat(c.len_utf8()) })
+        .collect();
+    blanked.push_str(&input[block.end..]);
+    Some(blanked)
+}
@@ -0,0 +1,561 @@
+//! The grammar as documentation, read straight from `patchwork.lalrpop` so
+//! it can't drift from what the parser accepts.
+//!
+//! `Grammar::read` keeps the shape of each rule and drops everything else:
+//! types, binding names, actions, and `@L`/`@R` location markers. Quoted
+//! terminals become `Expr::Literal` and the tokens the lexer names, like
+//! `identifier`, become `Expr::Token`. `Grammar::to_ebnf` writes the result
+//! in W3C EBNF notation, which most grammar tools read, and the `railroad`
+//! module draws each rule as an SVG diagram:
+//!
+//! ```text
+//! Block ::= "{" StatementList "}"
+//! ```
+//!
+//! `patchwork-grammar` prints both; `scripts/grammar-docs.sh` writes them to
+//! `docs/grammar/`.
+
+use std::collections::HashSet;
+use std::fmt;
+
+/// The lalrpop definition the parser is generated from.
+pub const SOURCE: &str = include_str!("patchwork.lalrpop");
+
+/// The right-hand side of a rule, or part of one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    /// A quoted terminal, like `"if"`.
+    Literal(String),
+    /// A token the lexer names, like `identifier` or `newline`.
+    Token(String),
+    /// Another rule.
+    Rule(String),
+    /// Each item in turn; empty matches nothing.
+    Sequence(Vec<Expr>),
+    Choice(Vec<Expr>),
+    Optional(Box<Expr>),
+    ZeroOrMore(Box<Expr>),
+    OneOrMore(Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub name: String,
+    /// Whether the parser exposes the rule as an entry point.
+    pub public: bool,
+    pub alternatives: Vec<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grammar {
+    /// The tokens the lexer names, in the order the grammar declares them.
+    pub tokens: Vec<String>,
+    pub rules: Vec<Rule>,
+}
+
+impl Grammar {
+    /// The grammar the parser was built from.
+    pub fn patchwork() -> Self {
+        Grammar::read(SOURCE).expect("patchwork.lalrpop should be readable")
+    }
+
+    /// Read a lalrpop grammar, checking that every name a rule uses is a
+    /// rule or a token.
+    pub fn read(source: &str) -> Result<Self, String> {
+        let mut reader = Reader { text: source, pos: 0 };
+        let mut tokens = Vec::new();
+        let mut rules = Vec::new();
+        loop {
+            reader.skip_trivia();
+            if reader.pos == source.len() {
+                break;
+            }
+            if reader.keyword("use") || reader.keyword("grammar") {
+                reader.skip_code(&[';'])?;
+                reader.expect(";")?;
+            } else if reader.keyword("extern") {
+                reader.read_extern(&mut tokens)?;
+            } else {
+                rules.push(reader.read_rule()?);
+            }
+        }
+
+        let defined: HashSet<String> = rules.iter().map(|rule| rule.name.clone()).collect();
+        for rule in &mut rules {
+            for alternative in &mut rule.alternatives {
+                resolve(alternative, &tokens, &defined, &rule.name)?;
+            }
+        }
+        Ok(Grammar { tokens, rules })
+    }
+
+    pub fn rule(&self, name: &str) -> Option<&Rule> {
+        self.rules.iter().find(|rule| rule.name == name)
+    }
+
+    /// Every rule in EBNF, after a comment listing the lexer's tokens.
+    pub fn to_ebnf(&self) -> String {
+        let mut out = format!("/* Tokens from the lexer: {} */\n", self.tokens.join(", "));
+        for rule in &self.rules {
+            out.push('\n');
+            out.push_str(&rule.to_ebnf());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl Rule {
+    /// The rule in EBNF, one alternative per line.
+    pub fn to_ebnf(&self) -> String {
+        let mut out = format!("{} ::= ", self.name);
+        // Lines up each `|` under the `=` of `::=`
+        let indent = " ".repeat(self.name.len() + 3);
+        for (i, alternative) in self.alternatives.iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+                out.push_str(&indent);
+                out.push_str("| ");
+            }
+            out.push_str(&alternative.to_string());
+        }
+        out
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Literal(text) if text.contains('"') => write!(f, "'{}'", text),
+            Expr::Literal(text) => write!(f, "\"{}\"", text),
+            Expr::Token(name) | Expr::Rule(name) => write!(f, "{}", name),
+            Expr::Sequence(items) if items.is_empty() => write!(f, "/* empty */"),
+            Expr::Sequence(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    match item {
+                        Expr::Choice(_) => write!(f, "( {} )", item)?,
+                        _ => write!(f, "{}", item)?,
+                    }
+                }
+                Ok(())
+            }
+            Expr::Choice(alternatives) => {
+                for (i, alternative) in alternatives.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " | ")?;
+                    }
+                    write!(f, "{}", alternative)?;
+                }
+                Ok(())
+            }
+            Expr::Optional(inner) => postfix(f, inner, '?'),
+            Expr::ZeroOrMore(inner) => postfix(f, inner, '*'),
+            Expr::OneOrMore(inner) => postfix(f, inner, '+'),
+        }
+    }
+}
+
+fn postfix(f: &mut fmt::Formatter<'_>, inner: &Expr, op: char) -> fmt::Result {
+    match inner {
+        Expr::Literal(_) | Expr::Token(_) | Expr::Rule(_) => write!(f, "{}{}", inner, op),
+        _ => write!(f, "( {} ){}", inner, op),
+    }
+}
+
+/// Turn the names the reader couldn't tell apart into tokens or rules.
+fn resolve(expr: &mut Expr, tokens: &[String], defined: &HashSet<String>, rule: &str) -> Result<(), String> {
+    match expr {
+        Expr::Rule(name) => {
+            if tokens.contains(name) {
+                let name = name.clone();
+                *expr = Expr::Token(name);
+            } else if !defined.contains(name.as_str()) {
+                return Err(format!("`{}` in rule `{}` is neither a rule nor a token", name, rule));
+            }
+        }
+        Expr::Sequence(items) | Expr::Choice(items) => {
+            for item in items {
+                resolve(item, tokens, defined, rule)?;
+            }
+        }
+        Expr::Optional(inner) | Expr::ZeroOrMore(inner) | Expr::OneOrMore(inner) => {
+            resolve(inner, tokens, defined, rule)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// A sequence of `items`, flattened, without location markers, and
+/// unwrapped when only one item is left.
+fn sequence(items: Vec<Expr>) -> Expr {
+    let mut flat = Vec::new();
+    for item in items {
+        match item {
+            Expr::Sequence(inner) => flat.extend(inner),
+            item => flat.push(item),
+        }
+    }
+    if flat.len() == 1 {
+        flat.pop().unwrap()
+    } else {
+        Expr::Sequence(flat)
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Reads just enough lalrpop to find the rules, skipping the Rust code
+/// around them.
+struct Reader<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn error(&self, message: &str) -> String {
+        let line = self.text[..self.pos].matches('\n').count() + 1;
+        format!("line {}: {}", line, message)
+    }
+
+    fn skip_line(&mut self) {
+        self.pos = self.rest().find('\n').map_or(self.text.len(), |end| self.pos + end);
+    }
+
+    fn skip_trivia(&mut self) {
+        loop {
+            let trimmed = self.rest().trim_start();
+            self.pos = self.text.len() - trimmed.len();
+            if !trimmed.starts_with("//") {
+                return;
+            }
+            self.skip_line();
+        }
+    }
+
+    fn eat(&mut self, text: &str) -> bool {
+        self.skip_trivia();
+        if self.rest().starts_with(text) {
+            self.pos += text.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String> {
+        if self.eat(text) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{}`", text)))
+        }
+    }
+
+    /// Eat `word` if it is the next whole word.
+    fn keyword(&mut self, word: &str) -> bool {
+        self.skip_trivia();
+        let rest = self.rest();
+        if rest.starts_with(word) && !rest[word.len()..].starts_with(is_ident_char) {
+            self.pos += word.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn ident(&mut self) -> Option<&'a str> {
+        self.skip_trivia();
+        let rest = self.rest();
+        if !rest.starts_with(is_ident_start) {
+            return None;
+        }
+        let len = rest.find(|c: char| !is_ident_char(c)).unwrap_or(rest.len());
+        self.pos += len;
+        Some(&rest[..len])
+    }
+
+    /// A double-quoted string, in the grammar or in Rust code.
+    fn string(&mut self) -> Result<String, String> {
+        self.expect("\"")?;
+        let mut value = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(value);
+                }
+                '\\' => {
+                    if let Some((_, escaped)) = chars.next() {
+                        value.push(escaped);
+                    }
+                }
+                c => value.push(c),
+            }
+        }
+        Err(self.error("unterminated string"))
+    }
+
+    /// Skip a char literal, or the `'` of a lifetime.
+    fn skip_quote(&mut self) {
+        let rest = self.rest();
+        if rest.starts_with("'\\") {
+            let end = rest[3..].find('\'').map_or(rest.len(), |end| end + 4);
+            self.pos += end;
+            return;
+        }
+        let mut chars = rest[1..].chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), Some('\'')) => self.pos += 2 + c.len_utf8(),
+            _ => self.pos += 1,
+        }
+    }
+
+    /// Skip Rust code up to one of `stops` outside any brackets.
+    fn skip_code(&mut self, stops: &[char]) -> Result<(), String> {
+        let mut depth = 0;
+        while let Some(c) = self.peek() {
+            if depth == 0 && stops.contains(&c) {
+                return Ok(());
+            }
+            match c {
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' if depth == 0 => return Err(self.error(&format!("unbalanced `{}`", c))),
+                ')' | ']' | '}' => depth -= 1,
+                '"' => {
+                    self.string()?;
+                    continue;
+                }
+                '\'' => {
+                    self.skip_quote();
+                    continue;
+                }
+                '/' if self.rest().starts_with("//") => {
+                    self.skip_line();
+                    continue;
+                }
+                _ => {}
+            }
+            self.pos += c.len_utf8();
+        }
+        Err(self.error("unexpected end of grammar"))
+    }
+
+    /// The `extern` block, collecting the names of the lexer's tokens.
+    fn read_extern(&mut self, tokens: &mut Vec<String>) -> Result<(), String> {
+        self.expect("{")?;
+        while !self.eat("}") {
+            if !self.keyword("enum") {
+                self.skip_code(&[';'])?;
+                self.expect(";")?;
+                continue;
+            }
+            self.skip_code(&['{'])?;
+            self.expect("{")?;
+            while !self.eat("}") {
+                if self.peek() == Some('"') {
+                    self.string()?;
+                } else {
+                    let name = self.ident().ok_or_else(|| self.error("expected a token"))?;
+                    tokens.push(name.to_string());
+                }
+                self.expect("=>")?;
+                self.skip_code(&[',', '}'])?;
+                self.eat(",");
+            }
+        }
+        Ok(())
+    }
+
+    /// `[pub] Name[: Type] = { alternative, ... };` or `Name = alternative;`
+    fn read_rule(&mut self) -> Result<Rule, String> {
+        let public = self.keyword("pub");
+        let name = self.ident().ok_or_else(|| self.error("expected a rule"))?.to_string();
+        if self.eat(":") {
+            self.skip_code(&['='])?;
+        }
+        self.expect("=")?;
+        let mut alternatives = Vec::new();
+        if self.eat("{") {
+            while !self.eat("}") {
+                let start = self.pos;
+                alternatives.extend(self.read_alternative()?);
+                self.eat(",");
+                if self.pos == start {
+                    return Err(self.error("expected an alternative"));
+                }
+            }
+        } else {
+            alternatives.extend(self.read_alternative()?);
+        }
+        self.expect(";")?;
+        Ok(Rule { name, public, alternatives })
+    }
+
+    /// An alternative and its action, or `None` for an error-recovery
+    /// alternative (`! => ...`), which accepts nothing a program could say.
+    fn read_alternative(&mut self) -> Result<Option<Expr>, String> {
+        let recovery = self.eat("!");
+        let items = self.read_symbols()?;
+        if self.eat("=>") {
+            self.eat("?");
+            self.skip_code(&[',', '}', ';'])?;
+        }
+        Ok(if recovery { None } else { Some(sequence(items)) })
+    }
+
+    fn read_symbols(&mut self) -> Result<Vec<Expr>, String> {
+        let mut items = Vec::new();
+        while let Some(item) = self.read_symbol()? {
+            items.push(item);
+        }
+        Ok(items)
+    }
+
+    fn read_symbol(&mut self) -> Result<Option<Expr>, String> {
+        self.skip_trivia();
+        let mut symbol = match self.peek() {
+            Some('"') => Expr::Literal(self.string()?),
+            Some('(') => {
+                self.pos += 1;
+                let items = self.read_symbols()?;
+                self.expect(")")?;
+                sequence(items)
+            }
+            Some('<') => {
+                self.pos += 1;
+                self.skip_binding_name();
+                let items = self.read_symbols()?;
+                self.expect(">")?;
+                sequence(items)
+            }
+            // `@L` and `@R` only record locations
+            Some('@') => {
+                self.pos += 1;
+                self.ident();
+                Expr::Sequence(Vec::new())
+            }
+            Some(c) if is_ident_start(c) => Expr::Rule(self.ident().unwrap_or_default().to_string()),
+            _ => return Ok(None),
+        };
+        loop {
+            self.skip_trivia();
+            symbol = match self.peek() {
+                Some('?') => Expr::Optional(Box::new(symbol)),
+                Some('*') => Expr::ZeroOrMore(Box::new(symbol)),
+                Some('+') => Expr::OneOrMore(Box::new(symbol)),
+                _ => return Ok(Some(symbol)),
+            };
+            self.pos += 1;
+        }
+    }
+
+    /// Skip the `name:` of `<name:Symbol>`, if there is one.
+    fn skip_binding_name(&mut self) {
+        let start = self.pos;
+        if self.ident().is_some() {
+            self.skip_trivia();
+            let rest = self.rest();
+            if rest.starts_with(':') && !rest.starts_with("::") {
+                self.pos += 1;
+                return;
+            }
+        }
+        self.pos = start;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+use crate::ast::*;
+
+grammar<'input>(input: &'input str);
+
+extern {
+    type Location = usize;
+
+    enum ParserToken<'input> {
+        "(" => ParserToken::LParen,
+        ")" => ParserToken::RParen,
+        "," => ParserToken::Comma,
+        identifier => ParserToken::Identifier(<&'input str>),
+        newline => ParserToken::Newline(<&'input str>),
+    }
+}
+
+// A list of names
+pub Names: Vec<&'input str> = {
+    "(" newline* ")" => vec![],
+    <l:@L> "(" <head:identifier> <tail:("," <identifier>)*> ")"? =>? {
+        let mut names = vec![head]; // "{" isn't a brace
+        names.extend(tail);
+        if names.contains(&"}") { names.push('}'.to_string().leak()); }
+        Ok(names)
+    },
+};
+
+Name: &'input str = {
+    identifier,
+    ! => "?",
+};
+"#;
+
+    #[test]
+    fn test_read_grammar() {
+        let grammar = Grammar::read(SAMPLE).unwrap();
+        assert_eq!(grammar.tokens, vec!["identifier", "newline"]);
+        let names = grammar.rule("Names").unwrap();
+        assert!(names.public);
+        assert_eq!(
+            names.alternatives[1],
+            Expr::Sequence(vec![
+                Expr::Literal("(".to_string()),
+                Expr::Token("identifier".to_string()),
+                Expr::ZeroOrMore(Box::new(Expr::Sequence(vec![
+                    Expr::Literal(",".to_string()),
+                    Expr::Token("identifier".to_string()),
+                ]))),
+                Expr::Optional(Box::new(Expr::Literal(")".to_string()))),
+            ])
+        );
+        assert_eq!(
+            grammar.to_ebnf(),
+            "/* Tokens from the lexer: identifier, newline */\n\n\
+             Names ::= \"(\" newline* \")\"\n        | \"(\" identifier ( \",\" identifier )* \")\"?\n\n\
+             Name ::= identifier\n"
+        );
+
+        let unknown = SAMPLE.replace("    identifier,", "    Identifier,");
+        assert_eq!(Grammar::read(&unknown).unwrap_err(), "`Identifier` in rule `Name` is neither a rule nor a token");
+    }
+
+    #[test]
+    fn test_read_patchwork_grammar() {
+        let grammar = Grammar::patchwork();
+        let public: Vec<&str> = grammar.rules.iter().filter(|rule| rule.public).map(|rule| rule.name.as_str()).collect();
+        assert_eq!(public, vec!["Program"]);
+        assert!(grammar.tokens.iter().any(|token| token == "identifier"));
+        assert_eq!(grammar.rule("Block").unwrap().to_ebnf(), "Block ::= \"{\" StatementList \"}\"");
+        assert_eq!(grammar.rule("Separator").unwrap().to_ebnf(), "Separator ::= newline\n            | \";\"");
+    }
+}
@@ -2,6 +2,15 @@ pub mod token;
 pub mod adapter;
 pub mod ast;
 pub mod ast_dump;
+pub mod diagnostics;
+pub mod document;
+pub mod error;
+pub mod explain;
+pub mod front_matter;
+pub mod grammar;
+pub mod railroad;
+pub mod resolve;
+pub mod version;
 
 // Include generated parser code from lalrpop
 #[allow(clippy::all)]
@@ -9,22 +18,39 @@ mod patchwork {
     include!(concat!(env!("OUT_DIR"), "/patchwork.rs"));
 }
 
-pub use adapter::{LexerAdapter, ParseError};
+pub use adapter::LexerAdapter;
+pub use document::parse_document;
+pub use error::{LexError, ParseError};
 pub use token::ParserToken;
 pub use ast::*;
+pub use version::{Feature, LanguageVersion, ParseOptions};
 
 use patchwork_lexer::lex_str;
 use lalrpop_util::ParseError as LalrpopError;
-use crate::adapter::ParseError::{LexerError, UnexpectedToken};
 
-/// Parse a patchwork program from a string
+/// Parse a patchwork program from a string, accepting all syntax this
+/// parser understands unless the file pins an older version.
 pub fn parse(input: &str) -> Result<Program<'_>, ParseError> {
+    parse_with(input, &ParseOptions::default())
+}
+
+/// Parse a patchwork program at the language version in `options`, or the
+/// version named by the file's `#patchwork X.Y` pragma.
+///
+/// Front matter at the start of the file is skipped; see `front_matter`.
+pub fn parse_with<'input>(input: &'input str, options: &ParseOptions) -> Result<Program<'input>, ParseError> {
+    let blanked = front_matter::blank(input);
+    let source = blanked.as_deref().unwrap_or(input);
+    let version = version::pragma_version(source)?.unwrap_or(options.version);
+    let program = parse_syntax(input, source)?;
+    version::check_features(&program, input, version, options)?;
+    Ok(program)
+}
+
+/// Parse `input`, lexing `source`: the same text, but with front matter blanked.
+fn parse_syntax<'input>(input: &'input str, source: &str) -> Result<Program<'input>, ParseError> {
     // Create lexer
-    let lexer = lex_str(input).map_err(|e| LexerError {
-        message: e.to_string(),
-        byte_offset: None,
-        span: None,
-    })?;
+    let lexer = lex_str(source).map_err(|e| LexError { message: e.to_string(), span: None })?;
 
     // Create adapter
     let adapter = LexerAdapter::new(input, lexer);
@@ -33,40 +59,45 @@ pub fn parse(input: &str) -> Result<Program<'_>, ParseError> {
     patchwork::ProgramParser::new()
         .parse(input, adapter)
         .map_err(|e| match e {
-            LalrpopError::InvalidToken { location } => UnexpectedToken {
-                message: "Invalid token".to_string(),
-                byte_offset: Some(location),
-                span: Some((location, location)),
-            },
-            LalrpopError::UnrecognizedEof { location, expected } => UnexpectedToken {
-                message: format!("Unexpected end of file, expected: {:?}", expected),
-                byte_offset: Some(location),
-                span: Some((location, location)),
+            LalrpopError::InvalidToken { location } => ParseError::InvalidToken { offset: location },
+            LalrpopError::UnrecognizedEof { location, expected } => {
+                ParseError::UnexpectedEof { offset: location, expected }
+            }
+            // The lexer ends its stream with an explicit End token
+            LalrpopError::UnrecognizedToken { token: (start, ParserToken::End, _), expected } => {
+                ParseError::UnexpectedEof { offset: start, expected }
+            }
+            LalrpopError::UnrecognizedToken { token: (start, token, end), expected } => ParseError::UnexpectedToken {
+                span: (start, end),
+                kind: token_kind(&token),
+                text: input.get(start..end).unwrap_or_default().to_string(),
+                expected,
             },
-            LalrpopError::UnrecognizedToken { token, expected } => {
-                let (location, tok, end) = token;
-                UnexpectedToken {
-                    message: format!("Unexpected token {:?}, expected: {:?}", tok, expected),
-                    byte_offset: Some(location),
-                    span: Some((location, end)),
-                }
-            }
-            LalrpopError::ExtraToken { token } => {
-                let (location, tok, end) = token;
-                UnexpectedToken {
-                    message: format!("Extra token {:?}", tok),
-                    byte_offset: Some(location),
-                    span: Some((location, end)),
-                }
-            }
-            LalrpopError::User { error } => UnexpectedToken {
-                message: format!("{:?}", error),
-                byte_offset: None,
-                span: None,
+            LalrpopError::ExtraToken { token: (start, token, end) } => ParseError::ExtraToken {
+                span: (start, end),
+                kind: token_kind(&token),
+                text: input.get(start..end).unwrap_or_default().to_string(),
             },
+            // Errors from the adapter and grammar actions are already ours
+            LalrpopError::User { error } => error,
         })
 }
 
+/// A token's variant name, like `RBrace` or `Identifier`.
+fn token_kind(token: &ParserToken) -> String {
+    let debug = format!("{:?}", token);
+    debug.split('(').next().unwrap_or(&debug).to_string()
+}
+
+/// Adjacent runs of prompt text as one string. A lone run stays a slice of
+/// the source; joining several allocates.
+fn join_prompt_text<'input>(runs: &[&'input str]) -> &'input str {
+    match runs {
+        [run] => run,
+        _ => runs.join(" ").leak(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -167,6 +198,42 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_annotated_skill() {
+        let input = "@agent local\nexport skill triage(issue) {}\n\nskill report() {}";
+        let program = parse(input).expect("Failed to parse annotated skill");
+        assert_eq!(program.items.len(), 2);
+
+        match &program.items[0] {
+            Item::Skill(decl) => {
+                assert_eq!(decl.name, "triage");
+                assert!(decl.is_exported);
+                assert_eq!(decl.annotations, vec![Annotation { name: "agent", args: vec!["local"] }]);
+            }
+            _ => panic!("Expected Skill item"),
+        }
+        match &program.items[1] {
+            Item::Skill(decl) => assert!(decl.annotations.is_empty()),
+            _ => panic!("Expected Skill item"),
+        }
+    }
+
+    #[test]
+    fn test_parse_annotation_args() {
+        let input = "@concurrency(1)\n@timeout(120s)\n@capabilities(shell, file_write)\n@sandbox()\nskill deploy() {}";
+        let program = parse(input).expect("Failed to parse annotation arguments");
+        let Item::Skill(decl) = &program.items[0] else { panic!("Expected Skill item") };
+        assert_eq!(
+            decl.annotations,
+            vec![
+                Annotation { name: "concurrency", args: vec!["1"] },
+                Annotation { name: "timeout", args: vec!["120s"] },
+                Annotation { name: "capabilities", args: vec!["shell", "file_write"] },
+                Annotation { name: "sandbox", args: vec![] },
+            ]
+        );
+    }
+
     #[test]
     fn test_parse_task() {
         let input = "worker analyst(session_id, work_dir, changeset) {}";
@@ -276,8 +343,54 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_data_import() {
+        let program = parse("import data \"./fixtures/policies.json\"\n").unwrap();
+        match &program.items[0] {
+            Item::Import(ImportDecl { path: ImportPath::Data { name, path } }) => {
+                assert_eq!(*name, "policies");
+                assert_eq!(*path, "./fixtures/policies.json");
+            }
+            other => panic!("Expected data import, got {:?}", other),
+        }
+
+        assert!(parse("import data \"./fixtures/access-policies.json\"\n").is_err());
+        assert!(parse("import schema \"./fixtures/policies.json\"\n").is_err());
+    }
+
     // ==================== Variable Declarations ====================
 
+    #[test]
+    fn test_module_level_var_and_const() {
+        let input = r#"
+            const MODELS = ["fast", "smart"]
+            export var { retries, timeout } = { retries: 3, timeout: 30 }
+
+            skill main() {}
+        "#;
+        let result = parse(input);
+        assert!(result.is_ok(), "Failed to parse module-level declarations: {:?}", result);
+
+        let program = result.unwrap();
+        assert_eq!(program.items.len(), 3);
+        match &program.items[0] {
+            Item::Var(decl) => {
+                assert!(decl.is_const);
+                assert!(!decl.is_exported);
+                assert_eq!(decl.pattern.names(), vec!["MODELS"]);
+            }
+            _ => panic!("Expected Var item"),
+        }
+        match &program.items[1] {
+            Item::Var(decl) => {
+                assert!(!decl.is_const);
+                assert!(decl.is_exported);
+                assert_eq!(decl.pattern.names(), vec!["retries", "timeout"]);
+            }
+            _ => panic!("Expected Var item"),
+        }
+    }
+
     #[test]
     fn test_var_decl_no_init() {
         let input = r#"
@@ -623,6 +736,150 @@ mod tests {
         assert!(matches!(task.body.statements[2], Statement::Break));
     }
 
+    #[test]
+    fn test_version_pragma_gates_newer_syntax() {
+        let plan = "skill main() {\n  plan { step \"one\" }\n}\n";
+        assert!(parse(plan).is_ok());
+
+        let pinned = format!("# Nightly report\n#patchwork 0.2\n{}", plan);
+        match parse(&pinned) {
+            Err(error @ ParseError::FeatureUnavailable { feature: Feature::PlanBlocks, span: Some((start, end)), .. }) => {
+                assert!(error.to_string().contains("requires patchwork 0.3"), "{}", error);
+                assert_eq!(error.hint().as_deref(), Some("change the `#patchwork` pragma to 0.3 or later"));
+                assert_eq!(&pinned[start..end], "one");
+            }
+            other => panic!("Expected a feature error, got {:?}", other),
+        }
+
+        // Options set the version for files without a pragma, and can
+        // enable single features on top of it
+        let options = ParseOptions::with_version(LanguageVersion::V0_1);
+        assert!(parse_with("const LIMIT = 3\n", &options).is_err());
+        assert!(parse_with("const LIMIT = 3\n", &options.clone().with_feature(Feature::ModuleVars)).is_ok());
+        assert!(parse_with("#patchwork 0.2\nconst LIMIT = 3\n", &options).is_ok());
+
+        assert!(parse("#patchwork 9.0\nskill main() {}\n").is_err());
+        assert!(parse("#patchwork latest\nskill main() {}\n").is_err());
+        // Only comments before the first code can hold the pragma
+        assert!(parse("skill main() {}\n#patchwork 9.0\n").is_ok());
+    }
+
+    #[test]
+    fn test_front_matter_is_skipped() {
+        let input = "---\nmodel: fast\nnote: \"naïve: {not code}\"\n---\n#patchwork 0.2\nskill main() { plan { step \"one\" } }\n";
+        let block = front_matter::find(input).unwrap();
+        assert_eq!(block.text, "model: fast\nnote: \"naïve: {not code}\"\n");
+        assert_eq!(&input[block.end..block.end + 10], "#patchwork");
+
+        // The pragma after the block still applies, and spans point into the file
+        match parse(input) {
+            Err(ParseError::FeatureUnavailable { span: Some((start, end)), .. }) => assert_eq!(&input[start..end], "one"),
+            other => panic!("Expected a feature error, got {:?}", other),
+        }
+        assert_eq!(parse("---\ntitle: x\n---\nskill main() {}\n").unwrap().items.len(), 1);
+
+        // Only the first line can open a block, and it must be closed
+        assert!(front_matter::find("\n---\ntitle: x\n---\n").is_none());
+        assert!(front_matter::find("---\ntitle: x\n").is_none());
+    }
+
+    #[test]
+    fn test_structured_parse_errors() {
+        let err = parse("skill main() {\n  var x = 1\n").unwrap_err();
+        assert!(matches!(&err, ParseError::UnexpectedEof { expected, .. } if expected.contains(&"\"}\"".to_string())), "{:?}", err);
+        assert_eq!(err.hint().as_deref(), Some("a block is missing its closing `}`"));
+
+        let input = "skill main() {\n  var = 5\n}\n";
+        match parse(input) {
+            Err(ParseError::UnexpectedToken { span: (start, end), kind, text, .. }) => {
+                assert_eq!(kind, "Assign");
+                assert_eq!(text, "=");
+                assert_eq!(&input[start..end], "=");
+            }
+            other => panic!("Expected an unexpected token, got {:?}", other),
+        }
+
+        match parse("skill main() {\n  plan {\n    stage \"one\"\n  }\n}\n") {
+            Err(error @ ParseError::ExpectedKeyword { .. }) => {
+                assert_eq!(error.to_string(), "expected `step` in a plan, found `stage`");
+            }
+            other => panic!("Expected a keyword error, got {:?}", other),
+        }
+
+        // Only lexer failures have an underlying source
+        assert!(std::error::Error::source(&err).is_none());
+        let lex = ParseError::from(LexError { message: "bad byte".to_string(), span: Some((0, 1)) });
+        assert_eq!(std::error::Error::source(&lex).map(ToString::to_string).as_deref(), Some("bad byte"));
+    }
+
+    #[test]
+    fn test_plan_statement() {
+        let input = r#"
+            skill report() {
+                plan { step "collect commits"; step "summarize"
+                    step "write ${name}" }
+                var plan = 1
+            }
+        "#;
+        let result = parse(input);
+        assert!(result.is_ok(), "Failed to parse plan: {:?}", result);
+
+        let program = result.unwrap();
+        let skill = match &program.items[0] {
+            Item::Skill(s) => s,
+            _ => panic!("Expected skill"),
+        };
+        match &skill.body.statements[0] {
+            Statement::Plan { steps } => {
+                assert_eq!(steps.len(), 3);
+                assert_eq!(steps[0].parts, vec![StringPart::Text("collect commits")]);
+            }
+            other => panic!("Expected plan, got {:?}", other),
+        }
+        // `plan` is only special before a block of steps
+        assert!(matches!(skill.body.statements[1], Statement::VarDecl { .. }));
+
+        assert!(parse("skill s() { plan { task \"x\" } }").is_err());
+        assert!(parse("skill s() { agenda { step \"x\" } }").is_err());
+    }
+
+    #[test]
+    fn test_eval_declaration() {
+        let input = r#"
+            import data "./cases.json"
+
+            eval "summary quality" {
+                input cases
+                output think { Summarize ${input.issue} in one line. }
+                expect_contains input.keyword; judge think {
+                    Rate from 0 to 1 how well "${output}" summarizes ${input.issue}.
+                }
+            }
+        "#;
+        let result = parse(input);
+        assert!(result.is_ok(), "Failed to parse eval: {:?}", result);
+
+        let program = result.unwrap();
+        let eval = match &program.items[1] {
+            Item::Eval(decl) => decl,
+            other => panic!("Expected eval, got {:?}", other),
+        };
+        assert_eq!(eval.name, "summary quality");
+        assert_eq!(eval.input, Expr::Identifier("cases"));
+        assert!(matches!(eval.output, Expr::Think(_)));
+        assert!(matches!(eval.checks[..], [EvalCheck::Contains(_), EvalCheck::Judge(Expr::Think(_))]));
+
+        let err = parse("eval \"e\" {\n  output 1\n}\n").unwrap_err();
+        assert!(err.to_string().contains("has no `input` dataset"), "{}", err);
+        let err = parse("eval \"e\" {\n  input []\n  output 1\n  expect 2\n}\n").unwrap_err();
+        assert!(err.to_string().contains("takes a type name"), "{}", err);
+        let err = parse("eval \"e\" {\n  input []\n  output 1\n  check 2\n}\n").unwrap_err();
+        assert!(err.to_string().contains("found `check`"), "{}", err);
+        let program = parse("eval \"e\" {\n  input []\n  output 1\n  expect Label\n}\n").unwrap();
+        assert!(matches!(&program.items[0], Item::Eval(decl) if decl.checks == [EvalCheck::Expect("Label")]));
+        assert!(parse("evaluate \"e\" {\n  input []\n  output 1\n}\n").is_err());
+    }
+
     // ==================== Statement Separation ====================
 
     #[test]
@@ -760,6 +1017,16 @@ mod tests {
         assert!(matches!(func.body.statements[4], Statement::Expr(Expr::Identifier("foo"))));
     }
 
+    #[test]
+    fn test_decimal_literals() {
+        let input = "worker main() {\n    0.25\n    x.y\n}";
+        let program = parse(input).unwrap();
+        let Item::Worker(worker) = &program.items[0] else { panic!("Expected worker") };
+        assert!(matches!(worker.body.statements[0], Statement::Expr(Expr::Number("0.25"))));
+        // A dot after a name is still member access
+        assert!(matches!(worker.body.statements[1], Statement::Expr(Expr::Member { .. })));
+    }
+
     #[test]
     fn test_string_literal() {
         let input = r#"
@@ -885,7 +1152,7 @@ mod tests {
 
         assert_eq!(func.body.statements.len(), 4);
 
-        let ops = vec![BinOp::Eq, BinOp::NotEq, BinOp::Lt, BinOp::Gt];
+        let ops = [BinOp::Eq, BinOp::NotEq, BinOp::Lt, BinOp::Gt];
         for (i, expected_op) in ops.iter().enumerate() {
             match &func.body.statements[i] {
                 Statement::Expr(Expr::Binary { op, .. }) => {
@@ -1274,6 +1541,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_approve_block() {
+        let input = r#"
+            worker test(branch) {
+                approve {
+                    Delete ${branch} and its pull request
+                }
+            }
+        "#;
+        let program = parse(input).expect("Should parse");
+        let Item::Worker(worker) = &program.items[0] else { panic!("Expected worker") };
+        let Statement::Expr(Expr::Approve(prompt)) = &worker.body.statements[0] else {
+            panic!("Expected approve block, got {:?}", worker.body.statements[0]);
+        };
+        assert!(matches!(prompt.items.as_slice(), [
+            PromptItem::Text("Delete"),
+            PromptItem::Interpolation(_),
+            PromptItem::Text("and its pull request"),
+        ]));
+    }
+
     #[test]
     fn test_prompt_with_embedded_do() {
         let input = r#"
@@ -1299,7 +1587,7 @@ mod tests {
                         match init.as_ref().unwrap() {
                             Expr::Think(prompt_block) => {
                                 // Should have at least some items
-                                assert!(prompt_block.items.len() > 0);
+                                assert!(!prompt_block.items.is_empty());
 
                                 // Find the Code item
                                 let has_code = prompt_block.items.iter()
@@ -1321,6 +1609,67 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_prompt_with_examples() {
+        let input = r#"
+            worker test() {
+                var label = think {
+                    Label these examples of feedback.
+                    examples {
+                        input: "Love it",
+                        output: "positive"
+                    }
+                    examples { input: sample, output: "negative" }
+                }
+            }
+        "#;
+        let program = parse(input).expect("Should parse");
+        let Item::Worker(task) = &program.items[0] else { panic!("Expected worker") };
+        let Statement::VarDecl { init: Some(Expr::Think(prompt)), .. } = &task.body.statements[0] else {
+            panic!("Expected think block");
+        };
+        // "examples" without a brace is just a word
+        assert_eq!(prompt.items[0], PromptItem::Text("Label these examples of feedback."));
+        let keys: Vec<Vec<&str>> = prompt.items[1..]
+            .iter()
+            .map(|item| match item {
+                PromptItem::Examples(fields) => fields.iter().map(|field| field.key).collect(),
+                other => panic!("Expected examples, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(keys, vec![vec!["input", "output"], vec!["input", "output"]]);
+    }
+
+    #[test]
+    fn test_prompt_with_variants() {
+        let input = r#"
+            worker test() {
+                var summary = think {
+                    Pick a variant of the summary style.
+                    variant concise { Summarize $issue in one line. }
+                    variant detailed {
+                        Summarize ${issue} in a paragraph, with {braces} kept.
+                    }
+                }
+            }
+        "#;
+        let program = parse(input).expect("Should parse");
+        let Item::Worker(task) = &program.items[0] else { panic!("Expected worker") };
+        let Statement::VarDecl { init: Some(Expr::Think(prompt)), .. } = &task.body.statements[0] else {
+            panic!("Expected think block");
+        };
+        // "variant" without a name and brace is just a word
+        assert_eq!(prompt.items[0], PromptItem::Text("Pick a variant of the summary style."));
+        let variants: Vec<(&str, usize)> = prompt.items[1..]
+            .iter()
+            .map(|item| match item {
+                PromptItem::Variant { name, block } => (*name, block.items.len()),
+                other => panic!("Expected variant, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(variants, vec![("concise", 3), ("detailed", 3)]);
+    }
+
     // Note: do { } is NOT a standalone expression in patchwork
     // It's only used inside think/ask prompt blocks
     // So we don't have a test for standalone do expressions
@@ -1480,6 +1829,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_prompt_text_run_is_source_slice() {
+        // A run of prompt text spanning lines is one slice of the input
+        let paragraph = "Lorem ipsum dolor sit amet.\n".repeat(2000);
+        let input = format!("worker main() {{\n    var result = think {{\n{}    }}\n}}\n", paragraph);
+        let program = parse(&input).expect("Should parse a long prompt");
+        let Item::Worker(worker) = &program.items[0] else { panic!("Expected worker") };
+        let Statement::VarDecl { init: Some(Expr::Think(prompt)), .. } = &worker.body.statements[0] else {
+            panic!("Expected think expression");
+        };
+        let texts: Vec<&str> = prompt.items.iter().filter_map(|item| match item {
+            PromptItem::Text(t) => Some(*t),
+            _ => None,
+        }).collect();
+        assert_eq!(texts, vec![paragraph.trim_end()]);
+        let offset = texts[0].as_ptr() as usize - input.as_ptr() as usize;
+        assert_eq!(&input[offset..offset + texts[0].len()], texts[0]);
+    }
+
     // ===== String Interpolation Tests =====
 
     #[test]
@@ -3035,13 +3403,11 @@ skill rewriting_git_branch(changeset_description) {
                 // Find a var decl that has think || ask pattern
                 let mut found_think_ask = false;
                 for stmt in &task.body.statements {
-                    if let Statement::VarDecl { init: Some(expr), .. } = stmt {
-                        // Check if it's a Binary OR with Think on left
-                        if let Expr::Binary { op: BinOp::Or, left, right } = expr {
-                            if matches!(&**left, Expr::Think(_)) && matches!(&**right, Expr::Ask(_)) {
-                                found_think_ask = true;
-                                break;
-                            }
+                    // Check if it's a Binary OR with Think on left
+                    if let Statement::VarDecl { init: Some(Expr::Binary { op: BinOp::Or, left, right }), .. } = stmt {
+                        if matches!(&**left, Expr::Think(_)) && matches!(&**right, Expr::Ask(_)) {
+                            found_think_ask = true;
+                            break;
                         }
                     }
                 }
@@ -3096,6 +3462,26 @@ skill rewriting_git_branch(changeset_description) {
         }
     }
 
+    #[test]
+    fn test_parse_background_command() {
+        let input = "skill main() {\n    var build = $(cargo build --release &)\n    for var line in build.lines() {\n        print(line)\n    }\n}";
+        let program = parse(input).expect("Should parse");
+        let Item::Skill(skill) = &program.items[0] else { panic!("Expected skill") };
+        match &skill.body.statements[0] {
+            Statement::VarDecl { init: Some(Expr::ShellBackground(inner)), .. } => match inner.as_ref() {
+                Expr::BareCommand { name, args } => {
+                    assert_eq!(*name, "cargo");
+                    assert_eq!(args.len(), 2);
+                }
+                other => panic!("Expected BareCommand inside ShellBackground, got {:?}", other),
+            },
+            other => panic!("Expected var decl, got {:?}", other),
+        }
+
+        // Only command substitution can run in the background
+        assert!(parse("skill main() {\n    $ cargo build &\n}").is_err());
+    }
+
     #[test]
     fn test_validate_bare_command_structure() {
         // Validate bare command creates correct AST
@@ -3390,6 +3776,20 @@ worker test() {
         let result = parse(input);
         assert!(result.is_ok(), "Failed to parse backtick in prompt: {:?}", result);
     }
+
+    #[test]
+    fn test_offsets_after_non_ascii_prompt_text() {
+        // Lexer columns count characters; offsets must still land on the
+        // right bytes after multibyte text, for every token on the line
+        let input = "skill s(name, role) {\n  think { Résumé of ${name}, a naïve ${role} }\n}\n";
+        let program = parse(input).unwrap();
+        let symbols = crate::resolve::resolve(&program, input).symbols;
+        let spans: Vec<(usize, usize)> = symbols.references().iter().filter_map(|r| r.span).collect();
+        assert_eq!(spans.len(), 2);
+        for (start, end) in spans {
+            assert!(matches!(&input[start..end], "name" | "role"), "{:?}", &input[start..end]);
+        }
+    }
 }
 
 #[cfg(test)]
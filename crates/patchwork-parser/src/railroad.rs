@@ -0,0 +1,272 @@
+//! Railroad diagrams of grammar rules, as standalone SVG.
+//!
+//! Each piece of a rule is laid out as a box with a line running through
+//! it: `width` across, `up` above the line and `down` below. Sequences sit
+//! side by side, the alternatives of a choice stack downward with the first
+//! on the line, and repetition loops back underneath. Terminals are drawn
+//! rounded and rules square, linking to `index.html#Rule` so the diagrams
+//! inlined in one page can be followed from rule to rule.
+
+use crate::grammar::{Expr, Grammar, Rule};
+
+/// Radius of every curve, and the length of the line on either side.
+const R: i32 = 10;
+/// Space between stacked alternatives, and under a loop.
+const GAP: i32 = 8;
+const CHAR_WIDTH: i32 = 8;
+const BOX_HEIGHT: i32 = 22;
+const PADDING: i32 = 20;
+
+const STYLE: &str = "path { stroke: #333; stroke-width: 2; fill: none; }
+rect { stroke: #333; stroke-width: 2; fill: #eef; }
+.rule rect { fill: #efe; }
+text { font: 13px monospace; text-anchor: middle; }
+.token text { font-style: italic; }";
+
+/// The diagram for `rule`, with all its alternatives.
+pub fn diagram(rule: &Rule) -> String {
+    let expr = match rule.alternatives.as_slice() {
+        [single] => single.clone(),
+        alternatives => Expr::Choice(alternatives.to_vec()),
+    };
+    let node = Node::new(&expr);
+    let width = node.width + 2 * (PADDING + R);
+    let height = node.up + node.down + 2 * PADDING;
+    let y = PADDING + node.up;
+    let end = PADDING + 2 * R + node.width;
+
+    let mut out = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">\n<style>\n{}\n</style>\n",
+        width, height, width, height, STYLE
+    );
+    out.push_str(&format!("<path d=\"M {} {} v {} M {} {} v {}\"/>\n", PADDING, y - R, 2 * R, end, y - R, 2 * R));
+    line(&mut out, PADDING, y, R);
+    node.draw(PADDING + R, y, &mut out);
+    line(&mut out, end - R, y, R);
+    out.push_str("</svg>\n");
+    out
+}
+
+/// A page with every rule's diagram and EBNF, for `docs/grammar/`.
+pub fn index(grammar: &Grammar) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Patchwork grammar</title>\n</head>\n<body>\n<h1>Patchwork grammar</h1>\n",
+    );
+    for rule in &grammar.rules {
+        out.push_str(&format!("<h2 id=\"{}\">{}</h2>\n", rule.name, rule.name));
+        out.push_str(&diagram(rule));
+        out.push_str(&format!("<pre>{}</pre>\n", escape(&rule.to_ebnf())));
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+enum Kind {
+    Text { text: String, class: &'static str },
+    Skip,
+    Sequence(Vec<Node>),
+    Choice(Vec<Node>),
+    Loop(Box<Node>),
+}
+
+struct Node {
+    kind: Kind,
+    width: i32,
+    up: i32,
+    down: i32,
+}
+
+impl Node {
+    fn new(expr: &Expr) -> Self {
+        match expr {
+            Expr::Literal(text) => Node::text(text, "literal"),
+            Expr::Token(name) => Node::text(name, "token"),
+            Expr::Rule(name) => Node::text(name, "rule"),
+            Expr::Sequence(items) if items.is_empty() => Node::skip(),
+            Expr::Sequence(items) => {
+                let items: Vec<Node> = items.iter().map(Node::new).collect();
+                let width = items.iter().map(|item| item.width).sum::<i32>() + R * (items.len() as i32 - 1);
+                let up = items.iter().map(|item| item.up).max().unwrap_or(0);
+                let down = items.iter().map(|item| item.down).max().unwrap_or(0);
+                Node { kind: Kind::Sequence(items), width, up, down }
+            }
+            Expr::Choice(alternatives) => Node::choice(alternatives.iter().map(Node::new).collect()),
+            Expr::Optional(inner) => Node::choice(vec![Node::skip(), Node::new(inner)]),
+            Expr::OneOrMore(inner) => Node::repeat(Node::new(inner)),
+            Expr::ZeroOrMore(inner) => Node::choice(vec![Node::skip(), Node::repeat(Node::new(inner))]),
+        }
+    }
+
+    fn text(text: &str, class: &'static str) -> Self {
+        let width = text.chars().count() as i32 * CHAR_WIDTH + 2 * R;
+        Node { kind: Kind::Text { text: text.to_string(), class }, width, up: BOX_HEIGHT / 2, down: BOX_HEIGHT / 2 }
+    }
+
+    fn skip() -> Self {
+        Node { kind: Kind::Skip, width: 0, up: 0, down: 0 }
+    }
+
+    fn choice(alternatives: Vec<Node>) -> Self {
+        let width = alternatives.iter().map(|alternative| alternative.width).max().unwrap_or(0) + 4 * R;
+        let offsets = offsets(&alternatives);
+        let up = alternatives.first().map_or(0, |first| first.up);
+        let down = match (offsets.last(), alternatives.last()) {
+            (Some(offset), Some(last)) => offset + last.down,
+            _ => 0,
+        };
+        Node { kind: Kind::Choice(alternatives), width, up, down }
+    }
+
+    fn repeat(inner: Node) -> Self {
+        let (width, up, down) = (inner.width + 2 * R, inner.up, loop_depth(&inner));
+        Node { kind: Kind::Loop(Box::new(inner)), width, up, down }
+    }
+
+    /// Draw the node with its line entering at `(x, y)`.
+    fn draw(&self, x: i32, y: i32, out: &mut String) {
+        match &self.kind {
+            Kind::Text { text, class } => {
+                let rounded = if *class == "rule" { 0 } else { R };
+                let (open, close) = match *class {
+                    "rule" => (format!("<a href=\"index.html#{}\">", text), "</a>"),
+                    _ => (String::new(), ""),
+                };
+                out.push_str(&format!(
+                    "<g class=\"{}\">{}<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"{}\"/><text x=\"{}\" y=\"{}\">{}</text>{}</g>\n",
+                    class,
+                    open,
+                    x,
+                    y - BOX_HEIGHT / 2,
+                    self.width,
+                    BOX_HEIGHT,
+                    rounded,
+                    x + self.width / 2,
+                    y + 4,
+                    escape(text),
+                    close
+                ));
+            }
+            Kind::Skip => line(out, x, y, self.width),
+            Kind::Sequence(items) => {
+                let mut x = x;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        line(out, x, y, R);
+                        x += R;
+                    }
+                    item.draw(x, y, out);
+                    x += item.width;
+                }
+            }
+            Kind::Choice(alternatives) => {
+                let inner = self.width - 4 * R;
+                for (alternative, offset) in alternatives.iter().zip(offsets(alternatives)) {
+                    if offset == 0 {
+                        line(out, x, y, 2 * R);
+                    } else {
+                        // Down from the main line into the alternative
+                        out.push_str(&format!(
+                            "<path d=\"M {} {} a {r} {r} 0 0 1 {r} {r} v {} a {r} {r} 0 0 0 {r} {r}\"/>\n",
+                            x,
+                            y,
+                            offset - 2 * R,
+                            r = R
+                        ));
+                    }
+                    alternative.draw(x + 2 * R, y + offset, out);
+                    line(out, x + 2 * R + alternative.width, y + offset, inner - alternative.width);
+                    if offset == 0 {
+                        line(out, x + 2 * R + inner, y, 2 * R);
+                    } else {
+                        // Back up to the main line
+                        out.push_str(&format!(
+                            "<path d=\"M {} {} a {r} {r} 0 0 0 {r} -{r} v -{} a {r} {r} 0 0 1 {r} -{r}\"/>\n",
+                            x + 2 * R + inner,
+                            y + offset,
+                            offset - 2 * R,
+                            r = R
+                        ));
+                    }
+                }
+            }
+            Kind::Loop(inner) => {
+                line(out, x, y, R);
+                inner.draw(x + R, y, out);
+                line(out, x + R + inner.width, y, R);
+                // From the end of the item, down, back left underneath it, and up to its start
+                out.push_str(&format!(
+                    "<path d=\"M {} {} a {r} {r} 0 0 1 {r} {r} v {} a {r} {r} 0 0 1 -{r} {r} h -{} a {r} {r} 0 0 1 -{r} -{r} v -{} a {r} {r} 0 0 1 {r} -{r}\"/>\n",
+                    x + R + inner.width,
+                    y,
+                    self.down - 2 * R,
+                    inner.width,
+                    self.down - 2 * R,
+                    r = R
+                ));
+            }
+        }
+    }
+}
+
+/// How far below the main line each alternative's line runs. The first is
+/// on the main line; the rest need room for two curves to reach them.
+fn offsets(alternatives: &[Node]) -> Vec<i32> {
+    let mut offsets: Vec<i32> = Vec::new();
+    for (i, alternative) in alternatives.iter().enumerate() {
+        let offset = match i {
+            0 => 0,
+            _ => (offsets[i - 1] + alternatives[i - 1].down + GAP + alternative.up).max(2 * R),
+        };
+        offsets.push(offset);
+    }
+    offsets
+}
+
+/// How far below the main line a loop around `inner` runs.
+fn loop_depth(inner: &Node) -> i32 {
+    (inner.down + GAP).max(2 * R)
+}
+
+fn line(out: &mut String, x: i32, y: i32, length: i32) {
+    if length > 0 {
+        out.push_str(&format!("<path d=\"M {} {} h {}\"/>\n", x, y, length));
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(alternatives: Vec<Expr>) -> Rule {
+        Rule { name: "Test".to_string(), public: false, alternatives }
+    }
+
+    #[test]
+    fn test_sequence_layout() {
+        let svg = diagram(&rule(vec![Expr::Sequence(vec![
+            Expr::Literal("<=".to_string()),
+            Expr::Rule("AddExpr".to_string()),
+        ])]));
+        // 36 + 10 + 76 across, with padding and a line at each end
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"182\" height=\"62\""));
+        assert!(svg.contains("<text x=\"48\" y=\"35\">&lt;=</text>"));
+        assert!(svg.contains("<a href=\"index.html#AddExpr\">"));
+    }
+
+    #[test]
+    fn test_choice_and_loop_layout() {
+        let item = || Expr::Token("newline".to_string());
+        let choice = Node::new(&Expr::Choice(vec![item(), item(), Expr::OneOrMore(Box::new(item()))]));
+        // Each alternative 11 + 8 + 11 below the last
+        let Kind::Choice(alternatives) = &choice.kind else { unreachable!() };
+        assert_eq!(offsets(alternatives), vec![0, 30, 60]);
+        assert_eq!((choice.width, choice.up, choice.down), (136, 11, 80));
+
+        let optional = Node::new(&Expr::Optional(Box::new(item())));
+        assert_eq!((optional.width, optional.up, optional.down), (116, 0, 31));
+    }
+}
@@ -0,0 +1,1107 @@
+//! Name resolution.
+//!
+//! The resolver walks a parsed program and connects every identifier to the
+//! declaration it refers to. The result is a `SymbolTable` holding one
+//! `Symbol` per declaration and one `Reference` per use, and a
+//! `ResolvedProgram` that pairs the table with the AST it describes.
+//!
+//! The AST carries no spans, but every identifier is a `&'input str` slice of
+//! the source text, so an identifier's address doubles as its location. The
+//! table is keyed by that address: given any identifier slice taken from the
+//! AST, `SymbolTable::resolution` returns what it refers to.
+//!
+//! Scopes mirror the interpreter's runtime exactly: each callable body has a
+//! parameter scope, every block pushes a scope, and a `for` loop pushes a
+//! scope for its loop variable outside the body block. A local's `slot` is
+//! its index in declaration order within its scope, so the interpreter can
+//! find a variable by walking up `hops` scopes and indexing `slot`.
+//!
+//! The table also records what a skill's annotations ask of the runtime
+//! while it runs, as `SkillSettings`:
+//!
+//! ```text
+//! @concurrency(1)
+//! @timeout(120s)
+//! @capabilities(shell)
+//! skill deploy() { ... }
+//! ```
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::ast::*;
+
+/// Index of a symbol in a `SymbolTable`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SymbolId(pub usize);
+
+/// What kind of declaration introduced a symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Import,
+    Skill,
+    Worker,
+    Trait,
+    Function,
+    /// A method declared inside a trait.
+    Method,
+    TypeAlias,
+    Param,
+    /// `var name = ...`, including names bound by destructuring patterns.
+    Var,
+    /// `for var name in ...`
+    LoopVar,
+    /// `var name = ...` at the top level of a module.
+    ModuleVar,
+    /// `const NAME = ...` at the top level of a module.
+    Const,
+}
+
+impl SymbolKind {
+    /// Is this a variable living in a runtime scope?
+    pub fn is_local(&self) -> bool {
+        matches!(self, SymbolKind::Param | SymbolKind::Var | SymbolKind::LoopVar)
+    }
+}
+
+/// A declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Byte range of the declaring identifier, if it appears in the source.
+    pub span: Option<(usize, usize)>,
+    /// Index of the declaration within its scope (locals only).
+    pub slot: usize,
+}
+
+/// What an identifier refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// A variable `hops` scopes up from the use site, at index `slot`.
+    Local { symbol: SymbolId, hops: usize, slot: usize },
+    /// A top-level item.
+    Global(SymbolId),
+    /// A builtin function or primitive type name.
+    Builtin,
+    /// Nothing in scope has this name.
+    Unresolved,
+}
+
+impl Resolution {
+    /// The declaration this resolves to, if any.
+    pub fn symbol(&self) -> Option<SymbolId> {
+        match self {
+            Resolution::Local { symbol, .. } | Resolution::Global(symbol) => Some(*symbol),
+            Resolution::Builtin | Resolution::Unresolved => None,
+        }
+    }
+}
+
+/// A use of a name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    pub name: String,
+    /// Byte range of the identifier, if it appears in the source.
+    pub span: Option<(usize, usize)>,
+    pub resolution: Resolution,
+    /// Is this the target of an assignment?
+    pub is_write: bool,
+}
+
+/// A module-level `var` or `const` declaration and what its initializer reads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Initializer {
+    /// Index of the declaration in `Program::items`.
+    pub item: usize,
+    /// Names the declaration binds.
+    pub binds: Vec<SymbolId>,
+    /// Other module-level variables the initializer reads.
+    pub reads: Vec<SymbolId>,
+}
+
+/// What a skill's annotations ask of the runtime while it runs.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SkillSettings {
+    /// `@concurrency(n)`: at most `n` runs of the skill at once.
+    pub concurrency: Option<usize>,
+    /// `@timeout(120s)`: how long a run may take.
+    pub timeout: Option<Duration>,
+    /// `@capabilities(shell, ...)`: the only capabilities a run may use.
+    pub capabilities: Option<Vec<String>>,
+}
+
+/// A skill annotation whose arguments don't fit its name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidAnnotation {
+    /// The annotated skill.
+    pub skill: String,
+    /// Byte range of the annotation's name, if it appears in the source.
+    pub span: Option<(usize, usize)>,
+    pub message: String,
+}
+
+/// Capabilities `@capabilities(...)` may name, as in the config's
+/// `capabilities` table.
+pub const SKILL_CAPABILITIES: &[&str] = &["shell", "file_write", "notify", "eval_code"];
+
+/// The longest `@timeout` a skill may ask for: a week.
+const MAX_SKILL_TIMEOUT: Duration = Duration::from_secs(7 * 24 * 3600);
+
+/// Builtin functions provided by the interpreter.
+pub const BUILTINS: &[&str] = &[
+    "cat", "json", "print", "len", "keys", "values", "typeof", "read", "write",
+    "type_of", "is_null", "is_string", "is_number", "is_boolean", "is_array", "is_object",
+    "is_set", "is_tuple", "set", "tuple", "union", "intersect", "contains",
+    "group_by", "sort_by", "unique", "chunk", "flatten",
+    "pad_left", "pad_right", "truncate", "to_fixed", "format_number", "template", "render_template", "include_prompt",
+    "budget_remaining", "history", "last_response", "last_call_meta", "pin", "pinned",
+    "fork_context", "in_context", "merge_context",
+    "progress", "warn", "step_done", "sleep", "schedule_at", "with_timeout", "now", "validate",
+    "wait_all", "wait_any",
+    "temp_file", "temp_dir",
+    "eval_patchwork",
+];
+
+/// Primitive type names accepted in annotations.
+pub const PRIMITIVE_TYPES: &[&str] = &[
+    "string", "number", "int", "float", "bool", "boolean", "null",
+];
+
+/// Declarations and uses of every name in a program.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+    references: Vec<Reference>,
+    /// Identifier address -> declaring symbol, sorted by address.
+    definitions: Vec<(usize, SymbolId)>,
+    /// Identifier address -> index into `references`, sorted by address.
+    /// The interpreter looks up every variable it reads here, and a binary
+    /// search is cheaper than hashing.
+    uses: Vec<(usize, usize)>,
+    /// Module-level declarations, in source order.
+    initializers: Vec<Initializer>,
+    /// Settings of skills with `@concurrency`, `@timeout`, or `@capabilities`.
+    skills: HashMap<String, SkillSettings>,
+    invalid_annotations: Vec<InvalidAnnotation>,
+}
+
+impl SymbolTable {
+    /// All declarations, in source order.
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// All uses, in source order.
+    pub fn references(&self) -> &[Reference] {
+        &self.references
+    }
+
+    pub fn symbol(&self, id: SymbolId) -> &Symbol {
+        &self.symbols[id.0]
+    }
+
+    /// What the identifier slice `ident` (taken from the resolved AST) refers to.
+    ///
+    /// Returns `None` for slices the resolver did not see, such as names
+    /// synthesized by the parser rather than read from the source.
+    pub fn resolution(&self, ident: &str) -> Option<Resolution> {
+        let i = self.uses.binary_search_by_key(&address(ident), |&(address, _)| address).ok()?;
+        Some(self.references[self.uses[i].1].resolution)
+    }
+
+    /// The use of the identifier slice `ident`, if the resolver saw it.
+    pub fn reference(&self, ident: &str) -> Option<&Reference> {
+        let i = self.uses.binary_search_by_key(&address(ident), |&(address, _)| address).ok()?;
+        Some(&self.references[self.uses[i].1])
+    }
+
+    /// The symbol declared by the identifier slice `ident`, if it is a declaration.
+    pub fn declared_by(&self, ident: &str) -> Option<SymbolId> {
+        let i = self.definitions.binary_search_by_key(&address(ident), |&(address, _)| address).ok()?;
+        Some(self.definitions[i].1)
+    }
+
+    /// Sort the address indexes for lookup once resolution is done. An
+    /// identifier recorded more than once keeps its last entry.
+    fn finish(&mut self) {
+        fn index<T>(entries: &mut Vec<(usize, T)>) {
+            entries.reverse();
+            // Stable, so the last entry for an address comes first and survives dedup
+            entries.sort_by_key(|&(address, _)| address);
+            entries.dedup_by_key(|&mut (address, _)| address);
+        }
+        index(&mut self.definitions);
+        index(&mut self.uses);
+    }
+
+    /// The symbol declared or referenced at a byte offset in the source.
+    pub fn symbol_at(&self, offset: usize) -> Option<SymbolId> {
+        let contains = |span: Option<(usize, usize)>| {
+            span.is_some_and(|(start, end)| start <= offset && offset <= end)
+        };
+        self.symbols
+            .iter()
+            .position(|s| contains(s.span))
+            .map(SymbolId)
+            .or_else(|| {
+                self.references
+                    .iter()
+                    .find(|r| contains(r.span))
+                    .and_then(|r| r.resolution.symbol())
+            })
+    }
+
+    /// Every use of a symbol.
+    pub fn references_to(&self, id: SymbolId) -> impl Iterator<Item = &Reference> {
+        self.references
+            .iter()
+            .filter(move |r| r.resolution.symbol() == Some(id))
+    }
+
+    /// Module-level `var` and `const` declarations, in source order.
+    pub fn initializers(&self) -> &[Initializer] {
+        &self.initializers
+    }
+
+    /// The order to run module-level initializers in: each declaration
+    /// comes after every declaration its initializer reads, and otherwise
+    /// in source order. Returns item indices into `Program::items`.
+    ///
+    /// Fails with a description of the cycle if initializers read each
+    /// other circularly, e.g. `const A = B` and `const B = A`.
+    pub fn initialization_order(&self) -> Result<Vec<usize>, String> {
+        let owners: HashMap<SymbolId, usize> = self
+            .initializers
+            .iter()
+            .enumerate()
+            .flat_map(|(i, init)| init.binds.iter().map(move |&id| (id, i)))
+            .collect();
+        let mut done = vec![false; self.initializers.len()];
+        let mut path = Vec::new();
+        let mut order = Vec::new();
+        for i in 0..self.initializers.len() {
+            self.visit_initializer(i, &owners, &mut done, &mut path, &mut order)?;
+        }
+        Ok(order)
+    }
+
+    fn visit_initializer(
+        &self,
+        i: usize,
+        owners: &HashMap<SymbolId, usize>,
+        done: &mut [bool],
+        path: &mut Vec<usize>,
+        order: &mut Vec<usize>,
+    ) -> Result<(), String> {
+        if done[i] {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|&p| p == i) {
+            let names: Vec<&str> = path[start..]
+                .iter()
+                .chain([&i])
+                .map(|&p| self.initializer_name(p))
+                .collect();
+            return Err(format!(
+                "Module-level declarations depend on each other: {}",
+                names.join(" -> ")
+            ));
+        }
+        path.push(i);
+        for read in &self.initializers[i].reads {
+            self.visit_initializer(owners[read], owners, done, path, order)?;
+        }
+        path.pop();
+        done[i] = true;
+        order.push(self.initializers[i].item);
+        Ok(())
+    }
+
+    fn initializer_name(&self, i: usize) -> &str {
+        self.initializers[i]
+            .binds
+            .first()
+            .map_or("_", |&id| self.symbols[id.0].name.as_str())
+    }
+
+    /// What the annotations of the skill called `name` ask for, if it has
+    /// any of `@concurrency`, `@timeout`, or `@capabilities`.
+    pub fn skill_settings(&self, name: &str) -> Option<&SkillSettings> {
+        self.skills.get(name)
+    }
+
+    /// Skill annotations whose arguments don't fit their name, in source order.
+    pub fn invalid_annotations(&self) -> &[InvalidAnnotation] {
+        &self.invalid_annotations
+    }
+
+    /// Uses that did not resolve to anything.
+    pub fn unresolved(&self) -> impl Iterator<Item = &Reference> {
+        self.references
+            .iter()
+            .filter(|r| r.resolution == Resolution::Unresolved)
+    }
+}
+
+/// A program together with its symbol table.
+#[derive(Debug, Clone)]
+pub struct ResolvedProgram<'a, 'input> {
+    pub program: &'a Program<'input>,
+    pub symbols: SymbolTable,
+}
+
+impl<'a, 'input> ResolvedProgram<'a, 'input> {
+    /// What an identifier expression refers to.
+    pub fn resolution_of(&self, expr: &Expr) -> Option<Resolution> {
+        match expr {
+            Expr::Identifier(name) => self.symbols.resolution(name),
+            _ => None,
+        }
+    }
+}
+
+/// Resolve every name in `program`, which must have been parsed from `source`.
+pub fn resolve<'a, 'input>(program: &'a Program<'input>, source: &'input str) -> ResolvedProgram<'a, 'input> {
+    let mut resolver = Resolver::new(source);
+    resolver.resolve_program(program);
+    resolver.table.finish();
+    ResolvedProgram {
+        program,
+        symbols: resolver.table,
+    }
+}
+
+fn address(ident: &str) -> usize {
+    ident.as_ptr() as usize
+}
+
+/// `@concurrency(n)`: a positive whole number.
+fn concurrency_arg(args: &[&str]) -> Result<usize, String> {
+    match args {
+        [arg] => arg
+            .parse()
+            .ok()
+            .filter(|&n| n > 0)
+            .ok_or_else(|| format!("`@concurrency` takes a positive whole number, not `{}`", arg)),
+        _ => Err("`@concurrency` takes one argument, such as `@concurrency(1)`".to_string()),
+    }
+}
+
+/// `@timeout(d)`: a whole number with a unit of `ms`, `s`, `m`, or `h`, up
+/// to `MAX_SKILL_TIMEOUT`.
+fn timeout_arg(args: &[&str]) -> Result<Duration, String> {
+    let [arg] = args else {
+        return Err("`@timeout` takes one argument, such as `@timeout(120s)`".to_string());
+    };
+    let invalid = || format!("`@timeout` takes a duration in ms, s, m, or h, such as `120s`, not `{}`", arg);
+    let split = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+    let (number, unit) = arg.split_at(split);
+    let n: u64 = number.parse().map_err(|_| invalid())?;
+    let timeout = match unit {
+        "ms" => Some(Duration::from_millis(n)),
+        "s" => Some(Duration::from_secs(n)),
+        "m" => n.checked_mul(60).map(Duration::from_secs),
+        "h" => n.checked_mul(3600).map(Duration::from_secs),
+        _ => None,
+    }
+    .ok_or_else(invalid)?;
+    if timeout > MAX_SKILL_TIMEOUT {
+        return Err(format!("`@timeout` can be at most {}h, not `{}`", MAX_SKILL_TIMEOUT.as_secs() / 3600, arg));
+    }
+    Ok(timeout)
+}
+
+/// `@capabilities(name, ...)`: names from `SKILL_CAPABILITIES`, possibly none.
+fn capabilities_arg(args: &[&str]) -> Result<Vec<String>, String> {
+    match args.iter().find(|arg| !SKILL_CAPABILITIES.contains(arg)) {
+        Some(arg) => Err(format!(
+            "`{}` isn't a capability `@capabilities` can name (expected {})",
+            arg,
+            SKILL_CAPABILITIES.join(", ")
+        )),
+        None => Ok(args.iter().map(|arg| arg.to_string()).collect()),
+    }
+}
+
+struct Resolver<'s> {
+    source: &'s str,
+    table: SymbolTable,
+    globals: HashMap<String, SymbolId>,
+    types: HashMap<String, SymbolId>,
+    /// Local scopes, innermost last. Each holds its symbols in slot order.
+    scopes: Vec<Vec<SymbolId>>,
+}
+
+impl<'s> Resolver<'s> {
+    fn new(source: &'s str) -> Self {
+        Self {
+            source,
+            table: SymbolTable::default(),
+            globals: HashMap::new(),
+            types: HashMap::new(),
+            scopes: Vec::new(),
+        }
+    }
+
+    /// Byte range of an identifier, if it is a slice of the source.
+    fn span_of(&self, ident: &str) -> Option<(usize, usize)> {
+        let base = self.source.as_ptr() as usize;
+        let start = address(ident).checked_sub(base)?;
+        let end = start + ident.len();
+        (end <= self.source.len() && !ident.is_empty()).then_some((start, end))
+    }
+
+    // ---- declarations ----
+
+    fn declare(&mut self, ident: &str, kind: SymbolKind) -> SymbolId {
+        let id = SymbolId(self.table.symbols.len());
+        let slot = match self.scopes.last_mut() {
+            Some(scope) if kind.is_local() => {
+                scope.push(id);
+                scope.len() - 1
+            }
+            _ => 0,
+        };
+        let span = self.span_of(ident);
+        self.table.symbols.push(Symbol {
+            name: ident.to_string(),
+            kind,
+            span,
+            slot,
+        });
+        // Synthesized names share static storage; only key real source slices
+        if span.is_some() {
+            self.table.definitions.push((address(ident), id));
+        }
+        id
+    }
+
+    fn declare_global(&mut self, ident: &str, kind: SymbolKind) {
+        let id = self.declare(ident, kind);
+        self.globals.insert(ident.to_string(), id);
+    }
+
+    fn declare_type(&mut self, ident: &str, kind: SymbolKind) {
+        let id = self.declare(ident, kind);
+        self.types.insert(ident.to_string(), id);
+    }
+
+    // ---- uses ----
+
+    fn lookup(&self, name: &str) -> Resolution {
+        for (hops, scope) in self.scopes.iter().rev().enumerate() {
+            // Later declarations in the same scope shadow earlier ones
+            if let Some(&symbol) = scope.iter().rev().find(|id| self.table.symbols[id.0].name == name) {
+                let slot = self.table.symbols[symbol.0].slot;
+                return Resolution::Local { symbol, hops, slot };
+            }
+        }
+        if let Some(&id) = self.globals.get(name) {
+            Resolution::Global(id)
+        } else if BUILTINS.contains(&name) || name == "?" {
+            // `$?`, the last command's exit status, is always defined
+            Resolution::Builtin
+        } else {
+            Resolution::Unresolved
+        }
+    }
+
+    fn lookup_type(&self, name: &str) -> Resolution {
+        if let Some(&id) = self.types.get(name) {
+            Resolution::Global(id)
+        } else if PRIMITIVE_TYPES.contains(&name) {
+            Resolution::Builtin
+        } else {
+            Resolution::Unresolved
+        }
+    }
+
+    fn record(&mut self, ident: &str, resolution: Resolution, is_write: bool) {
+        let span = self.span_of(ident);
+        if span.is_some() {
+            self.table.uses.push((address(ident), self.table.references.len()));
+        }
+        self.table.references.push(Reference {
+            name: ident.to_string(),
+            span,
+            resolution,
+            is_write,
+        });
+    }
+
+    fn use_name(&mut self, ident: &str, is_write: bool) {
+        let resolution = self.lookup(ident);
+        self.record(ident, resolution, is_write);
+    }
+
+    // ---- scopes ----
+
+    fn push_scope(&mut self) {
+        self.scopes.push(Vec::new());
+    }
+
+    fn pop_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    // ---- items ----
+
+    fn resolve_program(&mut self, program: &Program) {
+        // Items are visible everywhere, regardless of declaration order
+        for item in &program.items {
+            match item {
+                Item::Import(decl) => match &decl.path {
+                    ImportPath::Simple(parts) => {
+                        if let Some(name) = parts.last() {
+                            self.declare_global(name, SymbolKind::Import);
+                        }
+                    }
+                    ImportPath::RelativeMulti(names) => {
+                        for name in names {
+                            self.declare_global(name, SymbolKind::Import);
+                        }
+                    }
+                    ImportPath::Data { name, .. } => self.declare_global(name, SymbolKind::Import),
+                },
+                Item::Skill(decl) => {
+                    self.declare_global(decl.name, SymbolKind::Skill);
+                    self.record_skill_settings(decl);
+                }
+                Item::Worker(decl) => self.declare_global(decl.name, SymbolKind::Worker),
+                Item::Function(decl) => self.declare_global(decl.name, SymbolKind::Function),
+                Item::Trait(decl) => self.declare_type(decl.name, SymbolKind::Trait),
+                Item::Type(decl) => self.declare_type(decl.name, SymbolKind::TypeAlias),
+                Item::Var(_) | Item::Eval(_) => {}
+            }
+        }
+
+        // Module-level variables, in source order
+        for (index, item) in program.items.iter().enumerate() {
+            if let Item::Var(decl) = item {
+                let kind = if decl.is_const { SymbolKind::Const } else { SymbolKind::ModuleVar };
+                let mut binds = Vec::new();
+                self.declare_global_pattern(&decl.pattern, kind, &mut binds);
+                self.table.initializers.push(Initializer { item: index, binds, reads: Vec::new() });
+            }
+        }
+
+        // Initializers were declared in source order, so they pair up with
+        // the `Item::Var`s in this pass
+        let mut next_initializer = 0;
+        for item in &program.items {
+            match item {
+                Item::Skill(decl) => self.resolve_callable(&decl.params, &decl.body),
+                Item::Worker(decl) => self.resolve_callable(&decl.params, &decl.body),
+                Item::Function(decl) => self.resolve_callable(&decl.params, &decl.body),
+                Item::Trait(decl) => {
+                    if let Some(super_trait) = &decl.super_trait {
+                        self.resolve_type(super_trait);
+                    }
+                    for method in &decl.methods {
+                        self.declare(method.name, SymbolKind::Method);
+                        self.resolve_callable(&method.params, &method.body);
+                    }
+                }
+                Item::Type(decl) => self.resolve_type(&decl.type_expr),
+                Item::Var(decl) => {
+                    let first_reference = self.table.references.len();
+                    self.resolve_expr(&decl.init);
+                    self.resolve_pattern_types(&decl.pattern);
+                    let reads = self.module_reads(first_reference);
+                    self.table.initializers[next_initializer].reads = reads;
+                    next_initializer += 1;
+                }
+                Item::Eval(decl) => self.resolve_eval(decl),
+                Item::Import(_) => {}
+            }
+        }
+    }
+
+    /// Record the settings `decl`'s annotations ask for. Others, such as
+    /// `@agent`, are left to whatever reads them.
+    fn record_skill_settings(&mut self, decl: &SkillDecl) {
+        let mut settings = SkillSettings::default();
+        for annotation in &decl.annotations {
+            let recorded = match annotation.name {
+                "concurrency" => concurrency_arg(&annotation.args).map(|n| settings.concurrency = Some(n)),
+                "timeout" => timeout_arg(&annotation.args).map(|timeout| settings.timeout = Some(timeout)),
+                "capabilities" => {
+                    capabilities_arg(&annotation.args).map(|names| settings.capabilities = Some(names))
+                }
+                _ => continue,
+            };
+            if let Err(message) = recorded {
+                let span = self.span_of(annotation.name);
+                self.table.invalid_annotations.push(InvalidAnnotation { skill: decl.name.to_string(), span, message });
+            }
+        }
+        if settings != SkillSettings::default() {
+            self.table.skills.insert(decl.name.to_string(), settings);
+        }
+    }
+
+    /// Each case of an eval binds `input`, then `output`, in a scope of
+    /// its own, as the interpreter does.
+    fn resolve_eval(&mut self, decl: &EvalDecl) {
+        self.resolve_expr(&decl.input);
+        self.push_scope();
+        self.declare("input", SymbolKind::Param);
+        self.resolve_expr(&decl.output);
+        self.declare("output", SymbolKind::Param);
+        for check in &decl.checks {
+            match check {
+                EvalCheck::Contains(expr) | EvalCheck::Judge(expr) => self.resolve_expr(expr),
+                EvalCheck::Expect(name) => self.resolve_type(&TypeExpr::Name(name)),
+            }
+        }
+        self.pop_scope();
+    }
+
+    /// Declare the names bound by a module-level pattern as globals.
+    fn declare_global_pattern(&mut self, pattern: &Pattern, kind: SymbolKind, binds: &mut Vec<SymbolId>) {
+        match pattern {
+            Pattern::Identifier { name, .. } => {
+                let id = self.declare(name, kind);
+                self.globals.insert(name.to_string(), id);
+                binds.push(id);
+            }
+            Pattern::Ignore => {}
+            Pattern::Object(fields) => {
+                for field in fields {
+                    self.declare_global_pattern(&field.pattern, kind, binds);
+                }
+            }
+            Pattern::Array(patterns) => {
+                for pattern in patterns {
+                    self.declare_global_pattern(pattern, kind, binds);
+                }
+            }
+        }
+    }
+
+    /// Resolve the type annotations in a pattern whose names are already declared.
+    fn resolve_pattern_types(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Identifier { type_ann, .. } => {
+                if let Some(ann) = type_ann {
+                    self.resolve_type(ann);
+                }
+            }
+            Pattern::Ignore => {}
+            Pattern::Object(fields) => {
+                for field in fields {
+                    if let Some(ann) = &field.type_ann {
+                        self.resolve_type(ann);
+                    }
+                    self.resolve_pattern_types(&field.pattern);
+                }
+            }
+            Pattern::Array(patterns) => {
+                for pattern in patterns {
+                    self.resolve_pattern_types(pattern);
+                }
+            }
+        }
+    }
+
+    /// Module-level variables read by the references recorded since `first`.
+    fn module_reads(&self, first: usize) -> Vec<SymbolId> {
+        let mut reads = Vec::new();
+        for reference in &self.table.references[first..] {
+            if let Resolution::Global(id) = reference.resolution {
+                let kind = self.table.symbols[id.0].kind;
+                if matches!(kind, SymbolKind::ModuleVar | SymbolKind::Const) && !reads.contains(&id) {
+                    reads.push(id);
+                }
+            }
+        }
+        reads
+    }
+
+    fn resolve_callable(&mut self, params: &[Param], body: &Block) {
+        self.push_scope();
+        for param in params {
+            if let Some(ann) = &param.type_ann {
+                self.resolve_type(ann);
+            }
+            self.declare(param.name, SymbolKind::Param);
+        }
+        self.resolve_block(body);
+        self.pop_scope();
+    }
+
+    // ---- statements ----
+
+    fn resolve_block(&mut self, block: &Block) {
+        self.push_scope();
+        for stmt in &block.statements {
+            self.resolve_statement(stmt);
+        }
+        self.pop_scope();
+    }
+
+    fn resolve_statement(&mut self, stmt: &Statement) {
+        match stmt {
+            Statement::VarDecl { pattern, init } => {
+                // The initializer cannot see the names it binds
+                if let Some(init) = init {
+                    self.resolve_expr(init);
+                }
+                self.resolve_pattern(pattern);
+            }
+            Statement::Expr(expr) => self.resolve_expr(expr),
+            Statement::If { condition, then_block, else_block } => {
+                self.resolve_expr(condition);
+                self.resolve_block(then_block);
+                if let Some(else_block) = else_block {
+                    self.resolve_block(else_block);
+                }
+            }
+            Statement::ForIn { var, iter, body } => {
+                self.resolve_expr(iter);
+                self.push_scope();
+                self.declare(var, SymbolKind::LoopVar);
+                self.resolve_block(body);
+                self.pop_scope();
+            }
+            Statement::While { condition, body } => {
+                self.resolve_expr(condition);
+                self.resolve_block(body);
+            }
+            Statement::Return(expr) => {
+                if let Some(expr) = expr {
+                    self.resolve_expr(expr);
+                }
+            }
+            Statement::Succeed | Statement::Break => {}
+            Statement::TypeDecl { name, type_expr } => {
+                self.resolve_type(type_expr);
+                self.declare_type(name, SymbolKind::TypeAlias);
+            }
+            Statement::Plan { steps } => {
+                for step in steps {
+                    self.resolve_string(step);
+                }
+            }
+        }
+    }
+
+    fn resolve_pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Identifier { name, type_ann } => {
+                if let Some(ann) = type_ann {
+                    self.resolve_type(ann);
+                }
+                self.declare(name, SymbolKind::Var);
+            }
+            Pattern::Ignore => {}
+            Pattern::Object(fields) => {
+                for field in fields {
+                    if let Some(ann) = &field.type_ann {
+                        self.resolve_type(ann);
+                    }
+                    self.resolve_pattern(&field.pattern);
+                }
+            }
+            Pattern::Array(patterns) => {
+                for pattern in patterns {
+                    self.resolve_pattern(pattern);
+                }
+            }
+        }
+    }
+
+    fn resolve_type(&mut self, type_expr: &TypeExpr) {
+        match type_expr {
+            TypeExpr::Name(name) => {
+                let resolution = self.lookup_type(name);
+                self.record(name, resolution, false);
+            }
+            TypeExpr::Object(fields) => {
+                for field in fields {
+                    self.resolve_type(&field.type_expr);
+                }
+            }
+            TypeExpr::Array(elem) => self.resolve_type(elem),
+            TypeExpr::Union(types) => {
+                for ty in types {
+                    self.resolve_type(ty);
+                }
+            }
+            TypeExpr::Literal(_) => {}
+        }
+    }
+
+    // ---- expressions ----
+
+    fn resolve_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Identifier(name) => self.use_name(name, false),
+            Expr::Number(_) | Expr::True | Expr::False => {}
+            Expr::String(lit) => self.resolve_string(lit),
+            Expr::Array(items) => {
+                for item in items {
+                    self.resolve_expr(item);
+                }
+            }
+            Expr::Object(fields) => {
+                for field in fields {
+                    match &field.value {
+                        Some(value) => self.resolve_expr(value),
+                        // Shorthand `{x}` reads the variable `x`
+                        None => self.use_name(field.key, false),
+                    }
+                }
+            }
+            Expr::Binary { op: BinOp::Assign, left, right } => {
+                self.resolve_expr(right);
+                match left.as_ref() {
+                    Expr::Identifier(name) => self.use_name(name, true),
+                    other => self.resolve_expr(other),
+                }
+            }
+            Expr::Binary { left, right, .. }
+            | Expr::ShellPipe { left, right }
+            | Expr::ShellAnd { left, right }
+            | Expr::ShellOr { left, right } => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            Expr::Unary { operand, .. } => self.resolve_expr(operand),
+            Expr::Call { callee, args } => {
+                self.resolve_expr(callee);
+                for (i, arg) in args.iter().enumerate() {
+                    // `validate(value, Type)` names a type, not a variable
+                    match (callee.as_ref(), arg) {
+                        (Expr::Identifier("validate"), Expr::Identifier(name)) if i == 1 => {
+                            self.resolve_type(&TypeExpr::Name(name))
+                        }
+                        _ => self.resolve_expr(arg),
+                    }
+                }
+            }
+            Expr::Member { object, .. } => self.resolve_expr(object),
+            Expr::Index { object, index } => {
+                self.resolve_expr(object);
+                self.resolve_expr(index);
+            }
+            Expr::PostIncrement(inner)
+            | Expr::PostDecrement(inner)
+            | Expr::Paren(inner)
+            | Expr::Await(inner)
+            | Expr::CommandSubst(inner)
+            | Expr::ShellBackground(inner) => self.resolve_expr(inner),
+            Expr::Think(prompt) | Expr::Ask(prompt) | Expr::Approve(prompt) => self.resolve_prompt(prompt),
+            Expr::Do(block) => self.resolve_block(block),
+            Expr::BareCommand { args, .. } => {
+                for arg in args {
+                    if let CommandArg::String(lit) = arg {
+                        self.resolve_string(lit);
+                    }
+                }
+            }
+            Expr::ShellRedirect { command, target, .. } => {
+                self.resolve_expr(command);
+                self.resolve_expr(target);
+            }
+        }
+    }
+
+    fn resolve_prompt(&mut self, prompt: &PromptBlock) {
+        for item in &prompt.items {
+            match item {
+                PromptItem::Text(_) => {}
+                PromptItem::Interpolation(expr) => self.resolve_expr(expr),
+                PromptItem::Code(block) => self.resolve_block(block),
+                PromptItem::Examples(fields) => {
+                    for field in fields {
+                        match &field.value {
+                            Some(value) => self.resolve_expr(value),
+                            None => self.use_name(field.key, false),
+                        }
+                    }
+                }
+                PromptItem::Variant { block, .. } => self.resolve_prompt(block),
+            }
+        }
+    }
+
+    fn resolve_string(&mut self, lit: &StringLiteral) {
+        for part in &lit.parts {
+            if let StringPart::Interpolation(expr) = part {
+                self.resolve_expr(expr);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn resolution_of<'a>(resolved: &'a ResolvedProgram, name: &str, nth: usize) -> &'a Reference {
+        resolved
+            .symbols
+            .references()
+            .iter()
+            .filter(|r| r.name == name)
+            .nth(nth)
+            .unwrap_or_else(|| panic!("no reference #{} to {}", nth, name))
+    }
+
+    #[test]
+    fn test_locals_resolve_to_declarations() {
+        let source = "fun main(a) {\n  var x = a\n  print(x)\n}";
+        let program = parse(source).unwrap();
+        let resolved = resolve(&program, source);
+        let table = &resolved.symbols;
+
+        let a = resolution_of(&resolved, "a", 0);
+        let Resolution::Local { symbol, hops, slot } = a.resolution else {
+            panic!("expected local, got {:?}", a.resolution);
+        };
+        assert_eq!(table.symbol(symbol).kind, SymbolKind::Param);
+        // Parameter scope is one hop above the body block
+        assert_eq!((hops, slot), (1, 0));
+
+        let x = resolution_of(&resolved, "x", 0);
+        let Resolution::Local { symbol, hops, slot } = x.resolution else {
+            panic!("expected local, got {:?}", x.resolution);
+        };
+        assert_eq!(table.symbol(symbol).kind, SymbolKind::Var);
+        assert_eq!((hops, slot), (0, 0));
+        assert_eq!(table.symbol(symbol).span, Some((20, 21)));
+
+        assert_eq!(resolution_of(&resolved, "print", 0).resolution, Resolution::Builtin);
+    }
+
+    #[test]
+    fn test_resolution_by_identifier_slice() {
+        let source = "fun main() {\n  var x = 1\n  return x\n}";
+        let program = parse(source).unwrap();
+        let resolved = resolve(&program, source);
+
+        let Item::Function(main) = &program.items[0] else { panic!() };
+        let Statement::Return(Some(expr)) = &main.body.statements[1] else { panic!() };
+        let resolution = resolved.resolution_of(expr).unwrap();
+        let id = resolution.symbol().unwrap();
+        assert_eq!(resolved.symbols.symbol(id).name, "x");
+    }
+
+    #[test]
+    fn test_shadowing_and_loop_scopes() {
+        let source = "fun main(items) {\n  var x = 1\n  for var x in items {\n    print(x)\n  }\n  print(x)\n}";
+        let program = parse(source).unwrap();
+        let resolved = resolve(&program, source);
+        let table = &resolved.symbols;
+
+        let inner = resolution_of(&resolved, "x", 0).resolution;
+        let outer = resolution_of(&resolved, "x", 1).resolution;
+        assert_eq!(table.symbol(inner.symbol().unwrap()).kind, SymbolKind::LoopVar);
+        assert_eq!(table.symbol(outer.symbol().unwrap()).kind, SymbolKind::Var);
+        // Body block, then the loop variable's scope
+        assert!(matches!(inner, Resolution::Local { hops: 1, slot: 0, .. }));
+    }
+
+    #[test]
+    fn test_globals_and_types() {
+        let source = "type Status = \"ok\" | \"error\"\nfun helper(s: Status) { return s }\nfun main() { helper(\"ok\") }";
+        let program = parse(source).unwrap();
+        let resolved = resolve(&program, source);
+        let table = &resolved.symbols;
+
+        let helper = resolution_of(&resolved, "helper", 0).resolution;
+        let Resolution::Global(id) = helper else { panic!("expected global, got {:?}", helper) };
+        assert_eq!(table.symbol(id).kind, SymbolKind::Function);
+
+        let status = resolution_of(&resolved, "Status", 0).resolution;
+        assert_eq!(table.symbol(status.symbol().unwrap()).kind, SymbolKind::TypeAlias);
+    }
+
+    #[test]
+    fn test_module_initialization_order() {
+        let source = "const RATE = BASE * 2\nvar table = {rate: RATE}\nconst BASE = 10\nfun main() { print(table) }";
+        let program = parse(source).unwrap();
+        let resolved = resolve(&program, source);
+        let table = &resolved.symbols;
+
+        let rate = resolution_of(&resolved, "RATE", 0).resolution;
+        assert_eq!(table.symbol(rate.symbol().unwrap()).kind, SymbolKind::Const);
+        let used = resolution_of(&resolved, "table", 0).resolution;
+        assert_eq!(table.symbol(used.symbol().unwrap()).kind, SymbolKind::ModuleVar);
+        assert_eq!(table.initialization_order(), Ok(vec![2, 0, 1]));
+    }
+
+    #[test]
+    fn test_module_initialization_cycle() {
+        let source = "const A = B + 1\nconst B = A\n";
+        let program = parse(source).unwrap();
+        let resolved = resolve(&program, source);
+
+        let err = resolved.symbols.initialization_order().unwrap_err();
+        assert!(err.ends_with("A -> B -> A"), "{}", err);
+    }
+
+    #[test]
+    fn test_unresolved_and_assignment() {
+        let source = "fun main() {\n  var count = 0\n  count = missing\n}";
+        let program = parse(source).unwrap();
+        let resolved = resolve(&program, source);
+        let table = &resolved.symbols;
+
+        let unresolved: Vec<_> = table.unresolved().map(|r| r.name.as_str()).collect();
+        assert_eq!(unresolved, vec!["missing"]);
+        assert!(resolution_of(&resolved, "count", 0).is_write);
+    }
+
+    #[test]
+    fn test_skill_settings() {
+        let source = "@agent local\n@concurrency(1)\n@timeout(2m)\n@capabilities(shell)\nskill deploy() {}\n\n\
+@timeout(soon)\n@capabilities(network)\nskill check() {}\n\n@agent local\nskill plain() {}";
+        let program = parse(source).unwrap();
+        let table = resolve(&program, source).symbols;
+
+        let settings = table.skill_settings("deploy").unwrap();
+        assert_eq!(settings.concurrency, Some(1));
+        assert_eq!(settings.timeout, Some(Duration::from_secs(120)));
+        assert_eq!(settings.capabilities, Some(vec!["shell".to_string()]));
+        assert_eq!(table.skill_settings("plain"), None);
+
+        let skills: Vec<&str> = table.invalid_annotations().iter().map(|invalid| invalid.skill.as_str()).collect();
+        assert_eq!(skills, vec!["check", "check"]);
+        let span = table.invalid_annotations()[0].span.unwrap();
+        assert_eq!(&source[span.0..span.1], "timeout");
+        assert!(table.invalid_annotations()[1].message.contains("`network`"));
+    }
+
+    #[test]
+    fn test_timeout_arg_limits() {
+        assert_eq!(timeout_arg(&["168h"]), Ok(MAX_SKILL_TIMEOUT));
+        assert_eq!(timeout_arg(&["250ms"]), Ok(Duration::from_millis(250)));
+        assert_eq!(timeout_arg(&["169h"]).unwrap_err(), "`@timeout` can be at most 168h, not `169h`");
+        assert!(timeout_arg(&["999999999999999999m"]).unwrap_err().contains("takes a duration"));
+        assert!(timeout_arg(&["99999999999999999999s"]).unwrap_err().contains("takes a duration"));
+
+        // The resolver reports it rather than panicking
+        let source = "@timeout(999999999999999999m)\nskill slow() {}";
+        let program = parse(source).unwrap();
+        let table = resolve(&program, source).symbols;
+        assert_eq!(table.invalid_annotations().len(), 1);
+    }
+
+    #[test]
+    fn test_symbol_at_and_references() {
+        let source = "fun main() {\n  var x = 1\n  print(x)\n  print(x)\n}";
+        let program = parse(source).unwrap();
+        let resolved = resolve(&program, source);
+        let table = &resolved.symbols;
+
+        let use_offset = source.find("print(x)").unwrap() + 6;
+        let id = table.symbol_at(use_offset).unwrap();
+        assert_eq!(table.symbol(id).name, "x");
+        assert_eq!(table.symbol_at(table.symbol(id).span.unwrap().0), Some(id));
+        assert_eq!(table.references_to(id).count(), 2);
+    }
+}
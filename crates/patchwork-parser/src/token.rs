@@ -16,13 +16,19 @@ pub enum ParserToken<'input> {
     // Prompt operators
     Think,
     Ask,
+    Approve,
     Do,
+    /// `examples {` inside a prompt, brace included
+    Examples,
+    /// `variant name {` inside a prompt, brace included; carries the name
+    Variant(&'input str),
 
     // Keywords
     Import,
     Export,
     From,
     Var,
+    Const,
     If,
     Else,
     For,
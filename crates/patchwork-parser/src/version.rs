@@ -0,0 +1,211 @@
+//! Language versions and feature gating.
+//!
+//! Syntax added after the first release is tied to the version that
+//! introduced it, so a file can pin the dialect it was written against with
+//! a pragma on its first line:
+//!
+//! ```text
+//! #patchwork 0.2
+//! ```
+//!
+//! Parsing that file rejects anything newer, such as `plan { ... }` blocks,
+//! even with a parser that understands them. Files without a pragma are
+//! parsed at `ParseOptions::version`, which defaults to the latest version.
+//! Hosts can also enable individual features on top of the version.
+
+use std::fmt;
+
+use crate::error::ParseError;
+use crate::ast::*;
+
+/// A version of the Patchwork language, e.g. `0.3`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LanguageVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl LanguageVersion {
+    pub const V0_1: LanguageVersion = LanguageVersion::new(0, 1);
+    pub const V0_2: LanguageVersion = LanguageVersion::new(0, 2);
+    pub const V0_3: LanguageVersion = LanguageVersion::new(0, 3);
+    /// The newest version this parser understands.
+    pub const LATEST: LanguageVersion = LanguageVersion::V0_3;
+
+    pub const fn new(major: u32, minor: u32) -> Self {
+        Self { major, minor }
+    }
+
+    /// Parse `MAJOR.MINOR`.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (major, minor) = text.split_once('.')?;
+        Some(Self::new(major.parse().ok()?, minor.parse().ok()?))
+    }
+}
+
+impl Default for LanguageVersion {
+    fn default() -> Self {
+        Self::LATEST
+    }
+}
+
+impl fmt::Display for LanguageVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Syntax that is only available from some language version on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// Module-level `var` and `const` declarations.
+    ModuleVars,
+    /// `import data "./file.json"`.
+    DataImports,
+    /// `plan { step "..." }` blocks.
+    PlanBlocks,
+    /// `eval "name" { ... }` declarations.
+    Evals,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 4] = [Feature::ModuleVars, Feature::DataImports, Feature::PlanBlocks, Feature::Evals];
+
+    /// The version that made this feature part of the language.
+    pub fn introduced_in(self) -> LanguageVersion {
+        match self {
+            Feature::ModuleVars | Feature::DataImports => LanguageVersion::V0_2,
+            Feature::PlanBlocks | Feature::Evals => LanguageVersion::V0_3,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::ModuleVars => "a module-level `var` or `const`",
+            Feature::DataImports => "`import data`",
+            Feature::PlanBlocks => "a `plan` block",
+            Feature::Evals => "an `eval` declaration",
+        }
+    }
+}
+
+/// How to parse a program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParseOptions {
+    /// The version for files without a `#patchwork` pragma.
+    pub version: LanguageVersion,
+    /// Features to allow even if the file's version predates them.
+    pub features: Vec<Feature>,
+}
+
+impl ParseOptions {
+    pub fn with_version(version: LanguageVersion) -> Self {
+        Self { version, features: Vec::new() }
+    }
+
+    pub fn with_feature(mut self, feature: Feature) -> Self {
+        self.features.push(feature);
+        self
+    }
+
+    fn allows(&self, version: LanguageVersion, feature: Feature) -> bool {
+        feature.introduced_in() <= version || self.features.contains(&feature)
+    }
+}
+
+/// Read the `#patchwork X.Y` pragma, if the file starts with one.
+///
+/// Blank lines and other comments may come before it; any code ends the
+/// search.
+pub fn pragma_version(input: &str) -> Result<Option<LanguageVersion>, ParseError> {
+    let mut offset = 0;
+    for line in input.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let Some(rest) = trimmed.strip_prefix('#') else {
+            break;
+        };
+        let mut words = rest.split_whitespace();
+        if words.next() != Some("patchwork") {
+            continue;
+        }
+        let span = (start, start + line.trim_end().len());
+        let version = words.next().filter(|_| words.next().is_none()).and_then(LanguageVersion::parse);
+        let version = version.ok_or_else(|| ParseError::InvalidPragma { span, pragma: trimmed.to_string() })?;
+        if version > LanguageVersion::LATEST {
+            return Err(ParseError::UnsupportedVersion { span, version });
+        }
+        return Ok(Some(version));
+    }
+    Ok(None)
+}
+
+/// Reject syntax that `version` (plus any features the options enable)
+/// doesn't include.
+pub(crate) fn check_features(
+    program: &Program,
+    input: &str,
+    version: LanguageVersion,
+    options: &ParseOptions,
+) -> Result<(), ParseError> {
+    let mut used = Vec::new();
+    for item in &program.items {
+        match item {
+            Item::Var(decl) => used.push((Feature::ModuleVars, decl.pattern.names().first().copied())),
+            Item::Import(ImportDecl { path: ImportPath::Data { path, .. } }) => {
+                used.push((Feature::DataImports, Some(*path)))
+            }
+            Item::Skill(SkillDecl { body, .. })
+            | Item::Worker(WorkerDecl { body, .. })
+            | Item::Function(FunctionDecl { body, .. }) => find_plans(body, &mut used),
+            Item::Trait(decl) => {
+                for method in &decl.methods {
+                    find_plans(&method.body, &mut used);
+                }
+            }
+            Item::Eval(decl) => used.push((Feature::Evals, Some(decl.name))),
+            _ => {}
+        }
+    }
+
+    match used.into_iter().find(|(feature, _)| !options.allows(version, *feature)) {
+        None => Ok(()),
+        Some((feature, at)) => {
+            let span = at.and_then(|text| span_of(text, input));
+            Err(ParseError::FeatureUnavailable { span, feature, version })
+        }
+    }
+}
+
+fn find_plans<'input>(block: &Block<'input>, used: &mut Vec<(Feature, Option<&'input str>)>) {
+    for statement in &block.statements {
+        match statement {
+            Statement::Plan { steps } => {
+                let text = steps.iter().flat_map(|step| &step.parts).find_map(|part| match part {
+                    StringPart::Text(text) => Some(*text),
+                    StringPart::Interpolation(_) => None,
+                });
+                used.push((Feature::PlanBlocks, text));
+            }
+            Statement::If { then_block, else_block, .. } => {
+                find_plans(then_block, used);
+                if let Some(else_block) = else_block {
+                    find_plans(else_block, used);
+                }
+            }
+            Statement::ForIn { body, .. } | Statement::While { body, .. } => find_plans(body, used),
+            Statement::Expr(Expr::Do(body)) => find_plans(body, used),
+            _ => {}
+        }
+    }
+}
+
+/// Where `text`, a slice of `input`, sits in it.
+fn span_of(text: &str, input: &str) -> Option<(usize, usize)> {
+    let start = (text.as_ptr() as usize).checked_sub(input.as_ptr() as usize)?;
+    (start + text.len() <= input.len()).then_some((start, start + text.len()))
+}
@@ -0,0 +1,12 @@
+#!/usr/bin/env bash
+set -euo pipefail
+
+ROOT="$(cd "$(dirname "${BASH_SOURCE[0]}")/.." && pwd)"
+
+DEST="$ROOT/docs/grammar"
+
+mkdir -p "$DEST"
+cargo run --quiet --manifest-path "$ROOT/Cargo.toml" -p patchwork-parser --bin patchwork-grammar -- \
+    --svg "$DEST/railroad" > "$DEST/patchwork.ebnf"
+
+echo "Wrote the grammar's EBNF and railroad diagrams to $DEST"